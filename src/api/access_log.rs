@@ -0,0 +1,237 @@
+//! Access log of API requests, written in the Apache Combined Log Format
+//! followed by the address given in the `X-Forwarded-For` header
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use hyper::header::{self, HeaderMap};
+use hyper::{Method, StatusCode, Uri, Version};
+
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+
+use garage_util::error::Error as GarageError;
+
+/// Number of log entries that can be waiting to be written to the log file.
+/// When this limit is reached, new entries are dropped
+/// so that the API server is never slowed down by the access log.
+const ACCESS_LOG_QUEUE_LEN: usize = 4096;
+
+/// Handle used to send entries to the access log.
+/// Entries are written to the log file by a background task.
+#[derive(Clone)]
+pub struct AccessLog {
+	sender: mpsc::Sender<String>,
+}
+
+/// Information about a request and its response,
+/// as written in a line of the access log
+pub struct AccessLogEntry<'a> {
+	/// Address of the peer of the connection
+	pub remote_addr: &'a str,
+	/// Address given in the `X-Forwarded-For` header of the request, if any
+	pub forwarded_for: Option<IpAddr>,
+	pub time: DateTime<Utc>,
+	pub method: &'a Method,
+	pub uri: &'a Uri,
+	pub version: Version,
+	pub request_headers: &'a HeaderMap,
+	pub status: StatusCode,
+	pub response_size: Option<u64>,
+}
+
+impl AccessLog {
+	/// Open the log file (in append mode) and launch the task that writes entries to it.
+	/// The file is closed and reopened when the process receives SIGHUP,
+	/// so that the log can be rotated by an external tool.
+	pub async fn open(path: PathBuf) -> Result<Self, GarageError> {
+		let file = open_log_file(&path).await?;
+		let (sender, receiver) = mpsc::channel(ACCESS_LOG_QUEUE_LEN);
+		tokio::spawn(access_log_writer(path, file, receiver));
+		Ok(Self { sender })
+	}
+
+	/// Add an entry to the log, without waiting for it to be written
+	pub fn log(&self, entry: AccessLogEntry<'_>) {
+		if self.sender.try_send(entry.to_string()).is_err() {
+			warn!("Access log queue is full, dropping log entry");
+		}
+	}
+}
+
+impl<'a> std::fmt::Display for AccessLogEntry<'a> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let header = |name| {
+			self.request_headers
+				.get(name)
+				.and_then(|v| v.to_str().ok())
+				.map(escape)
+				.unwrap_or_else(|| "-".into())
+		};
+		let path = self.uri.path_and_query().map(|x| x.as_str()).unwrap_or("/");
+		write!(
+			f,
+			"{} - - [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\" \"{}\"",
+			self.remote_addr,
+			self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+			self.method,
+			escape(path),
+			self.version,
+			self.status.as_u16(),
+			self.response_size
+				.map(|x| x.to_string())
+				.unwrap_or_else(|| "-".into()),
+			header(header::REFERER),
+			header(header::USER_AGENT),
+			self.forwarded_for
+				.map(|x| x.to_string())
+				.unwrap_or_else(|| "-".into()),
+		)
+	}
+}
+
+/// Escape characters that would make a log line ambiguous
+fn escape(s: &str) -> String {
+	s.chars()
+		.flat_map(|c| match c {
+			'"' | '\\' => vec!['\\', c],
+			c if c.is_control() => c.escape_default().collect(),
+			c => vec![c],
+		})
+		.collect()
+}
+
+async fn open_log_file(path: &Path) -> Result<BufWriter<File>, GarageError> {
+	let file = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(path)
+		.await
+		.map_err(|e| {
+			GarageError::Message(format!(
+				"Unable to open access log file {}: {}",
+				path.display(),
+				e
+			))
+		})?;
+	Ok(BufWriter::new(file))
+}
+
+#[cfg(unix)]
+async fn access_log_writer(
+	path: PathBuf,
+	mut file: BufWriter<File>,
+	mut receiver: mpsc::Receiver<String>,
+) {
+	use tokio::signal::unix::*;
+
+	let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+
+	loop {
+		tokio::select! {
+			entry = receiver.recv() => {
+				let entry = match entry {
+					Some(e) => e,
+					None => break,
+				};
+				if let Err(e) = write_entries(&mut file, entry, &mut receiver).await {
+					error!("Unable to write to access log file {}: {}", path.display(), e);
+				}
+			}
+			_ = sighup.recv() => {
+				info!("Received SIGHUP, reopening access log file {}", path.display());
+				if let Err(e) = file.flush().await {
+					error!("Unable to write to access log file {}: {}", path.display(), e);
+				}
+				match open_log_file(&path).await {
+					Ok(f) => file = f,
+					Err(e) => error!("{}", e),
+				}
+			}
+		}
+	}
+
+	if let Err(e) = file.flush().await {
+		error!(
+			"Unable to write to access log file {}: {}",
+			path.display(),
+			e
+		);
+	}
+}
+
+#[cfg(not(unix))]
+async fn access_log_writer(
+	path: PathBuf,
+	mut file: BufWriter<File>,
+	mut receiver: mpsc::Receiver<String>,
+) {
+	while let Some(entry) = receiver.recv().await {
+		if let Err(e) = write_entries(&mut file, entry, &mut receiver).await {
+			error!(
+				"Unable to write to access log file {}: {}",
+				path.display(),
+				e
+			);
+		}
+	}
+	if let Err(e) = file.flush().await {
+		error!(
+			"Unable to write to access log file {}: {}",
+			path.display(),
+			e
+		);
+	}
+}
+
+/// Write an entry and all other entries that are already waiting in the queue,
+/// then flush the file
+async fn write_entries(
+	file: &mut BufWriter<File>,
+	entry: String,
+	receiver: &mut mpsc::Receiver<String>,
+) -> std::io::Result<()> {
+	let mut entry = Some(entry);
+	while let Some(e) = entry {
+		file.write_all(e.as_bytes()).await?;
+		file.write_all(b"\n").await?;
+		entry = receiver.try_recv().ok();
+	}
+	file.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	#[test]
+	fn test_access_log_entry_format() {
+		let mut headers = HeaderMap::new();
+		headers.insert(header::USER_AGENT, "aws-cli/2.0 \"test\"".parse().unwrap());
+		let mut entry = AccessLogEntry {
+			remote_addr: "192.0.2.1",
+			forwarded_for: None,
+			time: Utc.with_ymd_and_hms(2023, 10, 9, 13, 55, 36).unwrap(),
+			method: &Method::GET,
+			uri: &"/bucket/key?versionId=1".parse().unwrap(),
+			version: Version::HTTP_11,
+			request_headers: &headers,
+			status: StatusCode::OK,
+			response_size: Some(2326),
+		};
+		assert_eq!(
+			entry.to_string(),
+			r#"192.0.2.1 - - [09/Oct/2023:13:55:36 +0000] "GET /bucket/key?versionId=1 HTTP/1.1" 200 2326 "-" "aws-cli/2.0 \"test\"" "-""#
+		);
+
+		entry.forwarded_for = Some("198.51.100.7".parse().unwrap());
+		entry.status = StatusCode::INTERNAL_SERVER_ERROR;
+		entry.response_size = None;
+		assert_eq!(
+			entry.to_string(),
+			r#"192.0.2.1 - - [09/Oct/2023:13:55:36 +0000] "GET /bucket/key?versionId=1 HTTP/1.1" 500 - "-" "aws-cli/2.0 \"test\"" "198.51.100.7""#
+		);
+	}
+}
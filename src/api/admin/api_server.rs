@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 
@@ -16,30 +17,45 @@ use prometheus::{Encoder, TextEncoder};
 
 use garage_model::garage::Garage;
 use garage_rpc::system::ClusterHealthStatus;
+use garage_util::background::BackgroundRunner;
 use garage_util::error::Error as GarageError;
 use garage_util::socket_address::UnixOrTCPSocketAddress;
 
 use crate::generic_server::*;
 
+use crate::admin::audit_log::AuditLog;
 use crate::admin::bucket::*;
 use crate::admin::cluster::*;
 use crate::admin::error::*;
+use crate::admin::jwt::JwtAuth;
 use crate::admin::key::*;
+use crate::admin::log_level::*;
 use crate::admin::router::{Authorization, Endpoint};
+use crate::admin::worker::*;
 use crate::helpers::host_to_bucket;
 
+/// Time after which the metadata database is considered unresponsive
+/// by the readiness probe
+const READINESS_DB_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct AdminApiServer {
 	garage: Arc<Garage>,
+	background: Arc<BackgroundRunner>,
 	#[cfg(feature = "metrics")]
 	exporter: PrometheusExporter,
 	metrics_token: Option<String>,
 	admin_token: Option<String>,
+	jwt_auth: Option<JwtAuth>,
+	audit_log: Option<AuditLog>,
+	log_filter: LogFilter,
 }
 
 impl AdminApiServer {
 	pub fn new(
 		garage: Arc<Garage>,
+		background: Arc<BackgroundRunner>,
 		#[cfg(feature = "metrics")] exporter: PrometheusExporter,
+		log_filter: LogFilter,
 	) -> Self {
 		let cfg = &garage.config.admin;
 		let metrics_token = cfg
@@ -52,20 +68,29 @@ impl AdminApiServer {
 			.map(|tok| format!("Bearer {}", tok));
 		Self {
 			garage,
+			background,
 			#[cfg(feature = "metrics")]
 			exporter,
 			metrics_token,
 			admin_token,
+			jwt_auth: None,
+			audit_log: None,
+			log_filter,
 		}
 	}
 
 	pub async fn run(
-		self,
+		mut self,
 		bind_addr: UnixOrTCPSocketAddress,
 		shutdown_signal: impl Future<Output = ()>,
 	) -> Result<(), GarageError> {
+		self.jwt_auth = JwtAuth::new(&self.garage.config.admin)?;
+		if let Some(path) = &self.garage.config.admin.audit_log_file {
+			self.audit_log = Some(AuditLog::open(path.clone()).await?);
+		}
 		let region = self.garage.config.s3_api.s3_region.clone();
-		ApiServer::new(region, self)
+		let trusted_proxies = parse_trusted_proxies(&self.garage.config.trusted_proxies)?;
+		ApiServer::new(region, self, None, trusted_proxies)
 			.run_server(bind_addr, Some(0o220), shutdown_signal)
 			.await
 	}
@@ -191,6 +216,68 @@ impl AdminApiServer {
 			.body(Body::from(status_str))?)
 	}
 
+	fn handle_livez(&self) -> Result<Response<Body>, Error> {
+		Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "text/plain")
+			.body(Body::from("Garage is alive\n"))?)
+	}
+
+	async fn handle_readyz(&self) -> Result<Response<Body>, Error> {
+		let (status, status_str) = match self.check_ready().await {
+			Ok(()) => (
+				StatusCode::OK,
+				"Garage is ready to serve requests\n".to_string(),
+			),
+			Err(e) => (
+				StatusCode::SERVICE_UNAVAILABLE,
+				format!("Garage is not ready: {}\n", e),
+			),
+		};
+
+		Ok(Response::builder()
+			.status(status)
+			.header(http::header::CONTENT_TYPE, "text/plain")
+			.body(Body::from(status_str))?)
+	}
+
+	/// Check that this node can serve requests: it must have a role in an
+	/// applied cluster layout, store some partitions if it is a storage node,
+	/// and its metadata database must answer
+	async fn check_ready(&self) -> Result<(), String> {
+		let ring = self.garage.system.ring.borrow().clone();
+		if ring.layout.version == 0 {
+			return Err("no cluster layout has been applied yet".into());
+		}
+		let our_id = self.garage.system.id;
+		match ring.layout.node_role(&our_id) {
+			None => return Err("this node has no role in the cluster layout".into()),
+			Some(role) if role.capacity.is_some() => {
+				let has_partitions = ring
+					.partitions()
+					.iter()
+					.any(|(_, h)| ring.get_nodes(h, ring.replication_factor).contains(&our_id));
+				if !has_partitions {
+					return Err("no partition is assigned to this node".into());
+				}
+			}
+			Some(_) => (),
+		}
+
+		let db = self.garage.db.clone();
+		match tokio::time::timeout(
+			READINESS_DB_TIMEOUT,
+			tokio::task::spawn_blocking(move || db.list_trees()),
+		)
+		.await
+		{
+			Ok(Ok(Ok(_))) => Ok(()),
+			Ok(Ok(Err(e))) => Err(format!("metadata database error: {}", e)),
+			Ok(Err(e)) => Err(format!("metadata database error: {}", e)),
+			Err(_) => Err("metadata database is not responding".into()),
+		}
+	}
+
 	fn handle_metrics(&self) -> Result<Response<Body>, Error> {
 		#[cfg(feature = "metrics")]
 		{
@@ -234,48 +321,99 @@ impl ApiHandler for AdminApiServer {
 
 	async fn handle(
 		&self,
-		req: Request<Body>,
+		mut req: Request<Body>,
 		endpoint: Endpoint,
 	) -> Result<Response<Body>, Error> {
-		let expected_auth_header =
-			match endpoint.authorization_type() {
-				Authorization::None => None,
-				Authorization::MetricsToken => self.metrics_token.as_ref(),
-				Authorization::AdminToken => match &self.admin_token {
-					None => return Err(Error::forbidden(
+		let expected_auth_header = match endpoint.authorization_type() {
+			Authorization::None => None,
+			Authorization::MetricsToken => self.metrics_token.as_ref(),
+			Authorization::AdminToken => {
+				if self.admin_token.is_none() && self.jwt_auth.is_none() {
+					return Err(Error::forbidden(
 						"Admin token isn't configured, admin API access is disabled for security.",
-					)),
-					Some(t) => Some(t),
-				},
-			};
+					));
+				}
+				self.admin_token.as_ref()
+			}
+		};
+		// When JWTs are accepted, the admin token is checked first, then
+		// the bearer token is checked as a JWT
+		let jwt_auth = match endpoint.authorization_type() {
+			Authorization::AdminToken => self.jwt_auth.as_ref(),
+			_ => None,
+		};
 
-		if let Some(h) = expected_auth_header {
-			match req.headers().get("Authorization") {
+		if expected_auth_header.is_some() || jwt_auth.is_some() {
+			let header = match req.headers().get("Authorization") {
 				None => return Err(Error::forbidden("Authorization token must be provided")),
-				Some(v) => {
-					let authorized = v.to_str().map(|hv| hv.trim() == h).unwrap_or(false);
-					if !authorized {
-						return Err(Error::forbidden("Invalid authorization token provided"));
-					}
-				}
+				Some(v) => v.to_str().map(str::trim).unwrap_or_default(),
+			};
+			if expected_auth_header.map(String::as_str) != Some(header) {
+				let subject = match (jwt_auth, header.strip_prefix("Bearer ")) {
+					(Some(jwt_auth), Some(token)) => jwt_auth.check(token.trim(), &endpoint)?,
+					_ => return Err(Error::forbidden("Invalid authorization token provided")),
+				};
+				req.extensions_mut().insert(subject);
+			}
+		}
+
+		match &self.audit_log {
+			Some(audit_log) => {
+				audit_log
+					.audit(req, endpoint, |req, endpoint| {
+						self.handle_endpoint(req, endpoint)
+					})
+					.await
 			}
+			None => self.handle_endpoint(req, endpoint).await,
 		}
+	}
+}
 
+impl AdminApiServer {
+	async fn handle_endpoint(
+		&self,
+		req: Request<Body>,
+		endpoint: Endpoint,
+	) -> Result<Response<Body>, Error> {
 		match endpoint {
 			Endpoint::Options => self.handle_options(&req),
 			Endpoint::CheckDomain => self.handle_check_domain(req).await,
 			Endpoint::Health => self.handle_health(),
+			Endpoint::Livez => self.handle_livez(),
+			Endpoint::Readyz => self.handle_readyz().await,
 			Endpoint::Metrics => self.handle_metrics(),
 			Endpoint::GetClusterStatus => handle_get_cluster_status(&self.garage).await,
 			Endpoint::GetClusterHealth => handle_get_cluster_health(&self.garage).await,
+			Endpoint::GetClusterHealthHistory { count } => {
+				handle_get_cluster_health_history(&self.garage, count).await
+			}
 			Endpoint::ConnectClusterNodes => handle_connect_cluster_nodes(&self.garage, req).await,
+			Endpoint::GetLogLevel => handle_get_log_level(&self.log_filter).await,
+			Endpoint::PutLogLevel => handle_put_log_level(&self.log_filter, req).await,
+			// Workers
+			Endpoint::PauseWorker { id } => {
+				handle_set_worker_paused(&self.background, id, true).await
+			}
+			Endpoint::ResumeWorker { id } => {
+				handle_set_worker_paused(&self.background, id, false).await
+			}
+			Endpoint::GetWorkerVariable { variable } => {
+				handle_get_worker_variable(&self.garage, variable).await
+			}
+			Endpoint::SetWorkerVariable { variable } => {
+				handle_set_worker_variable(&self.garage, variable, req).await
+			}
 			// Layout
 			Endpoint::GetClusterLayout => handle_get_cluster_layout(&self.garage).await,
 			Endpoint::UpdateClusterLayout => handle_update_cluster_layout(&self.garage, req).await,
 			Endpoint::ApplyClusterLayout => handle_apply_cluster_layout(&self.garage, req).await,
 			Endpoint::RevertClusterLayout => handle_revert_cluster_layout(&self.garage, req).await,
 			// Keys
-			Endpoint::ListKeys => handle_list_keys(&self.garage).await,
+			Endpoint::ListKeys {
+				limit,
+				continuation_token,
+			} => handle_list_keys(&self.garage, limit, continuation_token).await,
 			Endpoint::GetKeyInfo { id, search } => {
 				handle_get_key_info(&self.garage, id, search).await
 			}
@@ -284,7 +422,10 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::UpdateKey { id } => handle_update_key(&self.garage, id, req).await,
 			Endpoint::DeleteKey { id } => handle_delete_key(&self.garage, id).await,
 			// Buckets
-			Endpoint::ListBuckets => handle_list_buckets(&self.garage).await,
+			Endpoint::ListBuckets {
+				limit,
+				continuation_token,
+			} => handle_list_buckets(&self.garage, limit, continuation_token).await,
 			Endpoint::GetBucketInfo { id, global_alias } => {
 				handle_get_bucket_info(&self.garage, id, global_alias).await
 			}
@@ -298,6 +439,14 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::BucketDenyKey => {
 				handle_bucket_change_key_perm(&self.garage, req, false).await
 			}
+			Endpoint::PresignBucketUrl => handle_presign_bucket_url(&self.garage, req).await,
+			// Bucket event notifications
+			Endpoint::GetBucketNotification { id } => {
+				handle_get_bucket_notification(&self.garage, id).await
+			}
+			Endpoint::PutBucketNotification { id } => {
+				handle_put_bucket_notification(&self.garage, id, req).await
+			}
 			// Bucket aliasing
 			Endpoint::GlobalAliasBucket { id, alias } => {
 				handle_global_alias_bucket(&self.garage, id, alias).await
@@ -0,0 +1,298 @@
+//! Audit log of the changes made through the admin API, written as JSON lines
+use std::path::{Path, PathBuf};
+
+use chrono::{SecondsFormat, Utc};
+use hyper::{Body, Request, Response};
+use serde::Serialize;
+use serde_json::Value;
+
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use garage_util::error::Error as GarageError;
+
+use crate::admin::error::*;
+use crate::admin::jwt::JwtSubject;
+use crate::admin::router::Endpoint;
+use crate::generic_server::RemoteAddr;
+
+/// Number of characters of the admin token that are written in the audit log
+const TOKEN_PREFIX_LEN: usize = 6;
+
+/// Fields of the request or response bodies whose value is never written to the audit log
+const REDACTED_FIELDS: &[&str] = &["secretAccessKey"];
+
+/// Log where a line is appended for each successful call to an admin API
+/// endpoint that changes the state of the cluster.
+///
+/// The file is opened in append mode for each entry, so that it can be rotated
+/// by an external tool, and entries are written before the response is sent.
+pub struct AuditLog {
+	path: PathBuf,
+	lock: Mutex<()>,
+}
+
+#[derive(Serialize)]
+pub struct AuditLogEntry {
+	pub ts: String,
+	pub remote_ip: Option<String>,
+	pub admin_token_prefix: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub jwt_subject: Option<String>,
+	pub action: &'static str,
+	pub subject: Option<String>,
+	pub diff: Value,
+}
+
+impl AuditLog {
+	/// Check that the log file can be opened and return the log
+	pub async fn open(path: PathBuf) -> Result<Self, GarageError> {
+		open_log_file(&path).await.map_err(|e| {
+			GarageError::Message(format!(
+				"Unable to open audit log file {}: {}",
+				path.display(),
+				e
+			))
+		})?;
+		Ok(Self {
+			path,
+			lock: Mutex::new(()),
+		})
+	}
+
+	/// Handle a call to an endpoint with the given handler,
+	/// and write an entry to the log if it changed the state of the cluster
+	pub async fn audit<F, Fut>(
+		&self,
+		req: Request<Body>,
+		endpoint: Endpoint,
+		handler: F,
+	) -> Result<Response<Body>, Error>
+	where
+		F: FnOnce(Request<Body>, Endpoint) -> Fut,
+		Fut: std::future::Future<Output = Result<Response<Body>, Error>>,
+	{
+		if !is_audited(&endpoint) {
+			return handler(req, endpoint).await;
+		}
+
+		let action = endpoint.name();
+		let mut subject = endpoint_subject(&endpoint);
+		let remote_ip = req
+			.extensions()
+			.get::<RemoteAddr>()
+			.and_then(|a| a.client)
+			.map(|ip| ip.to_string());
+		// The subject of a JWT identifies the caller better than its first
+		// characters, which only encode its header
+		let jwt_subject = req.extensions().get::<JwtSubject>().map(|s| s.0.clone());
+		let admin_token_prefix = req
+			.headers()
+			.get(hyper::header::AUTHORIZATION)
+			.and_then(|v| v.to_str().ok())
+			.and_then(|v| v.trim().strip_prefix("Bearer "))
+			.map(|t| t.chars().take(TOKEN_PREFIX_LEN).collect::<String>())
+			.filter(|_| jwt_subject.is_none());
+
+		// Read the body of the request to write it in the log
+		let (parts, body) = req.into_parts();
+		let body = hyper::body::to_bytes(body).await?;
+		let diff = if body.is_empty() {
+			query_diff(parts.uri.query())
+		} else {
+			serde_json::from_slice(&body)
+				.map(redact)
+				.unwrap_or(Value::Null)
+		};
+		let req = Request::from_parts(parts, Body::from(body));
+
+		let resp = handler(req, endpoint).await?;
+		if !resp.status().is_success() {
+			return Ok(resp);
+		}
+
+		// For the endpoints that create a key or a bucket,
+		// its identifier is only known once the response is ready
+		let resp = if subject.is_none() {
+			let (parts, body) = resp.into_parts();
+			let body = hyper::body::to_bytes(body).await?;
+			subject = response_subject(&body);
+			Response::from_parts(parts, Body::from(body))
+		} else {
+			resp
+		};
+
+		self.log(&AuditLogEntry {
+			ts: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+			remote_ip,
+			admin_token_prefix,
+			jwt_subject,
+			action,
+			subject,
+			diff,
+		})
+		.await;
+
+		Ok(resp)
+	}
+
+	/// Append an entry to the log file
+	pub async fn log(&self, entry: &AuditLogEntry) {
+		let mut line = match serde_json::to_vec(entry) {
+			Ok(line) => line,
+			Err(e) => {
+				error!("Could not serialize audit log entry: {}", e);
+				return;
+			}
+		};
+		line.push(b'\n');
+
+		let _lock = self.lock.lock().await;
+		let res = async {
+			let mut file = open_log_file(&self.path).await?;
+			file.write_all(&line).await?;
+			file.flush().await
+		}
+		.await;
+		if let Err(e) = res {
+			error!(
+				"Unable to write to audit log file {}: {}",
+				self.path.display(),
+				e
+			);
+		}
+	}
+}
+
+async fn open_log_file(path: &Path) -> std::io::Result<tokio::fs::File> {
+	let mut options = OpenOptions::new();
+	options.create(true).append(true);
+	#[cfg(unix)]
+	options.mode(0o600);
+	options.open(path).await
+}
+
+/// Whether calls to this endpoint change the state of the cluster
+fn is_audited(endpoint: &Endpoint) -> bool {
+	matches!(
+		endpoint,
+		Endpoint::ConnectClusterNodes
+			| Endpoint::PutLogLevel
+			| Endpoint::PauseWorker { .. }
+			| Endpoint::ResumeWorker { .. }
+			| Endpoint::SetWorkerVariable { .. }
+			| Endpoint::UpdateClusterLayout
+			| Endpoint::ApplyClusterLayout
+			| Endpoint::RevertClusterLayout
+			| Endpoint::CreateKey
+			| Endpoint::ImportKey
+			| Endpoint::UpdateKey { .. }
+			| Endpoint::DeleteKey { .. }
+			| Endpoint::CreateBucket
+			| Endpoint::UpdateBucket { .. }
+			| Endpoint::DeleteBucket { .. }
+			| Endpoint::BucketAllowKey
+			| Endpoint::BucketDenyKey
+			| Endpoint::PutBucketNotification { .. }
+			| Endpoint::GlobalAliasBucket { .. }
+			| Endpoint::GlobalUnaliasBucket { .. }
+			| Endpoint::LocalAliasBucket { .. }
+			| Endpoint::LocalUnaliasBucket { .. }
+	)
+}
+
+/// Identifier of the key, bucket, worker or variable changed by a call to an endpoint,
+/// when it is given in the query string
+fn endpoint_subject(endpoint: &Endpoint) -> Option<String> {
+	match endpoint {
+		Endpoint::UpdateKey { id }
+		| Endpoint::DeleteKey { id }
+		| Endpoint::PauseWorker { id }
+		| Endpoint::ResumeWorker { id }
+		| Endpoint::SetWorkerVariable { variable: id }
+		| Endpoint::UpdateBucket { id }
+		| Endpoint::DeleteBucket { id }
+		| Endpoint::PutBucketNotification { id }
+		| Endpoint::GlobalAliasBucket { id, .. }
+		| Endpoint::GlobalUnaliasBucket { id, .. }
+		| Endpoint::LocalAliasBucket { id, .. }
+		| Endpoint::LocalUnaliasBucket { id, .. } => Some(id.clone()),
+		_ => None,
+	}
+}
+
+/// Identifier of the key or bucket described in the body of a response
+fn response_subject(body: &[u8]) -> Option<String> {
+	let body: Value = serde_json::from_slice(body).ok()?;
+	["accessKeyId", "id"]
+		.iter()
+		.find_map(|field| body.get(field)?.as_str())
+		.map(String::from)
+}
+
+/// Parameters of the query string other than the identifier of the subject,
+/// for the endpoints that take all their parameters in the query string
+fn query_diff(query: Option<&str>) -> Value {
+	let params = url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+		.filter(|(k, _)| k != "id")
+		.map(|(k, v)| (k.into_owned(), Value::String(v.into_owned())))
+		.collect::<serde_json::Map<_, _>>();
+	if params.is_empty() {
+		Value::Null
+	} else {
+		Value::Object(params)
+	}
+}
+
+fn redact(mut value: Value) -> Value {
+	match &mut value {
+		Value::Object(fields) => {
+			for (name, field) in fields.iter_mut() {
+				if REDACTED_FIELDS.contains(&name.as_str()) {
+					*field = Value::String("<redacted>".into());
+				} else {
+					*field = redact(field.take());
+				}
+			}
+		}
+		Value::Array(items) => {
+			for item in items.iter_mut() {
+				*item = redact(item.take());
+			}
+		}
+		_ => (),
+	}
+	value
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_audit_diff() {
+		let body = serde_json::json!({
+			"accessKeyId": "GK31c2f218a2e44f485b94239e",
+			"secretAccessKey": "b892c0665f0ada8a4755dae98baa3b133590e11dae3bcc1f9d769d67f16c3835",
+		});
+		assert_eq!(
+			redact(body),
+			serde_json::json!({
+				"accessKeyId": "GK31c2f218a2e44f485b94239e",
+				"secretAccessKey": "<redacted>",
+			})
+		);
+
+		assert_eq!(
+			query_diff(Some("id=e6a14cd6&accessKeyId=GK31c2f218&alias=test")),
+			serde_json::json!({"accessKeyId": "GK31c2f218", "alias": "test"})
+		);
+		assert_eq!(query_diff(Some("id=e6a14cd6")), Value::Null);
+
+		assert_eq!(
+			response_subject(br#"{"name":"test","accessKeyId":"GK31c2f218"}"#).as_deref(),
+			Some("GK31c2f218")
+		);
+	}
+}
@@ -13,59 +13,78 @@ use garage_table::*;
 use garage_model::bucket_alias_table::*;
 use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
+use garage_model::key_table::Key;
 use garage_model::permission::*;
 use garage_model::s3::object_table::*;
 
 use crate::admin::error::*;
 use crate::admin::key::ApiBucketKeyPerm;
+use crate::admin::pagination::Pagination;
 use crate::common_error::CommonError;
+use crate::encoding::uri_encode;
 use crate::helpers::{json_ok_response, parse_json_body};
+use crate::signature::presign::presign_s3_path_and_query;
 
-pub async fn handle_list_buckets(garage: &Arc<Garage>) -> Result<Response<Body>, Error> {
-	let buckets = garage
+pub async fn handle_list_buckets(
+	garage: &Arc<Garage>,
+	limit: Option<usize>,
+	continuation_token: Option<String>,
+) -> Result<Response<Body>, Error> {
+	let pagination = Pagination::parse(limit, continuation_token)?;
+	let start = pagination
+		.start
+		.as_deref()
+		.map(|t| Uuid::try_from(t).ok_or_bad_request("Invalid continuation token"))
+		.transpose()?;
+
+	let mut buckets = garage
 		.bucket_table
 		.get_range(
 			&EmptyKey,
-			None,
+			start,
 			Some(DeletedFilter::NotDeleted),
-			10000,
+			pagination.count(),
 			EnumerationOrder::Forward,
 		)
 		.await?;
+	let next = pagination.truncate(&mut buckets, |b| b.id.as_slice());
 
 	let res = buckets
-		.into_iter()
-		.map(|b| {
-			let state = b.state.as_option().unwrap();
-			ListBucketResultItem {
-				id: hex::encode(b.id),
-				global_aliases: state
-					.aliases
-					.items()
-					.iter()
-					.filter(|(_, _, a)| *a)
-					.map(|(n, _, _)| n.to_string())
-					.collect::<Vec<_>>(),
-				local_aliases: state
-					.local_aliases
-					.items()
-					.iter()
-					.filter(|(_, _, a)| *a)
-					.map(|((k, n), _, _)| BucketLocalAlias {
-						access_key_id: k.to_string(),
-						alias: n.to_string(),
-					})
-					.collect::<Vec<_>>(),
-			}
-		})
+		.iter()
+		.map(list_bucket_result_item)
 		.collect::<Vec<_>>();
 
-	Ok(json_ok_response(&res)?)
+	pagination.response(res, next)
+}
+
+/// Description of a bucket in the bucket list of the admin API
+pub fn list_bucket_result_item(bucket: &Bucket) -> ListBucketResultItem {
+	let state = bucket.state.as_option().unwrap();
+	ListBucketResultItem {
+		id: hex::encode(bucket.id),
+		global_aliases: state
+			.aliases
+			.items()
+			.iter()
+			.filter(|(_, _, a)| *a)
+			.map(|(n, _, _)| n.to_string())
+			.collect::<Vec<_>>(),
+		local_aliases: state
+			.local_aliases
+			.items()
+			.iter()
+			.filter(|(_, _, a)| *a)
+			.map(|((k, n), _, _)| BucketLocalAlias {
+				access_key_id: k.to_string(),
+				alias: n.to_string(),
+			})
+			.collect::<Vec<_>>(),
+	}
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct ListBucketResultItem {
+pub struct ListBucketResultItem {
 	id: String,
 	global_aliases: Vec<String>,
 	local_aliases: Vec<BucketLocalAlias>,
@@ -162,70 +181,85 @@ async fn bucket_info_results(
 		}
 	}
 
+	Ok(json_ok_response(&bucket_info_result(
+		&bucket,
+		relevant_keys,
+		&counters,
+	))?)
+}
+
+/// Description of a bucket in the responses of the admin API, from the bucket,
+/// the keys that have access to it or aliases for it, and its counters
+pub fn bucket_info_result(
+	bucket: &Bucket,
+	relevant_keys: HashMap<String, Key>,
+	counters: &HashMap<String, i64>,
+) -> GetBucketInfoResult {
 	let state = bucket.state.as_option().unwrap();
 
 	let quotas = state.quotas.get();
-	let res =
-		GetBucketInfoResult {
-			id: hex::encode(bucket.id),
-			global_aliases: state
-				.aliases
-				.items()
-				.iter()
-				.filter(|(_, _, a)| *a)
-				.map(|(n, _, _)| n.to_string())
-				.collect::<Vec<_>>(),
-			website_access: state.website_config.get().is_some(),
-			website_config: state.website_config.get().clone().map(|wsc| {
-				GetBucketInfoWebsiteResult {
-					index_document: wsc.index_document,
-					error_document: wsc.error_document,
-				}
+	GetBucketInfoResult {
+		id: hex::encode(bucket.id),
+		global_aliases: state
+			.aliases
+			.items()
+			.iter()
+			.filter(|(_, _, a)| *a)
+			.map(|(n, _, _)| n.to_string())
+			.collect::<Vec<_>>(),
+		website_access: state.website_config.get().is_some(),
+		website_config: state
+			.website_config
+			.get()
+			.clone()
+			.map(|wsc| GetBucketInfoWebsiteResult {
+				index_document: wsc.index_document,
+				error_document: wsc.error_document,
 			}),
-			keys: relevant_keys
-				.into_values()
-				.map(|key| {
-					let p = key.state.as_option().unwrap();
-					GetBucketInfoKey {
-						access_key_id: key.key_id,
-						name: p.name.get().to_string(),
-						permissions: p
-							.authorized_buckets
-							.get(&bucket.id)
-							.map(|p| ApiBucketKeyPerm {
-								read: p.allow_read,
-								write: p.allow_write,
-								owner: p.allow_owner,
-							})
-							.unwrap_or_default(),
-						bucket_local_aliases: p
-							.local_aliases
-							.items()
-							.iter()
-							.filter(|(_, _, b)| *b == Some(bucket.id))
-							.map(|(n, _, _)| n.to_string())
-							.collect::<Vec<_>>(),
-					}
-				})
-				.collect::<Vec<_>>(),
-			objects: counters.get(OBJECTS).cloned().unwrap_or_default(),
-			bytes: counters.get(BYTES).cloned().unwrap_or_default(),
-			unfinished_uploads: counters
-				.get(UNFINISHED_UPLOADS)
-				.cloned()
-				.unwrap_or_default(),
-			quotas: ApiBucketQuotas {
-				max_size: quotas.max_size,
-				max_objects: quotas.max_objects,
-			},
-		};
-
-	Ok(json_ok_response(&res)?)
+		keys: relevant_keys
+			.into_values()
+			.map(|key| {
+				let p = key.state.as_option().unwrap();
+				GetBucketInfoKey {
+					access_key_id: key.key_id,
+					name: p.name.get().to_string(),
+					permissions: p
+						.authorized_buckets
+						.get(&bucket.id)
+						.map(|p| ApiBucketKeyPerm {
+							read: p.allow_read,
+							write: p.allow_write,
+							owner: p.allow_owner,
+						})
+						.unwrap_or_default(),
+					bucket_local_aliases: p
+						.local_aliases
+						.items()
+						.iter()
+						.filter(|(_, _, b)| *b == Some(bucket.id))
+						.map(|(n, _, _)| n.to_string())
+						.collect::<Vec<_>>(),
+				}
+			})
+			.collect::<Vec<_>>(),
+		objects: counters.get(OBJECTS).cloned().unwrap_or_default(),
+		bytes: counters.get(BYTES).cloned().unwrap_or_default(),
+		unfinished_uploads: counters
+			.get(UNFINISHED_UPLOADS)
+			.cloned()
+			.unwrap_or_default(),
+		quotas: ApiBucketQuotas {
+			max_size: quotas.max_size,
+			max_objects: quotas.max_objects,
+		},
+		block_gc_delay_secs: *state.block_gc_delay_secs.get(),
+		mpu_ttl_days: *state.mpu_ttl_days.get(),
+	}
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct GetBucketInfoResult {
+pub struct GetBucketInfoResult {
 	id: String,
 	global_aliases: Vec<String>,
 	website_access: bool,
@@ -236,6 +270,8 @@ struct GetBucketInfoResult {
 	bytes: i64,
 	unfinished_uploads: i64,
 	quotas: ApiBucketQuotas,
+	block_gc_delay_secs: Option<u64>,
+	mpu_ttl_days: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -411,11 +447,18 @@ pub async fn handle_update_bucket(
 
 	if let Some(wa) = req.website_access {
 		if wa.enabled {
+			let routing_rules = state
+				.website_config
+				.get()
+				.as_ref()
+				.map(|w| w.routing_rules.clone())
+				.unwrap_or_default();
 			state.website_config.update(Some(WebsiteConfig {
 				index_document: wa.index_document.ok_or_bad_request(
 					"Please specify indexDocument when enabling website access.",
 				)?,
 				error_document: wa.error_document,
+				routing_rules,
 			}));
 		} else {
 			if wa.index_document.is_some() || wa.error_document.is_some() {
@@ -434,6 +477,17 @@ pub async fn handle_update_bucket(
 		});
 	}
 
+	if let Some(gc) = req.block_gc {
+		state.block_gc_delay_secs.update(gc.delay_secs);
+	}
+
+	if let Some(ttl) = req.mpu_ttl {
+		if ttl.days == Some(0) {
+			return Err(Error::bad_request("mpuTtl.days must be at least 1."));
+		}
+		state.mpu_ttl_days.update(ttl.days);
+	}
+
 	garage.bucket_table.insert(&bucket).await?;
 
 	bucket_info_results(garage, bucket_id).await
@@ -444,6 +498,8 @@ pub async fn handle_update_bucket(
 struct UpdateBucketRequest {
 	website_access: Option<UpdateBucketWebsiteAccess>,
 	quotas: Option<ApiBucketQuotas>,
+	block_gc: Option<UpdateBucketBlockGc>,
+	mpu_ttl: Option<UpdateBucketMpuTtl>,
 }
 
 #[derive(Deserialize)]
@@ -454,6 +510,18 @@ struct UpdateBucketWebsiteAccess {
 	error_document: Option<String>,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateBucketBlockGc {
+	delay_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateBucketMpuTtl {
+	days: Option<u64>,
+}
+
 // ---- BUCKET/KEY PERMISSIONS ----
 
 pub async fn handle_bucket_change_key_perm(
@@ -508,6 +576,227 @@ struct BucketKeyPermChangeRequest {
 	permissions: ApiBucketKeyPerm,
 }
 
+// ---- PRESIGNED URLS ----
+
+/// Maximum validity of a presigned URL, as accepted by the S3 API
+const MAX_PRESIGN_EXPIRES_SECS: u64 = 7 * 24 * 3600;
+
+pub async fn handle_presign_bucket_url(
+	garage: &Arc<Garage>,
+	req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+	let req = parse_json_body::<PresignBucketUrlRequest>(req).await?;
+
+	let bucket_id = parse_bucket_id(&req.bucket_id)?;
+	let bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+	let state = bucket.state.as_option().unwrap();
+
+	let key = garage
+		.key_helper()
+		.get_existing_key(&req.access_key_id)
+		.await?;
+
+	let method = req.method.to_uppercase();
+	let allowed = match method.as_str() {
+		"GET" | "HEAD" => key.allow_read(&bucket.id),
+		"PUT" | "DELETE" => key.allow_write(&bucket.id),
+		_ => {
+			return Err(Error::bad_request(format!(
+				"Unsupported method for presigned URL: {}",
+				req.method
+			)))
+		}
+	};
+	if !allowed {
+		return Err(Error::bad_request(format!(
+			"Key {} does not have the permission to {} objects in this bucket",
+			key.key_id, method
+		)));
+	}
+
+	if req.expires_in_secs == 0 || req.expires_in_secs > MAX_PRESIGN_EXPIRES_SECS {
+		return Err(Error::bad_request(format!(
+			"expiresInSecs must be between 1 and {}",
+			MAX_PRESIGN_EXPIRES_SECS
+		)));
+	}
+
+	// Presigned URLs use path-style addressing, with a global alias of the bucket
+	// or a local alias of the key used for signing
+	let bucket_name = state
+		.aliases
+		.items()
+		.iter()
+		.find(|(_, _, active)| *active)
+		.map(|(name, _, _)| name.to_string())
+		.or_else(|| {
+			state
+				.local_aliases
+				.items()
+				.iter()
+				.find(|((k, _), _, active)| *active && *k == key.key_id)
+				.map(|((_, name), _, _)| name.to_string())
+		})
+		.ok_or_bad_request("Bucket has no global alias nor local alias for this key")?;
+
+	let endpoint = match (&req.endpoint, &garage.config.s3_api.root_domain) {
+		(Some(endpoint), _) => endpoint.clone(),
+		(None, Some(root_domain)) => format!("https://{}", root_domain.trim_start_matches('.')),
+		(None, None) => {
+			return Err(Error::bad_request(
+				"endpoint must be given as s3_api.root_domain is not set in the configuration",
+			))
+		}
+	};
+	let endpoint = endpoint
+		.parse::<hyper::Uri>()
+		.ok()
+		.filter(|uri| uri.scheme().is_some() && uri.authority().is_some())
+		.ok_or_bad_request("Invalid endpoint, expected for instance https://s3.example.com")?;
+	let host = endpoint.authority().unwrap().as_str();
+
+	let mut headers = vec![];
+	if let Some(content_type) = &req.content_type {
+		headers.push(("content-type", content_type.as_str()));
+	}
+
+	let date = chrono::Utc::now();
+	let path_and_query = presign_s3_path_and_query(
+		&method.parse().unwrap(),
+		host,
+		&format!("/{}/{}", bucket_name, uri_encode(&req.key, false)),
+		&headers,
+		&key.key_id,
+		&key.params().unwrap().secret_key,
+		&garage.config.s3_api.s3_region,
+		&date,
+		req.expires_in_secs,
+	);
+
+	let res = PresignBucketUrlResponse {
+		url: format!(
+			"{}://{}{}",
+			endpoint.scheme_str().unwrap(),
+			host,
+			path_and_query
+		),
+		expires_at: msec_to_rfc3339(date.timestamp_millis() as u64 + req.expires_in_secs * 1000),
+	};
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PresignBucketUrlRequest {
+	bucket_id: String,
+	access_key_id: String,
+	key: String,
+	method: String,
+	expires_in_secs: u64,
+	content_type: Option<String>,
+	endpoint: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PresignBucketUrlResponse {
+	url: String,
+	expires_at: String,
+}
+
+// ---- EVENT NOTIFICATIONS ----
+
+pub async fn handle_get_bucket_notification(
+	garage: &Arc<Garage>,
+	id: String,
+) -> Result<Response<Body>, Error> {
+	let bucket_id = parse_bucket_id(&id)?;
+	let bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+	let state = bucket.state.as_option().unwrap();
+
+	let webhooks = match state.notifications.get() {
+		Some(config) => config
+			.webhooks
+			.iter()
+			.map(|w| ApiNotificationWebhook {
+				url: w.url.clone(),
+				events: w.events.clone(),
+				key_prefix: w.key_prefix.clone(),
+			})
+			.collect(),
+		None => vec![],
+	};
+
+	Ok(json_ok_response(&ApiBucketNotification { webhooks })?)
+}
+
+pub async fn handle_put_bucket_notification(
+	garage: &Arc<Garage>,
+	id: String,
+	req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+	let req = parse_json_body::<ApiBucketNotification>(req).await?;
+	let bucket_id = parse_bucket_id(&id)?;
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+	let state = bucket.state.as_option_mut().unwrap();
+
+	let mut webhooks = Vec::with_capacity(req.webhooks.len());
+	for w in req.webhooks {
+		let url = w
+			.url
+			.parse::<hyper::Uri>()
+			.ok()
+			.filter(|u| matches!(u.scheme_str(), Some("http") | Some("https")))
+			.ok_or_bad_request(format!("Invalid webhook URL: {}", w.url))?;
+		if w.events.is_empty() {
+			return Err(Error::bad_request(format!(
+				"No events specified for webhook {}",
+				url
+			)));
+		}
+		webhooks.push(NotificationWebhook {
+			url: w.url,
+			events: w.events,
+			key_prefix: w.key_prefix,
+		});
+	}
+
+	if webhooks.is_empty() {
+		state.notifications.update(None);
+	} else {
+		state
+			.notifications
+			.update(Some(BucketNotificationConfig { webhooks }));
+	}
+	garage.bucket_table.insert(&bucket).await?;
+
+	handle_get_bucket_notification(garage, id).await
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiBucketNotification {
+	webhooks: Vec<ApiNotificationWebhook>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiNotificationWebhook {
+	url: String,
+	events: Vec<NotificationEventType>,
+	key_prefix: Option<String>,
+}
+
 // ---- BUCKET ALIASES ----
 
 pub async fn handle_global_alias_bucket(
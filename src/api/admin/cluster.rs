@@ -7,8 +7,10 @@ use serde::{Deserialize, Serialize};
 
 use garage_util::crdt::*;
 use garage_util::data::*;
+use garage_util::time::*;
 
 use garage_rpc::layout::*;
+use garage_rpc::system::ClusterHealth;
 
 use garage_model::garage::Garage;
 
@@ -49,6 +51,23 @@ pub async fn handle_get_cluster_health(garage: &Arc<Garage>) -> Result<Response<
 	Ok(json_ok_response(&health)?)
 }
 
+pub async fn handle_get_cluster_health_history(
+	garage: &Arc<Garage>,
+	count: Option<usize>,
+) -> Result<Response<Body>, Error> {
+	let res = garage
+		.system
+		.health_history(count.unwrap_or(usize::MAX))
+		.into_iter()
+		.map(|(time, health)| ClusterHealthSample {
+			time: msec_to_rfc3339(time),
+			health,
+		})
+		.collect::<Vec<_>>();
+
+	Ok(json_ok_response(&res)?)
+}
+
 pub async fn handle_connect_cluster_nodes(
 	garage: &Arc<Garage>,
 	req: Request<Body>,
@@ -80,8 +99,11 @@ pub async fn handle_get_cluster_layout(garage: &Arc<Garage>) -> Result<Response<
 }
 
 fn get_cluster_layout(garage: &Arc<Garage>) -> GetClusterLayoutResponse {
-	let layout = garage.system.get_cluster_layout();
+	format_cluster_layout(&garage.system.get_cluster_layout())
+}
 
+/// Description of a cluster layout in the responses of the admin API
+pub fn format_cluster_layout(layout: &ClusterLayout) -> GetClusterLayoutResponse {
 	GetClusterLayoutResponse {
 		version: layout.version,
 		roles: layout
@@ -113,6 +135,13 @@ struct GetClusterStatusResponse {
 	layout: GetClusterLayoutResponse,
 }
 
+#[derive(Serialize)]
+struct ClusterHealthSample {
+	time: String,
+	#[serde(flatten)]
+	health: ClusterHealth,
+}
+
 #[derive(Serialize)]
 struct ConnectClusterNodesResponse {
 	success: bool,
@@ -121,7 +150,7 @@ struct ConnectClusterNodesResponse {
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct GetClusterLayoutResponse {
+pub struct GetClusterLayoutResponse {
 	version: u64,
 	roles: HashMap<String, Option<NodeRole>>,
 	staged_role_changes: HashMap<String, Option<NodeRole>>,
@@ -169,7 +198,7 @@ pub async fn handle_apply_cluster_layout(
 	let param = parse_json_body::<ApplyRevertLayoutRequest>(req).await?;
 
 	let layout = garage.system.get_cluster_layout();
-	let layout = layout.apply_staged_changes(Some(param.version))?;
+	let (layout, _) = layout.apply_staged_changes(Some(param.version))?;
 	garage.system.update_cluster_layout(&layout).await?;
 
 	Ok(Response::builder()
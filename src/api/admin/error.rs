@@ -27,6 +27,10 @@ pub enum Error {
 		_0
 	)]
 	KeyAlreadyExists(String),
+
+	/// The worker does not exist or has exited
+	#[error(display = "Worker not found: {}", _0)]
+	NoSuchWorker(usize),
 }
 
 impl<T> From<T> for Error
@@ -58,6 +62,7 @@ impl Error {
 			Error::Common(c) => c.aws_code(),
 			Error::NoSuchAccessKey(_) => "NoSuchAccessKey",
 			Error::KeyAlreadyExists(_) => "KeyAlreadyExists",
+			Error::NoSuchWorker(_) => "NoSuchWorker",
 		}
 	}
 }
@@ -69,6 +74,7 @@ impl ApiError for Error {
 			Error::Common(c) => c.http_status_code(),
 			Error::NoSuchAccessKey(_) => StatusCode::NOT_FOUND,
 			Error::KeyAlreadyExists(_) => StatusCode::CONFLICT,
+			Error::NoSuchWorker(_) => StatusCode::NOT_FOUND,
 		}
 	}
 
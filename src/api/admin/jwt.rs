@@ -0,0 +1,186 @@
+//! Authentication to the admin API with JSON Web Tokens
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+
+use garage_util::config::AdminConfig;
+use garage_util::error::Error as GarageError;
+
+use crate::admin::error::*;
+use crate::admin::router::Endpoint;
+
+/// Action that allows calling the admin API endpoints that only read the state of the cluster
+const ACTION_READ: &str = "admin:read";
+/// Action that allows calling all of the admin API endpoints
+const ACTION_WRITE: &str = "admin:write";
+
+/// Subject of the JWT used to call the admin API, as given in its `sub` claim.
+/// It is added to the extensions of the request once the token is checked.
+#[derive(Clone)]
+pub struct JwtSubject(pub String);
+
+/// Checks the JWTs given as bearer tokens to the admin API, signed either with
+/// HS256 using `admin_jwt_secret`, or with RS256 using the private key matching
+/// `admin_jwt_public_key_file`
+pub struct JwtAuth {
+	hs256_key: Option<DecodingKey>,
+	rs256_key: Option<DecodingKey>,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+	sub: String,
+	action: ActionClaim,
+}
+
+/// The `action` claim can be a single action or a list of actions
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ActionClaim {
+	One(String),
+	Many(Vec<String>),
+}
+
+impl ActionClaim {
+	fn allows(&self, action: &str) -> bool {
+		match self {
+			Self::One(a) => a == action,
+			Self::Many(v) => v.iter().any(|a| a == action),
+		}
+	}
+}
+
+impl JwtAuth {
+	/// Load the keys used to check JWTs, if any is configured
+	pub fn new(cfg: &AdminConfig) -> Result<Option<Self>, GarageError> {
+		let hs256_key = cfg
+			.admin_jwt_secret
+			.as_ref()
+			.map(|s| DecodingKey::from_secret(s.as_bytes()));
+		let rs256_key = match &cfg.admin_jwt_public_key_file {
+			Some(path) => {
+				let pem = std::fs::read(path).map_err(|e| {
+					GarageError::Message(format!(
+						"Unable to read admin JWT public key {}: {}",
+						path.display(),
+						e
+					))
+				})?;
+				let key = DecodingKey::from_rsa_pem(&pem).map_err(|e| {
+					GarageError::Message(format!(
+						"Invalid admin JWT public key {}: {}",
+						path.display(),
+						e
+					))
+				})?;
+				Some(key)
+			}
+			None => None,
+		};
+
+		if hs256_key.is_none() && rs256_key.is_none() {
+			return Ok(None);
+		}
+		Ok(Some(Self {
+			hs256_key,
+			rs256_key,
+		}))
+	}
+
+	/// Check that a JWT is valid and allows calling an endpoint,
+	/// and return its subject
+	pub fn check(&self, token: &str, endpoint: &Endpoint) -> Result<JwtSubject, Error> {
+		let header = jsonwebtoken::decode_header(token)
+			.map_err(|_| Error::forbidden("Invalid authorization token provided"))?;
+		let key = match header.alg {
+			Algorithm::HS256 => self.hs256_key.as_ref(),
+			Algorithm::RS256 => self.rs256_key.as_ref(),
+			_ => None,
+		}
+		.ok_or_else(|| {
+			Error::forbidden(format!(
+				"JWTs signed with {:?} are not accepted by this server",
+				header.alg
+			))
+		})?;
+
+		let mut validation = Validation::new(header.alg);
+		validation.set_required_spec_claims(&["exp", "sub"]);
+		let claims = jsonwebtoken::decode::<Claims>(token, key, &validation)
+			.map_err(|e| Error::forbidden(format!("Invalid JWT provided: {}", e)))?
+			.claims;
+
+		let allowed = claims.action.allows(ACTION_WRITE)
+			|| (endpoint.is_read_only() && claims.action.allows(ACTION_READ));
+		if !allowed {
+			return Err(Error::forbidden(format!(
+				"The JWT does not allow the action required by this endpoint ({})",
+				if endpoint.is_read_only() {
+					ACTION_READ
+				} else {
+					ACTION_WRITE
+				}
+			)));
+		}
+
+		Ok(JwtSubject(claims.sub))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use jsonwebtoken::{encode, EncodingKey, Header};
+	use serde_json::json;
+
+	fn token(secret: &[u8], claims: serde_json::Value) -> String {
+		encode(
+			&Header::new(Algorithm::HS256),
+			&claims,
+			&EncodingKey::from_secret(secret),
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn test_jwt_auth() {
+		let auth = JwtAuth {
+			hs256_key: Some(DecodingKey::from_secret(b"s3cr3t")),
+			rs256_key: None,
+		};
+		let exp = jsonwebtoken::get_current_timestamp() + 60;
+		let read = Endpoint::GetClusterStatus;
+		let write = Endpoint::CreateBucket;
+
+		let t = token(
+			b"s3cr3t",
+			json!({"sub": "a", "exp": exp, "action": "admin:read"}),
+		);
+		assert_eq!(auth.check(&t, &read).unwrap().0, "a");
+		assert!(auth.check(&t, &write).is_err());
+
+		let t = token(
+			b"s3cr3t",
+			json!({"sub": "a", "exp": exp, "action": ["admin:write"]}),
+		);
+		assert!(auth.check(&t, &read).is_ok());
+		assert!(auth.check(&t, &write).is_ok());
+
+		let t = token(
+			b"other",
+			json!({"sub": "a", "exp": exp, "action": "admin:write"}),
+		);
+		assert!(auth.check(&t, &read).is_err());
+
+		let t = token(
+			b"s3cr3t",
+			json!({"sub": "a", "exp": exp - 3600, "action": "admin:write"}),
+		);
+		assert!(auth.check(&t, &read).is_err());
+
+		let t = token(b"s3cr3t", json!({"sub": "a", "action": "admin:write"}));
+		assert!(auth.check(&t, &read).is_err());
+
+		let t = token(b"s3cr3t", json!({"sub": "a", "exp": exp}));
+		assert!(auth.check(&t, &read).is_err());
+	}
+}
@@ -4,25 +4,44 @@ use std::sync::Arc;
 use hyper::{Body, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 
+use garage_util::data::*;
+
 use garage_table::*;
 
+use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::key_table::*;
 
 use crate::admin::error::*;
+use crate::admin::pagination::Pagination;
 use crate::helpers::{json_ok_response, parse_json_body};
 
-pub async fn handle_list_keys(garage: &Arc<Garage>) -> Result<Response<Body>, Error> {
-	let res = garage
+pub async fn handle_list_keys(
+	garage: &Arc<Garage>,
+	limit: Option<usize>,
+	continuation_token: Option<String>,
+) -> Result<Response<Body>, Error> {
+	let pagination = Pagination::parse(limit, continuation_token)?;
+	let start = pagination
+		.start
+		.clone()
+		.map(String::from_utf8)
+		.transpose()
+		.ok_or_bad_request("Invalid continuation token")?;
+
+	let mut keys = garage
 		.key_table
 		.get_range(
 			&EmptyKey,
-			None,
+			start,
 			Some(KeyFilter::Deleted(DeletedFilter::NotDeleted)),
-			10000,
+			pagination.count(),
 			EnumerationOrder::Forward,
 		)
-		.await?
+		.await?;
+	let next = pagination.truncate(&mut keys, |k| k.key_id.as_bytes());
+
+	let res = keys
 		.iter()
 		.map(|k| ListKeyResultItem {
 			id: k.key_id.to_string(),
@@ -30,13 +49,13 @@ pub async fn handle_list_keys(garage: &Arc<Garage>) -> Result<Response<Body>, Er
 		})
 		.collect::<Vec<_>>();
 
-	Ok(json_ok_response(&res)?)
+	pagination.response(res, next)
 }
 
 #[derive(Serialize)]
-struct ListKeyResultItem {
-	id: String,
-	name: String,
+pub struct ListKeyResultItem {
+	pub id: String,
+	pub name: String,
 }
 
 pub async fn handle_get_key_info(
@@ -124,6 +143,17 @@ pub async fn handle_update_key(
 			key_state.allow_create_bucket.update(false);
 		}
 	}
+	if let Some(rl) = req.rate_limit {
+		if rl.requests_per_second == Some(0) || rl.bandwidth_bytes_per_second == Some(0) {
+			return Err(Error::bad_request(
+				"Rate limits must be at least 1, or null to remove them.",
+			));
+		}
+		key_state.rate_limit_rps.update(rl.requests_per_second);
+		key_state
+			.rate_limit_bandwidth_bps
+			.update(rl.bandwidth_bytes_per_second);
+	}
 
 	garage.key_table.insert(&key).await?;
 
@@ -131,10 +161,12 @@ pub async fn handle_update_key(
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct UpdateKeyRequest {
 	name: Option<String>,
 	allow: Option<KeyPerm>,
 	deny: Option<KeyPerm>,
+	rate_limit: Option<ApiKeyRateLimit>,
 }
 
 pub async fn handle_delete_key(garage: &Arc<Garage>, id: String) -> Result<Response<Body>, Error> {
@@ -175,13 +207,25 @@ async fn key_info_results(garage: &Arc<Garage>, key: Key) -> Result<Response<Bod
 		}
 	}
 
-	let res = GetKeyInfoResult {
+	Ok(json_ok_response(&key_info_result(&key, relevant_buckets))?)
+}
+
+/// Description of a key in the responses of the admin API,
+/// from the key and the buckets it has access to or aliases for
+pub fn key_info_result(key: &Key, relevant_buckets: HashMap<Uuid, Bucket>) -> GetKeyInfoResult {
+	let key_state = key.state.as_option().unwrap();
+
+	GetKeyInfoResult {
 		name: key_state.name.get().clone(),
 		access_key_id: key.key_id.clone(),
 		secret_access_key: key_state.secret_key.clone(),
 		permissions: KeyPerm {
 			create_bucket: *key_state.allow_create_bucket.get(),
 		},
+		rate_limit: ApiKeyRateLimit {
+			requests_per_second: *key_state.rate_limit_rps.get(),
+			bandwidth_bytes_per_second: *key_state.rate_limit_bandwidth_bps.get(),
+		},
 		buckets: relevant_buckets
 			.into_values()
 			.map(|bucket| {
@@ -214,18 +258,17 @@ async fn key_info_results(garage: &Arc<Garage>, key: Key) -> Result<Response<Bod
 				}
 			})
 			.collect::<Vec<_>>(),
-	};
-
-	Ok(json_ok_response(&res)?)
+	}
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct GetKeyInfoResult {
+pub struct GetKeyInfoResult {
 	name: String,
 	access_key_id: String,
 	secret_access_key: String,
 	permissions: KeyPerm,
+	rate_limit: ApiKeyRateLimit,
 	buckets: Vec<KeyInfoBucketResult>,
 }
 
@@ -236,6 +279,13 @@ struct KeyPerm {
 	create_bucket: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiKeyRateLimit {
+	requests_per_second: Option<u32>,
+	bandwidth_bytes_per_second: Option<u64>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct KeyInfoBucketResult {
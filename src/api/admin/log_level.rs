@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use hyper::{Body, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::{reload, Registry};
+
+use crate::admin::error::*;
+use crate::helpers::*;
+
+/// Filter of the log messages of this node, whose levels can be changed
+/// without restarting the node.
+///
+/// The levels set through the admin API are added after the directives
+/// of `RUST_LOG`, so they take precedence over them. They are not persisted
+/// and are lost when the node restarts.
+pub struct LogFilter {
+	handle: reload::Handle<EnvFilter, Registry>,
+	directives: String,
+	/// Levels set through the admin API, by module (empty for all modules)
+	levels: Mutex<BTreeMap<String, LevelFilter>>,
+}
+
+impl LogFilter {
+	/// Create the handle of the log filter, from the reload handle of a filter
+	/// built from the given directives
+	pub fn new(handle: reload::Handle<EnvFilter, Registry>, directives: String) -> Self {
+		Self {
+			handle,
+			directives,
+			levels: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	fn info(&self) -> Result<LogLevelResponse, Error> {
+		let filter = self
+			.handle
+			.with_current(|filter| filter.to_string())
+			.ok_or_internal_error("Unable to read the log filter")?;
+		let levels = self
+			.levels
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|(module, level)| LogLevel {
+				module: Some(module.clone()).filter(|m| !m.is_empty()),
+				level: Some(level.to_string().to_lowercase()),
+			})
+			.collect();
+		Ok(LogLevelResponse { filter, levels })
+	}
+
+	fn set_level(&self, module: Option<String>, level: Option<LevelFilter>) -> Result<(), Error> {
+		let mut levels = self.levels.lock().unwrap();
+
+		let mut new_levels = levels.clone();
+		let module = module.unwrap_or_default();
+		match level {
+			Some(level) => new_levels.insert(module, level),
+			None => new_levels.remove(&module),
+		};
+
+		let directives = std::iter::once(self.directives.clone())
+			.chain(
+				new_levels
+					.iter()
+					.map(|(module, level)| match module.as_str() {
+						"" => level.to_string(),
+						_ => format!("{}={}", module, level),
+					}),
+			)
+			.filter(|d| !d.is_empty())
+			.collect::<Vec<_>>()
+			.join(",");
+		let filter = EnvFilter::try_new(&directives)
+			.map_err(|e| Error::bad_request(format!("Invalid log filter: {}", e)))?;
+		self.handle
+			.reload(filter)
+			.ok_or_internal_error("Unable to change the log filter")?;
+
+		*levels = new_levels;
+		Ok(())
+	}
+}
+
+pub async fn handle_get_log_level(log_filter: &LogFilter) -> Result<Response<Body>, Error> {
+	Ok(json_ok_response(&log_filter.info()?)?)
+}
+
+pub async fn handle_put_log_level(
+	log_filter: &LogFilter,
+	req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+	let req = parse_json_body::<LogLevel>(req).await?;
+
+	if let Some(module) = &req.module {
+		let valid = !module.is_empty()
+			&& module
+				.chars()
+				.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+		if !valid {
+			return Err(Error::bad_request(format!(
+				"Invalid module name: {}",
+				module
+			)));
+		}
+	}
+	let level = req
+		.level
+		.as_deref()
+		.map(|l| l.parse::<LevelFilter>())
+		.transpose()
+		.map_err(|_| {
+			Error::bad_request(
+				"Invalid log level, expected one of: trace, debug, info, warn, error, off",
+			)
+		})?;
+
+	log_filter.set_level(req.module, level)?;
+	info!("Log filter changed through the admin API");
+
+	Ok(json_ok_response(&log_filter.info()?)?)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LogLevelResponse {
+	filter: String,
+	levels: Vec<LogLevel>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogLevel {
+	module: Option<String>,
+	level: Option<String>,
+}
@@ -1,7 +1,16 @@
 pub mod api_server;
+mod audit_log;
 mod error;
+mod jwt;
+pub mod log_level;
+mod pagination;
 mod router;
 
 mod bucket;
 mod cluster;
 mod key;
+mod worker;
+
+pub use bucket::{bucket_info_result, list_bucket_result_item};
+pub use cluster::format_cluster_layout;
+pub use key::{key_info_result, ListKeyResultItem};
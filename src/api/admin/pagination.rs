@@ -0,0 +1,104 @@
+use base64::prelude::*;
+use hyper::{Body, Response};
+use serde::Serialize;
+
+use crate::admin::error::*;
+use crate::helpers::json_ok_response;
+
+/// Maximum number of items returned by the list endpoints of the admin API
+const MAX_LIST_ITEMS: usize = 10000;
+
+/// Pagination parameters of a list endpoint of the admin API.
+///
+/// The continuation token is the base64-encoded sort key of the first item
+/// of the next page, which can therefore be used directly as the (inclusive)
+/// start of the range read in the table.
+pub(crate) struct Pagination {
+	pub(crate) start: Option<Vec<u8>>,
+	pub(crate) limit: usize,
+	/// Whether pagination was requested by the client: if not, the items
+	/// are returned as a plain list, as before pagination was implemented
+	paginated: bool,
+}
+
+impl Pagination {
+	pub(crate) fn parse(
+		limit: Option<usize>,
+		continuation_token: Option<String>,
+	) -> Result<Self, Error> {
+		if limit == Some(0) {
+			return Err(Error::bad_request("limit must be at least 1"));
+		}
+		let start = continuation_token
+			.map(|t| BASE64_URL_SAFE_NO_PAD.decode(t))
+			.transpose()
+			.ok_or_bad_request("Invalid continuation token")?;
+		Ok(Self {
+			paginated: limit.is_some() || start.is_some(),
+			start,
+			limit: std::cmp::min(limit.unwrap_or(MAX_LIST_ITEMS), MAX_LIST_ITEMS),
+		})
+	}
+
+	/// Number of items to read in the table: one more than the limit,
+	/// to know whether there is a next page and where it starts
+	pub(crate) fn count(&self) -> usize {
+		self.limit + 1
+	}
+
+	/// Remove the items that go beyond the limit, and return the continuation
+	/// token of the next page if there are such items
+	pub(crate) fn truncate<T>(
+		&self,
+		items: &mut Vec<T>,
+		sort_key: impl Fn(&T) -> &[u8],
+	) -> Option<String> {
+		let next = items
+			.get(self.limit)
+			.map(|x| BASE64_URL_SAFE_NO_PAD.encode(sort_key(x)));
+		items.truncate(self.limit);
+		next
+	}
+
+	pub(crate) fn response<T: Serialize>(
+		&self,
+		items: Vec<T>,
+		next_continuation_token: Option<String>,
+	) -> Result<Response<Body>, Error> {
+		if self.paginated {
+			Ok(json_ok_response(&ListPage {
+				items,
+				next_continuation_token,
+			})?)
+		} else {
+			Ok(json_ok_response(&items)?)
+		}
+	}
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListPage<T> {
+	items: Vec<T>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	next_continuation_token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_pagination() {
+		let pagination = Pagination::parse(Some(2), None).unwrap();
+		let mut items = vec!["a", "b", "c"];
+		let next = pagination.truncate(&mut items, |x| x.as_bytes());
+		assert_eq!(items, vec!["a", "b"]);
+
+		let pagination = Pagination::parse(Some(2), next).unwrap();
+		assert_eq!(pagination.start.as_deref(), Some(&b"c"[..]));
+
+		assert!(Pagination::parse(Some(0), None).is_err());
+		assert!(Pagination::parse(None, Some("!!".into())).is_err());
+	}
+}
@@ -19,17 +19,40 @@ pub enum Endpoint {
 	Options,
 	CheckDomain,
 	Health,
+	Livez,
+	Readyz,
 	Metrics,
 	GetClusterStatus,
 	GetClusterHealth,
+	GetClusterHealthHistory {
+		count: Option<usize>,
+	},
 	ConnectClusterNodes,
+	GetLogLevel,
+	PutLogLevel,
+	// Workers
+	PauseWorker {
+		id: String,
+	},
+	ResumeWorker {
+		id: String,
+	},
+	GetWorkerVariable {
+		variable: Option<String>,
+	},
+	SetWorkerVariable {
+		variable: String,
+	},
 	// Layout
 	GetClusterLayout,
 	UpdateClusterLayout,
 	ApplyClusterLayout,
 	RevertClusterLayout,
 	// Keys
-	ListKeys,
+	ListKeys {
+		limit: Option<usize>,
+		continuation_token: Option<String>,
+	},
 	CreateKey,
 	ImportKey,
 	GetKeyInfo {
@@ -43,7 +66,10 @@ pub enum Endpoint {
 		id: String,
 	},
 	// Buckets
-	ListBuckets,
+	ListBuckets {
+		limit: Option<usize>,
+		continuation_token: Option<String>,
+	},
 	CreateBucket,
 	GetBucketInfo {
 		id: Option<String>,
@@ -58,6 +84,13 @@ pub enum Endpoint {
 	// Bucket-Key Permissions
 	BucketAllowKey,
 	BucketDenyKey,
+	PresignBucketUrl,
+	GetBucketNotification {
+		id: String,
+	},
+	PutBucketNotification {
+		id: String,
+	},
 	// Bucket aliases
 	GlobalAliasBucket {
 		id: String,
@@ -94,10 +127,20 @@ impl Endpoint {
 			OPTIONS _ => Options,
 			GET "/check" => CheckDomain,
 			GET "/health" => Health,
+			GET "/livez" => Livez,
+			GET "/readyz" => Readyz,
 			GET "/metrics" => Metrics,
 			GET "/v0/status" => GetClusterStatus,
 			GET "/v0/health" => GetClusterHealth,
+			GET "/v0/health/history" => GetClusterHealthHistory (opt_parse::count),
 			POST "/v0/connect" => ConnectClusterNodes,
+			GET "/v0/log-level" => GetLogLevel,
+			PUT "/v0/log-level" => PutLogLevel,
+			// Workers
+			PUT "/v0/worker/pause" => PauseWorker (query::id),
+			PUT "/v0/worker/resume" => ResumeWorker (query::id),
+			GET "/v0/worker/variable" => GetWorkerVariable (query_opt::variable),
+			PUT "/v0/worker/variable" => SetWorkerVariable (query::variable),
 			// Layout endpoints
 			GET "/v0/layout" => GetClusterLayout,
 			POST "/v0/layout" => UpdateClusterLayout,
@@ -110,17 +153,21 @@ impl Endpoint {
 			POST "/v0/key" => CreateKey,
 			POST "/v0/key/import" => ImportKey,
 			DELETE "/v0/key" if id => DeleteKey (query::id),
-			GET "/v0/key" => ListKeys,
+			GET "/v0/key" => ListKeys (opt_parse::limit, query_opt::continuation_token),
 			// Bucket endpoints
 			GET "/v0/bucket" if id => GetBucketInfo (query_opt::id, query_opt::global_alias),
 			GET "/v0/bucket" if global_alias => GetBucketInfo (query_opt::id, query_opt::global_alias),
-			GET "/v0/bucket" => ListBuckets,
+			GET "/v0/bucket" => ListBuckets (opt_parse::limit, query_opt::continuation_token),
 			POST "/v0/bucket" => CreateBucket,
 			DELETE "/v0/bucket" if id => DeleteBucket (query::id),
 			PUT "/v0/bucket" if id => UpdateBucket (query::id),
 			// Bucket-key permissions
 			POST "/v0/bucket/allow" => BucketAllowKey,
 			POST "/v0/bucket/deny" => BucketDenyKey,
+			POST "/v0/bucket/presign" => PresignBucketUrl,
+			// Bucket event notifications
+			GET "/v0/bucket/notification" => GetBucketNotification (query::id),
+			PUT "/v0/bucket/notification" => PutBucketNotification (query::id),
 			// Bucket aliases
 			PUT "/v0/bucket/alias/global" => GlobalAliasBucket (query::id, query::alias),
 			DELETE "/v0/bucket/alias/global" => GlobalUnaliasBucket (query::id, query::alias),
@@ -138,11 +185,31 @@ impl Endpoint {
 	pub fn authorization_type(&self) -> Authorization {
 		match self {
 			Self::Health => Authorization::None,
+			Self::Livez => Authorization::None,
+			Self::Readyz => Authorization::None,
 			Self::CheckDomain => Authorization::None,
 			Self::Metrics => Authorization::MetricsToken,
 			_ => Authorization::AdminToken,
 		}
 	}
+	/// Whether the operation only reads the state of the cluster, and can be
+	/// performed with an admin JWT that only allows the `admin:read` action
+	pub fn is_read_only(&self) -> bool {
+		matches!(
+			self,
+			Self::GetClusterStatus
+				| Self::GetClusterHealth
+				| Self::GetClusterHealthHistory { .. }
+				| Self::GetLogLevel
+				| Self::GetWorkerVariable { .. }
+				| Self::GetClusterLayout
+				| Self::GetKeyInfo { .. }
+				| Self::ListKeys { .. }
+				| Self::GetBucketInfo { .. }
+				| Self::ListBuckets { .. }
+				| Self::GetBucketNotification { .. }
+		)
+	}
 }
 
 generateQueryParameters! {
@@ -153,6 +220,10 @@ generateQueryParameters! {
 		"search" => search,
 		"globalAlias" => global_alias,
 		"alias" => alias,
-		"accessKeyId" => access_key_id
+		"accessKeyId" => access_key_id,
+		"limit" => limit,
+		"continuationToken" => continuation_token,
+		"variable" => variable,
+		"count" => count
 	]
 }
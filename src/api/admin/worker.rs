@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use garage_util::background::BackgroundRunner;
+
+use garage_model::garage::Garage;
+
+use crate::admin::error::*;
+use crate::helpers::{json_ok_response, parse_json_body};
+
+pub async fn handle_set_worker_paused(
+	background: &BackgroundRunner,
+	id: String,
+	paused: bool,
+) -> Result<Response<Body>, Error> {
+	let tid = id.parse::<usize>().ok_or_bad_request("Invalid worker id")?;
+
+	if !background.set_worker_paused(tid, paused) {
+		return Err(Error::NoSuchWorker(tid));
+	}
+	let name = background
+		.get_worker_info()
+		.get(&tid)
+		.map(|info| info.name.clone())
+		.unwrap_or_default();
+	info!(
+		"Worker {} (TID {}) {} through the admin API",
+		name,
+		tid,
+		if paused { "paused" } else { "resumed" }
+	);
+
+	Ok(json_ok_response(&WorkerPausedResponse {
+		id: tid,
+		name,
+		paused,
+	})?)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkerPausedResponse {
+	id: usize,
+	name: String,
+	paused: bool,
+}
+
+pub async fn handle_get_worker_variable(
+	garage: &Arc<Garage>,
+	variable: Option<String>,
+) -> Result<Response<Body>, Error> {
+	let vars = match variable {
+		Some(variable) => {
+			let value = garage
+				.bg_vars
+				.get(&variable)
+				.map_err(|e| Error::bad_request(format!("{}: {}", variable, e)))?;
+			BTreeMap::from([(variable, value)])
+		}
+		None => garage
+			.bg_vars
+			.get_all()
+			.into_iter()
+			.map(|(k, v)| (k.to_string(), v))
+			.collect(),
+	};
+
+	Ok(json_ok_response(&vars)?)
+}
+
+pub async fn handle_set_worker_variable(
+	garage: &Arc<Garage>,
+	variable: String,
+	req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+	let req = parse_json_body::<SetWorkerVariableRequest>(req).await?;
+
+	garage
+		.bg_vars
+		.set(&variable, &req.value)
+		.map_err(|e| Error::bad_request(format!("{}: {}", variable, e)))?;
+	info!(
+		"Variable {} set to {} through the admin API",
+		variable, req.value
+	);
+
+	let value = garage.bg_vars.get(&variable)?;
+	Ok(json_ok_response(&BTreeMap::from([(variable, value)]))?)
+}
+
+#[derive(Deserialize)]
+struct SetWorkerVariableRequest {
+	value: String,
+}
@@ -1,20 +1,25 @@
 use std::fs::{self, Permissions};
+use std::net::{IpAddr, SocketAddr};
 use std::os::unix::fs::PermissionsExt;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use chrono::Utc;
 
 use futures::future::Future;
 
+use hyper::body::HttpBody;
 use hyper::header::HeaderValue;
 use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server};
+use hyper::{Body, Request, Response, Server, Uri};
 use hyper::{HeaderMap, StatusCode};
 
 use hyperlocal::UnixServerExt;
+use ipnet::IpNet;
 
 use tokio::net::UnixStream;
+use tokio::sync::watch;
 
 use opentelemetry::{
 	global,
@@ -28,6 +33,46 @@ use garage_util::forwarded_headers;
 use garage_util::metrics::{gen_trace_id, RecordDuration};
 use garage_util::socket_address::UnixOrTCPSocketAddress;
 
+use crate::access_log::{AccessLog, AccessLogEntry};
+
+/// Address of the client that made a request,
+/// added to the extensions of each request before it is handled
+#[derive(Clone)]
+pub(crate) struct RemoteAddr {
+	/// Address of the client: the address given in the `X-Forwarded-For`
+	/// header if the peer is a trusted proxy, the address of the peer otherwise
+	pub client: Option<IpAddr>,
+}
+
+impl RemoteAddr {
+	fn new(peer: Option<IpAddr>, forwarded_for: Option<IpAddr>, trusted_proxies: &[IpNet]) -> Self {
+		// Requests received on a Unix socket can only come from a local reverse proxy
+		let trusted = match peer {
+			None => true,
+			Some(ip) => trusted_proxies.iter().any(|net| net.contains(&ip)),
+		};
+		let client = match forwarded_for {
+			Some(ip) if trusted => Some(ip),
+			_ => peer,
+		};
+		Self { client }
+	}
+}
+
+/// Parse the `trusted_proxies` configuration option: a list of IP addresses or networks
+pub(crate) fn parse_trusted_proxies(proxies: &[String]) -> Result<Vec<IpNet>, GarageError> {
+	proxies
+		.iter()
+		.map(|p| {
+			p.parse::<IpNet>()
+				.or_else(|_| p.parse::<IpAddr>().map(IpNet::from))
+				.map_err(|_| {
+					GarageError::Message(format!("Invalid address in trusted_proxies: {}", p))
+				})
+		})
+		.collect()
+}
+
 pub(crate) trait ApiEndpoint: Send + Sync + 'static {
 	fn name(&self) -> &'static str;
 	fn add_span_attributes(&self, span: SpanRef<'_>);
@@ -59,6 +104,15 @@ pub(crate) struct ApiServer<A: ApiHandler> {
 	region: String,
 	api_handler: A,
 
+	/// Set to true once the server has started shutting down:
+	/// new requests are then refused with 503 Service Unavailable
+	shutting_down: watch::Sender<bool>,
+
+	/// Log of all requests handled by this server, if enabled
+	access_log: Option<AccessLog>,
+	/// Reverse proxies whose X-Forwarded-For header is trusted
+	trusted_proxies: Vec<IpNet>,
+
 	// Metrics
 	request_counter: Counter<u64>,
 	error_counter: Counter<u64>,
@@ -66,11 +120,19 @@ pub(crate) struct ApiServer<A: ApiHandler> {
 }
 
 impl<A: ApiHandler> ApiServer<A> {
-	pub fn new(region: String, api_handler: A) -> Arc<Self> {
+	pub fn new(
+		region: String,
+		api_handler: A,
+		access_log: Option<AccessLog>,
+		trusted_proxies: Vec<IpNet>,
+	) -> Arc<Self> {
 		let meter = global::meter("garage/api");
 		Arc::new(Self {
 			region,
 			api_handler,
+			shutting_down: watch::channel(false).0,
+			access_log,
+			trusted_proxies,
 			request_counter: meter
 				.u64_counter(format!("api.{}.request_counter", A::API_NAME))
 				.with_description(format!(
@@ -101,6 +163,12 @@ impl<A: ApiHandler> ApiServer<A> {
 		unix_bind_addr_mode: Option<u32>,
 		shutdown_signal: impl Future<Output = ()>,
 	) -> Result<(), GarageError> {
+		let this = self.clone();
+		let shutdown_signal = async move {
+			shutdown_signal.await;
+			this.shutting_down.send_replace(true);
+		};
+
 		let tcp_service = make_service_fn(|conn: &AddrStream| {
 			let this = self.clone();
 
@@ -164,14 +232,13 @@ impl<A: ApiHandler> ApiServer<A> {
 
 	async fn handler(
 		self: Arc<Self>,
-		req: Request<Body>,
+		mut req: Request<Body>,
 		addr: String,
 	) -> Result<Response<Body>, GarageError> {
 		let uri = req.uri().clone();
 
-		if let Ok(forwarded_for_ip_addr) =
-			forwarded_headers::handle_forwarded_for_headers(req.headers())
-		{
+		let forwarded_for = forwarded_headers::handle_forwarded_for_headers(req.headers());
+		if let Ok(forwarded_for_ip_addr) = &forwarded_for {
 			info!(
 				"{} (via {}) {} {}",
 				forwarded_for_ip_addr,
@@ -184,6 +251,67 @@ impl<A: ApiHandler> ApiServer<A> {
 		}
 		debug!("{:?}", req);
 
+		let peer = addr.parse::<SocketAddr>().ok().map(|a| a.ip());
+		let forwarded_for = forwarded_for.ok().and_then(|ip| ip.parse().ok());
+		let remote = RemoteAddr::new(peer, forwarded_for, &self.trusted_proxies);
+		req.extensions_mut().insert(remote);
+
+		// Keep what is needed to write the access log once the response is ready
+		let access_log_request = self.access_log.as_ref().map(|_| {
+			(
+				Utc::now(),
+				req.method().clone(),
+				req.version(),
+				req.headers().clone(),
+			)
+		});
+
+		let res = self.handler_stage1(req, &uri).await;
+
+		if let (Some(access_log), Some((time, method, version, headers))) =
+			(&self.access_log, access_log_request)
+		{
+			let (status, response_size) = match &res {
+				Ok(resp) => (
+					resp.status(),
+					resp.body().size_hint().exact().or_else(|| {
+						resp.headers()
+							.get(hyper::header::CONTENT_LENGTH)
+							.and_then(|v| v.to_str().ok()?.parse().ok())
+					}),
+				),
+				Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, None),
+			};
+			let peer_addr = peer.map(|ip| ip.to_string()).unwrap_or(addr);
+			access_log.log(AccessLogEntry {
+				remote_addr: &peer_addr,
+				forwarded_for,
+				time,
+				method: &method,
+				uri: &uri,
+				version,
+				request_headers: &headers,
+				status,
+				response_size,
+			});
+		}
+
+		res
+	}
+
+	async fn handler_stage1(
+		&self,
+		req: Request<Body>,
+		uri: &Uri,
+	) -> Result<Response<Body>, GarageError> {
+		if *self.shutting_down.borrow() {
+			info!("Response: error 503, server is shutting down");
+			return Ok(Response::builder()
+				.status(StatusCode::SERVICE_UNAVAILABLE)
+				.header(hyper::header::CONNECTION, "close")
+				.body(Body::from("Server is shutting down\n"))?);
+		}
+
 		let tracer = opentelemetry::global::tracer("garage");
 		let span = tracer
 			.span_builder(format!("{} API call (unknown)", A::API_NAME_DISPLAY))
@@ -265,3 +393,37 @@ impl<A: ApiHandler> ApiServer<A> {
 		res
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_remote_addr() {
+		let proxies = parse_trusted_proxies(&["10.0.0.0/8".into(), "192.0.2.1".into()]).unwrap();
+		let client: IpAddr = "198.51.100.7".parse().unwrap();
+
+		// The X-Forwarded-For header is only used when sent by a trusted proxy
+		for (peer, trusted) in [
+			("10.1.2.3", true),
+			("192.0.2.1", true),
+			("192.0.2.2", false),
+		] {
+			let peer: IpAddr = peer.parse().unwrap();
+			let remote = RemoteAddr::new(Some(peer), Some(client), &proxies);
+			assert_eq!(remote.client, Some(if trusted { client } else { peer }));
+		}
+
+		let peer: IpAddr = "192.0.2.2".parse().unwrap();
+		assert_eq!(
+			RemoteAddr::new(Some(peer), None, &proxies).client,
+			Some(peer)
+		);
+		assert_eq!(
+			RemoteAddr::new(None, Some(client), &[]).client,
+			Some(client)
+		);
+
+		assert!(parse_trusted_proxies(&["proxy.example.com".into()]).is_err());
+	}
+}
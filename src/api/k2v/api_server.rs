@@ -41,7 +41,8 @@ impl K2VApiServer {
 		s3_region: String,
 		shutdown_signal: impl Future<Output = ()>,
 	) -> Result<(), GarageError> {
-		ApiServer::new(s3_region, K2VApiServer { garage })
+		let trusted_proxies = parse_trusted_proxies(&garage.config.trusted_proxies)?;
+		ApiServer::new(s3_region, K2VApiServer { garage }, None, trusted_proxies)
 			.run_server(bind_addr, None, shutdown_signal)
 			.await
 	}
@@ -154,6 +155,14 @@ impl ApiHandler for K2VApiServer {
 				)
 				.await
 			}
+			Endpoint::SubscribeItem {
+				partition_key,
+				sort_key,
+				causality_token,
+			} => {
+				handle_subscribe_item(garage, bucket_id, partition_key, sort_key, causality_token)
+					.await
+			}
 			Endpoint::ReadIndex {
 				prefix,
 				start,
@@ -161,6 +170,16 @@ impl ApiHandler for K2VApiServer {
 				limit,
 				reverse,
 			} => handle_read_index(garage, bucket_id, prefix, start, end, limit, reverse).await,
+			Endpoint::ReadRange {
+				partition_key,
+				start,
+				end,
+				limit,
+				reverse,
+			} => {
+				handle_read_range(garage, bucket_id, partition_key, start, end, limit, reverse)
+					.await
+			}
 			Endpoint::InsertBatch {} => handle_insert_batch(garage, bucket_id, req).await,
 			Endpoint::ReadBatch {} => handle_read_batch(garage, bucket_id, req).await,
 			Endpoint::DeleteBatch {} => handle_delete_batch(garage, bucket_id, req).await,
@@ -174,7 +193,7 @@ impl ApiHandler for K2VApiServer {
 		// add the corresponding CORS headers to the response
 		let mut resp_ok = resp?;
 		if let Some(rule) = matching_cors_rule {
-			add_cors_headers(&mut resp_ok, rule)
+			add_cors_headers(&mut resp_ok, &rule)
 				.ok_or_internal_error("Invalid bucket CORS configuration")?;
 		}
 
@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
 use base64::prelude::*;
+use hyper::header::HeaderValue;
 use hyper::{Body, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
 use garage_table::{EnumerationOrder, TableSchema};
 
@@ -12,10 +14,14 @@ use garage_model::garage::Garage;
 use garage_model::k2v::causality::*;
 use garage_model::k2v::item_table::*;
 
+use crate::encoding::uri_encode;
 use crate::helpers::*;
 use crate::k2v::error::*;
+use crate::k2v::item::{check_concurrent_values, check_item_size};
 use crate::k2v::range::read_range;
 
+pub const X_NEXT_START: &str = "X-Next-Start";
+
 pub async fn handle_insert_batch(
 	garage: Arc<Garage>,
 	bucket_id: Uuid,
@@ -27,16 +33,26 @@ pub async fn handle_insert_batch(
 	for it in items {
 		let ct = it.ct.map(|s| CausalContext::parse_helper(&s)).transpose()?;
 		let v = match it.v {
-			Some(vs) => DvvsValue::Value(
-				BASE64_STANDARD
+			Some(vs) => {
+				let v = BASE64_STANDARD
 					.decode(vs)
-					.ok_or_bad_request("Invalid base64 value")?,
-			),
+					.ok_or_bad_request("Invalid base64 value")?;
+				check_item_size(&garage, v.len())?;
+				DvvsValue::Value(v)
+			}
 			None => DvvsValue::Deleted,
 		};
 		items2.push((it.pk, it.sk, ct, v));
 	}
 
+	futures::future::try_join_all(
+		items2
+			.iter()
+			.filter(|(_, _, _, v)| matches!(v, DvvsValue::Value(_)))
+			.map(|(pk, sk, ct, _)| check_concurrent_values(&garage, bucket_id, pk, sk, ct)),
+	)
+	.await?;
+
 	garage.k2v.rpc.insert_batch(bucket_id, items2).await?;
 
 	Ok(Response::builder()
@@ -66,6 +82,43 @@ pub async fn handle_read_batch(
 	Ok(json_ok_response(&resps)?)
 }
 
+/// Read a range of items of a single partition, as a GET request on the partition key.
+/// When more items are available, the sort key from which to continue is
+/// returned URI-encoded in the `X-Next-Start` header, so that it can be
+/// passed as is in the `start` query parameter of the next request.
+pub async fn handle_read_range(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	partition_key: String,
+	start: Option<String>,
+	end: Option<String>,
+	limit: Option<u64>,
+	reverse: Option<bool>,
+) -> Result<Response<Body>, Error> {
+	let query = ReadBatchQuery {
+		partition_key,
+		prefix: None,
+		start,
+		end,
+		limit,
+		reverse: reverse.unwrap_or(false),
+		single_item: false,
+		conflicts_only: false,
+		tombstones: false,
+	};
+	let resp = handle_read_batch_query(&garage, bucket_id, query).await?;
+
+	let mut http_resp = json_ok_response(&resp)?;
+	if let Some(next_start) = &resp.next_start {
+		http_resp.headers_mut().insert(
+			X_NEXT_START,
+			HeaderValue::from_str(&uri_encode(next_start, true))
+				.ok_or_internal_error("Invalid next start key")?,
+		);
+	}
+	Ok(http_resp)
+}
+
 async fn handle_read_batch_query(
 	garage: &Arc<Garage>,
 	bucket_id: Uuid,
@@ -199,6 +252,7 @@ async fn handle_delete_batch_query(
 						i.sort_key,
 						Some(cc),
 						DvvsValue::Deleted,
+						None,
 					)
 					.await?;
 				1
@@ -357,14 +411,18 @@ struct ReadBatchResponseItem {
 impl ReadBatchResponseItem {
 	fn from(i: K2VItem) -> Self {
 		let ct = i.causal_context().serialize();
-		let v = i
-			.values()
-			.iter()
-			.map(|v| match v {
-				DvvsValue::Value(x) => Some(BASE64_STANDARD.encode(x)),
-				DvvsValue::Deleted => None,
-			})
-			.collect::<Vec<_>>();
+		let v = if i.is_expired(now_msec()) {
+			// Expired items are returned as tombstones until they are deleted
+			vec![None]
+		} else {
+			i.values()
+				.iter()
+				.map(|v| match v {
+					DvvsValue::Value(x) => Some(BASE64_STANDARD.encode(x)),
+					DvvsValue::Deleted => None,
+				})
+				.collect::<Vec<_>>()
+		};
 		Self {
 			sk: i.sort_key,
 			ct,
@@ -38,6 +38,10 @@ pub enum Error {
 	#[error(display = "Not acceptable: {}", _0)]
 	NotAcceptable(String),
 
+	/// The inserted item exceeds the limits set in the configuration
+	#[error(display = "Payload too large: {}", _0)]
+	PayloadTooLarge(String),
+
 	/// The request contained an invalid UTF-8 sequence in its path or in other parameters
 	#[error(display = "Invalid UTF-8: {}", _0)]
 	InvalidUtf8Str(#[error(source)] std::str::Utf8Error),
@@ -88,6 +92,7 @@ impl Error {
 			Error::Common(c) => c.aws_code(),
 			Error::NoSuchKey => "NoSuchKey",
 			Error::NotAcceptable(_) => "NotAcceptable",
+			Error::PayloadTooLarge(_) => "PayloadTooLarge",
 			Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
 			Error::InvalidBase64(_) => "InvalidBase64",
 			Error::InvalidHeader(_) => "InvalidHeaderValue",
@@ -103,6 +108,7 @@ impl ApiError for Error {
 			Error::Common(c) => c.http_status_code(),
 			Error::NoSuchKey => StatusCode::NOT_FOUND,
 			Error::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+			Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
 			Error::AuthorizationHeaderMalformed(_)
 			| Error::InvalidBase64(_)
 			| Error::InvalidHeader(_)
@@ -1,11 +1,16 @@
 use std::sync::Arc;
 
 use base64::prelude::*;
+use futures::StreamExt;
 use http::header;
 
+use hyper::body::HttpBody;
 use hyper::{Body, Request, Response, StatusCode};
+use serde::Serialize;
 
+use garage_util::config::K2VApiConfig;
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
 use garage_model::garage::Garage;
 use garage_model::k2v::causality::*;
@@ -14,6 +19,7 @@ use garage_model::k2v::item_table::*;
 use crate::k2v::error::*;
 
 pub const X_GARAGE_CAUSALITY_TOKEN: &str = "X-Garage-Causality-Token";
+pub const X_GARAGE_TTL: &str = "X-Garage-TTL";
 
 pub enum ReturnFormat {
 	Json,
@@ -117,6 +123,7 @@ pub async fn handle_read_item(
 			sort_key,
 		)
 		.await?
+		.filter(|item| !item.is_expired(now_msec()))
 		.ok_or(Error::NoSuchKey)?;
 
 	format.make_response(&item)
@@ -137,8 +144,27 @@ pub async fn handle_insert_item(
 		.map(CausalContext::parse_helper)
 		.transpose()?;
 
-	let body = hyper::body::to_bytes(req.into_body()).await?;
-	let value = DvvsValue::Value(body.to_vec());
+	let ttl_secs = req
+		.headers()
+		.get(X_GARAGE_TTL)
+		.map(|s| s.to_str())
+		.transpose()?
+		.map(|s| s.parse::<u64>().ok().filter(|ttl| *ttl > 0))
+		.map(|ttl| ttl.ok_or_bad_request("Invalid X-Garage-TTL header"))
+		.transpose()?;
+
+	let max_item_size = k2v_config(&garage)?.max_item_size;
+	let mut body = req.into_body();
+	let mut value = vec![];
+	while let Some(chunk) = body.data().await {
+		let chunk = chunk?;
+		if value.len() + chunk.len() > max_item_size {
+			return Err(item_too_large(max_item_size));
+		}
+		value.extend_from_slice(&chunk);
+	}
+	check_concurrent_values(&garage, bucket_id, partition_key, sort_key, &causal_context).await?;
+	let value = DvvsValue::Value(value);
 
 	garage
 		.k2v
@@ -149,6 +175,7 @@ pub async fn handle_insert_item(
 			sort_key.to_string(),
 			causal_context,
 			value,
+			ttl_secs,
 		)
 		.await?;
 
@@ -157,6 +184,66 @@ pub async fn handle_insert_item(
 		.body(Body::empty())?)
 }
 
+fn k2v_config(garage: &Garage) -> Result<&K2VApiConfig, Error> {
+	Ok(garage
+		.config
+		.k2v_api
+		.as_ref()
+		.ok_or_internal_error("K2V API is not configured")?)
+}
+
+pub(crate) fn check_item_size(garage: &Garage, size: usize) -> Result<(), Error> {
+	let max_item_size = k2v_config(garage)?.max_item_size;
+	if size > max_item_size {
+		return Err(item_too_large(max_item_size));
+	}
+	Ok(())
+}
+
+fn item_too_large(max_item_size: usize) -> Error {
+	Error::PayloadTooLarge(format!(
+		"the value is larger than the maximum item size of {} bytes",
+		max_item_size
+	))
+}
+
+/// Check that inserting a value with the given causality context does not
+/// give the item more concurrent values than allowed. This does not prevent
+/// concurrent insertions from going over the limit, but stops clients that
+/// keep inserting values without the causality token of previous reads.
+pub(crate) async fn check_concurrent_values(
+	garage: &Garage,
+	bucket_id: Uuid,
+	partition_key: &str,
+	sort_key: &str,
+	causal_context: &Option<CausalContext>,
+) -> Result<(), Error> {
+	let max_concurrent_values = k2v_config(garage)?.max_concurrent_values;
+
+	let item = garage
+		.k2v
+		.item_table
+		.get(
+			&K2VItemPartition {
+				bucket_id,
+				partition_key: partition_key.to_string(),
+			},
+			&sort_key.to_string(),
+		)
+		.await?;
+	let concurrent_values = item
+		.map(|item| item.count_concurrent_values(causal_context))
+		.unwrap_or(0);
+
+	if concurrent_values >= max_concurrent_values {
+		return Err(Error::PayloadTooLarge(format!(
+			"item {} already has {} concurrent values, the maximum is {}: read it and insert the new value with its causality token",
+			sort_key, concurrent_values, max_concurrent_values
+		)));
+	}
+	Ok(())
+}
+
 pub async fn handle_delete_item(
 	garage: Arc<Garage>,
 	req: Request<Body>,
@@ -183,6 +270,7 @@ pub async fn handle_delete_item(
 			sort_key.to_string(),
 			causal_context,
 			value,
+			None,
 		)
 		.await?;
 
@@ -229,3 +317,108 @@ pub async fn handle_poll_item(
 			.body(Body::empty())?)
 	}
 }
+
+/// Handle SubscribeItem request: stream the successive values of an item
+/// as server-sent events, until the client closes the connection
+pub async fn handle_subscribe_item(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	partition_key: String,
+	sort_key: String,
+	causality_token: Option<String>,
+) -> Result<Response<Body>, Error> {
+	// Without a causality token, the current value of the item is sent first
+	let (causal_context, first_event) = match causality_token {
+		Some(ct) => (
+			CausalContext::parse(&ct).ok_or_bad_request("Invalid causality token")?,
+			None,
+		),
+		None => {
+			let item = garage
+				.k2v
+				.item_table
+				.get(
+					&K2VItemPartition {
+						bucket_id,
+						partition_key: partition_key.clone(),
+					},
+					&sort_key,
+				)
+				.await?;
+			match item {
+				Some(item) => (item.causal_context(), Some(item_event(&item)?)),
+				None => (CausalContext::new(), None),
+			}
+		}
+	};
+
+	let events = futures::stream::unfold(Some(causal_context), move |state| {
+		let garage = garage.clone();
+		let partition_key = partition_key.clone();
+		let sort_key = sort_key.clone();
+		async move {
+			let causal_context = state?;
+			let item = garage
+				.k2v
+				.rpc
+				.poll_item(
+					bucket_id,
+					partition_key,
+					sort_key,
+					causal_context.clone(),
+					SUBSCRIBE_HEARTBEAT_MSEC,
+				)
+				.await;
+			let (event, next) = match item.map_err(Error::from) {
+				Ok(Some(item)) => match item_event(&item) {
+					Ok(event) => (event, Some(item.causal_context())),
+					Err(e) => (error_event(e), None),
+				},
+				// No new value before the timeout: send a comment,
+				// so that broken connections are detected
+				Ok(None) => (":heartbeat\n\n".to_string(), Some(causal_context)),
+				Err(e) => (error_event(e), None),
+			};
+			Some((Ok::<_, std::convert::Infallible>(event), next))
+		}
+	});
+	let body = futures::stream::iter(first_event.map(Ok)).chain(events);
+
+	Ok(Response::builder()
+		.header(header::CONTENT_TYPE, "text/event-stream")
+		.header(header::CACHE_CONTROL, "no-cache")
+		.status(StatusCode::OK)
+		.body(Body::wrap_stream(body))?)
+}
+
+const SUBSCRIBE_HEARTBEAT_MSEC: u64 = 30_000;
+
+#[derive(Serialize)]
+struct SubscribeEvent {
+	ct: String,
+	v: Vec<Option<String>>,
+}
+
+fn item_event(item: &K2VItem) -> Result<String, Error> {
+	let v = if item.is_expired(now_msec()) {
+		vec![None]
+	} else {
+		item.values()
+			.iter()
+			.map(|v| match v {
+				DvvsValue::Value(x) => Some(BASE64_STANDARD.encode(x)),
+				DvvsValue::Deleted => None,
+			})
+			.collect()
+	};
+	let event = SubscribeEvent {
+		ct: item.causal_context().serialize(),
+		v,
+	};
+	let json = serde_json::to_string(&event).ok_or_internal_error("JSON encoding error")?;
+	Ok(format!("data: {}\n\n", json))
+}
+
+fn error_event(e: Error) -> String {
+	format!("event: error\ndata: {}\n\n", e)
+}
@@ -48,6 +48,18 @@ pub enum Endpoint {
 		partition_key: String,
 		sort_key: String,
 	},
+	ReadRange {
+		partition_key: String,
+		start: Option<String>,
+		end: Option<String>,
+		limit: Option<u64>,
+		reverse: Option<bool>,
+	},
+	SubscribeItem {
+		partition_key: String,
+		sort_key: String,
+		causality_token: Option<String>,
+	},
 }}
 
 impl Endpoint {
@@ -101,8 +113,10 @@ impl Endpoint {
 			@gen_parser
 			(query.keyword.take().unwrap_or_default(), partition_key, query, None),
 			key: [
+				SUBSCRIBE => SubscribeItem (query::sort_key, query_opt::causality_token),
 				EMPTY if causality_token => PollItem (query::sort_key, query::causality_token, opt_parse::timeout),
-				EMPTY => ReadItem (query::sort_key),
+				EMPTY if sort_key => ReadItem (query::sort_key),
+				EMPTY => ReadRange (query_opt::start, query_opt::end, opt_parse::limit, opt_parse::reverse),
 			],
 			no_key: [
 				EMPTY => ReadIndex (query_opt::prefix, query_opt::start, query_opt::end, opt_parse::limit, opt_parse::reverse),
@@ -195,6 +209,8 @@ impl Endpoint {
 				InsertItem,
 				PollItem,
 				ReadItem,
+				ReadRange,
+				SubscribeItem,
 			]
 		}
 	}
@@ -211,6 +227,7 @@ impl Endpoint {
 				InsertItem,
 				PollItem,
 				ReadItem,
+				SubscribeItem,
 			]
 		}
 	}
@@ -225,6 +242,8 @@ impl Endpoint {
 				ReadBatch,
 				ReadIndex,
 				ReadItem,
+				ReadRange,
+				SubscribeItem,
 			]
 		};
 		if readonly {
@@ -240,7 +259,8 @@ generateQueryParameters! {
 	keywords: [
 		"delete" => DELETE,
 		"search" => SEARCH,
-		"poll_range" => POLL_RANGE
+		"poll_range" => POLL_RANGE,
+		"subscribe" => SUBSCRIBE
 	],
 	fields: [
 		"prefix" => prefix,
@@ -2,6 +2,7 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod access_log;
 pub mod common_error;
 
 mod encoding;
@@ -4,16 +4,18 @@ use async_trait::async_trait;
 
 use futures::future::Future;
 use hyper::header;
-use hyper::{Body, Request, Response};
+use hyper::{Body, Method, Request, Response};
 
 use opentelemetry::{trace::SpanRef, KeyValue};
 
+use garage_util::data::*;
 use garage_util::error::Error as GarageError;
 use garage_util::socket_address::UnixOrTCPSocketAddress;
 
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 
+use crate::access_log::AccessLog;
 use crate::generic_server::*;
 use crate::s3::error::*;
 
@@ -22,18 +24,26 @@ use crate::signature::streaming::*;
 
 use crate::helpers::*;
 use crate::s3::bucket::*;
+use crate::s3::bucket_metrics::BucketMetrics;
 use crate::s3::copy::*;
 use crate::s3::cors::*;
 use crate::s3::delete::*;
+use crate::s3::encryption::*;
 use crate::s3::get::*;
+use crate::s3::lifecycle::*;
 use crate::s3::list::*;
+use crate::s3::object_lock::*;
+use crate::s3::policy::*;
 use crate::s3::post_object::handle_post_object;
 use crate::s3::put::*;
+use crate::s3::rate_limit::{content_length, RateLimiter};
 use crate::s3::router::Endpoint;
 use crate::s3::website::*;
 
 pub struct S3ApiServer {
 	garage: Arc<Garage>,
+	rate_limiter: RateLimiter,
+	bucket_metrics: BucketMetrics,
 }
 
 pub(crate) struct S3ApiEndpoint {
@@ -48,7 +58,17 @@ impl S3ApiServer {
 		s3_region: String,
 		shutdown_signal: impl Future<Output = ()>,
 	) -> Result<(), GarageError> {
-		ApiServer::new(s3_region, S3ApiServer { garage })
+		let access_log = match &garage.config.s3_api_access_log {
+			Some(path) => Some(AccessLog::open(path.clone()).await?),
+			None => None,
+		};
+		let handler = S3ApiServer {
+			bucket_metrics: BucketMetrics::new(&garage),
+			garage,
+			rate_limiter: RateLimiter::new(),
+		};
+		let trusted_proxies = parse_trusted_proxies(&handler.garage.config.trusted_proxies)?;
+		ApiServer::new(s3_region, handler, access_log, trusted_proxies)
 			.run_server(addr, None, shutdown_signal)
 			.await
 	}
@@ -56,11 +76,11 @@ impl S3ApiServer {
 	async fn handle_request_without_bucket(
 		&self,
 		_req: Request<Body>,
-		api_key: Key,
+		api_key: &Key,
 		endpoint: Endpoint,
 	) -> Result<Response<Body>, Error> {
 		match endpoint {
-			Endpoint::ListBuckets => handle_list_buckets(&self.garage, &api_key).await,
+			Endpoint::ListBuckets => handle_list_buckets(&self.garage, api_key).await,
 			endpoint => Err(Error::NotImplemented(endpoint.name().to_owned())),
 		}
 	}
@@ -119,12 +139,123 @@ impl ApiHandler for S3ApiServer {
 			return handle_options_s3api(garage, &req, bucket_name).await;
 		}
 
-		let (api_key, mut content_sha256) = check_payload_signature(&garage, "s3", &req).await?;
-		let api_key = api_key
-			.ok_or_else(|| Error::forbidden("Garage does not support anonymous access yet"))?;
+		let (api_key, content_sha256) = check_payload_signature(&garage, "s3", &req).await?;
+		let api_key = match api_key {
+			Some(key) => key,
+			None => return self.handle_anonymous(req, bucket_name, endpoint).await,
+		};
+
+		// Responses to HEAD requests announce the size of the object but have no body
+		let is_head = req.method() == Method::HEAD;
+		self.rate_limiter
+			.check_request(&api_key, content_length(req.headers()))?;
+		let resp = self
+			.handle_with_key(req, bucket_name, endpoint, &api_key, content_sha256)
+			.await?;
+		if !is_head {
+			self.rate_limiter
+				.consume_bandwidth(&api_key, content_length(resp.headers()));
+		}
+
+		Ok(resp)
+	}
+}
+
+impl S3ApiServer {
+	/// Handle a request made without an access key, which is only possible
+	/// on objects of buckets whose policy allows it
+	async fn handle_anonymous(
+		&self,
+		req: Request<Body>,
+		bucket_name: Option<String>,
+		endpoint: Endpoint,
+	) -> Result<Response<Body>, Error> {
+		let garage = self.garage.clone();
+		let denied = || Error::forbidden("Anonymous access is not allowed by the bucket policy");
+
+		let bucket_name = bucket_name.ok_or_else(denied)?;
+		let bucket_id = garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&bucket_name)
+			.await?
+			.ok_or_else(denied)?;
+		let bucket = garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+
+		let (action, object_key) = policy_action(&endpoint).ok_or_else(denied)?;
+		let policy = bucket_policy(&bucket)?.ok_or_else(denied)?;
+		if policy.evaluate(None, action, object_key, source_ip(&req)) != PolicyDecision::Allow {
+			return Err(denied());
+		}
+
+		let matching_cors_rule = find_matching_cors_rule(&bucket, &req)?;
+
+		let method = req.method().clone();
+		let resp = match endpoint {
+			Endpoint::HeadObject {
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_head(
+					garage,
+					&req,
+					bucket_id,
+					&key,
+					part_number,
+					version_id.as_deref(),
+				)
+				.await
+			}
+			Endpoint::GetObject {
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_get(
+					garage,
+					&req,
+					bucket_id,
+					&key,
+					part_number,
+					version_id.as_deref(),
+				)
+				.await
+			}
+			Endpoint::PutObject { key } => handle_put(garage, req, &bucket, &key, None).await,
+			Endpoint::DeleteObject { key, version_id } => {
+				handle_delete(garage, &bucket, &key, version_id.as_deref(), false).await
+			}
+			endpoint => Err(Error::NotImplemented(format!(
+				"{} with anonymous access",
+				endpoint.name()
+			))),
+		};
+		self.bucket_metrics.record(bucket_id, &method, &resp);
+
+		let mut resp_ok = resp?;
+		if let Some(rule) = matching_cors_rule {
+			add_cors_headers(&mut resp_ok, &rule)
+				.ok_or_internal_error("Invalid bucket CORS configuration")?;
+		}
+
+		Ok(resp_ok)
+	}
+
+	async fn handle_with_key(
+		&self,
+		req: Request<Body>,
+		bucket_name: Option<String>,
+		endpoint: Endpoint,
+		api_key: &Key,
+		mut content_sha256: Option<Hash>,
+	) -> Result<Response<Body>, Error> {
+		let garage = self.garage.clone();
 
 		let req = parse_streaming_body(
-			&api_key,
+			api_key,
 			req,
 			&mut content_sha256,
 			&garage.config.s3_api.s3_region,
@@ -142,12 +273,19 @@ impl ApiHandler for S3ApiServer {
 
 		// Special code path for CreateBucket API endpoint
 		if let Endpoint::CreateBucket {} = endpoint {
-			return handle_create_bucket(&garage, req, content_sha256, api_key, bucket_name).await;
+			return handle_create_bucket(
+				&garage,
+				req,
+				content_sha256,
+				api_key.clone(),
+				bucket_name,
+			)
+			.await;
 		}
 
 		let bucket_id = garage
 			.bucket_helper()
-			.resolve_bucket(&bucket_name, &api_key)
+			.resolve_bucket(&bucket_name, api_key)
 			.await?;
 		let bucket = garage
 			.bucket_helper()
@@ -161,19 +299,60 @@ impl ApiHandler for S3ApiServer {
 			_ => unreachable!(),
 		};
 
-		if !allowed {
+		// The bucket policy can deny operations allowed to the key,
+		// and allow operations to everyone, including this key
+		let policy = bucket_policy(&bucket)?;
+		let decision = match (policy_action(&endpoint), &policy) {
+			(Some((action, object_key)), Some(policy)) => {
+				policy.evaluate(Some(&api_key.key_id), action, object_key, source_ip(&req))
+			}
+			_ => PolicyDecision::NotApplicable,
+		};
+		if decision == PolicyDecision::Deny {
+			return Err(Error::forbidden(
+				"Operation is denied by the bucket policy.",
+			));
+		}
+		// DeleteObjects checks the bucket policy for each object it deletes
+		let per_object_policy = matches!(endpoint, Endpoint::DeleteObjects {}) && policy.is_some();
+		if !allowed && decision != PolicyDecision::Allow && !per_object_policy {
 			return Err(Error::forbidden("Operation is not allowed for this key."));
 		}
 
 		let matching_cors_rule = find_matching_cors_rule(&bucket, &req)?;
 
+		let method = req.method().clone();
 		let resp = match endpoint {
 			Endpoint::HeadObject {
-				key, part_number, ..
-			} => handle_head(garage, &req, bucket_id, &key, part_number).await,
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_head(
+					garage,
+					&req,
+					bucket_id,
+					&key,
+					part_number,
+					version_id.as_deref(),
+				)
+				.await
+			}
 			Endpoint::GetObject {
-				key, part_number, ..
-			} => handle_get(garage, &req, bucket_id, &key, part_number).await,
+				key,
+				part_number,
+				version_id,
+			} => {
+				handle_get(
+					garage,
+					&req,
+					bucket_id,
+					&key,
+					part_number,
+					version_id.as_deref(),
+				)
+				.await
+			}
 			Endpoint::UploadPart {
 				key,
 				part_number,
@@ -190,9 +369,7 @@ impl ApiHandler for S3ApiServer {
 				)
 				.await
 			}
-			Endpoint::CopyObject { key } => {
-				handle_copy(garage, &api_key, &req, bucket_id, &key).await
-			}
+			Endpoint::CopyObject { key } => handle_copy(garage, api_key, &req, &bucket, &key).await,
 			Endpoint::UploadPartCopy {
 				key,
 				part_number,
@@ -200,7 +377,7 @@ impl ApiHandler for S3ApiServer {
 			} => {
 				handle_upload_part_copy(
 					garage,
-					&api_key,
+					api_key,
 					&req,
 					bucket_id,
 					&key,
@@ -215,9 +392,19 @@ impl ApiHandler for S3ApiServer {
 			Endpoint::AbortMultipartUpload { key, upload_id } => {
 				handle_abort_multipart_upload(garage, bucket_id, &key, &upload_id).await
 			}
-			Endpoint::DeleteObject { key, .. } => handle_delete(garage, bucket_id, &key).await,
+			Endpoint::DeleteObject { key, version_id } => {
+				let bypass_governance = can_bypass_governance(req.headers(), api_key, &bucket);
+				handle_delete(
+					garage,
+					&bucket,
+					&key,
+					version_id.as_deref(),
+					bypass_governance,
+				)
+				.await
+			}
 			Endpoint::CreateMultipartUpload { key } => {
-				handle_create_multipart_upload(garage, &req, &bucket_name, bucket_id, &key).await
+				handle_create_multipart_upload(garage, &req, &bucket_name, &bucket, &key).await
 			}
 			Endpoint::CompleteMultipartUpload { key, upload_id } => {
 				handle_complete_multipart_upload(
@@ -238,10 +425,13 @@ impl ApiHandler for S3ApiServer {
 				Ok(response)
 			}
 			Endpoint::DeleteBucket {} => {
-				handle_delete_bucket(&garage, bucket_id, bucket_name, api_key).await
+				handle_delete_bucket(&garage, bucket_id, bucket_name, api_key.clone()).await
 			}
 			Endpoint::GetBucketLocation {} => handle_get_bucket_location(garage),
-			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(),
+			Endpoint::GetBucketVersioning {} => handle_get_bucket_versioning(&bucket),
+			Endpoint::PutBucketVersioning {} => {
+				handle_put_bucket_versioning(garage, bucket_id, req, content_sha256).await
+			}
 			Endpoint::ListObjects {
 				delimiter,
 				encoding_type,
@@ -329,6 +519,31 @@ impl ApiHandler for S3ApiServer {
 				)
 				.await
 			}
+			Endpoint::ListObjectVersions {
+				delimiter,
+				encoding_type,
+				key_marker,
+				max_keys,
+				prefix,
+				version_id_marker,
+			} => {
+				handle_list_object_versions(
+					garage,
+					&ListObjectVersionsQuery {
+						common: ListQueryCommon {
+							bucket_name,
+							bucket_id,
+							delimiter: delimiter.map(|d| d.to_string()),
+							page_size: max_keys.map(|p| p.clamp(1, 1000)).unwrap_or(1000) as usize,
+							prefix: prefix.unwrap_or_default(),
+							urlencode_resp: encoding_type.map(|e| e == "url").unwrap_or(false),
+						},
+						key_marker,
+						version_id_marker,
+					},
+				)
+				.await
+			}
 			Endpoint::ListParts {
 				key,
 				max_parts,
@@ -349,7 +564,14 @@ impl ApiHandler for S3ApiServer {
 				.await
 			}
 			Endpoint::DeleteObjects {} => {
-				handle_delete_objects(garage, bucket_id, req, content_sha256).await
+				let auth = DeleteObjectsAuth {
+					allow_write: allowed,
+					key_id: &api_key.key_id,
+					policy,
+					source_ip: source_ip(&req),
+					bypass_governance: can_bypass_governance(req.headers(), api_key, &bucket),
+				};
+				handle_delete_objects(garage, &bucket, req, content_sha256, auth).await
 			}
 			Endpoint::GetBucketWebsite {} => handle_get_website(&bucket).await,
 			Endpoint::PutBucketWebsite {} => {
@@ -361,14 +583,56 @@ impl ApiHandler for S3ApiServer {
 				handle_put_cors(garage, bucket_id, req, content_sha256).await
 			}
 			Endpoint::DeleteBucketCors {} => handle_delete_cors(garage, bucket_id).await,
+			Endpoint::GetBucketPolicy {} => handle_get_bucket_policy(&bucket).await,
+			Endpoint::PutBucketPolicy {} => {
+				handle_put_bucket_policy(garage, api_key, bucket_id, req, content_sha256).await
+			}
+			Endpoint::DeleteBucketPolicy {} => handle_delete_bucket_policy(garage, bucket_id).await,
+			Endpoint::GetBucketLifecycleConfiguration {} => handle_get_lifecycle(&bucket).await,
+			Endpoint::PutBucketLifecycleConfiguration {} => {
+				handle_put_lifecycle(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::DeleteBucketLifecycle {} => handle_delete_lifecycle(garage, bucket_id).await,
+			Endpoint::GetBucketEncryption {} => handle_get_encryption(&bucket).await,
+			Endpoint::PutBucketEncryption {} => {
+				handle_put_encryption(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::DeleteBucketEncryption {} => {
+				handle_delete_encryption(garage, bucket_id).await
+			}
+			Endpoint::GetObjectLockConfiguration {} => {
+				handle_get_object_lock_configuration(&bucket).await
+			}
+			Endpoint::PutObjectLockConfiguration {} => {
+				handle_put_object_lock_configuration(garage, bucket_id, req, content_sha256).await
+			}
+			Endpoint::GetObjectAttributes { key, version_id } => {
+				handle_get_object_attributes(garage, &req, bucket_id, &key, version_id.as_deref())
+					.await
+			}
+			Endpoint::GetObjectLegalHold { key, version_id } => {
+				handle_get_object_legal_hold(garage, &bucket, &key, version_id.as_deref()).await
+			}
+			Endpoint::PutObjectLegalHold { key, version_id } => {
+				handle_put_object_legal_hold(
+					garage,
+					&bucket,
+					&key,
+					version_id.as_deref(),
+					req,
+					content_sha256,
+				)
+				.await
+			}
 			endpoint => Err(Error::NotImplemented(endpoint.name().to_owned())),
 		};
+		self.bucket_metrics.record(bucket_id, &method, &resp);
 
 		// If request was a success and we have a CORS rule that applies to it,
 		// add the corresponding CORS headers to the response
 		let mut resp_ok = resp?;
 		if let Some(rule) = matching_cors_rule {
-			add_cors_headers(&mut resp_ok, rule)
+			add_cors_headers(&mut resp_ok, &rule)
 				.ok_or_internal_error("Invalid bucket CORS configuration")?;
 		}
 
@@ -1,10 +1,11 @@
+use quick_xml::de::from_reader;
 use std::collections::HashMap;
 use std::sync::Arc;
 
 use hyper::{Body, Request, Response, StatusCode};
 
 use garage_model::bucket_alias_table::*;
-use garage_model::bucket_table::Bucket;
+use garage_model::bucket_table::{Bucket, BucketVersioning};
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 use garage_model::permission::BucketKeyPerm;
@@ -30,10 +31,19 @@ pub fn handle_get_bucket_location(garage: Arc<Garage>) -> Result<Response<Body>,
 		.body(Body::from(xml.into_bytes()))?)
 }
 
-pub fn handle_get_bucket_versioning() -> Result<Response<Body>, Error> {
+pub fn handle_get_bucket_versioning(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	let status = param.versioning.get().map(|v| match v {
+		BucketVersioning::Enabled => s3_xml::Value(VERSIONING_ENABLED.into()),
+		BucketVersioning::Suspended => s3_xml::Value(VERSIONING_SUSPENDED.into()),
+	});
 	let versioning = s3_xml::VersioningConfiguration {
 		xmlns: (),
-		status: None,
+		status,
+		mfa_delete: None,
 	};
 
 	let xml = s3_xml::to_xml_with_header(&versioning)?;
@@ -43,6 +53,68 @@ pub fn handle_get_bucket_versioning() -> Result<Response<Body>, Error> {
 		.body(Body::from(xml.into_bytes()))?)
 }
 
+pub async fn handle_put_bucket_versioning(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let conf: s3_xml::VersioningConfiguration = from_reader(&body as &[u8])?;
+
+	if let Some(mfa_delete) = &conf.mfa_delete {
+		match mfa_delete.0.as_str() {
+			VERSIONING_ENABLED => {
+				return Err(Error::NotImplemented(
+					"MFA delete is not supported by Garage".into(),
+				))
+			}
+			"Disabled" => (),
+			x => return Err(Error::bad_request(format!("Invalid MfaDelete: {}", x))),
+		}
+	}
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let versioning = match conf.status.as_ref().map(|s| s.0.as_str()) {
+		Some(VERSIONING_SUSPENDED) if bucket.object_lock_enabled() => {
+			return Err(Error::InvalidBucketState(
+				"Versioning can not be suspended on a bucket with object lock enabled".into(),
+			))
+		}
+		Some(VERSIONING_ENABLED) => BucketVersioning::Enabled,
+		Some(VERSIONING_SUSPENDED) => BucketVersioning::Suspended,
+		Some(x) => {
+			return Err(Error::bad_request(format!(
+				"Invalid versioning status: {}",
+				x
+			)))
+		}
+		None => return Err(Error::bad_request("Missing versioning status")),
+	};
+
+	let param = bucket.params_mut().unwrap();
+
+	param.versioning.update(Some(versioning));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+const X_AMZ_BUCKET_OBJECT_LOCK_ENABLED: &str = "x-amz-bucket-object-lock-enabled";
+
+const VERSIONING_ENABLED: &str = "Enabled";
+const VERSIONING_SUSPENDED: &str = "Suspended";
+
 pub async fn handle_list_buckets(garage: &Garage, api_key: &Key) -> Result<Response<Body>, Error> {
 	let key_p = api_key.params().ok_or_internal_error(
 		"Key should not be in deleted state at this point (in handle_list_buckets)",
@@ -119,6 +191,12 @@ pub async fn handle_create_bucket(
 	api_key: Key,
 	bucket_name: String,
 ) -> Result<Response<Body>, Error> {
+	let object_lock_enabled = req
+		.headers()
+		.get(X_AMZ_BUCKET_OBJECT_LOCK_ENABLED)
+		.map(|v| v.as_bytes().eq_ignore_ascii_case(b"true"))
+		.unwrap_or(false);
+
 	let body = hyper::body::to_bytes(req.into_body()).await?;
 
 	if let Some(content_sha256) = content_sha256 {
@@ -178,7 +256,12 @@ pub async fn handle_create_bucket(
 			)));
 		}
 
-		let bucket = Bucket::new();
+		let mut bucket = Bucket::new();
+		if object_lock_enabled {
+			let param = bucket.params_mut().unwrap();
+			param.object_lock_enabled.set();
+			param.versioning.update(Some(BucketVersioning::Enabled));
+		}
 		garage.bucket_table.insert(&bucket).await?;
 
 		garage
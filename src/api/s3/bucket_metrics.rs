@@ -0,0 +1,145 @@
+use std::sync::{Arc, Weak};
+
+use hyper::{Body, Method, Response};
+use opentelemetry::metrics::{Counter, Meter, ValueObserver};
+use opentelemetry::{global, KeyValue};
+
+use garage_util::data::*;
+
+use garage_table::*;
+
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::{BYTES, OBJECTS, UNFINISHED_UPLOADS};
+
+use crate::generic_server::ApiError;
+use crate::s3::error::*;
+
+/// Metrics of the S3 API broken down by bucket, with a `bucket_id` attribute.
+///
+/// The sizes of the buckets are read from the partitions of the object counter
+/// table that are stored on this node, so a bucket is only reported by the
+/// nodes that store its counters.
+pub struct BucketMetrics {
+	_objects: ValueObserver<u64>,
+	_bytes: ValueObserver<u64>,
+	_unfinished_uploads: ValueObserver<u64>,
+
+	request_counter: Counter<u64>,
+	error_counter: Counter<u64>,
+}
+
+impl BucketMetrics {
+	pub fn new(garage: &Arc<Garage>) -> Self {
+		let meter = global::meter("garage/api");
+		Self {
+			_objects: counter_observer(
+				&meter,
+				garage,
+				"api.s3.bucket.objects",
+				OBJECTS,
+				"Number of objects in each bucket",
+			),
+			_bytes: counter_observer(
+				&meter,
+				garage,
+				"api.s3.bucket.bytes",
+				BYTES,
+				"Total size in bytes of the objects in each bucket",
+			),
+			_unfinished_uploads: counter_observer(
+				&meter,
+				garage,
+				"api.s3.bucket.unfinished_uploads",
+				UNFINISHED_UPLOADS,
+				"Number of unfinished multipart uploads in each bucket",
+			),
+			request_counter: meter
+				.u64_counter("api.s3.bucket.request_counter")
+				.with_description("Number of S3 API calls to each bucket, by HTTP method")
+				.init(),
+			error_counter: meter
+				.u64_counter("api.s3.bucket.error_counter")
+				.with_description("Number of S3 API calls to each bucket that returned an error")
+				.init(),
+		}
+	}
+
+	/// Count a request made to a bucket, and the error it returned if any
+	pub fn record(&self, bucket_id: Uuid, method: &Method, res: &Result<Response<Body>, Error>) {
+		let bucket_id = KeyValue::new("bucket_id", hex::encode(bucket_id));
+		let method = KeyValue::new("method", method.as_str().to_string());
+		self.request_counter
+			.add(1, &[bucket_id.clone(), method.clone()]);
+
+		let status_code = match res {
+			Ok(r) => r.status(),
+			Err(e) => e.http_status_code(),
+		};
+		if status_code.is_client_error() || status_code.is_server_error() {
+			self.error_counter.add(
+				1,
+				&[
+					bucket_id,
+					method,
+					KeyValue::new("status_code", status_code.as_str().to_string()),
+				],
+			);
+		}
+	}
+}
+
+/// Observe one of the values of the object counter table for all the buckets
+/// whose counters are stored on this node
+fn counter_observer(
+	meter: &Meter,
+	garage: &Arc<Garage>,
+	name: &'static str,
+	counter: &'static str,
+	description: &'static str,
+) -> ValueObserver<u64> {
+	// Don't keep the Garage struct alive from the global meter provider
+	let garage: Weak<Garage> = Arc::downgrade(garage);
+	meter
+		.u64_value_observer(name, move |observer| {
+			let garage = match garage.upgrade() {
+				Some(garage) => garage,
+				None => return,
+			};
+			let ring = garage.system.ring.borrow().clone();
+			let table = &garage.object_counter_table.table;
+			let iter = match table.data.store.iter() {
+				Ok(iter) => iter,
+				Err(e) => {
+					warn!("Unable to read object counters for metrics: {}", e);
+					return;
+				}
+			};
+			for item in iter {
+				let (_, value) = match item {
+					Ok(kv) => kv,
+					Err(_) => break,
+				};
+				let entry = match table.data.decode_entry(&value) {
+					Ok(entry) => entry,
+					Err(_) => continue,
+				};
+				if entry.is_tombstone() {
+					continue;
+				}
+				let value = entry
+					.filtered_values(&ring)
+					.get(counter)
+					.copied()
+					.unwrap_or(0);
+				observer.observe(
+					std::cmp::max(value, 0) as u64,
+					&[KeyValue::new(
+						"bucket_id",
+						hex::encode(entry.partition_key()),
+					)],
+				);
+			}
+		})
+		.with_description(description)
+		.init()
+}
@@ -0,0 +1,115 @@
+use std::convert::TryInto;
+
+use base64::prelude::*;
+use hyper::header::{HeaderMap, HeaderValue};
+
+use garage_util::data::*;
+
+use garage_model::s3::version_table::ChecksumValue;
+
+use crate::s3::error::*;
+
+pub const X_AMZ_CHECKSUM_CRC32C: &str = "x-amz-checksum-crc32c";
+pub const X_AMZ_CHECKSUM_SHA256: &str = "x-amz-checksum-sha256";
+
+/// Checksums computed by Garage on the data it received,
+/// to be compared with the one declared by the client
+pub struct Checksums {
+	pub crc32c: u32,
+	pub sha256: Hash,
+}
+
+impl Checksums {
+	pub fn compute(data: &[u8]) -> Self {
+		Self {
+			crc32c: crc32c::crc32c(data),
+			sha256: sha256sum(data),
+		}
+	}
+}
+
+/// Read the checksum declared by the client in one of the x-amz-checksum-* headers
+pub fn request_checksum_value(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<ChecksumValue>, Error> {
+	let mut ret = vec![];
+
+	if let Some(crc32c) = headers.get(X_AMZ_CHECKSUM_CRC32C) {
+		let crc32c = BASE64_STANDARD
+			.decode(crc32c.to_str()?)
+			.ok()
+			.and_then(|x| x.try_into().ok())
+			.ok_or_bad_request("invalid x-amz-checksum-crc32c header")?;
+		ret.push(ChecksumValue::Crc32c(crc32c))
+	}
+	if let Some(sha256) = headers.get(X_AMZ_CHECKSUM_SHA256) {
+		let sha256 = BASE64_STANDARD
+			.decode(sha256.to_str()?)
+			.ok()
+			.and_then(|x| x.try_into().ok())
+			.ok_or_bad_request("invalid x-amz-checksum-sha256 header")?;
+		ret.push(ChecksumValue::Sha256(sha256))
+	}
+
+	if ret.len() > 1 {
+		return Err(Error::bad_request(
+			"multiple x-amz-checksum-* headers given",
+		));
+	}
+	Ok(ret.pop())
+}
+
+/// Check that the checksum declared by the client, if any,
+/// matches the data that was actually received
+pub fn ensure_checksum_value_matches(
+	expected: Option<ChecksumValue>,
+	computed: &Checksums,
+) -> Result<(), Error> {
+	match expected {
+		Some(ChecksumValue::Crc32c(crc32c)) if crc32c != computed.crc32c.to_be_bytes() => {
+			Err(Error::BadDigest(
+				"The CRC32C you specified did not match the calculated checksum".into(),
+			))
+		}
+		Some(ChecksumValue::Sha256(sha256)) if sha256 != computed.sha256.as_slice() => {
+			Err(Error::BadDigest(
+				"The SHA256 you specified did not match the calculated checksum".into(),
+			))
+		}
+		_ => Ok(()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_checksum_matches() {
+		let data = b"The quick brown fox jumps over the lazy dog";
+		let computed = Checksums::compute(data);
+
+		let mut headers = HeaderMap::new();
+		headers.insert(
+			X_AMZ_CHECKSUM_CRC32C,
+			BASE64_STANDARD
+				.encode(0x22620404u32.to_be_bytes())
+				.parse()
+				.unwrap(),
+		);
+		let expected = request_checksum_value(&headers).unwrap();
+		assert!(ensure_checksum_value_matches(expected, &computed).is_ok());
+
+		let wrong = Checksums::compute(b"The quick brown fox jumps over the lazy cat");
+		assert!(matches!(
+			ensure_checksum_value_matches(expected, &wrong),
+			Err(Error::BadDigest(_))
+		));
+
+		headers.insert(
+			X_AMZ_CHECKSUM_SHA256,
+			BASE64_STANDARD.encode(computed.sha256).parse().unwrap(),
+		);
+		assert!(request_checksum_value(&headers).is_err());
+	}
+}
@@ -15,6 +15,7 @@ use garage_table::*;
 use garage_util::data::*;
 use garage_util::time::*;
 
+use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
 use garage_model::s3::block_ref_table::*;
@@ -22,7 +23,10 @@ use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
 use crate::helpers::parse_bucket_key;
+use crate::s3::encryption::*;
 use crate::s3::error::*;
+use crate::s3::notification::{notify_object_event, ObjectEvent};
+use crate::s3::object_lock::*;
 use crate::s3::put::{decode_upload_id, get_headers};
 use crate::s3::xml::{self as s3_xml, xmlns_tag};
 
@@ -30,31 +34,77 @@ pub async fn handle_copy(
 	garage: Arc<Garage>,
 	api_key: &Key,
 	req: &Request<Body>,
-	dest_bucket_id: Uuid,
+	dest_bucket: &Bucket,
 	dest_key: &str,
 ) -> Result<Response<Body>, Error> {
+	let dest_bucket_id = dest_bucket.id;
 	let copy_precondition = CopyPreconditionHeaders::parse(req)?;
+	let lock = ObjectLockParams::from_request(dest_bucket, req.headers())?;
 
 	let source_object = get_copy_source(&garage, api_key, req).await?;
 
+	// Check that the copy does not replace a version protected by object lock
+	if dest_bucket.object_lock_enabled() {
+		if let Some(dest_object) = garage
+			.object_table
+			.get(&dest_bucket_id, &dest_key.to_string())
+			.await?
+		{
+			let bypass_governance = can_bypass_governance(req.headers(), api_key, dest_bucket);
+			check_object_lock_replace(&dest_object, bypass_governance)?;
+		}
+	}
+
 	let (source_version, source_version_data, source_version_meta) =
 		extract_source_info(&source_object)?;
 
 	// Check precondition, e.g. x-amz-copy-source-if-match
 	copy_precondition.check(source_version, &source_version_meta.etag)?;
 
+	// Blocks of an encrypted source can be shared with the copy, which keeps its
+	// encryption key. An unencrypted source is encrypted if the request or the
+	// default encryption of the destination bucket asks for it.
+	let encryption = match source_version_meta.encryption {
+		Some(encryption) => Some(encryption),
+		None => object_encryption_for_put(&garage, dest_bucket, req.headers())?,
+	};
+	let encrypt_cipher = match source_version_meta.encryption {
+		Some(_) => None,
+		None => ObjectCipher::for_object(&garage, encryption.as_ref())?,
+	};
+
 	// Generate parameters for copied object
 	let new_uuid = gen_uuid();
 	let new_timestamp = now_msec();
+	let versioned = dest_bucket.versioning_enabled();
 
 	// Implement x-amz-metadata-directive: REPLACE
+	// (object lock retention is never copied from the source object)
+	//
+	// Even when the source and destination are the same object and only
+	// metadata changes, we always write a new version instead of updating
+	// the metadata of the existing one in place:
+	// - the data of a version is indexed by its UUID in the version and
+	//   block ref tables, so the new metadata cannot point to the existing
+	//   data without giving it the UUID of the old version, and the old
+	//   version being removed from the object would then delete the data;
+	// - two different Complete states of the same version are merged by
+	//   picking one arbitrarily (see the AutoCrdt impl of ObjectVersionData),
+	//   so an in-place update would not be reliably applied on all nodes.
+	// The etag is kept from the source, as the data is unchanged.
 	let new_meta = match req.headers().get("x-amz-metadata-directive") {
 		Some(v) if v == hyper::header::HeaderValue::from_static("REPLACE") => ObjectVersionMeta {
 			headers: get_headers(req.headers())?,
 			size: source_version_meta.size,
 			etag: source_version_meta.etag.clone(),
+			lock: lock.retention,
+			encryption,
+		},
+		_ => ObjectVersionMeta {
+			lock: lock.retention,
+			encryption,
+			..source_version_meta.clone()
 		},
-		_ => source_version_meta.clone(),
 	};
 
 	let etag = new_meta.etag.to_string();
@@ -63,13 +113,16 @@ pub async fn handle_copy(
 	match source_version_data {
 		ObjectVersionData::DeleteMarker => unreachable!(),
 		ObjectVersionData::Inline(_meta, bytes) => {
+			let bytes = match &encrypt_cipher {
+				Some(cipher) => cipher.encrypt_block(1, 0, bytes)?,
+				None => bytes.clone(),
+			};
 			let dest_object_version = ObjectVersion {
 				uuid: new_uuid,
 				timestamp: new_timestamp,
-				state: ObjectVersionState::Complete(ObjectVersionData::Inline(
-					new_meta,
-					bytes.clone(),
-				)),
+				state: ObjectVersionState::Complete(ObjectVersionData::Inline(new_meta, bytes)),
+				versioned,
+				legal_hold: lock.legal_hold(),
 			};
 			let dest_object = Object::new(
 				dest_bucket_id,
@@ -78,7 +131,7 @@ pub async fn handle_copy(
 			);
 			garage.object_table.insert(&dest_object).await?;
 		}
-		ObjectVersionData::FirstBlock(_meta, first_block_hash) => {
+		ObjectVersionData::FirstBlock(_meta, _first_block_hash) => {
 			// Get block list from source version
 			let source_version = garage
 				.version_table
@@ -93,6 +146,8 @@ pub async fn handle_copy(
 				uuid: new_uuid,
 				timestamp: new_timestamp,
 				state: ObjectVersionState::Uploading(new_meta.headers.clone()),
+				versioned,
+				legal_hold: lock.legal_hold(),
 			};
 			let tmp_dest_object = Object::new(
 				dest_bucket_id,
@@ -111,8 +166,33 @@ pub async fn handle_copy(
 
 			// Fill in block list for version and insert block refs
 			for (bk, bv) in source_version.blocks.items().iter() {
-				dest_version.blocks.put(*bk, *bv);
+				match &encrypt_cipher {
+					Some(cipher) => {
+						// Encrypted blocks are different from the source blocks,
+						// they are written under their new hash
+						let data = garage.block_manager.rpc_get_block(&bv.hash, None).await?;
+						let data =
+							Bytes::from(cipher.encrypt_block(bk.part_number, bk.offset, &data)?);
+						let hash = blake2sum(&data[..]);
+						garage.block_manager.rpc_put_block(hash, data).await?;
+						dest_version.blocks.put(
+							*bk,
+							VersionBlock {
+								hash,
+								size: bv.size,
+							},
+						);
+					}
+					None => dest_version.blocks.put(*bk, *bv),
+				}
 			}
+			let first_block_hash = dest_version
+				.blocks
+				.items()
+				.first()
+				.ok_or_internal_error("Source version has no blocks")?
+				.1
+				.hash;
 			let dest_block_refs = dest_version
 				.blocks
 				.items()
@@ -139,8 +219,10 @@ pub async fn handle_copy(
 				timestamp: new_timestamp,
 				state: ObjectVersionState::Complete(ObjectVersionData::FirstBlock(
 					new_meta,
-					*first_block_hash,
+					first_block_hash,
 				)),
+				versioned,
+				legal_hold: lock.legal_hold(),
 			};
 			let dest_object = Object::new(
 				dest_bucket_id,
@@ -151,6 +233,18 @@ pub async fn handle_copy(
 		}
 	}
 
+	notify_object_event(
+		&garage,
+		dest_bucket,
+		ObjectEvent {
+			name: "ObjectCreated:Copy",
+			key: dest_key,
+			version_uuid: new_uuid,
+			size: Some(source_version_meta.size),
+			etag: Some(&etag),
+		},
+	);
+
 	let last_modified = msec_to_rfc3339(new_timestamp);
 	let result = CopyObjectResult {
 		last_modified: s3_xml::Value(last_modified),
@@ -158,14 +252,16 @@ pub async fn handle_copy(
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::builder()
-		.header("Content-Type", "application/xml")
-		.header("x-amz-version-id", hex::encode(new_uuid))
-		.header(
-			"x-amz-copy-source-version-id",
-			hex::encode(source_version.uuid),
-		)
-		.body(Body::from(xml))?)
+	Ok(
+		add_encryption_headers(encryption.as_ref(), Response::builder())
+			.header("Content-Type", "application/xml")
+			.header("x-amz-version-id", hex::encode(new_uuid))
+			.header(
+				"x-amz-copy-source-version-id",
+				hex::encode(source_version.uuid),
+			)
+			.body(Body::from(xml))?,
+	)
 }
 
 pub async fn handle_upload_part_copy(
@@ -218,13 +314,23 @@ pub async fn handle_upload_part_copy(
 	};
 
 	// Check destination version is indeed in uploading state
-	if !dest_object
+	let dest_encryption = match dest_object
 		.versions()
 		.iter()
-		.any(|v| v.uuid == dest_version_uuid && v.is_uploading())
+		.find(|v| v.uuid == dest_version_uuid && v.is_uploading())
+		.map(|v| &v.state)
 	{
-		return Err(Error::NoSuchUpload);
-	}
+		Some(ObjectVersionState::Uploading(headers)) => encryption_from_headers(&headers.other)?,
+		_ => return Err(Error::NoSuchUpload),
+	};
+
+	// Blocks of encrypted objects can only be decrypted with their original
+	// part number and offset, so they are decrypted when read from the source
+	// and encrypted again for the destination, instead of being reused
+	let source_cipher =
+		ObjectCipher::for_object(&garage, source_version_meta.encryption.as_ref())?.map(Arc::new);
+	let dest_cipher = ObjectCipher::for_object(&garage, dest_encryption.as_ref())?;
+	let reuse_blocks = source_cipher.is_none() && dest_cipher.is_none();
 
 	// Check source version is not inlined
 	match source_version_data {
@@ -272,7 +378,7 @@ pub async fn handle_upload_part_copy(
 
 	let mut blocks_to_copy = vec![];
 	let mut current_offset = 0;
-	for (_bk, block) in source_version.blocks.items().iter() {
+	for (bk, block) in source_version.blocks.items().iter() {
 		let (block_begin, block_end) = (current_offset, current_offset + block.size);
 
 		if block_begin < range_end && block_end > range_begin {
@@ -293,7 +399,7 @@ pub async fn handle_upload_part_copy(
 				(None, None) => None,
 			};
 
-			blocks_to_copy.push((block.hash, range_to_copy));
+			blocks_to_copy.push((*bk, block.hash, range_to_copy));
 		}
 
 		current_offset = block_end;
@@ -311,16 +417,26 @@ pub async fn handle_upload_part_copy(
 	let order_stream = OrderTag::stream();
 	let source_blocks = stream::iter(blocks_to_copy)
 		.enumerate()
-		.flat_map(|(i, (block_hash, range_to_copy))| {
+		.flat_map(|(i, (block_key, block_hash, range_to_copy))| {
 			let garage3 = garage2.clone();
+			let source_cipher = source_cipher.clone();
 			stream::once(async move {
 				let data = garage3
 					.block_manager
 					.rpc_get_block(&block_hash, Some(order_stream.order(i as u64)))
 					.await?;
+				let data = match &source_cipher {
+					Some(cipher) => Bytes::from(cipher.decrypt_block(
+						block_key.part_number,
+						block_key.offset,
+						&data,
+					)?),
+					None => data,
+				};
 				match range_to_copy {
 					Some(r) => Ok((data.slice(r), None)),
-					None => Ok((data, Some(block_hash))),
+					None if reuse_blocks => Ok((data, Some(block_hash))),
+					None => Ok((data, None)),
 				}
 			})
 		})
@@ -332,7 +448,7 @@ pub async fn handle_upload_part_copy(
 	// When it is done, it returns an empty vec.
 	// Same as the previous iterator, the Option is Some(_) if and only if
 	// it's an existing block of the Garage data store.
-	let mut defragmenter = Defragmenter::new(garage.config.block_size, Box::pin(source_blocks));
+	let mut defragmenter = Defragmenter::new(garage.block_size(), Box::pin(source_blocks));
 
 	let mut current_offset = 0;
 	let mut next_block = defragmenter.next().await?;
@@ -345,6 +461,13 @@ pub async fn handle_upload_part_copy(
 
 		md5hasher.update(&data[..]);
 
+		let data_len = data.len() as u64;
+		let data = match &dest_cipher {
+			Some(cipher) => {
+				Bytes::from(cipher.encrypt_block(part_number, current_offset, &data)?)
+			}
+			None => data,
+		};
 		let must_upload = existing_block_hash.is_none();
 		let final_hash = existing_block_hash.unwrap_or_else(|| blake2sum(&data[..]));
 
@@ -356,10 +479,10 @@ pub async fn handle_upload_part_copy(
 			},
 			VersionBlock {
 				hash: final_hash,
-				size: data.len() as u64,
+				size: data_len,
 			},
 		);
-		current_offset += data.len() as u64;
+		current_offset += data_len;
 
 		let block_ref = BlockRef {
 			block: final_hash,
@@ -373,15 +496,22 @@ pub async fn handle_upload_part_copy(
 			// we need to insert that data as a new block.
 			async move {
 				if must_upload {
-					garage2.block_manager.rpc_put_block(final_hash, data).await
+					garage2
+						.block_manager
+						.rpc_put_block(final_hash, data)
+						.await
+						.map_err(Error::from)
 				} else {
 					Ok(())
 				}
 			},
 			// Thing 2: we need to insert the block in the version
-			garage.version_table.insert(&version),
+			garage.version_table.insert(&version).map_err(Error::from),
 			// Thing 3: we need to add a block reference
-			garage.block_ref_table.insert(&block_ref),
+			garage
+				.block_ref_table
+				.insert(&block_ref)
+				.map_err(Error::from),
 			// Thing 4: we need to prefetch the next block
 			defragmenter.next(),
 		)?;
@@ -403,13 +533,15 @@ pub async fn handle_upload_part_copy(
 		last_modified: s3_xml::Value(msec_to_rfc3339(source_object_version.timestamp)),
 	})?;
 
-	Ok(Response::builder()
-		.header("Content-Type", "application/xml")
-		.header(
-			"x-amz-copy-source-version-id",
-			hex::encode(source_object_version.uuid),
-		)
-		.body(Body::from(resp_xml))?)
+	Ok(
+		add_encryption_headers(dest_encryption.as_ref(), Response::builder())
+			.header("Content-Type", "application/xml")
+			.header(
+				"x-amz-copy-source-version-id",
+				hex::encode(source_object_version.uuid),
+			)
+			.body(Body::from(resp_xml))?,
+	)
 }
 
 async fn get_copy_source(
@@ -447,12 +579,7 @@ async fn get_copy_source(
 fn extract_source_info(
 	source_object: &Object,
 ) -> Result<(&ObjectVersion, &ObjectVersionData, &ObjectVersionMeta), Error> {
-	let source_version = source_object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
+	let source_version = source_object.current_version().ok_or(Error::NoSuchKey)?;
 
 	let source_version_data = match &source_version.state {
 		ObjectVersionState::Complete(x) => x,
@@ -562,7 +689,7 @@ impl CopyPreconditionHeaders {
 }
 
 type BlockStreamItemOk = (Bytes, Option<Hash>);
-type BlockStreamItem = Result<BlockStreamItemOk, garage_util::error::Error>;
+type BlockStreamItem = Result<BlockStreamItemOk, Error>;
 
 struct Defragmenter<S: Stream<Item = BlockStreamItem>> {
 	block_size: usize,
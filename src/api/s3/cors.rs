@@ -3,7 +3,8 @@ use std::sync::Arc;
 
 use http::header::{
 	ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
-	ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+	ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+	ACCESS_CONTROL_REQUEST_METHOD, VARY,
 };
 use hyper::{header::HeaderName, Body, Method, Request, Response, StatusCode};
 
@@ -168,7 +169,23 @@ pub fn handle_options_for_bucket(
 			let mut resp = Response::builder()
 				.status(StatusCode::OK)
 				.body(Body::empty())?;
-			add_cors_headers(&mut resp, rule).ok_or_internal_error("Invalid CORS configuration")?;
+			let matching = MatchingCorsRule {
+				rule,
+				origin: origin.to_string(),
+			};
+			add_cors_headers(&mut resp, &matching)
+				.ok_or_internal_error("Invalid CORS configuration")?;
+			// Allowed headers can be patterns: answer a preflight request
+			// with the headers it asked for, that all matched the rule
+			if !request_headers.is_empty() {
+				resp.headers_mut().insert(
+					ACCESS_CONTROL_ALLOW_HEADERS,
+					request_headers
+						.join(", ")
+						.parse()
+						.ok_or_internal_error("Invalid CORS configuration")?,
+				);
+			}
 			return Ok(resp);
 		}
 	}
@@ -176,10 +193,17 @@ pub fn handle_options_for_bucket(
 	Err(Error::forbidden("This CORS request is not allowed."))
 }
 
+/// A CORS rule of a bucket that matched a request,
+/// along with the origin of that request
+pub struct MatchingCorsRule<'a> {
+	pub rule: &'a GarageCorsRule,
+	pub origin: String,
+}
+
 pub fn find_matching_cors_rule<'a>(
 	bucket: &'a Bucket,
 	req: &Request<Body>,
-) -> Result<Option<&'a GarageCorsRule>, Error> {
+) -> Result<Option<MatchingCorsRule<'a>>, Error> {
 	if let Some(cors_config) = bucket.params().unwrap().cors_config.get() {
 		if let Some(origin) = req.headers().get("Origin") {
 			let origin = origin.to_str()?;
@@ -187,9 +211,15 @@ pub fn find_matching_cors_rule<'a>(
 				Some(h) => h.to_str()?.split(',').map(|h| h.trim()).collect::<Vec<_>>(),
 				None => vec![],
 			};
-			return Ok(cors_config.iter().find(|rule| {
-				cors_rule_matches(rule, origin, req.method().as_ref(), request_headers.iter())
-			}));
+			return Ok(cors_config
+				.iter()
+				.find(|rule| {
+					cors_rule_matches(rule, origin, req.method().as_ref(), request_headers.iter())
+				})
+				.map(|rule| MatchingCorsRule {
+					rule,
+					origin: origin.to_string(),
+				}));
 		}
 	}
 	Ok(None)
@@ -205,24 +235,43 @@ where
 	HI: Iterator<Item = S>,
 	S: AsRef<str>,
 {
-	rule.allow_origins.iter().any(|x| x == "*" || x == origin)
+	rule.allow_origins.iter().any(|x| glob_match(x, origin))
 		&& rule.allow_methods.iter().any(|x| x == "*" || x == method)
 		&& request_headers.all(|h| {
+			let h = h.as_ref().to_ascii_lowercase();
 			rule.allow_headers
 				.iter()
-				.any(|x| x == "*" || x == h.as_ref())
+				.any(|x| glob_match(&x.to_ascii_lowercase(), &h))
 		})
 }
 
+/// Match a value against a pattern of CORS rule, in which a `*`
+/// stands for any sequence of characters (S3 allows at most one)
+fn glob_match(pattern: &str, value: &str) -> bool {
+	match pattern.split_once('*') {
+		None => pattern == value,
+		Some((prefix, suffix)) => {
+			value.len() >= prefix.len() + suffix.len()
+				&& value.starts_with(prefix)
+				&& value.ends_with(suffix)
+		}
+	}
+}
+
 pub fn add_cors_headers(
 	resp: &mut Response<Body>,
-	rule: &GarageCorsRule,
+	matching: &MatchingCorsRule,
 ) -> Result<(), http::header::InvalidHeaderValue> {
+	let rule = matching.rule;
 	let h = resp.headers_mut();
-	h.insert(
-		ACCESS_CONTROL_ALLOW_ORIGIN,
-		rule.allow_origins.join(", ").parse()?,
-	);
+	// A rule can allow several origins or origin patterns, but the
+	// response can only contain one: the origin of the request
+	if rule.allow_origins.iter().any(|x| x == "*") {
+		h.insert(ACCESS_CONTROL_ALLOW_ORIGIN, "*".parse()?);
+	} else {
+		h.insert(ACCESS_CONTROL_ALLOW_ORIGIN, matching.origin.parse()?);
+		h.append(VARY, "Origin".parse()?);
+	}
 	h.insert(
 		ACCESS_CONTROL_ALLOW_METHODS,
 		rule.allow_methods.join(", ").parse()?,
@@ -235,6 +284,9 @@ pub fn add_cors_headers(
 		ACCESS_CONTROL_EXPOSE_HEADERS,
 		rule.expose_headers.join(", ").parse()?,
 	);
+	if let Some(max_age) = rule.max_age_seconds {
+		h.insert(ACCESS_CONTROL_MAX_AGE, max_age.into());
+	}
 	Ok(())
 }
 
@@ -302,6 +354,18 @@ impl CorsConfiguration {
 
 impl CorsRule {
 	pub fn validate(&self) -> Result<(), Error> {
+		for pattern in self
+			.allowed_origins
+			.iter()
+			.chain(self.allowed_headers.iter())
+		{
+			if pattern.0.matches('*').count() > 1 {
+				return Err(Error::bad_request(format!(
+					"Invalid CORSRule: {} contains more than one wildcard",
+					pattern.0
+				)));
+			}
+		}
 		for method in self.allowed_methods.iter() {
 			method
 				.0
@@ -427,4 +491,82 @@ mod tests {
 
 		Ok(())
 	}
+
+	#[test]
+	fn test_rule_matches() {
+		let rule = GarageCorsRule {
+			id: None,
+			max_age_seconds: Some(600),
+			allow_origins: vec!["https://*.example.com".into(), "https://perdu.com".into()],
+			allow_methods: vec!["GET".into(), "PUT".into()],
+			allow_headers: vec!["x-amz-*".into(), "Content-Type".into()],
+			expose_headers: vec![],
+		};
+		let no_headers = std::iter::empty::<&str>;
+
+		assert!(cors_rule_matches(
+			&rule,
+			"https://cdn.example.com",
+			"GET",
+			no_headers()
+		));
+		assert!(cors_rule_matches(
+			&rule,
+			"https://a.b.example.com",
+			"PUT",
+			no_headers()
+		));
+		assert!(cors_rule_matches(
+			&rule,
+			"https://perdu.com",
+			"GET",
+			no_headers()
+		));
+		assert!(!cors_rule_matches(
+			&rule,
+			"https://example.com",
+			"GET",
+			no_headers()
+		));
+		assert!(!cors_rule_matches(
+			&rule,
+			"http://cdn.example.com",
+			"GET",
+			no_headers()
+		));
+		assert!(!cors_rule_matches(
+			&rule,
+			"https://perdu.com",
+			"DELETE",
+			no_headers()
+		));
+
+		assert!(cors_rule_matches(
+			&rule,
+			"https://perdu.com",
+			"PUT",
+			["X-Amz-Date", "content-type"].iter()
+		));
+		assert!(!cors_rule_matches(
+			&rule,
+			"https://perdu.com",
+			"PUT",
+			["Authorization"].iter()
+		));
+	}
+
+	#[test]
+	fn test_validate_wildcards() {
+		let rule = |origin: &str| CorsRule {
+			id: None,
+			max_age_seconds: None,
+			allowed_origins: vec![origin.into()],
+			allowed_methods: vec!["GET".into()],
+			allowed_headers: vec![],
+			expose_headers: vec![],
+		};
+		assert!(rule("*").validate().is_ok());
+		assert!(rule("https://*.example.com").validate().is_ok());
+		assert!(rule("https://*.*.example.com").validate().is_err());
+	}
 }
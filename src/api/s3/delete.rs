@@ -1,28 +1,39 @@
+use std::net::IpAddr;
 use std::sync::Arc;
 
+use futures::stream::{self, StreamExt};
 use hyper::{Body, Request, Response, StatusCode};
 
 use garage_util::data::*;
 use garage_util::time::*;
 
+use garage_model::bucket_table::Bucket;
 use garage_model::garage::Garage;
 use garage_model::s3::object_table::*;
 
 use crate::s3::error::*;
+use crate::s3::notification::{notify_object_event, ObjectEvent};
+use crate::s3::object_lock::{check_object_lock_replace, check_version_lock};
+use crate::s3::policy::{Policy, PolicyAction, PolicyDecision};
+use crate::s3::put::decode_version_id;
 use crate::s3::xml as s3_xml;
 use crate::signature::verify_signed_content;
 
 async fn handle_delete_internal(
 	garage: &Garage,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
+	bypass_governance: bool,
 ) -> Result<(Uuid, Uuid), Error> {
 	let object = garage
 		.object_table
-		.get(&bucket_id, &key.to_string())
+		.get(&bucket.id, &key.to_string())
 		.await?
 		.ok_or(Error::NoSuchKey)?; // No need to delete
 
+	// The delete marker must not remove a version protected by object lock
+	check_object_lock_replace(&object, bypass_governance)?;
+
 	let interesting_versions = object.versions().iter().filter(|v| {
 		!matches!(
 			v.state,
@@ -45,39 +56,232 @@ async fn handle_delete_internal(
 	let version_uuid = gen_uuid();
 
 	let object = Object::new(
-		bucket_id,
+		bucket.id,
 		key.into(),
 		vec![ObjectVersion {
 			uuid: version_uuid,
 			timestamp,
 			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			versioned: bucket.versioning_enabled(),
+			legal_hold: Default::default(),
 		}],
 	);
 
 	garage.object_table.insert(&object).await?;
 
+	notify_object_event(
+		garage,
+		bucket,
+		ObjectEvent {
+			name: if bucket.versioning_enabled() {
+				"ObjectRemoved:DeleteMarkerCreated"
+			} else {
+				"ObjectRemoved:Delete"
+			},
+			key,
+			version_uuid,
+			size: None,
+			etag: None,
+		},
+	);
+
 	Ok((deleted_version, version_uuid))
 }
 
+/// Permanently remove a version of an object, or a delete marker.
+/// Returns the removed version.
+async fn handle_delete_version_internal(
+	garage: &Garage,
+	bucket: &Bucket,
+	key: &str,
+	version_id: &str,
+	bypass_governance: bool,
+) -> Result<ObjectVersion, Error> {
+	let object = garage
+		.object_table
+		.get(&bucket.id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+
+	let version = match version_id {
+		"null" => object
+			.versions()
+			.iter()
+			.rev()
+			.find(|v| v.is_complete() && !v.versioned),
+		id => {
+			let uuid = decode_version_id(id)?;
+			object
+				.versions()
+				.iter()
+				.find(|v| v.is_complete() && v.uuid == uuid)
+		}
+	}
+	.ok_or(Error::NoSuchKey)?;
+
+	check_version_lock(version, bypass_governance)?;
+
+	// Aborted versions are removed from the object, and their data is deleted
+	let object = Object::new(
+		bucket.id,
+		key.into(),
+		vec![ObjectVersion {
+			state: ObjectVersionState::Aborted,
+			..version.clone()
+		}],
+	);
+	garage.object_table.insert(&object).await?;
+
+	notify_object_event(
+		garage,
+		bucket,
+		ObjectEvent {
+			name: "ObjectRemoved:Delete",
+			key,
+			version_uuid: version.uuid,
+			size: None,
+			etag: None,
+		},
+	);
+
+	Ok(version.clone())
+}
+
 pub async fn handle_delete(
 	garage: Arc<Garage>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
+	version_id: Option<&str>,
+	bypass_governance: bool,
 ) -> Result<Response<Body>, Error> {
-	match handle_delete_internal(&garage, bucket_id, key).await {
-		Ok(_) | Err(Error::NoSuchKey) => Ok(Response::builder()
-			.status(StatusCode::NO_CONTENT)
-			.body(Body::from(vec![]))
-			.unwrap()),
-		Err(e) => Err(e),
+	let resp = Response::builder().status(StatusCode::NO_CONTENT);
+	let resp = match version_id {
+		None => match handle_delete_internal(&garage, bucket, key, bypass_governance).await {
+			Ok((_, delete_marker)) if bucket.versioning_enabled() => resp
+				.header("x-amz-version-id", hex::encode(delete_marker))
+				.header("x-amz-delete-marker", "true"),
+			Ok(_) | Err(Error::NoSuchKey) => resp,
+			Err(e) => return Err(e),
+		},
+		Some(version_id) => {
+			match handle_delete_version_internal(
+				&garage,
+				bucket,
+				key,
+				version_id,
+				bypass_governance,
+			)
+			.await
+			{
+				Ok(version) if version.is_data() => {
+					resp.header("x-amz-version-id", hex::encode(version.uuid))
+				}
+				Ok(version) => resp
+					.header("x-amz-version-id", hex::encode(version.uuid))
+					.header("x-amz-delete-marker", "true"),
+				Err(Error::NoSuchKey) => resp,
+				Err(e) => return Err(e),
+			}
+		}
+	};
+	Ok(resp.body(Body::from(vec![])).unwrap())
+}
+
+/// Maximum number of objects in a DeleteObjects request, as in AWS
+const MAX_DELETE_OBJECTS: usize = 1000;
+
+/// Number of objects of a DeleteObjects request that are deleted concurrently
+const DELETE_OBJECTS_CONCURRENCY: usize = 16;
+
+/// Permissions of the key that makes a DeleteObjects request, which are
+/// checked for each of the objects to delete
+pub struct DeleteObjectsAuth<'a> {
+	pub allow_write: bool,
+	pub key_id: &'a str,
+	pub policy: Option<Policy>,
+	pub source_ip: Option<IpAddr>,
+	pub bypass_governance: bool,
+}
+
+impl<'a> DeleteObjectsAuth<'a> {
+	fn check(&self, key: &str) -> Result<(), Error> {
+		let decision = match &self.policy {
+			Some(policy) => policy.evaluate(
+				Some(self.key_id),
+				PolicyAction::DeleteObject,
+				Some(key),
+				self.source_ip,
+			),
+			None => PolicyDecision::NotApplicable,
+		};
+		match decision {
+			PolicyDecision::Deny => Err(Error::forbidden(
+				"Operation is denied by the bucket policy.",
+			)),
+			PolicyDecision::NotApplicable if !self.allow_write => {
+				Err(Error::forbidden("Operation is not allowed for this key."))
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Delete an object of a DeleteObjects request, or one of its versions if a
+/// version ID is given, the same way as with DeleteObject
+async fn delete_object_checked(
+	garage: &Garage,
+	bucket: &Bucket,
+	auth: &DeleteObjectsAuth<'_>,
+	obj: &DeleteObject,
+) -> Result<s3_xml::Deleted, Error> {
+	auth.check(&obj.key)?;
+	let key = s3_xml::Value(obj.key.clone());
+	match &obj.version_id {
+		None => {
+			let (deleted_version, delete_marker_version) =
+				handle_delete_internal(garage, bucket, &obj.key, auth.bypass_governance).await?;
+			Ok(s3_xml::Deleted {
+				key,
+				version_id: Some(s3_xml::Value(hex::encode(deleted_version))),
+				delete_marker: None,
+				delete_marker_version_id: Some(s3_xml::Value(hex::encode(delete_marker_version))),
+			})
+		}
+		Some(version_id) => {
+			let version_id_value = || Some(s3_xml::Value(version_id.clone()));
+			match handle_delete_version_internal(
+				garage,
+				bucket,
+				&obj.key,
+				version_id,
+				auth.bypass_governance,
+			)
+			.await
+			{
+				Ok(version) if !version.is_data() => Ok(s3_xml::Deleted {
+					key,
+					version_id: version_id_value(),
+					delete_marker: Some(s3_xml::Value("true".into())),
+					delete_marker_version_id: version_id_value(),
+				}),
+				Ok(_) | Err(Error::NoSuchKey) => Ok(s3_xml::Deleted {
+					key,
+					version_id: version_id_value(),
+					delete_marker: None,
+					delete_marker_version_id: None,
+				}),
+				Err(e) => Err(e),
+			}
+		}
 	}
 }
 
 pub async fn handle_delete_objects(
 	garage: Arc<Garage>,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	req: Request<Body>,
 	content_sha256: Option<Hash>,
+	auth: DeleteObjectsAuth<'_>,
 ) -> Result<Response<Body>, Error> {
 	let body = hyper::body::to_bytes(req.into_body()).await?;
 
@@ -88,27 +292,42 @@ pub async fn handle_delete_objects(
 	let cmd_xml = roxmltree::Document::parse(std::str::from_utf8(&body)?)?;
 	let cmd = parse_delete_objects_xml(&cmd_xml).ok_or_bad_request("Invalid delete XML query")?;
 
+	if cmd.objects.len() > MAX_DELETE_OBJECTS {
+		return Err(Error::InvalidXml(format!(
+			"A DeleteObjects request can delete at most {} objects",
+			MAX_DELETE_OBJECTS
+		)));
+	}
+
+	// Objects are deleted concurrently, but the results are reported in
+	// the order of the request
+	let deletes = cmd
+		.objects
+		.iter()
+		.map(|obj| delete_object_checked(&garage, bucket, &auth, obj))
+		.collect::<Vec<_>>();
+	let results = stream::iter(deletes)
+		.buffered(DELETE_OBJECTS_CONCURRENCY)
+		.collect::<Vec<_>>()
+		.await;
+
 	let mut ret_deleted = Vec::new();
 	let mut ret_errors = Vec::new();
 
-	for obj in cmd.objects.iter() {
-		match handle_delete_internal(&garage, bucket_id, &obj.key).await {
-			Ok((deleted_version, delete_marker_version)) => {
+	for (obj, res) in cmd.objects.iter().zip(results) {
+		match res {
+			Ok(deleted) => {
 				if cmd.quiet {
 					continue;
 				}
-				ret_deleted.push(s3_xml::Deleted {
-					key: s3_xml::Value(obj.key.clone()),
-					version_id: s3_xml::Value(hex::encode(deleted_version)),
-					delete_marker_version_id: s3_xml::Value(hex::encode(delete_marker_version)),
-				});
+				ret_deleted.push(deleted);
 			}
 			Err(e) => {
 				ret_errors.push(s3_xml::DeleteError {
 					code: s3_xml::Value(e.aws_code().to_string()),
 					key: Some(s3_xml::Value(obj.key.clone())),
 					message: s3_xml::Value(format!("{}", e)),
-					version_id: None,
+					version_id: obj.version_id.clone().map(s3_xml::Value),
 				});
 			}
 		}
@@ -132,6 +351,7 @@ struct DeleteRequest {
 
 struct DeleteObject {
 	key: String,
+	version_id: Option<String>,
 }
 
 fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest> {
@@ -151,8 +371,13 @@ fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest>
 		if item.has_tag_name("Object") {
 			let key = item.children().find(|e| e.has_tag_name("Key"))?;
 			let key_str = key.text()?;
+			let version_id = match item.children().find(|e| e.has_tag_name("VersionId")) {
+				Some(v) => Some(v.text()?.to_string()),
+				None => None,
+			};
 			ret.objects.push(DeleteObject {
 				key: key_str.to_string(),
+				version_id,
 			});
 		} else if item.has_tag_name("Quiet") {
 			if item.text()? == "true" {
@@ -167,3 +392,21 @@ fn parse_delete_objects_xml(xml: &roxmltree::Document) -> Option<DeleteRequest>
 
 	Some(ret)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_delete_objects_xml() {
+		let xml = r#"<Delete xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Object><Key>a</Key></Object><Object><Key>b</Key><VersionId>null</VersionId></Object><Quiet>true</Quiet></Delete>"#;
+		let doc = roxmltree::Document::parse(xml).unwrap();
+		let req = parse_delete_objects_xml(&doc).unwrap();
+		assert!(req.quiet);
+		assert_eq!(req.objects.len(), 2);
+		assert_eq!(req.objects[0].key, "a");
+		assert_eq!(req.objects[0].version_id, None);
+		assert_eq!(req.objects[1].key, "b");
+		assert_eq!(req.objects[1].version_id.as_deref(), Some("null"));
+	}
+}
@@ -0,0 +1,414 @@
+use quick_xml::de::from_reader;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use hmac::{Hmac, Mac};
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use sha2::Sha256;
+
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::{Bucket, BucketEncryption};
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::ObjectEncryption;
+use garage_util::data::*;
+
+pub const X_AMZ_SERVER_SIDE_ENCRYPTION: &str = "x-amz-server-side-encryption";
+
+/// Header in which the IV of an encrypted multipart upload is kept until it is completed
+const X_GARAGE_SSE_S3_IV: &str = "x-garage-sse-s3-iv";
+
+pub async fn handle_get_encryption(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	if let Some(encryption) = param.default_encryption.get() {
+		let conf = ServerSideEncryptionConfiguration::from_garage_encryption(*encryption);
+		let xml = to_xml_with_header(&conf)?;
+		Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/xml")
+			.body(Body::from(xml))?)
+	} else {
+		Err(Error::NoSuchEncryptionConfiguration)
+	}
+}
+
+pub async fn handle_delete_encryption(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.default_encryption.update(None);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_put_encryption(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	let conf: ServerSideEncryptionConfiguration = from_reader(&body as &[u8])?;
+	let encryption = conf.validate_into_garage_encryption()?;
+
+	param.default_encryption.update(Some(encryption));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- ENCRYPTION OF OBJECTS ----
+
+/// Determine the server-side encryption to apply to a new object, from the
+/// x-amz-server-side-encryption header and the default encryption of the bucket
+pub fn object_encryption_for_put(
+	garage: &Garage,
+	bucket: &Bucket,
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<ObjectEncryption>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	let sse_s3 = match headers.get(X_AMZ_SERVER_SIDE_ENCRYPTION) {
+		Some(alg) => match alg.to_str()? {
+			SSE_ALGORITHM_AES256 => true,
+			"aws:kms" | "aws:kms:dsse" => {
+				return Err(Error::NotImplemented(
+					"Server-side encryption with KMS keys".into(),
+				))
+			}
+			alg => {
+				return Err(Error::bad_request(format!(
+					"Invalid server-side encryption algorithm: {}",
+					alg
+				)))
+			}
+		},
+		None => matches!(
+			param.default_encryption.get(),
+			Some(BucketEncryption::SseS3)
+		),
+	};
+	if !sse_s3 {
+		return Ok(None);
+	}
+
+	if garage.config.s3_api.sse_s3_master_key.is_none() {
+		return Err(Error::NotImplemented(
+			"Server-side encryption (SSE-S3) is not available as no sse_s3_master_key is configured"
+				.into(),
+		));
+	}
+	let iv = gen_uuid().as_slice()[..16].try_into().unwrap();
+	Ok(Some(ObjectEncryption::SseS3 { iv }))
+}
+
+/// Store the encryption of a multipart upload in the headers of the object
+/// version being uploaded, so that its parts are encrypted with the same key
+pub fn store_encryption_in_headers(
+	encryption: Option<&ObjectEncryption>,
+	headers: &mut BTreeMap<String, String>,
+) {
+	if let Some(ObjectEncryption::SseS3 { iv }) = encryption {
+		headers.insert(X_GARAGE_SSE_S3_IV.to_string(), hex::encode(iv));
+	}
+}
+
+/// Get the encryption stored by `store_encryption_in_headers` in the headers
+/// of an object version being uploaded
+pub fn encryption_from_headers(
+	headers: &BTreeMap<String, String>,
+) -> Result<Option<ObjectEncryption>, Error> {
+	match headers.get(X_GARAGE_SSE_S3_IV) {
+		Some(iv) => {
+			let iv = hex::decode(iv)
+				.ok()
+				.and_then(|iv| iv.try_into().ok())
+				.ok_or_internal_error("Invalid encryption IV in the headers of the upload")?;
+			Ok(Some(ObjectEncryption::SseS3 { iv }))
+		}
+		None => Ok(None),
+	}
+}
+
+/// Remove the encryption stored by `store_encryption_in_headers` from the headers
+/// of an object version being uploaded
+pub fn take_encryption_from_headers(
+	headers: &mut BTreeMap<String, String>,
+) -> Result<Option<ObjectEncryption>, Error> {
+	let encryption = encryption_from_headers(headers)?;
+	headers.remove(X_GARAGE_SSE_S3_IV);
+	Ok(encryption)
+}
+
+/// Add the x-amz-server-side-encryption header to a response for an object
+pub fn add_encryption_headers(
+	encryption: Option<&ObjectEncryption>,
+	resp: http::response::Builder,
+) -> http::response::Builder {
+	match encryption {
+		Some(ObjectEncryption::SseS3 { .. }) => {
+			resp.header(X_AMZ_SERVER_SIDE_ENCRYPTION, SSE_ALGORITHM_AES256)
+		}
+		None => resp,
+	}
+}
+
+/// Cipher used to encrypt and decrypt the blocks of an object stored with SSE-S3.
+/// Each block is encrypted separately, using its part number and its offset
+/// in the part as the nonce, so that blocks can be decrypted independently
+/// and shared with copies of the object.
+pub struct ObjectCipher(Aes256Gcm);
+
+impl ObjectCipher {
+	pub fn new(garage: &Garage, encryption: &ObjectEncryption) -> Result<Self, Error> {
+		let master_key = garage
+			.config
+			.s3_api
+			.sse_s3_master_key
+			.ok_or_internal_error("Object is encrypted but no sse_s3_master_key is configured")?;
+		Ok(Self::from_master_key(&master_key, encryption))
+	}
+
+	fn from_master_key(master_key: &[u8; 32], encryption: &ObjectEncryption) -> Self {
+		let ObjectEncryption::SseS3 { iv } = encryption;
+		let mut kdf = <Hmac<Sha256> as Mac>::new_from_slice(master_key)
+			.expect("HMAC can take a key of any size");
+		kdf.update(iv);
+		let key = kdf.finalize().into_bytes();
+		Self(Aes256Gcm::new(&key))
+	}
+
+	/// Optionnally build the cipher for an object, if it is encrypted
+	pub fn for_object(
+		garage: &Garage,
+		encryption: Option<&ObjectEncryption>,
+	) -> Result<Option<Self>, Error> {
+		encryption.map(|e| Self::new(garage, e)).transpose()
+	}
+
+	pub fn encrypt_block(
+		&self,
+		part_number: u64,
+		offset: u64,
+		data: &[u8],
+	) -> Result<Vec<u8>, Error> {
+		Ok(self
+			.0
+			.encrypt(&Self::nonce(part_number, offset), data)
+			.ok()
+			.ok_or_internal_error("Unable to encrypt block")?)
+	}
+
+	pub fn decrypt_block(
+		&self,
+		part_number: u64,
+		offset: u64,
+		data: &[u8],
+	) -> Result<Vec<u8>, Error> {
+		Ok(self
+			.0
+			.decrypt(&Self::nonce(part_number, offset), data)
+			.ok()
+			.ok_or_internal_error("Unable to decrypt block")?)
+	}
+
+	fn nonce(part_number: u64, offset: u64) -> Nonce<aes_gcm::aead::consts::U12> {
+		let mut nonce = [0u8; 12];
+		nonce[..4].copy_from_slice(&(part_number as u32).to_be_bytes());
+		nonce[4..].copy_from_slice(&offset.to_be_bytes());
+		nonce.into()
+	}
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+pub const SSE_ALGORITHM_AES256: &str = "AES256";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename = "ServerSideEncryptionConfiguration")]
+pub struct ServerSideEncryptionConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Rule")]
+	pub rules: Vec<ServerSideEncryptionRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ServerSideEncryptionRule {
+	#[serde(rename = "ApplyServerSideEncryptionByDefault")]
+	pub apply_by_default: Option<ApplyServerSideEncryptionByDefault>,
+	#[serde(
+		rename = "BucketKeyEnabled",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub bucket_key_enabled: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ApplyServerSideEncryptionByDefault {
+	#[serde(rename = "SSEAlgorithm")]
+	pub sse_algorithm: Value,
+	#[serde(
+		rename = "KMSMasterKeyID",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub kms_master_key_id: Option<Value>,
+}
+
+impl ServerSideEncryptionConfiguration {
+	pub fn validate_into_garage_encryption(self) -> Result<BucketEncryption, Error> {
+		let rule = match &self.rules[..] {
+			[rule] => rule,
+			_ => {
+				return Err(Error::bad_request(
+					"Server-side encryption configuration must contain exactly one rule",
+				))
+			}
+		};
+		let apply = rule
+			.apply_by_default
+			.as_ref()
+			.ok_or_bad_request("Missing ApplyServerSideEncryptionByDefault")?;
+		match apply.sse_algorithm.0.as_str() {
+			SSE_ALGORITHM_AES256 if apply.kms_master_key_id.is_none() => {
+				Ok(BucketEncryption::SseS3)
+			}
+			SSE_ALGORITHM_AES256 => Err(Error::bad_request(
+				"KMSMasterKeyID can only be specified with the aws:kms algorithm",
+			)),
+			"aws:kms" | "aws:kms:dsse" => Err(Error::NotImplemented(
+				"Server-side encryption with KMS keys".into(),
+			)),
+			alg => Err(Error::bad_request(format!(
+				"Invalid server-side encryption algorithm: {}",
+				alg
+			))),
+		}
+	}
+
+	pub fn from_garage_encryption(encryption: BucketEncryption) -> Self {
+		let sse_algorithm = match encryption {
+			BucketEncryption::SseS3 => SSE_ALGORITHM_AES256,
+		};
+		Self {
+			xmlns: (),
+			rules: vec![ServerSideEncryptionRule {
+				apply_by_default: Some(ApplyServerSideEncryptionByDefault {
+					sse_algorithm: Value(sse_algorithm.into()),
+					kms_master_key_id: None,
+				}),
+				bucket_key_enabled: None,
+			}],
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_object_cipher() -> Result<(), Error> {
+		let enc = ObjectEncryption::SseS3 { iv: [3u8; 16] };
+		let cipher = ObjectCipher::from_master_key(&[7u8; 32], &enc);
+
+		let data = b"some object data";
+		let encrypted = cipher.encrypt_block(1, 0, data)?;
+		assert_ne!(&encrypted[..data.len()], &data[..]);
+		assert_eq!(cipher.decrypt_block(1, 0, &encrypted)?, data);
+
+		// The nonce depends on the position of the block in the object
+		assert!(cipher.decrypt_block(1, 1048576, &encrypted).is_err());
+		assert!(cipher.decrypt_block(2, 0, &encrypted).is_err());
+
+		// The key depends on the IV of the object
+		let other =
+			ObjectCipher::from_master_key(&[7u8; 32], &ObjectEncryption::SseS3 { iv: [4u8; 16] });
+		assert!(other.decrypt_block(1, 0, &encrypted).is_err());
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_deserialize_encryption_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ServerSideEncryptionConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <ApplyServerSideEncryptionByDefault>
+      <SSEAlgorithm>AES256</SSEAlgorithm>
+    </ApplyServerSideEncryptionByDefault>
+    <BucketKeyEnabled>false</BucketKeyEnabled>
+  </Rule>
+</ServerSideEncryptionConfiguration>"#;
+		let conf: ServerSideEncryptionConfiguration = from_str(message).unwrap();
+		assert_eq!(
+			conf.validate_into_garage_encryption()?,
+			BucketEncryption::SseS3
+		);
+
+		let message2 = to_xml_with_header(
+			&ServerSideEncryptionConfiguration::from_garage_encryption(BucketEncryption::SseS3),
+		)?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(
+			cleanup(&message2),
+			cleanup(
+				r#"<?xml version="1.0" encoding="UTF-8"?>
+<ServerSideEncryptionConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <ApplyServerSideEncryptionByDefault>
+      <SSEAlgorithm>AES256</SSEAlgorithm>
+    </ApplyServerSideEncryptionByDefault>
+  </Rule>
+</ServerSideEncryptionConfiguration>"#
+			)
+		);
+
+		let kms = message.replace("AES256", "aws:kms");
+		let conf: ServerSideEncryptionConfiguration = from_str(&kms).unwrap();
+		assert!(conf.validate_into_garage_encryption().is_err());
+
+		Ok(())
+	}
+}
@@ -44,6 +44,10 @@ pub enum Error {
 	#[error(display = "Parts given to CompleteMultipartUpload were not in ascending order")]
 	InvalidPartOrder,
 
+	/// The checksum declared by the client does not match the data received
+	#[error(display = "Bad digest: {}", _0)]
+	BadDigest(String),
+
 	/// In CompleteMultipartUpload: not enough data
 	/// (here we are more lenient than AWS S3)
 	#[error(display = "Proposed upload is smaller than the minimum allowed object size")]
@@ -73,6 +77,35 @@ pub enum Error {
 	/// The client sent a request for an action not supported by garage
 	#[error(display = "Unimplemented action: {}", _0)]
 	NotImplemented(String),
+
+	/// Object lock is not enabled on the bucket
+	#[error(display = "Object Lock configuration does not exist for this bucket")]
+	NoSuchObjectLockConfiguration,
+
+	/// The bucket has no policy
+	#[error(display = "The bucket policy does not exist")]
+	NoSuchBucketPolicy,
+
+	/// The bucket has no default encryption
+	#[error(display = "The server side encryption configuration was not found")]
+	NoSuchEncryptionConfiguration,
+
+	/// The bucket has no lifecycle configuration
+	#[error(display = "The lifecycle configuration does not exist")]
+	NoSuchLifecycleConfiguration,
+
+	/// The client sent an invalid bucket policy
+	#[error(display = "Invalid bucket policy: {}", _0)]
+	MalformedPolicy(String),
+
+	/// The request is not valid with the current state of the bucket
+	#[error(display = "Invalid bucket state: {}", _0)]
+	InvalidBucketState(String),
+
+	/// The rate limits of the access key were exceeded, the request
+	/// can be retried after the given number of seconds
+	#[error(display = "Rate limit exceeded, please retry in {} seconds", _0)]
+	SlowDown(u64),
 }
 
 impl<T> From<T> for Error
@@ -139,8 +172,18 @@ impl Error {
 			Error::InvalidPart => "InvalidPart",
 			Error::InvalidPartOrder => "InvalidPartOrder",
 			Error::EntityTooSmall => "EntityTooSmall",
+			Error::BadDigest(_) => "BadDigest",
 			Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
 			Error::NotImplemented(_) => "NotImplemented",
+			Error::NoSuchObjectLockConfiguration => "ObjectLockConfigurationNotFoundError",
+			Error::NoSuchBucketPolicy => "NoSuchBucketPolicy",
+			Error::NoSuchLifecycleConfiguration => "NoSuchLifecycleConfiguration",
+			Error::NoSuchEncryptionConfiguration => {
+				"ServerSideEncryptionConfigurationNotFoundError"
+			}
+			Error::MalformedPolicy(_) => "MalformedPolicy",
+			Error::InvalidBucketState(_) => "InvalidBucketState",
+			Error::SlowDown(_) => "SlowDown",
 			Error::InvalidXml(_) => "MalformedXML",
 			Error::InvalidRange(_) => "InvalidRange",
 			Error::InvalidUtf8Str(_) | Error::InvalidUtf8String(_) | Error::InvalidHeader(_) => {
@@ -155,15 +198,24 @@ impl ApiError for Error {
 	fn http_status_code(&self) -> StatusCode {
 		match self {
 			Error::Common(c) => c.http_status_code(),
-			Error::NoSuchKey | Error::NoSuchUpload => StatusCode::NOT_FOUND,
+			Error::NoSuchKey
+			| Error::NoSuchUpload
+			| Error::NoSuchObjectLockConfiguration
+			| Error::NoSuchBucketPolicy
+			| Error::NoSuchLifecycleConfiguration
+			| Error::NoSuchEncryptionConfiguration => StatusCode::NOT_FOUND,
+			Error::InvalidBucketState(_) => StatusCode::CONFLICT,
 			Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
 			Error::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
 			Error::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+			Error::SlowDown(_) => StatusCode::TOO_MANY_REQUESTS,
 			Error::AuthorizationHeaderMalformed(_)
 			| Error::InvalidPart
 			| Error::InvalidPartOrder
 			| Error::EntityTooSmall
+			| Error::BadDigest(_)
 			| Error::InvalidXml(_)
+			| Error::MalformedPolicy(_)
 			| Error::InvalidUtf8Str(_)
 			| Error::InvalidUtf8String(_)
 			| Error::InvalidHeader(_) => StatusCode::BAD_REQUEST,
@@ -175,7 +227,6 @@ impl ApiError for Error {
 
 		header_map.append(header::CONTENT_TYPE, "application/xml".parse().unwrap());
 
-		#[allow(clippy::single_match)]
 		match self {
 			Error::InvalidRange((_, len)) => {
 				header_map.append(
@@ -185,6 +236,9 @@ impl ApiError for Error {
 						.expect("header value only contain ascii"),
 				);
 			}
+			Error::SlowDown(retry_after) => {
+				header_map.append(header::RETRY_AFTER, (*retry_after).into());
+			}
 			_ => (),
 		}
 	}
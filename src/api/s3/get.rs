@@ -1,12 +1,14 @@
 //! Function related to GET and HEAD requests
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::{Duration, UNIX_EPOCH};
 
+use bytes::Bytes;
 use futures::future;
-use futures::stream::{self, StreamExt};
+use futures::stream::{self, Stream, StreamExt};
 use http::header::{
-	ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
-	IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+	ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE,
+	IF_NONE_MATCH, IF_UNMODIFIED_SINCE, LAST_MODIFIED, RANGE,
 };
 use hyper::{Body, Request, Response, StatusCode};
 use tokio::sync::mpsc;
@@ -20,9 +22,13 @@ use garage_model::garage::Garage;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
+use crate::s3::encryption::{add_encryption_headers, ObjectCipher};
 use crate::s3::error::*;
+use crate::s3::object_lock::{add_object_lock_headers, find_version};
+use crate::s3::xml as s3_xml;
 
 const X_AMZ_MP_PARTS_COUNT: &str = "x-amz-mp-parts-count";
+const X_AMZ_OBJECT_ATTRIBUTES: &str = "x-amz-object-attributes";
 
 fn object_headers(
 	version: &ObjectVersion,
@@ -42,49 +48,80 @@ fn object_headers(
 		resp = resp.header(ETAG, format!("\"{}\"", version_meta.etag));
 	}
 
+	if version.versioned {
+		resp = resp.header("x-amz-version-id", hex::encode(version.uuid));
+	}
+
 	for (k, v) in version_meta.headers.other.iter() {
 		resp = resp.header(k, v.to_string());
 	}
 
-	resp
+	resp = add_encryption_headers(version_meta.encryption.as_ref(), resp);
+
+	add_object_lock_headers(version, version_meta, resp)
 }
 
-fn try_answer_cached(
+/// Evaluate the conditional request headers (If-Match, If-Unmodified-Since,
+/// If-None-Match and If-Modified-Since), following the order of precedence
+/// defined in section 6 of RFC7232.
+/// Returns a 304 Not Modified response if the client's cached copy can be used,
+/// and fails with 412 Precondition Failed if a precondition does not hold.
+fn check_preconditions(
 	version: &ObjectVersion,
 	version_meta: &ObjectVersionMeta,
 	req: &Request<Body>,
-) -> Option<Response<Body>> {
+) -> Result<Option<Response<Body>>, Error> {
+	let etag = format!("\"{}\"", version_meta.etag);
+	let etag_matches = |header: &str| {
+		header
+			.split(',')
+			.map(str::trim)
+			.any(|x| x == etag || x == "*" || x == "\"*\"")
+	};
+	// HTTP dates have a precision of one second, which is also the precision
+	// of the Last-Modified header that we return
+	let server_date = UNIX_EPOCH + Duration::from_secs(version.timestamp / 1000);
+	// Invalid dates must be ignored (RFC7232, sections 3.3 and 3.4)
+	let header_date = |name| {
+		req.headers()
+			.get(name)
+			.and_then(|x| x.to_str().ok())
+			.and_then(|x| httpdate::parse_http_date(x).ok())
+	};
+
+	// Step 1 and 2: If-Match, or If-Unmodified-Since if If-Match is absent
+	if let Some(if_match) = req.headers().get(IF_MATCH) {
+		if !etag_matches(if_match.to_str()?) {
+			return Err(Error::PreconditionFailed);
+		}
+	} else if let Some(unmodified_since) = header_date(IF_UNMODIFIED_SINCE) {
+		if server_date > unmodified_since {
+			return Err(Error::PreconditionFailed);
+		}
+	}
+
+	// Step 3 and 4: If-None-Match, or If-Modified-Since if If-None-Match is absent.
 	// <trinity> It is possible, and is even usually the case, [that both If-None-Match and
 	// If-Modified-Since] are present in a request. In this situation If-None-Match takes
 	// precedence and If-Modified-Since is ignored (as per 6.Precedence from rfc7232). The rational
 	// being that etag based matching is more accurate, it has no issue with sub-second precision
 	// for instance (in case of very fast updates)
 	let cached = if let Some(none_match) = req.headers().get(IF_NONE_MATCH) {
-		let none_match = none_match.to_str().ok()?;
-		let expected = format!("\"{}\"", version_meta.etag);
-		let found = none_match
-			.split(',')
-			.map(str::trim)
-			.any(|etag| etag == expected || etag == "\"*\"");
-		found
-	} else if let Some(modified_since) = req.headers().get(IF_MODIFIED_SINCE) {
-		let modified_since = modified_since.to_str().ok()?;
-		let client_date = httpdate::parse_http_date(modified_since).ok()?;
-		let server_date = UNIX_EPOCH + Duration::from_millis(version.timestamp);
-		client_date >= server_date
+		etag_matches(none_match.to_str()?)
+	} else if let Some(modified_since) = header_date(IF_MODIFIED_SINCE) {
+		server_date <= modified_since
 	} else {
 		false
 	};
 
 	if cached {
-		Some(
+		Ok(Some(
 			Response::builder()
 				.status(StatusCode::NOT_MODIFIED)
-				.body(Body::empty())
-				.unwrap(),
-		)
+				.body(Body::empty())?,
+		))
 	} else {
-		None
+		Ok(None)
 	}
 }
 
@@ -95,6 +132,7 @@ pub async fn handle_head(
 	bucket_id: Uuid,
 	key: &str,
 	part_number: Option<u64>,
+	version_id: Option<&str>,
 ) -> Result<Response<Body>, Error> {
 	let object = garage
 		.object_table
@@ -102,12 +140,7 @@ pub async fn handle_head(
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	let object_version = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_data())
-		.ok_or(Error::NoSuchKey)?;
+	let object_version = find_version(&object, version_id)?;
 
 	let version_data = match &object_version.state {
 		ObjectVersionState::Complete(c) => c,
@@ -120,22 +153,20 @@ pub async fn handle_head(
 		_ => unreachable!(),
 	};
 
-	if let Some(cached) = try_answer_cached(object_version, version_meta, req) {
+	if let Some(cached) = check_preconditions(object_version, version_meta, req)? {
 		return Ok(cached);
 	}
 
 	if let Some(pn) = part_number {
 		match version_data {
-			ObjectVersionData::Inline(_, bytes) => {
+			ObjectVersionData::Inline(_, _) => {
 				if pn != 1 {
 					return Err(Error::InvalidPart);
 				}
+				let size = version_meta.size;
 				Ok(object_headers(object_version, version_meta)
-					.header(CONTENT_LENGTH, format!("{}", bytes.len()))
-					.header(
-						CONTENT_RANGE,
-						format!("bytes 0-{}/{}", bytes.len() - 1, bytes.len()),
-					)
+					.header(CONTENT_LENGTH, format!("{}", size))
+					.header(CONTENT_RANGE, format!("bytes 0-{}/{}", size - 1, size))
 					.header(X_AMZ_MP_PARTS_COUNT, "1")
 					.status(StatusCode::PARTIAL_CONTENT)
 					.body(Body::empty())?)
@@ -176,6 +207,96 @@ pub async fn handle_head(
 	}
 }
 
+/// Handle GetObjectAttributes request, which returns the attributes listed
+/// in the x-amz-object-attributes header without the content of the object
+pub async fn handle_get_object_attributes(
+	garage: Arc<Garage>,
+	req: &Request<Body>,
+	bucket_id: Uuid,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	let mut attributes = vec![];
+	for header in req.headers().get_all(X_AMZ_OBJECT_ATTRIBUTES) {
+		for attr in header.to_str()?.split(',').map(str::trim) {
+			match attr {
+				"ETag" | "Checksum" | "ObjectParts" | "StorageClass" | "ObjectSize" => {
+					attributes.push(attr)
+				}
+				_ => {
+					return Err(Error::bad_request(format!(
+						"Invalid object attribute: {}",
+						attr
+					)))
+				}
+			}
+		}
+	}
+	if attributes.is_empty() {
+		return Err(Error::bad_request(format!(
+			"Missing {} header",
+			X_AMZ_OBJECT_ATTRIBUTES
+		)));
+	}
+
+	let object = garage
+		.object_table
+		.get(&bucket_id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let object_version = find_version(&object, version_id)?;
+
+	let version_data = match &object_version.state {
+		ObjectVersionState::Complete(c) => c,
+		_ => unreachable!(),
+	};
+	let version_meta = match version_data {
+		ObjectVersionData::Inline(meta, _) => meta,
+		ObjectVersionData::FirstBlock(meta, _) => meta,
+		_ => unreachable!(),
+	};
+
+	// Only objects uploaded with a multipart upload have parts
+	let object_parts = match version_data {
+		ObjectVersionData::FirstBlock(_, _) if attributes.contains(&"ObjectParts") => {
+			let version = garage
+				.version_table
+				.get(&object_version.uuid, &EmptyKey)
+				.await?
+				.ok_or(Error::NoSuchKey)?;
+			let n_parts = version.parts_etags.items().len();
+			(n_parts > 0).then_some(s3_xml::ObjectParts {
+				total_parts_count: s3_xml::IntValue(n_parts as i64),
+			})
+		}
+		_ => None,
+	};
+
+	// Checksums are only stored for the parts of multipart uploads,
+	// so there is no checksum of the whole object to return
+	let result = s3_xml::GetObjectAttributesResponse {
+		xmlns: (),
+		etag: attributes
+			.contains(&"ETag")
+			.then(|| s3_xml::Value(version_meta.etag.clone())),
+		object_parts,
+		storage_class: attributes
+			.contains(&"StorageClass")
+			.then(|| s3_xml::Value("STANDARD".to_string())),
+		object_size: attributes
+			.contains(&"ObjectSize")
+			.then_some(s3_xml::IntValue(version_meta.size as i64)),
+	};
+	let xml = s3_xml::to_xml_with_header(&result)?;
+
+	let date = UNIX_EPOCH + Duration::from_millis(object_version.timestamp);
+	Ok(Response::builder()
+		.header(CONTENT_TYPE, "application/xml")
+		.header(LAST_MODIFIED, httpdate::fmt_http_date(date))
+		.header("x-amz-version-id", hex::encode(object_version.uuid))
+		.body(Body::from(xml))?)
+}
+
 /// Handle GET request
 pub async fn handle_get(
 	garage: Arc<Garage>,
@@ -183,6 +304,7 @@ pub async fn handle_get(
 	bucket_id: Uuid,
 	key: &str,
 	part_number: Option<u64>,
+	version_id: Option<&str>,
 ) -> Result<Response<Body>, Error> {
 	let object = garage
 		.object_table
@@ -190,12 +312,7 @@ pub async fn handle_get(
 		.await?
 		.ok_or(Error::NoSuchKey)?;
 
-	let last_v = object
-		.versions()
-		.iter()
-		.rev()
-		.find(|v| v.is_complete())
-		.ok_or(Error::NoSuchKey)?;
+	let last_v = find_version(&object, version_id)?;
 
 	let last_v_data = match &last_v.state {
 		ObjectVersionState::Complete(x) => x,
@@ -207,20 +324,21 @@ pub async fn handle_get(
 		ObjectVersionData::FirstBlock(meta, _) => meta,
 	};
 
-	if let Some(cached) = try_answer_cached(last_v, last_v_meta, req) {
+	if let Some(cached) = check_preconditions(last_v, last_v_meta, req)? {
 		return Ok(cached);
 	}
 
-	match (part_number, parse_range_header(req, last_v_meta.size)?) {
-		(Some(_), Some(_)) => {
+	let ranges = parse_range_header(req, last_v_meta.size)?;
+	match (part_number, &ranges[..]) {
+		(Some(_), [_, ..]) => {
 			return Err(Error::bad_request(
 				"Cannot specify both partNumber and Range header",
 			));
 		}
-		(Some(pn), None) => {
+		(Some(pn), []) => {
 			return handle_get_part(garage, last_v, last_v_data, last_v_meta, pn).await;
 		}
-		(None, Some(range)) => {
+		(None, [range]) => {
 			return handle_get_range(
 				garage,
 				last_v,
@@ -231,7 +349,28 @@ pub async fn handle_get(
 			)
 			.await;
 		}
-		(None, None) => (),
+		(None, [_, _, ..]) => {
+			return handle_get_multirange(garage, last_v, last_v_data, last_v_meta, ranges).await;
+		}
+		(None, []) => (),
+	}
+
+	if let (ObjectVersionData::FirstBlock(_, _), Some(_)) = (last_v_data, &last_v_meta.encryption) {
+		// Encrypted blocks have to be decrypted one by one, which is done
+		// when reading a range of the object
+		let resp = handle_get_range(
+			garage,
+			last_v,
+			last_v_data,
+			last_v_meta,
+			0,
+			last_v_meta.size,
+		)
+		.await?;
+		let (mut parts, body) = resp.into_parts();
+		parts.status = StatusCode::OK;
+		parts.headers.remove(CONTENT_RANGE);
+		return Ok(Response::from_parts(parts, body));
 	}
 
 	let resp_builder = object_headers(last_v, last_v_meta)
@@ -240,8 +379,8 @@ pub async fn handle_get(
 
 	match &last_v_data {
 		ObjectVersionData::DeleteMarker => unreachable!(),
-		ObjectVersionData::Inline(_, bytes) => {
-			let body: Body = Body::from(bytes.to_vec());
+		ObjectVersionData::Inline(meta, bytes) => {
+			let body: Body = Body::from(inline_bytes(&garage, meta, bytes)?);
 			Ok(resp_builder.body(body)?)
 		}
 		ObjectVersionData::FirstBlock(_, first_block_hash) => {
@@ -320,7 +459,8 @@ async fn handle_get_range(
 
 	match &version_data {
 		ObjectVersionData::DeleteMarker => unreachable!(),
-		ObjectVersionData::Inline(_meta, bytes) => {
+		ObjectVersionData::Inline(meta, bytes) => {
+			let bytes = inline_bytes(&garage, meta, bytes)?;
 			if end as usize <= bytes.len() {
 				let body: Body = Body::from(bytes[begin as usize..end as usize].to_vec());
 				Ok(resp_builder.body(body)?)
@@ -330,19 +470,111 @@ async fn handle_get_range(
 				))
 			}
 		}
-		ObjectVersionData::FirstBlock(_meta, _first_block_hash) => {
+		ObjectVersionData::FirstBlock(meta, _first_block_hash) => {
 			let version = garage
 				.version_table
 				.get(&version.uuid, &EmptyKey)
 				.await?
 				.ok_or(Error::NoSuchKey)?;
 
-			let body = body_from_blocks_range(garage, version.blocks.items(), begin, end);
+			let cipher = block_cipher(&garage, meta)?;
+			let body = body_from_blocks_range(garage, version.blocks.items(), begin, end, cipher);
 			Ok(resp_builder.body(body)?)
 		}
 	}
 }
 
+/// Handle a GET request with several ranges in its Range header, by sending
+/// them in order in a multipart/byteranges body (RFC7233 appendix A)
+async fn handle_get_multirange(
+	garage: Arc<Garage>,
+	version: &ObjectVersion,
+	version_data: &ObjectVersionData,
+	version_meta: &ObjectVersionMeta,
+	ranges: Vec<http_range::HttpRange>,
+) -> Result<Response<Body>, Error> {
+	let boundary = hex::encode(&gen_uuid().as_slice()[..16]);
+	let cipher = block_cipher(&garage, version_meta)?;
+
+	let mut inline = vec![];
+	let blocks = match version_data {
+		ObjectVersionData::DeleteMarker => unreachable!(),
+		ObjectVersionData::Inline(meta, bytes) => {
+			inline = inline_bytes(&garage, meta, bytes)?;
+			None
+		}
+		ObjectVersionData::FirstBlock(_, _) => Some(
+			garage
+				.version_table
+				.get(&version.uuid, &EmptyKey)
+				.await?
+				.ok_or(Error::NoSuchKey)?
+				.blocks,
+		),
+	};
+
+	let mut content_length = 0;
+	let mut parts: Vec<BodyPartStream> = Vec::with_capacity(2 * ranges.len() + 1);
+	for range in ranges {
+		let (begin, end) = (range.start, range.start + range.length);
+		let part_header = format!(
+			"\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+			boundary,
+			version_meta.headers.content_type,
+			begin,
+			end - 1,
+			version_meta.size
+		);
+		content_length += part_header.len() as u64 + range.length;
+		parts.push(Box::pin(stream::once(future::ready(Ok(Bytes::from(
+			part_header,
+		))))));
+
+		match (version_data, &blocks) {
+			(ObjectVersionData::Inline(_, _), _) => {
+				let bytes = inline
+					.get(begin as usize..end as usize)
+					.ok_or_internal_error(
+						"Requested range not present in inline bytes when it should have been",
+					)?;
+				parts.push(Box::pin(stream::once(future::ready(Ok(
+					Bytes::copy_from_slice(bytes),
+				)))));
+			}
+			(_, Some(blocks)) => {
+				parts.push(Box::pin(stream_from_blocks_range(
+					garage.clone(),
+					blocks.items(),
+					begin,
+					end,
+					cipher.clone(),
+				)));
+			}
+			_ => unreachable!(),
+		}
+	}
+	let trailer = format!("\r\n--{}--\r\n", boundary);
+	content_length += trailer.len() as u64;
+	parts.push(Box::pin(stream::once(future::ready(Ok(Bytes::from(
+		trailer,
+	))))));
+
+	let mut resp_builder = object_headers(version, version_meta)
+		.header(CONTENT_LENGTH, format!("{}", content_length))
+		.status(StatusCode::PARTIAL_CONTENT);
+	if let Some(headers) = resp_builder.headers_mut() {
+		headers.insert(
+			CONTENT_TYPE,
+			format!("multipart/byteranges; boundary={}", boundary)
+				.parse()
+				.unwrap(),
+		);
+	}
+
+	let body = Body::wrap_stream(stream::iter(parts).flatten());
+	Ok(resp_builder.body(body)?)
+}
+
 async fn handle_get_part(
 	garage: Arc<Garage>,
 	object_version: &ObjectVersion,
@@ -354,10 +586,11 @@ async fn handle_get_part(
 		object_headers(object_version, version_meta).status(StatusCode::PARTIAL_CONTENT);
 
 	match version_data {
-		ObjectVersionData::Inline(_, bytes) => {
+		ObjectVersionData::Inline(meta, bytes) => {
 			if part_number != 1 {
 				return Err(Error::InvalidPart);
 			}
+			let bytes = inline_bytes(&garage, meta, bytes)?;
 			Ok(resp_builder
 				.header(CONTENT_LENGTH, format!("{}", bytes.len()))
 				.header(
@@ -365,7 +598,7 @@ async fn handle_get_part(
 					format!("bytes {}-{}/{}", 0, bytes.len() - 1, bytes.len()),
 				)
 				.header(X_AMZ_MP_PARTS_COUNT, "1")
-				.body(Body::from(bytes.to_vec()))?)
+				.body(Body::from(bytes))?)
 		}
 		ObjectVersionData::FirstBlock(_, _) => {
 			let version = garage
@@ -378,7 +611,8 @@ async fn handle_get_part(
 				calculate_part_bounds(&version, part_number).ok_or(Error::InvalidPart)?;
 			let n_parts = version.parts_etags.items().len();
 
-			let body = body_from_blocks_range(garage, version.blocks.items(), begin, end);
+			let cipher = block_cipher(&garage, version_meta)?;
+			let body = body_from_blocks_range(garage, version.blocks.items(), begin, end, cipher);
 
 			Ok(resp_builder
 				.header(CONTENT_LENGTH, format!("{}", end - begin))
@@ -396,23 +630,35 @@ async fn handle_get_part(
 fn parse_range_header(
 	req: &Request<Body>,
 	total_size: u64,
-) -> Result<Option<http_range::HttpRange>, Error> {
-	let range = match req.headers().get(RANGE) {
+) -> Result<Vec<http_range::HttpRange>, Error> {
+	let ranges = match req.headers().get(RANGE) {
 		Some(range) => {
 			let range_str = range.to_str()?;
-			let mut ranges =
+			let ranges =
 				http_range::HttpRange::parse(range_str, total_size).map_err(|e| (e, total_size))?;
-			if ranges.len() > 1 {
-				// garage does not support multi-range requests yet, so we respond with the entire
-				// object when multiple ranges are requested
-				None
+			if ranges_overlap(&ranges) {
+				// garage does not support overlapping ranges, so we respond with the entire
+				// object when they are requested (this is allowed by RFC7233 section 4.1)
+				vec![]
 			} else {
-				ranges.pop()
+				ranges
 			}
 		}
-		None => None,
+		None => vec![],
 	};
-	Ok(range)
+	Ok(ranges)
+}
+
+fn ranges_overlap(ranges: &[http_range::HttpRange]) -> bool {
+	let mut sorted = ranges
+		.iter()
+		.map(|r| (r.start, r.length))
+		.collect::<Vec<_>>();
+	sorted.sort();
+	sorted
+		.iter()
+		.zip(sorted.iter().skip(1))
+		.any(|((start1, len1), (start2, _))| start1 + len1 > *start2)
 }
 
 fn calculate_part_bounds(v: &Version, part_number: u64) -> Option<(u64, u64)> {
@@ -431,43 +677,93 @@ fn calculate_part_bounds(v: &Version, part_number: u64) -> Option<(u64, u64)> {
 	None
 }
 
+/// Get the content of an object stored inline, decrypting it if necessary
+fn inline_bytes(garage: &Garage, meta: &ObjectVersionMeta, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+	match ObjectCipher::for_object(garage, meta.encryption.as_ref())? {
+		Some(cipher) => cipher.decrypt_block(1, 0, bytes),
+		None => Ok(bytes.to_vec()),
+	}
+}
+
+fn block_cipher(
+	garage: &Garage,
+	meta: &ObjectVersionMeta,
+) -> Result<Option<Arc<ObjectCipher>>, Error> {
+	Ok(ObjectCipher::for_object(garage, meta.encryption.as_ref())?.map(Arc::new))
+}
+
+type BodyPartStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
 fn body_from_blocks_range(
 	garage: Arc<Garage>,
 	all_blocks: &[(VersionBlockKey, VersionBlock)],
 	begin: u64,
 	end: u64,
+	cipher: Option<Arc<ObjectCipher>>,
 ) -> Body {
+	hyper::body::Body::wrap_stream(stream_from_blocks_range(
+		garage, all_blocks, begin, end, cipher,
+	))
+}
+
+fn stream_from_blocks_range(
+	garage: Arc<Garage>,
+	all_blocks: &[(VersionBlockKey, VersionBlock)],
+	begin: u64,
+	end: u64,
+	cipher: Option<Arc<ObjectCipher>>,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static {
 	// We will store here the list of blocks that have an intersection with the requested
 	// range, as well as their "true offset", which is their actual offset in the complete
 	// file (whereas block.offset designates the offset of the block WITHIN THE PART
 	// block.part_number, which is not the same in the case of a multipart upload)
-	let mut blocks: Vec<(VersionBlock, u64)> = Vec::with_capacity(std::cmp::min(
+	let mut blocks: Vec<(VersionBlockKey, VersionBlock, u64)> = Vec::with_capacity(std::cmp::min(
 		all_blocks.len(),
 		4 + ((end - begin) / std::cmp::max(all_blocks[0].1.size, 1024)) as usize,
 	));
 	let mut block_offset: u64 = 0;
-	for (_, b) in all_blocks.iter() {
+	for (k, b) in all_blocks.iter() {
 		if block_offset >= end {
 			break;
 		}
 		// Keep only blocks that have an intersection with the requested range
 		if block_offset < end && block_offset + b.size > begin {
-			blocks.push((*b, block_offset));
+			blocks.push((*k, *b, block_offset));
 		}
 		block_offset += b.size;
 	}
 
 	let order_stream = OrderTag::stream();
-	let body_stream = futures::stream::iter(blocks)
+	futures::stream::iter(blocks)
 		.enumerate()
-		.map(move |(i, (block, block_offset))| {
+		.map(move |(i, (key, block, block_offset))| {
 			let garage = garage.clone();
+			let cipher = cipher.clone();
 			async move {
-				garage
-					.block_manager
-					.rpc_get_block_streaming(&block.hash, Some(order_stream.order(i as u64)))
-					.await
-					.unwrap_or_else(|e| error_stream(i, e))
+				let block_stream = match cipher {
+					None => garage
+						.block_manager
+						.rpc_get_block_streaming(&block.hash, Some(order_stream.order(i as u64)))
+						.await
+						.unwrap_or_else(|e| error_stream(i, e)),
+					Some(cipher) => {
+						// Encrypted blocks are authenticated as a whole, so they
+						// can only be decrypted once they have been fully received
+						match garage
+							.block_manager
+							.rpc_get_block(&block.hash, Some(order_stream.order(i as u64)))
+							.await
+							.map_err(Error::from)
+							.and_then(|b| cipher.decrypt_block(key.part_number, key.offset, &b))
+						{
+							Ok(plaintext) => {
+								Box::pin(stream::once(future::ready(Ok(Bytes::from(plaintext)))))
+							}
+							Err(e) => error_stream(i, e),
+						}
+					}
+				};
+				block_stream
 					.scan(block_offset, move |chunk_offset, chunk| {
 						let r = match chunk {
 							Ok(chunk_bytes) => {
@@ -508,16 +804,14 @@ fn body_from_blocks_range(
 			}
 		})
 		.buffered(2)
-		.flatten();
-
-	hyper::body::Body::wrap_stream(body_stream)
+		.flatten()
 }
 
-fn error_stream(i: usize, e: garage_util::error::Error) -> ByteStream {
+// io::Error::other is not available in our MSRV (1.68)
+#[allow(clippy::io_other_error)]
+fn error_stream(i: usize, e: impl std::fmt::Display) -> ByteStream {
+	let msg = format!("Could not get block {}: {}", i, e);
 	Box::pin(futures::stream::once(async move {
-		Err(std::io::Error::new(
-			std::io::ErrorKind::Other,
-			format!("Could not get block {}: {}", i, e),
-		))
+		Err(std::io::Error::new(std::io::ErrorKind::Other, msg))
 	}))
 }
@@ -0,0 +1,491 @@
+use quick_xml::de::from_reader;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::{
+	Bucket, LifecycleExpiration as GarageLifecycleExpiration,
+	LifecycleFilter as GarageLifecycleFilter, LifecycleRule as GarageLifecycleRule,
+};
+use garage_model::garage::Garage;
+use garage_util::data::*;
+
+pub async fn handle_get_lifecycle(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	if let Some(lifecycle) = param.lifecycle_config.get() {
+		let wc = LifecycleConfiguration::from_garage_lifecycle_config(lifecycle);
+		let xml = to_xml_with_header(&wc)?;
+		Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/xml")
+			.body(Body::from(xml))?)
+	} else {
+		Err(Error::NoSuchLifecycleConfiguration)
+	}
+}
+
+pub async fn handle_delete_lifecycle(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.lifecycle_config.update(None);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_put_lifecycle(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	let conf: LifecycleConfiguration = from_reader(&body as &[u8])?;
+	let config = conf
+		.validate_into_garage_lifecycle_config()
+		.map_err(|e| Error::bad_request(format!("Invalid lifecycle configuration: {}", e)))?;
+
+	param.lifecycle_config.update(Some(config));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename = "LifecycleConfiguration")]
+pub struct LifecycleConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Rule")]
+	pub lifecycle_rules: Vec<LifecycleRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LifecycleRule {
+	#[serde(rename = "ID", skip_serializing_if = "Option::is_none")]
+	pub id: Option<Value>,
+	#[serde(rename = "Status")]
+	pub status: Value,
+	/// Deprecated way of specifying a prefix filter, still used by some clients
+	#[serde(rename = "Prefix", default, skip_serializing)]
+	pub prefix: Option<Value>,
+	#[serde(rename = "Filter", default)]
+	pub filter: Option<Filter>,
+	#[serde(
+		rename = "Expiration",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub expiration: Option<Expiration>,
+	#[serde(
+		rename = "AbortIncompleteMultipartUpload",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub abort_incomplete_mpu: Option<AbortIncompleteMpu>,
+	#[serde(
+		rename = "NoncurrentVersionExpiration",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub noncurrent_version_expiration: Option<NoncurrentVersionExpiration>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Filter {
+	#[serde(rename = "And", skip_serializing_if = "Option::is_none")]
+	pub and: Option<Box<Filter>>,
+	#[serde(rename = "Prefix", skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<Value>,
+	#[serde(
+		rename = "ObjectSizeGreaterThan",
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub size_gt: Option<IntValue>,
+	#[serde(rename = "ObjectSizeLessThan", skip_serializing_if = "Option::is_none")]
+	pub size_lt: Option<IntValue>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Expiration {
+	#[serde(rename = "Days", skip_serializing_if = "Option::is_none")]
+	pub days: Option<IntValue>,
+	#[serde(rename = "Date", skip_serializing_if = "Option::is_none")]
+	pub at_date: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AbortIncompleteMpu {
+	#[serde(rename = "DaysAfterInitiation")]
+	pub days: IntValue,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NoncurrentVersionExpiration {
+	#[serde(rename = "NoncurrentDays")]
+	pub days: IntValue,
+}
+
+impl LifecycleConfiguration {
+	pub fn validate_into_garage_lifecycle_config(
+		self,
+	) -> Result<Vec<GarageLifecycleRule>, &'static str> {
+		let mut ret = vec![];
+		for rule in self.lifecycle_rules {
+			ret.push(rule.validate_into_garage_lifecycle_rule()?);
+		}
+		Ok(ret)
+	}
+
+	pub fn from_garage_lifecycle_config(config: &[GarageLifecycleRule]) -> Self {
+		Self {
+			xmlns: (),
+			lifecycle_rules: config
+				.iter()
+				.map(LifecycleRule::from_garage_lifecycle_rule)
+				.collect(),
+		}
+	}
+}
+
+impl LifecycleRule {
+	pub fn validate_into_garage_lifecycle_rule(self) -> Result<GarageLifecycleRule, &'static str> {
+		let enabled = match self.status.0.as_str() {
+			"Enabled" => true,
+			"Disabled" => false,
+			_ => return Err("invalid value for <Status>"),
+		};
+
+		let filter = match (self.filter, self.prefix) {
+			(Some(_), Some(_)) => return Err("cannot have both <Prefix> and <Filter> in <Rule>"),
+			(Some(filter), None) => filter.validate_into_garage_lifecycle_filter()?,
+			(None, prefix) => GarageLifecycleFilter {
+				prefix: prefix.map(|x| x.0),
+				..Default::default()
+			},
+		};
+
+		let abort_incomplete_mpu_days = match self.abort_incomplete_mpu {
+			Some(x) if x.days.0 <= 0 => {
+				return Err("<DaysAfterInitiation> must be a positive number")
+			}
+			Some(x) => Some(x.days.0 as usize),
+			None => None,
+		};
+
+		let expiration = self
+			.expiration
+			.map(Expiration::validate_into_garage_lifecycle_expiration)
+			.transpose()?;
+
+		let noncurrent_version_expiration_days = match self.noncurrent_version_expiration {
+			Some(x) if x.days.0 <= 0 => return Err("<NoncurrentDays> must be a positive number"),
+			Some(x) => Some(x.days.0 as usize),
+			None => None,
+		};
+
+		if abort_incomplete_mpu_days.is_none()
+			&& expiration.is_none()
+			&& noncurrent_version_expiration_days.is_none()
+		{
+			return Err(
+				"<Rule> must contain an <Expiration>, <AbortIncompleteMultipartUpload> or <NoncurrentVersionExpiration> action",
+			);
+		}
+
+		Ok(GarageLifecycleRule {
+			id: self.id.map(|x| x.0),
+			enabled,
+			filter,
+			abort_incomplete_mpu_days,
+			expiration,
+			noncurrent_version_expiration_days,
+		})
+	}
+
+	pub fn from_garage_lifecycle_rule(rule: &GarageLifecycleRule) -> Self {
+		Self {
+			id: rule.id.as_deref().map(Value::from),
+			status: if rule.enabled {
+				Value::from("Enabled")
+			} else {
+				Value::from("Disabled")
+			},
+			prefix: None,
+			filter: Some(Filter::from_garage_lifecycle_filter(&rule.filter)),
+			abort_incomplete_mpu: rule
+				.abort_incomplete_mpu_days
+				.map(|days| AbortIncompleteMpu {
+					days: IntValue(days as i64),
+				}),
+			expiration: rule
+				.expiration
+				.as_ref()
+				.map(Expiration::from_garage_lifecycle_expiration),
+			noncurrent_version_expiration: rule.noncurrent_version_expiration_days.map(|days| {
+				NoncurrentVersionExpiration {
+					days: IntValue(days as i64),
+				}
+			}),
+		}
+	}
+}
+
+impl Filter {
+	pub fn count(&self) -> usize {
+		fn count<T>(x: &Option<T>) -> usize {
+			x.as_ref().map(|_| 1).unwrap_or(0)
+		}
+		count(&self.prefix) + count(&self.size_gt) + count(&self.size_lt)
+	}
+
+	pub fn validate_into_garage_lifecycle_filter(
+		self,
+	) -> Result<GarageLifecycleFilter, &'static str> {
+		if self.count() > 0 && self.and.is_some() {
+			Err("Filter tag cannot contain both <And> and another condition")
+		} else if let Some(and) = self.and {
+			if and.and.is_some() {
+				return Err("Nested <And> tags");
+			}
+			Ok(and.internal_into_garage_lifecycle_filter()?)
+		} else if self.count() > 1 {
+			Err("Multiple Filter conditions must be wrapped in an <And> tag")
+		} else {
+			Ok(self.internal_into_garage_lifecycle_filter()?)
+		}
+	}
+
+	fn internal_into_garage_lifecycle_filter(self) -> Result<GarageLifecycleFilter, &'static str> {
+		let size_gt = match self.size_gt {
+			Some(x) if x.0 < 0 => return Err("<ObjectSizeGreaterThan> must be positive"),
+			Some(x) => Some(x.0 as u64),
+			None => None,
+		};
+		let size_lt = match self.size_lt {
+			Some(x) if x.0 <= 0 => return Err("<ObjectSizeLessThan> must be positive"),
+			Some(x) => Some(x.0 as u64),
+			None => None,
+		};
+
+		Ok(GarageLifecycleFilter {
+			prefix: self.prefix.map(|x| x.0),
+			size_gt,
+			size_lt,
+		})
+	}
+
+	pub fn from_garage_lifecycle_filter(rule: &GarageLifecycleFilter) -> Self {
+		let filter = Filter {
+			and: None,
+			prefix: rule.prefix.as_deref().map(Value::from),
+			size_gt: rule.size_gt.map(|x| IntValue(x as i64)),
+			size_lt: rule.size_lt.map(|x| IntValue(x as i64)),
+		};
+		if filter.count() > 1 {
+			Filter {
+				and: Some(Box::new(filter)),
+				..Default::default()
+			}
+		} else {
+			filter
+		}
+	}
+}
+
+impl Expiration {
+	pub fn validate_into_garage_lifecycle_expiration(
+		self,
+	) -> Result<GarageLifecycleExpiration, &'static str> {
+		match (self.days, self.at_date) {
+			(Some(_), Some(_)) => Err("cannot have both <Days> and <Date> in <Expiration>"),
+			(None, None) => Err("<Expiration> must contain either <Days> or <Date>"),
+			(Some(days), None) if days.0 <= 0 => Err("<Days> must be a positive number"),
+			(Some(days), None) => Ok(GarageLifecycleExpiration::AfterDays(days.0 as usize)),
+			(None, Some(date)) => {
+				let date = parse_lifecycle_date(&date.0)?;
+				Ok(GarageLifecycleExpiration::AtDate(date.to_string()))
+			}
+		}
+	}
+
+	pub fn from_garage_lifecycle_expiration(exp: &GarageLifecycleExpiration) -> Self {
+		match exp {
+			GarageLifecycleExpiration::AfterDays(days) => Expiration {
+				days: Some(IntValue(*days as i64)),
+				at_date: None,
+			},
+			GarageLifecycleExpiration::AtDate(date) => Expiration {
+				days: None,
+				at_date: Some(Value(format!("{}T00:00:00Z", date))),
+			},
+		}
+	}
+}
+
+/// Lifecycle dates must be at midnight UTC, and can be given either
+/// as a plain date (2023-01-01) or as a full timestamp (2023-01-01T00:00:00Z)
+fn parse_lifecycle_date(date: &str) -> Result<chrono::NaiveDate, &'static str> {
+	let date = match date.split_once('T') {
+		Some((date, "00:00:00Z" | "00:00:00.000Z")) => date,
+		Some(_) => return Err("<Date> must be at midnight UTC"),
+		None => date,
+	};
+	chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| "Invalid expiration <Date>")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_lifecycle_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<LifecycleConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Rule>
+    <ID>id1</ID>
+    <Status>Enabled</Status>
+    <Filter>
+       <Prefix>documents/</Prefix>
+    </Filter>
+    <AbortIncompleteMultipartUpload>
+       <DaysAfterInitiation>7</DaysAfterInitiation>
+    </AbortIncompleteMultipartUpload>
+  </Rule>
+  <Rule>
+    <ID>id2</ID>
+    <Status>Disabled</Status>
+    <Filter>
+       <And>
+          <Prefix>logs/</Prefix>
+          <ObjectSizeGreaterThan>1000000</ObjectSizeGreaterThan>
+       </And>
+    </Filter>
+    <Expiration>
+      <Date>2023-01-01T00:00:00Z</Date>
+    </Expiration>
+  </Rule>
+  <Rule>
+    <ID>id3</ID>
+    <Status>Enabled</Status>
+    <NoncurrentVersionExpiration>
+      <NoncurrentDays>30</NoncurrentDays>
+    </NoncurrentVersionExpiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+
+		let garage_config = conf.validate_into_garage_lifecycle_config().unwrap();
+		assert_eq!(
+			garage_config,
+			vec![
+				GarageLifecycleRule {
+					id: Some("id1".into()),
+					enabled: true,
+					filter: GarageLifecycleFilter {
+						prefix: Some("documents/".into()),
+						..Default::default()
+					},
+					abort_incomplete_mpu_days: Some(7),
+					expiration: None,
+					noncurrent_version_expiration_days: None,
+				},
+				GarageLifecycleRule {
+					id: Some("id2".into()),
+					enabled: false,
+					filter: GarageLifecycleFilter {
+						prefix: Some("logs/".into()),
+						size_gt: Some(1000000),
+						size_lt: None,
+					},
+					abort_incomplete_mpu_days: None,
+					expiration: Some(GarageLifecycleExpiration::AtDate("2023-01-01".into())),
+					noncurrent_version_expiration_days: None,
+				},
+				GarageLifecycleRule {
+					id: Some("id3".into()),
+					enabled: true,
+					filter: GarageLifecycleFilter::default(),
+					abort_incomplete_mpu_days: None,
+					expiration: None,
+					noncurrent_version_expiration_days: Some(30),
+				},
+			]
+		);
+
+		// Check that serializing the configuration and parsing it back
+		// gives the same internal representation
+		let serialized = to_xml_with_header(
+			&LifecycleConfiguration::from_garage_lifecycle_config(&garage_config),
+		)?;
+		let conf2: LifecycleConfiguration = from_str(&serialized).unwrap();
+		assert_eq!(
+			conf2.validate_into_garage_lifecycle_config().unwrap(),
+			garage_config
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_invalid_lifecycle_config() {
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <Filter>
+       <Prefix>logs/</Prefix>
+       <ObjectSizeLessThan>1000</ObjectSizeLessThan>
+    </Filter>
+    <Expiration><Days>3</Days></Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		assert!(conf.validate_into_garage_lifecycle_config().is_err());
+
+		let message = r#"<LifecycleConfiguration>
+  <Rule>
+    <Status>Enabled</Status>
+    <Expiration><Date>2023-01-01T12:00:00Z</Date></Expiration>
+  </Rule>
+</LifecycleConfiguration>"#;
+		let conf: LifecycleConfiguration = from_str(message).unwrap();
+		assert!(conf.validate_into_garage_lifecycle_config().is_err());
+	}
+}
@@ -1,4 +1,4 @@
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
 use std::collections::{BTreeMap, BTreeSet};
 use std::iter::{Iterator, Peekable};
 use std::sync::Arc;
@@ -51,6 +51,13 @@ pub struct ListMultipartUploadsQuery {
 	pub common: ListQueryCommon,
 }
 
+#[derive(Debug)]
+pub struct ListObjectVersionsQuery {
+	pub key_marker: Option<String>,
+	pub version_id_marker: Option<String>,
+	pub common: ListQueryCommon,
+}
+
 #[derive(Debug)]
 pub struct ListPartsQuery {
 	pub bucket_name: String,
@@ -254,7 +261,121 @@ pub async fn handle_list_multipart_upload(
 			.common_prefixes
 			.iter()
 			.map(|c| s3_xml::CommonPrefix {
-				prefix: s3_xml::Value(c.to_string()),
+				prefix: uriencode_maybe(c, query.common.urlencode_resp),
+			})
+			.collect(),
+	};
+
+	let xml = s3_xml::to_xml_with_header(&result)?;
+
+	Ok(Response::builder()
+		.header("Content-Type", "application/xml")
+		.body(Body::from(xml.into_bytes()))?)
+}
+
+pub async fn handle_list_object_versions(
+	garage: Arc<Garage>,
+	query: &ListObjectVersionsQuery,
+) -> Result<Response<Body>, Error> {
+	let io = |bucket, key, count| {
+		let t = &garage.object_table;
+		async move {
+			t.get_range(&bucket, key, None, count, EnumerationOrder::Forward)
+				.await
+		}
+	};
+
+	debug!("ListObjectVersions {:?}", query);
+	let mut acc = query.build_accumulator();
+	let pagination = fetch_list_entries(&query.common, query.begin()?, &mut acc, &io).await?;
+
+	let owner = || s3_xml::Owner {
+		display_name: s3_xml::Value(DUMMY_NAME.to_string()),
+		id: s3_xml::Value(DUMMY_KEY.to_string()),
+	};
+
+	let mut versions = vec![];
+	let mut delete_markers = vec![];
+	for ((key, Reverse((timestamp, _))), info) in acc.keys.iter() {
+		let key = uriencode_maybe(key, query.common.urlencode_resp);
+		let version_id = match info.versioned {
+			true => s3_xml::Value(hex::encode(info.uuid)),
+			false => s3_xml::Value("null".to_string()),
+		};
+		let is_latest = s3_xml::Value(format!("{}", info.is_latest));
+		let last_modified = s3_xml::Value(msec_to_rfc3339(*timestamp));
+		match &info.etag {
+			Some(etag) => versions.push(s3_xml::ListVersionItem {
+				key,
+				version_id,
+				is_latest,
+				last_modified,
+				etag: s3_xml::Value(format!("\"{}\"", etag)),
+				size: s3_xml::IntValue(info.size as i64),
+				storage_class: s3_xml::Value("STANDARD".to_string()),
+				owner: owner(),
+			}),
+			None => delete_markers.push(s3_xml::DeleteMarkerItem {
+				key,
+				version_id,
+				is_latest,
+				last_modified,
+				owner: owner(),
+			}),
+		}
+	}
+
+	let result = s3_xml::ListVersionsResult {
+		xmlns: (),
+
+		// Sending back some information about the request
+		name: s3_xml::Value(query.common.bucket_name.to_string()),
+		prefix: uriencode_maybe(&query.common.prefix, query.common.urlencode_resp),
+		key_marker: query
+			.key_marker
+			.as_ref()
+			.map(|m| uriencode_maybe(m, query.common.urlencode_resp)),
+		version_id_marker: query
+			.version_id_marker
+			.as_ref()
+			.map(|m| s3_xml::Value(m.to_string())),
+		max_keys: s3_xml::IntValue(query.common.page_size as i64),
+		delimiter: query
+			.common
+			.delimiter
+			.as_ref()
+			.map(|d| uriencode_maybe(d, query.common.urlencode_resp)),
+		encoding_type: match query.common.urlencode_resp {
+			true => Some(s3_xml::Value("url".to_string())),
+			false => None,
+		},
+
+		// Handling pagination
+		is_truncated: s3_xml::Value(format!("{}", pagination.is_some())),
+		next_key_marker: match &pagination {
+			Some(RangeBegin::AfterKey { key })
+			| Some(RangeBegin::AfterUpload { key, .. })
+			| Some(RangeBegin::IncludingKey {
+				fallback_key: Some(key),
+				..
+			}) => Some(uriencode_maybe(key, query.common.urlencode_resp)),
+			_ => None,
+		},
+		next_version_id_marker: match &pagination {
+			Some(RangeBegin::AfterUpload { upload, .. }) => {
+				Some(s3_xml::Value(hex::encode(upload)))
+			}
+			_ => None,
+		},
+
+		// Result body
+		versions,
+		delete_markers,
+		common_prefixes: acc
+			.common_prefixes
+			.iter()
+			.map(|pfx| s3_xml::CommonPrefix {
+				prefix: uriencode_maybe(pfx, query.common.urlencode_resp),
 			})
 			.collect(),
 	};
@@ -334,6 +455,16 @@ struct UploadInfo {
 	timestamp: u64,
 }
 
+#[derive(Debug, PartialEq)]
+struct VersionInfo {
+	uuid: Uuid,
+	versioned: bool,
+	is_latest: bool,
+	size: u64,
+	/// None for delete markers
+	etag: Option<String>,
+}
+
 #[derive(Debug, PartialEq)]
 struct PartInfo {
 	etag: String,
@@ -668,6 +799,34 @@ impl ListMultipartUploadsQuery {
 	}
 }
 
+impl ListObjectVersionsQuery {
+	fn build_accumulator(&self) -> VersionAccumulator {
+		VersionAccumulator::new(self.common.page_size)
+	}
+
+	fn begin(&self) -> Result<RangeBegin, Error> {
+		match (&self.version_id_marker, &self.key_marker) {
+			// If both markers are set, we start listing versions of the given key
+			// that come after the given version (versions are listed from the most
+			// recent to the oldest one).
+			(Some(version_marker), Some(key_marker)) => Ok(RangeBegin::AfterUpload {
+				key: key_marker.to_string(),
+				upload: s3_put::decode_version_id(version_marker)?,
+			}),
+
+			// If only the key marker is specified, we start listing versions
+			// of the keys that come AFTER the specified key.
+			(None, Some(key_marker)) => Ok(RangeBegin::AfterKey {
+				key: key_marker.to_string(),
+			}),
+			_ => Ok(RangeBegin::IncludingKey {
+				key: self.common.prefix.to_string(),
+				fallback_key: None,
+			}),
+		}
+	}
+}
+
 /*
  * Accumulator logic
  */
@@ -689,6 +848,8 @@ struct Accumulator<K, V> {
 
 type ObjectAccumulator = Accumulator<String, ObjectInfo>;
 type UploadAccumulator = Accumulator<Uuid, UploadInfo>;
+/// Versions are sorted by key, then from the most recent to the oldest one
+type VersionAccumulator = Accumulator<(String, Reverse<(u64, Uuid)>), VersionInfo>;
 
 impl<K: std::cmp::Ord, V> Accumulator<K, V> {
 	fn new(page_size: usize) -> Accumulator<K, V> {
@@ -795,7 +956,7 @@ impl ExtractAccumulator for ObjectAccumulator {
 
 		let object = objects.next().expect("This iterator can not be empty as it is checked earlier in the code. This is a logic bug, please report it.");
 
-		let version = match object.versions().iter().find(|x| x.is_data()) {
+		let version = match object.current_data_version() {
 			Some(v) => v,
 			None => unreachable!(
 				"Expect to have objects having data due to earlier filtering. This is a logic bug."
@@ -914,6 +1075,82 @@ impl ExtractAccumulator for UploadAccumulator {
 	}
 }
 
+impl ExtractAccumulator for VersionAccumulator {
+	/// Observe the iterator, process a single key, and try to extract all of its versions
+	fn extract<'a>(
+		&mut self,
+		query: &ListQueryCommon,
+		cursor: &RangeBegin,
+		objects: &mut Peekable<impl Iterator<Item = &'a Object>>,
+	) -> ExtractionResult {
+		if let Some(e) = self.extract_common_prefix(objects, query) {
+			return e;
+		}
+
+		// Get the next object from the iterator
+		let object = objects.next().expect("This iterator can not be empty as it is checked earlier in the code. This is a logic bug, please report it.");
+
+		// A delete marker that is not versioned and is not stored along
+		// older versions is only the tombstone of a deleted object
+		let has_retained = object.versions().iter().any(|v| v.is_retained());
+		let mut versions = object
+			.versions()
+			.iter()
+			.filter(|v| v.is_complete() && (v.is_data() || v.versioned || has_retained))
+			.collect::<Vec<&ObjectVersion>>();
+		versions.sort_unstable_by_key(|v| Reverse((v.timestamp, v.uuid)));
+
+		// Skip the versions up to the version marker if it is provided
+		if let RangeBegin::AfterUpload { key, upload } = cursor {
+			if *key == object.key {
+				if let Some(i) = versions.iter().position(|v| v.uuid == *upload) {
+					versions = versions[i + 1..].to_vec();
+				}
+			}
+		}
+
+		let latest = object.current_version().map(|v| v.uuid);
+		let mut prev_uuid = None;
+		for version in versions {
+			let info = VersionInfo {
+				uuid: version.uuid,
+				versioned: version.versioned,
+				is_latest: Some(version.uuid) == latest,
+				size: version.size(),
+				etag: match &version.state {
+					ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+					| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+						Some(meta.etag.to_string())
+					}
+					_ => None,
+				},
+			};
+
+			// Insert data in our accumulator
+			// If it is full, return information to paginate.
+			let key = (
+				object.key.clone(),
+				Reverse((version.timestamp, version.uuid)),
+			);
+			if !self.try_insert_entry(key, info) {
+				return match prev_uuid {
+					Some(upload) => ExtractionResult::FilledAtUpload {
+						key: object.key.clone(),
+						upload,
+					},
+					None => ExtractionResult::Filled,
+				};
+			}
+			prev_uuid = Some(version.uuid);
+		}
+
+		// We successfully collected all the versions
+		ExtractionResult::Extracted {
+			key: object.key.clone(),
+		}
+	}
+}
+
 /*
  * Utility functions
  */
@@ -995,6 +1232,8 @@ mod tests {
 				content_type: "text/plain".to_string(),
 				other: BTreeMap::<String, String>::new(),
 			}),
+			versioned: false,
+			legal_hold: Default::default(),
 		}
 	}
 
@@ -1239,6 +1478,7 @@ mod tests {
 			deleted: false.into(),
 			blocks: crdt::Map::<VersionBlockKey, VersionBlock>::from_iter(blocks),
 			parts_etags: crdt::Map::<u64, String>::from_iter(etags),
+			parts_checksums: crdt::Map::new(),
 		}
 	}
 
@@ -2,13 +2,21 @@ pub mod api_server;
 pub mod error;
 
 mod bucket;
+mod bucket_metrics;
+mod checksum;
 mod copy;
 pub mod cors;
 mod delete;
+pub mod encryption;
 pub mod get;
+pub mod lifecycle;
 mod list;
+mod notification;
+mod object_lock;
+mod policy;
 mod post_object;
 mod put;
+mod rate_limit;
 mod website;
 
 mod router;
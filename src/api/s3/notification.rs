@@ -0,0 +1,243 @@
+use std::time::Duration;
+
+use chrono::{SecondsFormat, Utc};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnector;
+use serde::Serialize;
+
+use garage_util::data::*;
+
+use garage_model::bucket_table::{Bucket, NotificationEventType, NotificationWebhook};
+use garage_model::garage::Garage;
+
+use crate::encoding::uri_encode;
+
+/// Number of times the delivery of an event to a webhook is attempted
+const NOTIFICATION_MAX_ATTEMPTS: u32 = 5;
+/// Delay before the first retry, doubled after each failed attempt
+const NOTIFICATION_RETRY_DELAY: Duration = Duration::from_secs(1);
+const NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An event on an object, that can be sent to the webhooks configured on its bucket
+pub struct ObjectEvent<'a> {
+	/// Name of the event, e.g. `ObjectCreated:Put` or `ObjectRemoved:Delete`
+	pub name: &'static str,
+	pub key: &'a str,
+	pub version_uuid: Uuid,
+	pub size: Option<u64>,
+	pub etag: Option<&'a str>,
+}
+
+impl<'a> ObjectEvent<'a> {
+	fn event_type(&self) -> NotificationEventType {
+		if self.name.starts_with("ObjectRemoved:") {
+			NotificationEventType::ObjectRemoved
+		} else {
+			NotificationEventType::ObjectCreated
+		}
+	}
+}
+
+/// Send an event to the webhooks of the bucket that are interested in it.
+/// Events are delivered in the background, errors are only logged.
+pub fn notify_object_event(garage: &Garage, bucket: &Bucket, event: ObjectEvent<'_>) {
+	let webhooks = match bucket.params().and_then(|p| p.notifications.get().as_ref()) {
+		Some(config) => config
+			.webhooks
+			.iter()
+			.filter(|w| webhook_matches(w, &event))
+			.map(|w| w.url.clone())
+			.collect::<Vec<_>>(),
+		None => return,
+	};
+	if webhooks.is_empty() {
+		return;
+	}
+
+	let bucket_name = bucket
+		.aliases()
+		.iter()
+		.find(|(_, _, active)| *active)
+		.map(|(name, _, _)| name.clone())
+		.unwrap_or_else(|| hex::encode(bucket.id));
+	let event_body = S3EventNotification {
+		records: vec![S3EventRecord {
+			event_version: "2.1",
+			event_source: "aws:s3",
+			aws_region: garage.config.s3_api.s3_region.clone(),
+			event_time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+			event_name: event.name,
+			s3: S3EventEntity {
+				s3_schema_version: "1.0",
+				bucket: S3EventBucket {
+					arn: format!("arn:aws:s3:::{}", bucket_name),
+					name: bucket_name,
+				},
+				object: S3EventObject {
+					key: uri_encode(event.key, true),
+					size: event.size,
+					e_tag: event.etag.map(String::from),
+					version_id: hex::encode(event.version_uuid),
+					sequencer: format!("{:016X}", garage_util::time::now_msec()),
+				},
+			},
+		}],
+	};
+	let event_body = match serde_json::to_vec(&event_body) {
+		Ok(body) => body,
+		Err(e) => {
+			error!("Could not serialize event notification: {}", e);
+			return;
+		}
+	};
+
+	let client = Client::builder().build(
+		hyper_rustls::HttpsConnectorBuilder::new()
+			.with_native_roots()
+			.https_or_http()
+			.enable_http1()
+			.build(),
+	);
+	for url in webhooks {
+		tokio::spawn(send_notification(client.clone(), url, event_body.clone()));
+	}
+}
+
+fn webhook_matches(webhook: &NotificationWebhook, event: &ObjectEvent<'_>) -> bool {
+	webhook.events.contains(&event.event_type())
+		&& webhook
+			.key_prefix
+			.as_ref()
+			.map(|prefix| event.key.starts_with(prefix.as_str()))
+			.unwrap_or(true)
+}
+
+async fn send_notification(
+	client: Client<HttpsConnector<HttpConnector>>,
+	url: String,
+	body: Vec<u8>,
+) {
+	let mut delay = NOTIFICATION_RETRY_DELAY;
+	for attempt in 1..=NOTIFICATION_MAX_ATTEMPTS {
+		let req = Request::builder()
+			.method(Method::POST)
+			.uri(&url)
+			.header(hyper::header::CONTENT_TYPE, "application/json")
+			.body(Body::from(body.clone()));
+		let req = match req {
+			Ok(req) => req,
+			Err(e) => {
+				warn!("Invalid notification webhook URL {}: {}", url, e);
+				return;
+			}
+		};
+
+		match tokio::time::timeout(NOTIFICATION_TIMEOUT, client.request(req)).await {
+			Ok(Ok(resp)) if resp.status().is_success() => return,
+			Ok(Ok(resp)) => warn!(
+				"Notification webhook {} returned status {} (attempt {}/{})",
+				url,
+				resp.status(),
+				attempt,
+				NOTIFICATION_MAX_ATTEMPTS
+			),
+			Ok(Err(e)) => warn!(
+				"Could not send notification to webhook {}: {} (attempt {}/{})",
+				url, e, attempt, NOTIFICATION_MAX_ATTEMPTS
+			),
+			Err(_) => warn!(
+				"Timeout while sending notification to webhook {} (attempt {}/{})",
+				url, attempt, NOTIFICATION_MAX_ATTEMPTS
+			),
+		}
+
+		if attempt < NOTIFICATION_MAX_ATTEMPTS {
+			tokio::time::sleep(delay).await;
+			delay *= 2;
+		}
+	}
+	error!(
+		"Giving up sending notification to webhook {} after {} attempts",
+		url, NOTIFICATION_MAX_ATTEMPTS
+	);
+}
+
+// ---- S3 event message format ----
+
+#[derive(Serialize)]
+struct S3EventNotification {
+	#[serde(rename = "Records")]
+	records: Vec<S3EventRecord>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct S3EventRecord {
+	event_version: &'static str,
+	event_source: &'static str,
+	aws_region: String,
+	event_time: String,
+	event_name: &'static str,
+	s3: S3EventEntity,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct S3EventEntity {
+	s3_schema_version: &'static str,
+	bucket: S3EventBucket,
+	object: S3EventObject,
+}
+
+#[derive(Serialize)]
+struct S3EventBucket {
+	name: String,
+	arn: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct S3EventObject {
+	key: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	size: Option<u64>,
+	#[serde(rename = "eTag", skip_serializing_if = "Option::is_none")]
+	e_tag: Option<String>,
+	version_id: String,
+	sequencer: String,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_webhook_matches() {
+		let webhook = NotificationWebhook {
+			url: "http://localhost/hook".into(),
+			events: vec![NotificationEventType::ObjectCreated],
+			key_prefix: Some("photos/".into()),
+		};
+		let event = |name, key| ObjectEvent {
+			name,
+			key,
+			version_uuid: gen_uuid(),
+			size: None,
+			etag: None,
+		};
+
+		assert!(webhook_matches(
+			&webhook,
+			&event("ObjectCreated:Put", "photos/cat.jpg")
+		));
+		assert!(!webhook_matches(
+			&webhook,
+			&event("ObjectCreated:Put", "videos/cat.mp4")
+		));
+		assert!(!webhook_matches(
+			&webhook,
+			&event("ObjectRemoved:Delete", "photos/cat.jpg")
+		));
+	}
+}
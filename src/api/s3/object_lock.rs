@@ -0,0 +1,570 @@
+use quick_xml::de::from_reader;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use crate::s3::error::*;
+use crate::s3::put::decode_version_id;
+use crate::s3::xml::{to_xml_with_header, xmlns_tag, IntValue, Value};
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::{
+	Bucket, BucketVersioning, ObjectLockDefaultRetention, ObjectLockPeriod,
+};
+use garage_model::garage::Garage;
+use garage_model::key_table::Key;
+use garage_model::s3::object_table::*;
+use garage_util::crdt;
+use garage_util::data::*;
+use garage_util::time::*;
+
+pub const X_AMZ_OBJECT_LOCK_MODE: &str = "x-amz-object-lock-mode";
+pub const X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE: &str = "x-amz-object-lock-retain-until-date";
+pub const X_AMZ_OBJECT_LOCK_LEGAL_HOLD: &str = "x-amz-object-lock-legal-hold";
+const X_AMZ_BYPASS_GOVERNANCE_RETENTION: &str = "x-amz-bypass-governance-retention";
+
+const MODE_GOVERNANCE: &str = "GOVERNANCE";
+const MODE_COMPLIANCE: &str = "COMPLIANCE";
+const LEGAL_HOLD_ON: &str = "ON";
+const LEGAL_HOLD_OFF: &str = "OFF";
+
+const DAY_MSEC: u64 = 24 * 3600 * 1000;
+
+/// Object lock parameters of a new object version
+#[derive(Debug, Default)]
+pub struct ObjectLockParams {
+	pub retention: Option<ObjectLockConfig>,
+	pub legal_hold: bool,
+}
+
+impl ObjectLockParams {
+	/// Get the object lock parameters of a new object from the request headers,
+	/// or from the default retention of the bucket if the request does not specify one
+	pub fn from_request(bucket: &Bucket, headers: &HeaderMap<HeaderValue>) -> Result<Self, Error> {
+		let mode = get_header(headers, X_AMZ_OBJECT_LOCK_MODE)?;
+		let retain_until = get_header(headers, X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE)?;
+		let legal_hold = get_header(headers, X_AMZ_OBJECT_LOCK_LEGAL_HOLD)?;
+
+		if !bucket.object_lock_enabled() {
+			if mode.is_some() || retain_until.is_some() || legal_hold.is_some() {
+				return Err(Error::bad_request(
+					"Bucket is missing Object Lock Configuration",
+				));
+			}
+			return Ok(Self::default());
+		}
+
+		let retention = match (mode, retain_until) {
+			(Some(mode), Some(retain_until)) => {
+				let retain_until = parse_date(retain_until)?;
+				if retain_until <= now_msec() {
+					return Err(Error::bad_request(
+						"The retain until date must be in the future",
+					));
+				}
+				Some(ObjectLockConfig {
+					mode: parse_mode(mode)?,
+					retain_until,
+				})
+			}
+			(None, None) => default_retention(bucket, now_msec()),
+			_ => {
+				return Err(Error::bad_request(format!(
+					"{} and {} must be specified together",
+					X_AMZ_OBJECT_LOCK_MODE, X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE
+				)))
+			}
+		};
+
+		Ok(Self {
+			retention,
+			legal_hold: parse_legal_hold(legal_hold.unwrap_or(LEGAL_HOLD_OFF))?,
+		})
+	}
+
+	/// Legal hold of the new object version
+	pub fn legal_hold(&self) -> crdt::Lww<bool> {
+		match self.legal_hold {
+			true => crdt::Lww::new(true),
+			false => crdt::Lww::default(),
+		}
+	}
+
+	/// Store the retention in the headers of an object version being uploaded,
+	/// so that it can be applied when the upload is completed
+	pub fn store_in_headers(&self, headers: &mut BTreeMap<String, String>) {
+		if let Some(lock) = &self.retention {
+			headers.insert(
+				X_AMZ_OBJECT_LOCK_MODE.to_string(),
+				mode_str(lock.mode).to_string(),
+			);
+			headers.insert(
+				X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE.to_string(),
+				msec_to_rfc3339(lock.retain_until),
+			);
+		}
+	}
+
+	/// Remove the retention stored by `store_in_headers` from the headers
+	/// of an object version being uploaded
+	pub fn take_from_headers(
+		headers: &mut BTreeMap<String, String>,
+	) -> Result<Option<ObjectLockConfig>, Error> {
+		let mode = headers.remove(X_AMZ_OBJECT_LOCK_MODE);
+		let retain_until = headers.remove(X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE);
+		match (mode, retain_until) {
+			(Some(mode), Some(retain_until)) => Ok(Some(ObjectLockConfig {
+				mode: parse_mode(&mode)?,
+				retain_until: parse_date(&retain_until)?,
+			})),
+			_ => Ok(None),
+		}
+	}
+}
+
+/// Whether the request asks to bypass governance mode retention
+/// and the key is allowed to do it (it is an owner of the bucket)
+pub fn can_bypass_governance(
+	headers: &HeaderMap<HeaderValue>,
+	api_key: &Key,
+	bucket: &Bucket,
+) -> bool {
+	let bypass = headers
+		.get(X_AMZ_BYPASS_GOVERNANCE_RETENTION)
+		.and_then(|v| v.to_str().ok())
+		.map(|v| v.eq_ignore_ascii_case("true"))
+		.unwrap_or(false);
+	bypass && api_key.allow_owner(&bucket.id)
+}
+
+/// Check that writing a new version of an object (or a delete marker) does
+/// not remove previous versions of the object that are protected by object lock.
+/// Compliance mode retention and legal holds can not be bypassed by anyone.
+pub fn check_object_lock_replace(object: &Object, bypass_governance: bool) -> Result<(), Error> {
+	for v in object
+		.versions()
+		.iter()
+		.filter(|v| v.is_complete() && !v.is_retained())
+	{
+		check_version_lock(v, bypass_governance)?;
+	}
+	Ok(())
+}
+
+/// Check that a version of an object can be removed, i.e. that it is not
+/// protected by object lock. Compliance mode retention and legal holds
+/// can not be bypassed by anyone.
+pub fn check_version_lock(version: &ObjectVersion, bypass_governance: bool) -> Result<(), Error> {
+	if !version.is_locked(now_msec()) {
+		return Ok(());
+	}
+	if *version.legal_hold.get() {
+		return Err(Error::forbidden("The object is protected by a legal hold"));
+	}
+	if let Some(lock) = version_lock(version) {
+		match lock.mode {
+			ObjectLockMode::Compliance => {
+				return Err(Error::forbidden(format!(
+					"The object is protected by a compliance mode retention until {}",
+					msec_to_rfc3339(lock.retain_until)
+				)))
+			}
+			ObjectLockMode::Governance if !bypass_governance => {
+				return Err(Error::forbidden(format!(
+					"The object is protected by a governance mode retention until {}",
+					msec_to_rfc3339(lock.retain_until)
+				)))
+			}
+			ObjectLockMode::Governance => (),
+		}
+	}
+	Ok(())
+}
+
+/// Add the object lock headers of an object version to a response
+pub fn add_object_lock_headers(
+	version: &ObjectVersion,
+	version_meta: &ObjectVersionMeta,
+	mut resp: http::response::Builder,
+) -> http::response::Builder {
+	if let Some(lock) = &version_meta.lock {
+		resp = resp
+			.header(X_AMZ_OBJECT_LOCK_MODE, mode_str(lock.mode))
+			.header(
+				X_AMZ_OBJECT_LOCK_RETAIN_UNTIL_DATE,
+				msec_to_rfc3339(lock.retain_until),
+			);
+	}
+	if *version.legal_hold.get() {
+		resp = resp.header(X_AMZ_OBJECT_LOCK_LEGAL_HOLD, LEGAL_HOLD_ON);
+	}
+	resp
+}
+
+// ---- Bucket object lock configuration ----
+
+pub async fn handle_get_object_lock_configuration(
+	bucket: &Bucket,
+) -> Result<Response<Body>, Error> {
+	if !bucket.object_lock_enabled() {
+		return Err(Error::NoSuchObjectLockConfiguration);
+	}
+	let conf = ObjectLockConfiguration::from_garage_retention(
+		*bucket.params().unwrap().object_lock_default_retention.get(),
+	);
+	let xml = to_xml_with_header(&conf)?;
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_lock_configuration(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let conf: ObjectLockConfiguration = from_reader(&body as &[u8])?;
+	let default_retention = conf.validate_into_garage_retention()?;
+
+	if !bucket.versioning_enabled() {
+		return Err(Error::InvalidBucketState(
+			"Versioning must be enabled on the bucket to enable object lock".into(),
+		));
+	}
+
+	let param = bucket.params_mut().unwrap();
+
+	param.object_lock_enabled.set();
+	param.versioning.update(Some(BucketVersioning::Enabled));
+	param
+		.object_lock_default_retention
+		.update(default_retention);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+// ---- Object legal hold ----
+
+pub async fn handle_get_object_legal_hold(
+	garage: Arc<Garage>,
+	bucket: &Bucket,
+	key: &str,
+	version_id: Option<&str>,
+) -> Result<Response<Body>, Error> {
+	if !bucket.object_lock_enabled() {
+		return Err(Error::bad_request(
+			"Bucket is missing Object Lock Configuration",
+		));
+	}
+
+	let object = garage
+		.object_table
+		.get(&bucket.id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let version = find_version(&object, version_id)?;
+
+	let legal_hold = LegalHold {
+		xmlns: (),
+		status: Value(
+			match version.legal_hold.get() {
+				true => LEGAL_HOLD_ON,
+				false => LEGAL_HOLD_OFF,
+			}
+			.to_string(),
+		),
+	};
+	let xml = to_xml_with_header(&legal_hold)?;
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "application/xml")
+		.body(Body::from(xml))?)
+}
+
+pub async fn handle_put_object_legal_hold(
+	garage: Arc<Garage>,
+	bucket: &Bucket,
+	key: &str,
+	version_id: Option<&str>,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+
+	if !bucket.object_lock_enabled() {
+		return Err(Error::bad_request(
+			"Bucket is missing Object Lock Configuration",
+		));
+	}
+
+	let legal_hold: LegalHold = from_reader(&body as &[u8])?;
+	let legal_hold = parse_legal_hold(&legal_hold.status.0)?;
+
+	let object = garage
+		.object_table
+		.get(&bucket.id, &key.to_string())
+		.await?
+		.ok_or(Error::NoSuchKey)?;
+	let mut version = find_version(&object, version_id)?.clone();
+
+	version.legal_hold.update(legal_hold);
+	let object = Object::new(bucket.id, key.to_string(), vec![version]);
+	garage.object_table.insert(&object).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.body(Body::empty())?)
+}
+
+/// Find the version of an object that is targetted by a request
+/// (the current version if no version id is given)
+pub(crate) fn find_version<'a>(
+	object: &'a Object,
+	version_id: Option<&str>,
+) -> Result<&'a ObjectVersion, Error> {
+	let version = match version_id {
+		None => object.current_data_version(),
+		Some("null") => object
+			.versions()
+			.iter()
+			.rev()
+			.find(|v| v.is_data() && !v.versioned),
+		Some(id) => {
+			let uuid = decode_version_id(id)?;
+			object
+				.versions()
+				.iter()
+				.find(|v| v.is_data() && v.uuid == uuid)
+		}
+	};
+	version.ok_or(Error::NoSuchKey)
+}
+
+// ---- Helpers ----
+
+fn version_lock(version: &ObjectVersion) -> Option<&ObjectLockConfig> {
+	match &version.state {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+		| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta.lock.as_ref(),
+		_ => None,
+	}
+}
+
+fn default_retention(bucket: &Bucket, now: u64) -> Option<ObjectLockConfig> {
+	let default = (*bucket.params()?.object_lock_default_retention.get())?;
+	let days = match default.period {
+		ObjectLockPeriod::Days(d) => d as u64,
+		ObjectLockPeriod::Years(y) => y as u64 * 365,
+	};
+	Some(ObjectLockConfig {
+		mode: default.mode,
+		retain_until: now + days * DAY_MSEC,
+	})
+}
+
+fn get_header<'a>(
+	headers: &'a HeaderMap<HeaderValue>,
+	name: &str,
+) -> Result<Option<&'a str>, Error> {
+	match headers.get(name) {
+		Some(v) => Ok(Some(v.to_str()?)),
+		None => Ok(None),
+	}
+}
+
+fn parse_mode(mode: &str) -> Result<ObjectLockMode, Error> {
+	match mode {
+		MODE_GOVERNANCE => Ok(ObjectLockMode::Governance),
+		MODE_COMPLIANCE => Ok(ObjectLockMode::Compliance),
+		m => Err(Error::bad_request(format!(
+			"Invalid object lock mode: {}",
+			m
+		))),
+	}
+}
+
+fn mode_str(mode: ObjectLockMode) -> &'static str {
+	match mode {
+		ObjectLockMode::Governance => MODE_GOVERNANCE,
+		ObjectLockMode::Compliance => MODE_COMPLIANCE,
+	}
+}
+
+fn parse_legal_hold(status: &str) -> Result<bool, Error> {
+	match status {
+		LEGAL_HOLD_ON => Ok(true),
+		LEGAL_HOLD_OFF => Ok(false),
+		s => Err(Error::bad_request(format!(
+			"Invalid legal hold status: {}",
+			s
+		))),
+	}
+}
+
+fn parse_date(date: &str) -> Result<u64, Error> {
+	let date = chrono::DateTime::parse_from_rfc3339(date)
+		.ok_or_bad_request("Invalid object lock retain until date")?;
+	Ok(date.timestamp_millis().max(0) as u64)
+}
+
+// ---- SERIALIZATION AND DESERIALIZATION TO/FROM S3 XML ----
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename = "ObjectLockConfiguration")]
+pub struct ObjectLockConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "ObjectLockEnabled")]
+	pub object_lock_enabled: Option<Value>,
+	#[serde(rename = "Rule", default, skip_serializing_if = "Option::is_none")]
+	pub rule: Option<ObjectLockRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ObjectLockRule {
+	#[serde(rename = "DefaultRetention")]
+	pub default_retention: Option<DefaultRetention>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DefaultRetention {
+	#[serde(rename = "Mode")]
+	pub mode: Value,
+	#[serde(rename = "Days", default, skip_serializing_if = "Option::is_none")]
+	pub days: Option<IntValue>,
+	#[serde(rename = "Years", default, skip_serializing_if = "Option::is_none")]
+	pub years: Option<IntValue>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+// Requests use <LegalHold> as root element, while SDKs expect
+// responses to be rooted at <ObjectLockLegalHold>
+#[serde(rename = "ObjectLockLegalHold")]
+pub struct LegalHold {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Status")]
+	pub status: Value,
+}
+
+impl ObjectLockConfiguration {
+	pub fn validate_into_garage_retention(
+		self,
+	) -> Result<Option<ObjectLockDefaultRetention>, Error> {
+		match self.object_lock_enabled.as_ref().map(|v| v.0.as_str()) {
+			Some("Enabled") => (),
+			_ => {
+				return Err(Error::bad_request(
+					"ObjectLockEnabled must be set to Enabled",
+				))
+			}
+		}
+
+		let retention = match self.rule.and_then(|r| r.default_retention) {
+			Some(r) => r,
+			None => return Ok(None),
+		};
+		let period = match (retention.days, retention.years) {
+			(Some(IntValue(d)), None) if d > 0 && d <= u32::MAX as i64 => {
+				ObjectLockPeriod::Days(d as u32)
+			}
+			(None, Some(IntValue(y))) if y > 0 && y <= u32::MAX as i64 => {
+				ObjectLockPeriod::Years(y as u32)
+			}
+			_ => {
+				return Err(Error::bad_request(
+					"Default retention must specify a positive number of either Days or Years",
+				))
+			}
+		};
+		Ok(Some(ObjectLockDefaultRetention {
+			mode: parse_mode(&retention.mode.0)?,
+			period,
+		}))
+	}
+
+	pub fn from_garage_retention(retention: Option<ObjectLockDefaultRetention>) -> Self {
+		Self {
+			xmlns: (),
+			object_lock_enabled: Some(Value("Enabled".into())),
+			rule: retention.map(|r| ObjectLockRule {
+				default_retention: Some(DefaultRetention {
+					mode: Value(mode_str(r.mode).into()),
+					days: match r.period {
+						ObjectLockPeriod::Days(d) => Some(IntValue(d as i64)),
+						_ => None,
+					},
+					years: match r.period {
+						ObjectLockPeriod::Years(y) => Some(IntValue(y as i64)),
+						_ => None,
+					},
+				}),
+			}),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use quick_xml::de::from_str;
+
+	#[test]
+	fn test_deserialize_object_lock_config() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ObjectLockConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <ObjectLockEnabled>Enabled</ObjectLockEnabled>
+  <Rule>
+    <DefaultRetention>
+      <Mode>COMPLIANCE</Mode>
+      <Days>30</Days>
+    </DefaultRetention>
+  </Rule>
+</ObjectLockConfiguration>"#;
+		let conf: ObjectLockConfiguration = from_str(message).unwrap();
+		let retention = conf.validate_into_garage_retention()?;
+		assert_eq!(
+			retention,
+			Some(ObjectLockDefaultRetention {
+				mode: ObjectLockMode::Compliance,
+				period: ObjectLockPeriod::Days(30),
+			})
+		);
+
+		let message2 =
+			to_xml_with_header(&ObjectLockConfiguration::from_garage_retention(retention))?;
+		let cleanup = |c: &str| c.replace(char::is_whitespace, "");
+		assert_eq!(cleanup(message), cleanup(&message2));
+
+		let both = message.replace("<Days>30</Days>", "<Days>30</Days><Years>1</Years>");
+		let conf: ObjectLockConfiguration = from_str(&both).unwrap();
+		assert!(conf.validate_into_garage_retention().is_err());
+
+		Ok(())
+	}
+}
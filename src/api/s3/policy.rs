@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use crate::generic_server::RemoteAddr;
+use crate::s3::error::*;
+use crate::s3::router::Endpoint;
+use crate::signature::verify_signed_content;
+
+use garage_model::bucket_table::{Bucket, BucketPolicy};
+use garage_model::garage::Garage;
+use garage_model::key_table::Key;
+use garage_util::data::*;
+
+/// Maximum size of a bucket policy document, as in AWS
+const MAX_POLICY_SIZE: usize = 20 * 1024;
+
+const RESOURCE_ARN_PREFIX: &str = "arn:aws:s3:::";
+
+pub async fn handle_get_bucket_policy(bucket: &Bucket) -> Result<Response<Body>, Error> {
+	let param = bucket
+		.params()
+		.ok_or_internal_error("Bucket should not be deleted at this point")?;
+
+	match param.policy.get() {
+		Some(BucketPolicy(policy)) => Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/json")
+			.body(Body::from(policy.clone()))?),
+		None => Err(Error::NoSuchBucketPolicy),
+	}
+}
+
+pub async fn handle_delete_bucket_policy(
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+) -> Result<Response<Body>, Error> {
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.policy.update(None);
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+pub async fn handle_put_bucket_policy(
+	garage: Arc<Garage>,
+	api_key: &Key,
+	bucket_id: Uuid,
+	req: Request<Body>,
+	content_sha256: Option<Hash>,
+) -> Result<Response<Body>, Error> {
+	let body = hyper::body::to_bytes(req.into_body()).await?;
+
+	if let Some(content_sha256) = content_sha256 {
+		verify_signed_content(content_sha256, &body[..])?;
+	}
+	if body.len() > MAX_POLICY_SIZE {
+		return Err(Error::MalformedPolicy(format!(
+			"Policy exceeds the maximum size of {} bytes",
+			MAX_POLICY_SIZE
+		)));
+	}
+
+	let policy_json = String::from_utf8(body.to_vec())?;
+	let policy = Policy::parse(&policy_json)?;
+	policy.check_grants(api_key, &bucket_id)?;
+
+	let mut bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	// The resources of the policy must designate this bucket, by its global
+	// aliases or by the local aliases of the key that sets the policy
+	let global_aliases = bucket
+		.aliases()
+		.iter()
+		.filter(|(_, _, active)| *active)
+		.map(|(name, _, _)| name.as_str());
+	let local_aliases = bucket
+		.local_aliases()
+		.iter()
+		.filter(|((key_id, _), _, active)| *active && *key_id == api_key.key_id)
+		.map(|((_, name), _, _)| name.as_str());
+	policy.check_bucket(&global_aliases.chain(local_aliases).collect::<Vec<_>>())?;
+
+	let param = bucket.params_mut().unwrap();
+
+	param.policy.update(Some(BucketPolicy(policy_json)));
+	garage.bucket_table.insert(&bucket).await?;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(Body::empty())?)
+}
+
+// ---- Policy evaluation ----
+
+/// Object operations that can be allowed or denied by a bucket policy,
+/// named after the corresponding `s3:` actions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum PolicyAction {
+	GetObject,
+	PutObject,
+	DeleteObject,
+}
+
+/// Result of the evaluation of a bucket policy for a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyDecision {
+	/// A statement explicitly allows the request
+	Allow,
+	/// A statement explicitly denies the request; this takes precedence
+	/// over the statements that allow it and over the permissions of the key
+	Deny,
+	/// No statement applies to the request
+	NotApplicable,
+}
+
+/// A parsed bucket policy.
+///
+/// Statements that allow requests apply to any principal (`"*"`), including
+/// anonymous requests; statements that deny requests can be restricted to
+/// a list of access key IDs.
+#[derive(Debug)]
+pub struct Policy {
+	statements: Vec<Statement>,
+}
+
+#[derive(Debug)]
+struct Statement {
+	allow: bool,
+	/// Access key IDs the statement applies to, or None for any principal
+	principals: Option<Vec<String>>,
+	actions: Vec<PolicyAction>,
+	/// Names of the buckets in the resources of the statement
+	buckets: Vec<String>,
+	/// Patterns of the object keys the statement applies to
+	resources: Vec<String>,
+	/// Conditions on the source IP: (ranges, true if the IP must be in one of them)
+	source_ip: Vec<(Vec<IpNet>, bool)>,
+}
+
+/// Object operation made by a request to an endpoint, and the key of the object
+/// if there is a single one, for the endpoints that are subject to bucket policies.
+/// DeleteObjects is not included, as the policy is evaluated for each of the
+/// objects it deletes.
+pub fn policy_action(endpoint: &Endpoint) -> Option<(PolicyAction, Option<&str>)> {
+	match endpoint {
+		Endpoint::GetObject { key, .. }
+		| Endpoint::GetObjectAttributes { key, .. }
+		| Endpoint::HeadObject { key, .. } => Some((PolicyAction::GetObject, Some(key))),
+		Endpoint::PutObject { key }
+		| Endpoint::CopyObject { key }
+		| Endpoint::UploadPart { key, .. }
+		| Endpoint::UploadPartCopy { key, .. }
+		| Endpoint::CreateMultipartUpload { key }
+		| Endpoint::CompleteMultipartUpload { key, .. }
+		| Endpoint::AbortMultipartUpload { key, .. } => Some((PolicyAction::PutObject, Some(key))),
+		Endpoint::DeleteObject { key, .. } => Some((PolicyAction::DeleteObject, Some(key))),
+		_ => None,
+	}
+}
+
+/// Source IP of a request, used in the conditions of bucket policies: the address
+/// of the TCP peer, or the address it forwarded the request for if it is a trusted proxy
+pub fn source_ip(req: &Request<Body>) -> Option<IpAddr> {
+	req.extensions().get::<RemoteAddr>().and_then(|a| a.client)
+}
+
+/// Parse the policy of a bucket, if it has one
+pub fn bucket_policy(bucket: &Bucket) -> Result<Option<Policy>, Error> {
+	match bucket.params().and_then(|p| p.policy.get().as_ref()) {
+		Some(BucketPolicy(json)) => Ok(Some(
+			Policy::parse(json).ok_or_internal_error("Invalid bucket policy")?,
+		)),
+		None => Ok(None),
+	}
+}
+
+impl Policy {
+	pub fn parse(json: &str) -> Result<Self, Error> {
+		let raw: RawPolicy =
+			serde_json::from_str(json).map_err(|e| Error::MalformedPolicy(e.to_string()))?;
+		let statements = raw
+			.statement
+			.into_vec()
+			.into_iter()
+			.map(Statement::parse)
+			.collect::<Result<Vec<_>, _>>()?;
+		if statements.is_empty() {
+			return Err(Error::MalformedPolicy(
+				"Policy must contain at least one statement".into(),
+			));
+		}
+		Ok(Self { statements })
+	}
+
+	/// Check that the policy does not allow operations that the key
+	/// that sets it is not allowed to do itself
+	pub fn check_grants(&self, api_key: &Key, bucket_id: &Uuid) -> Result<(), Error> {
+		for action in self
+			.statements
+			.iter()
+			.filter(|s| s.allow)
+			.flat_map(|s| s.actions.iter())
+		{
+			let allowed = match action {
+				PolicyAction::GetObject => api_key.allow_read(bucket_id),
+				PolicyAction::PutObject | PolicyAction::DeleteObject => {
+					api_key.allow_write(bucket_id)
+				}
+			};
+			if !allowed {
+				return Err(Error::forbidden(format!(
+					"Policy can not allow {:?}, which is not allowed for this key",
+					action
+				)));
+			}
+		}
+		Ok(())
+	}
+
+	/// Check that the resources of the policy are in a bucket with one of the given names
+	pub fn check_bucket(&self, bucket_names: &[&str]) -> Result<(), Error> {
+		for bucket in self.statements.iter().flat_map(|s| s.buckets.iter()) {
+			if !bucket_names.contains(&bucket.as_str()) {
+				return Err(Error::MalformedPolicy(format!(
+					"Policy has a resource in another bucket: {}",
+					bucket
+				)));
+			}
+		}
+		Ok(())
+	}
+
+	/// Evaluate the policy for a request made with the given access key
+	/// (None for anonymous requests)
+	pub fn evaluate(
+		&self,
+		key_id: Option<&str>,
+		action: PolicyAction,
+		object_key: Option<&str>,
+		source_ip: Option<IpAddr>,
+	) -> PolicyDecision {
+		let mut decision = PolicyDecision::NotApplicable;
+		for statement in self.statements.iter() {
+			if statement.matches(key_id, action, object_key, source_ip) {
+				if !statement.allow {
+					return PolicyDecision::Deny;
+				}
+				decision = PolicyDecision::Allow;
+			}
+		}
+		decision
+	}
+}
+
+impl Statement {
+	fn parse(raw: RawStatement) -> Result<Self, Error> {
+		let allow = match raw.effect.as_str() {
+			"Allow" => true,
+			"Deny" => false,
+			e => return Err(Error::MalformedPolicy(format!("Invalid effect: {}", e))),
+		};
+
+		let principals = match raw.principal {
+			RawPrincipal::Wildcard(p) if p == "*" => None,
+			RawPrincipal::Aws { aws } => {
+				let aws = aws.into_vec();
+				if aws.iter().any(|p| p == "*") {
+					None
+				} else {
+					Some(aws)
+				}
+			}
+			RawPrincipal::Wildcard(p) => {
+				return Err(Error::MalformedPolicy(format!("Invalid principal: {}", p)))
+			}
+		};
+		if allow && principals.is_some() {
+			return Err(Error::MalformedPolicy(
+				"Statements that allow access must apply to all principals (\"*\"), \
+				use the permissions of access keys instead"
+					.into(),
+			));
+		}
+
+		let mut actions = vec![];
+		for action in raw.action.into_vec() {
+			match action.as_str() {
+				"s3:GetObject" => actions.push(PolicyAction::GetObject),
+				"s3:PutObject" => actions.push(PolicyAction::PutObject),
+				"s3:DeleteObject" => actions.push(PolicyAction::DeleteObject),
+				"s3:*" => actions.extend([
+					PolicyAction::GetObject,
+					PolicyAction::PutObject,
+					PolicyAction::DeleteObject,
+				]),
+				a => return Err(Error::MalformedPolicy(format!("Unsupported action: {}", a))),
+			}
+		}
+
+		let (buckets, resources) = raw
+			.resource
+			.into_vec()
+			.into_iter()
+			.map(|r| {
+				r.strip_prefix(RESOURCE_ARN_PREFIX)
+					.and_then(|r| r.split_once('/'))
+					.map(|(bucket, key)| (bucket.to_string(), key.to_string()))
+					.ok_or_else(|| {
+						Error::MalformedPolicy(format!(
+							"Invalid resource, expected {}<bucket>/<key>: {}",
+							RESOURCE_ARN_PREFIX, r
+						))
+					})
+			})
+			.collect::<Result<Vec<_>, _>>()?
+			.into_iter()
+			.unzip();
+
+		let mut source_ip = vec![];
+		for (operator, conditions) in raw.condition {
+			let must_match = match operator.as_str() {
+				"IpAddress" => true,
+				"NotIpAddress" => false,
+				o => {
+					return Err(Error::MalformedPolicy(format!(
+						"Unsupported condition operator: {}",
+						o
+					)))
+				}
+			};
+			for (condition_key, values) in conditions {
+				if condition_key != "aws:SourceIp" {
+					return Err(Error::MalformedPolicy(format!(
+						"Unsupported condition key: {}",
+						condition_key
+					)));
+				}
+				let ranges = values
+					.into_vec()
+					.iter()
+					.map(|v| parse_ip_range(v))
+					.collect::<Result<Vec<_>, _>>()?;
+				source_ip.push((ranges, must_match));
+			}
+		}
+
+		Ok(Self {
+			allow,
+			principals,
+			actions,
+			buckets,
+			resources,
+			source_ip,
+		})
+	}
+
+	fn matches(
+		&self,
+		key_id: Option<&str>,
+		action: PolicyAction,
+		object_key: Option<&str>,
+		source_ip: Option<IpAddr>,
+	) -> bool {
+		let principal_matches = match &self.principals {
+			None => true,
+			Some(keys) => key_id.map(|k| keys.iter().any(|x| x == k)).unwrap_or(false),
+		};
+		// When the request is on several objects, or when its source IP
+		// is not known, statements that deny access apply and
+		// statements that allow access don't
+		let resource_matches = match object_key {
+			Some(k) => self.resources.iter().any(|r| glob_match(r, k)),
+			None => !self.allow,
+		};
+		let conditions_match = self
+			.source_ip
+			.iter()
+			.all(|(ranges, must_match)| match source_ip {
+				Some(ip) => ranges.iter().any(|r| r.contains(&ip)) == *must_match,
+				None => !self.allow,
+			});
+		principal_matches && self.actions.contains(&action) && resource_matches && conditions_match
+	}
+}
+
+fn parse_ip_range(s: &str) -> Result<IpNet, Error> {
+	s.parse::<IpNet>()
+		.or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+		.map_err(|_| Error::MalformedPolicy(format!("Invalid IP range: {}", s)))
+}
+
+/// Match a string against a pattern where `*` matches any sequence of characters
+/// and `?` matches any single character
+fn glob_match(pattern: &str, s: &str) -> bool {
+	let pattern = pattern.chars().collect::<Vec<_>>();
+	let s = s.chars().collect::<Vec<_>>();
+	let (mut p, mut i) = (0, 0);
+	let mut backtrack = None;
+	while i < s.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+			p += 1;
+			i += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			backtrack = Some((p, i));
+			p += 1;
+		} else if let Some((bp, bi)) = backtrack {
+			p = bp + 1;
+			i = bi + 1;
+			backtrack = Some((bp, bi + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+// ---- Policy JSON format ----
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+struct RawPolicy {
+	#[serde(default)]
+	#[allow(dead_code)]
+	version: Option<String>,
+	#[serde(default)]
+	#[allow(dead_code)]
+	id: Option<String>,
+	statement: OneOrMany<RawStatement>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+struct RawStatement {
+	#[serde(default)]
+	#[allow(dead_code)]
+	sid: Option<String>,
+	effect: String,
+	principal: RawPrincipal,
+	action: OneOrMany<String>,
+	resource: OneOrMany<String>,
+	#[serde(default)]
+	condition: HashMap<String, HashMap<String, OneOrMany<String>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPrincipal {
+	Wildcard(String),
+	Aws {
+		#[serde(rename = "AWS")]
+		aws: OneOrMany<String>,
+	},
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+	One(T),
+	Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+	fn into_vec(self) -> Vec<T> {
+		match self {
+			OneOrMany::One(x) => vec![x],
+			OneOrMany::Many(v) => v,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_glob_match() {
+		assert!(glob_match("*", "photos/cat.jpg"));
+		assert!(glob_match("photos/*", "photos/cat.jpg"));
+		assert!(glob_match("photos/*.jpg", "photos/2023/cat.jpg"));
+		assert!(glob_match("photos/ca?.jpg", "photos/cat.jpg"));
+		assert!(!glob_match("photos/*", "videos/cat.mp4"));
+		assert!(!glob_match("photos/*.jpg", "photos/cat.png"));
+	}
+
+	#[test]
+	fn test_policy_evaluation() {
+		let policy = Policy::parse(
+			r#"{
+				"Version": "2012-10-17",
+				"Statement": [
+					{
+						"Sid": "PublicRead",
+						"Effect": "Allow",
+						"Principal": "*",
+						"Action": "s3:GetObject",
+						"Resource": "arn:aws:s3:::bucket/public/*"
+					},
+					{
+						"Effect": "Deny",
+						"Principal": {"AWS": ["GK31c2f218a2e44f485b94239e"]},
+						"Action": ["s3:PutObject", "s3:DeleteObject"],
+						"Resource": "arn:aws:s3:::bucket/*",
+						"Condition": {"NotIpAddress": {"aws:SourceIp": "192.0.2.0/24"}}
+					}
+				]
+			}"#,
+		)
+		.unwrap();
+
+		let inside = Some("192.0.2.10".parse().unwrap());
+		let outside = Some("198.51.100.1".parse().unwrap());
+		let key = Some("GK31c2f218a2e44f485b94239e");
+
+		assert_eq!(
+			policy.evaluate(None, PolicyAction::GetObject, Some("public/a"), outside),
+			PolicyDecision::Allow
+		);
+		assert_eq!(
+			policy.evaluate(None, PolicyAction::GetObject, Some("private/a"), outside),
+			PolicyDecision::NotApplicable
+		);
+		assert_eq!(
+			policy.evaluate(key, PolicyAction::PutObject, Some("a"), inside),
+			PolicyDecision::NotApplicable
+		);
+		assert_eq!(
+			policy.evaluate(key, PolicyAction::PutObject, Some("a"), outside),
+			PolicyDecision::Deny
+		);
+		assert_eq!(
+			policy.evaluate(key, PolicyAction::DeleteObject, None, inside),
+			PolicyDecision::NotApplicable
+		);
+		assert_eq!(
+			policy.evaluate(key, PolicyAction::DeleteObject, None, None),
+			PolicyDecision::Deny
+		);
+	}
+
+	#[test]
+	fn test_policy_bucket() {
+		let policy = Policy::parse(
+			r#"{"Statement": [
+				{"Effect": "Allow", "Principal": "*",
+					"Action": "s3:GetObject", "Resource": "arn:aws:s3:::photos/*"},
+				{"Effect": "Deny", "Principal": "*",
+					"Action": "s3:GetObject", "Resource": "arn:aws:s3:::pictures/private/*"}
+			]}"#,
+		)
+		.unwrap();
+
+		assert!(policy.check_bucket(&["photos", "pictures"]).is_ok());
+		assert!(matches!(
+			policy.check_bucket(&["photos"]),
+			Err(Error::MalformedPolicy(_))
+		));
+		assert!(matches!(
+			policy.check_bucket(&["videos"]),
+			Err(Error::MalformedPolicy(_))
+		));
+	}
+
+	#[test]
+	fn test_invalid_policies() {
+		let statement = |s: &str| format!(r#"{{"Statement": [{}]}}"#, s);
+		// Allow statements must apply to any principal
+		assert!(Policy::parse(&statement(
+			r#"{"Effect": "Allow", "Principal": {"AWS": "GKabc"},
+				"Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*"}"#
+		))
+		.is_err());
+		// Unsupported action
+		assert!(Policy::parse(&statement(
+			r#"{"Effect": "Allow", "Principal": "*",
+				"Action": "s3:ListBucket", "Resource": "arn:aws:s3:::b/*"}"#
+		))
+		.is_err());
+		// Unsupported condition key
+		assert!(Policy::parse(&statement(
+			r#"{"Effect": "Allow", "Principal": "*",
+				"Action": "s3:GetObject", "Resource": "arn:aws:s3:::b/*",
+				"Condition": {"StringEquals": {"aws:Referer": "example.com"}}}"#
+		))
+		.is_err());
+		// Unsupported statement field
+		assert!(Policy::parse(&statement(
+			r#"{"Effect": "Deny", "Principal": "*",
+				"NotAction": "s3:GetObject", "Resource": "arn:aws:s3:::b/*"}"#
+		))
+		.is_err());
+	}
+}
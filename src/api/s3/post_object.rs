@@ -15,7 +15,10 @@ use serde::Deserialize;
 
 use garage_model::garage::Garage;
 
+use crate::s3::encryption::object_encryption_for_put;
 use crate::s3::error::*;
+use crate::s3::notification::{notify_object_event, ObjectEvent};
+use crate::s3::object_lock::ObjectLockParams;
 use crate::s3::put::{get_headers, save_stream};
 use crate::s3::xml as s3_xml;
 use crate::signature::payload::{parse_date, verify_v4};
@@ -136,6 +139,7 @@ pub async fn handle_post_object(
 		.bucket_helper()
 		.get_existing_bucket(bucket_id)
 		.await?;
+	let encryption = object_encryption_for_put(&garage, &bucket, &params)?;
 
 	let decoded_policy = BASE64_STANDARD
 		.decode(policy)
@@ -227,19 +231,36 @@ pub async fn handle_post_object(
 	}
 
 	let headers = get_headers(&params)?;
+	let lock = ObjectLockParams::from_request(&bucket, &params)?;
 
 	let stream = field.map(|r| r.map_err(Into::into));
-	let (_, md5) = save_stream(
-		garage,
+	let (version_uuid, md5, size) = save_stream(
+		garage.clone(),
 		headers,
+		lock,
 		StreamLimiter::new(stream, conditions.content_length),
 		&bucket,
 		&key,
 		None,
 		None,
+		None,
+		false,
+		encryption,
 	)
 	.await?;
 
+	notify_object_event(
+		&garage,
+		&bucket,
+		ObjectEvent {
+			name: "ObjectCreated:Post",
+			key: &key,
+			version_uuid,
+			size: Some(size),
+			etag: Some(&md5),
+		},
+	);
+
 	let etag = format!("\"{}\"", md5);
 
 	let resp = if let Some(mut target) = params
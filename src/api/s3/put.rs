@@ -1,4 +1,5 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryInto;
 use std::sync::Arc;
 
 use base64::prelude::*;
@@ -29,10 +30,18 @@ use garage_model::s3::block_ref_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
+use crate::s3::checksum::*;
+use crate::s3::encryption::*;
 use crate::s3::error::*;
+use crate::s3::notification::{notify_object_event, ObjectEvent};
+use crate::s3::object_lock::ObjectLockParams;
 use crate::s3::xml as s3_xml;
 use crate::signature::verify_signed_content;
 
+const X_AMZ_MP_OBJECT_SIZE: &str = "x-amz-mp-object-size";
+const X_AMZ_IF_NONE_MATCH: &str = "x-amz-if-none-match";
+const X_GARAGE_CONDITIONAL_WRITE_RACE: &str = "x-garage-conditional-write-race";
+
 pub async fn handle_put(
 	garage: Arc<Garage>,
 	req: Request<Body>,
@@ -43,44 +52,127 @@ pub async fn handle_put(
 	// Retrieve interesting headers from request
 	let headers = get_headers(req.headers())?;
 	debug!("Object headers: {:?}", headers);
+	let lock = ObjectLockParams::from_request(bucket, req.headers())?;
 
 	let content_md5 = match req.headers().get("content-md5") {
 		Some(x) => Some(x.to_str()?.to_string()),
 		None => None,
 	};
+	let checksum = request_checksum_value(req.headers())?;
+
+	// If-None-Match: * means that the object must only be created if it doesn't exist yet
+	let if_none_match = req
+		.headers()
+		.get(hyper::header::IF_NONE_MATCH)
+		.or_else(|| req.headers().get(X_AMZ_IF_NONE_MATCH))
+		.map(|x| x.to_str())
+		.transpose()?;
+	let create_only = match if_none_match {
+		None => false,
+		Some("*") => true,
+		Some(_) => {
+			return Err(Error::NotImplemented(
+				"If-None-Match is only supported with value *".into(),
+			))
+		}
+	};
+
+	let encryption = object_encryption_for_put(&garage, bucket, req.headers())?;
 
 	let (_head, body) = req.into_parts();
 	let body = body.map_err(Error::from);
 
-	save_stream(
-		garage,
+	let (version_uuid, md5, size) = save_stream(
+		garage.clone(),
 		headers,
+		lock,
 		body,
 		bucket,
 		key,
 		content_md5,
 		content_sha256,
+		checksum,
+		create_only,
+		encryption,
 	)
-	.await
-	.map(|(uuid, md5)| put_response(uuid, md5))
+	.await?;
+
+	notify_object_event(
+		&garage,
+		bucket,
+		ObjectEvent {
+			name: "ObjectCreated:Put",
+			key,
+			version_uuid,
+			size: Some(size),
+			etag: Some(&md5),
+		},
+	);
+
+	let mut resp = put_response(version_uuid, md5);
+	if encryption.is_some() {
+		resp.headers_mut().insert(
+			X_AMZ_SERVER_SIDE_ENCRYPTION,
+			HeaderValue::from_static(SSE_ALGORITHM_AES256),
+		);
+	}
+
+	// The existence check done by save_stream and the write of the object are not
+	// atomic: another client may have created the object in between. We cannot
+	// undo our write once it is done, but we can tell the client that a concurrent
+	// write happened by looking at the versions of the object around ours.
+	if create_only {
+		let object = garage
+			.object_table
+			.get(&bucket.id, &key.to_string())
+			.await?;
+		let race = match &object {
+			Some(o) => conditional_write_race(o, version_uuid),
+			None => true,
+		};
+		if race {
+			warn!(
+				"Concurrent write detected for conditional PutObject of {:?}/{}",
+				bucket.id, key
+			);
+			resp.headers_mut().insert(
+				X_GARAGE_CONDITIONAL_WRITE_RACE,
+				HeaderValue::from_static("true"),
+			);
+		}
+	}
+
+	Ok(resp)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage: Arc<Garage>,
 	headers: ObjectVersionHeaders,
+	lock: ObjectLockParams,
 	body: S,
 	bucket: &Bucket,
 	key: &str,
 	content_md5: Option<String>,
 	content_sha256: Option<FixedBytes32>,
-) -> Result<(Uuid, String), Error> {
+	checksum: Option<ChecksumValue>,
+	create_only: bool,
+	encryption: Option<ObjectEncryption>,
+) -> Result<(Uuid, String, u64), Error> {
+	let cipher = ObjectCipher::for_object(&garage, encryption.as_ref())?;
+
 	// Generate identity of new version
 	let version_uuid = gen_uuid();
 	let version_timestamp = now_msec();
 
-	let mut chunker = StreamChunker::new(body, garage.config.block_size);
+	let mut chunker = StreamChunker::new(body, garage.block_size());
 	let first_block = chunker.next().await?.unwrap_or_default();
 
+	let mut headers = headers;
+	if garage.config.s3_api.content_type_autodetect {
+		autodetect_content_type(&mut headers, key, &first_block);
+	}
+
 	// If body is small enough, store it directly in the object table
 	// as "inline data". We can then return immediately.
 	if first_block.len() < INLINE_THRESHOLD {
@@ -89,17 +181,26 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		let data_md5sum = md5sum.finalize();
 		let data_md5sum_hex = hex::encode(data_md5sum);
 
-		let data_sha256sum = sha256sum(&first_block[..]);
+		let checksums = Checksums::compute(&first_block[..]);
 		let size = first_block.len() as u64;
 
 		ensure_checksum_matches(
 			data_md5sum.as_slice(),
-			data_sha256sum,
+			checksums.sha256,
 			content_md5.as_deref(),
 			content_sha256,
 		)?;
+		ensure_checksum_value_matches(checksum, &checksums)?;
 
 		check_quotas(&garage, bucket, key, size).await?;
+		if create_only {
+			check_object_does_not_exist(&garage, bucket, key).await?;
+		}
+
+		let inline_data = match &cipher {
+			Some(cipher) => cipher.encrypt_block(1, 0, &first_block[..])?,
+			None => first_block.to_vec(),
+		};
 
 		let object_version = ObjectVersion {
 			uuid: version_uuid,
@@ -109,15 +210,19 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 					headers,
 					size,
 					etag: data_md5sum_hex.clone(),
+					lock: lock.retention,
+					encryption,
 				},
-				first_block.to_vec(),
+				inline_data,
 			)),
+			versioned: bucket.versioning_enabled(),
+			legal_hold: lock.legal_hold(),
 		};
 
 		let object = Object::new(bucket.id, key.into(), vec![object_version]);
 		garage.object_table.insert(&object).await?;
 
-		return Ok((version_uuid, data_md5sum_hex));
+		return Ok((version_uuid, data_md5sum_hex, size));
 	}
 
 	// The following consists in many steps that can each fail.
@@ -137,6 +242,8 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		uuid: version_uuid,
 		timestamp: version_timestamp,
 		state: ObjectVersionState::Uploading(headers.clone()),
+		versioned: bucket.versioning_enabled(),
+		legal_hold: lock.legal_hold(),
 	};
 	let object = Object::new(bucket.id, key.into(), vec![object_version.clone()]);
 	garage.object_table.insert(&object).await?;
@@ -149,26 +256,28 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage.version_table.insert(&version).await?;
 
 	// Transfer data and verify checksum
-	let first_block_hash = async_blake2sum(first_block.clone()).await;
-
-	let (total_size, data_md5sum, data_sha256sum) = read_and_put_blocks(
+	let (total_size, data_md5sum, checksums, first_block_hash) = read_and_put_blocks(
 		&garage,
 		&version,
 		1,
 		first_block,
-		first_block_hash,
 		&mut chunker,
+		cipher.as_ref(),
 	)
 	.await?;
 
 	ensure_checksum_matches(
 		data_md5sum.as_slice(),
-		data_sha256sum,
+		checksums.sha256,
 		content_md5.as_deref(),
 		content_sha256,
 	)?;
+	ensure_checksum_value_matches(checksum, &checksums)?;
 
 	check_quotas(&garage, bucket, key, total_size).await?;
+	if create_only {
+		check_object_does_not_exist(&garage, bucket, key).await?;
+	}
 
 	// Save final object state, marked as Complete
 	let md5sum_hex = hex::encode(data_md5sum);
@@ -177,6 +286,8 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 			headers,
 			size: total_size,
 			etag: md5sum_hex.clone(),
+			lock: lock.retention,
+			encryption,
 		},
 		first_block_hash,
 	));
@@ -187,7 +298,7 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	// We won't have to clean up on drop.
 	interrupted_cleanup.cancel();
 
-	Ok((version_uuid, md5sum_hex))
+	Ok((version_uuid, md5sum_hex, total_size))
 }
 
 /// Validate MD5 sum against content-md5 header
@@ -242,9 +353,16 @@ async fn check_quotas(
 	let (prev_cnt_obj, prev_cnt_size) = match prev_object {
 		Some(o) => {
 			let prev_cnt = o.counts().into_iter().collect::<HashMap<_, _>>();
+			// Versions retained by bucket versioning are not replaced by the new one
+			let retained_size = o
+				.versions()
+				.iter()
+				.filter(|v| v.is_retained())
+				.map(|v| v.size())
+				.sum::<u64>();
 			(
 				prev_cnt.get(OBJECTS).cloned().unwrap_or_default(),
-				prev_cnt.get(BYTES).cloned().unwrap_or_default(),
+				prev_cnt.get(BYTES).cloned().unwrap_or_default() - retained_size as i64,
 			)
 		}
 		None => (0, 0),
@@ -275,16 +393,54 @@ async fn check_quotas(
 	Ok(())
 }
 
+/// Check that no version of the object currently holds data,
+/// for PutObject requests with If-None-Match: *
+async fn check_object_does_not_exist(
+	garage: &Arc<Garage>,
+	bucket: &Bucket,
+	key: &str,
+) -> Result<(), Error> {
+	let object = garage
+		.object_table
+		.get(&bucket.id, &key.to_string())
+		.await?;
+	match object.as_ref().and_then(|o| o.current_data_version()) {
+		Some(_) => Err(Error::PreconditionFailed),
+		None => Ok(()),
+	}
+}
+
+/// Check whether another version holding data was written concurrently
+/// with the given version, which was created with If-None-Match: *
+fn conditional_write_race(object: &Object, version_uuid: Uuid) -> bool {
+	let versions = object.versions();
+	let pos = match versions.iter().position(|v| v.uuid == version_uuid) {
+		Some(pos) => pos,
+		// Our version was removed, it was overwritten by a newer one
+		None => return true,
+	};
+	// Any data written after ours, or written before ours but after the
+	// delete marker that preceded it (if any), did not exist when we checked
+	versions[pos + 1..].iter().any(|v| v.is_data())
+		|| versions[..pos]
+			.iter()
+			.rev()
+			.take_while(|v| !v.is_complete() || v.is_data())
+			.any(|v| v.is_data())
+}
+
 async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	garage: &Garage,
 	version: &Version,
 	part_number: u64,
 	first_block: Bytes,
-	first_block_hash: Hash,
 	chunker: &mut StreamChunker<S>,
-) -> Result<(u64, GenericArray<u8, typenum::U16>, Hash), Error> {
+	cipher: Option<&ObjectCipher>,
+) -> Result<(u64, GenericArray<u8, typenum::U16>, Checksums, Hash), Error> {
 	let tracer = opentelemetry::global::tracer("garage");
 
+	let mut crc32c = crc32c::crc32c(&first_block[..]);
+
 	let md5hasher = AsyncHasher::<Md5>::new();
 	let sha256hasher = AsyncHasher::<Sha256>::new();
 
@@ -298,13 +454,16 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	.await;
 
 	let mut next_offset = first_block.len();
+	let first_block_len = first_block.len() as u64;
+	let first_block = encrypt_block(cipher, part_number, 0, first_block)?;
+	let first_block_hash = async_blake2sum(first_block.clone()).await;
 	let mut put_curr_version_block = put_block_meta(
 		garage,
 		version,
 		part_number,
 		0,
 		first_block_hash,
-		first_block.len() as u64,
+		first_block_len,
 	);
 	let mut put_curr_block = garage
 		.block_manager
@@ -317,16 +476,19 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 			chunker.next(),
 		)?;
 		if let Some(block) = next_block {
+			let block_len = block.len();
+			let stored_block =
+				encrypt_block(cipher, part_number, next_offset as u64, block.clone())?;
 			let (_, _, block_hash) = futures::future::join3(
 				md5hasher.update(block.clone()),
 				sha256hasher.update(block.clone()),
-				async_blake2sum(block.clone()),
+				async_blake2sum(stored_block.clone()),
 			)
 			.with_context(Context::current_with_span(
 				tracer.start("Hash block (md5, sha256, blake2)"),
 			))
 			.await;
-			let block_len = block.len();
+			crc32c = crc32c::crc32c_append(crc32c, &block[..]);
 			put_curr_version_block = put_block_meta(
 				garage,
 				version,
@@ -335,7 +497,7 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 				block_hash,
 				block_len as u64,
 			);
-			put_curr_block = garage.block_manager.rpc_put_block(block_hash, block);
+			put_curr_block = garage.block_manager.rpc_put_block(block_hash, stored_block);
 			next_offset += block_len;
 		} else {
 			break;
@@ -348,7 +510,29 @@ async fn read_and_put_blocks<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 	let data_sha256sum = sha256hasher.finalize().await;
 	let data_sha256sum = Hash::try_from(&data_sha256sum[..]).unwrap();
 
-	Ok((total_size, data_md5sum, data_sha256sum))
+	let checksums = Checksums {
+		crc32c,
+		sha256: data_sha256sum,
+	};
+
+	Ok((total_size, data_md5sum, checksums, first_block_hash))
+}
+
+/// Encrypt a block before it is stored, if the object is encrypted
+fn encrypt_block(
+	cipher: Option<&ObjectCipher>,
+	part_number: u64,
+	offset: u64,
+	block: Bytes,
+) -> Result<Bytes, Error> {
+	match cipher {
+		Some(cipher) => Ok(Bytes::from(cipher.encrypt_block(
+			part_number,
+			offset,
+			&block[..],
+		)?)),
+		None => Ok(block),
+	}
 }
 
 async fn put_block_meta(
@@ -440,6 +624,8 @@ impl Drop for InterruptedCleanup {
 					uuid: version_uuid,
 					timestamp: version_ts,
 					state: ObjectVersionState::Aborted,
+					versioned: false,
+					legal_hold: Default::default(),
 				};
 				let object = Object::new(bucket_id, key, vec![object_version]);
 				if let Err(e) = garage.object_table.insert(&object).await {
@@ -456,26 +642,42 @@ pub async fn handle_create_multipart_upload(
 	garage: Arc<Garage>,
 	req: &Request<Body>,
 	bucket_name: &str,
-	bucket_id: Uuid,
+	bucket: &Bucket,
 	key: &str,
 ) -> Result<Response<Body>, Error> {
 	let version_uuid = gen_uuid();
-	let headers = get_headers(req.headers())?;
+	let mut headers = get_headers(req.headers())?;
+
+	// The data of the parts is not known yet, so the content type can only
+	// be guessed from the extension of the key
+	if garage.config.s3_api.content_type_autodetect {
+		autodetect_content_type(&mut headers, key, &[]);
+	}
+
+	// The object lock retention is applied when the upload is completed
+	let lock = ObjectLockParams::from_request(bucket, req.headers())?;
+	lock.store_in_headers(&mut headers.other);
+
+	// All parts are encrypted with the key of the final object
+	let encryption = object_encryption_for_put(&garage, bucket, req.headers())?;
+	store_encryption_in_headers(encryption.as_ref(), &mut headers.other);
 
 	// Create object in object table
 	let object_version = ObjectVersion {
 		uuid: version_uuid,
 		timestamp: now_msec(),
 		state: ObjectVersionState::Uploading(headers),
+		versioned: bucket.versioning_enabled(),
+		legal_hold: lock.legal_hold(),
 	};
-	let object = Object::new(bucket_id, key.to_string(), vec![object_version]);
+	let object = Object::new(bucket.id, key.to_string(), vec![object_version]);
 	garage.object_table.insert(&object).await?;
 
 	// Insert empty version so that block_ref entries refer to something
 	// (they are inserted concurrently with blocks in the version table, so
 	// there is the possibility that they are inserted before the version table
 	// is created, in which case it is allowed to delete them, e.g. in repair_*)
-	let version = Version::new(version_uuid, bucket_id, key.into(), false);
+	let version = Version::new(version_uuid, bucket.id, key.into(), false);
 	garage.version_table.insert(&version).await?;
 
 	// Send success response
@@ -487,7 +689,10 @@ pub async fn handle_create_multipart_upload(
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::new(Body::from(xml.into_bytes())))
+	Ok(
+		add_encryption_headers(encryption.as_ref(), Response::builder())
+			.body(Body::from(xml.into_bytes()))?,
+	)
 }
 
 pub async fn handle_put_part(
@@ -505,12 +710,13 @@ pub async fn handle_put_part(
 		Some(x) => Some(x.to_str()?.to_string()),
 		None => None,
 	};
+	let checksum = request_checksum_value(req.headers())?;
 
 	// Read first chuck, and at the same time try to get object to see if it exists
 	let key = key.to_string();
 
 	let body = req.into_body().map_err(Error::from);
-	let mut chunker = StreamChunker::new(body, garage.config.block_size);
+	let mut chunker = StreamChunker::new(body, garage.block_size());
 
 	let (object, version, first_block) = futures::try_join!(
 		garage
@@ -528,13 +734,16 @@ pub async fn handle_put_part(
 	let first_block = first_block.ok_or_bad_request("Empty body")?;
 	let object = object.ok_or_bad_request("Object not found")?;
 
-	if !object
+	let encryption = match object
 		.versions()
 		.iter()
-		.any(|v| v.uuid == version_uuid && v.is_uploading())
+		.find(|v| v.uuid == version_uuid && v.is_uploading())
+		.map(|v| &v.state)
 	{
-		return Err(Error::NoSuchUpload);
-	}
+		Some(ObjectVersionState::Uploading(headers)) => encryption_from_headers(&headers.other)?,
+		_ => return Err(Error::NoSuchUpload),
+	};
+	let cipher = ObjectCipher::for_object(&garage, encryption.as_ref())?;
 
 	// Check part hasn't already been uploaded
 	if let Some(v) = version {
@@ -549,35 +758,37 @@ pub async fn handle_put_part(
 	// Copy block to store
 	let version = Version::new(version_uuid, bucket_id, key, false);
 
-	let first_block_hash = async_blake2sum(first_block.clone()).await;
-
-	let (_, data_md5sum, data_sha256sum) = read_and_put_blocks(
+	let (_, data_md5sum, checksums, _) = read_and_put_blocks(
 		&garage,
 		&version,
 		part_number,
 		first_block,
-		first_block_hash,
 		&mut chunker,
+		cipher.as_ref(),
 	)
 	.await?;
 
 	// Verify that checksums map
 	ensure_checksum_matches(
 		data_md5sum.as_slice(),
-		data_sha256sum,
+		checksums.sha256,
 		content_md5.as_deref(),
 		content_sha256,
 	)?;
+	ensure_checksum_value_matches(checksum, &checksums)?;
 
-	// Store part etag in version
+	// Store part etag and checksum in version
 	let data_md5sum_hex = hex::encode(data_md5sum);
 	let mut version = version;
 	version
 		.parts_etags
 		.put(part_number, data_md5sum_hex.clone());
+	if let Some(checksum) = checksum {
+		version.parts_checksums.put(part_number, checksum);
+	}
 	garage.version_table.insert(&version).await?;
 
-	let response = Response::builder()
+	let response = add_encryption_headers(encryption.as_ref(), Response::builder())
 		.header("ETag", format!("\"{}\"", data_md5sum_hex))
 		.body(Body::empty())
 		.unwrap();
@@ -593,6 +804,16 @@ pub async fn handle_complete_multipart_upload(
 	upload_id: &str,
 	content_sha256: Option<Hash>,
 ) -> Result<Response<Body>, Error> {
+	// Optional size of the final object, as expected by the client
+	let expected_size = req
+		.headers()
+		.get(X_AMZ_MP_OBJECT_SIZE)
+		.map(|x| x.to_str())
+		.transpose()?
+		.map(|x| x.trim().parse::<u64>())
+		.transpose()
+		.ok_or_bad_request("Invalid x-amz-mp-object-size header")?;
+
 	let body = hyper::body::to_bytes(req.into_body()).await?;
 
 	if let Some(content_sha256) = content_sha256 {
@@ -629,10 +850,12 @@ pub async fn handle_complete_multipart_upload(
 		return Err(Error::bad_request("No data was uploaded"));
 	}
 
-	let headers = match object_version.state {
+	let mut headers = match object_version.state {
 		ObjectVersionState::Uploading(headers) => headers,
 		_ => unreachable!(),
 	};
+	let lock = ObjectLockParams::take_from_headers(&mut headers.other)?;
+	let encryption = take_encryption_from_headers(&mut headers.other)?;
 
 	// Check that part numbers are an increasing sequence.
 	// (it doesn't need to start at 1 nor to be a continuous sequence,
@@ -675,6 +898,18 @@ pub async fn handle_complete_multipart_upload(
 		return Err(Error::InvalidPart);
 	}
 
+	// Check the checksums given for the parts against those sent when uploading them
+	for part in body_list_of_parts.iter() {
+		if let Some(expected) = &part.checksum {
+			if version.parts_checksums.get(&part.part_number) != Some(expected) {
+				return Err(Error::BadDigest(format!(
+					"The checksum given for part {} does not match the checksum of the uploaded part",
+					part.part_number
+				)));
+			}
+		}
+	}
+
 	// Check that all blocks belong to one of the parts
 	let block_parts = version
 		.blocks
@@ -705,6 +940,15 @@ pub async fn handle_complete_multipart_upload(
 	// Calculate total size of final object
 	let total_size = version.blocks.items().iter().map(|x| x.1.size).sum();
 
+	if let Some(expected_size) = expected_size {
+		if expected_size != total_size {
+			return Err(Error::bad_request(format!(
+				"Size of the completed object ({} bytes) does not match the value of the x-amz-mp-object-size header ({} bytes)",
+				total_size, expected_size
+			)));
+		}
+	}
+
 	if let Err(e) = check_quotas(&garage, bucket, &key, total_size).await {
 		object_version.state = ObjectVersionState::Aborted;
 		let final_object = Object::new(bucket.id, key.clone(), vec![object_version]);
@@ -719,13 +963,28 @@ pub async fn handle_complete_multipart_upload(
 			headers,
 			size: total_size,
 			etag: etag.clone(),
+			lock,
+			encryption,
 		},
 		version.blocks.items()[0].1.hash,
 	));
 
+	let version_uuid = object_version.uuid;
 	let final_object = Object::new(bucket.id, key.clone(), vec![object_version]);
 	garage.object_table.insert(&final_object).await?;
 
+	notify_object_event(
+		&garage,
+		bucket,
+		ObjectEvent {
+			name: "ObjectCreated:CompleteMultipartUpload",
+			key: &key,
+			version_uuid,
+			size: Some(total_size),
+			etag: Some(&etag),
+		},
+	);
+
 	// Send response saying ok we're done
 	let result = s3_xml::CompleteMultipartUploadResult {
 		xmlns: (),
@@ -736,7 +995,10 @@ pub async fn handle_complete_multipart_upload(
 	};
 	let xml = s3_xml::to_xml_with_header(&result)?;
 
-	Ok(Response::new(Body::from(xml.into_bytes())))
+	Ok(
+		add_encryption_headers(encryption.as_ref(), Response::builder())
+			.body(Body::from(xml.into_bytes()))?,
+	)
 }
 
 pub async fn handle_abort_multipart_upload(
@@ -769,6 +1031,25 @@ pub async fn handle_abort_multipart_upload(
 	Ok(Response::new(Body::from(vec![])))
 }
 
+/// If an object was uploaded without a meaningful content type, guess it
+/// from the extension of its key, or else from the first bytes of its data
+/// (if `first_block` is empty, only the extension is used)
+fn autodetect_content_type(headers: &mut ObjectVersionHeaders, key: &str, first_block: &[u8]) {
+	if headers.content_type != "blob" && headers.content_type != "application/octet-stream" {
+		return;
+	}
+	let detected = match mime_guess::from_path(key).first() {
+		Some(mime) => mime.to_string(),
+		// Empty objects, such as directory markers, have no content to look at
+		None if first_block.is_empty() => return,
+		None => tree_magic_mini::from_u8(first_block).to_string(),
+	};
+	// Content detection gives application/octet-stream when it finds nothing
+	if detected != "application/octet-stream" {
+		headers.content_type = detected;
+	}
+}
+
 fn get_mime_type(headers: &HeaderMap<HeaderValue>) -> Result<String, Error> {
 	Ok(headers
 		.get(hyper::header::CONTENT_TYPE)
@@ -792,6 +1073,18 @@ pub(crate) fn get_headers(headers: &HeaderMap<HeaderValue>) -> Result<ObjectVers
 	for h in standard_header.iter() {
 		if let Some(v) = headers.get(h) {
 			match v.to_str() {
+				// aws-chunked only describes how the request body is sent,
+				// it is not an encoding of the object's content
+				Ok(v_str) if *h == hyper::header::CONTENT_ENCODING => {
+					let encodings = v_str
+						.split(',')
+						.map(str::trim)
+						.filter(|x| !x.is_empty() && *x != "aws-chunked")
+						.collect::<Vec<_>>();
+					if !encodings.is_empty() {
+						other.insert(h.to_string(), encodings.join(", "));
+					}
+				}
 				Ok(v_str) => {
 					other.insert(h.to_string(), v_str.to_string());
 				}
@@ -832,10 +1125,21 @@ pub fn decode_upload_id(id: &str) -> Result<Uuid, Error> {
 	Ok(Uuid::from(uuid))
 }
 
+pub fn decode_version_id(id: &str) -> Result<Uuid, Error> {
+	let id_bin = hex::decode(id).map_err(|_| Error::bad_request("Invalid version ID"))?;
+	if id_bin.len() != 32 {
+		return Err(Error::bad_request("Invalid version ID"));
+	}
+	let mut uuid = [0u8; 32];
+	uuid.copy_from_slice(&id_bin[..]);
+	Ok(Uuid::from(uuid))
+}
+
 #[derive(Debug)]
 struct CompleteMultipartUploadPart {
 	etag: String,
 	part_number: u64,
+	checksum: Option<ChecksumValue>,
 }
 
 fn parse_complete_multipart_upload_body(
@@ -861,9 +1165,31 @@ fn parse_complete_multipart_upload_body(
 				.children()
 				.find(|e| e.has_tag_name("PartNumber"))?
 				.text()?;
+			let checksum = match (
+				item.children().find(|e| e.has_tag_name("ChecksumCRC32C")),
+				item.children().find(|e| e.has_tag_name("ChecksumSHA256")),
+			) {
+				(None, None) => None,
+				(Some(crc32c), None) => Some(ChecksumValue::Crc32c(
+					BASE64_STANDARD
+						.decode(crc32c.text()?)
+						.ok()?
+						.try_into()
+						.ok()?,
+				)),
+				(None, Some(sha256)) => Some(ChecksumValue::Sha256(
+					BASE64_STANDARD
+						.decode(sha256.text()?)
+						.ok()?
+						.try_into()
+						.ok()?,
+				)),
+				(Some(_), Some(_)) => return None,
+			};
 			parts.push(CompleteMultipartUploadPart {
 				etag: etag.trim_matches('"').to_string(),
 				part_number: part_number.parse().ok()?,
+				checksum,
 			});
 		} else {
 			return None;
@@ -872,3 +1198,56 @@ fn parse_complete_multipart_upload_body(
 
 	Some(parts)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0";
+
+	fn headers(content_type: &str) -> ObjectVersionHeaders {
+		ObjectVersionHeaders {
+			content_type: content_type.to_string(),
+			other: BTreeMap::new(),
+		}
+	}
+
+	fn detect(content_type: &str, key: &str, first_block: &[u8]) -> String {
+		let mut headers = headers(content_type);
+		autodetect_content_type(&mut headers, key, first_block);
+		headers.content_type
+	}
+
+	#[test]
+	fn test_autodetect_content_type_from_extension() {
+		assert_eq!(detect("blob", "index.html", b"hello"), "text/html");
+		assert_eq!(
+			detect("application/octet-stream", "dir/image.png", &[]),
+			"image/png"
+		);
+		assert_eq!(detect("blob", "style.css", PNG_HEADER), "text/css");
+	}
+
+	#[test]
+	fn test_autodetect_content_type_from_content() {
+		// Empty objects and unknown extensions stay as they are
+		assert_eq!(detect("blob", "dir/", &[]), "blob");
+		assert_eq!(detect("blob", "file.unknownext", &[]), "blob");
+
+		// Detection from the content needs the MIME-info database of the system
+		if tree_magic_mini::from_u8(PNG_HEADER) == "application/octet-stream" {
+			return;
+		}
+		assert_eq!(detect("blob", "image", PNG_HEADER), "image/png");
+		assert_eq!(
+			detect("application/octet-stream", "image", PNG_HEADER),
+			"image/png"
+		);
+	}
+
+	#[test]
+	fn test_autodetect_content_type_explicit() {
+		assert_eq!(detect("text/plain", "index.html", PNG_HEADER), "text/plain");
+		assert_eq!(detect("image/jpeg", "image", PNG_HEADER), "image/jpeg");
+	}
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_LENGTH};
+use opentelemetry::{global, metrics::Counter, KeyValue};
+
+use garage_model::key_table::Key;
+
+use crate::s3::error::*;
+
+/// Limits of the S3 requests made with each access key, enforced on this node
+/// with token buckets. The limits are read from the key on each request,
+/// so that changing them takes effect immediately.
+pub struct RateLimiter {
+	limiters: Mutex<HashMap<String, KeyLimiter>>,
+	throttled_counter: Counter<u64>,
+}
+
+#[derive(Default)]
+struct KeyLimiter {
+	requests: Option<TokenBucket>,
+	bandwidth: Option<TokenBucket>,
+}
+
+/// A token bucket that is refilled at a given rate, and can hold
+/// at most one second worth of tokens
+struct TokenBucket {
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl RateLimiter {
+	pub fn new() -> Self {
+		let meter = global::meter("garage/api");
+		Self {
+			limiters: Mutex::new(HashMap::new()),
+			throttled_counter: meter
+				.u64_counter("api.s3.throttled_request_counter")
+				.with_description(
+					"Number of S3 API calls refused because of the rate limits of their access key",
+				)
+				.init(),
+		}
+	}
+
+	/// Check that a new request made with this key, which sends `request_bytes`
+	/// bytes of data, is within the limits of the key.
+	/// Returns Error::SlowDown if it is not.
+	pub fn check_request(&self, key: &Key, request_bytes: u64) -> Result<(), Error> {
+		let (rps, bandwidth_bps) = match key_limits(key) {
+			Some(limits) => limits,
+			None => return Ok(()),
+		};
+		let now = Instant::now();
+
+		let mut limiters = self.limiters.lock().unwrap();
+		let limiter = limiters.entry(key.key_id.clone()).or_default();
+
+		let mut retry_after = 0f64;
+		if let Some(rps) = rps {
+			let bucket = limiter
+				.requests
+				.get_or_insert_with(|| TokenBucket::new(rps as f64, now));
+			bucket.refill(rps as f64, now);
+			if bucket.tokens < 1. {
+				retry_after = retry_after.max((1. - bucket.tokens) / rps as f64);
+			}
+		}
+		if let Some(bps) = bandwidth_bps {
+			let bucket = limiter
+				.bandwidth
+				.get_or_insert_with(|| TokenBucket::new(bps as f64, now));
+			bucket.refill(bps as f64, now);
+			// Bandwidth is allowed to go into debt, so that objects larger than
+			// the limit can be transferred: only refuse requests while it is paid back
+			if bucket.tokens < 0. {
+				retry_after = retry_after.max(-bucket.tokens / bps as f64);
+			}
+		}
+
+		if retry_after > 0. {
+			drop(limiters);
+			self.throttled_counter
+				.add(1, &[KeyValue::new("api_key", key.key_id.clone())]);
+			return Err(Error::SlowDown(retry_after.ceil() as u64));
+		}
+
+		if let Some(bucket) = &mut limiter.requests {
+			bucket.tokens -= 1.;
+		}
+		if let Some(bucket) = &mut limiter.bandwidth {
+			bucket.tokens -= request_bytes as f64;
+		}
+		Ok(())
+	}
+
+	/// Count bytes sent in the response to a request against the bandwidth limit of the key
+	pub fn consume_bandwidth(&self, key: &Key, bytes: u64) {
+		if !matches!(key_limits(key), Some((_, Some(_)))) {
+			return;
+		}
+		let mut limiters = self.limiters.lock().unwrap();
+		if let Some(bucket) = limiters
+			.get_mut(&key.key_id)
+			.and_then(|l| l.bandwidth.as_mut())
+		{
+			bucket.tokens -= bytes as f64;
+		}
+	}
+}
+
+/// Size of the body of a request or response, as announced in its Content-Length header
+pub fn content_length(headers: &HeaderMap<HeaderValue>) -> u64 {
+	headers
+		.get(CONTENT_LENGTH)
+		.and_then(|x| x.to_str().ok())
+		.and_then(|x| x.parse().ok())
+		.unwrap_or(0)
+}
+
+fn key_limits(key: &Key) -> Option<(Option<u32>, Option<u64>)> {
+	let params = key.params()?;
+	let rps = *params.rate_limit_rps.get();
+	let bandwidth_bps = *params.rate_limit_bandwidth_bps.get();
+	if rps.is_none() && bandwidth_bps.is_none() {
+		None
+	} else {
+		Some((rps, bandwidth_bps))
+	}
+}
+
+impl TokenBucket {
+	fn new(rate: f64, now: Instant) -> Self {
+		Self {
+			tokens: rate,
+			last_refill: now,
+		}
+	}
+
+	fn refill(&mut self, rate: f64, now: Instant) {
+		let elapsed = now
+			.saturating_duration_since(self.last_refill)
+			.as_secs_f64();
+		self.tokens = f64::min(self.tokens + elapsed * rate, rate);
+		self.last_refill = now;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_rate_limit_requests() {
+		let limiter = RateLimiter::new();
+		let mut key = Key::new("test");
+		assert!(limiter.check_request(&key, 0).is_ok());
+
+		key.params_mut().unwrap().rate_limit_rps.update(Some(2));
+		assert!(limiter.check_request(&key, 0).is_ok());
+		assert!(limiter.check_request(&key, 0).is_ok());
+		assert!(matches!(
+			limiter.check_request(&key, 0),
+			Err(Error::SlowDown(1))
+		));
+
+		key.params_mut().unwrap().rate_limit_rps.update(None);
+		assert!(limiter.check_request(&key, 0).is_ok());
+	}
+
+	#[test]
+	fn test_rate_limit_bandwidth() {
+		let limiter = RateLimiter::new();
+		let mut key = Key::new("test");
+		key.params_mut()
+			.unwrap()
+			.rate_limit_bandwidth_bps
+			.update(Some(1000));
+
+		// A request larger than the limit is accepted, but the next ones
+		// have to wait until the bandwidth has been paid back
+		assert!(limiter.check_request(&key, 2500).is_ok());
+		assert!(matches!(
+			limiter.check_request(&key, 0),
+			Err(Error::SlowDown(2))
+		));
+	}
+}
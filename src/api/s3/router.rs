@@ -130,6 +130,10 @@ pub enum Endpoint {
 		key: String,
 		version_id: Option<String>,
 	},
+	GetObjectAttributes {
+		key: String,
+		version_id: Option<String>,
+	},
 	GetObjectLegalHold {
 		key: String,
 		version_id: Option<String>,
@@ -360,6 +364,7 @@ impl Endpoint {
 				EMPTY if upload_id => ListParts (query::upload_id, opt_parse::max_parts, opt_parse::part_number_marker),
 				EMPTY => GetObject (query_opt::version_id, opt_parse::part_number),
 				ACL => GetObjectAcl (query_opt::version_id),
+				ATTRIBUTES => GetObjectAttributes (query_opt::version_id),
 				LEGAL_HOLD => GetObjectLegalHold (query_opt::version_id),
 				RETENTION => GetObjectRetention (query_opt::version_id),
 				TAGGING => GetObjectTagging (query_opt::version_id),
@@ -531,6 +536,7 @@ impl Endpoint {
 				DeleteObjectTagging,
 				GetObject,
 				GetObjectAcl,
+				GetObjectAttributes,
 				GetObjectLegalHold,
 				GetObjectRetention,
 				GetObjectTagging,
@@ -579,6 +585,7 @@ impl Endpoint {
 				GetBucketVersioning,
 				GetObject,
 				GetObjectAcl,
+				GetObjectAttributes,
 				GetObjectLegalHold,
 				GetObjectLockConfiguration,
 				GetObjectRetention,
@@ -610,6 +617,8 @@ impl Endpoint {
 				GetBucketCors,
 				PutBucketCors,
 				DeleteBucketCors,
+				PutBucketPolicy,
+				DeleteBucketPolicy,
 			]
 		};
 		if readonly {
@@ -628,6 +637,7 @@ generateQueryParameters! {
 		"accelerate" => ACCELERATE,
 		"acl" => ACL,
 		"analytics" => ANALYTICS,
+		"attributes" => ATTRIBUTES,
 		"cors" => CORS,
 		"delete" => DELETE,
 		"encryption" => ENCRYPTION,
@@ -851,7 +861,7 @@ mod tests {
 			DELETE "/?metrics&id=ExampleMetrics" => DeleteBucketMetricsConfiguration
 			DELETE "/?metrics&id=Id" => DeleteBucketMetricsConfiguration
 			DELETE "/?ownershipControls" => DeleteBucketOwnershipControls
-			DELETE "/?policy" => DeleteBucketPolicy
+			OWNER_DELETE "/?policy" => DeleteBucketPolicy
 			DELETE "/?replication" => DeleteBucketReplication
 			DELETE "/?tagging" => DeleteBucketTagging
 			OWNER_DELETE "/?website" => DeleteBucketWebsite
@@ -891,6 +901,8 @@ mod tests {
 			GET "/my-image.jpg?acl" => GetObjectAcl
 			GET "/my-image.jpg?versionId=3/L4kqtJlcpXroDVBH40Nr8X8gdRQBpUMLUo&acl" => GetObjectAcl
 			GET "/{Key+}?acl&versionId=VersionId" => GetObjectAcl
+			GET "/example-object?attributes" => GetObjectAttributes
+			GET "/{Key+}?attributes&versionId=VersionId" => GetObjectAttributes
 			GET "/{Key+}?legal-hold&versionId=VersionId" => GetObjectLegalHold
 			GET "/?object-lock" => GetObjectLockConfiguration
 			GET "/{Key+}?retention&versionId=VersionId" => GetObjectRetention
@@ -952,7 +964,7 @@ mod tests {
 			PUT "/?metrics&id=Id" => PutBucketMetricsConfiguration
 			PUT "/?notification" => PutBucketNotificationConfiguration
 			PUT "/?ownershipControls" => PutBucketOwnershipControls
-			PUT "/?policy" => PutBucketPolicy
+			OWNER_PUT "/?policy" => PutBucketPolicy
 			PUT "/?replication" => PutBucketReplication
 			PUT "/?requestPayment" => PutBucketRequestPayment
 			PUT "/?tagging" => PutBucketTagging
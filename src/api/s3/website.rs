@@ -27,7 +27,13 @@ pub async fn handle_get_website(bucket: &Bucket) -> Result<Response<Body>, Error
 				suffix: Value(website.index_document.to_string()),
 			}),
 			redirect_all_requests_to: None,
-			routing_rules: None,
+			routing_rules: (!website.routing_rules.is_empty()).then(|| RoutingRules {
+				rules: website
+					.routing_rules
+					.iter()
+					.map(RoutingRule::from_garage_routing_rule)
+					.collect(),
+			}),
 		};
 		let xml = to_xml_with_header(&wc)?;
 		Ok(Response::builder()
@@ -103,17 +109,17 @@ pub struct WebsiteConfiguration {
 	#[serde(rename = "RedirectAllRequestsTo")]
 	pub redirect_all_requests_to: Option<Target>,
 	#[serde(rename = "RoutingRules")]
-	pub routing_rules: Option<Vec<RoutingRule>>,
+	pub routing_rules: Option<RoutingRules>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct RoutingRule {
+pub struct RoutingRules {
 	#[serde(rename = "RoutingRule")]
-	pub inner: RoutingRuleInner,
+	pub rules: Vec<RoutingRule>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
-pub struct RoutingRuleInner {
+pub struct RoutingRule {
 	#[serde(rename = "Condition")]
 	pub condition: Option<Condition>,
 	#[serde(rename = "Redirect")]
@@ -183,8 +189,8 @@ impl WebsiteConfiguration {
 			rart.validate()?;
 		}
 		if let Some(ref rrs) = self.routing_rules {
-			for rr in rrs {
-				rr.inner.validate()?;
+			for rr in rrs.rules.iter() {
+				rr.validate()?;
 			}
 		}
 
@@ -196,10 +202,6 @@ impl WebsiteConfiguration {
 			Err(Error::NotImplemented(
 				"S3 website redirects are not currently implemented in Garage.".into(),
 			))
-		} else if self.routing_rules.map(|x| !x.is_empty()).unwrap_or(false) {
-			Err(Error::NotImplemented(
-				"S3 routing rules are not currently implemented in Garage.".into(),
-			))
 		} else {
 			Ok(WebsiteConfig {
 				index_document: self
@@ -207,6 +209,13 @@ impl WebsiteConfiguration {
 					.map(|x| x.suffix.0)
 					.unwrap_or_else(|| "index.html".to_string()),
 				error_document: self.error_document.map(|x| x.key.0),
+				routing_rules: self
+					.routing_rules
+					.map(|x| x.rules)
+					.unwrap_or_default()
+					.into_iter()
+					.map(RoutingRule::into_garage_routing_rule)
+					.collect(),
 			})
 		}
 	}
@@ -247,15 +256,67 @@ impl Target {
 	}
 }
 
-impl RoutingRuleInner {
+impl RoutingRule {
+	fn from_garage_routing_rule(rule: &WebsiteRoutingRule) -> Self {
+		let condition =
+			(rule.key_prefix.is_some() || rule.http_error_code.is_some()).then(|| Condition {
+				http_error_code: rule.http_error_code.map(|c| IntValue(c as i64)),
+				prefix: rule.key_prefix.as_deref().map(Value::from),
+			});
+		let redirect = &rule.redirect;
+		RoutingRule {
+			condition,
+			redirect: Redirect {
+				hostname: redirect.hostname.as_deref().map(Value::from),
+				protocol: redirect.protocol.as_deref().map(Value::from),
+				http_redirect_code: Some(IntValue(redirect.http_redirect_code as i64)),
+				replace_prefix: redirect.replace_key_prefix_with.as_deref().map(Value::from),
+				replace_full: redirect.replace_key_with.as_deref().map(Value::from),
+			},
+		}
+	}
+
 	pub fn validate(&self) -> Result<(), Error> {
 		let has_prefix = self
 			.condition
 			.as_ref()
 			.and_then(|c| c.prefix.as_ref())
 			.is_some();
+		if let Some(IntValue(code)) = self
+			.condition
+			.as_ref()
+			.and_then(|c| c.http_error_code.as_ref())
+		{
+			if !(400..600).contains(code) {
+				return Err(Error::bad_request(
+					"Bad XML: HttpErrorCodeReturnedEquals must be a 4xx or 5xx code",
+				));
+			}
+		}
 		self.redirect.validate(has_prefix)
 	}
+
+	fn into_garage_routing_rule(self) -> WebsiteRoutingRule {
+		let (key_prefix, http_error_code) = match self.condition {
+			Some(c) => (c.prefix.map(|x| x.0), c.http_error_code.map(|x| x.0 as u16)),
+			None => (None, None),
+		};
+		WebsiteRoutingRule {
+			key_prefix,
+			http_error_code,
+			redirect: WebsiteRedirect {
+				hostname: self.redirect.hostname.map(|x| x.0),
+				protocol: self.redirect.protocol.map(|x| x.0),
+				http_redirect_code: self
+					.redirect
+					.http_redirect_code
+					.map(|x| x.0 as u16)
+					.unwrap_or(301),
+				replace_key_prefix_with: self.redirect.replace_prefix.map(|x| x.0),
+				replace_key_with: self.redirect.replace_full.map(|x| x.0),
+			},
+		}
+	}
 }
 
 impl Redirect {
@@ -277,6 +338,11 @@ impl Redirect {
 				return Err(Error::bad_request("Bad XML: invalid protocol"));
 			}
 		}
+		if let Some(IntValue(code)) = self.http_redirect_code {
+			if ![301, 302, 303, 307, 308].contains(&code) {
+				return Err(Error::bad_request("Bad XML: invalid HttpRedirectCode"));
+			}
+		}
 		// TODO there are probably more invalide cases, but which ones?
 		Ok(())
 	}
@@ -316,6 +382,11 @@ mod tests {
             <ReplaceKeyWith>fullkey</ReplaceKeyWith>
          </Redirect>
       </RoutingRule>
+      <RoutingRule>
+         <Redirect>
+            <ReplaceKeyWith>fullkey</ReplaceKeyWith>
+         </Redirect>
+      </RoutingRule>
    </RoutingRules>
 </WebsiteConfiguration>"#;
 		let conf: WebsiteConfiguration = from_str(message).unwrap();
@@ -331,21 +402,33 @@ mod tests {
 				hostname: Value("garage.tld".to_owned()),
 				protocol: Some(Value("https".to_owned())),
 			}),
-			routing_rules: Some(vec![RoutingRule {
-				inner: RoutingRuleInner {
-					condition: Some(Condition {
-						http_error_code: Some(IntValue(404)),
-						prefix: Some(Value("prefix1".to_owned())),
-					}),
-					redirect: Redirect {
-						hostname: Some(Value("gara.ge".to_owned())),
-						protocol: Some(Value("http".to_owned())),
-						http_redirect_code: Some(IntValue(303)),
-						replace_prefix: Some(Value("prefix2".to_owned())),
-						replace_full: Some(Value("fullkey".to_owned())),
+			routing_rules: Some(RoutingRules {
+				rules: vec![
+					RoutingRule {
+						condition: Some(Condition {
+							http_error_code: Some(IntValue(404)),
+							prefix: Some(Value("prefix1".to_owned())),
+						}),
+						redirect: Redirect {
+							hostname: Some(Value("gara.ge".to_owned())),
+							protocol: Some(Value("http".to_owned())),
+							http_redirect_code: Some(IntValue(303)),
+							replace_prefix: Some(Value("prefix2".to_owned())),
+							replace_full: Some(Value("fullkey".to_owned())),
+						},
+					},
+					RoutingRule {
+						condition: None,
+						redirect: Redirect {
+							hostname: None,
+							protocol: None,
+							http_redirect_code: None,
+							replace_prefix: None,
+							replace_full: Some(Value("fullkey".to_owned())),
+						},
 					},
-				},
-			}]),
+				],
+			}),
 		};
 		assert_eq! {
 			ref_value,
@@ -68,9 +68,11 @@ pub struct Deleted {
 	#[serde(rename = "Key")]
 	pub key: Value,
 	#[serde(rename = "VersionId")]
-	pub version_id: Value,
+	pub version_id: Option<Value>,
+	#[serde(rename = "DeleteMarker")]
+	pub delete_marker: Option<Value>,
 	#[serde(rename = "DeleteMarkerVersionId")]
-	pub delete_marker_version_id: Value,
+	pub delete_marker_version_id: Option<Value>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -282,11 +284,99 @@ pub struct ListBucketResult {
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
-pub struct VersioningConfiguration {
+pub struct ListVersionItem {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "VersionId")]
+	pub version_id: Value,
+	#[serde(rename = "IsLatest")]
+	pub is_latest: Value,
+	#[serde(rename = "LastModified")]
+	pub last_modified: Value,
+	#[serde(rename = "ETag")]
+	pub etag: Value,
+	#[serde(rename = "Size")]
+	pub size: IntValue,
+	#[serde(rename = "StorageClass")]
+	pub storage_class: Value,
+	#[serde(rename = "Owner")]
+	pub owner: Owner,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct DeleteMarkerItem {
+	#[serde(rename = "Key")]
+	pub key: Value,
+	#[serde(rename = "VersionId")]
+	pub version_id: Value,
+	#[serde(rename = "IsLatest")]
+	pub is_latest: Value,
+	#[serde(rename = "LastModified")]
+	pub last_modified: Value,
+	#[serde(rename = "Owner")]
+	pub owner: Owner,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ListVersionsResult {
 	#[serde(serialize_with = "xmlns_tag")]
 	pub xmlns: (),
-	#[serde(rename = "Status")]
+	#[serde(rename = "Name")]
+	pub name: Value,
+	#[serde(rename = "Prefix")]
+	pub prefix: Value,
+	#[serde(rename = "KeyMarker")]
+	pub key_marker: Option<Value>,
+	#[serde(rename = "VersionIdMarker")]
+	pub version_id_marker: Option<Value>,
+	#[serde(rename = "NextKeyMarker")]
+	pub next_key_marker: Option<Value>,
+	#[serde(rename = "NextVersionIdMarker")]
+	pub next_version_id_marker: Option<Value>,
+	#[serde(rename = "MaxKeys")]
+	pub max_keys: IntValue,
+	#[serde(rename = "Delimiter")]
+	pub delimiter: Option<Value>,
+	#[serde(rename = "EncodingType")]
+	pub encoding_type: Option<Value>,
+	#[serde(rename = "IsTruncated")]
+	pub is_truncated: Value,
+	#[serde(rename = "Version")]
+	pub versions: Vec<ListVersionItem>,
+	#[serde(rename = "DeleteMarker")]
+	pub delete_markers: Vec<DeleteMarkerItem>,
+	#[serde(rename = "CommonPrefixes")]
+	pub common_prefixes: Vec<CommonPrefix>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VersioningConfiguration {
+	#[serde(serialize_with = "xmlns_tag", skip_deserializing)]
+	pub xmlns: (),
+	#[serde(rename = "Status", default)]
 	pub status: Option<Value>,
+	#[serde(rename = "MfaDelete", default, skip_serializing_if = "Option::is_none")]
+	pub mfa_delete: Option<Value>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct GetObjectAttributesResponse {
+	#[serde(serialize_with = "xmlns_tag")]
+	pub xmlns: (),
+	#[serde(rename = "ETag")]
+	pub etag: Option<Value>,
+	#[serde(rename = "ObjectParts")]
+	pub object_parts: Option<ObjectParts>,
+	#[serde(rename = "StorageClass")]
+	pub storage_class: Option<Value>,
+	#[serde(rename = "ObjectSize")]
+	pub object_size: Option<IntValue>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ObjectParts {
+	#[serde(rename = "TotalPartsCount")]
+	pub total_parts_count: IntValue,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -392,6 +482,7 @@ mod tests {
 		let get_bucket_versioning = VersioningConfiguration {
 			xmlns: (),
 			status: None,
+			mfa_delete: None,
 		};
 		assert_eq!(
 			to_xml_with_header(&get_bucket_versioning)?,
@@ -401,6 +492,7 @@ mod tests {
 		let get_bucket_versioning2 = VersioningConfiguration {
 			xmlns: (),
 			status: Some(Value("Suspended".to_string())),
+			mfa_delete: None,
 		};
 		assert_eq!(
 			to_xml_with_header(&get_bucket_versioning2)?,
@@ -418,13 +510,21 @@ mod tests {
 			deleted: vec![
 				Deleted {
 					key: Value("a/plop".to_string()),
-					version_id: Value("qsdfjklm".to_string()),
-					delete_marker_version_id: Value("wxcvbn".to_string()),
+					version_id: Some(Value("qsdfjklm".to_string())),
+					delete_marker: None,
+					delete_marker_version_id: Some(Value("wxcvbn".to_string())),
 				},
 				Deleted {
 					key: Value("b/plip".to_string()),
-					version_id: Value("1234".to_string()),
-					delete_marker_version_id: Value("4321".to_string()),
+					version_id: Some(Value("1234".to_string())),
+					delete_marker: Some(Value("true".to_string())),
+					delete_marker_version_id: Some(Value("1234".to_string())),
+				},
+				Deleted {
+					key: Value("c/plup".to_string()),
+					version_id: Some(Value("5678".to_string())),
+					delete_marker: None,
+					delete_marker_version_id: None,
 				},
 			],
 			errors: vec![
@@ -454,7 +554,12 @@ mod tests {
     <Deleted>\
         <Key>b/plip</Key>\
         <VersionId>1234</VersionId>\
-        <DeleteMarkerVersionId>4321</DeleteMarkerVersionId>\
+        <DeleteMarker>true</DeleteMarker>\
+        <DeleteMarkerVersionId>1234</DeleteMarkerVersionId>\
+    </Deleted>\
+    <Deleted>\
+        <Key>c/plup</Key>\
+        <VersionId>5678</VersionId>\
     </Deleted>\
     <Error>\
         <Code>NotFound</Code>\
@@ -841,4 +946,31 @@ mod tests {
 
 		Ok(())
 	}
+
+	#[test]
+	fn get_object_attributes() -> Result<(), ApiError> {
+		let result = GetObjectAttributesResponse {
+			xmlns: (),
+			etag: Some(Value("d41d8cd98f00b204e9800998ecf8427e-2".to_string())),
+			object_parts: Some(ObjectParts {
+				total_parts_count: IntValue(2),
+			}),
+			storage_class: None,
+			object_size: Some(IntValue(10485760)),
+		};
+
+		assert_eq!(
+			to_xml_with_header(&result)?,
+			"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+<GetObjectAttributesResponse xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+<ETag>d41d8cd98f00b204e9800998ecf8427e-2</ETag>\
+<ObjectParts>\
+<TotalPartsCount>2</TotalPartsCount>\
+</ObjectParts>\
+<ObjectSize>10485760</ObjectSize>\
+</GetObjectAttributesResponse>"
+		);
+
+		Ok(())
+	}
 }
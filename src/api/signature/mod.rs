@@ -6,6 +6,7 @@ use garage_util::data::{sha256sum, Hash};
 
 pub mod error;
 pub mod payload;
+pub mod presign;
 pub mod streaming;
 
 use error::*;
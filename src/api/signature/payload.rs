@@ -74,7 +74,9 @@ pub async fn check_payload_signature(
 	)
 	.await?;
 
-	let content_sha256 = if authorization.content_sha256 == "UNSIGNED-PAYLOAD" {
+	let content_sha256 = if authorization.content_sha256 == "UNSIGNED-PAYLOAD"
+		|| authorization.content_sha256 == "STREAMING-UNSIGNED-PAYLOAD-TRAILER"
+	{
 		None
 	} else if authorization.content_sha256 == "STREAMING-AWS4-HMAC-SHA256-PAYLOAD" {
 		let bytes = hex::decode(authorization.signature).ok_or_bad_request("Invalid signature")?;
@@ -59,6 +59,11 @@ pub fn parse_streaming_body(
 				)
 			}))
 		}
+		Some(header) if header == "STREAMING-UNSIGNED-PAYLOAD-TRAILER" => Ok(req.map(|body| {
+			Body::wrap_stream(
+				UnsignedPayloadStream::new(body.map_err(Error::from)).map_err(Error::from),
+			)
+		})),
 		_ => Ok(req),
 	}
 }
@@ -327,11 +332,121 @@ where
 	}
 }
 
+/// Stream of the data of a body sent with `STREAMING-UNSIGNED-PAYLOAD-TRAILER`.
+/// Chunks are framed as in signed payloads, but without chunk signatures,
+/// and the last one is followed by trailing headers, which are ignored.
+#[pin_project::pin_project]
+pub struct UnsignedPayloadStream<S>
+where
+	S: Stream<Item = Result<Bytes, Error>>,
+{
+	#[pin]
+	stream: S,
+	buf: bytes::BytesMut,
+	done: bool,
+}
+
+impl<S> UnsignedPayloadStream<S>
+where
+	S: Stream<Item = Result<Bytes, Error>>,
+{
+	pub fn new(stream: S) -> Self {
+		Self {
+			stream,
+			buf: bytes::BytesMut::new(),
+			done: false,
+		}
+	}
+
+	fn parse_next(input: &[u8]) -> nom::IResult<&[u8], Bytes, SignedPayloadStreamError> {
+		use nom::bytes::streaming::{tag, take, take_until};
+		use nom::number::streaming::hex_u32;
+
+		macro_rules! try_parse {
+			($expr:expr) => {
+				$expr.map_err(nom::Err::convert)?
+			};
+		}
+
+		let (input, size) = try_parse!(hex_u32::<nom::error::Error<_>>(input));
+		// Skip chunk extensions, if any
+		let (input, _) = try_parse!(take_until::<_, _, nom::error::Error<_>>("\r\n")(input));
+		let (input, _) = try_parse!(tag::<_, _, nom::error::Error<_>>("\r\n")(input));
+
+		// 0-sized chunk is the last
+		if size == 0 {
+			return Ok((input, Bytes::new()));
+		}
+
+		let (input, data) = try_parse!(take::<_, _, nom::error::Error<_>>(size)(input));
+		let (input, _) = try_parse!(tag::<_, _, nom::error::Error<_>>("\r\n")(input));
+
+		Ok((input, Bytes::from(data.to_vec())))
+	}
+}
+
+impl<S> Stream for UnsignedPayloadStream<S>
+where
+	S: Stream<Item = Result<Bytes, Error>> + Unpin,
+{
+	type Item = Result<Bytes, SignedPayloadStreamError>;
+
+	fn poll_next(
+		self: Pin<&mut Self>,
+		cx: &mut task::Context<'_>,
+	) -> task::Poll<Option<Self::Item>> {
+		use std::task::Poll;
+
+		let mut this = self.project();
+
+		if *this.done {
+			return Poll::Ready(None);
+		}
+
+		loop {
+			let (input, data) = match Self::parse_next(this.buf) {
+				Ok(res) => res,
+				Err(nom::Err::Incomplete(_)) => {
+					match futures::ready!(this.stream.as_mut().poll_next(cx)) {
+						Some(Ok(bytes)) => {
+							this.buf.extend(bytes);
+							continue;
+						}
+						Some(Err(e)) => {
+							return Poll::Ready(Some(Err(SignedPayloadStreamError::Stream(e))))
+						}
+						None => {
+							return Poll::Ready(Some(Err(SignedPayloadStreamError::message(
+								"Unexpected EOF",
+							))));
+						}
+					}
+				}
+				Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+					return Poll::Ready(Some(Err(e)))
+				}
+			};
+
+			if data.is_empty() {
+				*this.done = true;
+				return Poll::Ready(None);
+			}
+
+			*this.buf = input.into();
+			return Poll::Ready(Some(Ok(data)));
+		}
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.stream.size_hint()
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use futures::prelude::*;
 
-	use super::{SignedPayloadStream, SignedPayloadStreamError};
+	use super::{SignedPayloadStream, SignedPayloadStreamError, UnsignedPayloadStream};
 
 	#[tokio::test]
 	async fn test_interrupted_signed_payload_stream() {
@@ -365,4 +480,25 @@ mod tests {
 			),
 		}
 	}
+
+	#[tokio::test]
+	async fn test_unsigned_payload_stream() {
+		let body: &[&[u8]] = &[
+			b"5\r\nhel",
+			b"lo\r\n6;ext=1\r\n world\r\n",
+			b"0\r\nx-amz-checksum-crc32:DUoRhQ==\r\n\r\n",
+		];
+		let body = futures::stream::iter(body.iter().map(|block| Ok(block.as_ref().into())));
+
+		let data: Vec<_> = UnsignedPayloadStream::new(body)
+			.try_collect()
+			.await
+			.unwrap();
+		assert_eq!(data.concat(), b"hello world");
+
+		let body: &[&[u8]] = &[b"5\r\nhel"];
+		let body = futures::stream::iter(body.iter().map(|block| Ok(block.as_ref().into())));
+		let data: Result<Vec<_>, _> = UnsignedPayloadStream::new(body).try_collect().await;
+		assert!(data.is_err());
+	}
 }
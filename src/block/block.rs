@@ -1,41 +1,72 @@
+use std::io::{Read, Write};
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, Nonce};
 use bytes::Bytes;
+use lz4_flex::frame::{FrameDecoder as Lz4Decoder, FrameEncoder as Lz4Encoder, FrameInfo};
+use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use zstd::stream::{decode_all as zstd_decode, Encoder};
 
+use garage_util::config::CompressionAlgorithm;
 use garage_util::data::*;
 use garage_util::error::*;
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
 pub enum DataBlockHeader {
 	Plain,
 	Compressed,
+	Lz4,
+}
+
+impl DataBlockHeader {
+	/// Extension of the file in which a block stored in this format is written
+	pub fn extension(&self) -> Option<&'static str> {
+		match self {
+			DataBlockHeader::Plain => None,
+			DataBlockHeader::Compressed => Some("zst"),
+			DataBlockHeader::Lz4 => Some("lz4"),
+		}
+	}
 }
 
 /// A possibly compressed block of data
+#[derive(Clone)]
 pub enum DataBlock {
 	/// Uncompressed data
 	Plain(Bytes),
 	/// Data compressed with zstd
 	Compressed(Bytes),
+	/// Data compressed with lz4, in the lz4 frame format
+	Lz4(Bytes),
 }
 
 impl DataBlock {
 	/// Query whether this block is compressed
 	pub fn is_compressed(&self) -> bool {
-		matches!(self, DataBlock::Compressed(_))
+		!matches!(self, DataBlock::Plain(_))
+	}
+
+	/// Get the format in which this block is stored
+	pub fn header(&self) -> DataBlockHeader {
+		match self {
+			DataBlock::Plain(_) => DataBlockHeader::Plain,
+			DataBlock::Compressed(_) => DataBlockHeader::Compressed,
+			DataBlock::Lz4(_) => DataBlockHeader::Lz4,
+		}
 	}
 
 	/// Get the inner, possibly compressed buffer. You should probably use [`DataBlock::verify_get`]
 	/// instead
 	pub fn inner_buffer(&self) -> &[u8] {
 		use DataBlock::*;
-		let (Plain(ref res) | Compressed(ref res)) = self;
+		let (Plain(ref res) | Compressed(ref res) | Lz4(ref res)) = self;
 		res
 	}
 
 	/// Get the buffer, possibly decompressing it, and verify it's integrity.
-	/// For Plain block, data is compared to hash, for Compressed block, zstd checksumming system
-	/// is used instead.
+	/// For Plain block, data is compared to hash, for compressed blocks, the checksumming
+	/// system of zstd or of the lz4 frame format is used instead.
 	pub fn verify_get(self, hash: Hash) -> Result<Bytes, Error> {
 		match self {
 			DataBlock::Plain(data) => {
@@ -48,6 +79,13 @@ impl DataBlock {
 			DataBlock::Compressed(data) => zstd_decode(&data[..])
 				.map_err(|_| Error::CorruptData(hash))
 				.map(Bytes::from),
+			DataBlock::Lz4(data) => {
+				let mut res = vec![];
+				Lz4Decoder::new(&data[..])
+					.read_to_end(&mut res)
+					.map_err(|_| Error::CorruptData(hash))?;
+				Ok(res.into())
+			}
 		}
 	}
 
@@ -64,15 +102,28 @@ impl DataBlock {
 			}
 			DataBlock::Compressed(data) => zstd::stream::copy_decode(&data[..], std::io::sink())
 				.map_err(|_| Error::CorruptData(hash)),
+			DataBlock::Lz4(data) => {
+				std::io::copy(&mut Lz4Decoder::new(&data[..]), &mut std::io::sink())
+					.map(|_| ())
+					.map_err(|_| Error::CorruptData(hash))
+			}
 		}
 	}
 
-	pub async fn from_buffer(data: Bytes, level: Option<i32>) -> DataBlock {
+	pub async fn from_buffer(data: Bytes, algo: CompressionAlgorithm) -> DataBlock {
 		tokio::task::spawn_blocking(move || {
-			if let Some(level) = level {
-				if let Ok(data) = zstd_encode(&data[..], level) {
-					return DataBlock::Compressed(data.into());
+			match algo {
+				CompressionAlgorithm::Zstd(level) => {
+					if let Ok(data) = zstd_encode(&data[..], level) {
+						return DataBlock::Compressed(data.into());
+					}
+				}
+				CompressionAlgorithm::Lz4 => {
+					if let Ok(data) = lz4_encode(&data[..]) {
+						return DataBlock::Lz4(data.into());
+					}
 				}
+				CompressionAlgorithm::None => (),
 			}
 			DataBlock::Plain(data)
 		})
@@ -84,6 +135,7 @@ impl DataBlock {
 		match self {
 			DataBlock::Plain(data) => (DataBlockHeader::Plain, data),
 			DataBlock::Compressed(data) => (DataBlockHeader::Compressed, data),
+			DataBlock::Lz4(data) => (DataBlockHeader::Lz4, data),
 		}
 	}
 
@@ -91,6 +143,7 @@ impl DataBlock {
 		match h {
 			DataBlockHeader::Plain => DataBlock::Plain(bytes),
 			DataBlockHeader::Compressed => DataBlock::Compressed(bytes),
+			DataBlockHeader::Lz4 => DataBlock::Lz4(bytes),
 		}
 	}
 }
@@ -103,3 +156,32 @@ fn zstd_encode<R: std::io::Read>(mut source: R, level: i32) -> std::io::Result<V
 	encoder.finish()?;
 	Ok(result)
 }
+
+fn lz4_encode(data: &[u8]) -> std::io::Result<Vec<u8>> {
+	let frame_info = FrameInfo::new().content_checksum(true);
+	let mut encoder = Lz4Encoder::with_frame_info(frame_info, Vec::<u8>::new());
+	encoder.write_all(data)?;
+	Ok(encoder.finish()?)
+}
+
+/// Size of the random nonce prepended to the content of encrypted block files
+const ENCRYPTION_NONCE_LEN: usize = 12;
+
+/// Encrypt the content of a block file, the nonce being prepended to the ciphertext
+pub(crate) fn encrypt_block_file(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>, Error> {
+	let nonce = thread_rng().gen::<[u8; ENCRYPTION_NONCE_LEN]>();
+	let ciphertext = cipher
+		.encrypt(Nonce::from_slice(&nonce), data)
+		.map_err(|_| Error::Message("Unable to encrypt block".into()))?;
+	Ok([&nonce[..], &ciphertext[..]].concat())
+}
+
+/// Decrypt the content of a block file written by [`encrypt_block_file`].
+/// Returns None if the file is not valid.
+pub(crate) fn decrypt_block_file(cipher: &Aes256Gcm, data: &[u8]) -> Option<Vec<u8>> {
+	if data.len() < ENCRYPTION_NONCE_LEN {
+		return None;
+	}
+	let (nonce, ciphertext) = data.split_at(ENCRYPTION_NONCE_LEN);
+	cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()
+}
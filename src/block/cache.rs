@@ -0,0 +1,66 @@
+use std::sync::Mutex;
+
+use lru::LruCache;
+
+use garage_util::data::*;
+
+use crate::block::*;
+
+/// In-memory cache of recently read blocks, evicting the least recently
+/// used ones when the total size of cached data exceeds a given size
+pub(crate) struct BlockCache {
+	max_size: usize,
+	inner: Mutex<BlockCacheInner>,
+}
+
+struct BlockCacheInner {
+	lru: LruCache<Hash, DataBlock>,
+	size: usize,
+}
+
+impl BlockCache {
+	pub(crate) fn new(max_size: usize) -> Self {
+		Self {
+			max_size,
+			inner: Mutex::new(BlockCacheInner {
+				lru: LruCache::unbounded(),
+				size: 0,
+			}),
+		}
+	}
+
+	/// Get a block from the cache, marking it as recently used
+	pub(crate) fn get(&self, hash: &Hash) -> Option<DataBlock> {
+		self.inner.lock().unwrap().lru.get(hash).cloned()
+	}
+
+	/// Add a block to the cache, evicting other blocks if necessary.
+	/// Blocks bigger than the whole cache are not cached.
+	pub(crate) fn insert(&self, hash: Hash, block: &DataBlock) {
+		let block_size = block.inner_buffer().len();
+		if block_size > self.max_size {
+			return;
+		}
+
+		let mut inner = self.inner.lock().unwrap();
+		if let Some(old) = inner.lru.put(hash, block.clone()) {
+			inner.size -= old.inner_buffer().len();
+		}
+		inner.size += block_size;
+
+		while inner.size > self.max_size {
+			match inner.lru.pop_lru() {
+				Some((_, evicted)) => inner.size -= evicted.inner_buffer().len(),
+				None => break,
+			}
+		}
+	}
+
+	/// Remove a block from the cache
+	pub(crate) fn invalidate(&self, hash: &Hash) {
+		let mut inner = self.inner.lock().unwrap();
+		if let Some(old) = inner.lru.pop(hash) {
+			inner.size -= old.inner_buffer().len();
+		}
+	}
+}
@@ -6,5 +6,6 @@ pub mod repair;
 pub mod resync;
 
 mod block;
+mod cache;
 mod metrics;
 mod rc;
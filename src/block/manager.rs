@@ -1,9 +1,10 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
-use arc_swap::ArcSwapOption;
+use aes_gcm::{Aes256Gcm, KeyInit};
+use arc_swap::{ArcSwap, ArcSwapOption};
 use async_trait::async_trait;
 use bytes::Bytes;
 use rand::prelude::*;
@@ -13,18 +14,20 @@ use futures::Stream;
 use futures_util::stream::StreamExt;
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::{mpsc, Mutex, MutexGuard};
+use tokio::sync::{mpsc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use opentelemetry::{
 	trace::{FutureExt as OtelFutureExt, TraceContextExt, Tracer},
 	Context,
 };
 
+use garage_rpc::drain::RunningRpcHandler;
 use garage_rpc::rpc_helper::netapp::stream::{stream_asyncread, ByteStream};
 
 use garage_db as db;
 
 use garage_util::background::{vars, BackgroundRunner};
+use garage_util::config::CompressionAlgorithm;
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::metrics::RecordDuration;
@@ -38,6 +41,7 @@ use garage_rpc::*;
 use garage_table::replication::{TableReplication, TableShardedReplication};
 
 use crate::block::*;
+use crate::cache::*;
 use crate::metrics::*;
 use crate::rc::*;
 use crate::repair::*;
@@ -46,6 +50,16 @@ use crate::resync::*;
 /// Size under which data will be stored inlined in database instead of as files
 pub const INLINE_THRESHOLD: usize = 3072;
 
+/// Formats in which a block can be stored on disk, compressed ones first
+const BLOCK_FORMATS: [DataBlockHeader; 3] = [
+	DataBlockHeader::Compressed,
+	DataBlockHeader::Lz4,
+	DataBlockHeader::Plain,
+];
+
+/// Extension added to the name of encrypted block files
+const ENCRYPTED_EXTENSION: &str = "enc";
+
 // The delay between the moment when the reference counter
 // drops to zero, and the moment where we allow ourselves
 // to delete the block locally.
@@ -80,9 +94,17 @@ pub struct BlockManager {
 	/// Directory in which block are stored
 	pub data_dir: PathBuf,
 
-	compression_level: Option<i32>,
+	/// Compression of the blocks written by this node,
+	/// which can be changed while the node is running
+	compression_algorithm: Arc<ArcSwap<CompressionAlgorithm>>,
+
+	/// In-memory cache of recently read blocks, if enabled
+	block_cache: Option<BlockCache>,
 
-	mutation_lock: [Mutex<BlockManagerLocked>; 256],
+	/// Cipher used to encrypt block files, if encryption at rest is enabled
+	block_cipher: Option<Aes256Gcm>,
+
+	mutation_lock: [RwLock<BlockManagerLocked>; 256],
 
 	pub(crate) rc: BlockRc,
 	pub resync: BlockResyncManager,
@@ -93,6 +115,8 @@ pub struct BlockManager {
 	pub(crate) metrics: BlockManagerMetrics,
 
 	pub scrub_persister: PersisterShared<ScrubWorkerPersisted>,
+	/// Number of tasks scrubbing the data directory in parallel
+	scrub_worker_count: usize,
 	tx_scrub_command: ArcSwapOption<mpsc::Sender<ScrubWorkerCommand>>,
 }
 
@@ -105,16 +129,32 @@ pub struct BlockResyncErrorInfo {
 	pub next_try: u64,
 }
 
+/// Information about a block file stored in the data directory of this node
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LocalBlockInfo {
+	pub hash: Hash,
+	/// Size of the block file on disk, in bytes
+	pub size: u64,
+	pub compressed: bool,
+	pub refcount: u64,
+}
+
 // This custom struct contains functions that must only be ran
 // when the lock is held. We ensure that it is the case by storing
-// it INSIDE a Mutex.
+// it INSIDE a RwLock. Functions that only inspect the state of a block
+// take &self and can run under a shared lock, functions that modify
+// the files of a block take &mut self and require the exclusive lock.
 struct BlockManagerLocked();
 
 impl BlockManager {
+	#[allow(clippy::too_many_arguments)]
 	pub fn new(
 		db: &db::Db,
 		data_dir: PathBuf,
-		compression_level: Option<i32>,
+		compression_algorithm: CompressionAlgorithm,
+		block_cache_size: usize,
+		block_encryption_key: Option<[u8; 32]>,
+		scrub_worker_count: usize,
 		replication: TableShardedReplication,
 		system: Arc<System>,
 	) -> Arc<Self> {
@@ -129,10 +169,13 @@ impl BlockManager {
 			.netapp
 			.endpoint("garage_block/manager.rs/Rpc".to_string());
 
+		let compression_algorithm = Arc::new(ArcSwap::from_pointee(compression_algorithm));
+
 		let metrics = BlockManagerMetrics::new(
-			compression_level,
+			compression_algorithm.clone(),
 			rc.rc.clone(),
 			resync.queue.clone(),
+			resync.priority_queue.clone(),
 			resync.errors.clone(),
 		);
 
@@ -141,14 +184,19 @@ impl BlockManager {
 		let block_manager = Arc::new(Self {
 			replication,
 			data_dir,
-			compression_level,
-			mutation_lock: [(); 256].map(|_| Mutex::new(BlockManagerLocked())),
+			compression_algorithm,
+			block_cache: Some(block_cache_size)
+				.filter(|size| *size > 0)
+				.map(BlockCache::new),
+			block_cipher: block_encryption_key.map(|key| Aes256Gcm::new(&key.into())),
+			mutation_lock: [(); 256].map(|_| RwLock::new(BlockManagerLocked())),
 			rc,
 			resync,
 			system,
 			endpoint,
 			metrics,
 			scrub_persister,
+			scrub_worker_count: scrub_worker_count.max(1),
 			tx_scrub_command: ArcSwapOption::new(None),
 		});
 		block_manager.endpoint.set_handler(block_manager.clone());
@@ -171,12 +219,29 @@ impl BlockManager {
 			self.clone(),
 			scrub_rx,
 			self.scrub_persister.clone(),
+			self.scrub_worker_count,
 		));
 	}
 
+	/// Compression of the blocks written by this node
+	pub fn compression_algorithm(&self) -> CompressionAlgorithm {
+		**self.compression_algorithm.load()
+	}
+
 	pub fn register_bg_vars(&self, vars: &mut vars::BgVars) {
 		self.resync.register_bg_vars(vars);
 
+		let get_compression = self.compression_algorithm.clone();
+		let set_compression = self.compression_algorithm.clone();
+		vars.register_rw_fn(
+			"compression-level",
+			move || **get_compression.load(),
+			move |compression_algorithm| {
+				set_compression.store(Arc::new(compression_algorithm));
+				Ok(())
+			},
+		);
+
 		vars.register_rw(
 			&self.scrub_persister,
 			"scrub-tranquility",
@@ -194,6 +259,18 @@ impl BlockManager {
 		});
 	}
 
+	/// Time to wait for the first node of `nodes` to return a block before
+	/// trying the next one: preferred nodes are only given a short time to
+	/// answer if a node that is not preferred can be tried afterwards
+	fn node_read_timeout(&self, nodes: &[Uuid]) -> Duration {
+		let rpc = &self.system.rpc;
+		if rpc.is_preferred(&nodes[0]) && nodes[1..].iter().any(|n| !rpc.is_preferred(n)) {
+			rpc.preferred_nodes_timeout()
+		} else {
+			rpc.rpc_timeout()
+		}
+	}
+
 	/// Ask nodes that might have a (possibly compressed) block for it
 	/// Return it as a stream with a header
 	async fn rpc_get_raw_block_streaming(
@@ -204,8 +281,9 @@ impl BlockManager {
 		let who = self.replication.read_nodes(hash);
 		let who = self.system.rpc.request_order(&who);
 
-		for node in who.iter() {
+		for (i, node) in who.iter().enumerate() {
 			let node_id = NodeID::from(*node);
+			let timeout = self.node_read_timeout(&who[i..]);
 			let rpc = self.endpoint.call_streaming(
 				&node_id,
 				BlockRpc::GetBlock(*hash, order_tag),
@@ -229,7 +307,7 @@ impl BlockManager {
 					};
 					return Ok((header, stream));
 				}
-				_ = tokio::time::sleep(self.system.rpc.rpc_timeout()) => {
+				_ = tokio::time::sleep(timeout) => {
 					debug!("Node {:?} didn't return block in time, trying next.", node);
 				}
 			};
@@ -251,8 +329,9 @@ impl BlockManager {
 		let who = self.replication.read_nodes(hash);
 		let who = self.system.rpc.request_order(&who);
 
-		for node in who.iter() {
+		for (i, node) in who.iter().enumerate() {
 			let node_id = NodeID::from(*node);
+			let timeout = self.node_read_timeout(&who[i..]);
 			let rpc = self.endpoint.call_streaming(
 				&node_id,
 				BlockRpc::GetBlock(*hash, order_tag),
@@ -281,7 +360,7 @@ impl BlockManager {
 						}
 					}
 				}
-				_ = tokio::time::sleep(self.system.rpc.rpc_timeout()) => {
+				_ = tokio::time::sleep(timeout) => {
 					debug!("Node {:?} didn't return block in time, trying next.", node);
 				}
 			};
@@ -305,6 +384,11 @@ impl BlockManager {
 		Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static>>,
 		Error,
 	> {
+		if let Some(block) = self.get_cached_block(hash) {
+			let data = block.verify_get(*hash)?;
+			return Ok(Box::pin(futures::stream::once(async move { Ok(data) })));
+		}
+
 		let (header, stream) = self.rpc_get_raw_block_streaming(hash, order_tag).await?;
 		match header {
 			DataBlockHeader::Plain => Ok(stream),
@@ -315,6 +399,13 @@ impl BlockManager {
 				let reader = async_compression::tokio::bufread::ZstdDecoder::new(reader);
 				Ok(Box::pin(tokio_util::io::ReaderStream::new(reader)))
 			}
+			DataBlockHeader::Lz4 => {
+				// No streaming lz4 decoder is available, but blocks are small
+				// and lz4 decompression is fast: do it all at once
+				let bytes = read_stream_to_end(stream).await?;
+				let data = DataBlock::Lz4(bytes).verify_get(*hash)?;
+				Ok(Box::pin(futures::stream::once(async move { Ok(data) })))
+			}
 		}
 	}
 
@@ -324,6 +415,10 @@ impl BlockManager {
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
 	) -> Result<Bytes, Error> {
+		if let Some(block) = self.get_cached_block(hash) {
+			return block.verify_get(*hash);
+		}
+
 		self.rpc_get_raw_block(hash, order_tag)
 			.await?
 			.verify_get(*hash)
@@ -333,7 +428,7 @@ impl BlockManager {
 	pub async fn rpc_put_block(&self, hash: Hash, data: Bytes) -> Result<(), Error> {
 		let who = self.replication.write_nodes(&hash);
 
-		let (header, bytes) = DataBlock::from_buffer(data, self.compression_level)
+		let (header, bytes) = DataBlock::from_buffer(data, self.compression_algorithm())
 			.await
 			.into_parts();
 		let put_block_rpc =
@@ -346,7 +441,7 @@ impl BlockManager {
 				&who[..],
 				put_block_rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL | PRIO_SECONDARY)
-					.with_quorum(self.replication.write_quorum()),
+					.with_quorum(self.replication.write_quorum(&hash)),
 			)
 			.await?;
 
@@ -363,6 +458,14 @@ impl BlockManager {
 		Ok(self.rc.rc.fast_len()?)
 	}
 
+	/// Iterate over the refcount table, giving for each block hash its number
+	/// of references, or None if the entry is invalid
+	pub fn rc_counts(
+		&self,
+	) -> Result<impl Iterator<Item = Result<BlockRcCount, Error>> + '_, Error> {
+		self.rc.iter_counts()
+	}
+
 	/// Send command to start/stop/manager scrub worker
 	pub async fn send_scrub_command(&self, cmd: ScrubWorkerCommand) -> Result<(), Error> {
 		let tx = self.tx_scrub_command.load();
@@ -396,6 +499,67 @@ impl BlockManager {
 		Ok(blocks)
 	}
 
+	/// List block files stored on this node, in increasing order of hash.
+	/// Only blocks whose hash comes strictly after `after`, and whose
+	/// hexadecimal representation starts with `prefix`, are returned,
+	/// up to `limit` entries.
+	pub async fn list_local_blocks(
+		&self,
+		prefix: &str,
+		after: Option<&Hash>,
+		limit: usize,
+	) -> Result<Vec<LocalBlockInfo>, Error> {
+		let prefix = prefix.to_lowercase();
+		let after_hex = after.map(hex::encode);
+		// Part of the prefix and of the start position that apply
+		// to the directory at the given nesting level
+		let prefix_part = |level: usize| prefix.get(2 * level..).unwrap_or("");
+		let after_part = |level: usize| after_hex.as_ref().map(|a| &a[2 * level..2 * level + 2]);
+
+		let mut ret = vec![];
+		for (d1, d1_path) in sorted_dir_entries(&self.data_dir).await? {
+			if !dir_matches(&d1, prefix_part(0), after_part(0)) {
+				continue;
+			}
+			for (d2, d2_path) in sorted_dir_entries(&d1_path).await? {
+				if !dir_matches(
+					&d2,
+					prefix_part(1),
+					after_part(1).filter(|_| Some(&d1[..]) == after_part(0)),
+				) {
+					continue;
+				}
+				for (name, path) in sorted_dir_entries(&d2_path).await? {
+					let name = name.strip_suffix(".enc").unwrap_or(&name);
+					let (name, compressed) = match name
+						.strip_suffix(".zst")
+						.or_else(|| name.strip_suffix(".lz4"))
+					{
+						Some(n) => (n, true),
+						None => (name, false),
+					};
+					let hash = match hex::decode(name).ok().and_then(|h| Hash::try_from(&h)) {
+						Some(h) => h,
+						None => continue,
+					};
+					if !name.starts_with(&prefix) || after.map(|a| hash <= *a).unwrap_or(false) {
+						continue;
+					}
+					ret.push(LocalBlockInfo {
+						hash,
+						size: fs::metadata(&path).await?.len(),
+						compressed,
+						refcount: self.get_block_rc(&hash)?,
+					});
+					if ret.len() >= limit {
+						return Ok(ret);
+					}
+				}
+			}
+		}
+		Ok(ret)
+	}
+
 	//// ----- Managing the reference counter ----
 
 	/// Increment the number of time a block is used, putting it to resynchronization if it is
@@ -425,13 +589,17 @@ impl BlockManager {
 		Ok(())
 	}
 
-	/// Decrement the number of time a block is used
+	/// Decrement the number of time a block is used.
+	/// If it is no longer used, it is deleted after `gc_delay`,
+	/// or after the default delay of 10 minutes if not set.
 	pub fn block_decref(
 		self: &Arc<Self>,
 		tx: &mut db::Transaction,
 		hash: Hash,
+		gc_delay: Option<Duration>,
 	) -> db::TxOpResult<()> {
-		if self.rc.block_decref(tx, &hash)? {
+		let gc_delay = gc_delay.unwrap_or(BLOCK_GC_DELAY);
+		if self.rc.block_decref(tx, &hash, gc_delay)? {
 			// When the RC is decremented, it might drop to zero,
 			// indicating that we don't need the block.
 			// There is a delay before we garbage collect it;
@@ -441,7 +609,7 @@ impl BlockManager {
 			tokio::spawn(async move {
 				if let Err(e) = this
 					.resync
-					.put_to_resync(&hash, BLOCK_GC_DELAY + Duration::from_secs(10))
+					.put_to_resync(&hash, gc_delay + Duration::from_secs(10))
 				{
 					error!("Block {:?} could not be put in resync queue: {}.", hash, e);
 				}
@@ -520,48 +688,103 @@ impl BlockManager {
 	}
 
 	async fn read_block_internal(&self, hash: &Hash) -> Result<DataBlock, Error> {
-		let mut path = self.block_path(hash);
-		let compressed = match self.is_block_compressed(hash).await {
-			Ok(c) => c,
+		if let Some(block) = self.get_cached_block(hash) {
+			return Ok(block);
+		}
+
+		// Hold a shared lock so that the block file is not replaced or deleted
+		// between the moment we locate it and the moment we open it. Once the
+		// file is open, it can be read without the lock: the open file is not
+		// affected if it is renamed or deleted in the meantime.
+		let lock = self.lock_read(hash).await;
+
+		let format = match self.block_file_format(hash).await {
+			Ok(f) => f,
 			Err(e) => {
 				// Not found but maybe we should have had it ??
 				self.resync
-					.put_to_resync(hash, 2 * self.system.rpc.rpc_timeout())?;
-				return Err(Into::into(e));
+					.put_to_resync_priority(hash, 2 * self.system.rpc.rpc_timeout())?;
+				return Err(e);
 			}
 		};
-		if compressed {
-			path.set_extension("zst");
-		}
+		let path = self.block_file_path(hash, format);
 		let mut f = fs::File::open(&path).await?;
+		drop(lock);
 
 		let mut data = vec![];
 		f.read_to_end(&mut data).await?;
 		drop(f);
 
-		let data = if compressed {
-			DataBlock::Compressed(data.into())
-		} else {
-			DataBlock::Plain(data.into())
-		};
+		let data = match self.decode_block_file(hash, format, data)? {
+			Some(data) if data.verify(*hash).is_ok() => data,
+			_ => {
+				self.metrics.corruption_counter.add(1);
 
-		if data.verify(*hash).is_err() {
-			self.metrics.corruption_counter.add(1);
+				self.lock_mutate(hash)
+					.await
+					.move_block_to_corrupted(hash, self)
+					.await?;
+				self.resync
+					.put_to_resync_priority(hash, Duration::from_millis(0))?;
+				return Err(Error::CorruptData(*hash));
+			}
+		};
 
-			self.lock_mutate(hash)
-				.await
-				.move_block_to_corrupted(hash, self)
-				.await?;
-			self.resync.put_to_resync(hash, Duration::from_millis(0))?;
-			return Err(Error::CorruptData(*hash));
+		if let Some(cache) = &self.block_cache {
+			cache.insert(*hash, &data);
 		}
 
 		Ok(data)
 	}
 
+	/// Decrypt the content of a block file if it is encrypted.
+	/// Returns None if decryption failed because the file is corrupted,
+	/// and an error if the file cannot be decrypted on this node.
+	fn decode_block_file(
+		&self,
+		hash: &Hash,
+		format: BlockFileFormat,
+		data: Vec<u8>,
+	) -> Result<Option<DataBlock>, Error> {
+		let data = if format.encrypted {
+			let cipher = self.block_cipher.as_ref().ok_or_else(|| {
+				Error::Message(format!(
+					"Block {:?} is stored encrypted, but no block_encryption_key is configured",
+					hash
+				))
+			})?;
+			match decrypt_block_file(cipher, &data) {
+				Some(plaintext) => plaintext,
+				None => return Ok(None),
+			}
+		} else {
+			data
+		};
+		Ok(Some(DataBlock::from_parts(format.header, data.into())))
+	}
+
+	/// Look for a block in the in-memory cache, if it is enabled
+	fn get_cached_block(&self, hash: &Hash) -> Option<DataBlock> {
+		let cache = self.block_cache.as_ref()?;
+		let block = cache.get(hash);
+		if block.is_some() {
+			self.metrics.block_cache_hit_counter.add(1);
+		} else {
+			self.metrics.block_cache_miss_counter.add(1);
+		}
+		block
+	}
+
+	/// Remove a block from the in-memory cache, if it is enabled
+	fn invalidate_cached_block(&self, hash: &Hash) {
+		if let Some(cache) = &self.block_cache {
+			cache.invalidate(hash);
+		}
+	}
+
 	/// Check if this node has a block and whether it needs it
 	pub(crate) async fn check_block_status(&self, hash: &Hash) -> Result<BlockStatus, Error> {
-		self.lock_mutate(hash)
+		self.lock_read(hash)
 			.await
 			.check_block_status(hash, self)
 			.await
@@ -581,6 +804,18 @@ impl BlockManager {
 			.await
 	}
 
+	/// Check if a block is stored both compressed and uncompressed,
+	/// and if so delete one of the two copies
+	pub(crate) async fn fix_compression_inconsistency(
+		&self,
+		hash: &Hash,
+	) -> Result<CompressionFix, Error> {
+		self.lock_mutate(hash)
+			.await
+			.fix_compression_inconsistency(hash, self)
+			.await
+	}
+
 	/// Utility: gives the path of the directory in which a block should be found
 	fn block_dir(&self, hash: &Hash) -> PathBuf {
 		let mut path = self.data_dir.clone();
@@ -597,43 +832,83 @@ impl BlockManager {
 		path
 	}
 
-	/// Utility: check if block is stored compressed. Error if block is not stored
-	async fn is_block_compressed(&self, hash: &Hash) -> Result<bool, Error> {
+	/// Utility: give the full path of the file storing a block in the given format
+	fn block_file_path(&self, hash: &Hash, format: BlockFileFormat) -> PathBuf {
 		let mut path = self.block_path(hash);
+		match (format.header.extension(), format.encrypted) {
+			(Some(ext), true) => path.set_extension(format!("{}.{}", ext, ENCRYPTED_EXTENSION)),
+			(None, true) => path.set_extension(ENCRYPTED_EXTENSION),
+			(Some(ext), false) => path.set_extension(ext),
+			(None, false) => true,
+		};
+		path
+	}
 
-		// If compression is disabled on node - check for the raw block
-		// first and then a compressed one (as compression may have been
-		// previously enabled).
-		match self.compression_level {
-			None => {
-				if fs::metadata(&path).await.is_ok() {
-					return Ok(false);
-				}
+	/// Utility: all the formats in which a block file can be stored,
+	/// compressed ones first, and for each of them the encrypted one first
+	/// if encryption is enabled on this node
+	fn block_file_formats(&self) -> Vec<BlockFileFormat> {
+		let encrypted = self.block_cipher.is_some();
+		BLOCK_FORMATS
+			.iter()
+			.flat_map(|header| {
+				[encrypted, !encrypted].map(|encrypted| BlockFileFormat {
+					header: *header,
+					encrypted,
+				})
+			})
+			.collect()
+	}
 
-				path.set_extension("zst");
+	/// Utility: find in which format a block is stored. Error if block is not stored
+	async fn block_file_format(&self, hash: &Hash) -> Result<BlockFileFormat, Error> {
+		// Check first for the format this node currently writes blocks in,
+		// and then for the other ones (as compression and encryption settings
+		// may have been different previously).
+		let preferred = self.written_block_file_format(match self.compression_algorithm() {
+			CompressionAlgorithm::Zstd(_) => DataBlockHeader::Compressed,
+			CompressionAlgorithm::Lz4 => DataBlockHeader::Lz4,
+			CompressionAlgorithm::None => DataBlockHeader::Plain,
+		});
+		let all = self.block_file_formats();
+		let others = all.iter().copied().filter(|f| *f != preferred);
 
-				fs::metadata(&path).await.map(|_| true).map_err(Into::into)
+		for format in std::iter::once(preferred).chain(others) {
+			if fs::metadata(self.block_file_path(hash, format))
+				.await
+				.is_ok()
+			{
+				return Ok(format);
 			}
-			_ => {
-				path.set_extension("zst");
-
-				if fs::metadata(&path).await.is_ok() {
-					return Ok(true);
-				}
-
-				path.set_extension("");
+		}
+		Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
+	}
 
-				fs::metadata(&path).await.map(|_| false).map_err(Into::into)
-			}
+	/// Utility: format of the file in which this node writes a block
+	/// of data in the given format
+	fn written_block_file_format(&self, header: DataBlockHeader) -> BlockFileFormat {
+		BlockFileFormat {
+			header,
+			encrypted: self.block_cipher.is_some(),
 		}
 	}
 
-	async fn lock_mutate(&self, hash: &Hash) -> MutexGuard<'_, BlockManagerLocked> {
+	async fn lock_mutate(&self, hash: &Hash) -> RwLockWriteGuard<'_, BlockManagerLocked> {
 		let tracer = opentelemetry::global::tracer("garage");
 		self.mutation_lock[hash.as_slice()[0] as usize]
-			.lock()
+			.write()
 			.with_context(Context::current_with_span(
-				tracer.start("Acquire mutation_lock"),
+				tracer.start("Acquire mutation_lock (exclusive)"),
+			))
+			.await
+	}
+
+	async fn lock_read(&self, hash: &Hash) -> RwLockReadGuard<'_, BlockManagerLocked> {
+		let tracer = opentelemetry::global::tracer("garage");
+		self.mutation_lock[hash.as_slice()[0] as usize]
+			.read()
+			.with_context(Context::current_with_span(
+				tracer.start("Acquire mutation_lock (shared)"),
 			))
 			.await
 	}
@@ -642,6 +917,7 @@ impl BlockManager {
 #[async_trait]
 impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 	async fn handle(self: &Arc<Self>, mut message: Req<BlockRpc>, _from: NodeID) -> Resp<BlockRpc> {
+		let _running = RunningRpcHandler::start();
 		match message.msg() {
 			BlockRpc::PutBlock { hash, header } => Resp::new(
 				self.handle_put_block(*hash, *header, message.take_stream())
@@ -668,41 +944,39 @@ impl BlockManagerLocked {
 		hash: &Hash,
 		mgr: &BlockManager,
 	) -> Result<BlockStatus, Error> {
-		let exists = mgr.is_block_compressed(hash).await.is_ok();
+		let exists = mgr.block_file_format(hash).await.is_ok();
 		let needed = mgr.rc.get_block_rc(hash)?;
 
 		Ok(BlockStatus { exists, needed })
 	}
 
 	async fn write_block(
-		&self,
+		&mut self,
 		hash: &Hash,
 		data: &DataBlock,
 		mgr: &BlockManager,
 	) -> Result<(), Error> {
 		let compressed = data.is_compressed();
-		let data = data.inner_buffer();
-
-		let mut path = mgr.block_dir(hash);
-		let directory = path.clone();
-		path.push(hex::encode(hash));
+		let path = mgr.block_file_path(hash, mgr.written_block_file_format(data.header()));
 
+		let directory = mgr.block_dir(hash);
 		fs::create_dir_all(&directory).await?;
 
-		let to_delete = match (mgr.is_block_compressed(hash).await, compressed) {
-			(Ok(true), _) => return Ok(()),
-			(Ok(false), false) => return Ok(()),
-			(Ok(false), true) => {
-				let path_to_delete = path.clone();
-				path.set_extension("zst");
-				Some(path_to_delete)
+		let to_delete = match mgr.block_file_format(hash).await {
+			Ok(existing) if existing.header == DataBlockHeader::Plain && compressed => {
+				Some(mgr.block_file_path(hash, existing))
 			}
-			(Err(_), compressed) => {
-				if compressed {
-					path.set_extension("zst");
-				}
-				None
+			Ok(_) => return Ok(()),
+			Err(_) => None,
+		};
+
+		let encrypted;
+		let data = match &mgr.block_cipher {
+			Some(cipher) => {
+				encrypted = encrypt_block_file(cipher, data.inner_buffer())?;
+				&encrypted[..]
 			}
+			None => data.inner_buffer(),
 		};
 
 		let mut path_tmp = path.clone();
@@ -721,6 +995,7 @@ impl BlockManagerLocked {
 		delete_on_drop.cancel();
 
 		if let Some(to_delete) = to_delete {
+			mgr.invalidate_cached_block(hash);
 			fs::remove_file(to_delete).await?;
 		}
 
@@ -740,36 +1015,107 @@ impl BlockManagerLocked {
 		Ok(())
 	}
 
-	async fn move_block_to_corrupted(&self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
+	async fn move_block_to_corrupted(
+		&mut self,
+		hash: &Hash,
+		mgr: &BlockManager,
+	) -> Result<(), Error> {
 		warn!(
 			"Block {:?} is corrupted. Renaming to .corrupted and resyncing.",
 			hash
 		);
-		let mut path = mgr.block_path(hash);
-		let mut path2 = path.clone();
-		if mgr.is_block_compressed(hash).await? {
-			path.set_extension("zst");
-			path2.set_extension("zst.corrupted");
-		} else {
-			path2.set_extension("corrupted");
-		}
-		fs::rename(path, path2).await?;
+		mgr.invalidate_cached_block(hash);
+		let path = mgr.block_file_path(hash, mgr.block_file_format(hash).await?);
+		fs::rename(&path, corrupted_path(&path)).await?;
 		Ok(())
 	}
 
-	async fn delete_if_unneeded(&self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
+	async fn delete_if_unneeded(&mut self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
 		let BlockStatus { exists, needed } = self.check_block_status(hash, mgr).await?;
 
 		if exists && needed.is_deletable() {
-			let mut path = mgr.block_path(hash);
-			if mgr.is_block_compressed(hash).await? {
-				path.set_extension("zst");
-			}
+			mgr.invalidate_cached_block(hash);
+			let path = mgr.block_file_path(hash, mgr.block_file_format(hash).await?);
 			fs::remove_file(path).await?;
 			mgr.metrics.delete_counter.add(1);
 		}
 		Ok(())
 	}
+
+	async fn fix_compression_inconsistency(
+		&mut self,
+		hash: &Hash,
+		mgr: &BlockManager,
+	) -> Result<CompressionFix, Error> {
+		let mut copies = vec![];
+		for format in mgr.block_file_formats() {
+			let path = mgr.block_file_path(hash, format);
+			if fs::metadata(&path).await.is_ok() {
+				copies.push((format, path));
+			}
+		}
+		if copies.len() < 2 {
+			return Ok(CompressionFix::Consistent);
+		}
+		mgr.invalidate_cached_block(hash);
+
+		// Keep the first valid copy, compressed copies coming first
+		let mut kept = None;
+		for (format, path) in copies.iter() {
+			let data = mgr.decode_block_file(hash, *format, fs::read(path).await?)?;
+			if data.map(|d| d.verify(*hash).is_ok()).unwrap_or(false) {
+				kept = Some(*format);
+				break;
+			}
+		}
+
+		let fix = match kept {
+			Some(kept) => {
+				for (_, path) in copies.iter().filter(|(f, _)| *f != kept) {
+					fs::remove_file(path).await?;
+				}
+				if kept.header == DataBlockHeader::Plain {
+					CompressionFix::KeptPlain
+				} else {
+					CompressionFix::KeptCompressed
+				}
+			}
+			None => {
+				for (_, path) in copies.iter() {
+					fs::rename(path, corrupted_path(path)).await?;
+				}
+				mgr.metrics.corruption_counter.add(1);
+				mgr.resync
+					.put_to_resync_priority(hash, Duration::from_millis(0))?;
+				CompressionFix::BothCorrupted
+			}
+		};
+		Ok(fix)
+	}
+}
+
+/// Format of a block file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BlockFileFormat {
+	/// Format of the block data, possibly compressed
+	header: DataBlockHeader,
+	/// Whether the block data is encrypted in the file
+	encrypted: bool,
+}
+
+/// Outcome of [`BlockManager::fix_compression_inconsistency`] for a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompressionFix {
+	/// The block is not stored in several forms, nothing was done
+	Consistent,
+	/// The other copies were deleted, a compressed one was kept
+	KeptCompressed,
+	/// The compressed copies were invalid and were deleted,
+	/// the uncompressed one was kept
+	KeptPlain,
+	/// All copies were invalid, they were moved away and the block
+	/// was queued for resync
+	BothCorrupted,
 }
 
 async fn read_stream_to_end(mut stream: ByteStream) -> Result<Bytes, Error> {
@@ -786,6 +1132,37 @@ async fn read_stream_to_end(mut stream: ByteStream) -> Result<Bytes, Error> {
 		.into())
 }
 
+/// List the entries of a directory of the data store, sorted by name
+async fn sorted_dir_entries(path: &Path) -> Result<Vec<(String, PathBuf)>, Error> {
+	let mut entries = vec![];
+	let mut reader = fs::read_dir(path).await?;
+	while let Some(ent) = reader.next_entry().await? {
+		if let Ok(name) = ent.file_name().into_string() {
+			entries.push((name, ent.path()));
+		}
+	}
+	entries.sort();
+	Ok(entries)
+}
+
+/// Check if a block subdirectory (named after one byte of the hash, in hex)
+/// can contain blocks matching the given hex prefix and coming after
+/// the given start position
+fn dir_matches(name: &str, prefix: &str, after: Option<&str>) -> bool {
+	let prefix = &prefix[..std::cmp::min(prefix.len(), 2)];
+	name.len() == 2
+		&& hex::decode(name).is_ok()
+		&& name.starts_with(prefix)
+		&& after.map(|a| name >= a).unwrap_or(true)
+}
+
+/// Path to which a corrupted block file is moved
+fn corrupted_path(path: &Path) -> PathBuf {
+	let mut path = path.as_os_str().to_owned();
+	path.push(".corrupted");
+	path.into()
+}
+
 struct DeleteOnDrop(Option<PathBuf>);
 
 impl DeleteOnDrop {
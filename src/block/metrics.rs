@@ -1,13 +1,23 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
 use opentelemetry::{global, metrics::*};
 
 use garage_db as db;
 use garage_db::counted_tree_hack::CountedTree;
 
+use garage_util::config::CompressionAlgorithm;
+use garage_util::time::now_msec;
+
+use crate::resync::time_of_queue_key;
+
 /// TableMetrics reference all counter used for metrics
 pub struct BlockManagerMetrics {
 	pub(crate) _compression_level: ValueObserver<u64>,
 	pub(crate) _rc_size: ValueObserver<u64>,
 	pub(crate) _resync_queue_len: ValueObserver<u64>,
+	pub(crate) _replication_lag: ValueObserver<f64>,
+	pub(crate) _resync_priority_queue_len: ValueObserver<u64>,
 	pub(crate) _resync_errored_blocks: ValueObserver<u64>,
 
 	pub(crate) resync_counter: BoundCounter<u64>,
@@ -16,6 +26,9 @@ pub struct BlockManagerMetrics {
 	pub(crate) resync_send_counter: Counter<u64>,
 	pub(crate) resync_recv_counter: BoundCounter<u64>,
 
+	pub(crate) block_cache_hit_counter: BoundCounter<u64>,
+	pub(crate) block_cache_miss_counter: BoundCounter<u64>,
+
 	pub(crate) bytes_read: BoundCounter<u64>,
 	pub(crate) block_read_duration: BoundValueRecorder<f64>,
 	pub(crate) bytes_written: BoundCounter<u64>,
@@ -27,18 +40,19 @@ pub struct BlockManagerMetrics {
 
 impl BlockManagerMetrics {
 	pub fn new(
-		compression_level: Option<i32>,
+		compression_algorithm: Arc<ArcSwap<CompressionAlgorithm>>,
 		rc_tree: db::Tree,
 		resync_queue: CountedTree,
+		resync_priority_queue: CountedTree,
 		resync_errors: CountedTree,
 	) -> Self {
 		let meter = global::meter("garage_model/block");
 		Self {
 			_compression_level: meter
 				.u64_value_observer("block.compression_level", move |observer| {
-					match compression_level {
-						Some(v) => observer.observe(v as u64, &[]),
-						None => observer.observe(0_u64, &[]),
+					match **compression_algorithm.load() {
+						CompressionAlgorithm::Zstd(v) => observer.observe(v as u64, &[]),
+						_ => observer.observe(0_u64, &[]),
 					}
 				})
 				.with_description("Garage compression level for node")
@@ -52,13 +66,37 @@ impl BlockManagerMetrics {
 				.with_description("Number of blocks known to the reference counter")
 				.init(),
 			_resync_queue_len: meter
-				.u64_value_observer("block.resync_queue_length", move |observer| {
-					observer.observe(resync_queue.len() as u64, &[])
+				.u64_value_observer("block.resync_queue_length", {
+					let resync_queue = resync_queue.clone();
+					move |observer| observer.observe(resync_queue.len() as u64, &[])
 				})
 				.with_description(
 					"Number of block hashes queued for local check and possible resync",
 				)
 				.init(),
+			_replication_lag: meter
+				.f64_value_observer("block.replication_lag_seconds", move |observer| {
+					// Entries of the queue are sorted by the time at which they
+					// should be processed, which can be in the future
+					let lag_msec = match resync_queue.first() {
+						Ok(Some((key, _))) => now_msec().saturating_sub(time_of_queue_key(&key)),
+						Ok(None) => 0,
+						Err(_) => return,
+					};
+					observer.observe(lag_msec as f64 / 1000., &[])
+				})
+				.with_description(
+					"Age in seconds of the oldest block hash queued for resync that is due to be processed",
+				)
+				.init(),
+			_resync_priority_queue_len: meter
+				.u64_value_observer("block.resync_priority_queue_length", move |observer| {
+					observer.observe(resync_priority_queue.len() as u64, &[])
+				})
+				.with_description(
+					"Number of block hashes of needed but absent blocks queued for resync",
+				)
+				.init(),
 			_resync_errored_blocks: meter
 				.u64_value_observer("block.resync_errored_blocks", move |observer| {
 					observer.observe(resync_errors.len() as u64, &[])
@@ -91,6 +129,17 @@ impl BlockManagerMetrics {
 				.init()
 				.bind(&[]),
 
+			block_cache_hit_counter: meter
+				.u64_counter("block.cache_hit_counter")
+				.with_description("Number of block reads served from the in-memory block cache")
+				.init()
+				.bind(&[]),
+			block_cache_miss_counter: meter
+				.u64_counter("block.cache_miss_counter")
+				.with_description("Number of block reads not found in the in-memory block cache")
+				.init()
+				.bind(&[]),
+
 			bytes_read: meter
 				.u64_counter("block.bytes_read")
 				.with_description("Number of bytes read from disk")
@@ -1,12 +1,16 @@
 use std::convert::TryInto;
 
+use std::time::Duration;
+
 use garage_db as db;
 
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::time::*;
 
-use crate::manager::BLOCK_GC_DELAY;
+/// Hash of a block and its number of references,
+/// or None if the counter of the block is invalid
+pub type BlockRcCount = (Vec<u8>, Option<u64>);
 
 pub struct BlockRc {
 	pub(crate) rc: db::Tree,
@@ -33,13 +37,15 @@ impl BlockRc {
 	}
 
 	/// Decrement the reference counter associated to a hash.
+	/// If it drops to zero, the block can be deleted after `gc_delay`.
 	/// Returns true if the RC is now zero.
 	pub(crate) fn block_decref(
 		&self,
 		tx: &mut db::Transaction,
 		hash: &Hash,
+		gc_delay: Duration,
 	) -> db::TxOpResult<bool> {
-		let new_rc = RcEntry::parse_opt(tx.get(&self.rc, hash)?).decrement();
+		let new_rc = RcEntry::parse_opt(tx.get(&self.rc, hash)?).decrement(gc_delay);
 		match new_rc.serialize() {
 			Some(x) => tx.insert(&self.rc, hash, x)?,
 			None => tx.remove(&self.rc, hash)?,
@@ -52,6 +58,22 @@ impl BlockRc {
 		Ok(RcEntry::parse_opt(self.rc.get(hash.as_ref())?))
 	}
 
+	/// Iterate over the reference counters of all blocks, giving for each block
+	/// its number of references (zero if it is waiting to be deleted),
+	/// or None if the entry is invalid
+	pub(crate) fn iter_counts(
+		&self,
+	) -> Result<impl Iterator<Item = Result<BlockRcCount, Error>> + '_, Error> {
+		Ok(self.rc.iter()?.map(|item| {
+			let (hash, bytes) = item?;
+			let count = RcEntry::try_parse(&bytes).map(|rc| match rc {
+				RcEntry::Present { count } => count,
+				_ => 0,
+			});
+			Ok((hash, count))
+		}))
+	}
+
 	/// Delete an entry in the RC table if it is deletable and the
 	/// deletion time has passed
 	pub(crate) fn clear_deleted_block_rc(&self, hash: &Hash) -> Result<(), Error> {
@@ -93,19 +115,27 @@ pub(crate) enum RcEntry {
 }
 
 impl RcEntry {
-	fn parse(bytes: &[u8]) -> Self {
+	fn try_parse(bytes: &[u8]) -> Option<Self> {
 		if bytes.len() == 8 {
-			RcEntry::Present {
+			Some(RcEntry::Present {
 				count: u64::from_be_bytes(bytes.try_into().unwrap()),
-			}
+			})
 		} else if bytes.len() == 16 {
-			RcEntry::Deletable {
+			Some(RcEntry::Deletable {
 				at_time: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
-			}
+			})
 		} else {
-			panic!("Invalid RC entry: {:?}, database is corrupted. This is an error Garage is currently unable to recover from. Sorry, and also please report a bug.",
+			None
+		}
+	}
+
+	fn parse(bytes: &[u8]) -> Self {
+		match Self::try_parse(bytes) {
+			Some(rc) => rc,
+			None => panic!(
+				"Invalid RC entry: {:?}, database is corrupted. This is an error Garage is currently unable to recover from. Sorry, and also please report a bug.",
 				bytes
-			)
+			),
 		}
 	}
 
@@ -135,14 +165,14 @@ impl RcEntry {
 		}
 	}
 
-	fn decrement(self) -> Self {
+	fn decrement(self, gc_delay: Duration) -> Self {
 		match self {
 			RcEntry::Present { count } => {
 				if count > 1 {
 					RcEntry::Present { count: count - 1 }
 				} else {
 					RcEntry::Deletable {
-						at_time: now_msec() + BLOCK_GC_DELAY.as_millis() as u64,
+						at_time: now_msec() + gc_delay.as_millis() as u64,
 					}
 				}
 			}
@@ -1,7 +1,8 @@
 use core::ops::Bound;
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use rand::Rng;
@@ -9,6 +10,8 @@ use tokio::fs;
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::sync::watch;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 
 use garage_util::background::*;
 use garage_util::data::*;
@@ -204,14 +207,58 @@ mod v082 {
 	}
 }
 
-pub use v082::*;
+mod v085 {
+	use garage_util::data::Hash;
+	use serde::{Deserialize, Serialize};
+
+	use super::v082;
+
+	#[derive(Serialize, Deserialize)]
+	pub struct ScrubWorkerPersisted {
+		pub tranquility: u32,
+		pub(crate) time_last_complete_scrub: u64,
+		pub(crate) time_next_run_scrub: u64,
+		pub(crate) corruptions_detected: u64,
+		/// Shards of the data directory (identified by the first byte of
+		/// the hashes of their blocks) that were already scrubbed by the
+		/// scrub in progress, if there is one
+		pub(crate) shards_done: Option<Vec<u8>>,
+		/// Hash of the last block checked in each shard that was being
+		/// scrubbed when the state was last saved, so that the scrub of
+		/// that shard can resume from there after a restart
+		pub(crate) last_scanned_hashes: Vec<Hash>,
+	}
+
+	impl garage_util::migrate::Migrate for ScrubWorkerPersisted {
+		type Previous = v082::ScrubWorkerPersisted;
+		const VERSION_MARKER: &'static [u8] = b"G085bswp";
+
+		fn migrate(old: v082::ScrubWorkerPersisted) -> ScrubWorkerPersisted {
+			ScrubWorkerPersisted {
+				tranquility: old.tranquility,
+				time_last_complete_scrub: old.time_last_complete_scrub,
+				time_next_run_scrub: old.time_next_run_scrub,
+				corruptions_detected: old.corruptions_detected,
+				shards_done: None,
+				last_scanned_hashes: vec![],
+			}
+		}
+	}
+}
+
+pub use v085::*;
+
+/// Number of shards in which the data directory is split for scrubbing
+const SCRUB_SHARDS: usize = 256;
+/// Minimum interval between two saves of the position of the scrub in a shard
+const SCRUB_POSITION_SAVE_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct ScrubWorker {
 	manager: Arc<BlockManager>,
 	rx_cmd: mpsc::Receiver<ScrubWorkerCommand>,
 
 	work: ScrubWorkerState,
-	tranquilizer: Tranquilizer,
+	worker_count: usize,
 
 	persister: PersisterShared<ScrubWorkerPersisted>,
 }
@@ -235,14 +282,16 @@ impl Default for ScrubWorkerPersisted {
 			time_next_run_scrub: randomize_next_scrub_run_time(now_msec()),
 			tranquility: INITIAL_SCRUB_TRANQUILITY,
 			corruptions_detected: 0,
+			shards_done: None,
+			last_scanned_hashes: vec![],
 		}
 	}
 }
 
 #[derive(Default)]
 enum ScrubWorkerState {
-	Running(BlockStoreIterator),
-	Paused(BlockStoreIterator, u64), // u64 = time when to resume scrub
+	Running(ScrubRun),
+	Paused(u64), // u64 = time when to resume scrub
 	#[default]
 	Finished,
 }
@@ -260,24 +309,39 @@ impl ScrubWorker {
 		manager: Arc<BlockManager>,
 		rx_cmd: mpsc::Receiver<ScrubWorkerCommand>,
 		persister: PersisterShared<ScrubWorkerPersisted>,
+		worker_count: usize,
 	) -> Self {
 		Self {
 			manager,
 			rx_cmd,
 			work: ScrubWorkerState::Finished,
-			tranquilizer: Tranquilizer::new(30),
+			worker_count,
 			persister,
 		}
 	}
 
+	fn start_run(&self) -> ScrubRun {
+		ScrubRun::start(&self.manager, &self.persister, self.worker_count)
+	}
+
 	async fn handle_cmd(&mut self, cmd: ScrubWorkerCommand) {
 		match cmd {
 			ScrubWorkerCommand::Start => {
 				self.work = match std::mem::take(&mut self.work) {
 					ScrubWorkerState::Finished => {
-						info!("Scrub worker initializing, now performing datastore scrub");
-						let iterator = BlockStoreIterator::new(&self.manager);
-						ScrubWorkerState::Running(iterator)
+						let resumed = self.persister.get_with(|p| p.shards_done.is_some());
+						if resumed {
+							info!("Scrub worker initializing, now resuming interrupted datastore scrub");
+						} else {
+							info!("Scrub worker initializing, now performing datastore scrub");
+							if let Err(e) = self.persister.set_with(|p| {
+								p.shards_done = Some(vec![]);
+								p.last_scanned_hashes.clear();
+							}) {
+								error!("Could not save scrub progress: {}", e);
+							}
+						}
+						ScrubWorkerState::Running(self.start_run())
 					}
 					work => {
 						error!("Cannot start scrub worker: already running!");
@@ -287,8 +351,8 @@ impl ScrubWorker {
 			}
 			ScrubWorkerCommand::Pause(dur) => {
 				self.work = match std::mem::take(&mut self.work) {
-					ScrubWorkerState::Running(it) | ScrubWorkerState::Paused(it, _) => {
-						ScrubWorkerState::Paused(it, now_msec() + dur.as_millis() as u64)
+					ScrubWorkerState::Running(_) | ScrubWorkerState::Paused(_) => {
+						ScrubWorkerState::Paused(now_msec() + dur.as_millis() as u64)
 					}
 					work => {
 						error!("Cannot pause scrub worker: not running!");
@@ -298,7 +362,7 @@ impl ScrubWorker {
 			}
 			ScrubWorkerCommand::Resume => {
 				self.work = match std::mem::take(&mut self.work) {
-					ScrubWorkerState::Paused(it, _) => ScrubWorkerState::Running(it),
+					ScrubWorkerState::Paused(_) => ScrubWorkerState::Running(self.start_run()),
 					work => {
 						error!("Cannot resume scrub worker: not paused!");
 						work
@@ -307,7 +371,13 @@ impl ScrubWorker {
 			}
 			ScrubWorkerCommand::Cancel => {
 				self.work = match std::mem::take(&mut self.work) {
-					ScrubWorkerState::Running(_) | ScrubWorkerState::Paused(_, _) => {
+					ScrubWorkerState::Running(_) | ScrubWorkerState::Paused(_) => {
+						if let Err(e) = self.persister.set_with(|p| {
+							p.shards_done = None;
+							p.last_scanned_hashes.clear();
+						}) {
+							error!("Could not save scrub progress: {}", e);
+						}
 						ScrubWorkerState::Finished
 					}
 					work => {
@@ -318,6 +388,46 @@ impl ScrubWorker {
 			}
 		}
 	}
+
+	/// Handle the notification that a shard has been scrubbed,
+	/// or that all scrub tasks have exited if None
+	fn handle_shard_done(&mut self, shard: Option<u8>) -> Result<(), Error> {
+		if let Some(shard) = shard {
+			return self.persister.set_with(|p| {
+				if let Some(done) = p.shards_done.as_mut() {
+					done.push(shard);
+				}
+				p.last_scanned_hashes.retain(|h| h.as_slice()[0] != shard);
+			});
+		}
+
+		let shards_done = self
+			.persister
+			.get_with(|p| p.shards_done.as_ref().map(Vec::len).unwrap_or(0));
+		if shards_done < SCRUB_SHARDS {
+			warn!(
+				"Datastore scrub could not check {} shards of the data directory because of errors",
+				SCRUB_SHARDS - shards_done
+			);
+		}
+
+		let now = now_msec();
+		let next_scrub_timestamp = randomize_next_scrub_run_time(now);
+
+		self.persister.set_with(|p| {
+			p.time_last_complete_scrub = now;
+			p.time_next_run_scrub = next_scrub_timestamp;
+			p.shards_done = None;
+			p.last_scanned_hashes.clear();
+		})?;
+		self.work = ScrubWorkerState::Finished;
+
+		info!(
+			"Datastore scrub completed, next scrub scheduled for {}",
+			msec_to_rfc3339(next_scrub_timestamp)
+		);
+		Ok(())
+	}
 }
 
 #[async_trait]
@@ -327,15 +437,22 @@ impl Worker for ScrubWorker {
 	}
 
 	fn status(&self) -> WorkerStatus {
-		let (corruptions_detected, tranquility, time_last_complete_scrub, time_next_run_scrub) =
-			self.persister.get_with(|p| {
-				(
-					p.corruptions_detected,
-					p.tranquility,
-					p.time_last_complete_scrub,
-					p.time_next_run_scrub,
-				)
-			});
+		let (
+			corruptions_detected,
+			tranquility,
+			time_last_complete_scrub,
+			time_next_run_scrub,
+			shards_done,
+		) = self.persister.get_with(|p| {
+			(
+				p.corruptions_detected,
+				p.tranquility,
+				p.time_last_complete_scrub,
+				p.time_next_run_scrub,
+				p.shards_done.as_ref().map(Vec::len).unwrap_or(0),
+			)
+		});
+		let progress = format!("{:.2}%", shards_done as f32 * 100. / SCRUB_SHARDS as f32);
 
 		let mut s = WorkerStatus {
 			persistent_errors: Some(corruptions_detected),
@@ -343,11 +460,15 @@ impl Worker for ScrubWorker {
 			..Default::default()
 		};
 		match &self.work {
-			ScrubWorkerState::Running(bsi) => {
-				s.progress = Some(format!("{:.2}%", bsi.progress() * 100.));
+			ScrubWorkerState::Running(_) => {
+				s.progress = Some(progress);
+				s.freeform = vec![format!(
+					"Scrubbing with {} parallel tasks",
+					self.worker_count
+				)];
 			}
-			ScrubWorkerState::Paused(bsi, rt) => {
-				s.progress = Some(format!("{:.2}%", bsi.progress() * 100.));
+			ScrubWorkerState::Paused(rt) => {
+				s.progress = Some(progress);
 				s.freeform = vec![format!("Scrub paused, resumes at {}", msec_to_rfc3339(*rt))];
 			}
 			ScrubWorkerState::Finished => {
@@ -373,52 +494,43 @@ impl Worker for ScrubWorker {
 			Err(mpsc::error::TryRecvError::Empty) => (),
 		};
 
-		match &mut self.work {
-			ScrubWorkerState::Running(bsi) => {
-				self.tranquilizer.reset();
-				if let Some(hash) = bsi.next().await? {
-					match self.manager.read_block(&hash).await {
-						Err(Error::CorruptData(_)) => {
-							error!("Found corrupt data block during scrub: {:?}", hash);
-							self.persister.set_with(|p| p.corruptions_detected += 1)?;
-						}
-						Err(e) => return Err(e),
-						_ => (),
-					};
-					Ok(self
-						.tranquilizer
-						.tranquilize_worker(self.persister.get_with(|p| p.tranquility)))
-				} else {
-					let now = now_msec();
-					let next_scrub_timestamp = randomize_next_scrub_run_time(now);
-
-					self.persister.set_with(|p| {
-						p.time_last_complete_scrub = now;
-						p.time_next_run_scrub = next_scrub_timestamp;
-					})?;
-					self.work = ScrubWorkerState::Finished;
-					self.tranquilizer.clear();
-
-					info!(
-						"Datastore scrub completed, next scrub scheduled for {}",
-						msec_to_rfc3339(next_scrub_timestamp)
-					);
-
-					Ok(WorkerState::Idle)
-				}
+		// Scrubbing itself is done by the tasks of the scrub run,
+		// we only wait for them to report their progress
+		if let ScrubWorkerState::Running(run) = &mut self.work {
+			if let Ok(shard) = run.rx_shard_done.try_recv() {
+				self.handle_shard_done(Some(shard))?;
+				return Ok(WorkerState::Busy);
 			}
-			_ => Ok(WorkerState::Idle),
 		}
+		Ok(WorkerState::Idle)
 	}
 
 	async fn wait_for_work(&mut self) -> WorkerState {
-		let (wait_until, command) = match &self.work {
-			ScrubWorkerState::Running(_) => return WorkerState::Busy,
-			ScrubWorkerState::Paused(_, resume_time) => (*resume_time, ScrubWorkerCommand::Resume),
-			ScrubWorkerState::Finished => (
-				self.persister.get_with(|p| p.time_next_run_scrub),
-				ScrubWorkerCommand::Start,
-			),
+		let (wait_until, command) = match &mut self.work {
+			ScrubWorkerState::Running(run) => {
+				select! {
+					shard = run.rx_shard_done.recv() => {
+						if let Err(e) = self.handle_shard_done(shard) {
+							error!("Could not save scrub progress: {}", e);
+						}
+					}
+					cmd = self.rx_cmd.recv() => if let Some(cmd) = cmd {
+						self.handle_cmd(cmd).await;
+					} else {
+						return WorkerState::Done;
+					}
+				}
+				return WorkerState::Busy;
+			}
+			ScrubWorkerState::Paused(resume_time) => (*resume_time, ScrubWorkerCommand::Resume),
+			ScrubWorkerState::Finished => {
+				// A scrub that was interrupted by a restart is resumed immediately
+				let (in_progress, next_run) = self
+					.persister
+					.get_with(|p| (p.shards_done.is_some(), p.time_next_run_scrub));
+				let wait_until = if in_progress { now_msec() } else { next_run };
+				(wait_until, ScrubWorkerCommand::Start)
+			}
 		};
 
 		let now = now_msec();
@@ -443,27 +555,352 @@ impl Worker for ScrubWorker {
 	}
 }
 
+/// A run of the scrub, where the shards of the data directory that were not
+/// scrubbed yet are sent in a channel from which several tasks take them.
+/// The tasks are stopped when the run is dropped.
+struct ScrubRun {
+	tasks: Vec<JoinHandle<()>>,
+	rx_shard_done: mpsc::UnboundedReceiver<u8>,
+}
+
+impl ScrubRun {
+	fn start(
+		manager: &Arc<BlockManager>,
+		persister: &PersisterShared<ScrubWorkerPersisted>,
+		worker_count: usize,
+	) -> Self {
+		let shards_done = persister.get_with(|p| p.shards_done.clone().unwrap_or_default());
+
+		let (tx_shard, rx_shard) = mpsc::channel(SCRUB_SHARDS);
+		for shard in 0..=255u8 {
+			if !shards_done.contains(&shard) {
+				tx_shard.try_send(shard).unwrap();
+			}
+		}
+		drop(tx_shard);
+
+		let rx_shard = Arc::new(Mutex::new(rx_shard));
+		let (tx_shard_done, rx_shard_done) = mpsc::unbounded_channel();
+		let tasks = (0..worker_count)
+			.map(|_| {
+				tokio::spawn(scrub_task(
+					manager.clone(),
+					persister.clone(),
+					rx_shard.clone(),
+					tx_shard_done.clone(),
+					worker_count as u32,
+				))
+			})
+			.collect();
+
+		Self {
+			tasks,
+			rx_shard_done,
+		}
+	}
+}
+
+impl Drop for ScrubRun {
+	fn drop(&mut self) {
+		for task in self.tasks.iter() {
+			task.abort();
+		}
+	}
+}
+
+async fn scrub_task(
+	manager: Arc<BlockManager>,
+	persister: PersisterShared<ScrubWorkerPersisted>,
+	rx_shard: Arc<Mutex<mpsc::Receiver<u8>>>,
+	tx_shard_done: mpsc::UnboundedSender<u8>,
+	n_tasks: u32,
+) {
+	let mut tranquilizer = Tranquilizer::new(30);
+	loop {
+		let shard = match rx_shard.lock().await.recv().await {
+			Some(shard) => shard,
+			None => break,
+		};
+		match scrub_shard(&manager, &persister, &mut tranquilizer, shard, n_tasks).await {
+			Ok(()) => {
+				if tx_shard_done.send(shard).is_err() {
+					break;
+				}
+			}
+			Err(e) => error!(
+				"Could not scrub shard {:02x} of the data directory: {}",
+				shard, e
+			),
+		}
+	}
+}
+
+async fn scrub_shard(
+	manager: &BlockManager,
+	persister: &PersisterShared<ScrubWorkerPersisted>,
+	tranquilizer: &mut Tranquilizer,
+	shard: u8,
+	n_tasks: u32,
+) -> Result<(), Error> {
+	let shard_dir = manager.data_dir.join(hex::encode([shard]));
+	if fs::metadata(&shard_dir).await.is_err() {
+		// No block stored in this shard
+		return Ok(());
+	}
+
+	let start_after = persister.get_with(|p| {
+		p.last_scanned_hashes
+			.iter()
+			.find(|h| h.as_slice()[0] == shard)
+			.cloned()
+	});
+	if let Some(start) = start_after {
+		info!(
+			"Resuming scrub of shard {:02x} after block {:?}",
+			shard, start
+		);
+	}
+
+	let mut block_iter = BlockStoreIterator::from_dir(shard_dir, hex::encode([shard]), start_after);
+	let mut last_save = Instant::now();
+	while let Some(hash) = block_iter.next().await? {
+		tranquilizer.reset();
+		match manager.read_block(&hash).await {
+			Err(Error::CorruptData(_)) => {
+				error!("Found corrupt data block during scrub: {:?}", hash);
+				persister.set_with(|p| p.corruptions_detected += 1)?;
+			}
+			Err(e) => error!("Could not read block {:?} during scrub: {}", hash, e),
+			_ => (),
+		};
+		if last_save.elapsed() >= SCRUB_POSITION_SAVE_INTERVAL {
+			persister.set_with(|p| {
+				p.last_scanned_hashes.retain(|h| h.as_slice()[0] != shard);
+				p.last_scanned_hashes.push(hash);
+			})?;
+			last_save = Instant::now();
+		}
+		tranquilizer
+			.tranquilize_shared(persister.get_with(|p| p.tranquility), n_tasks)
+			.await;
+	}
+	Ok(())
+}
+
+// ---- ---- ----
+// THIRD KIND OF REPAIR: BLOCKS STORED BOTH COMPRESSED AND UNCOMPRESSED
+// This is a one-shot repair operation that lists all blocks on disk
+// and keeps only one copy of blocks that are stored in both forms
+// (preferably the compressed one), then exits.
+// ---- ---- ----
+
+pub struct CompressionRepairWorker {
+	manager: Arc<BlockManager>,
+	block_iter: BlockStoreIterator,
+	checked: usize,
+	kept_compressed: usize,
+	kept_plain: usize,
+	corrupted: usize,
+}
+
+impl CompressionRepairWorker {
+	pub fn new(manager: Arc<BlockManager>) -> Self {
+		let block_iter = BlockStoreIterator::new(&manager);
+		Self {
+			manager,
+			block_iter,
+			checked: 0,
+			kept_compressed: 0,
+			kept_plain: 0,
+			corrupted: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for CompressionRepairWorker {
+	fn name(&self) -> String {
+		"Block compression inconsistency repair worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(format!("{:.2}%", self.block_iter.progress() * 100.)),
+			freeform: vec![
+				format!("Blocks checked: {}", self.checked),
+				format!("Uncompressed copies deleted: {}", self.kept_compressed),
+				format!("Invalid compressed copies deleted: {}", self.kept_plain),
+				format!("Blocks with both copies invalid: {}", self.corrupted),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		// A block stored in both forms is returned twice by the iterator,
+		// the second time it is already fixed and nothing is done
+		let hash = match self.block_iter.next().await? {
+			Some(hash) => hash,
+			None => {
+				info!(
+					"Compression inconsistency repair: {} blocks checked, {} uncompressed copies deleted, {} invalid compressed copies deleted, {} blocks with both copies invalid",
+					self.checked, self.kept_compressed, self.kept_plain, self.corrupted
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+		self.checked += 1;
+
+		match self.manager.fix_compression_inconsistency(&hash).await? {
+			CompressionFix::Consistent => (),
+			CompressionFix::KeptCompressed => {
+				info!(
+					"Block {:?} was stored both compressed and uncompressed, deleted uncompressed copy",
+					hash
+				);
+				self.kept_compressed += 1;
+			}
+			CompressionFix::KeptPlain => {
+				warn!(
+					"Block {:?} was stored both compressed and uncompressed, compressed copy was invalid and has been deleted",
+					hash
+				);
+				self.kept_plain += 1;
+			}
+			CompressionFix::BothCorrupted => {
+				error!(
+					"Block {:?} was stored both compressed and uncompressed, both copies are invalid. Renamed to .corrupted and resyncing.",
+					hash
+				);
+				self.corrupted += 1;
+			}
+		}
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ---- ---- ----
+// ORPHAN BLOCK DETECTION
+// ---- ---- ----
+
+/// Finds the blocks stored on this node that have no references and are not
+/// in the resync queues, which can be left behind by interrupted repairs, and
+/// queues them for resync after the block GC delay so that the resync worker
+/// deletes them.
+pub struct OrphanBlockWorker {
+	manager: Arc<BlockManager>,
+	block_iter: BlockStoreIterator,
+	/// Blocks that were in the resync queues when the worker started
+	queued: Option<HashSet<Hash>>,
+	checked: usize,
+	orphans: usize,
+}
+
+impl OrphanBlockWorker {
+	pub fn new(manager: Arc<BlockManager>) -> Self {
+		let block_iter = BlockStoreIterator::new(&manager);
+		Self {
+			manager,
+			block_iter,
+			queued: None,
+			checked: 0,
+			orphans: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for OrphanBlockWorker {
+	fn name(&self) -> String {
+		"Orphan block detection worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(format!("{:.2}%", self.block_iter.progress() * 100.)),
+			freeform: vec![
+				format!("Blocks checked: {}", self.checked),
+				format!("Orphan blocks queued for deletion: {}", self.orphans),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if self.queued.is_none() {
+			self.queued = Some(self.manager.resync.queued_blocks()?);
+		}
+
+		let hash = match self.block_iter.next().await? {
+			Some(hash) => hash,
+			None => {
+				info!(
+					"Orphan block detection: {} blocks checked, {} orphan blocks queued for deletion",
+					self.checked, self.orphans
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+		self.checked += 1;
+
+		let queued = self.queued.as_ref().unwrap();
+		if self.manager.rc.get_block_rc(&hash)?.is_zero() && !queued.contains(&hash) {
+			info!(
+				"Block {:?} has no references and is not queued for resync, queuing it for deletion",
+				hash
+			);
+			self.manager.resync.put_to_resync(&hash, BLOCK_GC_DELAY)?;
+			self.orphans += 1;
+		}
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
 // ---- ---- ----
 // UTILITY FOR ENUMERATING THE BLOCK STORE
 // ---- ---- ----
 
+/// Iterates on the blocks of the data directory in the lexicographic
+/// order of their hashes
 struct BlockStoreIterator {
 	path: Vec<ReadingDir>,
+	/// Hex encoding of the hash after which the iteration starts
+	start_after: Option<String>,
 }
 
+/// A directory being read, along with the hex prefix shared by the hashes
+/// of all the blocks it contains
 enum ReadingDir {
-	Pending(PathBuf),
+	Pending(PathBuf, String),
 	Read {
 		subpaths: Vec<fs::DirEntry>,
 		pos: usize,
+		prefix: String,
 	},
 }
 
 impl BlockStoreIterator {
 	fn new(manager: &BlockManager) -> Self {
-		let root_dir = manager.data_dir.clone();
+		Self::from_dir(manager.data_dir.clone(), String::new(), None)
+	}
+
+	/// Iterate on the blocks stored in a subdirectory of the data directory,
+	/// whose hashes all start with `prefix` (hex-encoded). If `start_after`
+	/// is given, blocks whose hashes are not after it are skipped.
+	fn from_dir(root_dir: PathBuf, prefix: String, start_after: Option<Hash>) -> Self {
 		Self {
-			path: vec![ReadingDir::Pending(root_dir)],
+			path: vec![ReadingDir::Pending(root_dir, prefix)],
+			start_after: start_after.map(hex::encode),
 		}
 	}
 
@@ -476,8 +913,8 @@ impl BlockStoreIterator {
 			let mut next_div = 1;
 			for p in self.path.iter() {
 				match p {
-					ReadingDir::Pending(_) => break,
-					ReadingDir::Read { subpaths, pos } => {
+					ReadingDir::Pending(..) => break,
+					ReadingDir::Read { subpaths, pos, .. } => {
 						next_div *= subpaths.len();
 						ret += ((*pos - 1) as f32) / (next_div as f32);
 					}
@@ -494,21 +931,27 @@ impl BlockStoreIterator {
 				Some(lp) => lp,
 			};
 
-			if let ReadingDir::Pending(path) = last_path {
+			if let ReadingDir::Pending(path, prefix) = last_path {
 				let mut reader = fs::read_dir(&path).await?;
 				let mut subpaths = vec![];
 				while let Some(ent) = reader.next_entry().await? {
 					subpaths.push(ent);
 				}
-				*last_path = ReadingDir::Read { subpaths, pos: 0 };
+				subpaths.sort_by_key(|ent| ent.file_name());
+				*last_path = ReadingDir::Read {
+					subpaths,
+					pos: 0,
+					prefix: std::mem::take(prefix),
+				};
 			}
 
-			let (subpaths, pos) = match *last_path {
+			let (subpaths, pos, prefix) = match *last_path {
 				ReadingDir::Read {
 					ref subpaths,
 					ref mut pos,
-				} => (subpaths, pos),
-				ReadingDir::Pending(_) => unreachable!(),
+					ref prefix,
+				} => (subpaths, pos, prefix),
+				ReadingDir::Pending(..) => unreachable!(),
 			};
 
 			let data_dir_ent = match subpaths.get(*pos) {
@@ -530,11 +973,27 @@ impl BlockStoreIterator {
 			};
 			let ent_type = data_dir_ent.file_type().await?;
 
-			let name = name.strip_suffix(".zst").unwrap_or(&name);
+			let name = name.strip_suffix(".enc").unwrap_or(&name);
+			let name = name
+				.strip_suffix(".zst")
+				.or_else(|| name.strip_suffix(".lz4"))
+				.unwrap_or(name);
 			if name.len() == 2 && hex::decode(name).is_ok() && ent_type.is_dir() {
+				let dir_prefix = format!("{}{}", prefix, name.to_lowercase());
+				// Skip directories whose blocks all come before the start position
+				if let Some(start) = &self.start_after {
+					if start.get(..dir_prefix.len()) > Some(dir_prefix.as_str()) {
+						continue;
+					}
+				}
 				let path = data_dir_ent.path();
-				self.path.push(ReadingDir::Pending(path));
+				self.path.push(ReadingDir::Pending(path, dir_prefix));
 			} else if name.len() == 64 {
+				if let Some(start) = &self.start_after {
+					if name.to_lowercase().as_str() <= start.as_str() {
+						continue;
+					}
+				}
 				if let Ok(h) = hex::decode(name) {
 					let mut hash = [0u8; 32];
 					hash.copy_from_slice(&h);
@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 use std::convert::TryInto;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -46,8 +46,13 @@ pub(crate) const MAX_RESYNC_WORKERS: usize = 4;
 // and the updated version is persisted over Garage restarts
 const INITIAL_RESYNC_TRANQUILITY: u32 = 2;
 
+// Interval between two updates of the number of stored blocks
+// advertised by this node to the rest of the cluster
+const STORED_BLOCKS_UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct BlockResyncManager {
 	pub(crate) queue: CountedTree,
+	pub(crate) priority_queue: CountedTree,
 	pub(crate) notify: Arc<Notify>,
 	pub(crate) errors: CountedTree,
 
@@ -80,6 +85,8 @@ enum ResyncIterResult {
 type BusySet = Arc<Mutex<HashSet<Vec<u8>>>>;
 
 struct BusyBlock {
+	/// Whether the block was taken from the priority queue
+	priority: bool,
 	time_bytes: Vec<u8>,
 	hash_bytes: Vec<u8>,
 	busy_set: BusySet,
@@ -92,6 +99,12 @@ impl BlockResyncManager {
 			.expect("Unable to open block_local_resync_queue tree");
 		let queue = CountedTree::new(queue).expect("Could not count block_local_resync_queue");
 
+		let priority_queue = db
+			.open_tree("block_local_resync_priority_queue")
+			.expect("Unable to open block_local_resync_priority_queue tree");
+		let priority_queue = CountedTree::new(priority_queue)
+			.expect("Could not count block_local_resync_priority_queue");
+
 		let errors = db
 			.open_tree("block_local_resync_errors")
 			.expect("Unable to open block_local_resync_errors tree");
@@ -101,6 +114,7 @@ impl BlockResyncManager {
 
 		Self {
 			queue,
+			priority_queue,
 			notify: Arc::new(Notify::new()),
 			errors,
 			busy_set: Arc::new(Mutex::new(HashSet::new())),
@@ -116,6 +130,12 @@ impl BlockResyncManager {
 		Ok(self.queue.len())
 	}
 
+	/// Get length of resync priority queue
+	pub fn priority_queue_len(&self) -> Result<usize, Error> {
+		// (see queue_len comment)
+		Ok(self.priority_queue.len())
+	}
+
 	/// Get number of blocks that have an error
 	pub fn errors_len(&self) -> Result<usize, Error> {
 		// (see queue_len comment)
@@ -180,7 +200,7 @@ impl BlockResyncManager {
 	// deleted once the garbage collection delay has passed.
 	//
 	// Here are some explanations on how the resync queue works.
-	// There are two Sled trees that are used to have information
+	// There are three Sled trees that are used to have information
 	// about the status of blocks that need to be resynchronized:
 	//
 	// - resync.queue: a tree that is ordered first by a timestamp
@@ -190,8 +210,17 @@ impl BlockResyncManager {
 	//       concat(timestamp (8 bytes), hash (32 bytes))
 	//   The value is the same 32-byte hash.
 	//
+	// - resync.priority_queue: a tree with the same format as
+	//   resync.queue, for blocks that are needed but that we
+	//   know are absent or corrupted on this node. Items of this
+	//   queue are processed before those of resync.queue when
+	//   their time has come, so that missing blocks are fetched
+	//   without waiting for other resync operations (e.g. offloading
+	//   blocks we no longer need) to complete.
+	//
 	// - resync.errors: a tree that indicates for each block
-	//   if the last resync resulted in an error, and if so,
+	//   if the last resync resulted in an error (whichever queue
+	//   it was taken from), and if so,
 	//   the following two informations (see the ErrorCounter struct):
 	//   - how many consecutive resync errors for this block?
 	//   - when was the last try?
@@ -252,17 +281,52 @@ impl BlockResyncManager {
 
 	pub(crate) fn put_to_resync_at(&self, hash: &Hash, when: u64) -> db::Result<()> {
 		trace!("Put resync_queue: {} {:?}", when, hash);
+		self.put_to_queue_at(false, hash, when)
+	}
+
+	/// Put a block that is needed but absent or corrupted on this node
+	/// in the priority queue, so that it is fetched as soon as possible
+	pub(crate) fn put_to_resync_priority(&self, hash: &Hash, delay: Duration) -> db::Result<()> {
+		let when = now_msec() + delay.as_millis() as u64;
+		trace!("Put resync_priority_queue: {} {:?}", when, hash);
+		self.put_to_queue_at(true, hash, when)
+	}
+
+	fn put_to_queue_at(&self, priority: bool, hash: &Hash, when: u64) -> db::Result<()> {
 		let mut key = u64::to_be_bytes(when).to_vec();
 		key.extend(hash.as_ref());
-		self.queue.insert(key, hash.as_ref())?;
+		self.queue_tree(priority).insert(key, hash.as_ref())?;
 		self.notify.notify_waiters();
 		Ok(())
 	}
 
+	/// Hashes of all the blocks currently in one of the resync queues
+	pub(crate) fn queued_blocks(&self) -> Result<HashSet<Hash>, Error> {
+		let mut ret = HashSet::new();
+		for tree in [&self.queue, &self.priority_queue] {
+			for ent in tree.iter()? {
+				let (_, hash) = ent?;
+				if let Some(hash) = Hash::try_from(&hash) {
+					ret.insert(hash);
+				}
+			}
+		}
+		Ok(ret)
+	}
+
+	fn queue_tree(&self, priority: bool) -> &CountedTree {
+		if priority {
+			&self.priority_queue
+		} else {
+			&self.queue
+		}
+	}
+
 	async fn resync_iter(&self, manager: &BlockManager) -> Result<ResyncIterResult, db::Error> {
-		if let Some(block) = self.get_block_to_resync()? {
-			let time_msec = u64::from_be_bytes(block.time_bytes[0..8].try_into().unwrap());
-			let now = now_msec();
+		let now = now_msec();
+		if let Some(block) = self.get_block_to_resync(now)? {
+			let time_msec = time_of_queue_key(&block.time_bytes);
+			let queue = self.queue_tree(block.priority);
 
 			if now >= time_msec {
 				let hash = Hash::try_from(&block.hash_bytes[..]).unwrap();
@@ -273,12 +337,12 @@ impl BlockResyncManager {
 						// if next retry after an error is not yet,
 						// don't do resync and return early, but still
 						// make sure the item is still in queue at expected time
-						self.put_to_resync_at(&hash, ec.next_try())?;
+						self.put_to_queue_at(block.priority, &hash, ec.next_try())?;
 						// ec.next_try() > now >= time_msec, so this remove
 						// is not removing the one we added just above
 						// (we want to do the remove after the insert to ensure
 						// that the item is not lost if we crash in-between)
-						self.queue.remove(&block.time_bytes)?;
+						queue.remove(&block.time_bytes)?;
 						return Ok(ResyncIterResult::BusyDidNothing);
 					}
 				}
@@ -315,14 +379,14 @@ impl BlockResyncManager {
 
 					self.errors.insert(hash.as_slice(), err_counter.encode())?;
 
-					self.put_to_resync_at(&hash, err_counter.next_try())?;
+					self.put_to_queue_at(block.priority, &hash, err_counter.next_try())?;
 					// err_counter.next_try() >= now + 1 > now,
 					// the entry we remove from the queue is not
-					// the entry we inserted with put_to_resync_at
-					self.queue.remove(&block.time_bytes)?;
+					// the entry we inserted with put_to_queue_at
+					queue.remove(&block.time_bytes)?;
 				} else {
 					self.errors.remove(hash.as_slice())?;
-					self.queue.remove(&block.time_bytes)?;
+					queue.remove(&block.time_bytes)?;
 				}
 
 				Ok(ResyncIterResult::BusyDidSomething)
@@ -342,20 +406,37 @@ impl BlockResyncManager {
 		}
 	}
 
-	fn get_block_to_resync(&self) -> Result<Option<BusyBlock>, db::Error> {
+	/// Take the first block of the priority queue if its time has come,
+	/// otherwise the first block of both queues
+	fn get_block_to_resync(&self, now: u64) -> Result<Option<BusyBlock>, db::Error> {
 		let mut busy = self.busy_set.lock().unwrap();
-		for it in self.queue.iter()? {
-			let (time_bytes, hash_bytes) = it?;
-			if !busy.contains(&time_bytes) {
-				busy.insert(time_bytes.clone());
-				return Ok(Some(BusyBlock {
-					time_bytes,
-					hash_bytes,
-					busy_set: self.busy_set.clone(),
-				}));
+		let mut next: Option<(bool, Vec<u8>, Vec<u8>)> = None;
+		for priority in [true, false] {
+			for it in self.queue_tree(priority).iter()? {
+				let (time_bytes, hash_bytes) = it?;
+				if !busy.contains(&time_bytes) {
+					// Keys begin with the big-endian timestamp,
+					// so comparing them orders blocks by time
+					if !matches!(&next, Some((_, t, _)) if *t <= time_bytes) {
+						next = Some((priority, time_bytes, hash_bytes));
+					}
+					break;
+				}
+			}
+			if matches!(&next, Some((_, t, _)) if time_of_queue_key(t) <= now) {
+				break;
 			}
 		}
-		Ok(None)
+
+		Ok(next.map(|(priority, time_bytes, hash_bytes)| {
+			busy.insert(time_bytes.clone());
+			BusyBlock {
+				priority,
+				time_bytes,
+				hash_bytes,
+				busy_set: self.busy_set.clone(),
+			}
+		}))
 	}
 
 	async fn resync_block(&self, manager: &BlockManager, hash: &Hash) -> Result<(), Error> {
@@ -375,7 +456,7 @@ impl BlockResyncManager {
 			info!("Resync block {:?}: offloading and deleting", hash);
 
 			let mut who = manager.replication.write_nodes(hash);
-			if who.len() < manager.replication.write_quorum() {
+			if who.len() < manager.replication.write_quorum(hash) {
 				return Err(Error::Message("Not trying to offload block because we don't have a quorum of nodes to write to".to_string()));
 			}
 			who.retain(|id| *id != manager.system.id);
@@ -468,6 +549,10 @@ impl BlockResyncManager {
 	}
 }
 
+pub(crate) fn time_of_queue_key(key: &[u8]) -> u64 {
+	u64::from_be_bytes(key[0..8].try_into().unwrap())
+}
+
 impl Drop for BusyBlock {
 	fn drop(&mut self) {
 		let mut busy = self.busy_set.lock().unwrap();
@@ -481,6 +566,7 @@ pub(crate) struct ResyncWorker {
 	tranquilizer: Tranquilizer,
 	next_delay: Duration,
 	persister: PersisterShared<ResyncPersistedConfig>,
+	last_stored_blocks_update: Option<Instant>,
 }
 
 impl ResyncWorker {
@@ -492,6 +578,7 @@ impl ResyncWorker {
 			tranquilizer: Tranquilizer::new(30),
 			next_delay: Duration::from_secs(10),
 			persister,
+			last_stored_blocks_update: None,
 		}
 	}
 }
@@ -513,7 +600,10 @@ impl Worker for ResyncWorker {
 		}
 
 		WorkerStatus {
-			queue_length: Some(self.manager.resync.queue_len().unwrap_or(0) as u64),
+			queue_length: Some(
+				(self.manager.resync.queue_len().unwrap_or(0)
+					+ self.manager.resync.priority_queue_len().unwrap_or(0)) as u64,
+			),
 			tranquility: Some(tranquility),
 			persistent_errors: Some(self.manager.resync.errors_len().unwrap_or(0) as u64),
 			..Default::default()
@@ -527,6 +617,19 @@ impl Worker for ResyncWorker {
 			return Ok(WorkerState::Idle);
 		}
 
+		// The first worker advertises the number of blocks stored on this node,
+		// which lets other nodes follow the progress of a node being drained
+		if self.index == 0
+			&& self
+				.last_stored_blocks_update
+				.map(|t| t.elapsed() >= STORED_BLOCKS_UPDATE_INTERVAL)
+				.unwrap_or(true)
+		{
+			let stored_blocks = self.manager.rc_fast_len()?.map(|n| n as u64);
+			self.manager.system.set_stored_blocks(stored_blocks);
+			self.last_stored_blocks_update = Some(Instant::now());
+		}
+
 		self.tranquilizer.reset();
 		match self.manager.resync.resync_iter(&self.manager).await {
 			Ok(ResyncIterResult::BusyDidSomething) => {
@@ -64,6 +64,11 @@ fn open_db(path: PathBuf, engine: String) -> Result<Db> {
 				.unwrap();
 			Ok(lmdb_adapter::LmdbDb::init(db))
 		}
+		#[cfg(feature = "rocksdb")]
+		"rocksdb" => {
+			let db = rocksdb_adapter::open(path, Default::default())?;
+			Ok(rocksdb_adapter::RocksDb::init(db, Default::default()))
+		}
 		e => Err(Error(format!("Invalid DB engine: {}", e).into())),
 	}
 }
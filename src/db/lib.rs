@@ -2,11 +2,20 @@
 #[cfg(feature = "sqlite")]
 extern crate tracing;
 
-#[cfg(not(any(feature = "lmdb", feature = "sled", feature = "sqlite")))]
-compile_error!("Must activate the Cargo feature for at least one DB engine: lmdb, sled or sqlite.");
+#[cfg(not(any(
+	feature = "lmdb",
+	feature = "rocksdb",
+	feature = "sled",
+	feature = "sqlite"
+)))]
+compile_error!(
+	"Must activate the Cargo feature for at least one DB engine: lmdb, rocksdb, sled or sqlite."
+);
 
 #[cfg(feature = "lmdb")]
 pub mod lmdb_adapter;
+#[cfg(feature = "rocksdb")]
+pub mod rocksdb_adapter;
 #[cfg(feature = "sled")]
 pub mod sled_adapter;
 #[cfg(feature = "sqlite")]
@@ -21,6 +30,7 @@ use core::ops::{Bound, RangeBounds};
 
 use std::borrow::Cow;
 use std::cell::Cell;
+use std::path::Path;
 use std::sync::Arc;
 
 use err_derive::Error;
@@ -86,6 +96,17 @@ impl Db {
 		self.0.list_trees()
 	}
 
+	/// Ensure that all writes made so far have been persisted to disk
+	pub fn flush(&self) -> Result<()> {
+		self.0.flush()
+	}
+
+	/// Write a compacted copy of the database to the given path,
+	/// for the engines that support it
+	pub fn copy_compacted(&self, path: &Path) -> Result<()> {
+		self.0.copy_compacted(path)
+	}
+
 	pub fn transaction<R, E, F>(&self, fun: F) -> TxResult<R, E>
 	where
 		F: Fn(Transaction<'_>) -> TxResult<R, E>,
@@ -324,6 +345,16 @@ pub(crate) trait IDb: Send + Sync {
 	fn engine(&self) -> String;
 	fn open_tree(&self, name: &str) -> Result<usize>;
 	fn list_trees(&self) -> Result<Vec<String>>;
+	fn flush(&self) -> Result<()>;
+	fn copy_compacted(&self, _path: &Path) -> Result<()> {
+		Err(Error(
+			format!(
+				"compaction is not supported by the {} engine",
+				self.engine()
+			)
+			.into(),
+		))
+	}
 
 	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>>;
 	fn len(&self, tree: usize) -> Result<usize>;
@@ -3,6 +3,7 @@ use core::ptr::NonNull;
 
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
 use heed::types::ByteSlice;
@@ -102,6 +103,17 @@ impl IDb for LmdbDb {
 		Ok(ret2)
 	}
 
+	fn flush(&self) -> Result<()> {
+		self.db.force_sync()?;
+		Ok(())
+	}
+
+	fn copy_compacted(&self, path: &Path) -> Result<()> {
+		self.db
+			.copy_to_path(path, heed::CompactionOption::Enabled)?;
+		Ok(())
+	}
+
 	// ----
 
 	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
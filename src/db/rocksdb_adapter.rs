@@ -0,0 +1,373 @@
+use core::ops::Bound;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use rocksdb::{
+	BoundColumnFamily, ErrorKind, IteratorMode, MultiThreaded, OptimisticTransactionDB, Options,
+	ReadOptions,
+};
+
+use crate::{
+	Db, Error, IDb, ITx, ITxFn, Result, TxError, TxFnResult, TxOpError, TxOpResult, TxResult,
+	TxValueIter, Value, ValueIter,
+};
+
+pub use rocksdb;
+
+type RocksTransactionDb = OptimisticTransactionDB<MultiThreaded>;
+type RocksTransaction<'a> = rocksdb::Transaction<'a, RocksTransactionDb>;
+
+/// Number of times a transaction is retried when it conflicts with
+/// another write before giving up
+const TX_MAX_RETRIES: usize = 10;
+
+// -- err
+
+impl From<rocksdb::Error> for Error {
+	fn from(e: rocksdb::Error) -> Error {
+		Error(format!("RocksDB: {}", e).into())
+	}
+}
+
+impl From<rocksdb::Error> for TxOpError {
+	fn from(e: rocksdb::Error) -> TxOpError {
+		TxOpError(e.into())
+	}
+}
+
+// -- db
+
+/// Open a RocksDB database with all of its column families,
+/// creating it if it does not exist
+pub fn open(path: PathBuf, mut opts: Options) -> Result<RocksTransactionDb> {
+	opts.create_if_missing(true);
+	opts.create_missing_column_families(true);
+	let cfs = match RocksTransactionDb::list_cf(&opts, &path) {
+		Ok(cfs) => cfs,
+		// The database does not exist yet
+		Err(_) => vec![],
+	};
+	Ok(RocksTransactionDb::open_cf(&opts, &path, cfs)?)
+}
+
+pub struct RocksDb {
+	db: RocksTransactionDb,
+	cf_opts: Options,
+	trees: RwLock<(Vec<String>, HashMap<String, usize>)>,
+}
+
+impl RocksDb {
+	/// Each tree is stored in a column family of the same name,
+	/// created with the options given in `cf_opts`
+	pub fn init(db: RocksTransactionDb, cf_opts: Options) -> Db {
+		let s = Self {
+			db,
+			cf_opts,
+			trees: RwLock::new((Vec::new(), HashMap::new())),
+		};
+		Db(Arc::new(s))
+	}
+
+	fn get_tree(&self, i: usize) -> Result<Arc<BoundColumnFamily<'_>>> {
+		let trees = self.trees.read().unwrap();
+		let name = trees
+			.0
+			.get(i)
+			.ok_or_else(|| Error("invalid tree id".into()))?;
+		self.db
+			.cf_handle(name)
+			.ok_or_else(|| Error(format!("column family {} does not exist", name).into()))
+	}
+
+	fn iter_with<'a>(
+		&'a self,
+		tree: usize,
+		opts: ReadOptions,
+		mode: IteratorMode<'_>,
+		skip: Option<Vec<u8>>,
+	) -> Result<ValueIter<'a>> {
+		let cf = self.get_tree(tree)?;
+		let iter = self
+			.db
+			.iterator_cf_opt(&cf, opts, mode)
+			.map(|item| match item {
+				Ok((k, v)) => Ok((k.into_vec(), v.into_vec())),
+				Err(e) => Err(e.into()),
+			})
+			.filter(move |item| match (item, &skip) {
+				(Ok((k, _)), Some(skip)) => k != skip,
+				_ => true,
+			});
+		Ok(Box::new(iter))
+	}
+}
+
+impl IDb for RocksDb {
+	fn engine(&self) -> String {
+		"RocksDB".into()
+	}
+
+	fn open_tree(&self, name: &str) -> Result<usize> {
+		let mut trees = self.trees.write().unwrap();
+		if let Some(i) = trees.1.get(name) {
+			Ok(*i)
+		} else {
+			if self.db.cf_handle(name).is_none() {
+				self.db.create_cf(name, &self.cf_opts)?;
+			}
+			let i = trees.0.len();
+			trees.0.push(name.to_string());
+			trees.1.insert(name.to_string(), i);
+			Ok(i)
+		}
+	}
+
+	fn list_trees(&self) -> Result<Vec<String>> {
+		let cfs = RocksTransactionDb::list_cf(&Options::default(), self.db.path())?;
+		Ok(cfs
+			.into_iter()
+			.filter(|name| name != rocksdb::DEFAULT_COLUMN_FAMILY_NAME)
+			.collect())
+	}
+
+	fn flush(&self) -> Result<()> {
+		self.db.flush_wal(true)?;
+		Ok(())
+	}
+
+	// ----
+
+	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
+		let cf = self.get_tree(tree)?;
+		Ok(self.db.get_cf(&cf, key)?)
+	}
+
+	fn len(&self, tree: usize) -> Result<usize> {
+		let cf = self.get_tree(tree)?;
+		let mut len = 0;
+		for item in self.db.iterator_cf(&cf, IteratorMode::Start) {
+			item?;
+			len += 1;
+		}
+		Ok(len)
+	}
+
+	fn insert(&self, tree: usize, key: &[u8], value: &[u8]) -> Result<Option<Value>> {
+		let cf = self.get_tree(tree)?;
+		retry_transaction(|| {
+			let tx = self.db.transaction();
+			let old_val = tx.get_for_update_cf(&cf, key, true)?;
+			tx.put_cf(&cf, key, value)?;
+			tx.commit()?;
+			Ok(old_val)
+		})
+	}
+
+	fn remove(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
+		let cf = self.get_tree(tree)?;
+		retry_transaction(|| {
+			let tx = self.db.transaction();
+			let old_val = tx.get_for_update_cf(&cf, key, true)?;
+			tx.delete_cf(&cf, key)?;
+			tx.commit()?;
+			Ok(old_val)
+		})
+	}
+
+	fn clear(&self, tree: usize) -> Result<()> {
+		let cf = self.get_tree(tree)?;
+		let mut batch = rocksdb::WriteBatchWithTransaction::<true>::default();
+		for item in self.db.iterator_cf(&cf, IteratorMode::Start) {
+			let (k, _) = item?;
+			batch.delete_cf(&cf, k);
+		}
+		self.db.write(batch)?;
+		Ok(())
+	}
+
+	fn iter(&self, tree: usize) -> Result<ValueIter<'_>> {
+		self.iter_with(tree, ReadOptions::default(), IteratorMode::Start, None)
+	}
+
+	fn iter_rev(&self, tree: usize) -> Result<ValueIter<'_>> {
+		self.iter_with(tree, ReadOptions::default(), IteratorMode::End, None)
+	}
+
+	fn range<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> Result<ValueIter<'_>> {
+		let (opts, skip) = range_read_options(low, high);
+		self.iter_with(tree, opts, IteratorMode::Start, skip)
+	}
+	fn range_rev<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> Result<ValueIter<'_>> {
+		let (opts, skip) = range_read_options(low, high);
+		self.iter_with(tree, opts, IteratorMode::End, skip)
+	}
+
+	// ----
+
+	fn transaction(&self, f: &dyn ITxFn) -> TxResult<(), ()> {
+		let trees = self.trees.read().unwrap();
+		let mut cfs = vec![];
+		for name in trees.0.iter() {
+			let cf = self.db.cf_handle(name).ok_or_else(|| {
+				TxError::Db(Error(
+					format!("column family {} does not exist", name).into(),
+				))
+			})?;
+			cfs.push(cf);
+		}
+
+		for _ in 0..TX_MAX_RETRIES {
+			let mut tx = RocksTx {
+				cfs: &cfs[..],
+				tx: self.db.transaction(),
+			};
+
+			match f.try_on(&mut tx) {
+				TxFnResult::Ok => match tx.tx.commit() {
+					Ok(()) => return Ok(()),
+					Err(e) if is_conflict(&e) => continue,
+					Err(e) => return Err(TxError::Db(e.into())),
+				},
+				TxFnResult::Abort => {
+					tx.tx.rollback().map_err(Error::from).map_err(TxError::Db)?;
+					return Err(TxError::Abort(()));
+				}
+				TxFnResult::DbErr => {
+					tx.tx.rollback().map_err(Error::from).map_err(TxError::Db)?;
+					return Err(TxError::Db(Error(
+						"(this message will be discarded)".into(),
+					)));
+				}
+			}
+		}
+		Err(TxError::Db(Error(
+			"RocksDB: transaction kept conflicting with concurrent writes".into(),
+		)))
+	}
+}
+
+// ----
+
+struct RocksTx<'a> {
+	cfs: &'a [Arc<BoundColumnFamily<'a>>],
+	tx: RocksTransaction<'a>,
+}
+
+impl<'a> RocksTx<'a> {
+	fn get_tree(&self, i: usize) -> TxOpResult<&Arc<BoundColumnFamily<'a>>> {
+		self.cfs.get(i).ok_or_else(|| {
+			TxOpError(Error(
+				"invalid tree id (it might have been openned after the transaction started)".into(),
+			))
+		})
+	}
+}
+
+impl<'a> ITx for RocksTx<'a> {
+	fn get(&self, tree: usize, key: &[u8]) -> TxOpResult<Option<Value>> {
+		let cf = self.get_tree(tree)?;
+		Ok(self.tx.get_for_update_cf(cf, key, false)?)
+	}
+	fn len(&self, _tree: usize) -> TxOpResult<usize> {
+		unimplemented!(".len() in transaction not supported with RocksDB backend")
+	}
+
+	fn insert(&mut self, tree: usize, key: &[u8], value: &[u8]) -> TxOpResult<Option<Value>> {
+		let cf = self.get_tree(tree)?;
+		let old_val = self.tx.get_for_update_cf(cf, key, true)?;
+		self.tx.put_cf(cf, key, value)?;
+		Ok(old_val)
+	}
+	fn remove(&mut self, tree: usize, key: &[u8]) -> TxOpResult<Option<Value>> {
+		let cf = self.get_tree(tree)?;
+		let old_val = self.tx.get_for_update_cf(cf, key, true)?;
+		self.tx.delete_cf(cf, key)?;
+		Ok(old_val)
+	}
+
+	fn iter(&self, _tree: usize) -> TxOpResult<TxValueIter<'_>> {
+		unimplemented!("Iterators in transactions not supported with RocksDB backend");
+	}
+	fn iter_rev(&self, _tree: usize) -> TxOpResult<TxValueIter<'_>> {
+		unimplemented!("Iterators in transactions not supported with RocksDB backend");
+	}
+
+	fn range<'r>(
+		&self,
+		_tree: usize,
+		_low: Bound<&'r [u8]>,
+		_high: Bound<&'r [u8]>,
+	) -> TxOpResult<TxValueIter<'_>> {
+		unimplemented!("Iterators in transactions not supported with RocksDB backend");
+	}
+	fn range_rev<'r>(
+		&self,
+		_tree: usize,
+		_low: Bound<&'r [u8]>,
+		_high: Bound<&'r [u8]>,
+	) -> TxOpResult<TxValueIter<'_>> {
+		unimplemented!("Iterators in transactions not supported with RocksDB backend");
+	}
+}
+
+// ----
+
+/// Run a write that is done in its own transaction,
+/// retrying it if it conflicted with a concurrent write
+fn retry_transaction<F, R>(f: F) -> Result<R>
+where
+	F: Fn() -> std::result::Result<R, rocksdb::Error>,
+{
+	let mut retries = 0;
+	loop {
+		match f() {
+			Err(e) if is_conflict(&e) && retries < TX_MAX_RETRIES => retries += 1,
+			res => return Ok(res?),
+		}
+	}
+}
+
+fn is_conflict(e: &rocksdb::Error) -> bool {
+	matches!(e.kind(), ErrorKind::Busy | ErrorKind::TryAgain)
+}
+
+/// RocksDB iterators take an inclusive lower bound and an exclusive upper
+/// bound: an included upper bound is converted to an excluded bound on the
+/// key that is immediately after it (the key followed by a zero byte), and
+/// an excluded lower bound is returned so that the iterator can skip it.
+fn range_read_options(low: Bound<&[u8]>, high: Bound<&[u8]>) -> (ReadOptions, Option<Vec<u8>>) {
+	let mut opts = ReadOptions::default();
+	let mut skip = None;
+	match low {
+		Bound::Included(k) => opts.set_iterate_lower_bound(k),
+		Bound::Excluded(k) => {
+			opts.set_iterate_lower_bound(k);
+			skip = Some(k.to_vec());
+		}
+		Bound::Unbounded => (),
+	}
+	match high {
+		Bound::Included(k) => opts.set_iterate_upper_bound(next_key(k)),
+		Bound::Excluded(k) => opts.set_iterate_upper_bound(k),
+		Bound::Unbounded => (),
+	}
+	(opts, skip)
+}
+
+fn next_key(key: &[u8]) -> Vec<u8> {
+	let mut next = key.to_vec();
+	next.push(0);
+	next
+}
@@ -88,6 +88,11 @@ impl IDb for SledDb {
 		Ok(trees)
 	}
 
+	fn flush(&self) -> Result<()> {
+		self.db.flush()?;
+		Ok(())
+	}
+
 	// ----
 
 	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
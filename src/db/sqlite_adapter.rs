@@ -119,6 +119,12 @@ impl IDb for SqliteDb {
 		Ok(trees)
 	}
 
+	fn flush(&self) -> Result<()> {
+		// Transactions are committed to disk synchronously by sqlite,
+		// there is nothing more to do here.
+		Ok(())
+	}
+
 	// ----
 
 	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
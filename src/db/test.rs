@@ -75,6 +75,20 @@ fn test_suite(db: Db) {
 	drop(iter);
 }
 
+/// Check that writes are still there when the database is reopened after a flush
+fn test_flush(path: &std::path::Path, open: impl Fn(&std::path::Path) -> Db) {
+	let db = open(path);
+	let tree = db.open_tree("tree").unwrap();
+	tree.insert(&b"test"[..], &b"plop"[..]).unwrap();
+	db.flush().unwrap();
+	drop(tree);
+	drop(db);
+
+	let db = open(path);
+	let tree = db.open_tree("tree").unwrap();
+	assert_eq!(tree.get(&b"test"[..]).unwrap().unwrap(), &b"plop"[..]);
+}
+
 #[test]
 #[cfg(feature = "lmdb")]
 fn test_lmdb_db() {
@@ -88,6 +102,30 @@ fn test_lmdb_db() {
 	let db = LmdbDb::init(db);
 	test_suite(db);
 	drop(path);
+
+	let path = mktemp::Temp::new_dir().unwrap();
+	test_flush(&path, |path| {
+		let db = heed::EnvOpenOptions::new().max_dbs(100).open(path).unwrap();
+		LmdbDb::init(db)
+	});
+}
+
+#[test]
+#[cfg(feature = "rocksdb")]
+fn test_rocksdb_db() {
+	use crate::rocksdb_adapter::{self, RocksDb};
+
+	let path = mktemp::Temp::new_dir().unwrap();
+	let db = rocksdb_adapter::open(path.to_path_buf(), Default::default()).unwrap();
+	let db = RocksDb::init(db, Default::default());
+	test_suite(db);
+	drop(path);
+
+	let path = mktemp::Temp::new_dir().unwrap();
+	test_flush(&path, |path| {
+		let db = rocksdb_adapter::open(path.to_path_buf(), Default::default()).unwrap();
+		RocksDb::init(db, Default::default())
+	});
 }
 
 #[test]
@@ -99,6 +137,9 @@ fn test_sled_db() {
 	let db = SledDb::init(sled::open(path.to_path_buf()).unwrap());
 	test_suite(db);
 	drop(path);
+
+	let path = mktemp::Temp::new_dir().unwrap();
+	test_flush(&path, |path| SledDb::init(sled::open(path).unwrap()));
 }
 
 #[test]
@@ -108,4 +149,9 @@ fn test_sqlite_db() {
 
 	let db = SqliteDb::init(rusqlite::Connection::open_in_memory().unwrap());
 	test_suite(db);
+
+	let path = mktemp::Temp::new_dir().unwrap();
+	test_flush(&path, |path| {
+		SqliteDb::init(rusqlite::Connection::open(path.join("db.sqlite")).unwrap())
+	});
 }
@@ -10,12 +10,25 @@ use crate::cli::*;
 
 use super::*;
 
+/// Maximum number of blocks returned by a single `block list-local` RPC,
+/// the CLI fetches the list page by page
+pub const LIST_LOCAL_BLOCKS_PAGE_SIZE: usize = 1000;
+
 impl AdminRpcHandler {
 	pub(super) async fn handle_block_cmd(&self, cmd: &BlockOperation) -> Result<AdminRpc, Error> {
 		match cmd {
-			BlockOperation::ListErrors => Ok(AdminRpc::BlockErrorList(
+			BlockOperation::ListErrors { .. } => Ok(AdminRpc::BlockErrorList(
 				self.garage.block_manager.list_resync_errors()?,
 			)),
+			BlockOperation::ListLocal {
+				prefix,
+				limit,
+				after,
+				..
+			} => {
+				self.handle_block_list_local(prefix, *limit, after.as_ref())
+					.await
+			}
 			BlockOperation::Info { hash } => self.handle_block_info(hash).await,
 			BlockOperation::RetryNow { all, blocks } => {
 				self.handle_block_retry_now(*all, blocks).await
@@ -24,6 +37,27 @@ impl AdminRpcHandler {
 		}
 	}
 
+	async fn handle_block_list_local(
+		&self,
+		prefix: &str,
+		limit: Option<usize>,
+		after: Option<&Hash>,
+	) -> Result<AdminRpc, Error> {
+		if prefix.len() > 64 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+			return Err(Error::BadRequest(format!(
+				"invalid hash prefix: {}",
+				prefix
+			)));
+		}
+		let limit = std::cmp::min(limit.unwrap_or(usize::MAX), LIST_LOCAL_BLOCKS_PAGE_SIZE);
+		let blocks = self
+			.garage
+			.block_manager
+			.list_local_blocks(prefix, after, limit)
+			.await?;
+		Ok(AdminRpc::LocalBlockList(blocks))
+	}
+
 	async fn handle_block_info(&self, hash: &String) -> Result<AdminRpc, Error> {
 		let hash = hex::decode(hash).ok_or_bad_request("invalid hash")?;
 		let hash = Hash::try_from(&hash).ok_or_bad_request("invalid hash")?;
@@ -121,8 +155,7 @@ impl AdminRpcHandler {
 					.get(&version.bucket_id, &version.key)
 					.await?
 				{
-					let ov = object.versions().iter().rev().find(|v| v.is_complete());
-					if let Some(ov) = ov {
+					if let Some(ov) = object.current_version() {
 						if ov.uuid == br.version {
 							let del_uuid = gen_uuid();
 							let deleted_object = Object::new(
@@ -134,6 +167,8 @@ impl AdminRpcHandler {
 									state: ObjectVersionState::Complete(
 										ObjectVersionData::DeleteMarker,
 									),
+									versioned: false,
+									legal_hold: Default::default(),
 								}],
 							);
 							self.garage.object_table.insert(&deleted_object).await?;
@@ -10,15 +10,25 @@ use garage_model::bucket_alias_table::*;
 use garage_model::bucket_table::*;
 use garage_model::helper::error::{Error, OkOrBadRequest};
 use garage_model::permission::*;
+use garage_model::s3::object_table::{BYTES, OBJECTS};
 
 use crate::cli::*;
 
+use super::prefix_stats::*;
 use super::*;
 
+/// A bucket returned by `garage bucket list`, along with its usage counters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BucketListItem {
+	pub bucket: Bucket,
+	pub size_bytes: u64,
+	pub object_count: u64,
+}
+
 impl AdminRpcHandler {
 	pub(super) async fn handle_bucket_cmd(&self, cmd: &BucketOperation) -> Result<AdminRpc, Error> {
 		match cmd {
-			BucketOperation::List => self.handle_list_buckets().await,
+			BucketOperation::List(_) => self.handle_list_buckets().await,
 			BucketOperation::Info(query) => self.handle_bucket_info(query).await,
 			BucketOperation::Create(query) => self.handle_create_bucket(&query.name).await,
 			BucketOperation::Delete(query) => self.handle_delete_bucket(query).await,
@@ -31,6 +41,15 @@ impl AdminRpcHandler {
 			BucketOperation::CleanupIncompleteUploads(query) => {
 				self.handle_bucket_cleanup_incomplete_uploads(query).await
 			}
+			BucketOperation::SetLifecycle(query) => self.handle_bucket_set_lifecycle(query).await,
+			BucketOperation::GetLifecycle(query) => {
+				self.handle_bucket_get_lifecycle(&query.name).await
+			}
+			BucketOperation::DeleteLifecycle(query) => {
+				self.handle_bucket_delete_lifecycle(&query.name).await
+			}
+			BucketOperation::SetEncryption(query) => self.handle_bucket_set_encryption(query).await,
+			BucketOperation::ImportFile(query) => self.handle_import_bucket_file(query).await,
 		}
 	}
 
@@ -47,10 +66,36 @@ impl AdminRpcHandler {
 			)
 			.await?;
 
-		Ok(AdminRpc::BucketList(buckets))
+		// Fetch the counters of all buckets concurrently
+		let counters = futures::future::try_join_all(buckets.iter().map(|bucket| {
+			self.garage
+				.object_counter_table
+				.table
+				.get(&bucket.id, &EmptyKey)
+		}))
+		.await?;
+
+		let ring = self.garage.system.ring.borrow().clone();
+		let items = buckets
+			.into_iter()
+			.zip(counters)
+			.map(|(bucket, counters)| {
+				let counters = counters
+					.map(|x| x.filtered_values(&ring))
+					.unwrap_or_default();
+				let counter = |name| counters.get(name).cloned().unwrap_or_default().max(0) as u64;
+				BucketListItem {
+					size_bytes: counter(BYTES),
+					object_count: counter(OBJECTS),
+					bucket,
+				}
+			})
+			.collect::<Vec<_>>();
+
+		Ok(AdminRpc::BucketList(items))
 	}
 
-	async fn handle_bucket_info(&self, query: &BucketOpt) -> Result<AdminRpc, Error> {
+	async fn handle_bucket_info(&self, query: &BucketInfoOpt) -> Result<AdminRpc, Error> {
 		let bucket_id = self
 			.garage
 			.bucket_helper()
@@ -108,10 +153,27 @@ impl AdminRpcHandler {
 			}
 		}
 
+		let prefix_stats = if !query.per_prefix_stats {
+			None
+		} else if counters.get(OBJECTS).cloned().unwrap_or_default()
+			<= PREFIX_STATS_SYNC_MAX_OBJECTS
+		{
+			let stats = compute_prefix_stats(&self.garage, bucket_id, query.prefix_depth).await?;
+			Some(PrefixStatsResult::Done(stats))
+		} else {
+			self.background.spawn_worker(PrefixStatsWorker::new(
+				self.garage.clone(),
+				bucket_id,
+				query.prefix_depth,
+			));
+			Some(PrefixStatsResult::InBackground)
+		};
+
 		Ok(AdminRpc::BucketInfo {
 			bucket,
 			relevant_keys,
 			counters,
+			prefix_stats,
 		})
 	}
 
@@ -143,6 +205,109 @@ impl AdminRpcHandler {
 		Ok(AdminRpc::Ok(format!("Bucket {} was created.", name)))
 	}
 
+	async fn handle_import_bucket_file(
+		&self,
+		query: &BucketImportFileOpt,
+	) -> Result<AdminRpc, Error> {
+		let mut report = String::new();
+		let mut failed = 0;
+		for entry in query.buckets.iter() {
+			let name = entry.global_aliases.first().map(String::as_str);
+			match self.import_bucket_entry(entry).await {
+				Ok(status) => {
+					writeln!(&mut report, "{}: {}", name.unwrap_or_default(), status).unwrap();
+				}
+				Err(e) => {
+					failed += 1;
+					writeln!(
+						&mut report,
+						"{}: failed: {}",
+						name.unwrap_or("<no alias>"),
+						e
+					)
+					.unwrap();
+				}
+			}
+		}
+		write!(
+			&mut report,
+			"{} buckets imported, {} failed.",
+			query.buckets.len() - failed,
+			failed
+		)
+		.unwrap();
+		Ok(AdminRpc::Ok(report))
+	}
+
+	/// Create or update a bucket from an entry of an import file,
+	/// returning whether it was created or updated
+	async fn import_bucket_entry(&self, entry: &BucketImportEntry) -> Result<&'static str, Error> {
+		let helper = self.garage.bucket_helper();
+
+		let name = entry
+			.global_aliases
+			.first()
+			.ok_or_bad_request("a bucket must have at least one global alias")?;
+		for alias in entry.global_aliases.iter() {
+			if !is_valid_bucket_name(alias) {
+				return Err(Error::BadRequest(format!(
+					"{}: {}",
+					alias, INVALID_BUCKET_NAME_MESSAGE
+				)));
+			}
+		}
+
+		let (bucket_id, status) = match helper.resolve_global_bucket_name(name).await? {
+			Some(bucket_id) => (bucket_id, "updated"),
+			None => {
+				let bucket = Bucket::new();
+				self.garage.bucket_table.insert(&bucket).await?;
+				helper.set_global_bucket_alias(bucket.id, name).await?;
+				(bucket.id, "created")
+			}
+		};
+
+		for alias in entry.global_aliases.iter().skip(1) {
+			match helper.resolve_global_bucket_name(alias).await? {
+				Some(id) if id == bucket_id => (),
+				Some(_) => {
+					return Err(Error::BadRequest(format!(
+						"alias {} is already used by another bucket",
+						alias
+					)))
+				}
+				None => helper.set_global_bucket_alias(bucket_id, alias).await?,
+			}
+		}
+
+		if entry.website_config.is_some() || entry.quotas.is_some() {
+			let mut bucket = helper.get_existing_bucket(bucket_id).await?;
+			let bucket_state = bucket.state.as_option_mut().unwrap();
+			if let Some(website) = &entry.website_config {
+				let routing_rules = bucket_state
+					.website_config
+					.get()
+					.as_ref()
+					.map(|w| w.routing_rules.clone())
+					.unwrap_or_default();
+				bucket_state.website_config.update(Some(WebsiteConfig {
+					index_document: website.index_document.clone(),
+					error_document: website.error_document.clone(),
+					routing_rules,
+				}));
+			}
+			if let Some(quotas) = &entry.quotas {
+				bucket_state.quotas.update(BucketQuotas {
+					max_size: quotas.max_size,
+					max_objects: quotas.max_objects,
+				});
+			}
+			self.garage.bucket_table.insert(&bucket).await?;
+		}
+
+		Ok(status)
+	}
+
 	async fn handle_delete_bucket(&self, query: &DeleteBucketOpt) -> Result<AdminRpc, Error> {
 		let helper = self.garage.bucket_helper();
 
@@ -292,7 +457,10 @@ impl AdminRpcHandler {
 		}
 	}
 
-	async fn handle_bucket_allow(&self, query: &PermBucketOpt) -> Result<AdminRpc, Error> {
+	pub(super) async fn handle_bucket_allow(
+		&self,
+		query: &PermBucketOpt,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.bucket_helper();
 		let key_helper = self.garage.key_helper();
 
@@ -327,7 +495,10 @@ impl AdminRpcHandler {
 		)))
 	}
 
-	async fn handle_bucket_deny(&self, query: &PermBucketOpt) -> Result<AdminRpc, Error> {
+	pub(super) async fn handle_bucket_deny(
+		&self,
+		query: &PermBucketOpt,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.bucket_helper();
 		let key_helper = self.garage.key_helper();
 
@@ -383,10 +554,18 @@ impl AdminRpcHandler {
 			));
 		}
 
+		// Routing rules can only be set with the S3 API,
+		// keep them when changing the other settings
 		let website = if query.allow {
 			Some(WebsiteConfig {
 				index_document: query.index_document.clone(),
 				error_document: query.error_document.clone(),
+				routing_rules: bucket_state
+					.website_config
+					.get()
+					.as_ref()
+					.map(|w| w.routing_rules.clone())
+					.unwrap_or_default(),
 			})
 		} else {
 			None
@@ -404,6 +583,123 @@ impl AdminRpcHandler {
 		Ok(AdminRpc::Ok(msg))
 	}
 
+	async fn handle_bucket_set_lifecycle(
+		&self,
+		query: &SetLifecycleOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		bucket_state
+			.lifecycle_config
+			.update(Some(query.rules.clone()));
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"{} lifecycle rule(s) set for bucket {}",
+			query.rules.len(),
+			query.bucket
+		)))
+	}
+
+	async fn handle_bucket_get_lifecycle(&self, bucket_name: &String) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(bucket_name)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+
+		Ok(AdminRpc::BucketLifecycle(
+			bucket.params().unwrap().lifecycle_config.get().clone(),
+		))
+	}
+
+	async fn handle_bucket_delete_lifecycle(
+		&self,
+		bucket_name: &String,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(bucket_name)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		bucket_state.lifecycle_config.update(None);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		Ok(AdminRpc::Ok(format!(
+			"Lifecycle rules removed for bucket {}",
+			bucket_name
+		)))
+	}
+
+	async fn handle_bucket_set_encryption(
+		&self,
+		query: &SetEncryptionOpt,
+	) -> Result<AdminRpc, Error> {
+		let encryption = match (query.sse_s3, query.none) {
+			(true, false) => Some(BucketEncryption::SseS3),
+			(false, true) => None,
+			_ => {
+				return Err(Error::BadRequest(
+					"You must specify exactly one of --sse-s3 or --none".into(),
+				))
+			}
+		};
+
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&query.bucket)
+			.await?
+			.ok_or_bad_request("Bucket not found")?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		bucket_state.default_encryption.update(encryption);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		let msg = match encryption {
+			Some(BucketEncryption::SseS3) => format!(
+				"New objects of bucket {} must now be encrypted with SSE-S3",
+				query.bucket
+			),
+			None => format!("Default encryption removed for bucket {}", query.bucket),
+		};
+		Ok(AdminRpc::Ok(msg))
+	}
+
 	async fn handle_bucket_set_quotas(&self, query: &SetQuotasOpt) -> Result<AdminRpc, Error> {
 		let bucket_id = self
 			.garage
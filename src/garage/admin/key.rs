@@ -1,9 +1,13 @@
 use std::collections::HashMap;
+use std::fmt::Write;
+
+use garage_util::time::*;
 
 use garage_table::*;
 
-use garage_model::helper::error::Error;
+use garage_model::helper::error::{Error, OkOrBadRequest};
 use garage_model::key_table::*;
+use garage_model::permission::*;
 
 use crate::cli::*;
 
@@ -20,6 +24,27 @@ impl AdminRpcHandler {
 			KeyOperation::Allow(query) => self.handle_allow_key(query).await,
 			KeyOperation::Deny(query) => self.handle_deny_key(query).await,
 			KeyOperation::Import(query) => self.handle_import_key(query).await,
+			KeyOperation::ImportFile(query) => self.handle_import_key_file(query).await,
+			KeyOperation::AddBucketPermission(query) => {
+				self.handle_bucket_allow(&PermBucketOpt {
+					key_pattern: query.key_pattern.clone(),
+					read: query.read,
+					write: query.write,
+					owner: query.owner,
+					bucket: query.bucket.clone(),
+				})
+				.await
+			}
+			KeyOperation::RemoveBucketPermission(query) => {
+				self.handle_bucket_deny(&PermBucketOpt {
+					key_pattern: query.key_pattern.clone(),
+					read: true,
+					write: true,
+					owner: true,
+					bucket: query.bucket.clone(),
+				})
+				.await
+			}
 		}
 	}
 
@@ -128,6 +153,97 @@ impl AdminRpcHandler {
 		self.key_info_result(imported_key).await
 	}
 
+	async fn handle_import_key_file(&self, query: &KeyImportFileOpt) -> Result<AdminRpc, Error> {
+		let mut report = String::new();
+		let mut failed = 0;
+		for entry in query.keys.iter() {
+			match self.import_key_entry(entry).await {
+				Ok(status) => {
+					writeln!(&mut report, "{}: {}", entry.access_key_id, status).unwrap();
+				}
+				Err(e) => {
+					failed += 1;
+					writeln!(&mut report, "{}: failed: {}", entry.access_key_id, e).unwrap();
+				}
+			}
+		}
+		write!(
+			&mut report,
+			"{} keys imported, {} failed.",
+			query.keys.len() - failed,
+			failed
+		)
+		.unwrap();
+		Ok(AdminRpc::Ok(report))
+	}
+
+	/// Create or update a key from an entry of an import file,
+	/// returning whether it was created or updated
+	async fn import_key_entry(&self, entry: &KeyImportEntry) -> Result<&'static str, Error> {
+		let bucket_helper = self.garage.bucket_helper();
+
+		// Resolve all buckets before writing anything, so that
+		// an entry with an unknown bucket is not partially imported
+		let mut permissions = vec![];
+		for perm in entry.bucket_permissions.iter() {
+			let bucket_id = bucket_helper
+				.resolve_global_bucket_name(&perm.bucket)
+				.await?
+				.ok_or_bad_request(format!("Bucket {} not found", perm.bucket))?;
+			permissions.push((bucket_id, perm));
+		}
+
+		let (mut key, status) = match self
+			.garage
+			.key_table
+			.get(&EmptyKey, &entry.access_key_id)
+			.await?
+		{
+			None => {
+				let name = entry.name.as_deref().unwrap_or("Imported key");
+				let key = Key::import(&entry.access_key_id, &entry.secret_access_key, name);
+				(key, "created")
+			}
+			Some(key) if key.is_deleted() => {
+				return Err(Error::BadRequest(
+					"this key was deleted, its ID cannot be reused".into(),
+				));
+			}
+			Some(key) => {
+				if key.params().unwrap().secret_key != entry.secret_access_key {
+					return Err(Error::BadRequest(
+						"this key already exists with a different secret key".into(),
+					));
+				}
+				(key, "updated")
+			}
+		};
+
+		let params = key.params_mut().unwrap();
+		if let Some(name) = &entry.name {
+			params.name.update(name.clone());
+		}
+		params.allow_create_bucket.update(entry.allow_create_bucket);
+		self.garage.key_table.insert(&key).await?;
+
+		for (bucket_id, perm) in permissions {
+			bucket_helper
+				.set_bucket_key_permissions(
+					bucket_id,
+					&key.key_id,
+					BucketKeyPerm {
+						timestamp: now_msec(),
+						allow_read: perm.read,
+						allow_write: perm.write,
+						allow_owner: perm.owner,
+					},
+				)
+				.await?;
+		}
+
+		Ok(status)
+	}
+
 	async fn key_info_result(&self, key: Key) -> Result<AdminRpc, Error> {
 		let mut relevant_buckets = HashMap::new();
 
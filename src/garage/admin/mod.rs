@@ -1,6 +1,8 @@
 mod block;
 mod bucket;
 mod key;
+mod prefix_stats;
+mod state;
 
 use std::collections::HashMap;
 use std::fmt::Write;
@@ -18,10 +20,13 @@ use garage_util::error::Error as GarageError;
 use garage_table::replication::*;
 use garage_table::*;
 
+use garage_rpc::drain::RunningRpcHandler;
 use garage_rpc::ring::PARTITION_BITS;
+use garage_rpc::rpc_stats::{RpcEndpointStats, RpcStatsWindow};
+use garage_rpc::system::PeerConnectionInfo;
 use garage_rpc::*;
 
-use garage_block::manager::BlockResyncErrorInfo;
+use garage_block::manager::{BlockResyncErrorInfo, LocalBlockInfo};
 
 use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
@@ -32,6 +37,9 @@ use garage_model::s3::version_table::Version;
 
 use crate::cli::*;
 use crate::repair::online::launch_online_repair;
+pub use block::LIST_LOCAL_BLOCKS_PAGE_SIZE;
+pub use bucket::BucketListItem;
+pub use prefix_stats::PrefixStatsResult;
 
 pub const ADMIN_RPC_PATH: &str = "garage/admin_rpc.rs/Rpc";
 
@@ -45,15 +53,26 @@ pub enum AdminRpc {
 	Stats(StatsOpt),
 	Worker(WorkerOperation),
 	BlockOperation(BlockOperation),
+	GetConnectionInfo,
+	PingNode {
+		node: Uuid,
+		count: usize,
+		interval: std::time::Duration,
+	},
+	SetNodeHostname(String),
+	GetRpcStats(RpcStatsWindow),
+	ExportState,
 
 	// Replies
 	Ok(String),
-	BucketList(Vec<Bucket>),
+	BucketList(Vec<BucketListItem>),
 	BucketInfo {
 		bucket: Bucket,
 		relevant_keys: HashMap<String, Key>,
 		counters: HashMap<String, i64>,
+		prefix_stats: Option<PrefixStatsResult>,
 	},
+	BucketLifecycle(Option<Vec<LifecycleRule>>),
 	KeyList(Vec<(String, String)>),
 	KeyInfo(Key, HashMap<Uuid, Bucket>),
 	WorkerList(
@@ -63,11 +82,20 @@ pub enum AdminRpc {
 	WorkerVars(Vec<(Uuid, String, String)>),
 	WorkerInfo(usize, garage_util::background::WorkerInfo),
 	BlockErrorList(Vec<BlockResyncErrorInfo>),
+	LocalBlockList(Vec<LocalBlockInfo>),
 	BlockInfo {
 		hash: Hash,
 		refcount: u64,
 		versions: Vec<Result<Version, Uuid>>,
 	},
+	ConnectionInfo(Vec<PeerConnectionInfo>),
+	PingResult(Vec<f64>),
+	RpcStats(Vec<RpcEndpointStats>),
+	ClusterState {
+		buckets: Vec<BucketImportEntry>,
+		keys: Vec<KeyImportEntry>,
+		skipped_buckets: Vec<Uuid>,
+	},
 }
 
 impl Rpc for AdminRpc {
@@ -157,7 +185,37 @@ impl AdminRpcHandler {
 
 	// ================ STATS COMMANDS ====================
 
+	async fn handle_ping_node(
+		&self,
+		node: Uuid,
+		count: usize,
+		interval: std::time::Duration,
+	) -> Result<AdminRpc, Error> {
+		if count == 0 {
+			return Err(Error::BadRequest("Ping count must be at least 1".into()));
+		}
+
+		let mut rtt_msec = Vec::with_capacity(count);
+		for i in 0..count {
+			if i > 0 {
+				tokio::time::sleep(interval).await;
+			}
+			let rtt = self.garage.system.ping_node(node).await?;
+			rtt_msec.push(rtt.as_secs_f64() * 1000.);
+		}
+		Ok(AdminRpc::PingResult(rtt_msec))
+	}
+
 	async fn handle_stats(&self, opt: StatsOpt) -> Result<AdminRpc, Error> {
+		if opt.export_prometheus {
+			if opt.all_nodes {
+				return Err(Error::BadRequest(
+					"--export-prometheus cannot be used with --all-nodes, export the statistics of each node separately.".into(),
+				));
+			}
+			return Ok(AdminRpc::Ok(self.gather_stats_prometheus(&opt)?));
+		}
+
 		if opt.all_nodes {
 			let mut ret = String::new();
 			let ring = self.garage.system.ring.borrow().clone();
@@ -250,6 +308,12 @@ impl AdminRpcHandler {
 			self.garage.block_manager.resync.queue_len()?
 		)
 		.unwrap();
+		writeln!(
+			&mut ret,
+			"  resync priority queue length: {}",
+			self.garage.block_manager.resync.priority_queue_len()?
+		)
+		.unwrap();
 		writeln!(
 			&mut ret,
 			"  blocks with resync errors: {}",
@@ -362,6 +426,169 @@ impl AdminRpcHandler {
 		ret
 	}
 
+	/// Statistics of this node in the Prometheus text format, using the names
+	/// of the corresponding metrics exported on /metrics by the admin API
+	fn gather_stats_prometheus(&self, opt: &StatsOpt) -> Result<String, Error> {
+		let mut ret = String::new();
+
+		let tables = [
+			self.gather_table_metrics(&self.garage.bucket_table, opt.detailed)?,
+			self.gather_table_metrics(&self.garage.key_table, opt.detailed)?,
+			self.gather_table_metrics(&self.garage.object_table, opt.detailed)?,
+			self.gather_table_metrics(&self.garage.version_table, opt.detailed)?,
+			self.gather_table_metrics(&self.garage.block_ref_table, opt.detailed)?,
+		];
+		let per_table = |f: fn(&TableMetrics) -> Option<usize>| {
+			tables
+				.iter()
+				.filter_map(|t| Some((format!("table_name=\"{}\"", t.name), f(t)? as u64)))
+				.collect::<Vec<_>>()
+		};
+		write_prometheus_gauge(
+			&mut ret,
+			"table_size",
+			"Number of items in table",
+			&per_table(|t| t.items),
+		);
+		write_prometheus_gauge(
+			&mut ret,
+			"table_merkle_tree_size",
+			"Number of nodes in table's Merkle tree",
+			&per_table(|t| t.merkle_items),
+		);
+		write_prometheus_gauge(
+			&mut ret,
+			"table_merkle_updater_todo_queue_length",
+			"Merkle tree updater TODO queue length",
+			&per_table(|t| Some(t.merkle_todo)),
+		);
+		write_prometheus_gauge(
+			&mut ret,
+			"table_gc_todo_queue_length",
+			"Table garbage collector TODO queue length",
+			&per_table(|t| Some(t.gc_todo)),
+		);
+
+		let block_manager = &self.garage.block_manager;
+		let rc_len = if opt.detailed {
+			Some(block_manager.rc_len()?)
+		} else {
+			block_manager.rc_fast_len()?
+		};
+		let block_gauges = [
+			(
+				"block_rc_size",
+				"Number of blocks known to the reference counter",
+				rc_len,
+			),
+			(
+				"block_resync_queue_length",
+				"Number of block hashes queued for local check and possible resync",
+				Some(block_manager.resync.queue_len()?),
+			),
+			(
+				"block_resync_priority_queue_length",
+				"Number of block hashes queued for resync with a priority",
+				Some(block_manager.resync.priority_queue_len()?),
+			),
+			(
+				"block_resync_errored_blocks",
+				"Number of block hashes whose last resync resulted in an error",
+				Some(block_manager.resync.errors_len()?),
+			),
+		];
+		for (name, help, value) in block_gauges {
+			let samples = value
+				.map(|v| vec![(String::new(), v as u64)])
+				.unwrap_or_default();
+			write_prometheus_gauge(&mut ret, name, help, &samples);
+		}
+
+		if !opt.skip_global {
+			let layout = &self.garage.system.ring.borrow().layout;
+			let mut partitions = layout.partition_counts().into_iter().collect::<Vec<_>>();
+			partitions.sort();
+			let node_status = self
+				.garage
+				.system
+				.get_known_nodes()
+				.into_iter()
+				.map(|n| (n.id, n.status))
+				.collect::<HashMap<_, _>>();
+			let disk = |f: fn(&(u64, u64)) -> u64| {
+				let mut samples = vec![];
+				for (id, _) in partitions.iter() {
+					let status = node_status.get(id);
+					let volumes = [
+						("data", status.and_then(|s| s.data_disk_avail)),
+						("metadata", status.and_then(|s| s.meta_disk_avail)),
+					];
+					for (volume, avail) in volumes {
+						if let Some(avail) = avail {
+							let labels =
+								format!("node_id=\"{}\",volume=\"{}\"", hex::encode(id), volume);
+							samples.push((labels, f(&avail)));
+						}
+					}
+				}
+				samples
+			};
+
+			write_prometheus_gauge(
+				&mut ret,
+				"cluster_node_partitions",
+				"Number of partitions stored by each storage node",
+				&partitions
+					.iter()
+					.map(|(id, n)| (format!("node_id=\"{}\"", hex::encode(id)), *n as u64))
+					.collect::<Vec<_>>(),
+			);
+			write_prometheus_gauge(
+				&mut ret,
+				"cluster_node_disk_avail",
+				"Available disk space on each storage node",
+				&disk(|d| d.0),
+			);
+			write_prometheus_gauge(
+				&mut ret,
+				"cluster_node_disk_total",
+				"Total disk space on each storage node",
+				&disk(|d| d.1),
+			);
+		}
+
+		Ok(ret)
+	}
+
+	fn gather_table_metrics<F, R>(
+		&self,
+		t: &Arc<Table<F, R>>,
+		detailed: bool,
+	) -> Result<TableMetrics, Error>
+	where
+		F: TableSchema + 'static,
+		R: TableReplication + 'static,
+	{
+		let (items, merkle_items) = if detailed {
+			(
+				Some(t.data.store.len().map_err(GarageError::from)?),
+				Some(t.merkle_updater.merkle_tree_len()?),
+			)
+		} else {
+			(
+				t.data.store.fast_len().map_err(GarageError::from)?,
+				t.merkle_updater.merkle_tree_fast_len()?,
+			)
+		};
+		Ok(TableMetrics {
+			name: F::TABLE_NAME,
+			items,
+			merkle_items,
+			merkle_todo: t.merkle_updater.todo_len()?,
+			gc_todo: t.data.gc_todo_len()?,
+		})
+	}
+
 	fn gather_table_stats<F, R>(
 		&self,
 		t: &Arc<Table<F, R>>,
@@ -517,6 +744,32 @@ impl AdminRpcHandler {
 	}
 }
 
+/// Statistics of a table, values that are not known without
+/// counting all entries are None if not in detailed mode
+struct TableMetrics {
+	name: &'static str,
+	items: Option<usize>,
+	merkle_items: Option<usize>,
+	merkle_todo: usize,
+	gc_todo: usize,
+}
+
+/// Write a gauge in the Prometheus text format, with one sample per set of labels
+fn write_prometheus_gauge(ret: &mut String, name: &str, help: &str, samples: &[(String, u64)]) {
+	if samples.is_empty() {
+		return;
+	}
+	writeln!(ret, "# HELP {} {}", name, help).unwrap();
+	writeln!(ret, "# TYPE {} gauge", name).unwrap();
+	for (labels, value) in samples.iter() {
+		if labels.is_empty() {
+			writeln!(ret, "{} {}", name, value).unwrap();
+		} else {
+			writeln!(ret, "{}{{{}}} {}", name, labels, value).unwrap();
+		}
+	}
+}
+
 #[async_trait]
 impl EndpointHandler<AdminRpc> for AdminRpcHandler {
 	async fn handle(
@@ -524,6 +777,7 @@ impl EndpointHandler<AdminRpc> for AdminRpcHandler {
 		message: &AdminRpc,
 		_from: NodeID,
 	) -> Result<AdminRpc, Error> {
+		let _running = RunningRpcHandler::start();
 		match message {
 			AdminRpc::BucketOperation(bo) => self.handle_bucket_cmd(bo).await,
 			AdminRpc::KeyOperation(ko) => self.handle_key_cmd(ko).await,
@@ -532,6 +786,28 @@ impl EndpointHandler<AdminRpc> for AdminRpcHandler {
 			AdminRpc::Stats(opt) => self.handle_stats(opt.clone()).await,
 			AdminRpc::Worker(wo) => self.handle_worker_cmd(wo).await,
 			AdminRpc::BlockOperation(bo) => self.handle_block_cmd(bo).await,
+			AdminRpc::GetConnectionInfo => Ok(AdminRpc::ConnectionInfo(
+				self.garage.system.get_peer_connections(),
+			)),
+			AdminRpc::PingNode {
+				node,
+				count,
+				interval,
+			} => self.handle_ping_node(*node, *count, *interval).await,
+			AdminRpc::SetNodeHostname(hostname) => {
+				self.garage
+					.system
+					.set_hostname(hostname.clone())
+					.map_err(|e| Error::BadRequest(e.to_string()))?;
+				Ok(AdminRpc::Ok(format!(
+					"Hostname of this node set to {}",
+					hostname
+				)))
+			}
+			AdminRpc::GetRpcStats(window) => Ok(AdminRpc::RpcStats(
+				self.garage.system.rpc.rpc_stats(*window),
+			)),
+			AdminRpc::ExportState => self.handle_export_state().await,
 			m => Err(GarageError::unexpected_rpc_message(m).into()),
 		}
 	}
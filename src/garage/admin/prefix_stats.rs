@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error;
+
+use garage_table::*;
+
+use garage_model::garage::Garage;
+use garage_model::s3::object_table::*;
+
+/// Number of prefixes returned in per-prefix statistics
+pub const PREFIX_STATS_TOP_N: usize = 100;
+/// Buckets with at most this number of objects have their per-prefix statistics
+/// computed synchronously, bigger buckets are scanned by a background worker
+pub const PREFIX_STATS_SYNC_MAX_OBJECTS: i64 = 10000;
+
+const PREFIX_STATS_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefixStatsEntry {
+	pub prefix: String,
+	pub objects: u64,
+	pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PrefixStatsResult {
+	/// Statistics were computed during the RPC call
+	Done(Vec<PrefixStatsEntry>),
+	/// The bucket is too big, statistics are being computed by a background worker
+	InBackground,
+}
+
+/// Aggregation of object counts and sizes by common key prefix
+pub struct PrefixStats {
+	depth: usize,
+	stats: HashMap<String, (u64, u64)>,
+}
+
+impl PrefixStats {
+	pub fn new(depth: usize) -> Self {
+		Self {
+			depth,
+			stats: HashMap::new(),
+		}
+	}
+
+	/// Extract the prefix of `key` made of its first `depth` path components
+	/// (not counting the last component, which is the object's name)
+	fn prefix(&self, key: &str) -> String {
+		let n_dirs = key.matches('/').count().min(self.depth);
+		let mut prefix = String::new();
+		for component in key.split('/').take(n_dirs) {
+			prefix.push_str(component);
+			prefix.push('/');
+		}
+		prefix
+	}
+
+	pub fn add(&mut self, object: &Object) {
+		let size = match object.current_data_version() {
+			Some(ObjectVersion {
+				state:
+					ObjectVersionState::Complete(
+						ObjectVersionData::Inline(meta, _) | ObjectVersionData::FirstBlock(meta, _),
+					),
+				..
+			}) => meta.size,
+			_ => return,
+		};
+		let entry = self.stats.entry(self.prefix(&object.key)).or_default();
+		entry.0 += 1;
+		entry.1 += size;
+	}
+
+	/// Returns the prefixes that use the most storage space
+	pub fn top(&self, n: usize) -> Vec<PrefixStatsEntry> {
+		let mut ret = self
+			.stats
+			.iter()
+			.map(|(prefix, (objects, bytes))| PrefixStatsEntry {
+				prefix: prefix.clone(),
+				objects: *objects,
+				bytes: *bytes,
+			})
+			.collect::<Vec<_>>();
+		ret.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.prefix.cmp(&b.prefix)));
+		ret.truncate(n);
+		ret
+	}
+}
+
+/// Scan all objects of a bucket and aggregate their statistics by prefix
+pub async fn compute_prefix_stats(
+	garage: &Garage,
+	bucket_id: Uuid,
+	depth: usize,
+) -> Result<Vec<PrefixStatsEntry>, Error> {
+	let mut stats = PrefixStats::new(depth);
+	let mut pos = None;
+	while let Some(next_pos) = scan_batch(garage, bucket_id, pos, &mut stats).await? {
+		pos = Some(next_pos);
+	}
+	Ok(stats.top(PREFIX_STATS_TOP_N))
+}
+
+/// Aggregate a batch of objects starting at `pos`, returns the position
+/// of the next batch if there are more objects in the bucket
+async fn scan_batch(
+	garage: &Garage,
+	bucket_id: Uuid,
+	pos: Option<String>,
+	stats: &mut PrefixStats,
+) -> Result<Option<String>, Error> {
+	let objects = garage
+		.object_table
+		.get_range(
+			&bucket_id,
+			pos.clone(),
+			Some(ObjectFilter::IsData),
+			PREFIX_STATS_BATCH_SIZE,
+			EnumerationOrder::Forward,
+		)
+		.await?;
+	for object in objects.iter() {
+		// get_range is inclusive of the start position,
+		// skip the object we already counted in the previous batch
+		if Some(&object.key) != pos.as_ref() {
+			stats.add(object);
+		}
+	}
+	if objects.len() < PREFIX_STATS_BATCH_SIZE {
+		Ok(None)
+	} else {
+		Ok(objects.last().map(|o| o.key.clone()))
+	}
+}
+
+// ----
+
+/// Worker that computes per-prefix statistics for buckets that
+/// are too big to be scanned during an RPC call. The results
+/// are logged and displayed in the worker's status once finished
+pub struct PrefixStatsWorker {
+	garage: Arc<Garage>,
+	bucket_id: Uuid,
+	pos: Option<String>,
+	stats: PrefixStats,
+	counter: usize,
+	result: Option<Vec<PrefixStatsEntry>>,
+}
+
+impl PrefixStatsWorker {
+	pub fn new(garage: Arc<Garage>, bucket_id: Uuid, depth: usize) -> Self {
+		Self {
+			garage,
+			bucket_id,
+			pos: None,
+			stats: PrefixStats::new(depth),
+			counter: 0,
+			result: None,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for PrefixStatsWorker {
+	fn name(&self) -> String {
+		format!("Prefix stats for bucket {:?}", self.bucket_id)
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let freeform = match &self.result {
+			Some(res) => res
+				.iter()
+				.map(|e| {
+					format!(
+						"{}\t{} objects\t{}",
+						if e.prefix.is_empty() { "/" } else { &e.prefix },
+						e.objects,
+						bytesize::ByteSize::b(e.bytes)
+					)
+				})
+				.collect(),
+			None => vec![],
+		};
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform,
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let next_pos = scan_batch(
+			&self.garage,
+			self.bucket_id,
+			self.pos.clone(),
+			&mut self.stats,
+		)
+		.await?;
+		self.counter += PREFIX_STATS_BATCH_SIZE;
+
+		match next_pos {
+			Some(p) => {
+				self.pos = Some(p);
+				Ok(WorkerState::Busy)
+			}
+			None => {
+				let result = self.stats.top(PREFIX_STATS_TOP_N);
+				info!(
+					"Prefix stats for bucket {:?} finished, top prefixes by size:",
+					self.bucket_id
+				);
+				for e in result.iter() {
+					info!("  {}\t{} objects\t{} bytes", e.prefix, e.objects, e.bytes);
+				}
+				self.result = Some(result);
+				Ok(WorkerState::Done)
+			}
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
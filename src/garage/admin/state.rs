@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use garage_table::*;
+
+use garage_model::helper::error::Error;
+
+use crate::cli::*;
+
+use super::*;
+
+impl AdminRpcHandler {
+	/// Describe all buckets and keys of the cluster in the format of the
+	/// import files, for `garage export-state`
+	pub(super) async fn handle_export_state(&self) -> Result<AdminRpc, Error> {
+		let buckets = self
+			.garage
+			.bucket_table
+			.get_range(
+				&EmptyKey,
+				None,
+				Some(DeletedFilter::NotDeleted),
+				10000,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+
+		// Buckets are identified by their global aliases in the import
+		// format, so buckets that only have local aliases cannot be exported
+		let mut bucket_names = HashMap::new();
+		let mut bucket_entries = vec![];
+		let mut skipped_buckets = vec![];
+		for bucket in buckets {
+			let params = bucket.params().unwrap();
+			let global_aliases = bucket
+				.aliases()
+				.iter()
+				.filter(|(_, _, active)| *active)
+				.map(|(name, _, _)| name.clone())
+				.collect::<Vec<_>>();
+			let name = match global_aliases.first() {
+				Some(name) => name.clone(),
+				None => {
+					skipped_buckets.push(bucket.id);
+					continue;
+				}
+			};
+			bucket_names.insert(bucket.id, name);
+
+			let quotas = params.quotas.get();
+			bucket_entries.push(BucketImportEntry {
+				global_aliases,
+				website_config: params.website_config.get().as_ref().map(|website| {
+					BucketImportWebsiteConfig {
+						index_document: website.index_document.clone(),
+						error_document: website.error_document.clone(),
+					}
+				}),
+				quotas: (quotas.max_size.is_some() || quotas.max_objects.is_some()).then_some(
+					BucketImportQuotas {
+						max_size: quotas.max_size,
+						max_objects: quotas.max_objects,
+					},
+				),
+			});
+		}
+
+		let keys = self
+			.garage
+			.key_table
+			.get_range(
+				&EmptyKey,
+				None,
+				Some(KeyFilter::Deleted(DeletedFilter::NotDeleted)),
+				10000,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+
+		let mut key_entries = vec![];
+		for key in keys {
+			let params = key.params().unwrap();
+			let bucket_permissions = params
+				.authorized_buckets
+				.items()
+				.iter()
+				.filter(|(_, perm)| perm.is_any())
+				.filter_map(|(bucket_id, perm)| {
+					Some(KeyImportBucketPermission {
+						bucket: bucket_names.get(bucket_id)?.clone(),
+						read: perm.allow_read,
+						write: perm.allow_write,
+						owner: perm.allow_owner,
+					})
+				})
+				.collect();
+			key_entries.push(KeyImportEntry {
+				access_key_id: key.key_id.clone(),
+				secret_access_key: params.secret_key.clone(),
+				name: Some(params.name.get().clone()),
+				allow_create_bucket: *params.allow_create_bucket.get(),
+				bucket_permissions,
+			});
+		}
+
+		Ok(AdminRpc::ClusterState {
+			buckets: bucket_entries,
+			keys: key_entries,
+			skipped_buckets,
+		})
+	}
+}
@@ -1,15 +1,24 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 use std::time::Duration;
 
-use format_table::format_table;
+use serde::Serialize;
+
+use garage_util::data::Uuid;
 use garage_util::error::*;
+use garage_util::time::*;
 
 use garage_rpc::layout::*;
+use garage_rpc::rpc_stats::RpcStatsWindow;
 use garage_rpc::system::*;
 use garage_rpc::*;
 
 use garage_model::helper::error::Error as HelperError;
 
+use garage_api::admin::{
+	bucket_info_result, key_info_result, list_bucket_result_item, ListKeyResultItem,
+};
+
 use crate::admin::*;
 use crate::cli::*;
 
@@ -18,37 +27,173 @@ pub async fn cli_command_dispatch(
 	system_rpc_endpoint: &Endpoint<SystemRpc, ()>,
 	admin_rpc_endpoint: &Endpoint<AdminRpc, ()>,
 	rpc_host: NodeID,
+	output: OutputFormat,
 ) -> Result<(), HelperError> {
 	match cmd {
-		Command::Status => Ok(cmd_status(system_rpc_endpoint, rpc_host).await?),
+		Command::Status(opt) => Ok(cmd_status(system_rpc_endpoint, rpc_host, opt, output).await?),
 		Command::Node(NodeOperation::Connect(connect_opt)) => {
 			Ok(cmd_connect(system_rpc_endpoint, rpc_host, connect_opt).await?)
 		}
+		Command::Node(NodeOperation::ShowConnections(opt)) => {
+			cmd_show_connections(admin_rpc_endpoint, rpc_host, opt, output).await
+		}
+		Command::Node(NodeOperation::Ping(opt)) => {
+			cmd_ping_node(
+				system_rpc_endpoint,
+				admin_rpc_endpoint,
+				rpc_host,
+				opt,
+				output,
+			)
+			.await
+		}
+		Command::Node(NodeOperation::ShowRpcStats(opt)) => {
+			cmd_show_rpc_stats(admin_rpc_endpoint, rpc_host, opt, output).await
+		}
+		Command::Node(NodeOperation::Drain(opt)) => {
+			Ok(cmd_drain_node(system_rpc_endpoint, rpc_host, opt).await?)
+		}
+		Command::Node(NodeOperation::SetHostname(opt)) => {
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				AdminRpc::SetNodeHostname(opt.hostname),
+				output,
+			)
+			.await
+		}
 		Command::Layout(layout_opt) => {
-			Ok(cli_layout_command_dispatch(layout_opt, system_rpc_endpoint, rpc_host).await?)
+			Ok(
+				cli_layout_command_dispatch(layout_opt, system_rpc_endpoint, rpc_host, output)
+					.await?,
+			)
+		}
+		Command::Bucket(BucketOperation::List(opt)) => {
+			cmd_list_buckets(admin_rpc_endpoint, rpc_host, opt, output).await
+		}
+		Command::Bucket(BucketOperation::SetLifecycle(opt)) => {
+			cmd_set_lifecycle(admin_rpc_endpoint, rpc_host, opt, output).await
+		}
+		Command::Bucket(BucketOperation::ImportFile(opt)) => {
+			cmd_import_buckets_file(admin_rpc_endpoint, rpc_host, opt, output).await
+		}
+		Command::Key(KeyOperation::ImportFile(opt)) => {
+			cmd_import_keys_file(admin_rpc_endpoint, rpc_host, opt, output).await
+		}
+		Command::ExportState(opt) => {
+			cmd_export_state(system_rpc_endpoint, admin_rpc_endpoint, rpc_host, opt).await
+		}
+		Command::ImportState(opt) => {
+			cmd_import_state(
+				system_rpc_endpoint,
+				admin_rpc_endpoint,
+				rpc_host,
+				opt,
+				output,
+			)
+			.await
 		}
 		Command::Bucket(bo) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::BucketOperation(bo)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				AdminRpc::BucketOperation(bo),
+				output,
+			)
+			.await
 		}
 		Command::Key(ko) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::KeyOperation(ko)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				AdminRpc::KeyOperation(ko),
+				output,
+			)
+			.await
 		}
 		Command::Migrate(mo) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Migrate(mo)).await
+			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Migrate(mo), output).await
 		}
 		Command::Repair(ro) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::LaunchRepair(ro)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				AdminRpc::LaunchRepair(ro),
+				output,
+			)
+			.await
 		}
-		Command::Stats(so) => cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Stats(so)).await,
-		Command::Worker(wo) => cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Worker(wo)).await,
+		Command::Stats(so) => {
+			let file = OutputFile::open(so.output_file.as_deref())?;
+			cmd_admin_to_file(
+				admin_rpc_endpoint,
+				rpc_host,
+				AdminRpc::Stats(so),
+				file,
+				output,
+			)
+			.await
+		}
+		Command::Worker(wo) => {
+			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Worker(wo), output).await
+		}
+		Command::Block(BlockOperation::ListErrors { output_file }) => {
+			let file = OutputFile::open(output_file.as_deref())?;
+			cmd_admin_to_file(
+				admin_rpc_endpoint,
+				rpc_host,
+				AdminRpc::BlockOperation(BlockOperation::ListErrors { output_file: None }),
+				file,
+				output,
+			)
+			.await
+		}
+		Command::Block(BlockOperation::ListLocal {
+			format,
+			prefix,
+			limit,
+			..
+		}) => cmd_list_local_blocks(admin_rpc_endpoint, rpc_host, format, prefix, limit).await,
 		Command::Block(bo) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::BlockOperation(bo)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				AdminRpc::BlockOperation(bo),
+				output,
+			)
+			.await
 		}
 		_ => unreachable!(),
 	}
 }
 
-pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) -> Result<(), Error> {
+pub async fn cmd_status(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	opt: StatusOpt,
+	output: OutputFormat,
+) -> Result<(), Error> {
+	if opt.watch {
+		if output == OutputFormat::Json {
+			return Err(Error::Message(
+				"--watch cannot be used with JSON output".into(),
+			));
+		}
+		return cmd_status_watch(rpc_cli, rpc_host, opt.interval).await;
+	}
+
+	let (status, layout) = fetch_status(rpc_cli, rpc_host).await?;
+	if output == OutputFormat::Json {
+		return print_json(&cluster_status_json(&status, &layout));
+	}
+	print_status(&status, &layout);
+	Ok(())
+}
+
+async fn fetch_status(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+) -> Result<(Vec<KnownNodeInfo>, ClusterLayout), Error> {
 	let status = match rpc_cli
 		.call(&rpc_host, SystemRpc::GetKnownNodes, PRIO_NORMAL)
 		.await??
@@ -57,7 +202,10 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 		resp => return Err(Error::Message(format!("Invalid RPC response: {:?}", resp))),
 	};
 	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+	Ok((status, layout))
+}
 
+fn print_status(status: &[KnownNodeInfo], layout: &ClusterLayout) {
 	println!("==== HEALTHY NODES ====");
 	let mut healthy_nodes =
 		vec!["ID\tHostname\tAddress\tTags\tZone\tCapacity\tDataAvail".to_string()];
@@ -78,7 +226,7 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 					id = adv.id,
 					host = adv.status.hostname,
 					addr = adv.addr,
-					tags = cfg.tags.join(","),
+					tags = format_tags(&cfg.tags),
 					zone = cfg.zone,
 					capacity = cfg.capacity_string(),
 					data_avail = data_avail,
@@ -99,7 +247,7 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 			}
 		}
 	}
-	format_table(healthy_nodes);
+	print_table(healthy_nodes);
 
 	let status_keys = status.iter().map(|adv| adv.id).collect::<HashSet<_>>();
 	let failure_case_1 = status
@@ -122,7 +270,7 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 					id = adv.id,
 					host = adv.status.hostname,
 					addr = adv.addr,
-					tags = cfg.tags.join(","),
+					tags = format_tags(&cfg.tags),
 					zone = cfg.zone,
 					capacity = cfg.capacity_string(),
 					last_seen = adv
@@ -138,25 +286,269 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 					failed_nodes.push(format!(
 						"{id:?}\t??\t??\t[{tags}]\t{zone}\t{capacity}\tnever seen",
 						id = id,
-						tags = cfg.tags.join(","),
+						tags = format_tags(&cfg.tags),
 						zone = cfg.zone,
 						capacity = cfg.capacity_string(),
 					));
 				}
 			}
 		}
-		format_table(failed_nodes);
+		print_table(failed_nodes);
 	}
 
-	if print_staging_role_changes(&layout) {
+	if layout.previous_replication_factor.is_some() {
+		println!("\n==== REPLICATION FACTOR CHANGE ====");
+		print_replication_factor_change(layout);
+		let lagging_nodes = status
+			.iter()
+			.filter(|adv| adv.is_up && adv.status.replication_factor != layout.replication_factor)
+			.map(|adv| format!("{:?}", adv.id))
+			.collect::<Vec<_>>();
+		if !lagging_nodes.is_empty() {
+			println!(
+				"Nodes still using the previous replication factor: {}",
+				lagging_nodes.join(", ")
+			);
+		}
+	}
+
+	let staged_replication_factor = print_staging_replication_factor(layout);
+	if print_staging_role_changes(layout) || staged_replication_factor {
 		println!();
 		println!("Please use `garage layout show` to check the proposed new layout and apply it.");
 		println!();
 	}
+}
 
+/// Print the status of the cluster every `interval` until Ctrl-C is pressed,
+/// followed by what changed since the previous refresh
+async fn cmd_status_watch(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	interval: Duration,
+) -> Result<(), Error> {
+	let mut must_exit = crate::server::watch_shutdown_signal();
+	let mut previous: Option<StatusSnapshot> = None;
+
+	while !*must_exit.borrow() {
+		let (status, layout) = fetch_status(rpc_cli, rpc_host).await?;
+		let snapshot = StatusSnapshot::new(&status, &layout);
+
+		// Clear the terminal and move the cursor to its top left corner
+		print!("\x1b[2J\x1b[H");
+		println!(
+			"Every {}: garage status\t{}",
+			format_interval(interval),
+			msec_to_rfc3339(now_msec())
+		);
+		println!();
+		print_status(&status, &layout);
+		if let Some(previous) = &previous {
+			println!();
+			println!("==== CHANGES SINCE LAST REFRESH ====");
+			print_status_changes(previous, &snapshot);
+		}
+		previous = Some(snapshot);
+
+		tokio::select! {
+			_ = tokio::time::sleep(interval) => (),
+			_ = must_exit.changed() => (),
+		}
+	}
 	Ok(())
 }
 
+fn format_interval(interval: Duration) -> String {
+	if interval.subsec_millis() == 0 {
+		format!("{}s", interval.as_secs())
+	} else {
+		format!("{}ms", interval.as_millis())
+	}
+}
+
+/// The parts of the cluster status that are compared between two refreshes
+/// of `garage status --watch`
+struct StatusSnapshot {
+	layout_version: u64,
+	connected_nodes: HashMap<Uuid, String>,
+	/// Number of partitions whose replicas are all on connected nodes
+	partitions_all_ok: usize,
+	/// Number of partitions with no replica on a connected node
+	partitions_unavailable: usize,
+}
+
+impl StatusSnapshot {
+	fn new(status: &[KnownNodeInfo], layout: &ClusterLayout) -> Self {
+		let connected_nodes = status
+			.iter()
+			.filter(|adv| adv.is_up)
+			.map(|adv| (adv.id, adv.status.hostname.clone()))
+			.collect::<HashMap<_, _>>();
+
+		let mut partitions_all_ok = 0;
+		let mut partitions_unavailable = 0;
+		if layout.replication_factor > 0 {
+			for partition in layout
+				.ring_assignation_data
+				.chunks(layout.replication_factor)
+			{
+				let up = partition
+					.iter()
+					.filter_map(|i| layout.node_id_vec.get(*i as usize))
+					.filter(|id| connected_nodes.contains_key(id))
+					.count();
+				if up == partition.len() {
+					partitions_all_ok += 1;
+				} else if up == 0 {
+					partitions_unavailable += 1;
+				}
+			}
+		}
+
+		Self {
+			layout_version: layout.version,
+			connected_nodes,
+			partitions_all_ok,
+			partitions_unavailable,
+		}
+	}
+}
+
+fn print_status_changes(previous: &StatusSnapshot, current: &StatusSnapshot) {
+	const GREEN: &str = "\x1b[32m";
+	const RED: &str = "\x1b[31m";
+	const RESET: &str = "\x1b[0m";
+
+	let mut changes = false;
+	let mut change = |color: &str, msg: String| {
+		println!("{}{}{}", color, msg, RESET);
+		changes = true;
+	};
+
+	for (id, hostname) in current.connected_nodes.iter() {
+		if !previous.connected_nodes.contains_key(id) {
+			change(GREEN, format!("+ {:?} ({}) is now connected", id, hostname));
+		}
+	}
+	for (id, hostname) in previous.connected_nodes.iter() {
+		if !current.connected_nodes.contains_key(id) {
+			change(
+				RED,
+				format!("- {:?} ({}) is no longer connected", id, hostname),
+			);
+		}
+	}
+	if current.layout_version != previous.layout_version {
+		change(
+			"",
+			format!(
+				"Cluster layout version changed from {} to {}",
+				previous.layout_version, current.layout_version
+			),
+		);
+	}
+	if current.partitions_all_ok != previous.partitions_all_ok {
+		let color = if current.partitions_all_ok > previous.partitions_all_ok {
+			GREEN
+		} else {
+			RED
+		};
+		change(
+			color,
+			format!(
+				"Partitions with all replicas connected: {} -> {}",
+				previous.partitions_all_ok, current.partitions_all_ok
+			),
+		);
+	}
+	if current.partitions_unavailable != previous.partitions_unavailable {
+		let color = if current.partitions_unavailable < previous.partitions_unavailable {
+			GREEN
+		} else {
+			RED
+		};
+		change(
+			color,
+			format!(
+				"Partitions with no connected replica: {} -> {}",
+				previous.partitions_unavailable, current.partitions_unavailable
+			),
+		);
+	}
+
+	if !changes {
+		println!("No changes.");
+	}
+}
+
+/// Output of `garage status` with `--output json`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ClusterStatusJson {
+	layout_version: u64,
+	nodes: Vec<NodeStatusJson>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeStatusJson {
+	id: String,
+	hostname: Option<String>,
+	address: Option<SocketAddr>,
+	is_up: bool,
+	last_seen_secs_ago: Option<u64>,
+	role: Option<NodeRole>,
+	staged_role_change: bool,
+	data_available_bytes: Option<u64>,
+	data_total_bytes: Option<u64>,
+}
+
+fn cluster_status_json(status: &[KnownNodeInfo], layout: &ClusterLayout) -> ClusterStatusJson {
+	let role = |id: &Uuid| match layout.roles.get(id) {
+		Some(NodeRoleV(Some(cfg))) => Some(cfg.clone()),
+		_ => None,
+	};
+	let staged = |id: &Uuid| layout.staging.get(id).is_some();
+
+	let mut nodes = status
+		.iter()
+		.map(|adv| NodeStatusJson {
+			id: hex::encode(adv.id),
+			hostname: Some(adv.status.hostname.clone()),
+			address: Some(adv.addr),
+			is_up: adv.is_up,
+			last_seen_secs_ago: adv.last_seen_secs_ago,
+			role: role(&adv.id),
+			staged_role_change: staged(&adv.id),
+			data_available_bytes: adv.status.data_disk_avail.map(|(avail, _)| avail),
+			data_total_bytes: adv.status.data_disk_avail.map(|(_, total)| total),
+		})
+		.collect::<Vec<_>>();
+
+	// Nodes that have a role in the layout but were never seen
+	let status_keys = status.iter().map(|adv| adv.id).collect::<HashSet<_>>();
+	for (id, _, _) in layout.roles.items().iter() {
+		if let (false, Some(cfg)) = (status_keys.contains(id), role(id)) {
+			nodes.push(NodeStatusJson {
+				id: hex::encode(id),
+				hostname: None,
+				address: None,
+				is_up: false,
+				last_seen_secs_ago: None,
+				role: Some(cfg),
+				staged_role_change: staged(id),
+				data_available_bytes: None,
+				data_total_bytes: None,
+			});
+		}
+	}
+
+	ClusterStatusJson {
+		layout_version: layout.version,
+		nodes,
+	}
+}
+
 pub async fn cmd_connect(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
@@ -174,24 +566,294 @@ pub async fn cmd_connect(
 	}
 }
 
+pub async fn cmd_drain_node(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	args: DrainNodeOpt,
+) -> Result<(), Error> {
+	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+	let storage_nodes = layout
+		.roles
+		.items()
+		.iter()
+		.filter(|(_, _, v)| matches!(v, NodeRoleV(Some(r)) if r.capacity.is_some()))
+		.map(|(id, _, _)| *id)
+		.collect::<Vec<_>>();
+	let node = find_matching_node(storage_nodes.into_iter(), &args.node_id)?;
+
+	match rpc_cli
+		.call(&rpc_host, SystemRpc::DrainNode(node), PRIO_NORMAL)
+		.await??
+	{
+		SystemRpc::Ok => (),
+		m => return Err(Error::unexpected_rpc_message(m)),
+	}
+	println!(
+		"Node {:?} is no longer a storage node in cluster layout version {}.",
+		node,
+		layout.version + 1
+	);
+
+	if args.no_wait {
+		println!("Its data blocks will now be moved to other nodes.");
+		return Ok(());
+	}
+
+	println!("Waiting for its partitions and data blocks to be moved to other nodes...");
+	let mut initial_blocks = None;
+	loop {
+		// The node still has to keep its data as long as it stores replicas of
+		// partitions, which is the case while the replication factor is being
+		// changed, even if it has no partitions in the new assignation
+		let partitions = fetch_layout(rpc_cli, rpc_host)
+			.await?
+			.partitions_stored_by(&node);
+
+		let known_nodes = match rpc_cli
+			.call(&rpc_host, SystemRpc::GetKnownNodes, PRIO_NORMAL)
+			.await??
+		{
+			SystemRpc::ReturnKnownNodes(nodes) => nodes,
+			resp => return Err(Error::unexpected_rpc_message(resp)),
+		};
+		let stored_blocks = known_nodes
+			.iter()
+			.find(|n| n.id == node)
+			.and_then(|n| n.status.stored_blocks);
+
+		match (partitions, stored_blocks) {
+			(_, None) => {
+				println!("The number of blocks stored on the node is unknown, cannot follow the transfer of its data.");
+				println!("Check that the node is connected, or use `garage stats` on that node.");
+				return Ok(());
+			}
+			(0, Some(0)) => {
+				println!("All partitions and data blocks have been moved, the node can now be removed from the cluster.");
+				return Ok(());
+			}
+			(partitions, Some(n)) => {
+				let initial = *initial_blocks.get_or_insert(n);
+				let done = initial.saturating_sub(n);
+				println!(
+					"{} partitions still stored on the node, {} blocks remaining ({:.1}% transferred)",
+					partitions,
+					n,
+					if initial > 0 {
+						done as f32 * 100. / initial as f32
+					} else {
+						100.
+					}
+				);
+			}
+		}
+
+		tokio::time::sleep(Duration::from_secs(10)).await;
+	}
+}
+
+pub async fn cmd_show_connections(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	opt: ShowConnectionsOpt,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	let mut conns = match rpc_cli
+		.call(&rpc_host, AdminRpc::GetConnectionInfo, PRIO_NORMAL)
+		.await??
+	{
+		AdminRpc::ConnectionInfo(conns) => conns,
+		m => return Err(Error::unexpected_rpc_message(m).into()),
+	};
+	conns.sort_by_key(|c| (!c.is_up, c.id));
+	let conns = conns
+		.into_iter()
+		.map(ConnectionListRow::from)
+		.collect::<Vec<_>>();
+
+	if opt.json || output == OutputFormat::Json {
+		let json = serde_json::to_string_pretty(&conns)
+			.map_err(|e| Error::Message(format!("Unable to serialize output: {}", e)))?;
+		println!("{}", json);
+	} else {
+		print_connection_list(conns);
+	}
+	Ok(())
+}
+
+pub async fn cmd_show_rpc_stats(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	opt: ShowRpcStatsOpt,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	let window = if opt.last_minute && !opt.since_restart {
+		RpcStatsWindow::LastMinute
+	} else {
+		RpcStatsWindow::SinceRestart
+	};
+	let stats = match rpc_cli
+		.call(&rpc_host, AdminRpc::GetRpcStats(window), PRIO_NORMAL)
+		.await??
+	{
+		AdminRpc::RpcStats(stats) => stats,
+		m => return Err(Error::unexpected_rpc_message(m).into()),
+	};
+
+	if opt.json || output == OutputFormat::Json {
+		print_json(&stats)?;
+	} else {
+		print_rpc_stats(&stats);
+	}
+	Ok(())
+}
+
+pub async fn cmd_ping_node(
+	rpc_system: &Endpoint<SystemRpc, ()>,
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	opt: PingNodeOpt,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	let known_nodes = match rpc_system
+		.call(&rpc_host, SystemRpc::GetKnownNodes, PRIO_NORMAL)
+		.await??
+	{
+		SystemRpc::ReturnKnownNodes(nodes) => nodes,
+		resp => return Err(Error::unexpected_rpc_message(resp).into()),
+	};
+	let node = find_matching_node(known_nodes.iter().map(|n| n.id), &opt.node_id)?;
+
+	let rtt_msec = match rpc_cli
+		.call(
+			&rpc_host,
+			AdminRpc::PingNode {
+				node,
+				count: opt.count,
+				interval: opt.interval,
+			},
+			PRIO_NORMAL,
+		)
+		.await??
+	{
+		AdminRpc::PingResult(rtt_msec) => rtt_msec,
+		m => return Err(Error::unexpected_rpc_message(m).into()),
+	};
+
+	let stats = PingStats::new(node, rtt_msec);
+	if opt.json || output == OutputFormat::Json {
+		print_json(&stats)?;
+	} else {
+		print_ping_stats(&stats);
+	}
+	Ok(())
+}
+
+pub async fn cmd_list_local_blocks(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	format: ListFormat,
+	prefix: String,
+	limit: Option<usize>,
+) -> Result<(), HelperError> {
+	// Blocks are fetched and printed page by page,
+	// as there might be too many of them to fit in a single response
+	let mut remaining = limit.unwrap_or(usize::MAX);
+	let mut after = None;
+	print_local_block_list_header(format);
+	while remaining > 0 {
+		let page_size = std::cmp::min(remaining, LIST_LOCAL_BLOCKS_PAGE_SIZE);
+		let req = BlockOperation::ListLocal {
+			format,
+			prefix: prefix.clone(),
+			limit: Some(page_size),
+			after,
+		};
+		let blocks = match rpc_cli
+			.call(&rpc_host, AdminRpc::BlockOperation(req), PRIO_NORMAL)
+			.await??
+		{
+			AdminRpc::LocalBlockList(blocks) => blocks,
+			m => return Err(Error::unexpected_rpc_message(m).into()),
+		};
+		print_local_block_list(format, &blocks);
+
+		if blocks.len() < page_size {
+			break;
+		}
+		remaining -= blocks.len();
+		after = blocks.last().map(|b| b.hash);
+	}
+	Ok(())
+}
+
+pub async fn cmd_list_buckets(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	opt: BucketListOpt,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	let sort_by = opt.sort_by;
+	let reverse = opt.reverse;
+	let mut buckets = match rpc_cli
+		.call(
+			&rpc_host,
+			AdminRpc::BucketOperation(BucketOperation::List(opt)),
+			PRIO_NORMAL,
+		)
+		.await??
+	{
+		AdminRpc::BucketList(bl) => bl,
+		m => return Err(Error::unexpected_rpc_message(m).into()),
+	};
+
+	match sort_by {
+		Some(BucketSortBy::Name) => buckets.sort_by_cached_key(bucket_display_name),
+		Some(BucketSortBy::Size) => buckets.sort_by_key(|b| std::cmp::Reverse(b.size_bytes)),
+		Some(BucketSortBy::Objects) => buckets.sort_by_key(|b| std::cmp::Reverse(b.object_count)),
+		None => (),
+	}
+	if reverse {
+		buckets.reverse();
+	}
+
+	if output == OutputFormat::Json {
+		let buckets = buckets
+			.iter()
+			.map(|b| list_bucket_result_item(&b.bucket))
+			.collect::<Vec<_>>();
+		print_json(&buckets)?;
+	} else {
+		print_bucket_list(buckets);
+	}
+	Ok(())
+}
+
 pub async fn cmd_admin(
 	rpc_cli: &Endpoint<AdminRpc, ()>,
 	rpc_host: NodeID,
 	args: AdminRpc,
+	output: OutputFormat,
 ) -> Result<(), HelperError> {
-	match rpc_cli.call(&rpc_host, args, PRIO_NORMAL).await?? {
+	let resp = rpc_cli.call(&rpc_host, args, PRIO_NORMAL).await??;
+	// Lifecycle rules are always printed as JSON
+	if output == OutputFormat::Json && !matches!(resp, AdminRpc::BucketLifecycle(_)) {
+		println!("{}", admin_response_json(resp)?);
+		return Ok(());
+	}
+	match resp {
 		AdminRpc::Ok(msg) => {
 			println!("{}", msg);
 		}
-		AdminRpc::BucketList(bl) => {
-			print_bucket_list(bl);
-		}
 		AdminRpc::BucketInfo {
 			bucket,
 			relevant_keys,
 			counters,
+			prefix_stats,
 		} => {
-			print_bucket_info(&bucket, &relevant_keys, &counters);
+			print_bucket_info(&bucket, &relevant_keys, &counters, prefix_stats);
+		}
+		AdminRpc::BucketLifecycle(rules) => {
+			print_lifecycle_rules(rules.as_deref().unwrap_or_default())?;
 		}
 		AdminRpc::KeyList(kl) => {
 			print_key_list(kl);
@@ -224,3 +886,109 @@ pub async fn cmd_admin(
 	}
 	Ok(())
 }
+
+/// Same as `cmd_admin`, but for commands whose output might be large:
+/// the output is written to `output`, which can be either stdout or a file
+pub async fn cmd_admin_to_file(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	args: AdminRpc,
+	file: OutputFile,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	let resp = rpc_cli.call(&rpc_host, args, PRIO_NORMAL).await??;
+	let out = match resp {
+		resp if output == OutputFormat::Json => format!("{}\n", admin_response_json(resp)?),
+		AdminRpc::Ok(msg) => format!("{}\n", msg),
+		AdminRpc::BlockErrorList(el) => format_block_error_list(el),
+		r => {
+			error!("Unexpected response: {:?}", r);
+			return Ok(());
+		}
+	};
+	file.write(&out)?;
+	Ok(())
+}
+
+/// Serialize the response to an admin command for the `--output json` flag,
+/// using the same structures as the admin API where one exists
+fn admin_response_json(resp: AdminRpc) -> Result<String, Error> {
+	match resp {
+		AdminRpc::Ok(msg) => to_json_string(&serde_json::json!({ "message": msg })),
+		AdminRpc::BucketInfo {
+			bucket,
+			relevant_keys,
+			counters,
+			..
+		} => to_json_string(&bucket_info_result(&bucket, relevant_keys, &counters)),
+		AdminRpc::KeyList(kl) => to_json_string(
+			&kl.into_iter()
+				.map(|(id, name)| ListKeyResultItem { id, name })
+				.collect::<Vec<_>>(),
+		),
+		AdminRpc::KeyInfo(key, rb) => to_json_string(&key_info_result(&key, rb)),
+		AdminRpc::WorkerList(wi, _) => {
+			let mut wi = wi.into_iter().collect::<Vec<_>>();
+			wi.sort_by_key(|(tid, _)| *tid);
+			to_json_string(
+				&wi.into_iter()
+					.map(|(tid, info)| serde_json::json!({ "id": tid, "info": info }))
+					.collect::<Vec<_>>(),
+			)
+		}
+		AdminRpc::WorkerInfo(tid, info) => {
+			to_json_string(&serde_json::json!({ "id": tid, "info": info }))
+		}
+		AdminRpc::WorkerVars(wv) => to_json_string(
+			&wv.into_iter()
+				.map(|(node, name, value)| {
+					serde_json::json!({
+						"node": hex::encode(node),
+						"name": name,
+						"value": value,
+					})
+				})
+				.collect::<Vec<_>>(),
+		),
+		AdminRpc::BlockErrorList(el) => to_json_string(
+			&el.into_iter()
+				.map(|e| {
+					serde_json::json!({
+						"hash": hex::encode(e.hash),
+						"refcount": e.refcount,
+						"errorCount": e.error_count,
+						"lastTry": e.last_try,
+						"nextTry": e.next_try,
+					})
+				})
+				.collect::<Vec<_>>(),
+		),
+		AdminRpc::BlockInfo {
+			hash,
+			refcount,
+			versions,
+		} => to_json_string(&serde_json::json!({
+			"hash": hex::encode(hash),
+			"refcount": refcount,
+			"versions": versions
+				.into_iter()
+				.map(|v| match v {
+					Ok(ver) => serde_json::json!({
+						"version": hex::encode(ver.uuid),
+						"bucketId": hex::encode(ver.bucket_id),
+						"key": ver.key,
+						"deleted": ver.deleted.get(),
+					}),
+					Err(vh) => serde_json::json!({
+						"version": hex::encode(vh),
+						"deleted": true,
+					}),
+				})
+				.collect::<Vec<_>>(),
+		})),
+		r => Err(Error::Message(format!(
+			"JSON output is not supported for this command (response: {:?})",
+			r
+		))),
+	}
+}
@@ -0,0 +1,50 @@
+use serde::de::DeserializeOwned;
+
+use garage_rpc::*;
+
+use garage_model::helper::error::Error as HelperError;
+
+use crate::admin::*;
+use crate::cli::*;
+
+pub async fn cmd_import_keys_file(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	mut opt: KeyImportFileOpt,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	opt.keys = read_import_file(&opt.path)?;
+	cmd_admin(
+		rpc_cli,
+		rpc_host,
+		AdminRpc::KeyOperation(KeyOperation::ImportFile(opt)),
+		output,
+	)
+	.await
+}
+
+pub async fn cmd_import_buckets_file(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	mut opt: BucketImportFileOpt,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	opt.buckets = read_import_file(&opt.path)?;
+	cmd_admin(
+		rpc_cli,
+		rpc_host,
+		AdminRpc::BucketOperation(BucketOperation::ImportFile(opt)),
+		output,
+	)
+	.await
+}
+
+/// Read the JSON content of a file to import. The whole file is parsed
+/// before anything is sent to the node, so that a syntax error in the
+/// file does not leave it half imported.
+pub(crate) fn read_import_file<T: DeserializeOwned>(path: &str) -> Result<T, HelperError> {
+	let json = std::fs::read_to_string(path)
+		.map_err(|e| HelperError::BadRequest(format!("Could not read {}: {}", path, e)))?;
+	serde_json::from_str(&json)
+		.map_err(|e| HelperError::BadRequest(format!("Invalid JSON in {}: {}", path, e)))
+}
@@ -1,5 +1,12 @@
-use format_table::format_table;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+
 use garage_util::crdt::Crdt;
+use garage_util::data::Uuid;
 use garage_util::error::*;
 
 use garage_rpc::layout::*;
@@ -12,6 +19,7 @@ pub async fn cli_layout_command_dispatch(
 	cmd: LayoutOperation,
 	system_rpc_endpoint: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
+	output: OutputFormat,
 ) -> Result<(), Error> {
 	match cmd {
 		LayoutOperation::Assign(configure_opt) => {
@@ -20,13 +28,29 @@ pub async fn cli_layout_command_dispatch(
 		LayoutOperation::Remove(remove_opt) => {
 			cmd_remove_role(system_rpc_endpoint, rpc_host, remove_opt).await
 		}
-		LayoutOperation::Show => cmd_show_layout(system_rpc_endpoint, rpc_host).await,
+		LayoutOperation::Show(show_opt) => {
+			cmd_show_layout(system_rpc_endpoint, rpc_host, show_opt, output).await
+		}
+		LayoutOperation::Export(export_opt) => {
+			cmd_export_layout(system_rpc_endpoint, rpc_host, export_opt).await
+		}
 		LayoutOperation::Apply(apply_opt) => {
 			cmd_apply_layout(system_rpc_endpoint, rpc_host, apply_opt).await
 		}
 		LayoutOperation::Revert(revert_opt) => {
 			cmd_revert_layout(system_rpc_endpoint, rpc_host, revert_opt).await
 		}
+		LayoutOperation::Check => cmd_check_layout(system_rpc_endpoint, rpc_host).await,
+		LayoutOperation::Rebalance(rebalance_opt) => {
+			cmd_rebalance_layout(system_rpc_endpoint, rpc_host, rebalance_opt).await
+		}
+		LayoutOperation::BalanceZones => cmd_balance_zones(system_rpc_endpoint, rpc_host).await,
+		LayoutOperation::Rollback(rollback_opt) => {
+			cmd_rollback_layout(system_rpc_endpoint, rpc_host, rollback_opt).await
+		}
+		LayoutOperation::ReplicationFactor(rf_opt) => {
+			cmd_replication_factor(system_rpc_endpoint, rpc_host, rf_opt).await
+		}
 	}
 }
 
@@ -98,10 +122,11 @@ pub async fn cmd_assign_role(
 				let tags = if args.tags.is_empty() {
 					old.tags.clone()
 				} else {
-					args.tags.clone()
+					args.tags.iter().map(|t| parse_tag(t)).collect()
 				};
 				NodeRole {
 					zone: args.zone.clone().unwrap_or_else(|| old.zone.to_string()),
+					rack: args.rack.clone().or_else(|| old.rack.clone()),
 					capacity,
 					tags,
 				}
@@ -118,8 +143,9 @@ pub async fn cmd_assign_role(
 						.zone
 						.clone()
 						.ok_or("Please specifiy a zone with the -z flag")?,
+					rack: args.rack.clone(),
 					capacity,
-					tags: args.tags.clone(),
+					tags: args.tags.iter().map(|t| parse_tag(t)).collect(),
 				}
 			}
 		};
@@ -165,9 +191,27 @@ pub async fn cmd_remove_role(
 pub async fn cmd_show_layout(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
+	opt: ShowLayoutOpt,
+	output: OutputFormat,
 ) -> Result<(), Error> {
 	let mut layout = fetch_layout(rpc_cli, rpc_host).await?;
 
+	if let Some(old_json_file) = opt.diff {
+		if output == OutputFormat::Json {
+			return Err(Error::Message(
+				"--diff cannot be used with JSON output".into(),
+			));
+		}
+		let old_roles = read_layout_json(&old_json_file)?;
+		layout.roles.merge(&layout.staging);
+		print_layout_diff(&old_roles, &layout);
+		return Ok(());
+	}
+
+	if output == OutputFormat::Json {
+		return print_json(&garage_api::admin::format_cluster_layout(&layout));
+	}
+
 	println!("==== CURRENT CLUSTER LAYOUT ====");
 	if !print_cluster_layout(&layout) {
 		println!("No nodes currently have a role in the cluster.");
@@ -175,9 +219,22 @@ pub async fn cmd_show_layout(
 	}
 	println!();
 	println!("Current cluster layout version: {}", layout.version);
+	println!("Zone redundancy: {}", layout.zone_redundancy_string());
+	println!("Replication factor: {}", layout.replication_factor);
+	print_replication_factor_change(&layout);
+	if !layout.history.is_empty() {
+		println!(
+			"Previous layout versions available for rollback: {}",
+			layout.history_versions()
+		);
+	}
 
-	if print_staging_role_changes(&layout) {
+	let staged_replication_factor = print_staging_replication_factor(&layout);
+	if print_staging_role_changes(&layout) || staged_replication_factor {
 		layout.roles.merge(&layout.staging);
+		if let Some(replication_factor) = layout.staged_replication_factor() {
+			layout.replication_factor = replication_factor;
+		}
 
 		println!();
 		println!("==== NEW CLUSTER LAYOUT AFTER APPLYING CHANGES ====");
@@ -188,7 +245,8 @@ pub async fn cmd_show_layout(
 
 		// this will print the stats of what partitions
 		// will move around when we apply
-		if layout.calculate_partition_assignation() {
+		if let Some(msg) = layout.calculate_partition_assignation() {
+			print_message(&msg);
 			println!("To enact the staged role changes, type:");
 			println!();
 			println!("    garage layout apply --version {}", layout.version + 1);
@@ -206,6 +264,19 @@ pub async fn cmd_show_layout(
 	Ok(())
 }
 
+/// Write the current cluster layout in the JSON format of
+/// `garage --output json layout show`, which `layout show --diff` can read
+pub async fn cmd_export_layout(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	export_opt: ExportLayoutOpt,
+) -> Result<(), Error> {
+	let file = OutputFile::open(export_opt.output_file.as_deref())?;
+	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+	let json = to_json_string(&garage_api::admin::format_cluster_layout(&layout))?;
+	file.write(&format!("{}\n", json))
+}
+
 pub async fn cmd_apply_layout(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
@@ -213,12 +284,255 @@ pub async fn cmd_apply_layout(
 ) -> Result<(), Error> {
 	let layout = fetch_layout(rpc_cli, rpc_host).await?;
 
-	let layout = layout.apply_staged_changes(apply_opt.version)?;
+	if apply_opt.dry_run {
+		if matches!(apply_opt.version, Some(v) if v != layout.version + 1) {
+			return Err(Error::Message("Invalid new layout version".into()));
+		}
+		let simulation = match rpc_cli
+			.call(
+				&rpc_host,
+				SystemRpc::SimulateLayoutApply(layout),
+				PRIO_NORMAL,
+			)
+			.await??
+		{
+			SystemRpc::LayoutApplySimulation(s) => s,
+			m => return Err(Error::unexpected_rpc_message(m)),
+		};
+		print_layout_apply_simulation(&simulation);
+		println!();
+		println!("Dry run: the new cluster layout has not been applied.");
+		return Ok(());
+	}
+
+	let old_replication_factor = layout.replication_factor;
+	let (layout, msg) = layout.apply_staged_changes(apply_opt.version)?;
+	print_message(&msg);
+	let replication_factor_changed = layout.replication_factor != old_replication_factor;
+	let version = layout.version;
 
 	send_layout(rpc_cli, rpc_host, layout).await?;
 
 	println!("New cluster layout with updated role assignation has been applied in cluster.");
 	println!("Data will now be moved around between nodes accordingly.");
+	if replication_factor_changed {
+		println!();
+		println!("The replication factor has been changed: nodes keep the data they no longer");
+		println!("store until all data has been copied to its new replicas. Once this is done,");
+		println!("finish the change with:");
+		println!();
+		println!(
+			"    garage layout replication-factor --finish --version {}",
+			version + 1
+		);
+	}
+
+	Ok(())
+}
+
+pub async fn cmd_rollback_layout(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	rollback_opt: RollbackLayoutOpt,
+) -> Result<(), Error> {
+	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+
+	match rpc_cli
+		.call(
+			&rpc_host,
+			SystemRpc::RollbackLayout(rollback_opt.version),
+			PRIO_NORMAL,
+		)
+		.await??
+	{
+		SystemRpc::Ok => (),
+		m => return Err(Error::unexpected_rpc_message(m)),
+	}
+
+	println!(
+		"Cluster layout version {} has been restored as version {}.",
+		rollback_opt.version,
+		layout.version + 1
+	);
+	println!("Data will now be moved around between nodes accordingly.");
+
+	Ok(())
+}
+
+pub async fn cmd_replication_factor(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	rf_opt: ReplicationFactorOpt,
+) -> Result<(), Error> {
+	let replication_factor = match rf_opt.replication_factor {
+		Some(rf) if !rf_opt.finish => rf,
+		_ => {
+			let layout = fetch_layout(rpc_cli, rpc_host).await?;
+			let replication_factor = layout.replication_factor;
+			let layout = layout.finish_replication_factor_change(rf_opt.version)?;
+			send_layout(rpc_cli, rpc_host, layout).await?;
+
+			println!(
+				"The change of the replication factor to {} is finished.",
+				replication_factor
+			);
+			println!("Nodes will now delete the data they no longer store.");
+			return Ok(());
+		}
+	};
+
+	match rpc_cli
+		.call(
+			&rpc_host,
+			SystemRpc::ProposeReplicationFactorChange(replication_factor),
+			PRIO_NORMAL,
+		)
+		.await??
+	{
+		SystemRpc::Ok => (),
+		m => return Err(Error::unexpected_rpc_message(m)),
+	}
+
+	println!(
+		"Replication factor {} has been staged in the cluster layout.",
+		replication_factor
+	);
+	println!("Use `garage layout show` to review the new assignation of partitions,");
+	println!("and `garage layout apply` to enact it.");
+
+	Ok(())
+}
+
+pub async fn cmd_rebalance_layout(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	rebalance_opt: RebalanceLayoutOpt,
+) -> Result<(), Error> {
+	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+
+	let uniformity = layout
+		.uniformity()
+		.ok_or_message("The current cluster layout is not valid, cannot rebalance it.")?;
+	println!(
+		"Current uniformity of the cluster layout: {:.3} (target: {:.3})",
+		uniformity, rebalance_opt.target_uniformity
+	);
+	println!();
+	if uniformity >= rebalance_opt.target_uniformity {
+		println!("The cluster layout is already balanced enough, nothing to do.");
+		return Ok(());
+	}
+
+	let new_version = layout.version + 1;
+	match rebalance_opt.version {
+		None => {
+			// Compute the new assignation only to show the proposed changes
+			let (_, msg) = layout.rebalance(Some(new_version), rebalance_opt.target_uniformity)?;
+			print_message(&msg);
+			println!("To apply these changes, run:");
+			println!(
+				"    garage layout rebalance --target-uniformity {} --version {}",
+				rebalance_opt.target_uniformity, new_version
+			);
+		}
+		Some(v) => {
+			let (layout, msg) = layout.rebalance(Some(v), rebalance_opt.target_uniformity)?;
+			print_message(&msg);
+			send_layout(rpc_cli, rpc_host, layout).await?;
+			println!("Rebalanced cluster layout has been applied in cluster.");
+			println!("Data will now be moved around between nodes accordingly.");
+		}
+	}
+
+	Ok(())
+}
+
+pub async fn cmd_balance_zones(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+) -> Result<(), Error> {
+	let mut layout = fetch_layout(rpc_cli, rpc_host).await?;
+
+	// Analyze the layout as it would be after applying staged changes
+	if layout.has_staged_changes() {
+		println!("Analyzing the cluster layout with staged role changes applied.");
+		println!();
+		layout.roles.merge(&layout.staging);
+		layout.roles.retain(|(_, _, v)| v.0.is_some());
+		if let Some(replication_factor) = layout.staged_replication_factor() {
+			layout.replication_factor = replication_factor;
+		}
+		match layout.calculate_partition_assignation() {
+			Some(msg) => print_message(&msg),
+			None => return Err(Error::Message(
+				"Could not calculate the assignation of partitions with the staged role changes."
+					.into(),
+			)),
+		}
+	}
+
+	let report = layout.zone_balance().ok_or_message(
+		"No partitions are assigned in the cluster layout yet, nothing to analyze.",
+	)?;
+
+	let ideal_replicas = report.ideal_replicas();
+	let ideal_capacity = report.ideal_capacity();
+	let mut table = vec!["Zone\tNodes\tCapacity\tReplicas\tIdeal\tDeviation\tStatus".to_string()];
+	for zone in report.zones.iter() {
+		let deviation = report.replicas_deviation(zone);
+		let status = if deviation > ZONE_BALANCE_TOLERANCE {
+			"over-represented"
+		} else if deviation < -ZONE_BALANCE_TOLERANCE {
+			"under-represented"
+		} else {
+			"ok"
+		};
+		table.push(format!(
+			"{}\t{}\t{}\t{}\t{:.0}\t{:+.1}%\t{}",
+			zone.zone,
+			zone.nodes,
+			zone.capacity,
+			zone.replicas,
+			ideal_replicas,
+			100. * deviation,
+			status
+		));
+	}
+	print_table(table);
+	println!();
+	println!(
+		"Partitions with replicas unevenly spread over zones: {} of {} ({:.1}%)",
+		report.imbalanced_partitions,
+		report.partitions,
+		report.imbalanced_percentage()
+	);
+	println!();
+
+	if report.is_balanced(ZONE_BALANCE_TOLERANCE) {
+		println!("All zones are equally represented in the cluster layout.");
+		return Ok(());
+	}
+
+	println!("WARNING: zones are not equally represented in the cluster layout,");
+	println!("which reduces the tolerance of the cluster to the loss of a zone.");
+	println!();
+	if report.zones.iter().all(|z| z.capacity == ideal_capacity) {
+		println!("All zones already have the same capacity, the partition assignation");
+		println!("can be improved with `garage layout rebalance`.");
+		return Ok(());
+	}
+	println!("Suggested capacity adjustments (keeping the total capacity unchanged):");
+	for zone in report.zones.iter() {
+		let diff = ideal_capacity as i64 - zone.capacity as i64;
+		if diff != 0 {
+			println!(
+				"    zone {}: capacity {} -> {} ({:+})",
+				zone.zone, zone.capacity, ideal_capacity, diff
+			);
+		}
+	}
+	println!();
+	println!("Capacities can be changed with `garage layout assign -c <capacity> <node_id>`.");
 
 	Ok(())
 }
@@ -238,8 +552,63 @@ pub async fn cmd_revert_layout(
 	Ok(())
 }
 
+pub async fn cmd_check_layout(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+) -> Result<(), Error> {
+	let status = match rpc_cli
+		.call(&rpc_host, SystemRpc::GetKnownNodes, PRIO_NORMAL)
+		.await??
+	{
+		SystemRpc::ReturnKnownNodes(nodes) => nodes,
+		resp => return Err(Error::Message(format!("Invalid RPC response: {:?}", resp))),
+	};
+	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+
+	if let Err(e) = layout.check() {
+		return Err(Error::Message(format!(
+			"Cluster layout version {} is invalid: {}",
+			layout.version, e
+		)));
+	}
+
+	let mut offline_nodes = vec!["ID\tTags\tZone\tCapacity\tLast seen".to_string()];
+	for id in layout.node_ids().iter() {
+		let adv = status.iter().find(|adv| adv.id == *id);
+		if adv.map(|adv| adv.is_up).unwrap_or(false) {
+			continue;
+		}
+		let role = layout.node_role(id).unwrap();
+		let tf = timeago::Formatter::new();
+		offline_nodes.push(format!(
+			"{id:?}\t[{tags}]\t{zone}\t{capacity}\t{last_seen}",
+			id = id,
+			tags = format_tags(&role.tags),
+			zone = role.zone,
+			capacity = role.capacity_string(),
+			last_seen = adv
+				.and_then(|adv| adv.last_seen_secs_ago)
+				.map(|s| tf.convert(Duration::from_secs(s)))
+				.unwrap_or_else(|| "never seen".into()),
+		));
+	}
+
+	println!("Cluster layout version {} is valid.", layout.version);
+	if offline_nodes.len() > 1 {
+		println!();
+		println!("WARNING: the following nodes of the layout are currently offline:");
+		print_table(offline_nodes);
+	}
+
+	Ok(())
+}
+
 // --- utility ---
 
+/// Relative difference to the ideal number of replicas above which
+/// a zone is reported as over or under-represented
+const ZONE_BALANCE_TOLERANCE: f32 = 0.05;
+
 pub async fn fetch_layout(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
@@ -268,30 +637,115 @@ pub async fn send_layout(
 	Ok(())
 }
 
+pub fn print_layout_apply_simulation(simulation: &LayoutApplySimulation) {
+	println!(
+		"==== CHANGES IN CLUSTER LAYOUT VERSION {} ====",
+		simulation.version
+	);
+	let mut table = vec!["ID\tPartitions\tChange\tData\tProjected data".to_string()];
+	let bytes = |b: Option<u64>| {
+		b.map(|b| bytesize::ByteSize::b(b).to_string())
+			.unwrap_or_else(|| "?".into())
+	};
+	for node in simulation.nodes.iter() {
+		table.push(format!(
+			"{:?}\t{} -> {}\t{:+}\t{}\t{}",
+			node.id,
+			node.partitions_before,
+			node.partitions_after,
+			node.partitions_after as i64 - node.partitions_before as i64,
+			bytes(node.data_bytes),
+			bytes(node.projected_data_bytes),
+		));
+	}
+	print_table(table);
+	println!();
+
+	println!(
+		"Partition replicas moved to a different node: {}",
+		simulation.moved_partition_replicas
+	);
+	if let Some(b) = simulation.bytes_per_partition_replica {
+		println!(
+			"Estimated data to transfer: {}",
+			bytesize::ByteSize::b(b * simulation.moved_partition_replicas as u64)
+		);
+	}
+
+	let health = &simulation.health;
+	println!(
+		"Cluster health with the new layout: {:?} ({}/{} storage nodes connected, {}/{} partitions with quorum)",
+		health.status,
+		health.storage_nodes_ok,
+		health.storage_nodes,
+		health.partitions_quorum,
+		health.partitions,
+	);
+}
+
+/// Print the report of a computation of the partition assignation
+pub fn print_message(msg: &Message) {
+	for line in msg.iter() {
+		println!("{}", line);
+	}
+}
+
 pub fn print_cluster_layout(layout: &ClusterLayout) -> bool {
-	let mut table = vec!["ID\tTags\tZone\tCapacity".to_string()];
+	let partition_counts = layout.partition_counts();
+	let mut table = vec!["ID\tTags\tZone\tRack\tCapacity\tPartitions".to_string()];
 	for (id, _, role) in layout.roles.items().iter() {
 		let role = match &role.0 {
 			Some(r) => r,
 			_ => continue,
 		};
-		let tags = role.tags.join(",");
+		let tags = format_tags(&role.tags);
 		table.push(format!(
-			"{:?}\t{}\t{}\t{}",
+			"{:?}\t{}\t{}\t{}\t{}\t{}",
 			id,
 			tags,
 			role.zone,
-			role.capacity_string()
+			role.rack_string(),
+			role.capacity_string(),
+			partition_counts.get(id).cloned().unwrap_or(0)
 		));
 	}
 	if table.len() == 1 {
 		false
 	} else {
-		format_table(table);
+		print_table(table);
 		true
 	}
 }
 
+/// Print the change of the replication factor in progress in the cluster, if any
+pub fn print_replication_factor_change(layout: &ClusterLayout) {
+	if let Some(previous) = layout.previous_replication_factor {
+		println!(
+			"The replication factor is being changed from {} to {}. Once all data has been",
+			previous, layout.replication_factor
+		);
+		println!(
+			"copied to its new replicas, finish the change with `garage layout replication-factor --finish --version {}`.",
+			layout.version + 1
+		);
+	}
+}
+
+pub fn print_staging_replication_factor(layout: &ClusterLayout) -> bool {
+	match layout.staged_replication_factor() {
+		Some(rf) if rf != layout.replication_factor => {
+			println!();
+			println!("==== STAGED REPLICATION FACTOR CHANGE ====");
+			println!(
+				"Replication factor: {} -> {}",
+				layout.replication_factor, rf
+			);
+			true
+		}
+		_ => false,
+	}
+}
+
 pub fn print_staging_role_changes(layout: &ClusterLayout) -> bool {
 	let has_changes = layout
 		.staging
@@ -302,27 +756,126 @@ pub fn print_staging_role_changes(layout: &ClusterLayout) -> bool {
 	if has_changes {
 		println!();
 		println!("==== STAGED ROLE CHANGES ====");
-		let mut table = vec!["ID\tTags\tZone\tCapacity".to_string()];
+		let mut table = vec!["ID\tTags\tZone\tRack\tCapacity".to_string()];
 		for (id, _, role) in layout.staging.items().iter() {
 			if layout.roles.get(id) == Some(role) {
 				continue;
 			}
 			if let Some(role) = &role.0 {
-				let tags = role.tags.join(",");
+				let tags = format_tags(&role.tags);
 				table.push(format!(
-					"{:?}\t{}\t{}\t{}",
+					"{:?}\t{}\t{}\t{}\t{}",
 					id,
 					tags,
 					role.zone,
+					role.rack_string(),
 					role.capacity_string()
 				));
 			} else {
 				table.push(format!("{:?}\tREMOVED", id));
 			}
 		}
-		format_table(table);
+		print_table(table);
 		true
 	} else {
 		false
 	}
 }
+
+/// Read the node roles of a layout saved with `garage --output json layout show`
+fn read_layout_json(path: &Path) -> Result<BTreeMap<Uuid, NodeRole>, Error> {
+	#[derive(Deserialize)]
+	struct SavedLayout {
+		roles: HashMap<String, Option<NodeRole>>,
+	}
+
+	let json = std::fs::read(path)
+		.map_err(|e| Error::Message(format!("Unable to read {}: {}", path.display(), e)))?;
+	let saved: SavedLayout = serde_json::from_slice(&json).map_err(|e| {
+		Error::Message(format!(
+			"{} is not a layout saved with `garage --output json layout show`: {}",
+			path.display(),
+			e
+		))
+	})?;
+
+	let mut roles = BTreeMap::new();
+	for (id, role) in saved.roles {
+		let node = hex::decode(&id)
+			.ok()
+			.and_then(|b| Uuid::try_from(&b))
+			.ok_or_message(format!("Invalid node ID in saved layout: {}", id))?;
+		if let Some(role) = role {
+			roles.insert(node, role);
+		}
+	}
+	Ok(roles)
+}
+
+/// Print the role changes between the saved roles and those of a layout,
+/// in the style of a diff: removed roles are prefixed by `-` and added ones
+/// by `+`, in red and green if the output is a terminal
+pub fn print_layout_diff(old_roles: &BTreeMap<Uuid, NodeRole>, layout: &ClusterLayout) {
+	let new_roles = layout
+		.roles
+		.items()
+		.iter()
+		.filter_map(|(id, _, role)| Some((*id, role.0.clone()?)))
+		.collect::<BTreeMap<_, _>>();
+
+	let format_role = |marker: &str, id: &Uuid, role: &NodeRole| {
+		format!(
+			"{}\t{:?}\t{}\t{}\t{}\t{}",
+			marker,
+			id,
+			format_tags(&role.tags),
+			role.zone,
+			role.rack_string(),
+			role.capacity_string()
+		)
+	};
+
+	let mut table = vec!["\tID\tTags\tZone\tRack\tCapacity".to_string()];
+	let (mut added, mut removed, mut changed) = (0, 0, 0);
+	let nodes = old_roles
+		.keys()
+		.chain(new_roles.keys())
+		.collect::<BTreeSet<_>>();
+	for id in nodes {
+		match (old_roles.get(id), new_roles.get(id)) {
+			(Some(old), Some(new)) if old == new => table.push(format_role("", id, new)),
+			(old, new) => {
+				if let Some(old) = old {
+					table.push(format_role("-", id, old));
+				}
+				if let Some(new) = new {
+					table.push(format_role("+", id, new));
+				}
+				match (old, new) {
+					(Some(_), Some(_)) => changed += 1,
+					(None, _) => added += 1,
+					(_, None) => removed += 1,
+				}
+			}
+		}
+	}
+
+	let color = std::io::stdout().is_terminal();
+	for line in format_table::format_table_to_string(table).lines() {
+		match line.chars().next() {
+			Some('+') if color => println!("\x1b[32m{}\x1b[0m", line),
+			Some('-') if color => println!("\x1b[31m{}\x1b[0m", line),
+			_ => println!("{}", line),
+		}
+	}
+
+	println!();
+	if added + removed + changed == 0 {
+		println!("No role changes.");
+	} else {
+		println!(
+			"{} nodes added, {} nodes removed, {} nodes changed.",
+			added, removed, changed
+		);
+	}
+}
@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+
+use garage_rpc::*;
+
+use garage_api::s3::lifecycle as s3;
+use garage_api::s3::xml::{IntValue, Value};
+
+use garage_util::error::Error as GarageError;
+
+use garage_model::bucket_table::LifecycleRule;
+use garage_model::helper::error::Error as HelperError;
+
+use crate::admin::*;
+use crate::cli::*;
+
+pub async fn cmd_set_lifecycle(
+	rpc_cli: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	mut opt: SetLifecycleOpt,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	let conf = match (&opt.from_file, &opt.from_xml) {
+		(Some(file), None) => {
+			let json = read_lifecycle_file(file)?;
+			let conf: JsonLifecycleConfiguration = serde_json::from_str(&json)
+				.map_err(|e| HelperError::BadRequest(format!("Invalid JSON in {}: {}", file, e)))?;
+			s3::LifecycleConfiguration::from(conf)
+		}
+		(None, Some(file)) => {
+			let xml = read_lifecycle_file(file)?;
+			quick_xml::de::from_str(&xml)
+				.map_err(|e| HelperError::BadRequest(format!("Invalid XML in {}: {}", file, e)))?
+		}
+		_ => {
+			return Err(HelperError::BadRequest(
+				"You must specify exactly one of --from-file or --from-xml".into(),
+			))
+		}
+	};
+
+	opt.rules = conf
+		.validate_into_garage_lifecycle_config()
+		.map_err(|e| HelperError::BadRequest(format!("Invalid lifecycle configuration: {}", e)))?;
+
+	cmd_admin(
+		rpc_cli,
+		rpc_host,
+		AdminRpc::BucketOperation(BucketOperation::SetLifecycle(opt)),
+		output,
+	)
+	.await
+}
+
+pub fn print_lifecycle_rules(rules: &[LifecycleRule]) -> Result<(), HelperError> {
+	let conf = JsonLifecycleConfiguration::from(
+		s3::LifecycleConfiguration::from_garage_lifecycle_config(rules),
+	);
+	let json = serde_json::to_string_pretty(&conf).map_err(GarageError::from)?;
+	println!("{}", json);
+	Ok(())
+}
+
+fn read_lifecycle_file(file: &str) -> Result<String, HelperError> {
+	std::fs::read_to_string(file)
+		.map_err(|e| HelperError::BadRequest(format!("Could not read {}: {}", file, e)))
+}
+
+// ---- JSON representation of lifecycle rules ----
+//
+// The JSON format is the one used by the AWS CLI (`aws s3api
+// put-bucket-lifecycle-configuration --lifecycle-configuration file://...`).
+// It is converted to and from the S3 XML structures, so that the validation
+// rules are the same as for the PutBucketLifecycleConfiguration endpoint.
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct JsonLifecycleConfiguration {
+	pub rules: Vec<JsonLifecycleRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct JsonLifecycleRule {
+	#[serde(rename = "ID", default, skip_serializing_if = "Option::is_none")]
+	pub id: Option<String>,
+	pub status: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub filter: Option<JsonLifecycleFilter>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub expiration: Option<JsonLifecycleExpiration>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub abort_incomplete_multipart_upload: Option<JsonAbortIncompleteMpu>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub noncurrent_version_expiration: Option<JsonNoncurrentVersionExpiration>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct JsonLifecycleFilter {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub and: Option<Box<JsonLifecycleFilter>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub prefix: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub object_size_greater_than: Option<i64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub object_size_less_than: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct JsonLifecycleExpiration {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub days: Option<i64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub date: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct JsonAbortIncompleteMpu {
+	pub days_after_initiation: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct JsonNoncurrentVersionExpiration {
+	pub noncurrent_days: i64,
+}
+
+impl From<JsonLifecycleConfiguration> for s3::LifecycleConfiguration {
+	fn from(conf: JsonLifecycleConfiguration) -> Self {
+		s3::LifecycleConfiguration {
+			xmlns: (),
+			lifecycle_rules: conf.rules.into_iter().map(Into::into).collect(),
+		}
+	}
+}
+
+impl From<JsonLifecycleRule> for s3::LifecycleRule {
+	fn from(rule: JsonLifecycleRule) -> Self {
+		s3::LifecycleRule {
+			id: rule.id.map(Value),
+			status: Value(rule.status),
+			prefix: rule.prefix.map(Value),
+			filter: rule.filter.map(Into::into),
+			expiration: rule.expiration.map(|x| s3::Expiration {
+				days: x.days.map(IntValue),
+				at_date: x.date.map(Value),
+			}),
+			abort_incomplete_mpu: rule.abort_incomplete_multipart_upload.map(|x| {
+				s3::AbortIncompleteMpu {
+					days: IntValue(x.days_after_initiation),
+				}
+			}),
+			noncurrent_version_expiration: rule.noncurrent_version_expiration.map(|x| {
+				s3::NoncurrentVersionExpiration {
+					days: IntValue(x.noncurrent_days),
+				}
+			}),
+		}
+	}
+}
+
+impl From<JsonLifecycleFilter> for s3::Filter {
+	fn from(filter: JsonLifecycleFilter) -> Self {
+		s3::Filter {
+			and: filter.and.map(|x| Box::new((*x).into())),
+			prefix: filter.prefix.map(Value),
+			size_gt: filter.object_size_greater_than.map(IntValue),
+			size_lt: filter.object_size_less_than.map(IntValue),
+		}
+	}
+}
+
+impl From<s3::LifecycleConfiguration> for JsonLifecycleConfiguration {
+	fn from(conf: s3::LifecycleConfiguration) -> Self {
+		JsonLifecycleConfiguration {
+			rules: conf.lifecycle_rules.into_iter().map(Into::into).collect(),
+		}
+	}
+}
+
+impl From<s3::LifecycleRule> for JsonLifecycleRule {
+	fn from(rule: s3::LifecycleRule) -> Self {
+		JsonLifecycleRule {
+			id: rule.id.map(|x| x.0),
+			status: rule.status.0,
+			prefix: rule.prefix.map(|x| x.0),
+			filter: rule.filter.map(Into::into),
+			expiration: rule.expiration.map(|x| JsonLifecycleExpiration {
+				days: x.days.map(|x| x.0),
+				date: x.at_date.map(|x| x.0),
+			}),
+			abort_incomplete_multipart_upload: rule.abort_incomplete_mpu.map(|x| {
+				JsonAbortIncompleteMpu {
+					days_after_initiation: x.days.0,
+				}
+			}),
+			noncurrent_version_expiration: rule.noncurrent_version_expiration.map(|x| {
+				JsonNoncurrentVersionExpiration {
+					noncurrent_days: x.days.0,
+				}
+			}),
+		}
+	}
+}
+
+impl From<s3::Filter> for JsonLifecycleFilter {
+	fn from(filter: s3::Filter) -> Self {
+		JsonLifecycleFilter {
+			and: filter.and.map(|x| Box::new((*x).into())),
+			prefix: filter.prefix.map(|x| x.0),
+			object_size_greater_than: filter.size_gt.map(|x| x.0),
+			object_size_less_than: filter.size_lt.map(|x| x.0),
+		}
+	}
+}
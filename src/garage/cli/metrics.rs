@@ -0,0 +1,105 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+
+use hyper::body::Bytes;
+use hyper::header::{AUTHORIZATION, HOST};
+use hyper::{Body, Request, StatusCode};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+
+use garage_util::config::read_config;
+use garage_util::error::*;
+use garage_util::socket_address::UnixOrTCPSocketAddress;
+
+use crate::cli::*;
+use crate::{fill_secrets, Secrets};
+
+pub async fn export_metrics_snapshot(
+	config_file: PathBuf,
+	secrets: Secrets,
+	opt: ExportMetricsSnapshotOpt,
+) -> Result<(), Error> {
+	let config = fill_secrets(read_config(config_file)?, secrets);
+	let output = OutputFile::open(opt.output_file.as_deref())?;
+
+	let addr = config
+		.admin
+		.api_bind_addr
+		.as_ref()
+		.ok_or("The admin API is not enabled on this node (admin.api_bind_addr is not set)")?;
+
+	// The Prometheus metrics are served on /metrics and protected by the
+	// metrics token, the structured statistics of the cluster are served
+	// by the admin API and protected by the admin token
+	let (path, token) = match opt.format {
+		MetricsFormat::Prometheus => ("/metrics", opt.metrics_token.or(config.admin.metrics_token)),
+		MetricsFormat::Json => ("/v0/health", opt.admin_token.or(config.admin.admin_token)),
+	};
+	let mut req = Request::get(path).header(HOST, "localhost");
+	if let Some(token) = token {
+		req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+	}
+	let req = req.body(Body::empty())?;
+
+	let (status, body) = match addr {
+		UnixOrTCPSocketAddress::TCPSocket(addr) => {
+			let addr = connectable_addr(*addr);
+			let stream = TcpStream::connect(addr)
+				.await
+				.err_context(format!("Unable to connect to admin API at {}", addr))?;
+			send_request(stream, req).await?
+		}
+		UnixOrTCPSocketAddress::UnixSocket(path) => {
+			let stream = UnixStream::connect(path).await.err_context(format!(
+				"Unable to connect to admin API at {}",
+				path.display()
+			))?;
+			send_request(stream, req).await?
+		}
+	};
+
+	let body = String::from_utf8_lossy(&body);
+	if status != StatusCode::OK {
+		return Err(Error::Message(format!(
+			"Admin API returned {} when fetching metrics: {}",
+			status,
+			body.trim()
+		)));
+	}
+
+	let out = match opt.format {
+		MetricsFormat::Prometheus => body.into_owned(),
+		MetricsFormat::Json => {
+			let stats = serde_json::from_str::<serde_json::Value>(&body)?;
+			format!("{}\n", serde_json::to_string_pretty(&stats)?)
+		}
+	};
+	output.write(&out)
+}
+
+/// The admin API may listen on all interfaces,
+/// in which case we contact it on the loopback interface
+fn connectable_addr(addr: SocketAddr) -> SocketAddr {
+	match addr.ip() {
+		IpAddr::V4(ip) if ip.is_unspecified() => {
+			SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), addr.port())
+		}
+		IpAddr::V6(ip) if ip.is_unspecified() => {
+			SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port())
+		}
+		_ => addr,
+	}
+}
+
+async fn send_request<S>(stream: S, req: Request<Body>) -> Result<(StatusCode, Bytes), Error>
+where
+	S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+	let (mut sender, conn) = hyper::client::conn::handshake(stream).await?;
+	tokio::spawn(conn);
+
+	let resp = sender.send_request(req).await?;
+	let status = resp.status();
+	let body = hyper::body::to_bytes(resp.into_body()).await?;
+	Ok((status, body))
+}
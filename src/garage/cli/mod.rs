@@ -1,11 +1,19 @@
 pub(crate) mod cmd;
+pub(crate) mod import;
 pub(crate) mod init;
 pub(crate) mod layout;
+pub(crate) mod lifecycle;
+pub(crate) mod metrics;
+pub(crate) mod state;
 pub(crate) mod structs;
 pub(crate) mod util;
 
 pub(crate) use cmd::*;
+pub(crate) use import::*;
 pub(crate) use init::*;
 pub(crate) use layout::*;
+pub(crate) use lifecycle::*;
+pub(crate) use metrics::*;
+pub(crate) use state::*;
 pub(crate) use structs::*;
 pub(crate) use util::*;
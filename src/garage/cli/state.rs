@@ -0,0 +1,131 @@
+use garage_util::crdt::Crdt;
+use garage_util::data::*;
+use garage_util::error::Error as GarageError;
+
+use garage_rpc::layout::*;
+use garage_rpc::system::*;
+use garage_rpc::*;
+
+use garage_model::helper::error::Error as HelperError;
+
+use crate::admin::*;
+use crate::cli::*;
+
+pub async fn cmd_export_state(
+	system_rpc_endpoint: &Endpoint<SystemRpc, ()>,
+	admin_rpc_endpoint: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	opt: ExportStateOpt,
+) -> Result<(), HelperError> {
+	let layout = fetch_layout(system_rpc_endpoint, rpc_host).await?;
+	let roles = layout
+		.roles
+		.items()
+		.iter()
+		.filter_map(|(id, _, role)| Some((hex::encode(id), role.0.clone()?)))
+		.collect();
+
+	let (buckets, keys, skipped_buckets) = match admin_rpc_endpoint
+		.call(&rpc_host, AdminRpc::ExportState, PRIO_NORMAL)
+		.await??
+	{
+		AdminRpc::ClusterState {
+			buckets,
+			keys,
+			skipped_buckets,
+		} => (buckets, keys, skipped_buckets),
+		m => return Err(GarageError::unexpected_rpc_message(m).into()),
+	};
+
+	let state = ClusterStateFile {
+		roles,
+		buckets,
+		keys,
+	};
+	let json = serde_json::to_string_pretty(&state).map_err(GarageError::from)?;
+	std::fs::write(&opt.path, json + "\n").map_err(|e| {
+		HelperError::BadRequest(format!("Could not write {}: {}", opt.path.display(), e))
+	})?;
+
+	println!(
+		"Exported {} node roles, {} buckets and {} keys to {}.",
+		state.roles.len(),
+		state.buckets.len(),
+		state.keys.len(),
+		opt.path.display()
+	);
+	for id in skipped_buckets {
+		println!(
+			"Warning: bucket {:?} has no global alias and was not exported.",
+			id
+		);
+	}
+	Ok(())
+}
+
+pub async fn cmd_import_state(
+	system_rpc_endpoint: &Endpoint<SystemRpc, ()>,
+	admin_rpc_endpoint: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	opt: ImportStateOpt,
+	output: OutputFormat,
+) -> Result<(), HelperError> {
+	let path = opt.path.to_string_lossy();
+	let state: ClusterStateFile = read_import_file(&path)?;
+
+	let mut node_roles = vec![];
+	for (id, role) in state.roles {
+		let node_id = hex::decode(&id)
+			.ok()
+			.and_then(|bytes| Uuid::try_from(&bytes))
+			.ok_or_else(|| HelperError::BadRequest(format!("Invalid node ID: {}", id)))?;
+		node_roles.push((node_id, role));
+	}
+
+	// Node roles are only staged, not applied: the operator should
+	// review the resulting layout before data starts moving around
+	let mut layout = fetch_layout(system_rpc_endpoint, rpc_host).await?;
+	let mut roles = layout.roles.clone();
+	roles.merge(&layout.staging);
+	let mut staged = 0;
+	for (node_id, role) in node_roles {
+		if roles.get(&node_id).and_then(|r| r.0.as_ref()) != Some(&role) {
+			layout
+				.staging
+				.merge(&roles.update_mutator(node_id, NodeRoleV(Some(role))));
+			staged += 1;
+		}
+	}
+	if staged > 0 {
+		send_layout(system_rpc_endpoint, rpc_host, layout).await?;
+		println!(
+			"{} role changes are staged. Use `garage layout show` to review them,",
+			staged
+		);
+		println!("and `garage layout apply` to enact them.");
+	} else {
+		println!("All node roles are already assigned.");
+	}
+
+	// Buckets must be imported first, as key permissions refer to them
+	cmd_admin(
+		admin_rpc_endpoint,
+		rpc_host,
+		AdminRpc::BucketOperation(BucketOperation::ImportFile(BucketImportFileOpt {
+			path: path.to_string(),
+			buckets: state.buckets,
+		})),
+		output,
+	)
+	.await?;
+	cmd_admin(
+		admin_rpc_endpoint,
+		rpc_host,
+		AdminRpc::KeyOperation(KeyOperation::ImportFile(KeyImportFileOpt {
+			path: path.to_string(),
+			keys: state.keys,
+		})),
+		output,
+	)
+	.await
+}
@@ -1,17 +1,25 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
 use garage_util::version::garage_version;
 
+use garage_rpc::layout::NodeRole;
+
+use garage_model::bucket_table::LifecycleRule;
+
 #[derive(StructOpt, Debug)]
 pub enum Command {
 	/// Run Garage server
 	#[structopt(name = "server", version = garage_version())]
-	Server,
+	Server(ServerOpt),
 
 	/// Get network status
 	#[structopt(name = "status", version = garage_version())]
-	Status,
+	Status(StatusOpt),
 
 	/// Operations on individual Garage nodes
 	#[structopt(name = "node", version = garage_version())]
@@ -29,6 +37,14 @@ pub enum Command {
 	#[structopt(name = "key", version = garage_version())]
 	Key(KeyOperation),
 
+	/// Write the node roles, buckets and keys of the cluster to a JSON file
+	#[structopt(name = "export-state", version = garage_version())]
+	ExportState(ExportStateOpt),
+
+	/// Restore the node roles, buckets and keys written by `export-state`
+	#[structopt(name = "import-state", version = garage_version())]
+	ImportState(ImportStateOpt),
+
 	/// Run migrations from previous Garage version
 	/// (DO NOT USE WITHOUT READING FULL DOCUMENTATION)
 	#[structopt(name = "migrate", version = garage_version())]
@@ -54,6 +70,85 @@ pub enum Command {
 	/// Low-level debug operations on data blocks
 	#[structopt(name = "block", version = garage_version())]
 	Block(BlockOperation),
+
+	/// Print a shell completion script for the Garage CLI
+	#[structopt(name = "completion", version = garage_version())]
+	Completion(ShellOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ShellOpt {
+	/// Shell for which to generate the completion script
+	#[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+	pub shell: Shell,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct StatusOpt {
+	/// Refresh the status continuously, highlighting changes, until Ctrl-C is pressed
+	#[structopt(short = "w", long = "watch")]
+	pub(crate) watch: bool,
+
+	/// Interval between two refreshes in watch mode (e.g. 5s, 1m)
+	#[structopt(long = "interval", default_value = "5s", parse(try_from_str = parse_duration::parse::parse))]
+	pub(crate) interval: std::time::Duration,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ServerOpt {
+	#[structopt(subcommand)]
+	pub cmd: Option<ServerOperation>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum ServerOperation {
+	/// Fetch the metrics of the Garage node running on this machine once,
+	/// through its admin API
+	#[structopt(name = "export-metrics-snapshot", version = garage_version())]
+	ExportMetricsSnapshot(ExportMetricsSnapshotOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExportMetricsSnapshotOpt {
+	/// Output format: `prometheus` for the Prometheus metrics of the node,
+	/// `json` for the health statistics of the cluster from the admin API
+	#[structopt(long = "format", default_value = "prometheus")]
+	pub format: MetricsFormat,
+
+	/// Token used to fetch the Prometheus metrics
+	/// (defaults to `admin.metrics_token` from the configuration)
+	#[structopt(long = "metrics-token")]
+	pub metrics_token: Option<String>,
+
+	/// Token used to fetch the statistics of the cluster in JSON
+	/// (defaults to `admin.admin_token` from the configuration)
+	#[structopt(long = "admin-token")]
+	pub admin_token: Option<String>,
+
+	/// Write the metrics to this file instead of stdout (`-` for stdout)
+	#[structopt(long = "output-file")]
+	pub output_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MetricsFormat {
+	Prometheus,
+	Json,
+}
+
+impl std::str::FromStr for MetricsFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"prometheus" => Ok(MetricsFormat::Prometheus),
+			"json" => Ok(MetricsFormat::Json),
+			_ => Err(format!(
+				"invalid format '{}', expected prometheus or json",
+				s
+			)),
+		}
+	}
 }
 
 #[derive(StructOpt, Debug)]
@@ -65,6 +160,35 @@ pub enum NodeOperation {
 	/// Connect to Garage node that is currently isolated from the system
 	#[structopt(name = "connect", version = garage_version())]
 	Connect(ConnectNodeOpt),
+
+	/// List RPC connections of the Garage node to other cluster nodes
+	#[structopt(name = "show-connections", version = garage_version())]
+	ShowConnections(ShowConnectionsOpt),
+
+	/// Measure RPC round-trip time from the Garage node to another cluster node
+	#[structopt(name = "ping", version = garage_version())]
+	Ping(PingNodeOpt),
+
+	/// Change the hostname shown for this node (until it is restarted,
+	/// use `node_hostname` in the config file to make it permanent)
+	#[structopt(name = "set-hostname", version = garage_version())]
+	SetHostname(SetHostnameOpt),
+
+	/// Show the number of calls, errors and latencies of the RPCs
+	/// emitted by the Garage node, for each RPC endpoint
+	#[structopt(name = "show-rpc-stats", version = garage_version())]
+	ShowRpcStats(ShowRpcStatsOpt),
+
+	/// Remove the storage role of a node in a new version of the cluster layout,
+	/// and follow the transfer of its data blocks to other nodes
+	#[structopt(name = "drain", version = garage_version())]
+	Drain(DrainNodeOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct SetHostnameOpt {
+	/// New hostname of the node
+	pub(crate) hostname: String,
 }
 
 #[derive(StructOpt, Debug)]
@@ -81,6 +205,57 @@ pub struct ConnectNodeOpt {
 	pub(crate) node: String,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct ShowConnectionsOpt {
+	/// Output connection information as JSON
+	#[structopt(long = "json")]
+	pub(crate) json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ShowRpcStatsOpt {
+	/// Show statistics of all RPCs since the node was started (default)
+	#[structopt(long = "since-restart")]
+	pub(crate) since_restart: bool,
+
+	/// Show statistics of the RPCs of the last minute
+	#[structopt(long = "last-minute", conflicts_with = "since-restart")]
+	pub(crate) last_minute: bool,
+
+	/// Output RPC statistics as JSON
+	#[structopt(long = "json")]
+	pub(crate) json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PingNodeOpt {
+	/// ID of the node to ping (a prefix of the node ID is enough)
+	pub(crate) node_id: String,
+
+	/// Number of pings to send
+	#[structopt(short = "c", long = "count", default_value = "5")]
+	pub(crate) count: usize,
+
+	/// Interval between two pings (e.g. 500ms, 1s)
+	#[structopt(short = "i", long = "interval", default_value = "1s", parse(try_from_str = parse_duration::parse::parse))]
+	pub(crate) interval: std::time::Duration,
+
+	/// Output ping statistics as JSON
+	#[structopt(long = "json")]
+	pub(crate) json: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct DrainNodeOpt {
+	/// ID of the node to drain (a prefix of the node ID is enough)
+	pub(crate) node_id: String,
+
+	/// Return once the new cluster layout is applied,
+	/// without waiting for the data blocks to be transferred
+	#[structopt(long = "no-wait")]
+	pub(crate) no_wait: bool,
+}
+
 #[derive(StructOpt, Debug)]
 pub enum LayoutOperation {
 	/// Assign role to Garage node
@@ -93,7 +268,11 @@ pub enum LayoutOperation {
 
 	/// Show roles currently assigned to nodes and changes staged for commit
 	#[structopt(name = "show", version = garage_version())]
-	Show,
+	Show(ShowLayoutOpt),
+
+	/// Export the current cluster layout as JSON
+	#[structopt(name = "export", version = garage_version())]
+	Export(ExportLayoutOpt),
 
 	/// Apply staged changes to cluster layout
 	#[structopt(name = "apply", version = garage_version())]
@@ -102,6 +281,42 @@ pub enum LayoutOperation {
 	/// Revert staged changes to cluster layout
 	#[structopt(name = "revert", version = garage_version())]
 	Revert(RevertLayoutOpt),
+
+	/// Check that the current cluster layout is valid and that all its nodes are online
+	#[structopt(name = "check", version = garage_version())]
+	Check,
+
+	/// Move partitions between nodes so that data is spread more evenly
+	#[structopt(name = "rebalance", version = garage_version())]
+	Rebalance(RebalanceLayoutOpt),
+
+	/// Check that all zones store an equal share of the replicas of each partition
+	#[structopt(name = "balance-zones", version = garage_version())]
+	BalanceZones,
+
+	/// Restore the roles of a previous version of the cluster layout
+	#[structopt(name = "rollback", version = garage_version())]
+	Rollback(RollbackLayoutOpt),
+
+	/// Change the number of copies of data stored in the cluster
+	#[structopt(name = "replication-factor", version = garage_version())]
+	ReplicationFactor(ReplicationFactorOpt),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ShowLayoutOpt {
+	/// Show the role changes between a layout saved with
+	/// `garage --output json layout show` and the layout that
+	/// would result from applying the staged changes
+	#[structopt(long = "diff", name = "old-json-file")]
+	pub diff: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExportLayoutOpt {
+	/// Write the layout to this file instead of stdout (`-` for stdout)
+	#[structopt(long = "output-file")]
+	pub output_file: Option<String>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -114,6 +329,10 @@ pub struct AssignRoleOpt {
 	#[structopt(short = "z", long = "zone")]
 	pub(crate) zone: Option<String>,
 
+	/// Rack of the node in its zone
+	#[structopt(short = "r", long = "rack")]
+	pub(crate) rack: Option<String>,
+
 	/// Capacity (in relative terms, use 1 to represent your smallest server)
 	#[structopt(short = "c", long = "capacity")]
 	pub(crate) capacity: Option<u32>,
@@ -122,7 +341,7 @@ pub struct AssignRoleOpt {
 	#[structopt(short = "g", long = "gateway")]
 	pub(crate) gateway: bool,
 
-	/// Optional tags to add to node
+	/// Optional tags to add to node, as `key=value` (or `key` for an empty value)
 	#[structopt(short = "t", long = "tag")]
 	pub(crate) tags: Vec<String>,
 
@@ -143,6 +362,50 @@ pub struct ApplyLayoutOpt {
 	/// it is not exactly 1 + the previous configuration's version
 	#[structopt(long = "version")]
 	pub(crate) version: Option<u64>,
+
+	/// Only show the changes that applying the staged role changes would make
+	#[structopt(long = "dry-run")]
+	pub(crate) dry_run: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RollbackLayoutOpt {
+	/// Version of the layout to restore, among the versions
+	/// shown in the history by `garage layout show`
+	pub(crate) version: u64,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ReplicationFactorOpt {
+	/// New replication factor, staged for the next version of the cluster layout
+	#[structopt(required_unless = "finish")]
+	pub(crate) replication_factor: Option<usize>,
+
+	/// Finish the change of the replication factor once data has been
+	/// copied to all its new replicas, so that nodes delete the data
+	/// they no longer store
+	#[structopt(long = "finish", conflicts_with = "replication-factor")]
+	pub(crate) finish: bool,
+
+	/// Version number of new configuration when finishing the change:
+	/// this command will fail if it is not exactly 1 + the previous
+	/// configuration's version
+	#[structopt(long = "version")]
+	pub(crate) version: Option<u64>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RebalanceLayoutOpt {
+	/// Stop moving partitions once every node stores at least this
+	/// fraction of its fair share of data (between 0 and 1)
+	#[structopt(long = "target-uniformity", default_value = "0.9")]
+	pub(crate) target_uniformity: f32,
+
+	/// Version number of new configuration: this command will only show
+	/// the proposed changes if it is not given, and fail if it is not
+	/// exactly 1 + the previous configuration's version
+	#[structopt(long = "version")]
+	pub(crate) version: Option<u64>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -156,11 +419,11 @@ pub struct RevertLayoutOpt {
 pub enum BucketOperation {
 	/// List buckets
 	#[structopt(name = "list", version = garage_version())]
-	List,
+	List(BucketListOpt),
 
 	/// Get bucket info
 	#[structopt(name = "info", version = garage_version())]
-	Info(BucketOpt),
+	Info(BucketInfoOpt),
 
 	/// Create bucket
 	#[structopt(name = "create", version = garage_version())]
@@ -197,6 +460,105 @@ pub enum BucketOperation {
 	/// Clean up (abort) old incomplete multipart uploads
 	#[structopt(name = "cleanup-incomplete-uploads", version = garage_version())]
 	CleanupIncompleteUploads(CleanupIncompleteUploadsOpt),
+
+	/// Set the lifecycle rules of a bucket, from a JSON or S3 XML file
+	#[structopt(name = "set-lifecycle", version = garage_version())]
+	SetLifecycle(SetLifecycleOpt),
+
+	/// Print the lifecycle rules of a bucket as JSON
+	#[structopt(name = "get-lifecycle", version = garage_version())]
+	GetLifecycle(BucketOpt),
+
+	/// Remove all lifecycle rules of a bucket
+	#[structopt(name = "delete-lifecycle", version = garage_version())]
+	DeleteLifecycle(BucketOpt),
+
+	/// Set the server-side encryption applied by default to new objects of a bucket
+	#[structopt(
+		name = "set-encryption",
+		alias = "set-default-encryption",
+		version = garage_version()
+	)]
+	SetEncryption(SetEncryptionOpt),
+
+	/// Create or update buckets from a JSON file
+	#[structopt(name = "import-file", version = garage_version())]
+	ImportFile(BucketImportFileOpt),
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct BucketImportFileOpt {
+	/// JSON file containing an array of buckets
+	pub path: String,
+
+	/// Buckets read from the input file by the CLI
+	#[structopt(skip)]
+	pub buckets: Vec<BucketImportEntry>,
+}
+
+/// Bucket in the input file of `garage bucket import-file`.
+/// Buckets are identified by their first global alias: a bucket
+/// that already has this alias is updated instead of being created.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BucketImportEntry {
+	pub global_aliases: Vec<String>,
+	#[serde(default)]
+	pub website_config: Option<BucketImportWebsiteConfig>,
+	#[serde(default)]
+	pub quotas: Option<BucketImportQuotas>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BucketImportWebsiteConfig {
+	pub index_document: String,
+	#[serde(default)]
+	pub error_document: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BucketImportQuotas {
+	#[serde(default)]
+	pub max_size: Option<u64>,
+	#[serde(default)]
+	pub max_objects: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct BucketListOpt {
+	/// Sort buckets by `name` (alphabetical order), `size` or `objects`
+	/// (biggest buckets first)
+	#[structopt(long = "sort-by")]
+	pub sort_by: Option<BucketSortBy>,
+
+	/// Reverse the sort order
+	#[structopt(long = "reverse")]
+	pub reverse: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BucketSortBy {
+	Name,
+	Size,
+	Objects,
+}
+
+impl std::str::FromStr for BucketSortBy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"name" => Ok(BucketSortBy::Name),
+			"size" => Ok(BucketSortBy::Size),
+			"objects" => Ok(BucketSortBy::Objects),
+			_ => Err(format!(
+				"invalid sort key '{}', expected name, size or objects",
+				s
+			)),
+		}
+	}
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
@@ -221,12 +583,60 @@ pub struct WebsiteOpt {
 	pub error_document: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetLifecycleOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// JSON file containing the lifecycle rules, in the format used by
+	/// `aws s3api put-bucket-lifecycle-configuration`
+	#[structopt(long = "from-file")]
+	pub from_file: Option<String>,
+
+	/// XML file containing the lifecycle rules, in the native S3 format
+	#[structopt(long = "from-xml")]
+	pub from_xml: Option<String>,
+
+	/// Rules read from the input file by the CLI
+	#[structopt(skip)]
+	pub rules: Vec<LifecycleRule>,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SetEncryptionOpt {
+	/// Bucket name
+	pub bucket: String,
+
+	/// Encrypt all new objects with keys managed by Garage (SSE-S3)
+	#[structopt(long = "sse-s3")]
+	pub sse_s3: bool,
+
+	/// Do not encrypt new objects unless requested by the client
+	#[structopt(long = "none")]
+	pub none: bool,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct BucketOpt {
 	/// Bucket name
 	pub name: String,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct BucketInfoOpt {
+	/// Bucket name
+	pub name: String,
+
+	/// Show the objects and storage space used by the biggest key prefixes
+	/// (this requires scanning all objects of the bucket)
+	#[structopt(long = "per-prefix-stats")]
+	pub per_prefix_stats: bool,
+
+	/// Number of path components (separated by `/`) that make up a prefix
+	#[structopt(long = "prefix-depth", default_value = "1")]
+	pub prefix_depth: usize,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct DeleteBucketOpt {
 	/// Bucket name
@@ -342,6 +752,19 @@ pub enum KeyOperation {
 	/// Import key
 	#[structopt(name = "import", version = garage_version())]
 	Import(KeyImportOpt),
+
+	/// Allow key to read, write or administrate a bucket
+	/// (same as `garage bucket allow`)
+	#[structopt(name = "add-bucket-permission", version = garage_version())]
+	AddBucketPermission(KeyAddBucketPermOpt),
+
+	/// Remove all permissions of key on a bucket
+	#[structopt(name = "remove-bucket-permission", version = garage_version())]
+	RemoveBucketPermission(KeyRemoveBucketPermOpt),
+
+	/// Create or update keys from a JSON file
+	#[structopt(name = "import-file", version = garage_version())]
+	ImportFile(KeyImportFileOpt),
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
@@ -386,6 +809,38 @@ pub struct KeyPermOpt {
 	pub create_bucket: bool,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct KeyAddBucketPermOpt {
+	/// ID or name of the key
+	pub key_pattern: String,
+
+	/// Bucket name
+	pub bucket: String,
+
+	/// Allow read operations
+	#[structopt(long = "read")]
+	pub read: bool,
+
+	/// Allow write operations
+	#[structopt(long = "write")]
+	pub write: bool,
+
+	/// Allow administrative operations operations
+	/// (such as deleting bucket or changing bucket website configuration),
+	/// requires --write
+	#[structopt(long = "owner", requires = "write")]
+	pub owner: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct KeyRemoveBucketPermOpt {
+	/// ID or name of the key
+	pub key_pattern: String,
+
+	/// Bucket name
+	pub bucket: String,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct KeyImportOpt {
 	/// Access key ID
@@ -399,6 +854,67 @@ pub struct KeyImportOpt {
 	pub name: String,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct KeyImportFileOpt {
+	/// JSON file containing an array of keys
+	pub path: String,
+
+	/// Keys read from the input file by the CLI
+	#[structopt(skip)]
+	pub keys: Vec<KeyImportEntry>,
+}
+
+/// Key in the input file of `garage key import-file`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct KeyImportEntry {
+	pub access_key_id: String,
+	pub secret_access_key: String,
+	#[serde(default)]
+	pub name: Option<String>,
+	#[serde(default)]
+	pub allow_create_bucket: bool,
+	#[serde(default)]
+	pub bucket_permissions: Vec<KeyImportBucketPermission>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct KeyImportBucketPermission {
+	/// Global alias or ID of the bucket
+	pub bucket: String,
+	#[serde(default)]
+	pub read: bool,
+	#[serde(default)]
+	pub write: bool,
+	#[serde(default)]
+	pub owner: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExportStateOpt {
+	/// JSON file to write the cluster state to
+	pub path: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ImportStateOpt {
+	/// JSON file written by `garage export-state`
+	pub path: PathBuf,
+}
+
+/// Content of the file written by `garage export-state`. Buckets and
+/// keys use the same format as `garage bucket import-file` and
+/// `garage key import-file`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ClusterStateFile {
+	/// Roles of the nodes in the current layout, by hex node ID
+	pub roles: BTreeMap<String, NodeRole>,
+	pub buckets: Vec<BucketImportEntry>,
+	pub keys: Vec<KeyImportEntry>,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
 pub struct MigrateOpt {
 	/// Confirm the launch of the migrate operation
@@ -440,10 +956,53 @@ pub enum RepairWhat {
 	Blocks,
 	/// Only redo the propagation of object deletions to the version table (slow)
 	#[structopt(name = "versions", version = garage_version())]
-	Versions,
+	Versions {
+		/// Start from the beginning of the table instead of resuming
+		/// an interrupted repair where it stopped
+		#[structopt(long = "reset")]
+		reset: bool,
+	},
 	/// Only redo the propagation of version deletions to the block ref table (extremely slow)
 	#[structopt(name = "block_refs", version = garage_version())]
-	BlockRefs,
+	BlockRefs {
+		/// Start from the beginning of the table instead of resuming
+		/// an interrupted repair where it stopped
+		#[structopt(long = "reset")]
+		reset: bool,
+	},
+	/// Queue deleted versions that are no longer referenced for garbage collection
+	#[structopt(name = "gc-versions", version = garage_version())]
+	GcVersions {
+		/// Only collect versions deleted at least this many days ago
+		/// (at least `version_gc_delay_days` from the configuration)
+		#[structopt(long = "older-than-days")]
+		older_than_days: Option<u64>,
+	},
+	/// Check that permissions and local aliases stored in keys and buckets
+	/// point to existing objects, and remove dangling entries
+	#[structopt(name = "key-bucket-links", version = garage_version())]
+	KeyBucketLinks,
+	/// Check that objects and permissions stored in metadata tables satisfy
+	/// the invariants of their CRDT types, and log violations (no repair is made)
+	#[structopt(name = "validate-crdt-invariants", version = garage_version())]
+	ValidateCrdtInvariants,
+	/// Find blocks stored both compressed and uncompressed on this node,
+	/// and delete one of the two copies (the uncompressed one if both are valid)
+	#[structopt(name = "compression-inconsistency", version = garage_version())]
+	CompressionInconsistency,
+	/// Find blocks stored on this node that have no references and are not
+	/// queued for resync, and queue them so that they are deleted
+	#[structopt(name = "orphan-blocks", version = garage_version())]
+	OrphanBlocks,
+	/// Apply the AbortIncompleteMultipartUpload lifecycle rules and multipart
+	/// upload TTLs of buckets to the objects stored on this node right away,
+	/// instead of waiting for the daily pass of the lifecycle worker
+	#[structopt(name = "lifecycle", version = garage_version())]
+	Lifecycle {
+		/// Only count the uploads that would be aborted, shown by `garage worker list`
+		#[structopt(long = "dry-run")]
+		dry_run: bool,
+	},
 	/// Verify integrity of all blocks on disc (extremely slow, i/o intensive)
 	#[structopt(name = "scrub", version = garage_version())]
 	Scrub {
@@ -493,6 +1052,27 @@ pub enum OfflineRepairWhat {
 	/// Repair object counters
 	#[structopt(name = "object_counters", version = garage_version())]
 	ObjectCounters,
+	/// Check that all entries of the metadata database can be decoded and that the
+	/// reference counters of blocks are consistent, without changing anything
+	#[structopt(name = "check-db", version = garage_version())]
+	CheckDb {
+		/// File to which the list of problems found is written
+		#[structopt(long = "report")]
+		report: PathBuf,
+	},
+	/// Rewrite the LMDB metadata database to reclaim the space of its free pages
+	#[structopt(name = "compact-db", version = garage_version())]
+	CompactDb,
+	/// Copy the metadata database to another database engine
+	#[structopt(name = "migrate-db", version = garage_version())]
+	MigrateDb {
+		/// Engine of the current database, as set by `db_engine` in the configuration file
+		#[structopt(long = "from")]
+		from: String,
+		/// Engine of the new database
+		#[structopt(long = "to")]
+		to: String,
+	},
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
@@ -509,6 +1089,16 @@ pub struct StatsOpt {
 	#[structopt(skip)]
 	#[serde(default)]
 	pub skip_global: bool,
+
+	/// Output the statistics of the node in the Prometheus text format
+	#[structopt(long = "export-prometheus")]
+	#[serde(default)]
+	pub export_prometheus: bool,
+
+	/// Write statistics to this file instead of stdout (`-` for stdout)
+	#[structopt(long = "output-file")]
+	#[serde(skip)]
+	pub output_file: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
@@ -558,7 +1148,29 @@ pub struct WorkerListOpt {
 pub enum BlockOperation {
 	/// List all blocks that currently have a resync error
 	#[structopt(name = "list-errors", version = garage_version())]
-	ListErrors,
+	ListErrors {
+		/// Write the list to this file instead of stdout (`-` for stdout)
+		#[structopt(long = "output-file")]
+		#[serde(skip)]
+		output_file: Option<String>,
+	},
+	/// List all blocks stored in the data directory of the node
+	#[structopt(name = "list-local", version = garage_version())]
+	ListLocal {
+		/// Output format (`table` or `csv`)
+		#[structopt(long = "format", default_value = "table")]
+		#[serde(skip)]
+		format: ListFormat,
+		/// Only list blocks whose hash starts with this hexadecimal prefix
+		#[structopt(long = "prefix", default_value = "")]
+		prefix: String,
+		/// Maximum number of blocks to list
+		#[structopt(long = "limit")]
+		limit: Option<usize>,
+		/// Only list blocks whose hash comes after this one
+		#[structopt(skip)]
+		after: Option<garage_util::data::Hash>,
+	},
 	/// Get detailed information about a single block
 	#[structopt(name = "info", version = garage_version())]
 	Info {
@@ -585,3 +1197,50 @@ pub enum BlockOperation {
 		blocks: Vec<String>,
 	},
 }
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ListFormat {
+	#[default]
+	Table,
+	Csv,
+}
+
+impl std::str::FromStr for ListFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"table" => Ok(ListFormat::Table),
+			"csv" => Ok(ListFormat::Csv),
+			_ => Err(format!("invalid format '{}', expected table or csv", s)),
+		}
+	}
+}
+
+/// Format in which the CLI prints the results of commands
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+	/// Aligned tables and messages, for humans
+	#[default]
+	Table,
+	/// The same data as the admin API, serialized as JSON
+	Json,
+	/// Tables printed as raw tab-separated values
+	Tsv,
+}
+
+impl std::str::FromStr for OutputFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"table" => Ok(OutputFormat::Table),
+			"json" => Ok(OutputFormat::Json),
+			"tsv" => Ok(OutputFormat::Tsv),
+			_ => Err(format!(
+				"invalid output format '{}', expected table, json or tsv",
+				s
+			)),
+		}
+	}
+}
@@ -1,27 +1,76 @@
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
-use format_table::format_table;
+use serde::Serialize;
+
+use format_table::format_table_to_string;
 use garage_util::background::*;
 use garage_util::crdt::*;
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::time::*;
 
-use garage_block::manager::BlockResyncErrorInfo;
+use garage_rpc::rpc_stats::RpcEndpointStats;
+use garage_rpc::system::PeerConnectionInfo;
+
+use garage_block::manager::{BlockResyncErrorInfo, LocalBlockInfo};
 
 use garage_model::bucket_table::*;
 use garage_model::key_table::*;
 use garage_model::s3::object_table::{BYTES, OBJECTS, UNFINISHED_UPLOADS};
 use garage_model::s3::version_table::Version;
 
-use crate::cli::structs::WorkerListOpt;
+use crate::admin::{BucketListItem, PrefixStatsResult};
+use crate::cli::structs::{ListFormat, WorkerListOpt};
+
+/// Whether tables are printed as raw tab-separated values instead of being aligned,
+/// set once from the `--output` flag before any command is run
+static TSV_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_tsv_output(tsv: bool) {
+	TSV_OUTPUT.store(tsv, Ordering::Relaxed);
+}
+
+/// Print a table given as lines of tab-separated cells,
+/// either aligned or as raw tab-separated values
+pub fn print_table(table: Vec<String>) {
+	print!("{}", table_to_string(table));
+}
+
+fn table_to_string(table: Vec<String>) -> String {
+	if TSV_OUTPUT.load(Ordering::Relaxed) {
+		// Leading tabs are only used to indent the aligned tables
+		table
+			.iter()
+			.map(|line| format!("{}\n", line.trim_start_matches('\t')))
+			.collect()
+	} else {
+		format_table_to_string(table)
+	}
+}
+
+/// Serialize a value to pretty-printed JSON, for the `--output json` flag
+pub fn to_json_string<T: Serialize>(value: &T) -> Result<String, Error> {
+	serde_json::to_string_pretty(value)
+		.map_err(|e| Error::Message(format!("Unable to serialize output: {}", e)))
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> Result<(), Error> {
+	println!("{}", to_json_string(value)?);
+	Ok(())
+}
 
-pub fn print_bucket_list(bl: Vec<Bucket>) {
+pub fn print_bucket_list(bl: Vec<BucketListItem>) {
 	println!("List of buckets:");
 
 	let mut table = vec![];
-	for bucket in bl {
+	for item in bl {
+		let bucket = &item.bucket;
 		let aliases = bucket
 			.aliases()
 			.iter()
@@ -40,13 +89,36 @@ pub fn print_bucket_list(bl: Vec<Bucket>) {
 		};
 
 		table.push(format!(
-			"\t{}\t{}\t{}",
+			"\t{}\t{}\t{}\t{}\t{}",
 			aliases.join(","),
 			local_aliases_n,
 			hex::encode(bucket.id),
+			bytesize::ByteSize::b(item.size_bytes).to_string_as(true),
+			item.object_count,
 		));
 	}
-	format_table(table);
+	print_table(table);
+}
+
+/// Name under which a bucket is sorted in `garage bucket list --sort-by name`:
+/// its first global alias, or its first local alias if it has none
+pub fn bucket_display_name(item: &BucketListItem) -> String {
+	let bucket = &item.bucket;
+	bucket
+		.aliases()
+		.iter()
+		.filter(|(_, _, active)| *active)
+		.map(|(name, _, _)| name.clone())
+		.min()
+		.or_else(|| {
+			bucket
+				.local_aliases()
+				.iter()
+				.filter(|(_, _, active)| *active)
+				.map(|((_, name), _, _)| name.clone())
+				.min()
+		})
+		.unwrap_or_default()
 }
 
 pub fn print_key_list(kl: Vec<(String, String)>) {
@@ -55,7 +127,7 @@ pub fn print_key_list(kl: Vec<(String, String)>) {
 	for key in kl {
 		table.push(format!("\t{}\t{}", key.0, key.1));
 	}
-	format_table(table);
+	print_table(table);
 }
 
 pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
@@ -94,7 +166,7 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 					));
 				}
 			}
-			format_table(table);
+			print_table(table);
 
 			println!("\nAuthorized buckets:");
 			let mut table = vec![];
@@ -123,7 +195,7 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 					bucket_id
 				));
 			}
-			format_table(table);
+			print_table(table);
 		}
 		Deletable::Deleted => {
 			println!("Key {} is deleted.", key.key_id);
@@ -135,6 +207,7 @@ pub fn print_bucket_info(
 	bucket: &Bucket,
 	relevant_keys: &HashMap<String, Key>,
 	counters: &HashMap<String, i64>,
+	prefix_stats: Option<PrefixStatsResult>,
 ) {
 	let key_name = |k| {
 		relevant_keys
@@ -167,6 +240,29 @@ pub fn print_bucket_info(
 			);
 
 			println!("\nWebsite access: {}", p.website_config.get().is_some());
+			if let Some(BucketEncryption::SseS3) = p.default_encryption.get() {
+				println!("Default encryption: SSE-S3");
+			}
+			match p.versioning.get() {
+				Some(BucketVersioning::Enabled) => println!("Versioning: enabled"),
+				Some(BucketVersioning::Suspended) => println!("Versioning: suspended"),
+				None => (),
+			}
+			if p.object_lock_enabled.get() {
+				match p.object_lock_default_retention.get() {
+					Some(r) => {
+						let period = match r.period {
+							ObjectLockPeriod::Days(d) => format!("{} days", d),
+							ObjectLockPeriod::Years(y) => format!("{} years", y),
+						};
+						println!(
+							"Object lock: enabled (default retention: {:?}, {})",
+							r.mode, period
+						);
+					}
+					None => println!("Object lock: enabled"),
+				}
+			}
 
 			let quotas = p.quotas.get();
 			if quotas.max_size.is_some() || quotas.max_objects.is_some() {
@@ -198,7 +294,7 @@ pub fn print_bucket_info(
 					table.push(format!("\t{} ({})\t{}", key_id, key_name(key_id), alias));
 				}
 			}
-			format_table(table);
+			print_table(table);
 
 			println!("\nAuthorized keys:");
 			let mut table = vec![];
@@ -218,7 +314,30 @@ pub fn print_bucket_info(
 					key_name(k)
 				));
 			}
-			format_table(table);
+			print_table(table);
+
+			match prefix_stats {
+				None => (),
+				Some(PrefixStatsResult::InBackground) => {
+					println!("\nThis bucket is big, per-prefix statistics are being computed by a background worker.");
+					println!(
+						"Use `garage worker list` and `garage worker info` to see the results."
+					);
+				}
+				Some(PrefixStatsResult::Done(ps)) => {
+					println!("\nBiggest prefixes:");
+					let mut table = vec!["\tPrefix\tObjects\tSize".into()];
+					for e in ps {
+						table.push(format!(
+							"\t{}\t{}\t{}",
+							if e.prefix.is_empty() { "/" } else { &e.prefix },
+							e.objects,
+							bytesize::ByteSize::b(e.bytes)
+						));
+					}
+					print_table(table);
+				}
+			}
 		}
 	};
 }
@@ -281,7 +400,11 @@ pub fn print_worker_list(wi: HashMap<usize, WorkerInfo>, wlo: WorkerListOpt) {
 		table.push(format!(
 			"{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
 			tid,
-			info.state,
+			if info.paused {
+				"Paused".to_string()
+			} else {
+				info.state.to_string()
+			},
 			info.name,
 			info.status
 				.tranquility
@@ -299,7 +422,7 @@ pub fn print_worker_list(wi: HashMap<usize, WorkerInfo>, wlo: WorkerListOpt) {
 			err_ago,
 		));
 	}
-	format_table(table);
+	print_table(table);
 }
 
 pub fn print_worker_info(tid: usize, info: WorkerInfo) {
@@ -317,6 +440,9 @@ pub fn print_worker_info(tid: usize, info: WorkerInfo) {
 			table.push(format!("Worker state:\t{}", s));
 		}
 	};
+	if info.paused {
+		table.push("Paused:\tyes".into());
+	}
 	if let Some(tql) = info.status.tranquility {
 		table.push(format!("Tranquility:\t{}", tql));
 	}
@@ -354,7 +480,7 @@ pub fn print_worker_info(tid: usize, info: WorkerInfo) {
 			table.push(format!("\t{}", s));
 		}
 	}
-	format_table(table);
+	print_table(table);
 }
 
 pub fn print_worker_vars(wv: Vec<(Uuid, String, String)>) {
@@ -362,10 +488,163 @@ pub fn print_worker_vars(wv: Vec<(Uuid, String, String)>) {
 		.into_iter()
 		.map(|(n, k, v)| format!("{:?}\t{}\t{}", n, k, v))
 		.collect::<Vec<_>>();
-	format_table(table);
+	print_table(table);
+}
+
+/// Row of the output of `garage node show-connections`
+#[derive(Serialize)]
+pub struct ConnectionListRow {
+	pub id: String,
+	pub addr: SocketAddr,
+	pub state: String,
+	pub is_up: bool,
+	/// `inbound`, `outbound`, `both` or `none`
+	pub direction: &'static str,
+	pub last_seen_secs_ago: Option<u64>,
+	pub rpcs_sent: u64,
+	pub responses_received: u64,
+	pub avg_ping_msec: Option<f64>,
+	pub med_ping_msec: Option<f64>,
+	pub max_ping_msec: Option<f64>,
+}
+
+impl From<PeerConnectionInfo> for ConnectionListRow {
+	fn from(c: PeerConnectionInfo) -> Self {
+		Self {
+			id: hex::encode(c.id),
+			addr: c.addr,
+			state: c.state,
+			is_up: c.is_up,
+			direction: match (c.inbound, c.outbound) {
+				(true, true) => "both",
+				(true, false) => "inbound",
+				(false, true) => "outbound",
+				(false, false) => "none",
+			},
+			last_seen_secs_ago: c.last_seen_secs_ago,
+			rpcs_sent: c.rpcs_sent,
+			responses_received: c.responses_received,
+			avg_ping_msec: c.avg_ping_msec,
+			med_ping_msec: c.med_ping_msec,
+			max_ping_msec: c.max_ping_msec,
+		}
+	}
+}
+
+pub fn print_connection_list(conns: Vec<ConnectionListRow>) {
+	let ping = |p: Option<f64>| p.map(|x| format!("{:.1}", x)).unwrap_or_else(|| "-".into());
+	let mut table = vec![
+		"ID\tAddress\tState\tDirection\tRPCs sent\tResponses\tAvg ping (ms)\tMedian\tMax\tLast seen"
+			.to_string(),
+	];
+	for c in conns {
+		let tf = timeago::Formatter::new();
+		table.push(format!(
+			"{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+			&c.id[..16],
+			c.addr,
+			c.state,
+			c.direction,
+			c.rpcs_sent,
+			c.responses_received,
+			ping(c.avg_ping_msec),
+			ping(c.med_ping_msec),
+			ping(c.max_ping_msec),
+			c.last_seen_secs_ago
+				.map(|s| tf.convert(Duration::from_secs(s)))
+				.unwrap_or_else(|| "never seen".into()),
+		));
+	}
+	print_table(table);
+}
+
+pub fn print_rpc_stats(stats: &[RpcEndpointStats]) {
+	let mut table =
+		vec!["Endpoint\tCalls\tErrors\tMean latency (ms)\tP99 latency (ms)".to_string()];
+	for s in stats {
+		table.push(format!(
+			"{}\t{}\t{}\t{:.3}\t{:.3}",
+			s.endpoint_name, s.calls_total, s.errors_total, s.mean_latency_ms, s.p99_latency_ms
+		));
+	}
+	print_table(table);
+}
+
+/// Round-trip time statistics of a series of pings to a node
+#[derive(Serialize)]
+pub struct PingStats {
+	pub node: String,
+	pub rtt_msec: Vec<f64>,
+	pub min_msec: f64,
+	pub max_msec: f64,
+	pub mean_msec: f64,
+	pub stddev_msec: f64,
+}
+
+impl PingStats {
+	pub fn new(node: Uuid, rtt_msec: Vec<f64>) -> Self {
+		let n = rtt_msec.len() as f64;
+		let mean = rtt_msec.iter().sum::<f64>() / n;
+		let variance = rtt_msec.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+		Self {
+			node: hex::encode(node),
+			min_msec: rtt_msec.iter().cloned().fold(f64::INFINITY, f64::min),
+			max_msec: rtt_msec.iter().cloned().fold(0., f64::max),
+			mean_msec: mean,
+			stddev_msec: variance.sqrt(),
+			rtt_msec,
+		}
+	}
+}
+
+pub fn print_ping_stats(stats: &PingStats) {
+	for (i, rtt) in stats.rtt_msec.iter().enumerate() {
+		println!("ping {}: seq={} time={:.3} ms", stats.node, i + 1, rtt);
+	}
+	println!();
+	println!(
+		"{} pings, rtt min/mean/max/stddev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+		stats.rtt_msec.len(),
+		stats.min_msec,
+		stats.mean_msec,
+		stats.max_msec,
+		stats.stddev_msec
+	);
+}
+
+pub fn print_local_block_list_header(format: ListFormat) {
+	match format {
+		ListFormat::Table => println!("{:<64}  {:>12}  {:<10}  RC", "Hash", "Size", "Compressed"),
+		ListFormat::Csv => println!("hash,size_bytes,is_compressed,rc"),
+	}
+}
+
+pub fn print_local_block_list(format: ListFormat, blocks: &[LocalBlockInfo]) {
+	for b in blocks {
+		match format {
+			ListFormat::Table => println!(
+				"{:<64}  {:>12}  {:<10}  {}",
+				hex::encode(b.hash),
+				b.size,
+				if b.compressed { "yes" } else { "no" },
+				b.refcount
+			),
+			ListFormat::Csv => println!(
+				"{},{},{},{}",
+				hex::encode(b.hash),
+				b.size,
+				b.compressed,
+				b.refcount
+			),
+		}
+	}
 }
 
 pub fn print_block_error_list(el: Vec<BlockResyncErrorInfo>) {
+	print!("{}", format_block_error_list(el));
+}
+
+pub fn format_block_error_list(el: Vec<BlockResyncErrorInfo>) -> String {
 	let now = now_msec();
 	let tf = timeago::Formatter::new();
 	let mut tf2 = timeago::Formatter::new();
@@ -387,7 +666,7 @@ pub fn print_block_error_list(el: Vec<BlockResyncErrorInfo>) {
 			next_try
 		));
 	}
-	format_table(table);
+	table_to_string(table)
 }
 
 pub fn print_block_info(hash: Hash, refcount: u64, versions: Vec<Result<Version, Uuid>>) {
@@ -416,10 +695,71 @@ pub fn print_block_info(hash: Hash, refcount: u64, versions: Vec<Result<Version,
 			}
 		}
 	}
-	format_table(table);
+	print_table(table);
 
 	if refcount != nondeleted_count {
 		println!();
 		println!("Warning: refcount does not match number of non-deleted versions");
 	}
 }
+
+/// Destination for the output of commands that can produce a large amount of data.
+/// When writing to a file, the output is first written to a temporary file
+/// in the same directory, which is then renamed over the destination path,
+/// so that the destination file is replaced atomically.
+pub struct OutputFile {
+	file: Option<(PathBuf, PathBuf, File)>,
+}
+
+impl OutputFile {
+	/// Open the output destination: `None` or `-` mean stdout.
+	/// The temporary file is created immediately, so that permission
+	/// errors are reported before any RPC call is made.
+	pub fn open(path: Option<&str>) -> Result<Self, Error> {
+		let path = match path {
+			None | Some("-") => return Ok(Self { file: None }),
+			Some(p) => PathBuf::from(p),
+		};
+
+		let mut tmp_path = path.clone().into_os_string();
+		tmp_path.push(format!(".tmp-{}", hex::encode(rand::random::<[u8; 4]>())));
+		let tmp_path = PathBuf::from(tmp_path);
+
+		let file = File::create(&tmp_path).err_context(format!(
+			"Unable to create output file {}",
+			tmp_path.display()
+		))?;
+
+		Ok(Self {
+			file: Some((path, tmp_path, file)),
+		})
+	}
+
+	/// Write all of the output and move it to its final destination
+	pub fn write(mut self, data: &str) -> Result<(), Error> {
+		match self.file.take() {
+			None => {
+				print!("{}", data);
+				Ok(())
+			}
+			Some((path, tmp_path, mut file)) => {
+				let res = file
+					.write_all(data.as_bytes())
+					.and_then(|_| file.sync_all())
+					.and_then(|_| std::fs::rename(&tmp_path, &path));
+				if res.is_err() {
+					let _ = std::fs::remove_file(&tmp_path);
+				}
+				res.err_context(format!("Unable to write output file {}", path.display()))
+			}
+		}
+	}
+}
+
+impl Drop for OutputFile {
+	fn drop(&mut self) {
+		if let Some((_, tmp_path, _)) = self.file.take() {
+			let _ = std::fs::remove_file(tmp_path);
+		}
+	}
+}
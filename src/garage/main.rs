@@ -19,23 +19,31 @@ compile_error!("Only one of bundled-libs and system-libs Cargo features must be
 
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use structopt::StructOpt;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload};
 
 use netapp::util::parse_and_resolve_peer_addr;
 use netapp::NetworkKey;
 
-use garage_util::config::Config;
+use garage_util::config::{read_config, Config, LogFormat};
 use garage_util::error::*;
 
 use garage_rpc::system::*;
 use garage_rpc::*;
 
+use garage_api::admin::log_level::LogFilter;
+
 use garage_model::helper::error::Error as HelperError;
 
 use admin::*;
 use cli::*;
 
+/// Maximum time to wait for in-flight RPCs to complete before aborting on a panic
+const PANIC_RPC_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[derive(StructOpt, Debug)]
 #[structopt(
 	name = "garage",
@@ -59,11 +67,15 @@ struct Opt {
 	)]
 	pub config_file: PathBuf,
 
+	/// Output format of the commands: table, json or tsv
+	#[structopt(long = "output", global = true, default_value = "table")]
+	pub output: OutputFormat,
+
 	#[structopt(subcommand)]
 	cmd: Command,
 }
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 pub struct Secrets {
 	/// RPC secret network key, used to replace rpc_secret in config.toml when running the
 	/// daemon or doing admin operations
@@ -97,6 +109,8 @@ async fn main() {
 		"consul-discovery",
 		#[cfg(feature = "kubernetes-discovery")]
 		"kubernetes-discovery",
+		#[cfg(feature = "dns-discovery")]
+		"dns-discovery",
 		#[cfg(feature = "metrics")]
 		"metrics",
 		#[cfg(feature = "telemetry-otlp")]
@@ -148,6 +162,10 @@ async fn main() {
 		eprintln!();
 		eprintln!("BACKTRACE:");
 		eprintln!("{:?}", backtrace::Backtrace::new());
+
+		// Give RPCs being handled on other threads a chance to complete,
+		// so that other nodes don't see them fail
+		garage_rpc::drain::drain_rpc_handlers_blocking(PANIC_RPC_DRAIN_TIMEOUT);
 		std::process::abort();
 	}));
 
@@ -157,25 +175,61 @@ async fn main() {
 	// Initialize logging as well as other libraries used in Garage
 	if std::env::var("RUST_LOG").is_err() {
 		let default_log = match &opt.cmd {
-			Command::Server => "netapp=info,garage=info",
+			Command::Server(ServerOpt { cmd: None }) => "netapp=info,garage=info",
 			_ => "netapp=warn,garage=warn",
 		};
 		std::env::set_var("RUST_LOG", default_log)
 	}
-	tracing_subscriber::fmt()
-		.with_writer(std::io::stderr)
-		.with_env_filter(tracing_subscriber::filter::EnvFilter::from_default_env())
-		.init();
+	// Only the server reads its log format from the configuration file. If the file
+	// can't be read, the server reports the error once logging is initialized.
+	let log_format = match &opt.cmd {
+		Command::Server(ServerOpt { cmd: None }) => read_config(opt.config_file.clone())
+			.map(|config| config.log_format)
+			.unwrap_or_default(),
+		_ => LogFormat::Text,
+	};
+	// The filter can be changed at runtime through the admin API
+	let (log_filter_layer, log_filter_handle) =
+		reload::Layer::new(tracing_subscriber::filter::EnvFilter::from_default_env());
+	let log_filter = LogFilter::new(
+		log_filter_handle,
+		std::env::var("RUST_LOG").unwrap_or_default(),
+	);
+	let subscriber = tracing_subscriber::registry().with(log_filter_layer);
+	match log_format {
+		LogFormat::Text => subscriber
+			.with(fmt::layer().with_writer(std::io::stderr))
+			.init(),
+		LogFormat::Json => subscriber
+			.with(
+				fmt::layer()
+					.with_writer(std::io::stderr)
+					.json()
+					.flatten_event(true),
+			)
+			.init(),
+	}
 	sodiumoxide::init().expect("Unable to init sodiumoxide");
 
 	let res = match opt.cmd {
-		Command::Server => server::run_server(opt.config_file, opt.secrets).await,
+		Command::Server(ServerOpt { cmd: None }) => {
+			server::run_server(opt.config_file, opt.secrets, log_filter).await
+		}
+		Command::Server(ServerOpt {
+			cmd: Some(ServerOperation::ExportMetricsSnapshot(export_opt)),
+		}) => export_metrics_snapshot(opt.config_file, opt.secrets, export_opt).await,
 		Command::OfflineRepair(repair_opt) => {
 			repair::offline::offline_repair(opt.config_file, opt.secrets, repair_opt).await
 		}
 		Command::Node(NodeOperation::NodeId(node_id_opt)) => {
 			node_id_command(opt.config_file, node_id_opt.quiet)
 		}
+		Command::Completion(shell_opt) => {
+			// Completion scripts are generated from the definition of the
+			// command line only, so that they don't need a running node
+			Opt::clap().gen_completions_to("garage", shell_opt.shell, &mut std::io::stdout());
+			Ok(())
+		}
 		_ => cli_command(opt).await,
 	};
 
@@ -248,7 +302,16 @@ async fn cli_command(opt: Opt) -> Result<(), Error> {
 	let system_rpc_endpoint = netapp.endpoint::<SystemRpc, ()>(SYSTEM_RPC_PATH.into());
 	let admin_rpc_endpoint = netapp.endpoint::<AdminRpc, ()>(ADMIN_RPC_PATH.into());
 
-	match cli_command_dispatch(opt.cmd, &system_rpc_endpoint, &admin_rpc_endpoint, id).await {
+	set_tsv_output(opt.output == OutputFormat::Tsv);
+	match cli_command_dispatch(
+		opt.cmd,
+		&system_rpc_endpoint,
+		&admin_rpc_endpoint,
+		id,
+		opt.output,
+	)
+	.await
+	{
 		Err(HelperError::Internal(i)) => Err(Error::Message(format!("Internal error: {}", i))),
 		Err(HelperError::BadRequest(b)) => Err(Error::Message(b)),
 		Err(e) => Err(Error::Message(format!("{}", e))),
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use garage_util::data::*;
+use garage_util::error::*;
+use garage_util::migrate::Migrate;
+
+use garage_table::replication::TableReplication;
+use garage_table::*;
+
+use garage_model::garage::Garage;
+use garage_model::s3::block_ref_table::BlockRef;
+
+/// Check that all the entries of the tables of the metadata database can be
+/// decoded, and that the reference counters of blocks match the block
+/// references stored on this node. Nothing is written to the database,
+/// the problems found are written to the report file.
+pub fn check_db(garage: &Garage, report_file: &Path) -> Result<(), Error> {
+	let mut report = String::new();
+	let mut problems = 0;
+
+	writeln!(
+		&mut report,
+		"Metadata database check of node {:?}",
+		garage.system.id
+	)
+	.unwrap();
+	writeln!(&mut report, "Database engine: {}", garage.db.engine()).unwrap();
+	writeln!(&mut report).unwrap();
+
+	problems += check_table(&garage.bucket_table, &mut report)?;
+	problems += check_table(&garage.bucket_alias_table, &mut report)?;
+	problems += check_table(&garage.key_table, &mut report)?;
+	problems += check_table(&garage.object_table, &mut report)?;
+	problems += check_table(&garage.object_counter_table.table, &mut report)?;
+	problems += check_table(&garage.version_table, &mut report)?;
+	problems += check_table(&garage.block_ref_table, &mut report)?;
+	#[cfg(feature = "k2v")]
+	{
+		problems += check_table(&garage.k2v.item_table, &mut report)?;
+		problems += check_table(&garage.k2v.counter_table.table, &mut report)?;
+	}
+
+	writeln!(&mut report).unwrap();
+	problems += check_block_rc(garage, &mut report)?;
+
+	std::fs::write(report_file, &report)?;
+
+	if problems > 0 {
+		Err(Error::Message(format!(
+			"{} problems found in the metadata database, see {} for details",
+			problems,
+			report_file.display()
+		)))
+	} else {
+		println!(
+			"No problem found in the metadata database, report written to {}",
+			report_file.display()
+		);
+		Ok(())
+	}
+}
+
+/// Try to decode all the entries of a table, returning the number of
+/// entries that could not be read or decoded
+fn check_table<F: TableSchema, R: TableReplication>(
+	table: &Table<F, R>,
+	report: &mut String,
+) -> Result<usize, Error> {
+	info!("Checking table {}...", F::TABLE_NAME);
+
+	let mut entries = 0;
+	let mut invalid = vec![];
+	for item in table.data.store.iter()? {
+		let (key, value) = match item {
+			Ok(kv) => kv,
+			Err(e) => {
+				// The iterator can't go further once the database
+				// returned an error, so stop here for this table
+				invalid.push(format!("read error after {} entries: {}", entries, e));
+				break;
+			}
+		};
+		entries += 1;
+		if F::E::decode(&value).is_none() {
+			invalid.push(format!("entry {} cannot be decoded", hex::encode(&key)));
+		}
+	}
+
+	writeln!(
+		report,
+		"Table {}: {} entries, {} invalid",
+		F::TABLE_NAME,
+		entries,
+		invalid.len()
+	)
+	.unwrap();
+	for line in invalid.iter() {
+		writeln!(report, "  {}", line).unwrap();
+	}
+
+	Ok(invalid.len())
+}
+
+/// Compare the reference counter of each block with the number of block
+/// references to it that are not deleted, returning the number of mismatches
+fn check_block_rc(garage: &Garage, report: &mut String) -> Result<usize, Error> {
+	info!("Checking block reference counters...");
+
+	let mut refs = HashMap::<Hash, u64>::new();
+	for item in garage.block_ref_table.data.store.iter()? {
+		let (_, value) = item?;
+		// Entries that can't be decoded were reported with the table
+		if let Some(block_ref) = BlockRef::decode(&value) {
+			if !block_ref.deleted.get() {
+				*refs.entry(block_ref.block).or_default() += 1;
+			}
+		}
+	}
+
+	let mut blocks = 0;
+	let mut mismatches = vec![];
+	for item in garage.block_manager.rc_counts()? {
+		let (hash, count) = item?;
+		blocks += 1;
+		let hash_str = hex::encode(&hash);
+		let expected = Hash::try_from(&hash).and_then(|h| refs.remove(&h));
+		match (count, expected) {
+			(None, _) => mismatches.push(format!("block {}: invalid counter", hash_str)),
+			(Some(count), expected) if count != expected.unwrap_or(0) => mismatches.push(format!(
+				"block {}: counter is {}, but there are {} block references",
+				hash_str,
+				count,
+				expected.unwrap_or(0)
+			)),
+			_ => (),
+		}
+	}
+	// The blocks that remain have block references but no counter
+	for (hash, expected) in refs {
+		blocks += 1;
+		mismatches.push(format!(
+			"block {}: no counter, but there are {} block references",
+			hex::encode(hash),
+			expected
+		));
+	}
+
+	writeln!(
+		report,
+		"Block reference counters: {} blocks, {} mismatches",
+		blocks,
+		mismatches.len()
+	)
+	.unwrap();
+	for line in mismatches.iter() {
+		writeln!(report, "  {}", line).unwrap();
+	}
+
+	Ok(mismatches.len())
+}
@@ -1,2 +1,3 @@
+pub mod check_db;
 pub mod offline;
 pub mod online;
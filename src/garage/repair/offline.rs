@@ -1,11 +1,12 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use garage_util::config::*;
 use garage_util::error::*;
 
-use garage_model::garage::Garage;
+use garage_model::garage::{db_path, open_db, Garage};
 
 use crate::cli::structs::*;
+use crate::repair::check_db::check_db;
 use crate::{fill_secrets, Secrets};
 
 pub async fn offline_repair(
@@ -13,14 +14,23 @@ pub async fn offline_repair(
 	secrets: Secrets,
 	opt: OfflineRepairOpt,
 ) -> Result<(), Error> {
-	if !opt.yes {
+	// Checking the database is the only operation that doesn't change it
+	if !opt.yes && !matches!(opt.what, OfflineRepairWhat::CheckDb { .. }) {
 		return Err(Error::Message(
 			"Please add the --yes flag to launch repair operation".into(),
 		));
 	}
 
 	info!("Loading configuration...");
-	let config = fill_secrets(read_config(config_file)?, secrets);
+	let config = fill_secrets(read_config(config_file.clone())?, secrets);
+
+	match &opt.what {
+		OfflineRepairWhat::MigrateDb { from, to } => {
+			return migrate_db(&config_file, &config, from, to)
+		}
+		OfflineRepairWhat::CompactDb => return compact_db(&config),
+		_ => (),
+	}
 
 	info!("Initializing Garage main data store...");
 	let garage = Garage::new(config)?;
@@ -39,9 +49,148 @@ pub async fn offline_repair(
 				.object_counter_table
 				.offline_recount_all(&garage.object_table)?;
 		}
+		OfflineRepairWhat::CheckDb { report } => {
+			check_db(&garage, &report)?;
+		}
+		OfflineRepairWhat::MigrateDb { .. } | OfflineRepairWhat::CompactDb => unreachable!(),
 	}
 
 	info!("Repair operation finished, shutting down...");
 
 	Ok(())
 }
+
+/// Copy all the trees of the metadata database to a new database with another
+/// engine, then set aside the old database and update the configuration file
+fn migrate_db(config_file: &Path, config: &Config, from: &str, to: &str) -> Result<(), Error> {
+	if config.db_engine != from {
+		return Err(Error::Message(format!(
+			"The configuration file uses the {} engine, not {}",
+			config.db_engine, from
+		)));
+	}
+
+	let old_path = db_path(&config.metadata_dir, from);
+	let new_path = db_path(&config.metadata_dir, to);
+	let mut bak_path = old_path.clone().into_os_string();
+	bak_path.push(".bak");
+	let bak_path = PathBuf::from(bak_path);
+	if old_path == new_path {
+		return Err(Error::Message(format!(
+			"The {} and {} engines use the same database path",
+			from, to
+		)));
+	}
+	for path in [&new_path, &bak_path] {
+		if path.exists() {
+			return Err(Error::Message(format!(
+				"{} already exists, please move it away before migrating the database",
+				path.display()
+			)));
+		}
+	}
+
+	let input = open_db(config, from)?;
+	let output = open_db(config, to)?;
+
+	let mut counts = vec![];
+	for name in input.list_trees()? {
+		let count = input.open_tree(&name)?.len()?;
+		counts.push((name, count));
+	}
+
+	info!("Copying {} trees from {} to {}...", counts.len(), from, to);
+	output.import(&input)?;
+	output.flush()?;
+
+	for (name, count) in counts.iter() {
+		let new_count = output.open_tree(name)?.len()?;
+		if new_count != *count {
+			return Err(Error::Message(format!(
+				"Tree {} has {} items in the {} database but {} in the {} database, \
+				the configuration file was not changed",
+				name, count, from, new_count, to
+			)));
+		}
+	}
+	drop(input);
+	drop(output);
+
+	info!(
+		"All {} trees copied, moving the old database to {}",
+		counts.len(),
+		bak_path.display()
+	);
+	std::fs::rename(&old_path, &bak_path)?;
+
+	info!(
+		"Setting db_engine = \"{}\" in {}",
+		to,
+		config_file.display()
+	);
+	let content = std::fs::read_to_string(config_file)?;
+	std::fs::write(config_file, set_db_engine(&content, to))?;
+
+	Ok(())
+}
+
+/// Write a compacted copy of the LMDB database next to it, and replace the
+/// database file with it once the copy is complete
+fn compact_db(config: &Config) -> Result<(), Error> {
+	if !matches!(config.db_engine.as_str(), "lmdb" | "heed") {
+		return Err(Error::Message(format!(
+			"Only LMDB databases can be compacted, this node uses the {} engine",
+			config.db_engine
+		)));
+	}
+
+	let path = db_path(&config.metadata_dir, &config.db_engine);
+	let data_file = path.join("data.mdb");
+	let compact_file = path.join("data.mdb.compact");
+	if compact_file.exists() {
+		std::fs::remove_file(&compact_file)?;
+	}
+	let metadata_before = std::fs::metadata(&data_file)?;
+
+	let db = open_db(config, &config.db_engine)?;
+	info!(
+		"Writing a compacted copy of the database to {}...",
+		compact_file.display()
+	);
+	db.copy_compacted(&compact_file)?;
+	drop(db);
+
+	std::fs::set_permissions(&compact_file, metadata_before.permissions())?;
+	std::fs::rename(&compact_file, &data_file)?;
+	let size_after = std::fs::metadata(&data_file)?.len();
+
+	info!(
+		"Database compacted, size went from {} to {}",
+		bytesize::ByteSize::b(metadata_before.len()),
+		bytesize::ByteSize::b(size_after)
+	);
+
+	Ok(())
+}
+
+/// Replace the value of `db_engine` in the content of a configuration file,
+/// keeping the rest of the file as is
+fn set_db_engine(content: &str, engine: &str) -> String {
+	let setting = format!("db_engine = \"{}\"", engine);
+	let mut found = false;
+	let mut lines = content
+		.lines()
+		.map(|line| match line.split_once('=') {
+			Some((key, _)) if key.trim() == "db_engine" => {
+				found = true;
+				setting.clone()
+			}
+			_ => line.to_string(),
+		})
+		.collect::<Vec<_>>();
+	if !found {
+		// db_engine is a top-level setting, it must come before any [section]
+		lines.insert(0, setting);
+	}
+	lines.join("\n") + "\n"
+}
@@ -1,18 +1,28 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tokio::sync::watch;
 
 use garage_block::repair::ScrubWorkerCommand;
+use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
+use garage_model::key_table::*;
+use garage_model::permission::BucketKeyPerm;
 use garage_model::s3::block_ref_table::*;
+use garage_model::s3::lifecycle_worker::LifecycleWorker;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 use garage_table::*;
 use garage_util::background::*;
+use garage_util::crdt::{self, Crdt};
+use garage_util::data::*;
 use garage_util::error::Error;
 use garage_util::migrate::Migrate;
+use garage_util::persister::PersisterShared;
+use garage_util::time::*;
 
 use crate::*;
 
@@ -30,13 +40,32 @@ pub async fn launch_online_repair(
 			garage.block_ref_table.syncer.add_full_sync()?;
 			garage.key_table.syncer.add_full_sync()?;
 		}
-		RepairWhat::Versions => {
+		RepairWhat::Versions { reset } => {
 			info!("Repairing the versions table");
-			bg.spawn_worker(RepairVersionsWorker::new(garage.clone()));
+			bg.spawn_worker(RepairVersionsWorker::new(garage.clone(), reset));
 		}
-		RepairWhat::BlockRefs => {
+		RepairWhat::BlockRefs { reset } => {
 			info!("Repairing the block refs table");
-			bg.spawn_worker(RepairBlockrefsWorker::new(garage.clone()));
+			bg.spawn_worker(RepairBlockrefsWorker::new(garage.clone(), reset));
+		}
+		RepairWhat::GcVersions { older_than_days } => {
+			let older_than_days = std::cmp::max(
+				older_than_days.unwrap_or(0),
+				garage.config.version_gc_delay_days,
+			);
+			info!(
+				"Queuing versions deleted more than {} days ago for garbage collection",
+				older_than_days
+			);
+			bg.spawn_worker(GcVersionsWorker::new(garage.clone(), older_than_days));
+		}
+		RepairWhat::KeyBucketLinks => {
+			info!("Checking links between keys and buckets");
+			bg.spawn_worker(KeyBucketLinksWorker::new(garage.clone()));
+		}
+		RepairWhat::ValidateCrdtInvariants => {
+			info!("Checking invariants of objects and permissions");
+			bg.spawn_worker(CrdtInvariantsWorker::new(garage.clone()));
 		}
 		RepairWhat::Blocks => {
 			info!("Repairing the stored blocks");
@@ -44,6 +73,22 @@ pub async fn launch_online_repair(
 				garage.block_manager.clone(),
 			));
 		}
+		RepairWhat::Lifecycle { dry_run } => {
+			info!("Applying lifecycle rules (dry run: {})", dry_run);
+			bg.spawn_worker(LifecycleWorker::new_single_pass(garage.clone(), dry_run));
+		}
+		RepairWhat::OrphanBlocks => {
+			info!("Looking for orphan blocks");
+			bg.spawn_worker(garage_block::repair::OrphanBlockWorker::new(
+				garage.block_manager.clone(),
+			));
+		}
+		RepairWhat::CompressionInconsistency => {
+			info!("Repairing blocks stored both compressed and uncompressed");
+			bg.spawn_worker(garage_block::repair::CompressionRepairWorker::new(
+				garage.block_manager.clone(),
+			));
+		}
 		RepairWhat::Scrub { cmd } => {
 			let cmd = match cmd {
 				ScrubCmd::Start => ScrubWorkerCommand::Start,
@@ -67,18 +112,62 @@ pub async fn launch_online_repair(
 
 // ----
 
+/// Number of entries processed by a table repair worker between two saves of its progress
+const REPAIR_PROGRESS_SAVE_INTERVAL: usize = 1000;
+
+/// Position reached by a table repair worker. It is saved regularly, so that
+/// a repair interrupted by a restart of the node continues where it stopped
+/// when it is launched again, instead of scanning the whole table again.
+#[derive(Serialize, Deserialize, Default)]
+struct RepairProgress {
+	#[serde(with = "serde_bytes")]
+	pos: Vec<u8>,
+	counter: usize,
+}
+
+impl garage_util::migrate::InitialFormat for RepairProgress {}
+
+/// Load the saved progress of a table repair worker, or discard it if `reset` is set
+fn load_repair_progress(
+	garage: &Garage,
+	file_name: &str,
+	reset: bool,
+) -> (PersisterShared<RepairProgress>, Vec<u8>, usize) {
+	let progress = PersisterShared::<RepairProgress>::new(&garage.system.metadata_dir, file_name);
+	if reset {
+		if let Err(e) = progress.set_with(|p| *p = RepairProgress::default()) {
+			warn!(
+				"Could not reset the progress of the repair in {}: {}",
+				file_name, e
+			);
+		}
+	}
+	let (pos, counter) = progress.get_with(|p| (p.pos.clone(), p.counter));
+	if counter > 0 {
+		info!(
+			"Resuming repair from {} after {} entries (use --reset to start over)",
+			file_name, counter
+		);
+	}
+	(progress, pos, counter)
+}
+
 struct RepairVersionsWorker {
 	garage: Arc<Garage>,
+	progress: PersisterShared<RepairProgress>,
 	pos: Vec<u8>,
 	counter: usize,
 }
 
 impl RepairVersionsWorker {
-	fn new(garage: Arc<Garage>) -> Self {
+	fn new(garage: Arc<Garage>, reset: bool) -> Self {
+		let (progress, pos, counter) =
+			load_repair_progress(&garage, "repair_versions_progress", reset);
 		Self {
 			garage,
-			pos: vec![],
-			counter: 0,
+			progress,
+			pos,
+			counter,
 		}
 	}
 }
@@ -101,6 +190,7 @@ impl Worker for RepairVersionsWorker {
 			Some((k, v)) => (v, k),
 			None => {
 				info!("repair_versions: finished, done {}", self.counter);
+				self.progress.set_with(|p| *p = RepairProgress::default())?;
 				return Ok(WorkerState::Done);
 			}
 		};
@@ -135,6 +225,13 @@ impl Worker for RepairVersionsWorker {
 
 		self.counter += 1;
 		self.pos = next_pos;
+		let saved_counter = self.progress.get_with(|p| p.counter);
+		if self.counter >= saved_counter + REPAIR_PROGRESS_SAVE_INTERVAL {
+			self.progress.set_with(|p| {
+				p.pos = self.pos.clone();
+				p.counter = self.counter;
+			})?;
+		}
 
 		Ok(WorkerState::Busy)
 	}
@@ -146,18 +243,526 @@ impl Worker for RepairVersionsWorker {
 
 // ----
 
-struct RepairBlockrefsWorker {
+struct GcVersionsWorker {
 	garage: Arc<Garage>,
+	older_than_days: u64,
+	cutoff: u64,
+	// Versions referenced by block refs that are not deleted,
+	// collected from the block ref table before the version table is scanned
+	live_versions: HashSet<Uuid>,
+	scanning_versions: bool,
 	pos: Vec<u8>,
 	counter: usize,
+	queued: usize,
 }
 
-impl RepairBlockrefsWorker {
+impl GcVersionsWorker {
+	fn new(garage: Arc<Garage>, older_than_days: u64) -> Self {
+		let delay_msec = older_than_days * 24 * 3600 * 1000;
+		Self {
+			garage,
+			older_than_days,
+			cutoff: now_msec().saturating_sub(delay_msec),
+			live_versions: HashSet::new(),
+			scanning_versions: false,
+			pos: vec![],
+			counter: 0,
+			queued: 0,
+		}
+	}
+}
+
+/// Whether the tombstone of a deleted version can be garbage collected:
+/// its deletion must have been propagated (its blocks are cleared), no block ref
+/// that is not deleted may reference it, its object must not use it anymore,
+/// and that object must not have been written since `cutoff`
+/// (the version was deleted when a newer version of the object was written)
+fn is_version_collectible(
+	version: &Version,
+	object: Option<&Object>,
+	live_versions: &HashSet<Uuid>,
+	cutoff: u64,
+) -> bool {
+	if !version.deleted.get() || !version.blocks.items().is_empty() {
+		return false;
+	}
+	if live_versions.contains(&version.uuid) {
+		return false;
+	}
+	match object {
+		Some(o) => o.versions().iter().all(|x| {
+			x.timestamp < cutoff
+				&& (x.uuid != version.uuid || x.state == ObjectVersionState::Aborted)
+		}),
+		None => true,
+	}
+}
+
+#[async_trait]
+impl Worker for GcVersionsWorker {
+	fn name(&self) -> String {
+		"Version GC repair worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let phase = if self.scanning_versions {
+			"versions"
+		} else {
+			"block refs"
+		};
+		WorkerStatus {
+			progress: Some(format!("{} ({})", self.counter, phase)),
+			freeform: vec![
+				format!(
+					"Versions deleted more than {} days ago",
+					self.older_than_days
+				),
+				format!("Versions queued for GC: {}", self.queued),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if !self.scanning_versions {
+			match self.garage.block_ref_table.data.store.get_gt(&self.pos)? {
+				Some((k, v)) => {
+					let block_ref = BlockRef::decode(&v).ok_or_message("Cannot decode BlockRef")?;
+					if !block_ref.deleted.get() {
+						self.live_versions.insert(block_ref.version);
+					}
+					self.counter += 1;
+					self.pos = k;
+				}
+				None => {
+					self.scanning_versions = true;
+					self.counter = 0;
+					self.pos = vec![];
+				}
+			}
+			return Ok(WorkerState::Busy);
+		}
+
+		let (item_bytes, next_pos) = match self.garage.version_table.data.store.get_gt(&self.pos)? {
+			Some((k, v)) => (v, k),
+			None => {
+				info!(
+					"gc_versions: finished, done {}, queued {} for GC",
+					self.counter, self.queued
+				);
+				return Ok(WorkerState::Done);
+			}
+		};
+
+		let version = Version::decode(&item_bytes).ok_or_message("Cannot decode Version")?;
+		if version.deleted.get() && version.blocks.items().is_empty() {
+			let object = self
+				.garage
+				.object_table
+				.get(&version.bucket_id, &version.key)
+				.await?;
+			if is_version_collectible(&version, object.as_ref(), &self.live_versions, self.cutoff) {
+				// The tombstone is only deleted after the delay has elapsed again,
+				// so that it is at least that old even if its age could not be checked
+				let delay = Duration::from_secs(self.older_than_days * 24 * 3600);
+				if self.garage.version_table.data.queue_tombstone_gc(
+					&next_pos,
+					&item_bytes,
+					delay,
+				)? {
+					self.queued += 1;
+				}
+			}
+		}
+
+		self.counter += 1;
+		self.pos = next_pos;
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+struct KeyBucketLinksWorker {
+	garage: Arc<Garage>,
+	checking_buckets: bool,
+	pos: Vec<u8>,
+	counter: usize,
+	fixed: usize,
+}
+
+impl KeyBucketLinksWorker {
+	fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			checking_buckets: false,
+			pos: vec![],
+			counter: 0,
+			fixed: 0,
+		}
+	}
+
+	async fn bucket_exists(&self, bucket_id: Uuid) -> Result<bool, Error> {
+		Ok(matches!(
+			self.garage.bucket_table.get(&EmptyKey, &bucket_id).await?,
+			Some(b) if !b.is_deleted()
+		))
+	}
+
+	async fn key_exists(&self, key_id: &String) -> Result<bool, Error> {
+		Ok(matches!(
+			self.garage.key_table.get(&EmptyKey, key_id).await?,
+			Some(k) if !k.state.is_deleted()
+		))
+	}
+
+	async fn check_key(&mut self, mut key: Key) -> Result<(), Error> {
+		let kstate = match key.state.as_option_mut() {
+			Some(s) => s,
+			None => return Ok(()),
+		};
+
+		let mut authorized_buckets = crdt::Map::new();
+		for (bucket_id, perm) in kstate.authorized_buckets.items() {
+			if perm.is_any() && !self.bucket_exists(*bucket_id).await? {
+				info!(
+					"key-bucket-links: removing permissions of key {} on missing bucket {:?}",
+					key.key_id, bucket_id
+				);
+				authorized_buckets.put(
+					*bucket_id,
+					BucketKeyPerm {
+						timestamp: increment_logical_clock(perm.timestamp),
+						..BucketKeyPerm::NO_PERMISSIONS
+					},
+				);
+			}
+		}
+
+		let mut local_aliases = crdt::LwwMap::new();
+		for (alias, _, bucket_id) in kstate.local_aliases.items() {
+			if let Some(bucket_id) = bucket_id {
+				if !self.bucket_exists(*bucket_id).await? {
+					info!(
+						"key-bucket-links: removing local alias {} of key {} to missing bucket {:?}",
+						alias, key.key_id, bucket_id
+					);
+					local_aliases.merge(&kstate.local_aliases.update_mutator(alias.clone(), None));
+				}
+			}
+		}
+
+		if !authorized_buckets.is_empty() || !local_aliases.is_empty() {
+			self.fixed += authorized_buckets.len() + local_aliases.len();
+			kstate.authorized_buckets = authorized_buckets;
+			kstate.local_aliases = local_aliases;
+			self.garage.key_table.insert(&key).await?;
+		}
+
+		Ok(())
+	}
+
+	async fn check_bucket(&mut self, mut bucket: Bucket) -> Result<(), Error> {
+		let bstate = match bucket.state.as_option_mut() {
+			Some(s) => s,
+			None => return Ok(()),
+		};
+
+		let mut authorized_keys = crdt::Map::new();
+		for (key_id, perm) in bstate.authorized_keys.items() {
+			if perm.is_any() && !self.key_exists(key_id).await? {
+				info!(
+					"key-bucket-links: removing permissions of missing key {} on bucket {:?}",
+					key_id, bucket.id
+				);
+				authorized_keys.put(
+					key_id.clone(),
+					BucketKeyPerm {
+						timestamp: increment_logical_clock(perm.timestamp),
+						..BucketKeyPerm::NO_PERMISSIONS
+					},
+				);
+			}
+		}
+
+		let mut local_aliases = crdt::LwwMap::new();
+		for ((key_id, alias), _, active) in bstate.local_aliases.items() {
+			if *active && !self.key_exists(key_id).await? {
+				info!(
+					"key-bucket-links: removing local alias {} of missing key {} on bucket {:?}",
+					alias, key_id, bucket.id
+				);
+				local_aliases.merge(
+					&bstate
+						.local_aliases
+						.update_mutator((key_id.clone(), alias.clone()), false),
+				);
+			}
+		}
+
+		if !authorized_keys.is_empty() || !local_aliases.is_empty() {
+			self.fixed += authorized_keys.len() + local_aliases.len();
+			bstate.authorized_keys = authorized_keys;
+			bstate.local_aliases = local_aliases;
+			self.garage.bucket_table.insert(&bucket).await?;
+		}
+
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for KeyBucketLinksWorker {
+	fn name(&self) -> String {
+		"Key-bucket links repair worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform: vec![format!("Dangling entries removed: {}", self.fixed)],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if !self.checking_buckets {
+			match self.garage.key_table.data.store.get_gt(&self.pos)? {
+				Some((k, v)) => {
+					let key = Key::decode(&v).ok_or_message("Cannot decode Key")?;
+					self.check_key(key).await?;
+					self.pos = k;
+				}
+				None => {
+					self.checking_buckets = true;
+					self.pos = vec![];
+				}
+			}
+		} else {
+			match self.garage.bucket_table.data.store.get_gt(&self.pos)? {
+				Some((k, v)) => {
+					let bucket = Bucket::decode(&v).ok_or_message("Cannot decode Bucket")?;
+					self.check_bucket(bucket).await?;
+					self.pos = k;
+				}
+				None => {
+					info!(
+						"key_bucket_links: finished, done {}, removed {} dangling entries",
+						self.counter, self.fixed
+					);
+					return Ok(WorkerState::Done);
+				}
+			}
+		}
+
+		self.counter += 1;
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+struct CrdtInvariantsWorker {
+	garage: Arc<Garage>,
+	checking_buckets: bool,
+	pos: Vec<u8>,
+	counter: usize,
+	violations: usize,
+}
+
+impl CrdtInvariantsWorker {
 	fn new(garage: Arc<Garage>) -> Self {
 		Self {
 			garage,
+			checking_buckets: false,
 			pos: vec![],
 			counter: 0,
+			violations: 0,
+		}
+	}
+
+	fn violation(&mut self, msg: String) {
+		error!("crdt-invariants: {}", msg);
+		self.violations += 1;
+	}
+
+	fn check_object(&mut self, object: &Object) {
+		for msg in object_invariant_violations(object, now_msec()) {
+			self.violation(msg);
+		}
+	}
+
+	async fn check_bucket(&mut self, bucket: &Bucket) -> Result<(), Error> {
+		let bstate = match bucket.state.as_option() {
+			Some(s) => s,
+			None => return Ok(()),
+		};
+
+		// Deleted keys no longer store their authorized buckets: permissions
+		// must have been removed on the bucket side when the key was deleted
+		for (key_id, perm) in bstate.authorized_keys.items() {
+			if !perm.is_any() {
+				continue;
+			}
+			let key = self.garage.key_table.get(&EmptyKey, key_id).await?;
+			if matches!(key, Some(k) if k.state.is_deleted()) {
+				self.violation(format!(
+					"deleted key {} still has permissions {:?} on bucket {:?}",
+					key_id, perm, bucket.id
+				));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Check the invariants of the CRDT of an object, returning the violations found
+fn object_invariant_violations(object: &Object, now: u64) -> Vec<String> {
+	let mut violations = vec![];
+	let versions = object.versions();
+
+	// An object with no versions is neither data nor a tombstone,
+	// and would never be garbage collected
+	if versions.is_empty() {
+		violations.push(format!("object has no versions: {:?}", object));
+		return violations;
+	}
+
+	// Versions are kept sorted by (timestamp, uuid), and merged when
+	// they have the same timestamp and uuid
+	if versions
+		.windows(2)
+		.any(|w| (w[0].timestamp, w[0].uuid) >= (w[1].timestamp, w[1].uuid))
+	{
+		violations.push(format!(
+			"object versions are not sorted or contain duplicates: {:?}",
+			object
+		));
+	}
+
+	// A version uuid can only be used for one non-aborted version
+	for (i, v) in versions.iter().enumerate() {
+		if v.state != ObjectVersionState::Aborted
+			&& versions[i + 1..]
+				.iter()
+				.any(|v2| v2.uuid == v.uuid && v2.state != ObjectVersionState::Aborted)
+		{
+			violations.push(format!(
+				"version {:?} is used for several non-aborted versions of object: {:?}",
+				v.uuid, object
+			));
+		}
+	}
+
+	// Versions that come before the last complete one are removed on merge,
+	// except those retained by bucket versioning, so among the other versions
+	// a complete one can only be the first one
+	if versions
+		.iter()
+		.filter(|v| !v.is_retained())
+		.skip(1)
+		.any(|v| v.is_complete())
+	{
+		violations.push(format!(
+			"object has versions stored before a complete version: {:?}",
+			object
+		));
+	}
+
+	// Tombstones are removed by the table GC, so an object can only be
+	// a tombstone if it stores nothing that must be kept: its only version
+	// must be a delete marker that is not locked
+	if object.is_tombstone() && versions[0].is_locked(now) {
+		violations.push(format!(
+			"object is a tombstone but its delete marker is locked: {:?}",
+			object
+		));
+	}
+
+	violations
+}
+
+#[async_trait]
+impl Worker for CrdtInvariantsWorker {
+	fn name(&self) -> String {
+		"CRDT invariants validation worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.counter.to_string()),
+			freeform: vec![format!("Invariant violations found: {}", self.violations)],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if !self.checking_buckets {
+			match self.garage.object_table.data.store.get_gt(&self.pos)? {
+				Some((k, v)) => {
+					let object = Object::decode(&v).ok_or_message("Cannot decode Object")?;
+					self.check_object(&object);
+					self.pos = k;
+				}
+				None => {
+					self.checking_buckets = true;
+					self.pos = vec![];
+				}
+			}
+		} else {
+			match self.garage.bucket_table.data.store.get_gt(&self.pos)? {
+				Some((k, v)) => {
+					let bucket = Bucket::decode(&v).ok_or_message("Cannot decode Bucket")?;
+					self.check_bucket(&bucket).await?;
+					self.pos = k;
+				}
+				None => {
+					info!(
+						"crdt_invariants: finished, done {}, found {} violations",
+						self.counter, self.violations
+					);
+					return Ok(WorkerState::Done);
+				}
+			}
+		}
+
+		self.counter += 1;
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ----
+
+struct RepairBlockrefsWorker {
+	garage: Arc<Garage>,
+	progress: PersisterShared<RepairProgress>,
+	pos: Vec<u8>,
+	counter: usize,
+}
+
+impl RepairBlockrefsWorker {
+	fn new(garage: Arc<Garage>, reset: bool) -> Self {
+		let (progress, pos, counter) =
+			load_repair_progress(&garage, "repair_blockrefs_progress", reset);
+		Self {
+			garage,
+			progress,
+			pos,
+			counter,
 		}
 	}
 }
@@ -181,6 +786,7 @@ impl Worker for RepairBlockrefsWorker {
 				Some((k, v)) => (v, k),
 				None => {
 					info!("repair_block_ref: finished, done {}", self.counter);
+					self.progress.set_with(|p| *p = RepairProgress::default())?;
 					return Ok(WorkerState::Done);
 				}
 			};
@@ -212,6 +818,13 @@ impl Worker for RepairBlockrefsWorker {
 
 		self.counter += 1;
 		self.pos = next_pos;
+		let saved_counter = self.progress.get_with(|p| p.counter);
+		if self.counter >= saved_counter + REPAIR_PROGRESS_SAVE_INTERVAL {
+			self.progress.set_with(|p| {
+				p.pos = self.pos.clone();
+				p.counter = self.counter;
+			})?;
+		}
 
 		Ok(WorkerState::Busy)
 	}
@@ -220,3 +833,113 @@ impl Worker for RepairBlockrefsWorker {
 		unreachable!()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_version_collectible() {
+		let bucket_id = gen_uuid();
+		let now = now_msec();
+		let day = 24 * 3600 * 1000;
+		let cutoff = now - 7 * day;
+
+		let deleted = Version::new(gen_uuid(), bucket_id, "key".into(), true);
+		let object_version = |uuid, timestamp, state| ObjectVersion {
+			uuid,
+			timestamp,
+			state,
+			versioned: false,
+			legal_hold: crdt::Lww::new(false),
+		};
+		let object = |versions| Object::new(bucket_id, "key".into(), versions);
+		let no_refs = HashSet::new();
+
+		// Only deleted versions whose deletion was propagated can be collected
+		let live = Version::new(gen_uuid(), bucket_id, "key".into(), false);
+		assert!(!is_version_collectible(&live, None, &no_refs, cutoff));
+		assert!(is_version_collectible(&deleted, None, &no_refs, cutoff));
+
+		// Versions referenced by a block ref that is not deleted are kept
+		let mut refs = HashSet::new();
+		refs.insert(deleted.uuid);
+		assert!(!is_version_collectible(&deleted, None, &refs, cutoff));
+
+		// Versions of objects written after the cutoff are kept
+		let old = object(vec![object_version(
+			gen_uuid(),
+			now - 30 * day,
+			ObjectVersionState::Aborted,
+		)]);
+		assert!(is_version_collectible(
+			&deleted,
+			Some(&old),
+			&no_refs,
+			cutoff
+		));
+		let recent = object(vec![object_version(
+			gen_uuid(),
+			now - day,
+			ObjectVersionState::Aborted,
+		)]);
+		assert!(!is_version_collectible(
+			&deleted,
+			Some(&recent),
+			&no_refs,
+			cutoff
+		));
+
+		// Versions still used by their object are kept, unless aborted
+		let aborted = object(vec![object_version(
+			deleted.uuid,
+			now - 30 * day,
+			ObjectVersionState::Aborted,
+		)]);
+		assert!(is_version_collectible(
+			&deleted,
+			Some(&aborted),
+			&no_refs,
+			cutoff
+		));
+		let uploading = object(vec![object_version(
+			deleted.uuid,
+			now - 30 * day,
+			ObjectVersionState::Uploading(ObjectVersionHeaders {
+				content_type: "text/plain".into(),
+				other: Default::default(),
+			}),
+		)]);
+		assert!(!is_version_collectible(
+			&deleted,
+			Some(&uploading),
+			&no_refs,
+			cutoff
+		));
+	}
+
+	#[test]
+	fn test_tombstone_invariant() {
+		let bucket_id = gen_uuid();
+		let now = now_msec();
+		let delete_marker = |legal_hold| ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp: now,
+			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			versioned: false,
+			legal_hold: crdt::Lww::new(legal_hold),
+		};
+
+		let tombstone = Object::new(bucket_id, "key".into(), vec![delete_marker(false)]);
+		assert!(tombstone.is_tombstone());
+		assert!(object_invariant_violations(&tombstone, now).is_empty());
+
+		// A tombstone would be garbage collected with its locked delete marker
+		let bad_tombstone = Object::new(bucket_id, "key".into(), vec![delete_marker(true)]);
+		assert!(bad_tombstone.is_tombstone());
+		assert_eq!(object_invariant_violations(&bad_tombstone, now).len(), 1);
+
+		let empty = Object::new(bucket_id, "key".into(), vec![]);
+		assert_eq!(object_invariant_violations(&empty, now).len(), 1);
+	}
+}
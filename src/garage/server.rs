@@ -1,4 +1,6 @@
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::sync::watch;
 
@@ -7,8 +9,10 @@ use garage_util::config::*;
 use garage_util::error::Error;
 
 use garage_api::admin::api_server::AdminApiServer;
+use garage_api::admin::log_level::LogFilter;
 use garage_api::s3::api_server::S3ApiServer;
 use garage_model::garage::Garage;
+use garage_rpc::system::DEFAULT_PING_TIMEOUT_MSEC;
 use garage_web::WebServer;
 
 #[cfg(feature = "k2v")]
@@ -27,9 +31,13 @@ async fn wait_from(mut chan: watch::Receiver<bool>) {
 	}
 }
 
-pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Error> {
+pub async fn run_server(
+	config_file: PathBuf,
+	secrets: Secrets,
+	log_filter: LogFilter,
+) -> Result<(), Error> {
 	info!("Loading configuration...");
-	let config = fill_secrets(read_config(config_file)?, secrets);
+	let config = fill_secrets(read_config(config_file.clone())?, secrets.clone());
 
 	// ---- Initialize Garage internals ----
 
@@ -46,11 +54,23 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	info!("Spawning Garage workers...");
 	garage.spawn_workers(&background);
 
+	#[cfg(unix)]
+	tokio::spawn(reload_config_on_sighup(
+		garage.clone(),
+		config_file,
+		secrets,
+		watch_cancel.clone(),
+	));
+
 	if config.admin.trace_sink.is_some() {
 		info!("Initialize tracing...");
 
 		#[cfg(feature = "telemetry-otlp")]
-		init_tracing(config.admin.trace_sink.as_ref().unwrap(), garage.system.id)?;
+		init_tracing(
+			config.admin.trace_sink.as_ref().unwrap(),
+			config.admin.telemetry_sample_rate,
+			garage.system.id,
+		)?;
 
 		#[cfg(not(feature = "telemetry-otlp"))]
 		error!("Garage was built without OTLP exporter, admin.trace_sink is ignored.");
@@ -59,8 +79,10 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	info!("Initialize Admin API server and metrics collector...");
 	let admin_server = AdminApiServer::new(
 		garage.clone(),
+		background.clone(),
 		#[cfg(feature = "metrics")]
 		metrics_exporter,
+		log_filter,
 	);
 
 	info!("Launching internal Garage cluster communications...");
@@ -139,15 +161,39 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	} else {
 		// Stuff runs
 
-		// When a cancel signal is sent, stuff stops
+		// When a cancel signal is sent, stuff stops accepting new connections
+		// and waits for in-flight requests to complete, for at most
+		// graceful_shutdown_timeout_secs seconds
+		let shutdown_timeout = Duration::from_secs(config.graceful_shutdown_timeout_secs);
+		let shutdown_deadline = async {
+			wait_from(watch_cancel.clone()).await;
+			tokio::time::sleep(shutdown_timeout).await;
+		};
+		tokio::pin!(shutdown_deadline);
+		let mut deadline_passed = false;
 
 		// Collect stuff
-		for (desc, join_handle) in servers {
-			if let Err(e) = join_handle.await? {
-				error!("{} server exited with error: {}", desc, e);
-			} else {
-				info!("{} server exited without error.", desc);
+		for (desc, mut join_handle) in servers {
+			if !deadline_passed {
+				tokio::select! {
+					res = &mut join_handle => {
+						if let Err(e) = res? {
+							error!("{} server exited with error: {}", desc, e);
+						} else {
+							info!("{} server exited without error.", desc);
+						}
+						continue;
+					}
+					_ = &mut shutdown_deadline => {
+						deadline_passed = true;
+					}
+				}
 			}
+			warn!(
+				"{} server did not finish processing requests within {} seconds, aborting.",
+				desc, config.graceful_shutdown_timeout_secs
+			);
+			join_handle.abort();
 		}
 	}
 
@@ -160,19 +206,31 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	run_system.await?;
 	info!("Netapp exited");
 
+	info!("Saving cluster layout and peer list...");
+	if let Err(e) = garage.system.save_state().await {
+		error!("Unable to save cluster layout and peer list: {}", e);
+	}
+
 	// Drop all references so that stuff can terminate properly
+	let db = garage.db.clone();
 	drop(garage);
 
 	// Await for all background tasks to end
 	await_background_done.await?;
 
+	info!("Flushing metadata database...");
+	if let Err(e) = db.flush() {
+		error!("Unable to flush metadata database: {}", e);
+	}
+	drop(db);
+
 	info!("Cleaning up...");
 
 	Ok(())
 }
 
 #[cfg(unix)]
-fn watch_shutdown_signal() -> watch::Receiver<bool> {
+pub(crate) fn watch_shutdown_signal() -> watch::Receiver<bool> {
 	use tokio::signal::unix::*;
 
 	let (send_cancel, watch_cancel) = watch::channel(false);
@@ -180,11 +238,9 @@ fn watch_shutdown_signal() -> watch::Receiver<bool> {
 		let mut sigint = signal(SignalKind::interrupt()).expect("Failed to install SIGINT handler");
 		let mut sigterm =
 			signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
-		let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
 		tokio::select! {
 			_ = sigint.recv() => info!("Received SIGINT, shutting down."),
 			_ = sigterm.recv() => info!("Received SIGTERM, shutting down."),
-			_ = sighup.recv() => info!("Received SIGHUP, shutting down."),
 		}
 		send_cancel.send(true).unwrap();
 	});
@@ -192,7 +248,7 @@ fn watch_shutdown_signal() -> watch::Receiver<bool> {
 }
 
 #[cfg(windows)]
-fn watch_shutdown_signal() -> watch::Receiver<bool> {
+pub(crate) fn watch_shutdown_signal() -> watch::Receiver<bool> {
 	use tokio::signal::windows::*;
 
 	let (send_cancel, watch_cancel) = watch::channel(false);
@@ -211,3 +267,84 @@ fn watch_shutdown_signal() -> watch::Receiver<bool> {
 	});
 	watch_cancel
 }
+
+/// Re-read the configuration file when the process receives SIGHUP, and apply
+/// the changes of the settings that can be changed while the node is running
+#[cfg(unix)]
+async fn reload_config_on_sighup(
+	garage: Arc<Garage>,
+	config_file: PathBuf,
+	secrets: Secrets,
+	mut must_exit: watch::Receiver<bool>,
+) {
+	use tokio::signal::unix::*;
+
+	let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+	while !*must_exit.borrow() {
+		tokio::select! {
+			_ = sighup.recv() => (),
+			_ = must_exit.changed() => continue,
+		}
+
+		info!(
+			"Received SIGHUP, reloading configuration file {}",
+			config_file.display()
+		);
+		match read_config(config_file.clone()) {
+			Ok(c) => apply_config_changes(&garage, fill_secrets(c, secrets.clone())),
+			Err(e) => error!("Unable to reload configuration file: {}", e),
+		}
+	}
+}
+
+/// Apply the settings that differ from their current values through the
+/// background variables, and warn if other settings differ from the
+/// configuration the node was started with
+fn apply_config_changes(garage: &Garage, mut new: Config) {
+	let reloadable = [
+		("block_size", "block-size", new.block_size.to_string()),
+		(
+			"compression_level",
+			"compression-level",
+			new.compression_level.to_string(),
+		),
+		(
+			"rpc_ping_timeout_msec",
+			"rpc-ping-timeout-msec",
+			new.rpc_ping_timeout_msec
+				.unwrap_or(DEFAULT_PING_TIMEOUT_MSEC)
+				.to_string(),
+		),
+	];
+	let mut changed = 0;
+	for (setting, var, new_value) in reloadable {
+		let old_value = match garage.bg_vars.get(var) {
+			Ok(v) => v,
+			Err(e) => {
+				error!("Unable to read the current value of {}: {}", setting, e);
+				continue;
+			}
+		};
+		if old_value == new_value {
+			continue;
+		}
+		changed += 1;
+		match garage.bg_vars.set(var, &new_value) {
+			Ok(()) => info!("{} changed from {} to {}", setting, old_value, new_value),
+			Err(e) => error!("Unable to change {} to {}: {}", setting, new_value, e),
+		}
+	}
+
+	// All other settings are only read when the node starts
+	let old = &garage.config;
+	new.block_size = old.block_size;
+	new.compression_level = old.compression_level;
+	new.rpc_ping_timeout_msec = old.rpc_ping_timeout_msec;
+	let need_restart = format!("{:?}", old) != format!("{:?}", new);
+	if need_restart {
+		warn!("Some settings which are only read when the node starts were changed, restart the node to apply them");
+	}
+	if changed == 0 && !need_restart {
+		info!("No setting was changed in the configuration file");
+	}
+}
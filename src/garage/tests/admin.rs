@@ -51,3 +51,398 @@ async fn test_admin_bucket_perms() {
 
 	assert!(hb().await.is_err());
 }
+
+#[tokio::test]
+async fn test_admin_key_bucket_permission() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("keyperms");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "deny", "--read", "--write", "--owner", "--key"])
+		.args([&ctx.key.id, &bucket])
+		.quiet()
+		.expect_success_status("Could not deny bucket permissions");
+
+	let hb = || ctx.client.head_bucket().bucket(&bucket).send();
+	assert!(hb().await.is_err());
+
+	// --owner requires --write
+	let output = ctx
+		.garage
+		.command()
+		.args([
+			"key",
+			"add-bucket-permission",
+			&ctx.key.id,
+			&bucket,
+			"--owner",
+		])
+		.output()
+		.unwrap();
+	assert!(!output.status.success());
+
+	ctx.garage
+		.command()
+		.args([
+			"key",
+			"add-bucket-permission",
+			&ctx.key.id,
+			&bucket,
+			"--read",
+		])
+		.quiet()
+		.expect_success_status("Could not add bucket permission");
+
+	assert!(hb().await.is_ok());
+
+	ctx.garage
+		.command()
+		.args(["key", "remove-bucket-permission", &ctx.key.id, &bucket])
+		.quiet()
+		.expect_success_status("Could not remove bucket permission");
+
+	assert!(hb().await.is_err());
+}
+
+#[tokio::test]
+async fn test_admin_bucket_prefix_stats() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("prefixstats");
+
+	for key in ["a/1/x", "a/1/y", "a/2/z", "b/w", "root"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(aws_sdk_s3::primitives::ByteStream::from_static(b"hello"))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let output = ctx
+		.garage
+		.command()
+		.args([
+			"bucket",
+			"info",
+			"--per-prefix-stats",
+			"--prefix-depth",
+			"2",
+		])
+		.arg(&bucket)
+		.expect_success_output("Could not get bucket info");
+	let stdout = String::from_utf8(output.stdout).unwrap();
+
+	let prefix_line = |prefix: &str| {
+		stdout
+			.lines()
+			.map(|l| l.split_whitespace().collect::<Vec<_>>())
+			.find(|l| l.first() == Some(&prefix))
+			.unwrap_or_else(|| panic!("prefix {} not in output:\n{}", prefix, stdout))
+	};
+	assert_eq!(prefix_line("a/1/")[1], "2");
+	assert_eq!(prefix_line("a/2/")[1], "1");
+	assert_eq!(prefix_line("b/")[1], "1");
+	assert_eq!(prefix_line("/")[1], "1");
+}
+
+#[tokio::test]
+async fn test_admin_node_ping() {
+	let ctx = common::context();
+	let node_id = ctx.garage.node_id();
+
+	let output = ctx
+		.garage
+		.command()
+		.args([
+			"node",
+			"ping",
+			&node_id[..16],
+			"-c",
+			"3",
+			"-i",
+			"10ms",
+			"--json",
+		])
+		.expect_success_output("Could not ping node");
+	let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+	assert_eq!(stats["node"].as_str(), Some(&node_id[..64]));
+	assert_eq!(stats["rtt_msec"].as_array().map(Vec::len), Some(3));
+	assert!(stats["min_msec"].as_f64().unwrap() <= stats["max_msec"].as_f64().unwrap());
+}
+
+#[tokio::test]
+async fn test_admin_block_list_local() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("blocklistlocal");
+
+	// Objects bigger than the inline threshold are stored as data blocks
+	for (key, byte) in [("obj1", 1u8), ("obj2", 2u8)] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(aws_sdk_s3::primitives::ByteStream::from(vec![byte; 10_000]))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let list = |args: &[&str]| {
+		let output = ctx
+			.garage
+			.command()
+			.args(["block", "list-local", "--format", "csv"])
+			.args(args)
+			.expect_success_output("Could not list local blocks");
+		String::from_utf8(output.stdout)
+			.unwrap()
+			.lines()
+			.map(String::from)
+			.collect::<Vec<_>>()
+	};
+
+	let all = list(&[]);
+	assert_eq!(all[0], "hash,size_bytes,is_compressed,rc");
+	let blocks = &all[1..];
+	assert!(blocks.len() >= 2);
+	let mut sorted = blocks.to_vec();
+	sorted.sort();
+	assert_eq!(blocks, &sorted[..]);
+	for b in blocks {
+		let cols = b.split(',').collect::<Vec<_>>();
+		assert_eq!(cols.len(), 4);
+		assert_eq!(cols[0].len(), 64);
+		assert!(cols[3].parse::<u64>().unwrap() >= 1);
+	}
+
+	let limited = list(&["--limit", "1"]);
+	assert_eq!(&limited[1..], &blocks[..1]);
+
+	let prefix = &blocks[0][..3];
+	let by_prefix = list(&["--prefix", prefix]);
+	assert!(by_prefix[1..].iter().all(|b| b.starts_with(prefix)));
+	assert!(by_prefix[1..].contains(&blocks[0]));
+}
+
+#[tokio::test]
+async fn test_admin_bucket_lifecycle() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("lifecyclecli");
+
+	let json_file = ctx.garage.path.join("lifecycle.json");
+	std::fs::write(
+		&json_file,
+		r#"{"Rules": [{
+			"ID": "expire-logs",
+			"Status": "Enabled",
+			"Filter": {"Prefix": "logs/"},
+			"Expiration": {"Days": 30}
+		}]}"#,
+	)
+	.unwrap();
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-lifecycle", &bucket, "--from-file"])
+		.arg(&json_file)
+		.quiet()
+		.expect_success_status("Could not set lifecycle rules");
+
+	// Rules are visible through the S3 API
+	let r = ctx
+		.client
+		.get_bucket_lifecycle_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let rules = r.rules.unwrap();
+	assert_eq!(rules.len(), 1);
+	assert_eq!(rules[0].id.as_deref(), Some("expire-logs"));
+	assert_eq!(rules[0].expiration.as_ref().unwrap().days, 30);
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "get-lifecycle", &bucket])
+		.expect_success_output("Could not get lifecycle rules");
+	let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+	assert_eq!(json["Rules"][0]["ID"], "expire-logs");
+	assert_eq!(json["Rules"][0]["Filter"]["Prefix"], "logs/");
+	assert_eq!(json["Rules"][0]["Expiration"]["Days"], 30);
+
+	// Invalid rules are rejected
+	std::fs::write(
+		&json_file,
+		r#"{"Rules": [{"Status": "Enabled", "Expiration": {"Days": -1}}]}"#,
+	)
+	.unwrap();
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "set-lifecycle", &bucket, "--from-file"])
+		.arg(&json_file)
+		.output()
+		.unwrap();
+	assert!(!output.status.success());
+
+	ctx.garage
+		.command()
+		.args(["bucket", "delete-lifecycle", &bucket])
+		.quiet()
+		.expect_success_status("Could not delete lifecycle rules");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["bucket", "get-lifecycle", &bucket])
+		.expect_success_output("Could not get lifecycle rules");
+	let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+	assert_eq!(json["Rules"].as_array().map(Vec::len), Some(0));
+}
+
+#[tokio::test]
+async fn test_admin_bucket_set_encryption() {
+	use aws_sdk_s3::primitives::ByteStream;
+	use aws_sdk_s3::types::ServerSideEncryption;
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("encryptioncli");
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-encryption", &bucket, "--sse-s3"])
+		.quiet()
+		.expect_success_status("Could not set default encryption");
+
+	// The setting is visible through the S3 API
+	let r = ctx
+		.client
+		.get_bucket_encryption()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let rules = r
+		.server_side_encryption_configuration
+		.unwrap()
+		.rules
+		.unwrap();
+	assert_eq!(rules.len(), 1);
+	assert_eq!(
+		rules[0]
+			.apply_server_side_encryption_by_default
+			.as_ref()
+			.unwrap()
+			.sse_algorithm,
+		Some(ServerSideEncryption::Aes256)
+	);
+
+	// Objects cannot be stored unencrypted
+	let put = || {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key("test")
+			.body(ByteStream::from_static(b"Hello world!"))
+			.send()
+	};
+	assert!(put().await.is_err());
+
+	ctx.garage
+		.command()
+		.args(["bucket", "set-encryption", &bucket, "--none"])
+		.quiet()
+		.expect_success_status("Could not remove default encryption");
+	put().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_admin_node_set_hostname() {
+	let ctx = common::context();
+
+	ctx.garage
+		.command()
+		.args(["node", "set-hostname", "garage-test-node"])
+		.quiet()
+		.expect_success_status("Could not set hostname");
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["status"])
+		.expect_success_output("Could not get status");
+	assert!(String::from_utf8(output.stdout)
+		.unwrap()
+		.contains("garage-test-node"));
+
+	let long_hostname = "x".repeat(256);
+	let output = ctx
+		.garage
+		.command()
+		.args(["node", "set-hostname", &long_hostname])
+		.output()
+		.unwrap();
+	assert!(!output.status.success());
+}
+
+#[tokio::test]
+async fn test_admin_bucket_list_sort() {
+	let ctx = common::context();
+	let small = ctx.create_bucket("sortsmall");
+	let big = ctx.create_bucket("sortbig");
+
+	for (bucket, size) in [(&small, 10), (&big, 1000)] {
+		ctx.client
+			.put_object()
+			.bucket(bucket)
+			.key("obj")
+			.body(aws_sdk_s3::primitives::ByteStream::from(vec![0u8; size]))
+			.send()
+			.await
+			.unwrap();
+	}
+
+	let list = |args: &[&str]| {
+		let output = ctx
+			.garage
+			.command()
+			.args(["bucket", "list"])
+			.args(args)
+			.expect_success_output("Could not list buckets");
+		let stdout = String::from_utf8(output.stdout).unwrap();
+		let pos = |name: &str| stdout.find(&format!("{} ", name)).unwrap();
+		pos(&big) < pos(&small)
+	};
+
+	assert!(list(&["--sort-by", "size"]));
+	assert!(!list(&["--sort-by", "size", "--reverse"]));
+	assert!(list(&["--sort-by", "name"]));
+	assert!(!list(&["--sort-by", "name", "--reverse"]));
+}
+
+#[tokio::test]
+async fn test_admin_export_metrics_snapshot() {
+	let ctx = common::context();
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["server", "export-metrics-snapshot"])
+		.expect_success_output("Could not export metrics");
+	let text = String::from_utf8(output.stdout).unwrap();
+	assert!(text.contains("# TYPE"));
+
+	let output = ctx
+		.garage
+		.command()
+		.args(["server", "export-metrics-snapshot", "--format", "json"])
+		.expect_success_output("Could not export metrics");
+	let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+	assert!(stats["status"].is_string());
+	assert!(stats["known_nodes"].as_u64().unwrap() >= 1);
+}
@@ -11,6 +11,9 @@ pub const DEFAULT_PORT: u16 = 49995;
 static GARAGE_TEST_SECRET: &str =
 	"c3ea8cb80333d04e208d136698b1a01ae370d463f0d435ab2177510b3478bf44";
 
+static GARAGE_TEST_BLOCK_ENCRYPTION_KEY: &str =
+	"5b0f8e3a7d2c41e69a1f6c08b4d3e2a17f9c5e80d1b6a4f23c7e9d0a8b5f1c36";
+
 #[derive(Debug, Default, Clone)]
 pub struct Key {
 	pub name: Option<String>,
@@ -54,6 +57,8 @@ metadata_dir = "{path}/meta"
 data_dir = "{path}/data"
 
 replication_mode = "1"
+block_cache_size = "16MiB"
+block_encryption_key = "{enc_key}"
 
 rpc_bind_addr = "127.0.0.1:{rpc_port}"
 rpc_public_addr = "127.0.0.1:{rpc_port}"
@@ -77,6 +82,7 @@ api_bind_addr = "127.0.0.1:{admin_port}"
 "#,
 			path = path.display(),
 			secret = GARAGE_TEST_SECRET,
+			enc_key = GARAGE_TEST_BLOCK_ENCRYPTION_KEY,
 			region = super::REGION,
 			s3_port = port,
 			k2v_port = port + 1,
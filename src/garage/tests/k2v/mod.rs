@@ -2,4 +2,5 @@ pub mod batch;
 pub mod errorcodes;
 pub mod item;
 pub mod poll;
+pub mod range;
 pub mod simple;
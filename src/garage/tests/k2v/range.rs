@@ -0,0 +1,108 @@
+use crate::common;
+
+use base64::prelude::*;
+
+use crate::json_body;
+use hyper::{Method, StatusCode};
+
+#[tokio::test]
+async fn test_read_range() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-read-range");
+
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.body(
+			format!(
+				r#"[
+	{{"pk": "root", "sk": "a", "ct": null, "v": "{}"}},
+	{{"pk": "root", "sk": "b", "ct": null, "v": "{}"}},
+	{{"pk": "root", "sk": "c", "ct": null, "v": "{}"}},
+	{{"pk": "root", "sk": "d/1", "ct": null, "v": "{}"}},
+	{{"pk": "root", "sk": "e", "ct": null, "v": "{}"}}
+		]"#,
+				BASE64_STANDARD.encode("value a"),
+				BASE64_STANDARD.encode("value b"),
+				BASE64_STANDARD.encode("value c"),
+				BASE64_STANDARD.encode("value d/1"),
+				BASE64_STANDARD.encode("value e"),
+			)
+			.into_bytes(),
+		)
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+	// Paginate through the whole partition, three items at a time:
+	// the X-Next-Start header contains the URI-encoded sort key of the
+	// next page, and is passed as is in the start parameter
+	let mut start: Option<String> = None;
+	let mut seen = vec![];
+	let mut next_starts = vec![];
+	loop {
+		let res = ctx
+			.k2v
+			.request
+			.builder(bucket.clone())
+			.path("root")
+			.query_param("limit", Some("3"))
+			.query_param("start", start.as_deref())
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+		let next_start = res
+			.headers()
+			.get("x-next-start")
+			.map(|v| v.to_str().unwrap().to_string());
+
+		let json_res = json_body(res).await;
+		for item in json_res["items"].as_array().unwrap() {
+			assert!(!item["ct"].as_str().unwrap().is_empty());
+			seen.push(item["sk"].as_str().unwrap().to_string());
+		}
+		assert_eq!(json_res["more"].as_bool(), Some(next_start.is_some()));
+
+		match next_start {
+			Some(ns) => {
+				let json_ns = json_res["nextStart"].as_str().unwrap().to_string();
+				next_starts.push((ns.clone(), json_ns));
+				start = Some(ns);
+			}
+			None => break,
+		}
+	}
+	assert_eq!(next_starts, [("d%2F1".to_string(), "d/1".to_string())]);
+	assert_eq!(seen, ["a", "b", "c", "d/1", "e"]);
+
+	// Bounded reverse range
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("root")
+		.query_param("start", Some("d/1"))
+		.query_param("end", Some("a"))
+		.query_param("reverse", Some("true"))
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	assert!(res.headers().get("x-next-start").is_none());
+	let json_res = json_body(res).await;
+	let sks = json_res["items"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|i| i["sk"].as_str().unwrap())
+		.collect::<Vec<_>>();
+	assert_eq!(sks, ["d/1", "c", "b"]);
+	assert_eq!(
+		json_res["items"][0]["v"][0].as_str(),
+		Some(BASE64_STANDARD.encode("value d/1").as_str())
+	);
+}
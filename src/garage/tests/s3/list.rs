@@ -1,4 +1,5 @@
 use crate::common;
+use aws_sdk_s3::types::{BucketVersioningStatus, VersioningConfiguration};
 
 const KEYS: [&str; 8] = ["a", "a/a", "a/b", "a/c", "a/d/a", "a/é", "b", "c"];
 const KEYS_MULTIPART: [&str; 5] = ["a", "a", "c", "c/a", "c/b"];
@@ -613,3 +614,126 @@ async fn test_listmultipart() {
 		assert!(r.common_prefixes.is_none());
 	}
 }
+
+#[tokio::test]
+async fn test_listobjectversions() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("listobjectversions");
+
+	let r = ctx
+		.client
+		.get_bucket_versioning()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert!(r.status.is_none());
+
+	// Written before versioning is enabled: replaced by the next version
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_bucket_versioning()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.status, Some(BucketVersioningStatus::Enabled));
+
+	for k in ["a", "a", "b"] {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(k)
+			.send()
+			.await
+			.unwrap();
+	}
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key("b")
+		.send()
+		.await
+		.unwrap();
+
+	{
+		let r = ctx
+			.client
+			.list_object_versions()
+			.bucket(&bucket)
+			.send()
+			.await
+			.unwrap();
+
+		let versions = r.versions.unwrap();
+		assert_eq!(versions.len(), 3);
+		assert_eq!(versions.iter().filter(|v| v.is_latest).count(), 1);
+		assert!(versions
+			.iter()
+			.all(|v| v.version_id.as_deref() != Some("null")));
+
+		let delete_markers = r.delete_markers.unwrap();
+		assert_eq!(delete_markers.len(), 1);
+		assert_eq!(delete_markers[0].key.as_deref(), Some("b"));
+		assert!(delete_markers[0].is_latest);
+		assert!(!r.is_truncated);
+	}
+
+	{
+		// With pagination
+		let mut seen = 0;
+		let mut key_marker = None;
+		let mut version_id_marker = None;
+		loop {
+			let r = ctx
+				.client
+				.list_object_versions()
+				.bucket(&bucket)
+				.max_keys(1)
+				.set_key_marker(key_marker)
+				.set_version_id_marker(version_id_marker)
+				.send()
+				.await
+				.unwrap();
+
+			seen += r.versions.map(|v| v.len()).unwrap_or(0)
+				+ r.delete_markers.map(|v| v.len()).unwrap_or(0);
+			if !r.is_truncated {
+				break;
+			}
+			key_marker = r.next_key_marker;
+			version_id_marker = r.next_version_id_marker;
+		}
+		assert_eq!(seen, 4);
+	}
+
+	// Only the current version is listed as an object
+	let r = ctx
+		.client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(r.contents.unwrap().len(), 1);
+}
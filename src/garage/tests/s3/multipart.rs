@@ -413,3 +413,69 @@ async fn test_uploadpartcopy() {
 	assert_eq!(real_obj.len(), exp_obj.len());
 	assert_eq!(real_obj, exp_obj);
 }
+
+#[tokio::test]
+async fn test_complete_multipart_object_size() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("completempobjectsize");
+
+	let up = ctx
+		.client
+		.create_multipart_upload()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	let uid = up.upload_id.unwrap();
+
+	let p1 = ctx
+		.client
+		.upload_part()
+		.bucket(&bucket)
+		.key("a")
+		.upload_id(&uid)
+		.part_number(1)
+		.body(ByteStream::from(vec![0x42; 10_000]))
+		.send()
+		.await
+		.unwrap();
+
+	let body = format!(
+		"<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>{}</ETag></Part></CompleteMultipartUpload>",
+		p1.e_tag.unwrap()
+	);
+	let complete = |size: &'static str| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.method(hyper::Method::POST)
+			.path("a")
+			.query_param("uploadId", Some(&uid))
+			.signed_header("x-amz-mp-object-size", size)
+			.body(body.clone().into_bytes());
+		async move { req.send().await.unwrap().status() }
+	};
+
+	// Wrong expected size: the upload is not completed
+	assert_eq!(complete("9999").await, 400);
+	assert_eq!(complete("not a number").await, 400);
+	assert!(ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.is_err());
+
+	// Correct expected size
+	assert_eq!(complete("10000").await, 200);
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("a")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.content_length, 10_000);
+}
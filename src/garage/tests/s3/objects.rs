@@ -1,6 +1,6 @@
 use crate::common;
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use aws_sdk_s3::types::{Delete, ObjectAttributes, ObjectIdentifier};
 
 const STD_KEY: &str = "hello world";
 const CTRL_KEY: &str = "\x00\x01\x02\x00";
@@ -185,6 +185,44 @@ async fn test_getobject() {
 		assert_eq!(o.content_range.unwrap().as_str(), "bytes 57-61/62");
 		assert_bytes_eq!(o.body, &BODY[57..]);
 	}
+	{
+		let object_type = ctx
+			.client
+			.head_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.send()
+			.await
+			.unwrap()
+			.content_type
+			.unwrap();
+		let o = ctx
+			.client
+			.get_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.range("bytes=1-2,10-12")
+			.send()
+			.await
+			.unwrap();
+		let content_type = o.content_type.unwrap();
+		let boundary = content_type
+			.strip_prefix("multipart/byteranges; boundary=")
+			.unwrap();
+		let expected = [
+			format!("\r\n--{}\r\n", boundary).as_bytes(),
+			format!("Content-Type: {}\r\n", object_type).as_bytes(),
+			&b"Content-Range: bytes 1-2/62\r\n\r\n"[..],
+			&BODY[1..3],
+			format!("\r\n--{}\r\n", boundary).as_bytes(),
+			format!("Content-Type: {}\r\n", object_type).as_bytes(),
+			&b"Content-Range: bytes 10-12/62\r\n\r\n"[..],
+			&BODY[10..13],
+			format!("\r\n--{}--\r\n", boundary).as_bytes(),
+		]
+		.concat();
+		assert_bytes_eq!(o.body, &expected[..]);
+	}
 }
 
 #[tokio::test]
@@ -264,6 +302,20 @@ async fn test_deleteobject() {
 
 	assert!(l.contents.is_none());
 
+	// At most 1000 objects can be deleted at once
+	let mut too_many = Delete::builder();
+	for i in 0..1001 {
+		too_many = too_many.objects(ObjectIdentifier::builder().key(format!("m-{}", i)).build());
+	}
+	assert!(ctx
+		.client
+		.delete_objects()
+		.bucket(&bucket)
+		.delete(too_many.build())
+		.send()
+		.await
+		.is_err());
+
 	// Deleting a non-existing object shouldn't be a problem
 	ctx.client
 		.delete_object()
@@ -273,3 +325,453 @@ async fn test_deleteobject() {
 		.await
 		.unwrap();
 }
+
+#[tokio::test]
+async fn test_getobject_conditional() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobjectconditional");
+
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("conditional")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	let etag = r.e_tag.unwrap();
+
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("conditional")
+		.send()
+		.await
+		.unwrap();
+	let last_modified = o
+		.last_modified
+		.unwrap()
+		.fmt(aws_sdk_s3::primitives::DateTimeFormat::HttpDate)
+		.unwrap();
+	let before = "Mon, 01 Jan 2001 00:00:00 GMT";
+	let after = "Fri, 01 Jan 2100 00:00:00 GMT";
+
+	let status = |headers: &[(&str, &str)]| {
+		let mut req = ctx.custom_request.builder(bucket.clone());
+		req.path("conditional");
+		for (name, value) in headers {
+			req.unsigned_header(name, value);
+		}
+		async move { req.send().await.unwrap().status().as_u16() }
+	};
+
+	assert_eq!(status(&[]).await, 200);
+
+	// If-Match / If-Unmodified-Since
+	assert_eq!(status(&[("if-match", &etag)]).await, 200);
+	assert_eq!(status(&[("if-match", "\"nope\"")]).await, 412);
+	assert_eq!(status(&[("if-unmodified-since", after)]).await, 200);
+	assert_eq!(
+		status(&[("if-unmodified-since", &last_modified)]).await,
+		200
+	);
+	assert_eq!(status(&[("if-unmodified-since", before)]).await, 412);
+	// If-Match takes precedence over If-Unmodified-Since
+	assert_eq!(
+		status(&[("if-match", &etag), ("if-unmodified-since", before)]).await,
+		200
+	);
+
+	// If-None-Match / If-Modified-Since
+	assert_eq!(status(&[("if-none-match", &etag)]).await, 304);
+	assert_eq!(status(&[("if-none-match", "\"nope\"")]).await, 200);
+	assert_eq!(status(&[("if-modified-since", before)]).await, 200);
+	assert_eq!(status(&[("if-modified-since", &last_modified)]).await, 304);
+	assert_eq!(status(&[("if-modified-since", "not a date")]).await, 200);
+	// If-None-Match takes precedence over If-Modified-Since
+	assert_eq!(
+		status(&[("if-none-match", "\"nope\""), ("if-modified-since", after)]).await,
+		200
+	);
+	// A failed If-Match precondition takes precedence over If-None-Match
+	assert_eq!(
+		status(&[("if-match", "\"nope\""), ("if-none-match", &etag)]).await,
+		412
+	);
+}
+
+#[tokio::test]
+async fn test_getobject_attributes() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobjectattributes");
+
+	let r = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("attributes")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+	let etag = r.e_tag.unwrap();
+
+	let a = ctx
+		.client
+		.get_object_attributes()
+		.bucket(&bucket)
+		.key("attributes")
+		.object_attributes(ObjectAttributes::Etag)
+		.object_attributes(ObjectAttributes::ObjectSize)
+		.object_attributes(ObjectAttributes::ObjectParts)
+		.send()
+		.await
+		.unwrap();
+	// The ETag is not quoted in GetObjectAttributes responses
+	assert_eq!(a.e_tag().unwrap(), etag.trim_matches('"'));
+	assert_eq!(a.object_size(), BODY.len() as i64);
+	// Only objects created by a multipart upload have parts
+	assert!(a.object_parts().is_none());
+	assert!(a.storage_class().is_none());
+
+	let a = ctx
+		.client
+		.get_object_attributes()
+		.bucket(&bucket)
+		.key("nope")
+		.object_attributes(ObjectAttributes::Etag)
+		.send()
+		.await;
+	assert!(a.is_err());
+}
+
+#[tokio::test]
+async fn test_getobject_cache_headers() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("getobjectcacheheaders");
+
+	let expires = aws_sdk_s3::primitives::DateTime::from_secs(1_800_000_000);
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key("cached")
+		.cache_control("public, max-age=3600")
+		.expires(expires)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	// Caching headers given at upload are sent back on GET and HEAD,
+	// so that caching proxies in front of Garage can use them
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("cached")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.cache_control.unwrap(), "public, max-age=3600");
+	assert_eq!(o.expires.unwrap(), expires);
+
+	let o = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key("cached")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.cache_control.unwrap(), "public, max-age=3600");
+	assert_eq!(o.expires.unwrap(), expires);
+}
+
+#[tokio::test]
+async fn test_objectlock() {
+	use aws_sdk_s3::types::{
+		BucketVersioningStatus, DefaultRetention, ObjectLockConfiguration, ObjectLockEnabled,
+		ObjectLockLegalHold, ObjectLockLegalHoldStatus, ObjectLockMode, ObjectLockRetentionMode,
+		ObjectLockRule, VersioningConfiguration,
+	};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("objectlock");
+
+	let lock_config = ObjectLockConfiguration::builder()
+		.object_lock_enabled(ObjectLockEnabled::Enabled)
+		.rule(
+			ObjectLockRule::builder()
+				.default_retention(
+					DefaultRetention::builder()
+						.mode(ObjectLockRetentionMode::Compliance)
+						.days(1)
+						.build(),
+				)
+				.build(),
+		)
+		.build();
+
+	// Object lock can only be enabled on a versioned bucket
+	assert!(ctx
+		.client
+		.put_object_lock_configuration()
+		.bucket(&bucket)
+		.object_lock_configuration(lock_config.clone())
+		.send()
+		.await
+		.is_err());
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	ctx.client
+		.put_object_lock_configuration()
+		.bucket(&bucket)
+		.object_lock_configuration(lock_config)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_object_lock_configuration()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let conf = r.object_lock_configuration.unwrap();
+	assert_eq!(conf.object_lock_enabled, Some(ObjectLockEnabled::Enabled));
+	let retention = conf.rule.unwrap().default_retention.unwrap();
+	assert_eq!(retention.mode, Some(ObjectLockRetentionMode::Compliance));
+	assert_eq!(retention.days, 1);
+
+	// Versioning can no longer be suspended
+	assert!(ctx
+		.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Suspended)
+				.build(),
+		)
+		.send()
+		.await
+		.is_err());
+
+	// New objects get the default retention
+	ctx.client
+		.put_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap();
+
+	let h = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(h.object_lock_mode, Some(ObjectLockMode::Compliance));
+	assert!(h.object_lock_retain_until_date.is_some());
+
+	// Legal hold
+	ctx.client
+		.put_object_legal_hold()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.legal_hold(
+			ObjectLockLegalHold::builder()
+				.status(ObjectLockLegalHoldStatus::On)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.get_object_legal_hold()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(
+		r.legal_hold.unwrap().status,
+		Some(ObjectLockLegalHoldStatus::On)
+	);
+
+	// Deleting only adds a delete marker, the locked version is kept
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+
+	let r = ctx
+		.client
+		.list_object_versions()
+		.bucket(&bucket)
+		.send()
+		.await
+		.unwrap();
+	let locked_versions = r.versions.unwrap();
+	assert_eq!(locked_versions.len(), 1);
+	assert_eq!(r.delete_markers.unwrap().len(), 1);
+
+	// The locked version can not be removed with its version id
+	assert!(ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.version_id(locked_versions[0].version_id.as_deref().unwrap())
+		.send()
+		.await
+		.is_err());
+
+	// The bucket still holds a locked version and cannot be deleted
+	assert!(ctx
+		.client
+		.delete_bucket()
+		.bucket(&bucket)
+		.send()
+		.await
+		.is_err());
+}
+
+#[tokio::test]
+async fn test_versioned_object() {
+	use aws_sdk_s3::types::{BucketVersioningStatus, VersioningConfiguration};
+
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("versionedobject");
+
+	ctx.client
+		.put_bucket_versioning()
+		.bucket(&bucket)
+		.versioning_configuration(
+			VersioningConfiguration::builder()
+				.status(BucketVersioningStatus::Enabled)
+				.build(),
+		)
+		.send()
+		.await
+		.unwrap();
+
+	let mut versions = vec![];
+	for body in [&BODY[..10], &BODY[..20]] {
+		let r = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(STD_KEY)
+			.body(ByteStream::from(body.to_vec()))
+			.send()
+			.await
+			.unwrap();
+		versions.push(r.version_id.unwrap());
+	}
+
+	// A previous version can be read with its version id
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.version_id(&versions[0])
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.version_id.as_ref(), Some(&versions[0]));
+	assert_bytes_eq!(o.body, &BODY[..10]);
+
+	let h = ctx
+		.client
+		.head_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.version_id(&versions[0])
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(h.content_length, 10);
+
+	// Deleting the current version by its id makes the previous one current
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.version_id(&versions[1])
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.version_id.as_ref(), Some(&versions[0]));
+
+	// Removing a delete marker restores the object
+	let d = ctx
+		.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert!(d.delete_marker);
+	assert!(ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.is_err());
+
+	ctx.client
+		.delete_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.version_id(d.version_id.unwrap())
+		.send()
+		.await
+		.unwrap();
+
+	let o = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key(STD_KEY)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(o.version_id.as_ref(), Some(&versions[0]));
+}
@@ -175,6 +175,7 @@ async fn test_website_s3_api() {
 		.put_object()
 		.bucket(&bucket)
 		.key("err/error.html")
+		.content_type("text/html; charset=utf-8")
 		.body(ByteStream::from_static(BODY_ERR))
 		.send()
 		.await
@@ -279,12 +280,45 @@ async fn test_website_s3_api() {
 		let mut resp = client.request(req).await.unwrap();
 
 		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+		assert_eq!(
+			resp.headers().get("content-type").unwrap(),
+			"text/html; charset=utf-8"
+		);
 		assert_eq!(
 			to_bytes(resp.body_mut()).await.unwrap().as_ref(),
 			BODY_ERR.as_ref()
 		);
 	}
 
+	// Test plain 404 when the ErrorDocument itself is missing
+	{
+		ctx.client
+			.delete_object()
+			.bucket(&bucket)
+			.key("err/error.html")
+			.send()
+			.await
+			.unwrap();
+
+		let req = Request::builder()
+			.method("GET")
+			.uri(format!(
+				"http://127.0.0.1:{}/wrong.html",
+				ctx.garage.web_port
+			))
+			.header("Host", format!("{}.web.garage", BCKT_NAME))
+			.body(Body::empty())
+			.unwrap();
+
+		let mut resp = client.request(req).await.unwrap();
+
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+		assert_eq!(
+			to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+			b"API error: Key not found\n".as_ref()
+		);
+	}
+
 	// Test CORS with an allowed preflight request
 	{
 		let req = Request::builder()
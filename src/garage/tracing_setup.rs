@@ -10,9 +10,23 @@ use opentelemetry_otlp::WithExportConfig;
 use garage_util::data::*;
 use garage_util::error::*;
 
-pub fn init_tracing(export_to: &str, node_id: Uuid) -> Result<(), Error> {
+pub fn init_tracing(export_to: &str, sample_rate: f64, node_id: Uuid) -> Result<(), Error> {
+	if !(0.0..=1.0).contains(&sample_rate) {
+		return Err(Error::Message(format!(
+			"Invalid telemetry_sample_rate {}, it must be between 0 and 1",
+			sample_rate
+		)));
+	}
 	let node_id = hex::encode(&node_id.as_slice()[..8]);
 
+	// Spans whose parent was sampled are always sampled, so that a trace
+	// is either exported entirely or not at all
+	let sampler = if sample_rate >= 1.0 {
+		Sampler::AlwaysOn
+	} else {
+		Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(sample_rate)))
+	};
+
 	opentelemetry_otlp::new_pipeline()
 		.tracing()
 		.with_exporter(
@@ -24,7 +38,7 @@ pub fn init_tracing(export_to: &str, node_id: Uuid) -> Result<(), Error> {
 		.with_trace_config(
 			trace::config()
 				.with_id_generator(IdGenerator::default())
-				.with_sampler(Sampler::AlwaysOn)
+				.with_sampler(sampler)
 				.with_resource(Resource::new(vec![
 					KeyValue::new("service.name", "garage"),
 					KeyValue::new("service.instance.id", node_id),
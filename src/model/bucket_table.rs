@@ -7,6 +7,7 @@ use crate::permission::BucketKeyPerm;
 
 mod v08 {
 	use crate::permission::BucketKeyPerm;
+	use crate::s3::object_table::ObjectLockMode;
 	use garage_util::crdt;
 	use garage_util::data::Uuid;
 	use serde::{Deserialize, Serialize};
@@ -51,12 +52,74 @@ mod v08 {
 		/// Bucket quotas
 		#[serde(default)]
 		pub quotas: crdt::Lww<BucketQuotas>,
+		/// Lifecycle rules
+		#[serde(default)]
+		pub lifecycle_config: crdt::Lww<Option<Vec<LifecycleRule>>>,
+		/// Server-side encryption applied to all new objects of the bucket,
+		/// even if it is not requested by the client
+		#[serde(default)]
+		pub default_encryption: crdt::Lww<Option<BucketEncryption>>,
+		/// Versioning state of the bucket (None if versioning was never enabled)
+		#[serde(default)]
+		pub versioning: crdt::Lww<Option<BucketVersioning>>,
+		/// Whether object lock is enabled on the bucket
+		/// (it can not be disabled once it has been enabled)
+		#[serde(default)]
+		pub object_lock_enabled: crdt::Bool,
+		/// Retention applied to new objects of the bucket when object lock is enabled,
+		/// if they don't specify one
+		#[serde(default)]
+		pub object_lock_default_retention: crdt::Lww<Option<ObjectLockDefaultRetention>>,
+		/// Delay in seconds before blocks that are no longer used by objects
+		/// of this bucket are deleted, if different from the global one
+		#[serde(default)]
+		pub block_gc_delay_secs: crdt::Lww<Option<u64>>,
+		/// Number of days after which incomplete multipart uploads of the bucket
+		/// are aborted, regardless of lifecycle rules
+		#[serde(default)]
+		pub mpu_ttl_days: crdt::Lww<Option<u64>>,
+		/// Webhooks that are notified when objects of the bucket are
+		/// created or removed
+		#[serde(default)]
+		pub notifications: crdt::Lww<Option<BucketNotificationConfig>>,
+		/// Access policy of the bucket, that can allow anonymous access
+		/// to some objects or restrict the access of keys
+		#[serde(default)]
+		pub policy: crdt::Lww<Option<BucketPolicy>>,
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 	pub struct WebsiteConfig {
 		pub index_document: String,
 		pub error_document: Option<String>,
+		/// Redirection rules, evaluated in order before serving a request
+		#[serde(default)]
+		pub routing_rules: Vec<WebsiteRoutingRule>,
+	}
+
+	/// Website routing rule: requests that match its conditions are
+	/// redirected instead of being served
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct WebsiteRoutingRule {
+		/// Only match keys that start with this prefix
+		pub key_prefix: Option<String>,
+		/// Only match requests that would return this HTTP error code
+		pub http_error_code: Option<u16>,
+		pub redirect: WebsiteRedirect,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct WebsiteRedirect {
+		/// Host name to redirect to, instead of the host of the request
+		pub hostname: Option<String>,
+		/// Protocol to redirect to (http or https)
+		pub protocol: Option<String>,
+		/// HTTP status code of the redirection (3xx)
+		pub http_redirect_code: u16,
+		/// Replace the matched key prefix with this one
+		pub replace_key_prefix_with: Option<String>,
+		/// Replace the whole key with this one
+		pub replace_key_with: Option<String>,
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -69,6 +132,106 @@ mod v08 {
 		pub expose_headers: Vec<String>,
 	}
 
+	/// Lifecycle configuration rule
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct LifecycleRule {
+		/// The ID of the rule
+		pub id: Option<String>,
+		/// Whether the rule is active
+		pub enabled: bool,
+		/// The filter to check whether rule applies to a given object
+		pub filter: LifecycleFilter,
+		/// Number of days after which incomplete multipart uploads are aborted
+		pub abort_incomplete_mpu_days: Option<usize>,
+		/// Expiration policy for stored objects
+		pub expiration: Option<LifecycleExpiration>,
+		/// Number of days after which versions of objects that have been
+		/// replaced by a newer version are deleted
+		#[serde(default)]
+		pub noncurrent_version_expiration_days: Option<usize>,
+	}
+
+	/// A lifecycle filter is a set of conditions that must all be true.
+	/// For each condition, if it is None, it is not verified (always true),
+	/// and if it is Some(x), then it is verified for value x
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, Default)]
+	pub struct LifecycleFilter {
+		/// If Some(x), object key has to start with prefix x
+		pub prefix: Option<String>,
+		/// If Some(x), object size has to be more than x
+		pub size_gt: Option<u64>,
+		/// If Some(x), object size has to be less than x
+		pub size_lt: Option<u64>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub enum LifecycleExpiration {
+		/// Objects expire x days after they were created
+		AfterDays(usize),
+		/// Objects expire at date x (must be in yyyy-mm-dd format)
+		AtDate(String),
+	}
+
+	/// Server-side encryption algorithm
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum BucketEncryption {
+		/// SSE-S3: objects are encrypted with keys managed by Garage
+		SseS3,
+	}
+
+	/// Versioning state of a bucket
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum BucketVersioning {
+		/// All versions of objects are kept when newer versions are written
+		Enabled,
+		/// Versioning was enabled and then suspended: versions written while
+		/// it was enabled are kept, but new versions replace the previous ones
+		Suspended,
+	}
+
+	/// Default object lock retention of a bucket
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub struct ObjectLockDefaultRetention {
+		pub mode: ObjectLockMode,
+		pub period: ObjectLockPeriod,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ObjectLockPeriod {
+		Days(u32),
+		Years(u32),
+	}
+
+	/// Event notification configuration of a bucket
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct BucketNotificationConfig {
+		pub webhooks: Vec<NotificationWebhook>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct NotificationWebhook {
+		/// URL to which the events are sent in a POST request
+		pub url: String,
+		/// Kinds of events that are sent to this webhook
+		pub events: Vec<NotificationEventType>,
+		/// If Some(x), only events on objects whose key starts with x are sent
+		pub key_prefix: Option<String>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum NotificationEventType {
+		/// An object was written by PutObject, PostObject, CopyObject
+		/// or CompleteMultipartUpload
+		ObjectCreated,
+		/// An object was deleted, or a delete marker was created for it
+		ObjectRemoved,
+	}
+
+	/// Bucket policy, stored as the JSON document given by the user
+	/// (it is parsed and evaluated by the S3 API)
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct BucketPolicy(pub String);
+
 	#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 	pub struct BucketQuotas {
 		/// Maximum size in bytes (bucket size = sum of sizes of objects in the bucket)
@@ -97,6 +260,15 @@ impl BucketParams {
 			website_config: crdt::Lww::new(None),
 			cors_config: crdt::Lww::new(None),
 			quotas: crdt::Lww::new(BucketQuotas::default()),
+			lifecycle_config: crdt::Lww::new(None),
+			default_encryption: crdt::Lww::new(None),
+			versioning: crdt::Lww::new(None),
+			object_lock_enabled: crdt::Bool::new(false),
+			object_lock_default_retention: crdt::Lww::new(None),
+			block_gc_delay_secs: crdt::Lww::new(None),
+			mpu_ttl_days: crdt::Lww::new(None),
+			notifications: crdt::Lww::new(None),
+			policy: crdt::Lww::new(None),
 		}
 	}
 }
@@ -112,6 +284,16 @@ impl Crdt for BucketParams {
 		self.website_config.merge(&o.website_config);
 		self.cors_config.merge(&o.cors_config);
 		self.quotas.merge(&o.quotas);
+		self.lifecycle_config.merge(&o.lifecycle_config);
+		self.default_encryption.merge(&o.default_encryption);
+		self.versioning.merge(&o.versioning);
+		self.object_lock_enabled.merge(&o.object_lock_enabled);
+		self.object_lock_default_retention
+			.merge(&o.object_lock_default_retention);
+		self.block_gc_delay_secs.merge(&o.block_gc_delay_secs);
+		self.mpu_ttl_days.merge(&o.mpu_ttl_days);
+		self.notifications.merge(&o.notifications);
+		self.policy.merge(&o.policy);
 	}
 }
 
@@ -168,6 +350,25 @@ impl Bucket {
 			.map(|s| s.local_aliases.items())
 			.unwrap_or(&[])
 	}
+
+	/// Whether versions of objects written now must be kept
+	/// when newer versions of the objects are written
+	/// (this is always the case when object lock is enabled)
+	pub fn versioning_enabled(&self) -> bool {
+		self.params()
+			.map(|s| {
+				matches!(s.versioning.get(), Some(BucketVersioning::Enabled))
+					|| s.object_lock_enabled.get()
+			})
+			.unwrap_or(false)
+	}
+
+	/// Whether object lock is enabled on this bucket
+	pub fn object_lock_enabled(&self) -> bool {
+		self.params()
+			.map(|s| s.object_lock_enabled.get())
+			.unwrap_or(false)
+	}
 }
 
 impl Entry<EmptyKey, Uuid> for Bucket {
@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use netapp::NetworkKey;
@@ -12,11 +14,13 @@ use garage_rpc::replication_mode::ReplicationMode;
 use garage_rpc::system::System;
 
 use garage_block::manager::*;
+use garage_table::data::TableData;
 use garage_table::replication::TableFullReplication;
 use garage_table::replication::TableShardedReplication;
 use garage_table::*;
 
 use crate::s3::block_ref_table::*;
+use crate::s3::lifecycle_worker::LifecycleWorker;
 use crate::s3::object_table::*;
 use crate::s3::version_table::*;
 
@@ -27,7 +31,7 @@ use crate::index_counter::*;
 use crate::key_table::*;
 
 #[cfg(feature = "k2v")]
-use crate::k2v::{item_table::*, rpc::*, sub::*};
+use crate::k2v::{expiry_worker::K2VExpiryWorker, item_table::*, rpc::*, sub::*};
 
 /// An entire Garage full of data
 pub struct Garage {
@@ -35,6 +39,9 @@ pub struct Garage {
 	pub config: Config,
 	/// The set of background variables that can be viewed/modified at runtime
 	pub bg_vars: vars::BgVars,
+	/// Size of the blocks in which the objects that are written are split,
+	/// which can be changed while the node is running
+	block_size: Arc<AtomicUsize>,
 
 	/// The replication mode of this cluster
 	pub replication_mode: ReplicationMode,
@@ -86,91 +93,7 @@ impl Garage {
 			.ok_or_message("Unable to create Garage data directory")?;
 
 		info!("Opening database...");
-		let mut db_path = config.metadata_dir.clone();
-		let db = match config.db_engine.as_str() {
-			// ---- Sled DB ----
-			#[cfg(feature = "sled")]
-			"sled" => {
-				db_path.push("db");
-				info!("Opening Sled database at: {}", db_path.display());
-				let db = db::sled_adapter::sled::Config::default()
-					.path(&db_path)
-					.cache_capacity(config.sled_cache_capacity as u64)
-					.flush_every_ms(Some(config.sled_flush_every_ms))
-					.open()
-					.ok_or_message("Unable to open sled DB")?;
-				db::sled_adapter::SledDb::init(db)
-			}
-			#[cfg(not(feature = "sled"))]
-			"sled" => return Err(Error::Message("sled db not available in this build".into())),
-			// ---- Sqlite DB ----
-			#[cfg(feature = "sqlite")]
-			"sqlite" | "sqlite3" | "rusqlite" => {
-				db_path.push("db.sqlite");
-				info!("Opening Sqlite database at: {}", db_path.display());
-				let db = db::sqlite_adapter::rusqlite::Connection::open(db_path)
-					.ok_or_message("Unable to open sqlite DB")?;
-				db::sqlite_adapter::SqliteDb::init(db)
-			}
-			#[cfg(not(feature = "sqlite"))]
-			"sqlite" | "sqlite3" | "rusqlite" => {
-				return Err(Error::Message(
-					"sqlite db not available in this build".into(),
-				))
-			}
-			// ---- LMDB DB ----
-			#[cfg(feature = "lmdb")]
-			"lmdb" | "heed" => {
-				db_path.push("db.lmdb");
-				info!("Opening LMDB database at: {}", db_path.display());
-				std::fs::create_dir_all(&db_path)
-					.ok_or_message("Unable to create LMDB data directory")?;
-				let map_size = match config.lmdb_map_size {
-					v if v == usize::default() => garage_db::lmdb_adapter::recommended_map_size(),
-					v => v - (v % 4096),
-				};
-
-				use db::lmdb_adapter::heed;
-				let mut env_builder = heed::EnvOpenOptions::new();
-				env_builder.max_dbs(100);
-				env_builder.max_readers(500);
-				env_builder.map_size(map_size);
-				unsafe {
-					env_builder.flag(heed::flags::Flags::MdbNoSync);
-					env_builder.flag(heed::flags::Flags::MdbNoMetaSync);
-				}
-				let db = match env_builder.open(&db_path) {
-					Err(heed::Error::Io(e)) if e.kind() == std::io::ErrorKind::OutOfMemory => {
-						return Err(Error::Message(
-							"OutOfMemory error while trying to open LMDB database. This can happen \
-							if your operating system is not allowing you to use sufficient virtual \
-							memory address space. Please check that no limit is set (ulimit -v). \
-							You may also try to set a smaller `lmdb_map_size` configuration parameter. \
-							On 32-bit machines, you should probably switch to another database engine.".into()))
-					}
-					x => x.ok_or_message("Unable to open LMDB DB")?,
-				};
-				db::lmdb_adapter::LmdbDb::init(db)
-			}
-			#[cfg(not(feature = "lmdb"))]
-			"lmdb" | "heed" => return Err(Error::Message("lmdb db not available in this build".into())),
-			// ---- Unavailable DB engine ----
-			e => {
-				return Err(Error::Message(format!(
-					"Unsupported DB engine: {} (options: {})",
-					e,
-					vec![
-						#[cfg(feature = "sled")]
-						"sled",
-						#[cfg(feature = "sqlite")]
-						"sqlite",
-						#[cfg(feature = "lmdb")]
-						"lmdb",
-					]
-					.join(", ")
-				)));
-			}
-		};
+		let db = open_db(&config, &config.db_engine)?;
 
 		let network_key = hex::decode(config.rpc_secret.as_ref().ok_or_message(
 			"rpc_secret value is missing, not present in config file or in environment",
@@ -187,16 +110,12 @@ impl Garage {
 
 		let data_rep_param = TableShardedReplication {
 			system: system.clone(),
-			replication_factor: replication_mode.replication_factor(),
-			write_quorum: replication_mode.write_quorum(),
-			read_quorum: 1,
+			read_one: true,
 		};
 
 		let meta_rep_param = TableShardedReplication {
 			system: system.clone(),
-			replication_factor: replication_mode.replication_factor(),
-			write_quorum: replication_mode.write_quorum(),
-			read_quorum: replication_mode.read_quorum(),
+			read_one: false,
 		};
 
 		let control_rep_param = TableFullReplication {
@@ -209,13 +128,23 @@ impl Garage {
 			&db,
 			config.data_dir.clone(),
 			config.compression_level,
+			config.block_cache_size,
+			config.block_encryption_key,
+			config.scrub_worker_count,
 			data_rep_param,
 			system.clone(),
 		);
 
 		// ---- admin tables ----
 		info!("Initialize bucket_table...");
-		let bucket_table = Table::new(BucketTable, control_rep_param.clone(), system.clone(), &db);
+		let bucket_table = Table::new(
+			BucketTable,
+			control_rep_param.clone(),
+			system.clone(),
+			&db,
+			config.table_sync.bucket_table_priority,
+			config.table_sync_parallelism,
+		);
 
 		info!("Initialize bucket_alias_table...");
 		let bucket_alias_table = Table::new(
@@ -223,19 +152,34 @@ impl Garage {
 			control_rep_param.clone(),
 			system.clone(),
 			&db,
+			config.table_sync.bucket_alias_table_priority,
+			config.table_sync_parallelism,
 		);
 		info!("Initialize key_table_table...");
-		let key_table = Table::new(KeyTable, control_rep_param, system.clone(), &db);
+		let key_table = Table::new(
+			KeyTable,
+			control_rep_param,
+			system.clone(),
+			&db,
+			config.table_sync.key_table_priority,
+			config.table_sync_parallelism,
+		);
 
 		// ---- S3 tables ----
 		info!("Initialize block_ref_table...");
+		// The block ref table reads versions, which are stored in a table created after it
+		let version_store = TableData::<VersionTable, TableShardedReplication>::open_store(&db);
 		let block_ref_table = Table::new(
 			BlockRefTable {
 				block_manager: block_manager.clone(),
+				bucket_table: bucket_table.clone(),
+				version_store,
 			},
 			meta_rep_param.clone(),
 			system.clone(),
 			&db,
+			config.table_sync.block_ref_table_priority,
+			config.table_sync_parallelism,
 		);
 
 		info!("Initialize version_table...");
@@ -246,10 +190,17 @@ impl Garage {
 			meta_rep_param.clone(),
 			system.clone(),
 			&db,
+			config.table_sync.version_table_priority,
+			config.table_sync_parallelism,
 		);
 
 		info!("Initialize object counter table...");
-		let object_counter_table = IndexCounter::new(system.clone(), meta_rep_param.clone(), &db);
+		let object_counter_table = IndexCounter::new(
+			system.clone(),
+			meta_rep_param.clone(),
+			&db,
+			config.table_sync_parallelism,
+		);
 
 		info!("Initialize object_table...");
 		#[allow(clippy::redundant_clone)]
@@ -261,20 +212,46 @@ impl Garage {
 			meta_rep_param.clone(),
 			system.clone(),
 			&db,
+			config.table_sync.object_table_priority,
+			config.table_sync_parallelism,
 		);
 
 		// ---- K2V ----
 		#[cfg(feature = "k2v")]
-		let k2v = GarageK2V::new(system.clone(), &db, meta_rep_param);
+		let k2v = GarageK2V::new(
+			system.clone(),
+			&db,
+			meta_rep_param,
+			config.table_sync_parallelism,
+		);
 
 		// Initialize bg vars
 		let mut bg_vars = vars::BgVars::new();
+		system.register_bg_vars(&mut bg_vars);
 		block_manager.register_bg_vars(&mut bg_vars);
 
+		let block_size = Arc::new(AtomicUsize::new(config.block_size));
+		let get_block_size = block_size.clone();
+		let set_block_size = block_size.clone();
+		bg_vars.register_rw_fn(
+			"block-size",
+			move || get_block_size.load(Ordering::Relaxed),
+			move |size: usize| {
+				if size == 0 {
+					return Err(Error::Message(
+						"The block size must be greater than zero".into(),
+					));
+				}
+				set_block_size.store(size, Ordering::Relaxed);
+				Ok(())
+			},
+		);
+
 		// -- done --
 		Ok(Arc::new(Self {
 			config,
 			bg_vars,
+			block_size,
 			replication_mode,
 			db,
 			system,
@@ -291,7 +268,7 @@ impl Garage {
 		}))
 	}
 
-	pub fn spawn_workers(&self, bg: &BackgroundRunner) {
+	pub fn spawn_workers(self: &Arc<Self>, bg: &BackgroundRunner) {
 		self.block_manager.spawn_workers(bg);
 
 		self.bucket_table.spawn_workers(bg);
@@ -303,10 +280,17 @@ impl Garage {
 		self.version_table.spawn_workers(bg);
 		self.block_ref_table.spawn_workers(bg);
 
+		bg.spawn_worker(LifecycleWorker::new(self.clone()));
+
 		#[cfg(feature = "k2v")]
 		self.k2v.spawn_workers(bg);
 	}
 
+	/// Size of the blocks in which the objects that are written are split
+	pub fn block_size(&self) -> usize {
+		self.block_size.load(Ordering::Relaxed)
+	}
+
 	pub fn bucket_helper(&self) -> helper::bucket::BucketHelper {
 		helper::bucket::BucketHelper(self)
 	}
@@ -318,22 +302,34 @@ impl Garage {
 
 #[cfg(feature = "k2v")]
 impl GarageK2V {
-	fn new(system: Arc<System>, db: &db::Db, meta_rep_param: TableShardedReplication) -> Self {
+	fn new(
+		system: Arc<System>,
+		db: &db::Db,
+		meta_rep_param: TableShardedReplication,
+		sync_parallelism: usize,
+	) -> Self {
 		info!("Initialize K2V counter table...");
-		let counter_table = IndexCounter::new(system.clone(), meta_rep_param.clone(), db);
+		let counter_table =
+			IndexCounter::new(system.clone(), meta_rep_param.clone(), db, sync_parallelism);
 
 		info!("Initialize K2V subscription manager...");
 		let subscriptions = Arc::new(SubscriptionManager::new());
 
 		info!("Initialize K2V item table...");
+		let expiry_tree = db
+			.open_tree("k2v_item_expiry")
+			.expect("Unable to open DB tree for K2V item expiry");
 		let item_table = Table::new(
 			K2VItemTable {
 				counter_table: counter_table.clone(),
 				subscriptions: subscriptions.clone(),
+				expiry_tree,
 			},
 			meta_rep_param,
 			system.clone(),
 			db,
+			SyncPriority::Normal,
+			sync_parallelism,
 		);
 
 		info!("Initialize K2V RPC handler...");
@@ -349,5 +345,119 @@ impl GarageK2V {
 	pub fn spawn_workers(&self, bg: &BackgroundRunner) {
 		self.item_table.spawn_workers(bg);
 		self.counter_table.spawn_workers(bg);
+
+		bg.spawn_worker(K2VExpiryWorker::new(
+			self.item_table.clone(),
+			self.rpc.clone(),
+		));
+	}
+}
+
+/// Path of the database of the given engine in the metadata directory
+pub fn db_path(metadata_dir: &Path, engine: &str) -> PathBuf {
+	match engine {
+		"sqlite" | "sqlite3" | "rusqlite" => metadata_dir.join("db.sqlite"),
+		"lmdb" | "heed" => metadata_dir.join("db.lmdb"),
+		"rocksdb" => metadata_dir.join("db.rocksdb"),
+		_ => metadata_dir.join("db"),
+	}
+}
+
+/// Open the database of the given engine in the metadata directory,
+/// using the engine parameters of the configuration
+pub fn open_db(config: &Config, engine: &str) -> Result<db::Db, Error> {
+	let db_path = db_path(&config.metadata_dir, engine);
+	match engine {
+		// ---- Sled DB ----
+		#[cfg(feature = "sled")]
+		"sled" => {
+			info!("Opening Sled database at: {}", db_path.display());
+			let db = db::sled_adapter::sled::Config::default()
+				.path(&db_path)
+				.cache_capacity(config.sled_cache_capacity as u64)
+				.flush_every_ms(Some(config.sled_flush_every_ms))
+				.open()
+				.ok_or_message("Unable to open sled DB")?;
+			Ok(db::sled_adapter::SledDb::init(db))
+		}
+		#[cfg(not(feature = "sled"))]
+		"sled" => Err(Error::Message("sled db not available in this build".into())),
+		// ---- Sqlite DB ----
+		#[cfg(feature = "sqlite")]
+		"sqlite" | "sqlite3" | "rusqlite" => {
+			info!("Opening Sqlite database at: {}", db_path.display());
+			let db = db::sqlite_adapter::rusqlite::Connection::open(&db_path)
+				.ok_or_message("Unable to open sqlite DB")?;
+			Ok(db::sqlite_adapter::SqliteDb::init(db))
+		}
+		#[cfg(not(feature = "sqlite"))]
+		"sqlite" | "sqlite3" | "rusqlite" => Err(Error::Message(
+			"sqlite db not available in this build".into(),
+		)),
+		// ---- LMDB DB ----
+		#[cfg(feature = "lmdb")]
+		"lmdb" | "heed" => {
+			info!("Opening LMDB database at: {}", db_path.display());
+			std::fs::create_dir_all(&db_path)
+				.ok_or_message("Unable to create LMDB data directory")?;
+			let map_size = match config.lmdb_map_size {
+				v if v == usize::default() => garage_db::lmdb_adapter::recommended_map_size(),
+				v => v - (v % 4096),
+			};
+
+			use db::lmdb_adapter::heed;
+			let mut env_builder = heed::EnvOpenOptions::new();
+			env_builder.max_dbs(100);
+			env_builder.max_readers(500);
+			env_builder.map_size(map_size);
+			unsafe {
+				env_builder.flag(heed::flags::Flags::MdbNoSync);
+				env_builder.flag(heed::flags::Flags::MdbNoMetaSync);
+			}
+			let db = match env_builder.open(&db_path) {
+				Err(heed::Error::Io(e)) if e.kind() == std::io::ErrorKind::OutOfMemory => {
+					return Err(Error::Message(
+						"OutOfMemory error while trying to open LMDB database. This can happen \
+						if your operating system is not allowing you to use sufficient virtual \
+						memory address space. Please check that no limit is set (ulimit -v). \
+						You may also try to set a smaller `lmdb_map_size` configuration parameter. \
+						On 32-bit machines, you should probably switch to another database engine."
+							.into(),
+					))
+				}
+				x => x.ok_or_message("Unable to open LMDB DB")?,
+			};
+			Ok(db::lmdb_adapter::LmdbDb::init(db))
+		}
+		#[cfg(not(feature = "lmdb"))]
+		"lmdb" | "heed" => Err(Error::Message("lmdb db not available in this build".into())),
+		// ---- RocksDB ----
+		#[cfg(feature = "rocksdb")]
+		"rocksdb" => {
+			info!("Opening RocksDB database at: {}", db_path.display());
+			let db = db::rocksdb_adapter::open(db_path, Default::default())
+				.ok_or_message("Unable to open RocksDB")?;
+			Ok(db::rocksdb_adapter::RocksDb::init(db, Default::default()))
+		}
+		#[cfg(not(feature = "rocksdb"))]
+		"rocksdb" => Err(Error::Message(
+			"rocksdb db not available in this build".into(),
+		)),
+		// ---- Unavailable DB engine ----
+		e => Err(Error::Message(format!(
+			"Unsupported DB engine: {} (options: {})",
+			e,
+			[
+				#[cfg(feature = "sled")]
+				"sled",
+				#[cfg(feature = "sqlite")]
+				"sqlite",
+				#[cfg(feature = "lmdb")]
+				"lmdb",
+				#[cfg(feature = "rocksdb")]
+				"rocksdb",
+			]
+			.join(", ")
+		))),
 	}
 }
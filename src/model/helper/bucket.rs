@@ -335,7 +335,8 @@ impl<'a> BucketHelper<'a> {
 			.local_aliases
 			.get(alias_name)
 			.cloned()
-			.flatten() != Some(bucket_id)
+			.flatten()
+			!= Some(bucket_id)
 		{
 			return Err(GarageError::Message(format!(
 				"Bucket {:?} does not have alias {} in namespace of key {}",
@@ -439,7 +440,7 @@ impl<'a> BucketHelper<'a> {
 			.get_range(
 				&bucket_id,
 				None,
-				Some(ObjectFilter::IsData),
+				Some(ObjectFilter::HasData),
 				10,
 				EnumerationOrder::Forward,
 			)
@@ -513,6 +514,8 @@ impl<'a> BucketHelper<'a> {
 							state: ObjectVersionState::Aborted,
 							uuid: v.uuid,
 							timestamp: v.timestamp,
+							versioned: v.versioned,
+							legal_hold: v.legal_hold.clone(),
 						})
 						.collect::<Vec<_>>();
 					if !aborted_versions.is_empty() {
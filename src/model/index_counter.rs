@@ -10,6 +10,7 @@ use garage_db as db;
 use garage_rpc::ring::Ring;
 use garage_rpc::system::System;
 use garage_util::background::BackgroundRunner;
+use garage_util::config::SyncPriority;
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::migrate::Migrate;
@@ -173,6 +174,7 @@ impl<T: CountedItem> IndexCounter<T> {
 		system: Arc<System>,
 		replication: TableShardedReplication,
 		db: &db::Db,
+		sync_parallelism: usize,
 	) -> Arc<Self> {
 		Arc::new(Self {
 			this_node: system.id,
@@ -186,6 +188,8 @@ impl<T: CountedItem> IndexCounter<T> {
 				replication,
 				system,
 				db,
+				SyncPriority::Normal,
+				sync_parallelism,
 			),
 		})
 	}
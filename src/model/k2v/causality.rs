@@ -43,7 +43,7 @@ pub fn vclock_max(a: &VectorClock, b: &VectorClock) -> VectorClock {
 	ret
 }
 
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Default)]
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, Default)]
 pub struct CausalContext {
 	pub vector_clock: VectorClock,
 }
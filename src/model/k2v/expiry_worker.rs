@@ -0,0 +1,109 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use garage_db as db;
+
+use garage_util::background::*;
+use garage_util::error::Error;
+use garage_util::time::now_msec;
+
+use garage_table::replication::TableShardedReplication;
+use garage_table::Table;
+
+use crate::k2v::item_table::*;
+use crate::k2v::rpc::*;
+
+/// Time between two checks of the expiry tree when no item is due to expire
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Worker that replaces the K2V items stored on this node whose TTL
+/// has expired by tombstones
+pub struct K2VExpiryWorker {
+	item_table: Arc<Table<K2VItemTable, TableShardedReplication>>,
+	rpc: Arc<K2VRpcHandler>,
+	expiry_tree: db::Tree,
+	expired: usize,
+}
+
+impl K2VExpiryWorker {
+	pub fn new(
+		item_table: Arc<Table<K2VItemTable, TableShardedReplication>>,
+		rpc: Arc<K2VRpcHandler>,
+	) -> Self {
+		let expiry_tree = item_table.data.instance.expiry_tree.clone();
+		Self {
+			item_table,
+			rpc,
+			expiry_tree,
+			expired: 0,
+		}
+	}
+
+	async fn expire(&mut self, key: &[u8], expires_at: u64) -> Result<(), Error> {
+		// The item might have been updated since it was registered in the
+		// expiry tree: only delete it if it is still expired, at that date
+		let item = match self.item_table.data.store.get(&key[8..])? {
+			Some(bytes) => Some(self.item_table.data.decode_entry(&bytes)?),
+			None => None,
+		};
+		if let Some(item) = item.filter(|x| x.expires_at() == Some(expires_at)) {
+			self.rpc
+				.insert(
+					item.partition.bucket_id,
+					item.partition.partition_key.clone(),
+					item.sort_key.clone(),
+					Some(item.causal_context()),
+					DvvsValue::Deleted,
+					None,
+				)
+				.await?;
+			self.expired += 1;
+		}
+
+		self.expiry_tree.remove(key)?;
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for K2VExpiryWorker {
+	fn name(&self) -> String {
+		"K2V item expiry".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(format!("{} items expired", self.expired)),
+			queue_length: Some(self.expiry_tree.len().unwrap_or(0) as u64),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let key = match self.expiry_tree.first()? {
+			Some((k, _)) => k,
+			None => return Ok(WorkerState::Idle),
+		};
+
+		let expires_at = key
+			.get(..8)
+			.and_then(|x| x.try_into().ok())
+			.map(u64::from_be_bytes)
+			.unwrap_or_default();
+		if expires_at > now_msec() {
+			return Ok(WorkerState::Idle);
+		}
+
+		self.expire(&key, expires_at).await?;
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		tokio::time::sleep(EXPIRY_CHECK_INTERVAL).await;
+		WorkerState::Busy
+	}
+}
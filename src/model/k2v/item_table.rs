@@ -5,6 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use garage_db as db;
 use garage_util::data::*;
+use garage_util::time::now_msec;
 
 use garage_table::crdt::*;
 use garage_table::*;
@@ -53,7 +54,59 @@ mod v08 {
 	impl garage_util::migrate::InitialFormat for K2VItem {}
 }
 
-pub use v08::*;
+mod v09 {
+	use crate::k2v::causality::K2VNodeId;
+	use serde::{Deserialize, Serialize};
+	use std::collections::BTreeMap;
+
+	use super::v08;
+
+	pub use v08::{DvvsValue, K2VItemPartition};
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct K2VItem {
+		pub partition: K2VItemPartition,
+		pub sort_key: String,
+
+		pub(super) items: BTreeMap<K2VNodeId, DvvsEntry>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct DvvsEntry {
+		pub(super) t_discard: u64,
+		pub(super) values: Vec<(u64, DvvsValue)>,
+		/// Expiration date (in msec since the epoch) of the values that
+		/// were inserted with a TTL, indexed by the timestamp of the value
+		pub(super) expires_at: BTreeMap<u64, u64>,
+	}
+
+	impl garage_util::migrate::Migrate for K2VItem {
+		const VERSION_MARKER: &'static [u8] = b"G09k2vi";
+
+		type Previous = v08::K2VItem;
+
+		fn migrate(old: v08::K2VItem) -> K2VItem {
+			K2VItem {
+				partition: old.partition,
+				sort_key: old.sort_key,
+				items: old
+					.items
+					.into_iter()
+					.map(|(node, ent)| {
+						let ent = DvvsEntry {
+							t_discard: ent.t_discard,
+							values: ent.values,
+							expires_at: BTreeMap::new(),
+						};
+						(node, ent)
+					})
+					.collect(),
+			}
+		}
+	}
+}
+
+pub use v09::*;
 
 impl K2VItem {
 	/// Creates a new K2VItem when no previous entry existed in the db
@@ -67,12 +120,14 @@ impl K2VItem {
 			items: BTreeMap::new(),
 		}
 	}
-	/// Updates a K2VItem with a new value or a deletion event
+	/// Updates a K2VItem with a new value or a deletion event,
+	/// that expires at the given date if one is given
 	pub fn update(
 		&mut self,
 		this_node: Uuid,
 		context: &Option<CausalContext>,
 		new_value: DvvsValue,
+		expires_at: Option<u64>,
 		node_ts: u64,
 	) -> u64 {
 		if let Some(context) = context {
@@ -85,6 +140,7 @@ impl K2VItem {
 						DvvsEntry {
 							t_discard: *t_discard,
 							values: vec![],
+							expires_at: BTreeMap::new(),
 						},
 					);
 				}
@@ -97,10 +153,14 @@ impl K2VItem {
 		let e = self.items.entry(node_id).or_insert(DvvsEntry {
 			t_discard: 0,
 			values: vec![],
+			expires_at: BTreeMap::new(),
 		});
 		let t_prev = e.max_time();
 		let t_new = std::cmp::max(t_prev + 1, node_ts + 1);
 		e.values.push((t_new, new_value));
+		if let Some(expires_at) = expires_at {
+			e.expires_at.insert(t_new, expires_at);
+		}
 		t_new
 	}
 
@@ -126,6 +186,45 @@ impl K2VItem {
 		ret
 	}
 
+	/// Count the values of this item that are not superseded by the given
+	/// causality context, i.e. that would be kept as concurrent values by
+	/// an update made with this context
+	pub fn count_concurrent_values(&self, context: &Option<CausalContext>) -> usize {
+		self.items
+			.iter()
+			.map(|(node, ent)| {
+				let t_discard = context
+					.as_ref()
+					.and_then(|c| c.vector_clock.get(node))
+					.map(|t| std::cmp::max(*t, ent.t_discard))
+					.unwrap_or(ent.t_discard);
+				ent.values.iter().filter(|(t, _)| *t > t_discard).count()
+			})
+			.sum()
+	}
+
+	/// Date at which this item expires: the latest expiration date of its
+	/// values, if all of them were inserted with a TTL
+	pub fn expires_at(&self) -> Option<u64> {
+		let mut ret = None;
+		for (_, ent) in self.items.iter() {
+			for (t, v) in ent.values.iter() {
+				if let DvvsValue::Value(_) = v {
+					let expires_at = ent.expires_at.get(t)?;
+					ret = std::cmp::max(ret, Some(*expires_at));
+				}
+			}
+		}
+		ret
+	}
+
+	/// Returns true if the values of this item have expired at the given date.
+	/// Expired items are hidden from reads until they are replaced
+	/// by tombstones by the K2V expiry worker.
+	pub fn is_expired(&self, now: u64) -> bool {
+		self.expires_at().map(|t| t <= now).unwrap_or(false)
+	}
+
 	fn discard(&mut self) {
 		for (_, ent) in self.items.iter_mut() {
 			ent.discard();
@@ -145,6 +244,9 @@ impl DvvsEntry {
 			.into_iter()
 			.filter(|(t, _)| *t > self.t_discard)
 			.collect::<Vec<_>>();
+		let values = &self.values;
+		self.expires_at
+			.retain(|t, _| values.iter().any(|(vt, _)| vt == t));
 	}
 }
 
@@ -169,6 +271,9 @@ impl Crdt for DvvsEntry {
 		for (vt, vv) in other.values.iter() {
 			if *vt > t_max {
 				self.values.push((*vt, vv.clone()));
+				if let Some(expires_at) = other.expires_at.get(vt) {
+					self.expires_at.insert(*vt, *expires_at);
+				}
 			}
 		}
 	}
@@ -204,6 +309,17 @@ impl Entry<K2VItemPartition, String> for K2VItem {
 pub struct K2VItemTable {
 	pub(crate) counter_table: Arc<IndexCounter<K2VItem>>,
 	pub(crate) subscriptions: Arc<SubscriptionManager>,
+	/// Items that have an expiration date, sorted by that date
+	pub(crate) expiry_tree: db::Tree,
+}
+
+/// Key of an item in the expiry tree: the expiration date of the item
+/// followed by its key in the item table
+pub(crate) fn expiry_key(expires_at: u64, item: &K2VItem) -> Vec<u8> {
+	let mut ret = u64::to_be_bytes(expires_at).to_vec();
+	ret.extend(item.partition.hash().as_slice());
+	ret.extend(item.sort_key.as_bytes());
+	ret
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -238,7 +354,19 @@ impl TableSchema for K2VItemTable {
 			);
 		}
 
-		// 2. Notify
+		// 2. Register the expiration date
+		let old_expiry = old.and_then(|x| Some(expiry_key(x.expires_at()?, x)));
+		let new_expiry = new.and_then(|x| Some(expiry_key(x.expires_at()?, x)));
+		if old_expiry != new_expiry {
+			if let Some(k) = old_expiry {
+				tx.remove(&self.expiry_tree, k)?;
+			}
+			if let Some(k) = new_expiry {
+				tx.insert(&self.expiry_tree, k, [])?;
+			}
+		}
+
+		// 3. Notify
 		if let Some(new_ent) = new {
 			self.subscriptions.notify(new_ent);
 		}
@@ -250,7 +378,8 @@ impl TableSchema for K2VItemTable {
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
 		let v = entry.values();
 		!(filter.conflicts_only && v.len() < 2)
-			&& !(filter.exclude_only_tombstones && entry.is_tombstone())
+			&& !(filter.exclude_only_tombstones
+				&& (entry.is_tombstone() || entry.is_expired(now_msec())))
 	}
 }
 
@@ -307,14 +436,38 @@ mod tests {
 				(5, DvvsValue::Value(vec![15])),
 				(6, DvvsValue::Value(vec![16])),
 			],
+			expires_at: BTreeMap::new(),
 		};
 		let e2 = DvvsEntry {
 			t_discard: 5,
 			values: vec![(6, DvvsValue::Value(vec![16])), (7, DvvsValue::Deleted)],
+			expires_at: BTreeMap::new(),
 		};
 
 		let mut e3 = e1;
 		e3.merge(&e2);
 		assert_eq!(e2, e3);
 	}
+
+	#[test]
+	fn test_item_expiry() {
+		let node = gen_uuid();
+		let mut item = K2VItem::new(gen_uuid(), "pk".into(), "sk".into());
+
+		let t1 = item.update(node, &None, DvvsValue::Value(vec![1]), Some(1000), 0);
+		assert_eq!(item.expires_at(), Some(1000));
+		assert!(!item.is_expired(999));
+		assert!(item.is_expired(1000));
+
+		// A concurrent value without a TTL prevents the item from expiring
+		let mut other = item.clone();
+		other.update(node, &None, DvvsValue::Value(vec![2]), None, t1);
+		assert_eq!(other.expires_at(), None);
+
+		// Replacing the value discards its expiration date
+		let ct = item.causal_context();
+		item.update(node, &Some(ct), DvvsValue::Deleted, None, t1);
+		assert_eq!(item.expires_at(), None);
+		assert!(item.items.values().all(|e| e.expires_at.is_empty()));
+	}
 }
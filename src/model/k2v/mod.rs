@@ -5,4 +5,6 @@ pub mod item_table;
 
 pub mod rpc;
 
+pub mod expiry_worker;
+
 pub mod sub;
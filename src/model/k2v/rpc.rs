@@ -23,6 +23,7 @@ use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::time::now_msec;
 
+use garage_rpc::drain::RunningRpcHandler;
 use garage_rpc::system::System;
 use garage_rpc::*;
 
@@ -65,6 +66,8 @@ struct InsertedItem {
 	sort_key: String,
 	causal_context: Option<CausalContext>,
 	value: DvvsValue,
+	/// Time to live of the value in seconds, after which it expires
+	ttl_secs: Option<u64>,
 }
 
 impl Rpc for K2VRpc {
@@ -118,6 +121,7 @@ impl K2VRpcHandler {
 		sort_key: String,
 		causal_context: Option<CausalContext>,
 		value: DvvsValue,
+		ttl_secs: Option<u64>,
 	) -> Result<(), Error> {
 		let partition = K2VItemPartition {
 			bucket_id,
@@ -140,6 +144,7 @@ impl K2VRpcHandler {
 					sort_key,
 					causal_context,
 					value,
+					ttl_secs,
 				}),
 				RequestStrategy::with_priority(PRIO_NORMAL)
 					.with_quorum(1)
@@ -176,6 +181,7 @@ impl K2VRpcHandler {
 				sort_key,
 				causal_context,
 				value,
+				ttl_secs: None,
 			});
 		}
 
@@ -228,6 +234,11 @@ impl K2VRpcHandler {
 			.data
 			.replication
 			.write_nodes(&poll_key.partition.hash());
+		let quorum = self
+			.item_table
+			.data
+			.replication
+			.read_quorum(&poll_key.partition.hash());
 
 		let rpc = self.system.rpc.try_call_many(
 			&self.endpoint,
@@ -238,7 +249,7 @@ impl K2VRpcHandler {
 				timeout_msec,
 			},
 			RequestStrategy::with_priority(PRIO_NORMAL)
-				.with_quorum(self.item_table.data.replication.read_quorum())
+				.with_quorum(quorum)
 				.without_timeout(),
 		);
 		let timeout_duration = Duration::from_millis(timeout_msec) + self.system.rpc.rpc_timeout();
@@ -288,7 +299,11 @@ impl K2VRpcHandler {
 			.data
 			.replication
 			.write_nodes(&range.partition.hash());
-		let quorum = self.item_table.data.replication.read_quorum();
+		let quorum = self
+			.item_table
+			.data
+			.replication
+			.read_quorum(&range.partition.hash());
 		let msg = K2VRpc::PollRange {
 			range,
 			seen_str,
@@ -434,6 +449,8 @@ impl K2VRpcHandler {
 					self.system.id,
 					&item.causal_context,
 					item.value.clone(),
+					item.ttl_secs
+						.map(|ttl| now.saturating_add(ttl.saturating_mul(1000))),
 					std::cmp::max(old_local_timestamp, now),
 				);
 
@@ -540,6 +557,7 @@ impl K2VRpcHandler {
 #[async_trait]
 impl EndpointHandler<K2VRpc> for K2VRpcHandler {
 	async fn handle(self: &Arc<Self>, message: &K2VRpc, _from: NodeID) -> Result<K2VRpc, Error> {
+		let _running = RunningRpcHandler::start();
 		match message {
 			K2VRpc::InsertItem(item) => self.handle_insert(item).await,
 			K2VRpc::InsertManyItems(items) => self.handle_insert_many(&items[..]).await,
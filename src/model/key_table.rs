@@ -84,6 +84,15 @@ mod v08 {
 		/// A key can have a local view of buckets names it is
 		/// the only one to see, this is the namespace for these aliases
 		pub local_aliases: crdt::LwwMap<String, Option<Uuid>>,
+
+		/// Maximum number of S3 requests per second made with this key
+		#[serde(default)]
+		pub rate_limit_rps: crdt::Lww<Option<u32>>,
+
+		/// Maximum number of bytes per second sent and received
+		/// in S3 requests made with this key
+		#[serde(default)]
+		pub rate_limit_bandwidth_bps: crdt::Lww<Option<u64>>,
 	}
 
 	impl garage_util::migrate::Migrate for Key {
@@ -104,6 +113,8 @@ mod v08 {
 					allow_create_bucket: crdt::Lww::new(false),
 					authorized_buckets: crdt::Map::new(),
 					local_aliases: crdt::LwwMap::new(),
+					rate_limit_rps: crdt::Lww::new(None),
+					rate_limit_bandwidth_bps: crdt::Lww::new(None),
 				})
 			};
 			Key {
@@ -124,6 +135,8 @@ impl KeyParams {
 			allow_create_bucket: crdt::Lww::new(false),
 			authorized_buckets: crdt::Map::new(),
 			local_aliases: crdt::LwwMap::new(),
+			rate_limit_rps: crdt::Lww::new(None),
+			rate_limit_bandwidth_bps: crdt::Lww::new(None),
 		}
 	}
 }
@@ -134,6 +147,9 @@ impl Crdt for KeyParams {
 		self.allow_create_bucket.merge(&o.allow_create_bucket);
 		self.authorized_buckets.merge(&o.authorized_buckets);
 		self.local_aliases.merge(&o.local_aliases);
+		self.rate_limit_rps.merge(&o.rate_limit_rps);
+		self.rate_limit_bandwidth_bps
+			.merge(&o.rate_limit_bandwidth_bps);
 	}
 }
 
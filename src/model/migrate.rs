@@ -62,6 +62,7 @@ impl Migrate {
 			Some(WebsiteConfig {
 				index_document: "index.html".into(),
 				error_document: None,
+				routing_rules: vec![],
 			})
 		} else {
 			None
@@ -79,6 +80,15 @@ impl Migrate {
 					website_config: Lww::new(website),
 					cors_config: Lww::new(None),
 					quotas: Lww::new(Default::default()),
+					lifecycle_config: Lww::new(None),
+					default_encryption: Lww::new(None),
+					versioning: Lww::new(None),
+					object_lock_enabled: Bool::new(false),
+					object_lock_default_retention: Lww::new(None),
+					block_gc_delay_secs: Lww::new(None),
+					mpu_ttl_days: Lww::new(None),
+					notifications: Lww::new(None),
+					policy: Lww::new(None),
 				}),
 			})
 			.await?;
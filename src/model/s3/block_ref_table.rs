@@ -1,14 +1,20 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use garage_db as db;
 
 use garage_util::data::*;
+use garage_util::migrate::Migrate;
 
 use garage_table::crdt::Crdt;
+use garage_table::replication::TableFullReplication;
 use garage_table::*;
 
 use garage_block::manager::*;
 
+use crate::bucket_table::*;
+use crate::s3::version_table::Version;
+
 mod v08 {
 	use garage_util::crdt;
 	use garage_util::data::{Hash, Uuid};
@@ -52,6 +58,36 @@ impl Crdt for BlockRef {
 
 pub struct BlockRefTable {
 	pub block_manager: Arc<BlockManager>,
+	pub bucket_table: Arc<Table<BucketTable, TableFullReplication>>,
+	/// Local store of the version table, which is created after this table
+	pub version_store: db::Tree,
+}
+
+impl BlockRefTable {
+	/// Find the delay before a block that is no longer referenced by a version
+	/// can be deleted, if the bucket of that version overrides the global one.
+	/// The bucket can only be found if the version is stored on this node.
+	fn block_gc_delay(
+		&self,
+		tx: &mut db::Transaction,
+		version: &Uuid,
+	) -> db::TxOpResult<Option<Duration>> {
+		// Versions have no sort key, their entries are stored under their uuid
+		let bucket_id = match tx
+			.get(&self.version_store, version.as_slice())?
+			.and_then(|v| Version::decode(&v))
+		{
+			Some(v) => v.bucket_id,
+			None => return Ok(None),
+		};
+		let bucket_key = self.bucket_table.data.tree_key(&EmptyKey, &bucket_id);
+		let gc_delay = tx
+			.get(&self.bucket_table.data.store, bucket_key)?
+			.and_then(|b| self.bucket_table.data.decode_entry(&b).ok())
+			.and_then(|b| b.params().and_then(|p| *p.block_gc_delay_secs.get()))
+			.map(Duration::from_secs);
+		Ok(gc_delay)
+	}
 }
 
 impl TableSchema for BlockRefTable {
@@ -69,13 +105,15 @@ impl TableSchema for BlockRefTable {
 		new: Option<&Self::E>,
 	) -> db::TxOpResult<()> {
 		let block = old.or(new).unwrap().block;
+		let version = old.or(new).unwrap().version;
 		let was_before = old.map(|x| !x.deleted.get()).unwrap_or(false);
 		let is_after = new.map(|x| !x.deleted.get()).unwrap_or(false);
 		if is_after && !was_before {
 			self.block_manager.block_incref(tx, block)?;
 		}
 		if was_before && !is_after {
-			self.block_manager.block_decref(tx, block)?;
+			let gc_delay = self.block_gc_delay(tx, &version)?;
+			self.block_manager.block_decref(tx, block, gc_delay)?;
 		}
 		Ok(())
 	}
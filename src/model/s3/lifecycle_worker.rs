@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use garage_table::*;
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::{Error, OkOrMessage};
+use garage_util::migrate::Migrate;
+use garage_util::time::*;
+
+use crate::bucket_table::*;
+use crate::garage::Garage;
+use crate::s3::object_table::*;
+
+/// Time between two passes of the lifecycle worker over the object table
+const LIFECYCLE_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+const DAY_MSEC: u64 = 24 * 3600 * 1000;
+
+/// Worker that goes through the objects stored on this node, and aborts
+/// the multipart uploads that have been in progress for longer than
+/// allowed by the configuration of their bucket, and deletes the
+/// non-current versions of objects that have expired
+pub struct LifecycleWorker {
+	garage: Arc<Garage>,
+	state: State,
+	buckets: HashMap<Uuid, Option<Bucket>>,
+	counter: usize,
+	mpu_aborted: usize,
+	noncurrent_expired: usize,
+	/// Stop after one pass over the object table instead of running every day
+	single_pass: bool,
+	/// Only count the uploads and versions that would be deleted
+	dry_run: bool,
+}
+
+enum State {
+	Running { pos: Vec<u8> },
+	Waiting { next_run: Instant },
+}
+
+impl LifecycleWorker {
+	pub fn new(garage: Arc<Garage>) -> Self {
+		Self {
+			garage,
+			state: State::Running { pos: vec![] },
+			buckets: HashMap::new(),
+			counter: 0,
+			mpu_aborted: 0,
+			noncurrent_expired: 0,
+			single_pass: false,
+			dry_run: false,
+		}
+	}
+
+	/// Worker that does a single pass over the object table right away,
+	/// used by `garage repair lifecycle`
+	pub fn new_single_pass(garage: Arc<Garage>, dry_run: bool) -> Self {
+		Self {
+			single_pass: true,
+			dry_run,
+			..Self::new(garage)
+		}
+	}
+
+	fn aborted_label(&self) -> &'static str {
+		if self.dry_run {
+			"uploads to abort"
+		} else {
+			"uploads aborted"
+		}
+	}
+
+	fn expired_label(&self) -> &'static str {
+		if self.dry_run {
+			"non-current versions to expire"
+		} else {
+			"non-current versions expired"
+		}
+	}
+
+	async fn process_object(&mut self, object: &Object) -> Result<(), Error> {
+		let has_uploads = object.versions().iter().any(|v| v.is_uploading());
+		let noncurrent = noncurrent_versions(object);
+		let has_noncurrent = !noncurrent.is_empty();
+		if !has_uploads && !has_noncurrent {
+			return Ok(());
+		}
+
+		let (mpu_ttl_days, noncurrent_days) = match self.get_bucket(object.bucket_id).await? {
+			Some(params) => (
+				mpu_ttl_days(params, &object.key),
+				noncurrent_version_expiration_days(params, &object.key),
+			),
+			None => return Ok(()),
+		};
+
+		let now = now_msec();
+		let mut aborted = vec![];
+		let mut mpu_aborted = 0;
+		let mut noncurrent_expired = 0;
+
+		if let Some(days) = mpu_ttl_days {
+			let deadline = now.saturating_sub(days * DAY_MSEC);
+			for v in object.versions() {
+				if v.is_uploading() && v.timestamp < deadline {
+					aborted.push(v.clone());
+					mpu_aborted += 1;
+				}
+			}
+		}
+
+		if let Some(days) = noncurrent_days {
+			let deadline = now.saturating_sub(days * DAY_MSEC);
+			for (v, noncurrent_since) in noncurrent {
+				if noncurrent_since < deadline && !v.is_locked(now) {
+					aborted.push(v.clone());
+					noncurrent_expired += 1;
+				}
+			}
+		}
+
+		if aborted.is_empty() {
+			return Ok(());
+		}
+
+		self.mpu_aborted += mpu_aborted;
+		self.noncurrent_expired += noncurrent_expired;
+		if self.dry_run {
+			info!(
+				"Lifecycle (dry run): would abort {} incomplete multipart upload(s) and expire {} non-current version(s) of {:?}/{}",
+				mpu_aborted,
+				noncurrent_expired,
+				object.bucket_id,
+				object.key
+			);
+		} else {
+			info!(
+				"Lifecycle: aborting {} incomplete multipart upload(s) and expiring {} non-current version(s) of {:?}/{}",
+				mpu_aborted,
+				noncurrent_expired,
+				object.bucket_id,
+				object.key
+			);
+			let aborted = aborted
+				.into_iter()
+				.map(|v| ObjectVersion {
+					state: ObjectVersionState::Aborted,
+					..v
+				})
+				.collect::<Vec<_>>();
+			let object = Object::new(object.bucket_id, object.key.clone(), aborted);
+			self.garage.object_table.insert(&object).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Get the parameters of a bucket, caching them for the current pass
+	async fn get_bucket(&mut self, bucket_id: Uuid) -> Result<Option<&BucketParams>, Error> {
+		if !self.buckets.contains_key(&bucket_id) {
+			let bucket = self.garage.bucket_table.get(&EmptyKey, &bucket_id).await?;
+			self.buckets.insert(bucket_id, bucket);
+		}
+		Ok(self.buckets[&bucket_id].as_ref().and_then(|b| b.params()))
+	}
+}
+
+/// Number of days after which an incomplete multipart upload of a given key
+/// is aborted: the smallest of the TTL of the bucket and of the applicable
+/// AbortIncompleteMultipartUpload lifecycle rules
+fn mpu_ttl_days(params: &BucketParams, key: &str) -> Option<u64> {
+	let from_rules = params
+		.lifecycle_config
+		.get()
+		.iter()
+		.flatten()
+		.filter(|rule| rule.enabled)
+		.filter(|rule| match &rule.filter.prefix {
+			Some(prefix) => key.starts_with(prefix.as_str()),
+			None => true,
+		})
+		.filter_map(|rule| rule.abort_incomplete_mpu_days)
+		.map(|days| days as u64);
+	params
+		.mpu_ttl_days
+		.get()
+		.iter()
+		.cloned()
+		.chain(from_rules)
+		.min()
+}
+
+/// Non-current versions of an object, i.e. the retained versions that come
+/// before its current version (whether or not the current version was itself
+/// written with versioning enabled), along with the timestamp at which each
+/// of them became non-current: the timestamp of the next complete version
+fn noncurrent_versions(object: &Object) -> Vec<(&ObjectVersion, u64)> {
+	let complete = object
+		.versions()
+		.iter()
+		.filter(|v| v.is_complete())
+		.collect::<Vec<_>>();
+	complete
+		.windows(2)
+		.filter(|pair| pair[0].is_retained())
+		.map(|pair| (pair[0], pair[1].timestamp))
+		.collect()
+}
+
+/// Number of days after which a non-current version of a given key is
+/// deleted: the smallest value of the applicable NoncurrentVersionExpiration
+/// lifecycle rules
+fn noncurrent_version_expiration_days(params: &BucketParams, key: &str) -> Option<u64> {
+	params
+		.lifecycle_config
+		.get()
+		.iter()
+		.flatten()
+		.filter(|rule| rule.enabled)
+		.filter(|rule| match &rule.filter.prefix {
+			Some(prefix) => key.starts_with(prefix.as_str()),
+			None => true,
+		})
+		.filter_map(|rule| rule.noncurrent_version_expiration_days)
+		.map(|days| days as u64)
+		.min()
+}
+
+#[async_trait]
+impl Worker for LifecycleWorker {
+	fn name(&self) -> String {
+		match (self.single_pass, self.dry_run) {
+			(false, _) => "Object lifecycle worker".into(),
+			(true, false) => "Object lifecycle repair worker".into(),
+			(true, true) => "Object lifecycle repair worker (dry run)".into(),
+		}
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let freeform = match &self.state {
+			State::Running { .. } => vec![],
+			State::Waiting { next_run } => vec![format!(
+				"Next run in {}s",
+				next_run.saturating_duration_since(Instant::now()).as_secs()
+			)],
+		};
+		WorkerStatus {
+			progress: Some(format!(
+				"{} objects, {} {}, {} {}",
+				self.counter,
+				self.mpu_aborted,
+				self.aborted_label(),
+				self.noncurrent_expired,
+				self.expired_label()
+			)),
+			freeform,
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let pos = match &self.state {
+			State::Running { pos } => pos.clone(),
+			State::Waiting { .. } => return Ok(WorkerState::Idle),
+		};
+
+		let (item_bytes, next_pos) = match self.garage.object_table.data.store.get_gt(&pos)? {
+			Some((k, v)) => (v, k),
+			None => {
+				info!(
+					"Lifecycle: pass finished, {} objects checked, {} {}, {} {}",
+					self.counter,
+					self.mpu_aborted,
+					self.aborted_label(),
+					self.noncurrent_expired,
+					self.expired_label()
+				);
+				if self.single_pass {
+					return Ok(WorkerState::Done);
+				}
+				self.buckets.clear();
+				self.state = State::Waiting {
+					next_run: Instant::now() + LIFECYCLE_INTERVAL,
+				};
+				return Ok(WorkerState::Idle);
+			}
+		};
+
+		let object = Object::decode(&item_bytes).ok_or_message("Cannot decode Object")?;
+		self.process_object(&object).await?;
+
+		self.counter += 1;
+		self.state = State::Running { pos: next_pos };
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		if let State::Waiting { next_run } = &self.state {
+			tokio::time::sleep_until((*next_run).into()).await;
+			self.counter = 0;
+			self.mpu_aborted = 0;
+			self.noncurrent_expired = 0;
+			self.state = State::Running { pos: vec![] };
+		}
+		WorkerState::Busy
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(timestamp: u64, versioned: bool) -> ObjectVersion {
+		ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp,
+			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+			versioned,
+			legal_hold: Default::default(),
+		}
+	}
+
+	#[test]
+	fn test_noncurrent_versions() {
+		// Versioning enabled: all versions but the last one are non-current
+		let object = Object::new(
+			gen_uuid(),
+			"a".into(),
+			vec![version(1, true), version(2, true), version(3, true)],
+		);
+		let noncurrent = noncurrent_versions(&object);
+		assert_eq!(
+			noncurrent
+				.iter()
+				.map(|(v, since)| (v.timestamp, *since))
+				.collect::<Vec<_>>(),
+			vec![(1, 2), (2, 3)]
+		);
+
+		// Versioning suspended: the current version was written unversioned,
+		// and the single retained version before it is non-current
+		let object = Object::new(
+			gen_uuid(),
+			"a".into(),
+			vec![version(1, true), version(2, false)],
+		);
+		let noncurrent = noncurrent_versions(&object);
+		assert_eq!(noncurrent.len(), 1);
+		assert_eq!(noncurrent[0].0.timestamp, 1);
+		assert_eq!(noncurrent[0].1, 2);
+
+		// A single retained version is the current one
+		let object = Object::new(gen_uuid(), "a".into(), vec![version(1, true)]);
+		assert!(noncurrent_versions(&object).is_empty());
+	}
+}
@@ -1,3 +1,4 @@
 pub mod block_ref_table;
+pub mod lifecycle_worker;
 pub mod object_table;
 pub mod version_table;
@@ -130,7 +130,160 @@ mod v08 {
 	}
 }
 
-pub use v08::*;
+mod v09 {
+	use garage_util::crdt;
+	use garage_util::data::{Hash, Uuid};
+	use serde::{Deserialize, Serialize};
+
+	use super::v08;
+
+	pub use v08::ObjectVersionHeaders;
+
+	/// An object
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct Object {
+		/// The bucket in which the object is stored, used as partition key
+		pub bucket_id: Uuid,
+
+		/// The key at which the object is stored in its bucket, used as sorting key
+		pub key: String,
+
+		/// The list of currenty stored versions of the object
+		pub(super) versions: Vec<ObjectVersion>,
+	}
+
+	/// Informations about a version of an object
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectVersion {
+		/// Id of the version
+		pub uuid: Uuid,
+		/// Timestamp of when the object was created
+		pub timestamp: u64,
+		/// State of the version
+		pub state: ObjectVersionState,
+		/// Whether the version was written while versioning was enabled on the bucket,
+		/// in which case it is not removed when newer versions of the object are written
+		pub versioned: bool,
+		/// Whether a legal hold prevents the version from being removed
+		pub legal_hold: crdt::Lww<bool>,
+	}
+
+	/// State of an object version
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub enum ObjectVersionState {
+		/// The version is being received
+		Uploading(ObjectVersionHeaders),
+		/// The version is fully received
+		Complete(ObjectVersionData),
+		/// The version uploaded containded errors or the upload was explicitly aborted
+		Aborted,
+	}
+
+	/// Data stored in object version
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+	pub enum ObjectVersionData {
+		/// The object was deleted, this Version is a tombstone to mark it as such
+		DeleteMarker,
+		/// The object is short, it's stored inlined
+		Inline(ObjectVersionMeta, #[serde(with = "serde_bytes")] Vec<u8>),
+		/// The object is not short, Hash of first block is stored here, next segments hashes are
+		/// stored in the version table
+		FirstBlock(ObjectVersionMeta, Hash),
+	}
+
+	/// Metadata about the object version
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectVersionMeta {
+		/// Headers to send to the client
+		pub headers: ObjectVersionHeaders,
+		/// Size of the object
+		pub size: u64,
+		/// etag of the object
+		pub etag: String,
+		/// Object lock retention of the object, if any
+		pub lock: Option<ObjectLockConfig>,
+		/// Server-side encryption of the data of the object, if any
+		pub encryption: Option<ObjectEncryption>,
+	}
+
+	/// Server-side encryption of an object version
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ObjectEncryption {
+		/// SSE-S3: the blocks of the object are encrypted with AES-256-GCM, using a key
+		/// derived from the master key of the cluster and from this random IV
+		SseS3 { iv: [u8; 16] },
+	}
+
+	/// Object lock retention of an object version
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub struct ObjectLockConfig {
+		pub mode: ObjectLockMode,
+		/// The version can not be removed before this date (timestamp in msec)
+		pub retain_until: u64,
+	}
+
+	/// Object lock retention mode
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ObjectLockMode {
+		/// The retention can be bypassed by the owners of the bucket
+		Governance,
+		/// The retention can not be bypassed by anyone
+		Compliance,
+	}
+
+	impl garage_util::migrate::Migrate for Object {
+		const VERSION_MARKER: &'static [u8] = b"G09s3o";
+
+		type Previous = v08::Object;
+
+		fn migrate(old: v08::Object) -> Object {
+			Object {
+				bucket_id: old.bucket_id,
+				key: old.key,
+				versions: old.versions.into_iter().map(migrate_version).collect(),
+			}
+		}
+	}
+
+	fn migrate_version(old: v08::ObjectVersion) -> ObjectVersion {
+		let state = match old.state {
+			v08::ObjectVersionState::Uploading(h) => ObjectVersionState::Uploading(h),
+			v08::ObjectVersionState::Complete(d) => ObjectVersionState::Complete(migrate_data(d)),
+			v08::ObjectVersionState::Aborted => ObjectVersionState::Aborted,
+		};
+		ObjectVersion {
+			uuid: old.uuid,
+			timestamp: old.timestamp,
+			state,
+			versioned: false,
+			legal_hold: crdt::Lww::default(),
+		}
+	}
+
+	fn migrate_data(old: v08::ObjectVersionData) -> ObjectVersionData {
+		match old {
+			v08::ObjectVersionData::DeleteMarker => ObjectVersionData::DeleteMarker,
+			v08::ObjectVersionData::Inline(meta, data) => {
+				ObjectVersionData::Inline(migrate_meta(meta), data)
+			}
+			v08::ObjectVersionData::FirstBlock(meta, hash) => {
+				ObjectVersionData::FirstBlock(migrate_meta(meta), hash)
+			}
+		}
+	}
+
+	fn migrate_meta(old: v08::ObjectVersionMeta) -> ObjectVersionMeta {
+		ObjectVersionMeta {
+			headers: old.headers,
+			size: old.size,
+			etag: old.etag,
+			lock: None,
+			encryption: None,
+		}
+	}
+}
+
+pub use v09::*;
 
 impl Object {
 	/// Initialize an Object struct from parts
@@ -166,6 +319,18 @@ impl Object {
 	pub fn versions(&self) -> &[ObjectVersion] {
 		&self.versions[..]
 	}
+
+	/// Get the current version of `Object`, i.e. the last complete version
+	/// (it can be a delete marker)
+	pub fn current_version(&self) -> Option<&ObjectVersion> {
+		self.versions.iter().rev().find(|v| v.is_complete())
+	}
+
+	/// Get the current version of `Object` if it contains data
+	/// (i.e. if the object exists and is not deleted)
+	pub fn current_data_version(&self) -> Option<&ObjectVersion> {
+		self.current_version().filter(|v| v.is_data())
+	}
 }
 
 impl Crdt for ObjectVersionState {
@@ -216,6 +381,36 @@ impl ObjectVersion {
 			_ => false,
 		}
 	}
+
+	/// Is the object version kept when newer versions are written
+	/// (complete version written with versioning enabled on the bucket)
+	pub fn is_retained(&self) -> bool {
+		self.versioned && self.is_complete()
+	}
+
+	/// Is the object version protected by object lock at this date
+	/// (by a legal hold or by a retention that has not expired)
+	pub fn is_locked(&self, now: u64) -> bool {
+		if *self.legal_hold.get() {
+			return true;
+		}
+		match &self.state {
+			ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+			| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+				matches!(meta.lock, Some(lock) if lock.retain_until > now)
+			}
+			_ => false,
+		}
+	}
+
+	/// Size of the data of the object version (0 if it is not complete or a delete marker)
+	pub fn size(&self) -> u64 {
+		match &self.state {
+			ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+			| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta.size,
+			_ => 0,
+		}
+	}
 }
 
 impl Entry<Uuid, String> for Object {
@@ -242,6 +437,8 @@ impl Crdt for Object {
 			{
 				Ok(i) => {
 					self.versions[i].state.merge(&other_v.state);
+					self.versions[i].versioned |= other_v.versioned;
+					self.versions[i].legal_hold.merge(&other_v.legal_hold);
 				}
 				Err(i) => {
 					self.versions.insert(i, other_v.clone());
@@ -250,7 +447,8 @@ impl Crdt for Object {
 		}
 
 		// Remove versions which are obsolete, i.e. those that come
-		// before the last version which .is_complete().
+		// before the last version which .is_complete(), unless they
+		// were written with versioning enabled on the bucket.
 		let last_complete = self
 			.versions
 			.iter()
@@ -260,7 +458,9 @@ impl Crdt for Object {
 			.map(|(vi, _)| vi);
 
 		if let Some(last_vi) = last_complete {
-			self.versions = self.versions.drain(last_vi..).collect::<Vec<_>>();
+			let current = self.versions.split_off(last_vi);
+			self.versions.retain(|v| v.is_retained());
+			self.versions.extend(current);
 		}
 	}
 }
@@ -274,6 +474,9 @@ pub struct ObjectTable {
 pub enum ObjectFilter {
 	IsData,
 	IsUploading,
+	/// The object has data in its current version
+	/// or in versions retained by bucket versioning
+	HasData,
 }
 
 impl TableSchema for ObjectTable {
@@ -332,8 +535,12 @@ impl TableSchema for ObjectTable {
 
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
 		match filter {
-			ObjectFilter::IsData => entry.versions.iter().any(|v| v.is_data()),
+			ObjectFilter::IsData => entry.current_data_version().is_some(),
 			ObjectFilter::IsUploading => entry.versions.iter().any(|v| v.is_uploading()),
+			ObjectFilter::HasData => entry
+				.versions
+				.iter()
+				.any(|v| v.is_data() && (v.is_retained() || Some(v) == entry.current_version())),
 		}
 	}
 }
@@ -355,7 +562,7 @@ impl CountedItem for Object {
 
 	fn counts(&self) -> Vec<(&'static str, i64)> {
 		let versions = self.versions();
-		let n_objects = if versions.iter().any(|v| v.is_data()) {
+		let n_objects = if self.current_data_version().is_some() {
 			1
 		} else {
 			0
@@ -364,14 +571,7 @@ impl CountedItem for Object {
 			.iter()
 			.filter(|v| matches!(v.state, ObjectVersionState::Uploading(_)))
 			.count();
-		let n_bytes = versions
-			.iter()
-			.map(|v| match &v.state {
-				ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
-				| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => meta.size,
-				_ => 0,
-			})
-			.sum::<u64>();
+		let n_bytes = versions.iter().map(|v| v.size()).sum::<u64>();
 
 		vec![
 			(OBJECTS, n_objects),
@@ -380,3 +580,55 @@ impl CountedItem for Object {
 		]
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_util::migrate::Migrate;
+	use std::collections::BTreeMap;
+
+	#[test]
+	fn test_migrate_v08() {
+		let headers = ObjectVersionHeaders {
+			content_type: "text/plain".into(),
+			other: BTreeMap::new(),
+		};
+		let old = v08::Object {
+			bucket_id: gen_uuid(),
+			key: "a".into(),
+			versions: vec![v08::ObjectVersion {
+				uuid: gen_uuid(),
+				timestamp: 1,
+				state: v08::ObjectVersionState::Complete(v08::ObjectVersionData::Inline(
+					v08::ObjectVersionMeta {
+						headers,
+						size: 5,
+						etag: "etag".into(),
+					},
+					b"hello".to_vec(),
+				)),
+			}],
+		};
+
+		let obj = Object::decode(&old.encode().unwrap()).unwrap();
+		assert_eq!(obj.bucket_id, old.bucket_id);
+		assert_eq!(obj.versions().len(), 1);
+		let version = &obj.versions()[0];
+		assert_eq!(version.uuid, old.versions[0].uuid);
+		assert!(!version.versioned);
+		assert!(!version.legal_hold.get());
+		match &version.state {
+			ObjectVersionState::Complete(ObjectVersionData::Inline(meta, data)) => {
+				assert_eq!(meta.etag, "etag");
+				assert!(meta.lock.is_none() && meta.encryption.is_none());
+				assert_eq!(data, b"hello");
+			}
+			_ => panic!("unexpected state {:?}", version.state),
+		}
+
+		// The new format is recognized by its version marker
+		let encoded = obj.encode().unwrap();
+		assert!(encoded.starts_with(b"G09s3o"));
+		assert_eq!(Object::decode(&encoded), Some(obj));
+	}
+}
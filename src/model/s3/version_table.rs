@@ -81,6 +81,10 @@ mod v08 {
 		pub blocks: crdt::Map<VersionBlockKey, VersionBlock>,
 		/// Etag of each part in case of a multipart upload, empty otherwise
 		pub parts_etags: crdt::Map<u64, String>,
+		/// Checksum of each part in case of a multipart upload, for the parts
+		/// for which the client sent a x-amz-checksum-* header
+		#[serde(default)]
+		pub parts_checksums: crdt::Map<u64, ChecksumValue>,
 
 		// Back link to bucket+key so that we can figure if
 		// this was deleted later on
@@ -90,6 +94,14 @@ mod v08 {
 		pub key: String,
 	}
 
+	/// Checksum of some data, as declared by the client using
+	/// one of the x-amz-checksum-* headers
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum ChecksumValue {
+		Crc32c([u8; 4]),
+		Sha256([u8; 32]),
+	}
+
 	pub use v05::{VersionBlock, VersionBlockKey};
 
 	impl garage_util::migrate::Migrate for Version {
@@ -103,6 +115,7 @@ mod v08 {
 				deleted: old.deleted,
 				blocks: old.blocks,
 				parts_etags: old.parts_etags,
+				parts_checksums: crdt::Map::new(),
 				bucket_id: blake2sum(old.bucket.as_bytes()),
 				key: old.key,
 			}
@@ -119,6 +132,7 @@ impl Version {
 			deleted: deleted.into(),
 			blocks: crdt::Map::new(),
 			parts_etags: crdt::Map::new(),
+			parts_checksums: crdt::Map::new(),
 			bucket_id,
 			key,
 		}
@@ -157,6 +171,10 @@ impl AutoCrdt for VersionBlock {
 	const WARN_IF_DIFFERENT: bool = true;
 }
 
+impl AutoCrdt for ChecksumValue {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
 impl Entry<Uuid, EmptyKey> for Version {
 	fn partition_key(&self) -> &Uuid {
 		&self.uuid
@@ -176,9 +194,11 @@ impl Crdt for Version {
 		if self.deleted.get() {
 			self.blocks.clear();
 			self.parts_etags.clear();
+			self.parts_checksums.clear();
 		} else {
 			self.blocks.merge(&other.blocks);
 			self.parts_etags.merge(&other.parts_etags);
+			self.parts_checksums.merge(&other.parts_checksums);
 		}
 	}
 }
@@ -10,6 +10,7 @@ use netapp::NodeID;
 
 use garage_util::config::ConsulDiscoveryAPI;
 use garage_util::config::ConsulDiscoveryConfig;
+use garage_util::socket_address::UnixOrTCPSocketAddress;
 
 const META_PREFIX: &str = "fr-deuxfleurs-garagehq";
 
@@ -70,16 +71,35 @@ struct ConsulPublishService {
 	port: u16,
 	#[serde(rename = "Meta")]
 	meta: HashMap<String, String>,
+	#[serde(rename = "Checks", skip_serializing_if = "Vec::is_empty")]
+	checks: Vec<ConsulServiceCheck>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct ConsulServiceCheck {
+	#[serde(rename = "Name")]
+	name: String,
+	#[serde(rename = "HTTP")]
+	http: String,
+	#[serde(rename = "Interval")]
+	interval: String,
+	#[serde(rename = "DeregisterCriticalServiceAfter")]
+	deregister_critical_service_after: String,
 }
 
 // ----
 pub struct ConsulDiscovery {
 	config: ConsulDiscoveryConfig,
 	client: reqwest::Client,
+	/// Port of the admin API, on which Consul can check the health of this node
+	admin_api_port: Option<u16>,
 }
 
 impl ConsulDiscovery {
-	pub fn new(config: ConsulDiscoveryConfig) -> Result<Self, ConsulError> {
+	pub fn new(
+		config: ConsulDiscoveryConfig,
+		admin_api_bind_addr: Option<&UnixOrTCPSocketAddress>,
+	) -> Result<Self, ConsulError> {
 		let mut builder: reqwest::ClientBuilder = reqwest::Client::builder().use_rustls_tls();
 		if config.tls_skip_verify {
 			builder = builder.danger_accept_invalid_certs(true);
@@ -122,7 +142,19 @@ impl ConsulDiscovery {
 
 		let client: reqwest::Client = builder.build()?;
 
-		Ok(Self { client, config })
+		let admin_api_port = match admin_api_bind_addr {
+			Some(UnixOrTCPSocketAddress::TCPSocket(addr)) => Some(addr.port()),
+			_ => None,
+		};
+		if matches!(config.api, ConsulDiscoveryAPI::Catalog) && config.health_check_url.is_some() {
+			warn!("Consul health checks are only registered when using the agent API (api = \"agent\")");
+		}
+
+		Ok(Self {
+			client,
+			config,
+			admin_api_port,
+		})
 	}
 
 	// ---- READING FROM CONSUL CATALOG ----
@@ -207,6 +239,7 @@ impl ConsulDiscovery {
 				meta,
 				address: rpc_public_addr.ip(),
 				port: rpc_public_addr.port(),
+				checks: self.health_check(rpc_public_addr).into_iter().collect(),
 			}),
 		};
 		let http = req.json(&advertisement).send().await?;
@@ -214,6 +247,31 @@ impl ConsulDiscovery {
 
 		Ok(())
 	}
+
+	/// Health check run by the Consul agent, which makes the service unavailable
+	/// when the node cannot serve requests (see the `/health` endpoint of the admin API),
+	/// and deregisters it if this lasts for too long
+	fn health_check(&self, rpc_public_addr: SocketAddr) -> Option<ConsulServiceCheck> {
+		let url = match (&self.config.health_check_url, self.admin_api_port) {
+			(Some(url), _) => url.clone(),
+			(None, Some(port)) => {
+				format!(
+					"http://{}/health",
+					SocketAddr::new(rpc_public_addr.ip(), port)
+				)
+			}
+			(None, None) => return None,
+		};
+		Some(ConsulServiceCheck {
+			name: format!("{} health", self.config.service_name),
+			http: url,
+			interval: format!("{}s", self.config.health_check_interval_secs),
+			deregister_critical_service_after: format!(
+				"{}s",
+				self.config.health_check_deregister_after_secs
+			),
+		})
+	}
 }
 
 /// Regroup all Consul discovery errors
@@ -0,0 +1,62 @@
+use std::net::SocketAddr;
+
+use trust_dns_resolver::error::ResolveError;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use netapp::util::parse_and_resolve_peer_addr_async;
+use netapp::NodeID;
+
+use garage_util::config::DnsDiscoveryConfig;
+
+/// Look up the nodes announced by the SRV records of the configured service.
+///
+/// A public key does not fit in a DNS label, so each SRV target must also
+/// have a TXT record containing the hex-encoded public key of the node.
+pub async fn get_dns_nodes(
+	dns_config: &DnsDiscoveryConfig,
+) -> Result<Vec<(NodeID, SocketAddr)>, ResolveError> {
+	let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+	let srv = resolver
+		.srv_lookup(dns_config.service_name.as_str())
+		.await?;
+
+	let mut ret = vec![];
+
+	for record in srv.iter() {
+		let target = record.target().to_utf8();
+		let host = target.trim_end_matches('.');
+
+		let txt = match resolver.txt_lookup(record.target().clone()).await {
+			Ok(txt) => txt,
+			Err(e) => {
+				warn!("Could not look up TXT record of {}: {}", host, e);
+				continue;
+			}
+		};
+		let pubkey = txt
+			.iter()
+			.flat_map(|t| t.txt_data().iter())
+			.filter_map(|d| std::str::from_utf8(d).ok())
+			.find(|d| d.len() == 64 && hex::decode(d).is_ok());
+		let pubkey = match pubkey {
+			Some(pk) => pk,
+			None => {
+				warn!("No node public key found in TXT records of {}", host);
+				continue;
+			}
+		};
+
+		let peer = format!("{}@{}:{}", pubkey, host, record.port());
+		match parse_and_resolve_peer_addr_async(&peer).await {
+			Some((pubkey, addrs)) => {
+				info!("Found node {} in DNS", peer);
+				ret.extend(addrs.into_iter().map(|addr| (pubkey, addr)));
+			}
+			None => {
+				warn!("Unable to parse and/or resolve peer hostname {}", peer);
+			}
+		}
+	}
+
+	Ok(ret)
+}
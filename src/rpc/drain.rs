@@ -0,0 +1,51 @@
+//! Tracking of the RPC handlers running on this node, so that they can be
+//! given some time to complete when the node shuts down
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static RUNNING_RPC_HANDLERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Marks an incoming RPC as being handled, until it is dropped.
+/// RPC handlers hold one for the whole duration of their processing.
+pub struct RunningRpcHandler(());
+
+impl RunningRpcHandler {
+	pub fn start() -> Self {
+		RUNNING_RPC_HANDLERS.fetch_add(1, Ordering::SeqCst);
+		Self(())
+	}
+}
+
+impl Drop for RunningRpcHandler {
+	fn drop(&mut self) {
+		RUNNING_RPC_HANDLERS.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// Number of RPC handlers currently running on this node
+pub fn running_rpc_handlers() -> usize {
+	RUNNING_RPC_HANDLERS.load(Ordering::SeqCst)
+}
+
+/// Wait for running RPC handlers to complete, for at most `timeout`.
+/// Returns the number of handlers that are still running.
+pub async fn drain_rpc_handlers(timeout: Duration) -> usize {
+	let deadline = Instant::now() + timeout;
+	while running_rpc_handlers() > 0 && Instant::now() < deadline {
+		tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+	}
+	running_rpc_handlers()
+}
+
+/// Same as `drain_rpc_handlers`, but blocks the current thread.
+/// Used when the node is about to abort, e.g. after a panic.
+pub fn drain_rpc_handlers_blocking(timeout: Duration) -> usize {
+	let deadline = Instant::now() + timeout;
+	while running_rpc_handlers() > 0 && Instant::now() < deadline {
+		std::thread::sleep(DRAIN_POLL_INTERVAL);
+	}
+	running_rpc_handlers()
+}
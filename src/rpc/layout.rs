@@ -1,15 +1,22 @@
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 use serde::{Deserialize, Serialize};
 
-use garage_util::crdt::{AutoCrdt, Crdt, LwwMap};
+use garage_util::crdt::{AutoCrdt, Crdt, Lww, LwwMap};
 use garage_util::data::*;
 use garage_util::encode::nonversioned_encode;
 use garage_util::error::*;
 
 use crate::ring::*;
 
+/// Number of previous versions of the cluster layout kept in its history
+pub const LAYOUT_HISTORY_LEN: usize = 5;
+
+/// Lines of text describing the result of a layout computation,
+/// to be displayed to the user
+pub type Message = Vec<String>;
+
 /// The layout of the cluster, i.e. the list of roles
 /// which are assigned to each cluster node
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -17,6 +24,16 @@ pub struct ClusterLayout {
 	pub version: u64,
 
 	pub replication_factor: usize,
+	/// Replication factor of the layout before the replication factor was
+	/// changed, while data is being copied to the nodes that store the
+	/// additional replicas. Nodes keep the data of the partitions they
+	/// no longer store until `finish_replication_factor_change()` is applied.
+	#[serde(default)]
+	pub previous_replication_factor: Option<usize>,
+	/// Minimum number of zones over which the replicas of each partition
+	/// are spread (as many as possible if None)
+	#[serde(default)]
+	pub zone_redundancy: Option<usize>,
 	pub roles: LwwMap<Uuid, NodeRoleV>,
 
 	/// node_id_vec: a vector of node IDs with a role assigned
@@ -30,14 +47,34 @@ pub struct ClusterLayout {
 	/// are indices in node_id_vec
 	#[serde(with = "serde_bytes")]
 	pub ring_assignation_data: Vec<CompactNodeType>,
+	/// node_id_vec and ring_assignation_data of the layout before the
+	/// replication factor was changed: until the change is finished, the
+	/// nodes of both assignations are used for reads and writes
+	#[serde(default)]
+	pub previous_node_id_vec: Vec<Uuid>,
+	#[serde(default, with = "serde_bytes")]
+	pub previous_ring_assignation_data: Vec<CompactNodeType>,
 
 	/// Role changes which are staged for the next version of the layout
 	pub staging: LwwMap<Uuid, NodeRoleV>,
 	pub staging_hash: Hash,
+	/// Change of the replication factor which is staged for the next version,
+	/// the most recent one wins when several are staged concurrently
+	#[serde(default = "default_staging_replication_factor")]
+	pub staging_replication_factor: Lww<Option<usize>>,
+
+	/// Previous versions of the layout (without their own history), oldest
+	/// first, whose role assignation can be restored with `rollback()`
+	#[serde(default)]
+	pub history: VecDeque<ClusterLayout>,
 }
 
 impl garage_util::migrate::InitialFormat for ClusterLayout {}
 
+fn default_staging_replication_factor() -> Lww<Option<usize>> {
+	Lww::raw(0, None)
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct NodeRoleV(pub Option<NodeRole>);
 
@@ -51,12 +88,78 @@ pub struct NodeRole {
 	/// Datacenter at which this entry belong. This information might be used to perform a better
 	/// geodistribution
 	pub zone: String,
+	/// Rack in which the node is located in its zone. Nodes of a zone without
+	/// a rack are considered to each be in their own rack.
+	#[serde(default)]
+	pub rack: Option<String>,
 	/// The (relative) capacity of the node
 	/// If this is set to None, the node does not participate in storing data for the system
 	/// and is only active as an API gateway to other nodes
 	pub capacity: Option<u32>,
-	/// A set of tags to recognize the node
-	pub tags: Vec<String>,
+	/// Tags to recognize the node, as key/value pairs (e.g. `dc=paris`).
+	/// Layouts saved when tags were a list of strings are read with
+	/// `key=value` items as key/value pairs and other items as keys with
+	/// an empty value.
+	#[serde(default, deserialize_with = "deserialize_tags")]
+	pub tags: BTreeMap<String, String>,
+}
+
+/// Parse a tag given as `key=value`, or as `key` for a tag with an empty value
+pub fn parse_tag(tag: &str) -> (String, String) {
+	match tag.split_once('=') {
+		Some((k, v)) => (k.to_string(), v.to_string()),
+		None => (tag.to_string(), String::new()),
+	}
+}
+
+/// Format tags as a comma-separated list of `key=value` (or `key`) items
+pub fn format_tags(tags: &BTreeMap<String, String>) -> String {
+	tags.iter()
+		.map(|(k, v)| match v.as_str() {
+			"" => k.clone(),
+			v => format!("{}={}", k, v),
+		})
+		.collect::<Vec<_>>()
+		.join(",")
+}
+
+fn deserialize_tags<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+	D: serde::Deserializer<'de>,
+{
+	struct TagsVisitor;
+
+	impl<'de> serde::de::Visitor<'de> for TagsVisitor {
+		type Value = BTreeMap<String, String>;
+
+		fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+			write!(f, "a map of tags, or a list of tags")
+		}
+
+		fn visit_seq<A: serde::de::SeqAccess<'de>>(
+			self,
+			mut seq: A,
+		) -> Result<Self::Value, A::Error> {
+			let mut tags = BTreeMap::new();
+			while let Some(tag) = seq.next_element::<String>()? {
+				tags.extend([parse_tag(&tag)]);
+			}
+			Ok(tags)
+		}
+
+		fn visit_map<A: serde::de::MapAccess<'de>>(
+			self,
+			mut map: A,
+		) -> Result<Self::Value, A::Error> {
+			let mut tags = BTreeMap::new();
+			while let Some((k, v)) = map.next_entry::<String, String>()? {
+				tags.insert(k, v);
+			}
+			Ok(tags)
+		}
+	}
+
+	deserializer.deserialize_any(TagsVisitor)
 }
 
 impl NodeRole {
@@ -66,21 +169,36 @@ impl NodeRole {
 			None => "gateway".to_string(),
 		}
 	}
+
+	pub fn rack_string(&self) -> &str {
+		self.rack.as_deref().unwrap_or("")
+	}
+
+	/// Check whether the node was given all of the tags in `tags`, with the same values
+	pub fn has_tags(&self, tags: &HashMap<String, String>) -> bool {
+		tags.iter().all(|(k, v)| self.tags.get(k) == Some(v))
+	}
 }
 
 impl ClusterLayout {
-	pub fn new(replication_factor: usize) -> Self {
+	pub fn new(replication_factor: usize, zone_redundancy: Option<usize>) -> Self {
 		let empty_lwwmap = LwwMap::new();
 		let empty_lwwmap_hash = blake2sum(&nonversioned_encode(&empty_lwwmap).unwrap()[..]);
 
 		ClusterLayout {
 			version: 0,
 			replication_factor,
+			previous_replication_factor: None,
+			zone_redundancy,
 			roles: LwwMap::new(),
 			node_id_vec: Vec::new(),
 			ring_assignation_data: Vec::new(),
+			previous_node_id_vec: Vec::new(),
+			previous_ring_assignation_data: Vec::new(),
 			staging: empty_lwwmap,
 			staging_hash: empty_lwwmap_hash,
+			staging_replication_factor: default_staging_replication_factor(),
+			history: VecDeque::new(),
 		}
 	}
 
@@ -92,8 +210,10 @@ impl ClusterLayout {
 			}
 			Ordering::Equal => {
 				self.staging.merge(&other.staging);
+				self.staging_replication_factor
+					.merge(&other.staging_replication_factor);
 
-				let new_staging_hash = blake2sum(&nonversioned_encode(&self.staging).unwrap()[..]);
+				let new_staging_hash = self.calculate_staging_hash();
 				let changed = new_staging_hash != self.staging_hash;
 
 				self.staging_hash = new_staging_hash;
@@ -104,7 +224,7 @@ impl ClusterLayout {
 		}
 	}
 
-	pub fn apply_staged_changes(mut self, version: Option<u64>) -> Result<Self, Error> {
+	pub fn apply_staged_changes(mut self, version: Option<u64>) -> Result<(Self, Message), Error> {
 		match version {
 			None => {
 				let error = r#"
@@ -120,19 +240,40 @@ To know the correct value of the new layout version, invoke `garage layout show`
 			}
 		}
 
+		self.push_history();
+
+		let staged_replication_factor = self.staged_replication_factor();
+		self.staging_replication_factor.update(None);
+		if let Some(replication_factor) = staged_replication_factor {
+			if replication_factor != self.replication_factor {
+				if let Some(previous) = self.previous_replication_factor {
+					return Err(Error::Message(format!("The replication factor is still being changed from {} to {}, please finish this change before changing it again.", previous, self.replication_factor)));
+				}
+				// Data only has to be copied around if it has already
+				// been assigned to nodes with the previous replication factor
+				if !self.ring_assignation_data.is_empty() {
+					self.previous_replication_factor = Some(self.replication_factor);
+					self.previous_node_id_vec = self.node_id_vec.clone();
+					self.previous_ring_assignation_data = self.ring_assignation_data.clone();
+				}
+				self.replication_factor = replication_factor;
+			}
+		}
+
 		self.roles.merge(&self.staging);
 		self.roles.retain(|(_, _, v)| v.0.is_some());
 
-		if !self.calculate_partition_assignation() {
-			return Err(Error::Message("Could not calculate new assignation of partitions to nodes. This can happen if there are less nodes than the desired number of copies of your data (see the replication_mode configuration parameter).".into()));
-		}
+		let msg = match self.calculate_partition_assignation() {
+			Some(msg) => msg,
+			None => return Err(Error::Message("Could not calculate new assignation of partitions to nodes. This can happen if there are less nodes than the desired number of copies of your data (see the replication_mode configuration parameter).".into())),
+		};
 
 		self.staging.clear();
-		self.staging_hash = blake2sum(&nonversioned_encode(&self.staging).unwrap()[..]);
+		self.staging_hash = self.calculate_staging_hash();
 
 		self.version += 1;
 
-		Ok(self)
+		Ok((self, msg))
 	}
 
 	pub fn revert_staged_changes(mut self, version: Option<u64>) -> Result<Self, Error> {
@@ -152,19 +293,181 @@ To know the correct value of the new layout version, invoke `garage layout show`
 		}
 
 		self.staging.clear();
-		self.staging_hash = blake2sum(&nonversioned_encode(&self.staging).unwrap()[..]);
+		self.staging_replication_factor.update(None);
+		self.staging_hash = self.calculate_staging_hash();
 
 		self.version += 1;
 
 		Ok(self)
 	}
 
+	/// Create a new version of the layout which ends the change of the replication
+	/// factor, once the data of all partitions has been copied to the nodes of the
+	/// new assignation. Nodes then delete the data of the partitions they no longer store.
+	pub fn finish_replication_factor_change(mut self, version: Option<u64>) -> Result<Self, Error> {
+		match version {
+			None => {
+				let error = r#"
+Please pass the new layout version number to ensure that you are writing the correct version of the cluster layout.
+To know the correct value of the new layout version, invoke `garage layout show` and review the proposed changes.
+				"#;
+				return Err(Error::Message(error.into()));
+			}
+			Some(v) => {
+				if v != self.version + 1 {
+					return Err(Error::Message("Invalid new layout version".into()));
+				}
+			}
+		}
+
+		if self.previous_replication_factor.take().is_none() {
+			return Err(Error::Message(
+				"The replication factor of the cluster is not being changed.".into(),
+			));
+		}
+		self.previous_node_id_vec.clear();
+		self.previous_ring_assignation_data.clear();
+
+		self.version += 1;
+
+		Ok(self)
+	}
+
+	/// Create a new version of the layout that restores the roles and the
+	/// assignation of partitions of a previous version kept in the history.
+	/// Nodes only adopt layouts with a higher version number than theirs,
+	/// so the restored layout is given the next version number.
+	pub fn rollback(mut self, version: u64) -> Result<Self, Error> {
+		if self.has_staged_changes() {
+			return Err(Error::Message("There are staged role changes in the cluster layout, please apply or revert them before rolling back.".into()));
+		}
+		if let Some(previous) = self.previous_replication_factor {
+			return Err(Error::Message(format!("The replication factor is still being changed from {} to {}, please finish this change before rolling back.", previous, self.replication_factor)));
+		}
+
+		let old = match self.history.iter().find(|l| l.version == version) {
+			Some(old) => old.clone(),
+			None => {
+				return Err(Error::Message(format!(
+					"Layout version {} is not in the history of the cluster layout (available versions: {})",
+					version,
+					self.history_versions()
+				)))
+			}
+		};
+
+		self.push_history();
+
+		if old.replication_factor != self.replication_factor {
+			self.previous_replication_factor = Some(self.replication_factor);
+			self.previous_node_id_vec = self.node_id_vec.clone();
+			self.previous_ring_assignation_data = self.ring_assignation_data.clone();
+			self.replication_factor = old.replication_factor;
+		}
+		self.roles = old.roles;
+		self.node_id_vec = old.node_id_vec;
+		self.ring_assignation_data = old.ring_assignation_data;
+		self.version += 1;
+
+		Ok(self)
+	}
+
+	/// Returns true if role changes or a change of the replication
+	/// factor are staged for the next version of the layout
+	pub fn has_staged_changes(&self) -> bool {
+		!self.staging.items().is_empty() || self.staged_replication_factor().is_some()
+	}
+
+	/// Replication factor staged for the next version of the layout, if any
+	pub fn staged_replication_factor(&self) -> Option<usize> {
+		*self.staging_replication_factor.get()
+	}
+
+	/// Stage a change of the replication factor for the next version of the layout
+	pub fn stage_replication_factor(&mut self, replication_factor: usize) {
+		self.staging_replication_factor
+			.update(Some(replication_factor));
+		self.staging_hash = self.calculate_staging_hash();
+	}
+
+	/// Hash of the staged changes, which nodes advertise to each other
+	/// to find out that they need to exchange their staged changes
+	pub(crate) fn calculate_staging_hash(&self) -> Hash {
+		match self.staged_replication_factor() {
+			None => blake2sum(&nonversioned_encode(&self.staging).unwrap()[..]),
+			Some(_) => blake2sum(
+				&nonversioned_encode(&(&self.staging, &self.staging_replication_factor)).unwrap()[..],
+			),
+		}
+	}
+
+	/// Versions of the layout kept in the history, as a human-readable list
+	pub fn history_versions(&self) -> String {
+		self.history
+			.iter()
+			.map(|l| l.version.to_string())
+			.collect::<Vec<_>>()
+			.join(", ")
+	}
+
+	/// Save the current version of the layout in the history before
+	/// it is replaced by a new version
+	fn push_history(&mut self) {
+		if self.ring_assignation_data.is_empty() {
+			// No assignation to restore in this version
+			return;
+		}
+
+		let history = std::mem::take(&mut self.history);
+		let current = self.clone();
+		self.history = history;
+
+		self.history.push_back(current);
+		while self.history.len() > LAYOUT_HISTORY_LEN {
+			self.history.pop_front();
+		}
+	}
+
 	/// Returns a list of IDs of nodes that currently have
 	/// a role in the cluster
 	pub fn node_ids(&self) -> &[Uuid] {
 		&self.node_id_vec[..]
 	}
 
+	/// Number of partitions stored by each node in the current assignation
+	pub fn partition_counts(&self) -> HashMap<Uuid, usize> {
+		let mut ret = HashMap::new();
+		for i in self.ring_assignation_data.iter() {
+			if let Some(id) = self.node_id_vec.get(*i as usize) {
+				*ret.entry(*id).or_insert(0) += 1;
+			}
+		}
+		ret
+	}
+
+	/// Number of partitions of which a node stores a replica, either in the
+	/// current assignation or, while the replication factor is being changed,
+	/// in the assignation made with the previous replication factor
+	pub fn partitions_stored_by(&self, node: &Uuid) -> usize {
+		let holds = |node_id_vec: &[Uuid], data: &[CompactNodeType], partition: usize| {
+			let rf = data.len() >> PARTITION_BITS;
+			data.get(partition * rf..(partition + 1) * rf)
+				.unwrap_or_default()
+				.iter()
+				.any(|i| node_id_vec.get(*i as usize) == Some(node))
+		};
+		(0..(1 << PARTITION_BITS))
+			.filter(|p| {
+				holds(&self.node_id_vec, &self.ring_assignation_data, *p)
+					|| holds(
+						&self.previous_node_id_vec,
+						&self.previous_ring_assignation_data,
+						*p,
+					)
+			})
+			.count()
+	}
+
 	pub fn num_nodes(&self) -> usize {
 		self.node_id_vec.len()
 	}
@@ -178,12 +481,13 @@ To know the correct value of the new layout version, invoke `garage layout show`
 	}
 
 	/// Check a cluster layout for internal consistency
-	/// returns true if consistent, false if error
-	pub fn check(&self) -> bool {
+	/// (assignation, roles, parameters, partition per node ...)
+	/// returns a description of the first inconsistency found, if any
+	pub fn check(&self) -> Result<(), String> {
 		// Check that the hash of the staging data is correct
-		let staging_hash = blake2sum(&nonversioned_encode(&self.staging).unwrap()[..]);
+		let staging_hash = self.calculate_staging_hash();
 		if staging_hash != self.staging_hash {
-			return false;
+			return Err("staging_hash is incorrect".into());
 		}
 
 		// Check that node_id_vec contains the correct list of nodes
@@ -198,12 +502,18 @@ To know the correct value of the new layout version, invoke `garage layout show`
 		let mut node_id_vec = self.node_id_vec.clone();
 		node_id_vec.sort();
 		if expected_nodes != node_id_vec {
-			return false;
+			return Err("node_id_vec does not contain the correct set of nodes".into());
 		}
 
 		// Check that the assignation data has the correct length
-		if self.ring_assignation_data.len() != (1 << PARTITION_BITS) * self.replication_factor {
-			return false;
+		let expected_len = (1 << PARTITION_BITS) * self.replication_factor;
+		if self.ring_assignation_data.len() != expected_len {
+			return Err(format!(
+				"ring_assignation_data has incorrect length {} instead of {} (no layout has been applied yet, or not enough storage nodes for replication factor {})",
+				self.ring_assignation_data.len(),
+				expected_len,
+				self.replication_factor
+			));
 		}
 
 		// Check that the assigned nodes are correct identifiers
@@ -211,25 +521,224 @@ To know the correct value of the new layout version, invoke `garage layout show`
 		// and that role is not the role of a gateway nodes
 		for x in self.ring_assignation_data.iter() {
 			if *x as usize >= self.node_id_vec.len() {
-				return false;
+				return Err(format!(
+					"ring_assignation_data contains invalid node index {}",
+					*x
+				));
 			}
 			let node = self.node_id_vec[*x as usize];
 			match self.roles.get(&node) {
 				Some(NodeRoleV(Some(x))) if x.capacity.is_some() => (),
-				_ => return false,
+				_ => {
+					return Err(format!(
+						"partition is assigned to node {:?}, which is not a storage node",
+						node
+					))
+				}
 			}
 		}
 
-		true
+		// Check that each partition is spread over as many different zones
+		// as required by the zone redundancy, given the replication factor,
+		// and that inside each zone it is spread over as many racks as possible
+		let (configured_nodes, zones) = self.configured_nodes_and_zones();
+		let expected_zones = self.target_zones(zones.len());
+		let zone_racks = racks_per_zone(&configured_nodes);
+		for (i, part) in self
+			.ring_assignation_data
+			.chunks(self.replication_factor)
+			.enumerate()
+		{
+			let part_zones = part
+				.iter()
+				.filter_map(|x| self.node_role(&self.node_id_vec[*x as usize]))
+				.map(|role| role.zone.as_str())
+				.collect::<HashSet<&str>>();
+			if part_zones.len() < expected_zones {
+				return Err(format!(
+					"partition {} is stored in {} zone(s) ({}), expected {} different zones for replication factor {} and zone redundancy {}",
+					i,
+					part_zones.len(),
+					part_zones.into_iter().collect::<Vec<_>>().join(", "),
+					expected_zones,
+					self.replication_factor,
+					self.zone_redundancy_string()
+				));
+			}
+
+			let part_nodes = part
+				.iter()
+				.filter_map(|x| {
+					let id = &self.node_id_vec[*x as usize];
+					self.node_role(id).map(|role| (id, role))
+				})
+				.collect::<Vec<_>>();
+			for zone in part_zones.iter() {
+				let in_zone = part_nodes
+					.iter()
+					.filter(|(_, role)| role.zone == *zone)
+					.collect::<Vec<_>>();
+				let used_racks = count_racks(in_zone.iter().map(|(id, role)| (*id, *role)));
+				let expected_racks =
+					std::cmp::min(in_zone.len(), zone_racks.get(zone).cloned().unwrap_or(0));
+				if used_racks < expected_racks {
+					return Err(format!(
+						"partition {} is stored on {} node(s) of zone {} in only {} rack(s), expected {} different racks",
+						i,
+						in_zone.len(),
+						zone,
+						used_racks,
+						expected_racks
+					));
+				}
+			}
+		}
+
+		Ok(())
 	}
 
-	/// Calculate an assignation of partitions to nodes
-	pub fn calculate_partition_assignation(&mut self) -> bool {
+	/// Move partitions between nodes of the current layout so that they are
+	/// spread more evenly according to node capacities, without changing node roles.
+	/// Partitions are moved until the layout reaches `target_uniformity`
+	/// (see `uniformity()`), or until no more progress can be made.
+	pub fn rebalance(
+		mut self,
+		version: Option<u64>,
+		target_uniformity: f32,
+	) -> Result<(Self, Message), Error> {
+		match version {
+			None => {
+				let error = r#"
+Please pass the new layout version number to ensure that you are writing the correct version of the cluster layout.
+To know the correct value of the new layout version, invoke `garage layout rebalance` without the --version flag.
+				"#;
+				return Err(Error::Message(error.into()));
+			}
+			Some(v) => {
+				if v != self.version + 1 {
+					return Err(Error::Message("Invalid new layout version".into()));
+				}
+			}
+		}
+
+		if self.has_staged_changes() {
+			return Err(Error::Message("There are staged role changes in the cluster layout, please apply or revert them before rebalancing.".into()));
+		}
+		if !(0.0..=1.0).contains(&target_uniformity) {
+			return Err(Error::Message(
+				"Target uniformity must be between 0 and 1".into(),
+			));
+		}
+
+		self.push_history();
+
+		let msg = self
+			.calculate_partition_assignation_with_target(Some(target_uniformity))
+			.ok_or_else(|| {
+				Error::Message("Could not calculate new assignation of partitions to nodes.".into())
+			})?;
+
+		self.version += 1;
+
+		Ok((self, msg))
+	}
+
+	/// Returns how evenly partitions are spread over storage nodes, relative to
+	/// their capacity, as the smallest ratio of the number of partitions stored by
+	/// a node to the number of partitions it should ideally store (capped to 1).
+	/// A value of 1 means that all nodes store at least their fair share of data.
+	/// Returns None if no partitions are assigned yet.
+	pub fn uniformity(&self) -> Option<f32> {
+		if self.check().is_err() {
+			return None;
+		}
+		let (configured_nodes, _) = self.configured_nodes_and_zones();
+		let partitions = self.parse_assignation_data();
+		Some(uniformity(
+			&self.target_partitions_per_node(&configured_nodes),
+			&self.partitions_per_node(&partitions[..]),
+		))
+	}
+
+	/// Analyze how the replicas of each partition are spread over zones.
+	/// Ideally, all zones store the same number of replicas and the replicas of
+	/// a partition are spread as evenly as possible over all zones.
+	/// The layout does not need to pass `check()`, so that it can be called on a
+	/// layout with staged changes applied and a newly calculated assignation.
+	/// Returns None if no partitions are assigned yet.
+	pub fn zone_balance(&self) -> Option<ZoneBalanceReport> {
+		let partitions = self.parse_assignation_data();
+		if partitions.iter().all(|p| p.nodes.is_empty()) {
+			return None;
+		}
 		let (configured_nodes, zones) = self.configured_nodes_and_zones();
-		let n_zones = zones.len();
 
-		println!("Calculating updated partition assignation, this may take some time...");
-		println!();
+		let mut zones = zones
+			.into_iter()
+			.map(|zone| {
+				let storage_nodes = configured_nodes
+					.iter()
+					.filter(|(_, info)| info.zone == zone && info.capacity.is_some())
+					.map(|(_, info)| info.capacity.unwrap() as u64)
+					.collect::<Vec<_>>();
+				ZoneBalance {
+					zone: zone.to_string(),
+					nodes: storage_nodes.len(),
+					capacity: storage_nodes.iter().sum(),
+					replicas: 0,
+				}
+			})
+			.collect::<Vec<_>>();
+		zones.sort_by(|a, b| a.zone.cmp(&b.zone));
+
+		let mut imbalanced_partitions = 0;
+		for p in partitions.iter() {
+			let mut replicas_per_zone = vec![0usize; zones.len()];
+			for (_, info) in p.nodes.iter() {
+				let zone_i = info.and_then(|info| zones.iter().position(|z| z.zone == info.zone));
+				if let Some(i) = zone_i {
+					replicas_per_zone[i] += 1;
+				}
+			}
+			for (z, n) in zones.iter_mut().zip(replicas_per_zone.iter()) {
+				z.replicas += n;
+			}
+			// The replicas of a partition are evenly spread if no zone
+			// stores more than one replica more than any other zone
+			let max = replicas_per_zone.iter().max().cloned().unwrap_or(0);
+			let min = replicas_per_zone.iter().min().cloned().unwrap_or(0);
+			if max > min + 1 {
+				imbalanced_partitions += 1;
+			}
+		}
+
+		Some(ZoneBalanceReport {
+			zones,
+			partitions: partitions.len(),
+			imbalanced_partitions,
+		})
+	}
+
+	/// Calculate an assignation of partitions to nodes, returning a report of
+	/// the changes to display to the user, or None if there are not enough
+	/// storage nodes for the replication factor
+	pub fn calculate_partition_assignation(&mut self) -> Option<Message> {
+		self.calculate_partition_assignation_with_target(None)
+	}
+
+	/// Calculate an assignation of partitions to nodes, stopping to move
+	/// partitions around once the target uniformity is reached, if specified
+	fn calculate_partition_assignation_with_target(
+		&mut self,
+		target_uniformity: Option<f32>,
+	) -> Option<Message> {
+		let mut msg = Message::new();
+		let (configured_nodes, zones) = self.configured_nodes_and_zones();
+		let target_zones = self.target_zones(zones.len());
+		let zone_racks = racks_per_zone(&configured_nodes);
+
+		msg.push("Calculating updated partition assignation, this may take some time...".into());
+		msg.push(String::new());
 
 		// Get old partition assignation
 		let old_partitions = self.parse_assignation_data();
@@ -241,8 +750,11 @@ To know the correct value of the new layout version, invoke `garage layout show`
 				let mut new_part = PartitionAss::new();
 				for node in old_part.nodes.iter() {
 					if let Some(role) = node.1 {
-						if role.capacity.is_some() {
-							new_part.add(None, n_zones, node.0, role);
+						// If the replication factor has been decreased,
+						// only some of the old nodes can be kept
+						if role.capacity.is_some() && new_part.nodes.len() < self.replication_factor
+						{
+							new_part.add(None, target_zones, &zone_racks, node.0, role);
 						}
 					}
 				}
@@ -260,7 +772,7 @@ To know the correct value of the new layout version, invoke `garage layout show`
 					for _ in 0..2 {
 						for (id, info) in ipart.nodes.iter() {
 							if part.nodes.len() < self.replication_factor {
-								part.add(None, n_zones, id, info.unwrap());
+								part.add(None, target_zones, &zone_racks, id, info.unwrap());
 							}
 						}
 					}
@@ -269,46 +781,41 @@ To know the correct value of the new layout version, invoke `garage layout show`
 			}
 			None => {
 				// Not enough nodes in cluster to build a correct assignation.
-				// Signal it by returning an error.
-				return false;
+				// Signal it by returning no message.
+				return None;
 			}
 		}
 
 		// Calculate how many partitions each node should ideally store,
 		// and how many partitions they are storing with the current assignation
 		// This defines our target for which we will optimize in the following loop.
-		let total_capacity = configured_nodes
-			.iter()
-			.map(|(_, info)| info.capacity.unwrap_or(0))
-			.sum::<u32>() as usize;
-		let total_partitions = self.replication_factor * (1 << PARTITION_BITS);
-		let target_partitions_per_node = configured_nodes
-			.iter()
-			.map(|(id, info)| {
-				(
-					*id,
-					info.capacity.unwrap_or(0) as usize * total_partitions / total_capacity,
-				)
-			})
-			.collect::<HashMap<&Uuid, usize>>();
+		let target_partitions_per_node = self.target_partitions_per_node(&configured_nodes);
 
 		let mut partitions_per_node = self.partitions_per_node(&partitions[..]);
 
-		println!("Target number of partitions per node:");
+		msg.push("Target number of partitions per node:".into());
 		for (node, npart) in target_partitions_per_node.iter() {
-			println!("{:?}\t{}", node, npart);
+			msg.push(format!("{:?}\t{}", node, npart));
 		}
-		println!();
+		msg.push(String::new());
 
 		// Shuffle partitions between nodes so that nodes will reach (or better approach)
 		// their target number of stored partitions
 		loop {
+			if let Some(target) = target_uniformity {
+				if uniformity(&target_partitions_per_node, &partitions_per_node) >= target {
+					break;
+				}
+			}
+
 			let mut option = None;
 			for (i, part) in partitions.iter_mut().enumerate() {
 				for (irm, (idrm, _)) in part.nodes.iter().enumerate() {
+					// Nodes whose capacity is too small to be given any partition
+					// have a target of zero, which must not make the ratio infinite
 					let errratio = |node, parts| {
 						let tgt = *target_partitions_per_node.get(node).unwrap() as f32;
-						(parts - tgt) / tgt
+						(parts - tgt) / f32::max(tgt, 1.)
 					};
 					let square = |x| x * x;
 
@@ -336,7 +843,7 @@ To know the correct value of the new layout version, invoke `garage layout show`
 						let mut newpart = part.clone();
 
 						newpart.nodes.remove(irm);
-						if !newpart.add(None, n_zones, idadd, infoadd) {
+						if !newpart.add(None, target_zones, &zone_racks, idadd, infoadd) {
 							continue;
 						}
 						assert!(newpart.nodes.len() == self.replication_factor);
@@ -373,17 +880,27 @@ To know the correct value of the new layout version, invoke `garage layout show`
 			.iter()
 			.all(|p| p.nodes.len() == self.replication_factor));
 
+		// (nodes from which all partitions were moved are not counted)
+		let mut counted_partitions_per_node = partitions_per_node.clone();
+		counted_partitions_per_node.retain(|_, n| *n > 0);
 		let new_partitions_per_node = self.partitions_per_node(&partitions[..]);
-		assert!(new_partitions_per_node == partitions_per_node);
+		assert!(new_partitions_per_node == counted_partitions_per_node);
 
 		// Show statistics
-		println!("New number of partitions per node:");
+		msg.push("New number of partitions per node:".into());
 		for (node, npart) in partitions_per_node.iter() {
 			let tgt = *target_partitions_per_node.get(node).unwrap();
 			let pct = 100f32 * (*npart as f32) / (tgt as f32);
-			println!("{:?}\t{}\t({}% of {})", node, npart, pct as i32, tgt);
+			msg.push(format!(
+				"{:?}\t{}\t({}% of {})",
+				node, npart, pct as i32, tgt
+			));
 		}
-		println!();
+		msg.push(format!(
+			"Uniformity: {:.3}",
+			uniformity(&target_partitions_per_node, &partitions_per_node)
+		));
+		msg.push(String::new());
 
 		let mut diffcount = HashMap::new();
 		for (oldpart, newpart) in old_partitions.iter().zip(partitions.iter()) {
@@ -395,16 +912,16 @@ To know the correct value of the new layout version, invoke `garage layout show`
 			}
 		}
 		if diffcount.is_empty() {
-			println!("No data will be moved between nodes.");
+			msg.push("No data will be moved between nodes.".into());
 		} else {
 			let mut diffcount = diffcount.into_iter().collect::<Vec<_>>();
 			diffcount.sort();
-			println!("Number of partitions that move:");
+			msg.push("Number of partitions that move:".into());
 			for ((nminus, nplus), npart) in diffcount {
-				println!("\t{}\t{} -> {}", npart, nminus, nplus);
+				msg.push(format!("\t{}\t{} -> {}", npart, nminus, nplus));
 			}
 		}
-		println!();
+		msg.push(String::new());
 
 		// Calculate and save new assignation data
 		let (nodes, assignation_data) =
@@ -413,12 +930,13 @@ To know the correct value of the new layout version, invoke `garage layout show`
 		self.node_id_vec = nodes;
 		self.ring_assignation_data = assignation_data;
 
-		true
+		Some(msg)
 	}
 
 	fn initial_partition_assignation(&self) -> Option<Vec<PartitionAss<'_>>> {
 		let (configured_nodes, zones) = self.configured_nodes_and_zones();
-		let n_zones = zones.len();
+		let target_zones = self.target_zones(zones.len());
+		let zone_racks = racks_per_zone(&configured_nodes);
 
 		// Create a vector of partition indices (0 to 2**PARTITION_BITS-1)
 		let partitions_idx = (0usize..(1usize << PARTITION_BITS)).collect::<Vec<_>>();
@@ -448,10 +966,10 @@ To know the correct value of the new layout version, invoke `garage layout show`
 			})
 			.collect::<Vec<_>>();
 
-		let max_capacity = configured_nodes
-			.iter()
-			.filter_map(|(_, node_info)| node_info.capacity)
-			.fold(0, std::cmp::max);
+		// Each node is given a number of slots in each round proportional
+		// to its capacity, relative to the smallest capacity in the cluster
+		let weights = node_weights(&configured_nodes);
+		let max_weight = weights.values().cloned().fold(0, std::cmp::max);
 
 		// Fill up ring
 		for rep in 0..self.replication_factor {
@@ -467,13 +985,22 @@ To know the correct value of the new layout version, invoke `garage layout show`
 			let mut remaining = partitions_idx.len();
 			while remaining > 0 {
 				let remaining0 = remaining;
-				for i_round in 0..max_capacity {
+				for i_round in 0..max_weight {
+					if remaining == 0 {
+						break;
+					}
 					for (node_id, node_info, q, pos) in queues.iter_mut() {
-						if i_round >= node_info.capacity.unwrap() {
+						if i_round >= weights[*node_id] {
 							continue;
 						}
 						for (pos2, &qv) in q.iter().enumerate().skip(*pos) {
-							if partitions[qv].add(Some(rep + 1), n_zones, node_id, node_info) {
+							if partitions[qv].add(
+								Some(rep + 1),
+								target_zones,
+								&zone_racks,
+								node_id,
+								node_info,
+							) {
 								remaining -= 1;
 								*pos = pos2 + 1;
 								break;
@@ -491,6 +1018,23 @@ To know the correct value of the new layout version, invoke `garage layout show`
 		Some(partitions)
 	}
 
+	/// Number of different zones over which the replicas of each partition
+	/// must be spread, given the number of zones that contain configured nodes
+	fn target_zones(&self, n_zones: usize) -> usize {
+		let max_zones = std::cmp::min(n_zones, self.replication_factor);
+		match self.zone_redundancy {
+			Some(zr) => std::cmp::min(max_zones, zr),
+			None => max_zones,
+		}
+	}
+
+	pub fn zone_redundancy_string(&self) -> String {
+		match self.zone_redundancy {
+			Some(zr) => format!("{}", zr),
+			None => "maximum".to_string(),
+		}
+	}
+
 	fn configured_nodes_and_zones(&self) -> (Vec<(&Uuid, &NodeRole)>, HashSet<&str>) {
 		let configured_nodes = self
 			.roles
@@ -547,13 +1091,19 @@ To know the correct value of the new layout version, invoke `garage layout show`
 	}
 
 	fn parse_assignation_data(&self) -> Vec<PartitionAss<'_>> {
-		if self.ring_assignation_data.len() == self.replication_factor * (1 << PARTITION_BITS) {
+		// The assignation data might have been calculated for a
+		// different replication factor than the current one
+		// if it has just been changed
+		let replication_factor = self.ring_assignation_data.len() >> PARTITION_BITS;
+		if replication_factor > 0
+			&& self.ring_assignation_data.len() == replication_factor * (1 << PARTITION_BITS)
+		{
 			// If the previous assignation data is correct, use that
 			let mut partitions = vec![];
 			for i in 0..(1 << PARTITION_BITS) {
 				let mut part = PartitionAss::new();
 				for node_i in self.ring_assignation_data
-					[i * self.replication_factor..(i + 1) * self.replication_factor]
+					[i * replication_factor..(i + 1) * replication_factor]
 					.iter()
 				{
 					let node_id = &self.node_id_vec[*node_i as usize];
@@ -575,6 +1125,29 @@ To know the correct value of the new layout version, invoke `garage layout show`
 		}
 	}
 
+	/// Number of partitions each node should ideally store, given its capacity
+	fn target_partitions_per_node<'a>(
+		&self,
+		configured_nodes: &[(&'a Uuid, &'a NodeRole)],
+	) -> HashMap<&'a Uuid, usize> {
+		let total_capacity = configured_nodes
+			.iter()
+			.map(|(_, info)| info.capacity.unwrap_or(0) as usize)
+			.sum::<usize>();
+		let total_partitions = self.replication_factor * (1 << PARTITION_BITS);
+		configured_nodes
+			.iter()
+			.map(|(id, info)| {
+				(
+					*id,
+					(info.capacity.unwrap_or(0) as usize * total_partitions)
+						.checked_div(total_capacity)
+						.unwrap_or(0),
+				)
+			})
+			.collect::<HashMap<&Uuid, usize>>()
+	}
+
 	fn partitions_per_node<'a>(&self, partitions: &[PartitionAss<'a>]) -> HashMap<&'a Uuid, usize> {
 		let mut partitions_per_node = HashMap::<&Uuid, usize>::new();
 		for p in partitions.iter() {
@@ -586,6 +1159,133 @@ To know the correct value of the new layout version, invoke `garage layout show`
 	}
 }
 
+/// Weight of each storage node in the initial partition assignation:
+/// its capacity divided by the smallest non-zero capacity of a storage node,
+/// rounded down, and at most the number of partitions, as a node can not be
+/// given more than one replica of each partition in a round.
+/// Exact proportions are then approached when optimizing the assignation.
+fn node_weights<'a>(configured_nodes: &[(&'a Uuid, &NodeRole)]) -> HashMap<&'a Uuid, usize> {
+	let min_capacity = configured_nodes
+		.iter()
+		.filter_map(|(_, info)| info.capacity)
+		.filter(|c| *c > 0)
+		.min()
+		.unwrap_or(1);
+	configured_nodes
+		.iter()
+		.filter_map(|(id, info)| {
+			let weight = (info.capacity? / min_capacity) as usize;
+			Some((*id, std::cmp::min(weight, 1 << PARTITION_BITS)))
+		})
+		.collect()
+}
+
+fn uniformity(target: &HashMap<&Uuid, usize>, actual: &HashMap<&Uuid, usize>) -> f32 {
+	target
+		.iter()
+		.filter(|(_, tgt)| **tgt > 0)
+		.map(|(node, tgt)| {
+			let npart = actual.get(node).cloned().unwrap_or(0);
+			f32::min(1.0, npart as f32 / *tgt as f32)
+		})
+		.fold(1.0, f32::min)
+}
+
+/// Number of racks of each zone among the storage nodes
+fn racks_per_zone<'a>(configured_nodes: &[(&'a Uuid, &'a NodeRole)]) -> HashMap<&'a str, usize> {
+	let mut nodes_per_zone = HashMap::<&str, Vec<(&Uuid, &NodeRole)>>::new();
+	for (id, role) in configured_nodes.iter() {
+		if role.capacity.is_some() {
+			nodes_per_zone
+				.entry(role.zone.as_str())
+				.or_default()
+				.push((id, role));
+		}
+	}
+	nodes_per_zone
+		.into_iter()
+		.map(|(zone, nodes)| (zone, count_racks(nodes.into_iter())))
+		.collect()
+}
+
+/// Number of different racks of a set of nodes,
+/// where each node without a rack counts as its own rack
+fn count_racks<'a>(nodes: impl Iterator<Item = (&'a Uuid, &'a NodeRole)>) -> usize {
+	let mut racks = HashSet::new();
+	let mut no_rack = 0;
+	for (_, role) in nodes {
+		match &role.rack {
+			Some(rack) => {
+				racks.insert(rack.as_str());
+			}
+			None => no_rack += 1,
+		}
+	}
+	racks.len() + no_rack
+}
+
+// ---- Analysis of the spread of replicas over zones ----
+
+/// How the replicas of all partitions are spread over zones,
+/// as computed by `ClusterLayout::zone_balance()`
+#[derive(Clone, Debug)]
+pub struct ZoneBalanceReport {
+	/// Storage zones of the layout, sorted by name
+	pub zones: Vec<ZoneBalance>,
+	/// Total number of partitions
+	pub partitions: usize,
+	/// Number of partitions whose replicas are not evenly spread over zones
+	pub imbalanced_partitions: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct ZoneBalance {
+	pub zone: String,
+	/// Number of storage nodes in the zone
+	pub nodes: usize,
+	/// Total capacity of the storage nodes in the zone
+	pub capacity: u64,
+	/// Number of partition replicas stored in the zone
+	pub replicas: usize,
+}
+
+impl ZoneBalanceReport {
+	/// Number of replicas each zone would store if they were equally represented
+	pub fn ideal_replicas(&self) -> f32 {
+		let total = self.zones.iter().map(|z| z.replicas).sum::<usize>();
+		total as f32 / self.zones.len() as f32
+	}
+
+	/// Capacity each zone should have for all zones to be equally represented,
+	/// keeping the total capacity of the cluster unchanged
+	pub fn ideal_capacity(&self) -> u64 {
+		let total = self.zones.iter().map(|z| z.capacity).sum::<u64>();
+		total / self.zones.len() as u64
+	}
+
+	/// Percentage of partitions whose replicas are not evenly spread over zones
+	pub fn imbalanced_percentage(&self) -> f32 {
+		100. * self.imbalanced_partitions as f32 / self.partitions as f32
+	}
+
+	/// Whether zones store a number of replicas that is within the given
+	/// ratio of the ideal number of replicas, and all partitions are evenly spread
+	pub fn is_balanced(&self, tolerance: f32) -> bool {
+		self.imbalanced_partitions == 0
+			&& self
+				.zones
+				.iter()
+				.all(|z| self.replicas_deviation(z).abs() <= tolerance)
+	}
+
+	/// Relative difference between the number of replicas stored in a zone
+	/// and the ideal number of replicas (positive if over-represented)
+	pub fn replicas_deviation(&self, zone: &ZoneBalance) -> f32 {
+		let ideal = self.ideal_replicas();
+		(zone.replicas as f32 - ideal) / ideal
+	}
+}
+
 // ---- Internal structs for partition assignation in layout ----
 
 #[derive(Clone)]
@@ -634,15 +1334,19 @@ impl<'a> PartitionAss<'a> {
 	// to which a partition is assigned. It tries to add a certain node id to the
 	// assignation, but checks that doing so is compatible with the NECESSARY
 	// condition that the partition assignation must be dispersed over different
-	// zones (datacenters) if enough zones exist. This is why it takes a n_zones
-	// parameter, which is the total number of zones that have existing nodes:
-	// if nodes in the assignation already cover all n_zones zones, then any node
-	// that is not yet in the assignation can be added. Otherwise, only nodes
-	// that are in a new zone can be added.
+	// zones (datacenters) if enough zones exist. This is why it takes a target_zones
+	// parameter, which is the number of zones the partition must be spread over
+	// (see ClusterLayout::target_zones): if nodes in the assignation already cover
+	// target_zones zones, then any node that is not yet in the assignation can be
+	// added, as long as it does not share a rack with another node of its zone in
+	// the assignation while other racks of that zone are unused (zone_racks gives
+	// the number of racks of each zone). Otherwise, only nodes that are in a new
+	// zone can be added.
 	fn add(
 		&mut self,
 		target_len: Option<usize>,
-		n_zones: usize,
+		target_zones: usize,
+		zone_racks: &HashMap<&str, usize>,
 		node: &'a Uuid,
 		role: &'a NodeRole,
 	) -> bool {
@@ -657,8 +1361,10 @@ impl<'a> PartitionAss<'a> {
 			.iter()
 			.map(|(_id, info)| info.unwrap().zone.as_str())
 			.collect::<HashSet<&str>>();
-		if (p_zns.len() < n_zones && !p_zns.contains(&role.zone.as_str()))
-			|| (p_zns.len() == n_zones && !self.nodes.iter().any(|(id, _)| *id == node))
+		if (p_zns.len() < target_zones && !p_zns.contains(&role.zone.as_str()))
+			|| (p_zns.len() >= target_zones
+				&& !self.nodes.iter().any(|(id, _)| *id == node)
+				&& self.rack_available(zone_racks, role))
 		{
 			self.nodes.push((node, Some(role)));
 			true
@@ -666,4 +1372,498 @@ impl<'a> PartitionAss<'a> {
 			false
 		}
 	}
+
+	// Checks that a node with the given role can be added without storing two
+	// replicas in the same rack of a zone while another rack of that zone is unused
+	fn rack_available(&self, zone_racks: &HashMap<&str, usize>, role: &NodeRole) -> bool {
+		let rack = match &role.rack {
+			Some(rack) => rack,
+			None => return true,
+		};
+		let in_zone = self
+			.nodes
+			.iter()
+			.filter_map(|(id, info)| Some((*id, (*info)?)))
+			.filter(|(_, info)| info.zone == role.zone)
+			.collect::<Vec<_>>();
+		if !in_zone
+			.iter()
+			.any(|(_, info)| info.rack.as_ref() == Some(rack))
+		{
+			return true;
+		}
+		let n_racks = zone_racks.get(role.zone.as_str()).cloned().unwrap_or(0);
+		count_racks(in_zone.into_iter()) >= n_racks
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assign(layout: &mut ClusterLayout, node: u8, zone: &str, capacity: u32) {
+		let mut id = [0u8; 32];
+		id[0] = node;
+		layout.staging.merge(&LwwMap::raw_item(
+			id.into(),
+			1,
+			NodeRoleV(Some(NodeRole {
+				zone: zone.to_string(),
+				rack: None,
+				capacity: Some(capacity),
+				tags: BTreeMap::new(),
+			})),
+		));
+	}
+
+	fn set_rack(roles: &mut LwwMap<Uuid, NodeRoleV>, node: u8, rack: &str) {
+		let mut id = [0u8; 32];
+		id[0] = node;
+		let mut role = roles.get(&id.into()).unwrap().0.clone().unwrap();
+		role.rack = Some(rack.to_string());
+		roles.merge(&roles.update_mutator(id.into(), NodeRoleV(Some(role))));
+	}
+
+	#[test]
+	fn test_node_role_tags() {
+		use garage_util::encode::nonversioned_decode;
+
+		// Roles saved when tags were a list of strings
+		#[derive(Serialize)]
+		struct OldNodeRole {
+			zone: String,
+			capacity: Option<u32>,
+			tags: Vec<String>,
+		}
+		let old = OldNodeRole {
+			zone: "a".into(),
+			capacity: Some(100),
+			tags: vec!["dc=paris".into(), "fast".into()],
+		};
+		let expected = BTreeMap::from([
+			("dc".to_string(), "paris".to_string()),
+			("fast".to_string(), String::new()),
+		]);
+		let role: NodeRole = nonversioned_decode(&nonversioned_encode(&old).unwrap()).unwrap();
+		assert_eq!(role.tags, expected);
+		let role: NodeRole = serde_json::from_str(&serde_json::to_string(&old).unwrap()).unwrap();
+		assert_eq!(role.tags, expected);
+		assert_eq!(format_tags(&role.tags), "dc=paris,fast");
+
+		let role: NodeRole = nonversioned_decode(&nonversioned_encode(&role).unwrap()).unwrap();
+		assert_eq!(role.tags, expected);
+		let role: NodeRole = serde_json::from_str(r#"{"zone":"a","capacity":null}"#).unwrap();
+		assert!(role.tags.is_empty());
+
+		let role = NodeRole {
+			tags: expected,
+			..role
+		};
+		let preferred = |tags: &[(&str, &str)]| {
+			tags.iter()
+				.map(|(k, v)| (k.to_string(), v.to_string()))
+				.collect::<HashMap<_, _>>()
+		};
+		assert!(role.has_tags(&preferred(&[])));
+		assert!(role.has_tags(&preferred(&[("dc", "paris")])));
+		assert!(role.has_tags(&preferred(&[("dc", "paris"), ("fast", "")])));
+		assert!(!role.has_tags(&preferred(&[("dc", "london")])));
+		assert!(!role.has_tags(&preferred(&[("dc", "paris"), ("arch", "arm64")])));
+	}
+
+	#[test]
+	fn test_rebalance() {
+		let mut layout = ClusterLayout::new(3, None);
+		for (node, zone) in [(1, "a"), (2, "b"), (3, "c"), (4, "a")] {
+			assign(&mut layout, node, zone, 100);
+		}
+		let mut layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+		let node_id = |node: u8| {
+			let mut id = [0u8; 32];
+			id[0] = node;
+			Uuid::from(id)
+		};
+		let index = |layout: &ClusterLayout, node: u8| {
+			layout
+				.node_id_vec
+				.iter()
+				.position(|id| *id == node_id(node))
+				.unwrap() as CompactNodeType
+		};
+
+		// Make the layout imbalanced: node 1 stores all the replicas of zone a
+		let (idx1, idx4) = (index(&layout, 1), index(&layout, 4));
+		for x in layout.ring_assignation_data.iter_mut() {
+			if *x == idx4 {
+				*x = idx1;
+			}
+		}
+		assert!(layout.check().is_ok());
+		assert_eq!(layout.uniformity(), Some(0.0));
+
+		// Version number must be given and correct
+		assert!(layout.clone().rebalance(None, 0.9).is_err());
+		assert!(layout.clone().rebalance(Some(3), 0.9).is_err());
+		assert!(layout.clone().rebalance(Some(2), 1.5).is_err());
+
+		let rebalanced = layout.clone().rebalance(Some(2), 1.0).unwrap().0;
+		assert_eq!(rebalanced.version, 2);
+		assert!(rebalanced.check().is_ok());
+		// Nodes 1 and 4 can store at most one replica of each partition
+		// between them, which is less than their share of the capacity
+		assert_eq!(rebalanced.uniformity(), Some(2.0 / 3.0));
+
+		// Only replicas of node 1 were moved, and all of them to node 4
+		let nodes_of = |layout: &ClusterLayout, i: usize| {
+			let n = layout.replication_factor;
+			layout.ring_assignation_data[i * n..(i + 1) * n]
+				.iter()
+				.map(|x| layout.node_id_vec[*x as usize])
+				.collect::<HashSet<_>>()
+		};
+		let mut moved = 0;
+		for i in 0..(1 << PARTITION_BITS) {
+			let (before, after) = (nodes_of(&layout, i), nodes_of(&rebalanced, i));
+			if before != after {
+				moved += 1;
+				assert_eq!(before.difference(&after).collect::<Vec<_>>(), [&node_id(1)]);
+				assert_eq!(after.difference(&before).collect::<Vec<_>>(), [&node_id(4)]);
+			}
+		}
+
+		// Nodes 1 and 4 now share the replicas of zone a evenly
+		let counts = rebalanced.partition_counts();
+		let half = 1 << (PARTITION_BITS - 1);
+		assert_eq!(moved, half);
+		assert_eq!(counts.get(&node_id(1)), Some(&half));
+		assert_eq!(counts.get(&node_id(4)), Some(&half));
+		assert_eq!(counts.get(&node_id(2)), Some(&(1 << PARTITION_BITS)));
+
+		// Partitions stop being moved once the target uniformity is reached
+		let partial = layout.clone().rebalance(Some(2), 0.5).unwrap().0;
+		assert!(partial.uniformity().unwrap() >= 0.5);
+		let moved = partial.partition_counts().get(&node_id(4)).cloned();
+		assert!(moved.unwrap() < half);
+
+		// Rebalancing is refused when role changes are staged
+		let mut staged = layout;
+		assign(&mut staged, 5, "b", 100);
+		assert!(staged.rebalance(Some(2), 0.9).is_err());
+	}
+
+	#[test]
+	fn test_capacity_weights() {
+		// Capacities are large to check that assignation time
+		// does not depend on their absolute values
+		let mut layout = ClusterLayout::new(3, None);
+		for (node, zone, capacity) in [
+			(1, "a", 1_000_000_000),
+			(2, "a", 3_000_000_000),
+			(3, "b", 1_000_000_000),
+			(4, "c", 1_000_000_000),
+		] {
+			assign(&mut layout, node, zone, capacity);
+		}
+		let layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+		assert!(layout.check().is_ok());
+
+		let counts = layout.partition_counts();
+		let count = |node: u8| {
+			let mut id = [0u8; 32];
+			id[0] = node;
+			counts.get(&Uuid::from(id)).cloned().unwrap_or(0)
+		};
+		assert_eq!(count(1) + count(2), 1 << PARTITION_BITS);
+		assert_eq!(count(2), 3 * count(1));
+		assert_eq!(count(3), 1 << PARTITION_BITS);
+		assert_eq!(count(4), 1 << PARTITION_BITS);
+
+		// Extreme ratios are assigned in a bounded number of rounds, and nodes
+		// with a zero capacity (which only the CLI refuses) get no partitions
+		for (small, large) in [(1, 10_000), (1, 4_000_000_000), (0, 1_000)] {
+			let mut layout = ClusterLayout::new(3, None);
+			for (node, zone, capacity) in [
+				(1, "a", small),
+				(2, "a", large),
+				(3, "b", large),
+				(4, "c", large),
+			] {
+				assign(&mut layout, node, zone, capacity);
+			}
+			let layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+			assert!(layout.check().is_ok());
+
+			let counts = layout.partition_counts();
+			let count = |node: u8| {
+				let mut id = [0u8; 32];
+				id[0] = node;
+				counts.get(&Uuid::from(id)).cloned().unwrap_or(0)
+			};
+			assert_eq!(count(1) + count(2), 1 << PARTITION_BITS);
+			assert!(count(1) <= 1);
+			if small == 0 {
+				assert_eq!(count(1), 0);
+			}
+		}
+	}
+
+	#[test]
+	fn test_rollback() {
+		let mut layout = ClusterLayout::new(3, None);
+		for (node, zone) in [(1, "a"), (2, "b"), (3, "c")] {
+			assign(&mut layout, node, zone, 100);
+		}
+		let v1 = layout.apply_staged_changes(Some(1)).unwrap().0;
+		assert!(v1.history.is_empty());
+
+		let mut layout = v1.clone();
+		assign(&mut layout, 4, "a", 100);
+		let v2 = layout.apply_staged_changes(Some(2)).unwrap().0;
+		assert_eq!(v2.history_versions(), "1");
+
+		assert!(v2.clone().rollback(2).is_err());
+		let v3 = v2.rollback(1).unwrap();
+		assert_eq!(v3.version, 3);
+		assert_eq!(v3.node_id_vec, v1.node_id_vec);
+		assert_eq!(v3.ring_assignation_data, v1.ring_assignation_data);
+		assert_eq!(v3.history_versions(), "1, 2");
+		assert!(v3.check().is_ok());
+
+		// Only the last versions are kept
+		let mut layout = v3;
+		for v in 4..(4 + LAYOUT_HISTORY_LEN as u64) {
+			layout = layout.rebalance(Some(v), 1.0).unwrap().0;
+		}
+		assert_eq!(layout.history.len(), LAYOUT_HISTORY_LEN);
+		assert_eq!(layout.history[0].version, 3);
+	}
+
+	#[test]
+	fn test_replication_factor_change() {
+		let mut layout = ClusterLayout::new(2, None);
+		for (node, zone) in [(1, "a"), (2, "b"), (3, "c")] {
+			assign(&mut layout, node, zone, 100);
+		}
+		let v1 = layout.apply_staged_changes(Some(1)).unwrap().0;
+		assert!(v1.previous_replication_factor.is_none());
+
+		// The staged replication factor is propagated with the staged changes
+		let mut staged = v1.clone();
+		staged.stage_replication_factor(3);
+		assert!(staged.has_staged_changes());
+		assert!(staged.check().is_ok());
+		let mut other = v1.clone();
+		assert!(other.merge(&staged));
+		assert_eq!(other.staged_replication_factor(), Some(3));
+		assert!(other.clone().rebalance(Some(2), 0.9).is_err());
+
+		// The last staged replication factor wins, even if it is lower
+		let mut later = other.clone();
+		later.stage_replication_factor(2);
+		let mut merged = staged.clone();
+		assert!(merged.merge(&later));
+		assert_eq!(merged.staged_replication_factor(), Some(2));
+		assert!(!later.merge(&staged));
+		assert_eq!(later.staged_replication_factor(), Some(2));
+
+		// The old replicas of each partition are kept when it is increased
+		let v2 = staged.apply_staged_changes(Some(2)).unwrap().0;
+		assert_eq!(v2.replication_factor, 3);
+		assert_eq!(v2.previous_replication_factor, Some(2));
+		assert_eq!(v2.previous_node_id_vec, v1.node_id_vec);
+		assert_eq!(v2.previous_ring_assignation_data, v1.ring_assignation_data);
+		assert!(!v2.has_staged_changes());
+		assert!(v2.check().is_ok());
+		let old_parts = v1.ring_assignation_data.chunks(2);
+		for (old, new) in old_parts.zip(v2.ring_assignation_data.chunks(3)) {
+			assert!(old.iter().all(|n| new.contains(n)));
+		}
+
+		// It cannot be changed again before the change is finished
+		let mut staged = v2.clone();
+		staged.stage_replication_factor(2);
+		assert!(staged.apply_staged_changes(Some(3)).is_err());
+		assert!(v2.clone().finish_replication_factor_change(None).is_err());
+		let v3 = v2.finish_replication_factor_change(Some(3)).unwrap();
+		assert!(v3.previous_replication_factor.is_none());
+		assert!(v3.previous_ring_assignation_data.is_empty());
+		assert!(v3
+			.clone()
+			.finish_replication_factor_change(Some(4))
+			.is_err());
+
+		// Decreasing it only keeps some of the old replicas
+		let mut staged = v3.clone();
+		staged.stage_replication_factor(2);
+		let v4 = staged.apply_staged_changes(Some(4)).unwrap().0;
+		assert_eq!(v4.replication_factor, 2);
+		assert_eq!(v4.previous_replication_factor, Some(3));
+		assert!(v4.check().is_ok());
+
+		// Nodes keep their old replicas until the change is finished
+		let nodes = v4.node_id_vec.clone();
+		assert!(nodes
+			.iter()
+			.all(|n| v4.partitions_stored_by(n) == 1 << PARTITION_BITS));
+		let v5 = v4
+			.clone()
+			.finish_replication_factor_change(Some(5))
+			.unwrap();
+		let stored = nodes.iter().map(|n| v5.partitions_stored_by(n));
+		assert_eq!(stored.sum::<usize>(), 2 << PARTITION_BITS);
+
+		// Rolling back is not possible before the change is finished,
+		// and rolling back to a layout with another replication factor
+		// is also a change of the replication factor
+		assert!(v4.clone().rollback(3).is_err());
+		let v5 = v4.finish_replication_factor_change(Some(5)).unwrap();
+		let v6 = v5.rollback(3).unwrap();
+		assert_eq!(v6.replication_factor, 3);
+		assert_eq!(v6.previous_replication_factor, Some(2));
+		assert!(v6.check().is_ok());
+	}
+
+	#[test]
+	fn test_zone_balance() {
+		assert!(ClusterLayout::new(3, None).zone_balance().is_none());
+
+		let mut layout = ClusterLayout::new(3, None);
+		for (node, zone) in [(1, "a"), (2, "b"), (3, "c"), (4, "a"), (5, "b"), (6, "c")] {
+			assign(&mut layout, node, zone, 100);
+		}
+		let layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+		let report = layout.zone_balance().unwrap();
+		assert_eq!(report.partitions, 1 << PARTITION_BITS);
+		assert_eq!(report.imbalanced_partitions, 0);
+		assert!(report.is_balanced(0.01));
+		for zone in report.zones.iter() {
+			assert_eq!(zone.nodes, 2);
+			assert_eq!(zone.replicas, 1 << PARTITION_BITS);
+		}
+
+		// With a single node in zone b, it can store at most one replica of
+		// each partition, so zone a is over-represented
+		let mut layout = ClusterLayout::new(3, None);
+		for (node, zone) in [(1, "a"), (2, "a"), (3, "a"), (4, "b")] {
+			assign(&mut layout, node, zone, 100);
+		}
+		let layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+		let report = layout.zone_balance().unwrap();
+		assert_eq!(report.imbalanced_partitions, 0);
+		assert!(!report.is_balanced(0.01));
+		let (a, b) = (&report.zones[0], &report.zones[1]);
+		assert_eq!((a.zone.as_str(), a.capacity), ("a", 300));
+		assert_eq!((b.zone.as_str(), b.capacity), ("b", 100));
+		assert_eq!(b.replicas, 1 << PARTITION_BITS);
+		assert!(report.replicas_deviation(a) > 0.);
+		assert!(report.replicas_deviation(b) < 0.);
+		assert_eq!(report.ideal_capacity(), 200);
+
+		// Staged changes applied in place, as `garage layout balance-zones`
+		// does: the layout does not pass check() but can still be analyzed
+		let mut layout = layout;
+		assign(&mut layout, 5, "b", 100);
+		layout.roles.merge(&layout.staging);
+		assert!(layout.calculate_partition_assignation().is_some());
+		assert!(layout.check().is_err());
+		let report = layout.zone_balance().unwrap();
+		assert_eq!(report.zones[1].nodes, 2);
+		assert_eq!(report.zones[1].capacity, 200);
+
+		// Total capacities that do not fit in a u32
+		let mut layout = ClusterLayout::new(3, None);
+		for (node, zone) in [(1, "a"), (2, "a"), (3, "b"), (4, "c")] {
+			assign(&mut layout, node, zone, u32::MAX);
+		}
+		let layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+		let report = layout.zone_balance().unwrap();
+		assert_eq!(report.zones[0].capacity, 2 * u32::MAX as u64);
+		assert_eq!(report.ideal_capacity(), 4 * u32::MAX as u64 / 3);
+	}
+
+	#[test]
+	fn test_rack_spread() {
+		let mut layout = ClusterLayout::new(3, None);
+		for (node, zone) in [(1, "a"), (2, "a"), (3, "a"), (4, "b"), (5, "b"), (6, "b")] {
+			assign(&mut layout, node, zone, 100);
+		}
+		set_rack(&mut layout.staging, 1, "r1");
+		set_rack(&mut layout.staging, 2, "r1");
+		set_rack(&mut layout.staging, 3, "r2");
+		let mut layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+		assert!(layout.check().is_ok());
+
+		// Each partition has two replicas in one of the two zones, which are
+		// never both in rack r1 of zone a as rack r2 is available
+		let idx = |layout: &ClusterLayout, node: u8| {
+			layout
+				.node_id_vec
+				.iter()
+				.position(|id| id.as_slice()[0] == node)
+				.unwrap() as CompactNodeType
+		};
+		let has_pair = |layout: &ClusterLayout, n1: u8, n2: u8| {
+			let (i1, i2) = (idx(layout, n1), idx(layout, n2));
+			layout
+				.ring_assignation_data
+				.chunks(layout.replication_factor)
+				.any(|part| part.contains(&i1) && part.contains(&i2))
+		};
+		assert!(!has_pair(&layout, 1, 2));
+		assert!(has_pair(&layout, 2, 3));
+
+		// Moving node 2 to rack r2 makes the current assignation invalid
+		set_rack(&mut layout.roles, 2, "r2");
+		assert!(layout.check().is_err());
+	}
+
+	#[test]
+	fn test_zone_redundancy() {
+		// Zone a has more capacity than zones b and c together, but each
+		// partition must still have a replica in each of the three zones
+		let nodes = [(1, "a"), (2, "a"), (3, "a"), (4, "a"), (5, "b"), (6, "c")];
+		let part_zones = |layout: &ClusterLayout| {
+			layout
+				.ring_assignation_data
+				.chunks(layout.replication_factor)
+				.map(|part| {
+					part.iter()
+						.map(|x| {
+							&layout
+								.node_role(&layout.node_id_vec[*x as usize])
+								.unwrap()
+								.zone
+						})
+						.collect::<HashSet<_>>()
+						.len()
+				})
+				.collect::<Vec<_>>()
+		};
+
+		let mut layout = ClusterLayout::new(3, Some(3));
+		for (node, zone) in nodes {
+			assign(&mut layout, node, zone, 100);
+		}
+		let mut layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+		assert!(layout.check().is_ok());
+		let zones = part_zones(&layout);
+		assert_eq!(zones.len(), 1 << PARTITION_BITS);
+		assert!(zones.iter().all(|n| *n >= 3));
+
+		// A partition stored only in zone a does not satisfy the zone redundancy
+		let index_of = |node: u8| {
+			let mut id = [0u8; 32];
+			id[0] = node;
+			layout
+				.node_id_vec
+				.iter()
+				.position(|x| *x == Uuid::from(id))
+				.unwrap() as CompactNodeType
+		};
+		let zone_a = [index_of(1), index_of(2), index_of(3)];
+		layout.ring_assignation_data[..3].copy_from_slice(&zone_a);
+		assert_eq!(part_zones(&layout)[0], 1);
+		let err = layout.check().unwrap_err();
+		assert!(err.starts_with("partition 0 is stored in 1 zone(s)"));
+	}
 }
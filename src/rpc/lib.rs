@@ -8,14 +8,18 @@ mod system_metrics;
 
 #[cfg(feature = "consul-discovery")]
 mod consul;
+#[cfg(feature = "dns-discovery")]
+mod dns;
 #[cfg(feature = "kubernetes-discovery")]
 mod kubernetes;
 
+pub mod drain;
 pub mod layout;
 pub mod replication_mode;
 pub mod ring;
 pub mod system;
 
 pub mod rpc_helper;
+pub mod rpc_stats;
 
 pub use rpc_helper::*;
@@ -21,6 +21,24 @@ impl ReplicationMode {
 		}
 	}
 
+	/// The replication mode that stores data `replication_factor` times with
+	/// the same consistency guarantees as this one, if there is such a mode.
+	/// This gives the quorums to use when the replication factor of the
+	/// cluster layout has been changed from the one of the configuration.
+	pub fn with_replication_factor(&self, replication_factor: usize) -> Option<Self> {
+		let dangerous = matches!(self, Self::TwoWayDangerous | Self::ThreeWayDangerous);
+		let degraded = matches!(self, Self::ThreeWayDegraded);
+		match replication_factor {
+			1 => Some(Self::None),
+			2 if dangerous => Some(Self::TwoWayDangerous),
+			2 => Some(Self::TwoWay),
+			3 if dangerous => Some(Self::ThreeWayDangerous),
+			3 if degraded => Some(Self::ThreeWayDegraded),
+			3 => Some(Self::ThreeWay),
+			_ => None,
+		}
+	}
+
 	pub fn control_write_max_faults(&self) -> usize {
 		match self {
 			Self::None => 0,
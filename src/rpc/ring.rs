@@ -35,6 +35,10 @@ pub struct Ring {
 
 	// The list of entries in the ring
 	ring: Vec<RingEntry>,
+
+	// The replication factor, the order of nodes and the entries of the ring
+	// before the replication factor was changed, while it is being changed
+	previous: Option<(usize, Vec<Uuid>, Vec<RingEntry>)>,
 }
 
 // Type to store compactly the id of a node in the system
@@ -60,9 +64,13 @@ struct RingEntry {
 }
 
 impl Ring {
-	pub(crate) fn new(layout: ClusterLayout, replication_factor: usize) -> Self {
-		if replication_factor != layout.replication_factor {
-			warn!("Could not build ring: replication factor does not match between local configuration and network role assignation.");
+	pub(crate) fn new(layout: ClusterLayout) -> Self {
+		let replication_factor = layout.replication_factor;
+		if replication_factor == 0 || replication_factor > MAX_REPLICATION {
+			warn!(
+				"Could not build ring: unsupported replication factor {} in network role assignation",
+				replication_factor
+			);
 			return Self::empty(layout, replication_factor);
 		}
 
@@ -72,27 +80,54 @@ impl Ring {
 		}
 
 		let nodes = layout.node_id_vec.clone();
-		let ring = (0..(1 << PARTITION_BITS))
+		let ring = Self::build_entries(replication_factor, &layout.ring_assignation_data);
+
+		let previous = match layout.previous_replication_factor {
+			Some(previous_rf)
+				if previous_rf > 0
+					&& previous_rf <= MAX_REPLICATION
+					&& layout.previous_ring_assignation_data.len()
+						== previous_rf * (1 << PARTITION_BITS)
+					&& layout
+						.previous_ring_assignation_data
+						.iter()
+						.all(|i| (*i as usize) < layout.previous_node_id_vec.len()) =>
+			{
+				Some((
+					previous_rf,
+					layout.previous_node_id_vec.clone(),
+					Self::build_entries(previous_rf, &layout.previous_ring_assignation_data),
+				))
+			}
+			_ => None,
+		};
+
+		Self {
+			replication_factor,
+			layout,
+			nodes,
+			ring,
+			previous,
+		}
+	}
+
+	fn build_entries(
+		replication_factor: usize,
+		assignation_data: &[CompactNodeType],
+	) -> Vec<RingEntry> {
+		(0..(1 << PARTITION_BITS))
 			.map(|i| {
 				let top = (i as u16) << (16 - PARTITION_BITS);
 				let mut nodes_buf = [0u8; MAX_REPLICATION];
 				nodes_buf[..replication_factor].copy_from_slice(
-					&layout.ring_assignation_data
-						[replication_factor * i..replication_factor * (i + 1)],
+					&assignation_data[replication_factor * i..replication_factor * (i + 1)],
 				);
 				RingEntry {
 					hash_prefix: top,
 					nodes_buf,
 				}
 			})
-			.collect::<Vec<_>>();
-
-		Self {
-			replication_factor,
-			layout,
-			nodes,
-			ring,
-		}
+			.collect::<Vec<_>>()
 	}
 
 	fn empty(layout: ClusterLayout, replication_factor: usize) -> Self {
@@ -101,6 +136,7 @@ impl Ring {
 			layout,
 			nodes: vec![],
 			ring: vec![],
+			previous: None,
 		}
 	}
 
@@ -150,14 +186,161 @@ impl Ring {
 			.map(|i| self.nodes[*i as usize])
 			.collect::<Vec<_>>()
 	}
+
+	/// Get the servers that store the data of a position in the ring, and how
+	/// many of them must respond so that `quorum(replication_factor)` of them
+	/// respond in the current assignation and, while the replication factor is
+	/// being changed, in the assignation from before the change as well
+	/// (the servers of both assignations are returned in that case)
+	pub fn get_nodes_with_quorum(
+		&self,
+		position: &Hash,
+		quorum: impl Fn(usize) -> usize,
+	) -> (Vec<Uuid>, usize) {
+		let mut nodes = self.get_nodes(position, self.replication_factor);
+		let mut nodes_quorum = quorum(self.replication_factor);
+
+		if let Some((previous_rf, previous_nodes, previous_ring)) = &self.previous {
+			let partition = &previous_ring[self.partition_of(position) as usize];
+			let only_previous = partition.nodes_buf[..*previous_rf]
+				.iter()
+				.map(|i| previous_nodes[*i as usize])
+				.filter(|n| !nodes.contains(n))
+				.collect::<Vec<_>>();
+			let only_new = (nodes.len() + only_previous.len()).saturating_sub(*previous_rf);
+			// The responses of the nodes of one assignation don't count
+			// towards the quorum of the other one
+			nodes_quorum = std::cmp::max(
+				nodes_quorum + only_previous.len(),
+				quorum(*previous_rf) + only_new,
+			);
+			nodes.extend(only_previous);
+		}
+
+		(nodes, nodes_quorum)
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::layout::{NodeRole, NodeRoleV};
+	use crate::replication_mode::ReplicationMode;
+	use garage_util::crdt::{Crdt, LwwMap};
+	use garage_util::time::now_msec;
 
 	#[test]
 	fn test_ring_entry_size() {
 		assert_eq!(std::mem::size_of::<RingEntry>(), 8);
 	}
+
+	fn node_id(node: u8) -> Uuid {
+		let mut id = [0u8; 32];
+		id[0] = node;
+		id.into()
+	}
+
+	fn set_role(layout: &mut ClusterLayout, node: u8, zone: Option<&str>) {
+		let role = zone.map(|zone| NodeRole {
+			zone: zone.to_string(),
+			rack: None,
+			capacity: Some(100),
+			tags: Default::default(),
+		});
+		layout.staging.merge(&LwwMap::raw_item(
+			node_id(node),
+			now_msec(),
+			NodeRoleV(role),
+		));
+	}
+
+	fn read_quorum(replication_factor: usize) -> usize {
+		ReplicationMode::ThreeWay
+			.with_replication_factor(replication_factor)
+			.unwrap()
+			.read_quorum()
+	}
+
+	fn write_quorum(replication_factor: usize) -> usize {
+		ReplicationMode::ThreeWay
+			.with_replication_factor(replication_factor)
+			.unwrap()
+			.write_quorum()
+	}
+
+	/// Check that whichever `quorum` nodes respond, at least `set_quorum`
+	/// of them are in `set`
+	fn assert_quorum_in(nodes: &[Uuid], quorum: usize, set: &[Uuid], set_quorum: usize) {
+		assert!(set.iter().all(|n| nodes.contains(n)));
+		let outside = nodes.len() - set.len();
+		assert!(quorum <= nodes.len());
+		assert!(quorum.saturating_sub(outside) >= set_quorum);
+	}
+
+	fn check_change(old_ring: &Ring, ring: &Ring) {
+		for (_, hash) in ring.partitions() {
+			let old_nodes = old_ring.get_nodes(&hash, old_ring.replication_factor);
+			let new_nodes = ring.get_nodes(&hash, ring.replication_factor);
+			for quorum in [read_quorum, write_quorum] {
+				let (nodes, n) = ring.get_nodes_with_quorum(&hash, quorum);
+				assert_quorum_in(&nodes, n, &old_nodes, quorum(old_ring.replication_factor));
+				assert_quorum_in(&nodes, n, &new_nodes, quorum(ring.replication_factor));
+			}
+		}
+	}
+
+	#[test]
+	fn test_replication_factor_change() {
+		let mut layout = ClusterLayout::new(1, None);
+		for (node, zone) in [(1, "a"), (2, "b"), (3, "c")] {
+			set_role(&mut layout, node, Some(zone));
+		}
+		let v1 = layout.apply_staged_changes(Some(1)).unwrap().0;
+		let ring1 = Ring::new(v1.clone());
+
+		// While it is changed from 1 to 3, reads have to reach the node
+		// that has the only replica from before the change
+		let mut layout = v1;
+		layout.stage_replication_factor(3);
+		let v2 = layout.apply_staged_changes(Some(2)).unwrap().0;
+		let ring2 = Ring::new(v2.clone());
+		for (_, hash) in ring2.partitions() {
+			let (nodes, quorum) = ring2.get_nodes_with_quorum(&hash, read_quorum);
+			assert_eq!(nodes.len(), 3);
+			assert_eq!(quorum, 3);
+		}
+		check_change(&ring1, &ring2);
+
+		// Once it is finished, only the new assignation is used
+		let v3 = v2.finish_replication_factor_change(Some(3)).unwrap();
+		let ring3 = Ring::new(v3);
+		for (_, hash) in ring3.partitions() {
+			let (nodes, quorum) = ring3.get_nodes_with_quorum(&hash, read_quorum);
+			assert_eq!(nodes, ring3.get_nodes(&hash, 3));
+			assert_eq!(quorum, 2);
+		}
+
+		// Nodes of the previous assignation which are no longer
+		// in the new one are also used
+		let mut layout = ClusterLayout::new(2, None);
+		for (node, zone) in [(1, "a"), (2, "b")] {
+			set_role(&mut layout, node, Some(zone));
+		}
+		let v1 = layout.apply_staged_changes(Some(1)).unwrap().0;
+		let ring1 = Ring::new(v1.clone());
+		let mut layout = v1;
+		set_role(&mut layout, 1, None);
+		for (node, zone) in [(3, "c"), (4, "d")] {
+			set_role(&mut layout, node, Some(zone));
+		}
+		layout.stage_replication_factor(3);
+		let v2 = layout.apply_staged_changes(Some(2)).unwrap().0;
+		let ring2 = Ring::new(v2);
+		for (_, hash) in ring2.partitions() {
+			let (nodes, _) = ring2.get_nodes_with_quorum(&hash, read_quorum);
+			assert_eq!(nodes.len(), 4);
+			assert!(nodes.contains(&node_id(1)));
+		}
+		check_change(&ring1, &ring2);
+	}
 }
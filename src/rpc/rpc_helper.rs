@@ -1,6 +1,7 @@
 //! Contain structs related to making RPCs
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use futures::future::join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
@@ -28,9 +29,12 @@ use garage_util::metrics::RecordDuration;
 
 use crate::metrics::RpcMetrics;
 use crate::ring::Ring;
+use crate::rpc_stats::*;
 
 // Default RPC timeout = 5 minutes
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+// Default time to wait for preferred nodes before falling back to others = 1 second
+const DEFAULT_PREFERRED_NODES_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Strategy to apply when making RPC
 #[derive(Copy, Clone)]
@@ -93,7 +97,12 @@ struct RpcHelperInner {
 	fullmesh: Arc<FullMeshPeeringStrategy>,
 	ring: watch::Receiver<Arc<Ring>>,
 	metrics: RpcMetrics,
+	stats: RpcStats,
 	rpc_timeout: Duration,
+	/// Number of RPCs sent to each node, and of responses received from it
+	rpc_counts: Mutex<HashMap<Uuid, (u64, u64)>>,
+	preferred_tags: HashMap<String, String>,
+	preferred_nodes_timeout: Duration,
 }
 
 impl RpcHelper {
@@ -102,6 +111,8 @@ impl RpcHelper {
 		fullmesh: Arc<FullMeshPeeringStrategy>,
 		ring: watch::Receiver<Arc<Ring>>,
 		rpc_timeout: Option<Duration>,
+		preferred_tags: HashMap<String, String>,
+		preferred_nodes_timeout: Option<Duration>,
 	) -> Self {
 		let metrics = RpcMetrics::new();
 
@@ -110,7 +121,12 @@ impl RpcHelper {
 			fullmesh,
 			ring,
 			metrics,
+			stats: RpcStats::new(),
 			rpc_timeout: rpc_timeout.unwrap_or(DEFAULT_TIMEOUT),
+			rpc_counts: Mutex::new(HashMap::new()),
+			preferred_tags,
+			preferred_nodes_timeout: preferred_nodes_timeout
+				.unwrap_or(DEFAULT_PREFERRED_NODES_TIMEOUT),
 		}))
 	}
 
@@ -118,6 +134,49 @@ impl RpcHelper {
 		self.0.rpc_timeout
 	}
 
+	/// Get the number of RPCs emitted by this node to a node, and the number
+	/// of responses received from it (netapp does not expose byte counters)
+	pub fn rpc_counts(&self, node: &Uuid) -> (u64, u64) {
+		self.0
+			.rpc_counts
+			.lock()
+			.unwrap()
+			.get(node)
+			.cloned()
+			.unwrap_or_default()
+	}
+
+	fn record_rpc(&self, node: Uuid, answered: bool) {
+		let mut rpc_counts = self.0.rpc_counts.lock().unwrap();
+		let entry = rpc_counts.entry(node).or_default();
+		entry.0 += 1;
+		entry.1 += answered as u64;
+	}
+
+	/// Time to wait for preferred nodes before sending requests to other nodes
+	pub fn preferred_nodes_timeout(&self) -> Duration {
+		self.0.preferred_nodes_timeout
+	}
+
+	/// Check whether a node has all of the tags in `preferred_tags`.
+	/// No node is preferred if no preferred tags are configured.
+	pub fn is_preferred(&self, node: &Uuid) -> bool {
+		!self.0.preferred_tags.is_empty()
+			&& self
+				.0
+				.ring
+				.borrow()
+				.layout
+				.node_role(node)
+				.map(|role| role.has_tags(&self.0.preferred_tags))
+				.unwrap_or(false)
+	}
+
+	/// Get statistics of the RPCs emitted by this node, for each endpoint
+	pub fn rpc_stats(&self, window: RpcStatsWindow) -> Vec<RpcEndpointStats> {
+		self.0.stats.get(window)
+	}
+
 	pub async fn call<M, N, H, S>(
 		&self,
 		endpoint: &Endpoint<M, H>,
@@ -151,24 +210,33 @@ impl RpcHelper {
 			}
 		};
 
-		select! {
+		let request_start = Instant::now();
+		let res = select! {
 			res = rpc_call => {
 				if res.is_err() {
 					self.0.metrics.rpc_netapp_error_counter.add(1, &metric_tags);
 				}
-				let res = res?.into_msg();
-
-				if res.is_err() {
-					self.0.metrics.rpc_garage_error_counter.add(1, &metric_tags);
-				}
-
-				Ok(res?)
+				self.record_rpc(to, res.is_ok());
+				res.map_err(Error::from).and_then(|res| {
+					let res = res.into_msg();
+					if res.is_err() {
+						self.0.metrics.rpc_garage_error_counter.add(1, &metric_tags);
+					}
+					res
+				})
 			}
 			() = timeout => {
 				self.0.metrics.rpc_timeout_counter.add(1, &metric_tags);
+				self.record_rpc(to, false);
 				Err(Error::Timeout)
 			}
-		}
+		};
+
+		self.0
+			.stats
+			.record(endpoint.path(), request_start.elapsed(), res.is_err());
+
+		res
 	}
 
 	pub async fn call_many<M, N, H, S>(
@@ -297,6 +365,9 @@ impl RpcHelper {
 			// What we do here is only send the required number of requests
 			// to reach a quorum, priorizing nodes with the lowest latency.
 			// When there are errors, we start new requests to compensate.
+			// If preferred nodes were asked first and quorum is not reached
+			// when the preferred nodes timeout expires, all remaining
+			// requests are started at once.
 
 			// Reorder requests to priorize closeness / low latency
 			let request_order = self.request_order(to);
@@ -310,7 +381,20 @@ impl RpcHelper {
 			}
 
 			// Make an iterator to take requests in their sorted order
-			let mut requests = ord_requests.into_iter().map(Option::unwrap);
+			let mut requests = ord_requests
+				.into_iter()
+				.map(Option::unwrap)
+				.map(|(req_to, fut)| {
+					let tracer = opentelemetry::global::tracer("garage");
+					let span = tracer.start(format!("RPC to {:?}", req_to));
+					fut.with_context(Context::current_with_span(span))
+				});
+
+			// Fallback to other nodes is only useful if some nodes are
+			// preferred over others
+			let mut fallback_pending = request_order.iter().any(|n| self.is_preferred(n))
+				&& request_order.iter().any(|n| !self.is_preferred(n));
+			let fallback_deadline = tokio::time::Instant::now() + self.0.preferred_nodes_timeout;
 
 			// resp_stream will contain all of the requests that are currently in flight.
 			// (for the moment none, they will be added in the loop below)
@@ -321,12 +405,8 @@ impl RpcHelper {
 				// If the current set of requests that are running is not enough to possibly
 				// reach quorum, start some new requests.
 				while successes.len() + resp_stream.len() < quorum {
-					if let Some((req_to, fut)) = requests.next() {
-						let tracer = opentelemetry::global::tracer("garage");
-						let span = tracer.start(format!("RPC to {:?}", req_to));
-						resp_stream.push(tokio::spawn(
-							fut.with_context(Context::current_with_span(span)),
-						));
+					if let Some(fut) = requests.next() {
+						resp_stream.push(tokio::spawn(fut));
 					} else {
 						// If we have no request to add, we know that we won't ever
 						// reach quorum: bail out now.
@@ -335,8 +415,21 @@ impl RpcHelper {
 				}
 				assert!(!resp_stream.is_empty()); // because of loop invariants
 
-				// Wait for one request to terminate
-				match resp_stream.next().await.unwrap().unwrap() {
+				// Wait for one request to terminate, or for the preferred
+				// nodes timeout to expire
+				let resp = if fallback_pending {
+					select! {
+						resp = resp_stream.next() => resp,
+						_ = tokio::time::sleep_until(fallback_deadline) => {
+							fallback_pending = false;
+							resp_stream.extend(requests.by_ref().map(tokio::spawn));
+							continue;
+						}
+					}
+				} else {
+					resp_stream.next().await
+				};
+				match resp.unwrap().unwrap() {
 					Ok(msg) => {
 						successes.push(msg);
 					}
@@ -400,10 +493,11 @@ impl RpcHelper {
 
 		// Augment requests with some information used to sort them.
 		// The tuples are as follows:
-		//         (is another node?, is another zone?, latency, node ID, request future)
+		//         (is another node?, is not preferred?, is another zone?, latency, node ID)
 		// We store all of these tuples in a vec that we can sort.
-		// By sorting this vec, we priorize ourself, then nodes in the same zone,
-		// and within a same zone we priorize nodes with the lowest latency.
+		// By sorting this vec, we priorize ourself, then preferred nodes,
+		// then nodes in the same zone, and within a same zone we priorize
+		// nodes with the lowest latency.
 		let mut nodes = nodes
 			.iter()
 			.map(|to| {
@@ -416,8 +510,15 @@ impl RpcHelper {
 					.find(|x| x.id.as_ref() == to.as_slice())
 					.and_then(|pi| pi.avg_ping)
 					.unwrap_or_else(|| Duration::from_secs(10));
+				let peer_preferred = !self.0.preferred_tags.is_empty()
+					&& ring
+						.layout
+						.node_role(to)
+						.map(|role| role.has_tags(&self.0.preferred_tags))
+						.unwrap_or(false);
 				(
 					*to != self.0.our_node_id,
+					!peer_preferred,
 					peer_zone != our_zone,
 					peer_avg_ping,
 					*to,
@@ -425,12 +526,15 @@ impl RpcHelper {
 			})
 			.collect::<Vec<_>>();
 
-		// Sort requests by (priorize ourself, priorize same zone, priorize low latency)
-		nodes.sort_by_key(|(diffnode, diffzone, ping, _to)| (*diffnode, *diffzone, *ping));
+		// Sort requests by (priorize ourself, priorize preferred nodes,
+		// priorize same zone, priorize low latency)
+		nodes.sort_by_key(|(diffnode, notpreferred, diffzone, ping, _to)| {
+			(*diffnode, *notpreferred, *diffzone, *ping)
+		});
 
 		nodes
 			.into_iter()
-			.map(|(_, _, _, to)| to)
+			.map(|(_, _, _, _, to)| to)
 			.collect::<Vec<_>>()
 	}
 }
@@ -0,0 +1,204 @@
+//! In-memory statistics of the RPCs emitted by this node, for each RPC endpoint
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of buckets of the latency histograms. Bucket `i` counts the calls
+/// that took less than 2^i microseconds, the last one counts all slower calls.
+const LATENCY_BUCKETS: usize = 32;
+
+/// Statistics over the last minute are computed from slots of 5 seconds
+const WINDOW_SLOT_DURATION: Duration = Duration::from_secs(5);
+const WINDOW_SLOTS: u64 = 12;
+
+/// Period of time over which RPC statistics are computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RpcStatsWindow {
+	SinceRestart,
+	LastMinute,
+}
+
+/// Statistics of the RPCs emitted to an endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcEndpointStats {
+	pub endpoint_name: String,
+	pub calls_total: u64,
+	/// Calls that returned an error or timed out
+	pub errors_total: u64,
+	pub mean_latency_ms: f64,
+	/// Approximated from a histogram with exponential buckets,
+	/// the value is an upper bound of the actual 99th percentile
+	pub p99_latency_ms: f64,
+}
+
+/// Collects statistics of all RPCs emitted through the `RpcHelper`
+pub(crate) struct RpcStats {
+	start: Instant,
+	endpoints: Mutex<HashMap<String, EndpointStats>>,
+}
+
+#[derive(Default)]
+struct EndpointStats {
+	total: Counters,
+	/// Counters of the last slots, with the index of the slot they belong to
+	window: [(u64, Counters); WINDOW_SLOTS as usize],
+}
+
+#[derive(Default, Clone)]
+struct Counters {
+	calls: u64,
+	errors: u64,
+	latency_sum: Duration,
+	latency_max: Duration,
+	latency_hist: [u64; LATENCY_BUCKETS],
+}
+
+impl RpcStats {
+	pub(crate) fn new() -> Self {
+		Self {
+			start: Instant::now(),
+			endpoints: Mutex::new(HashMap::new()),
+		}
+	}
+
+	pub(crate) fn record(&self, endpoint: &str, latency: Duration, is_err: bool) {
+		let slot = self.current_slot();
+		let mut endpoints = self.endpoints.lock().unwrap();
+		let stats = match endpoints.get_mut(endpoint) {
+			Some(s) => s,
+			None => endpoints.entry(endpoint.to_string()).or_default(),
+		};
+
+		stats.total.record(latency, is_err);
+
+		let (slot_idx, counters) = &mut stats.window[(slot % WINDOW_SLOTS) as usize];
+		if *slot_idx != slot {
+			*slot_idx = slot;
+			*counters = Counters::default();
+		}
+		counters.record(latency, is_err);
+	}
+
+	/// Get statistics of all endpoints that have been called during the given window,
+	/// sorted by decreasing number of calls
+	pub(crate) fn get(&self, window: RpcStatsWindow) -> Vec<RpcEndpointStats> {
+		let slot = self.current_slot();
+		let endpoints = self.endpoints.lock().unwrap();
+
+		let mut ret = endpoints
+			.iter()
+			.map(|(name, stats)| {
+				let counters = match window {
+					RpcStatsWindow::SinceRestart => stats.total.clone(),
+					RpcStatsWindow::LastMinute => {
+						let mut sum = Counters::default();
+						for (slot_idx, counters) in stats.window.iter() {
+							if *slot_idx + WINDOW_SLOTS > slot {
+								sum.merge(counters);
+							}
+						}
+						sum
+					}
+				};
+				counters.to_endpoint_stats(name.clone())
+			})
+			.filter(|s| s.calls_total > 0)
+			.collect::<Vec<_>>();
+
+		ret.sort_by(|a, b| {
+			b.calls_total
+				.cmp(&a.calls_total)
+				.then_with(|| a.endpoint_name.cmp(&b.endpoint_name))
+		});
+		ret
+	}
+
+	fn current_slot(&self) -> u64 {
+		self.start.elapsed().as_secs() / WINDOW_SLOT_DURATION.as_secs()
+	}
+}
+
+impl Counters {
+	fn record(&mut self, latency: Duration, is_err: bool) {
+		self.calls += 1;
+		if is_err {
+			self.errors += 1;
+		}
+		self.latency_sum += latency;
+		self.latency_max = std::cmp::max(self.latency_max, latency);
+
+		let micros = latency.as_micros() as u64;
+		let bucket = (64 - micros.leading_zeros()) as usize;
+		self.latency_hist[std::cmp::min(bucket, LATENCY_BUCKETS - 1)] += 1;
+	}
+
+	fn merge(&mut self, other: &Counters) {
+		self.calls += other.calls;
+		self.errors += other.errors;
+		self.latency_sum += other.latency_sum;
+		self.latency_max = std::cmp::max(self.latency_max, other.latency_max);
+		for (a, b) in self.latency_hist.iter_mut().zip(other.latency_hist.iter()) {
+			*a += b;
+		}
+	}
+
+	// u64::div_ceil is not available in our MSRV (1.68)
+	#[allow(clippy::manual_div_ceil)]
+	fn p99_latency(&self) -> Duration {
+		let target = (self.calls * 99 + 99) / 100;
+		let mut seen = 0;
+		for (i, count) in self.latency_hist.iter().enumerate() {
+			seen += count;
+			if seen >= target {
+				let bucket_max = Duration::from_micros(1 << i);
+				return std::cmp::min(bucket_max, self.latency_max);
+			}
+		}
+		self.latency_max
+	}
+
+	fn to_endpoint_stats(&self, endpoint_name: String) -> RpcEndpointStats {
+		let to_ms = |d: Duration| d.as_secs_f64() * 1000.;
+		RpcEndpointStats {
+			endpoint_name,
+			calls_total: self.calls,
+			errors_total: self.errors,
+			mean_latency_ms: if self.calls > 0 {
+				to_ms(self.latency_sum) / self.calls as f64
+			} else {
+				0.
+			},
+			p99_latency_ms: to_ms(self.p99_latency()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_rpc_stats() {
+		let stats = RpcStats::new();
+		for i in 0..100 {
+			stats.record("garage_table/Item", Duration::from_millis(1), i == 0);
+		}
+		stats.record("garage_table/Item", Duration::from_millis(200), false);
+		stats.record("garage_block/Block", Duration::from_millis(3), false);
+
+		let res = stats.get(RpcStatsWindow::SinceRestart);
+		assert_eq!(res.len(), 2);
+		assert_eq!(res[0].endpoint_name, "garage_table/Item");
+		assert_eq!(res[0].calls_total, 101);
+		assert_eq!(res[0].errors_total, 1);
+		assert!(res[0].mean_latency_ms > 1. && res[0].mean_latency_ms < 3.5);
+		// 1ms falls in the bucket of calls under 1024us
+		assert!(res[0].p99_latency_ms >= 1. && res[0].p99_latency_ms <= 1.024);
+		assert_eq!(res[1].p99_latency_ms, 3.);
+
+		let res = stats.get(RpcStatsWindow::LastMinute);
+		assert_eq!(res[0].calls_total, 101);
+	}
+}
@@ -1,9 +1,9 @@
 //! Module containing structs related to membership management
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{Read, Write};
 use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
@@ -18,20 +18,28 @@ use tokio::sync::Mutex;
 
 use netapp::endpoint::{Endpoint, EndpointHandler};
 use netapp::message::*;
-use netapp::peering::fullmesh::FullMeshPeeringStrategy;
+use netapp::peering::fullmesh::{FullMeshPeeringStrategy, PeerConnState};
 use netapp::util::parse_and_resolve_peer_addr_async;
 use netapp::{NetApp, NetworkKey, NodeID, NodeKey};
 
+use garage_util::background::vars;
 use garage_util::config::Config;
+#[cfg(feature = "dns-discovery")]
+use garage_util::config::DnsDiscoveryConfig;
 #[cfg(feature = "kubernetes-discovery")]
 use garage_util::config::KubernetesDiscoveryConfig;
+use garage_util::crdt::Crdt;
 use garage_util::data::*;
+use garage_util::encode::{nonversioned_decode, nonversioned_encode};
 use garage_util::error::*;
 use garage_util::persister::Persister;
 use garage_util::time::*;
 
 #[cfg(feature = "consul-discovery")]
 use crate::consul::ConsulDiscovery;
+#[cfg(feature = "dns-discovery")]
+use crate::dns::get_dns_nodes;
+use crate::drain::*;
 #[cfg(feature = "kubernetes-discovery")]
 use crate::kubernetes::*;
 use crate::layout::*;
@@ -44,10 +52,18 @@ use crate::system_metrics::*;
 const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
 const STATUS_EXCHANGE_INTERVAL: Duration = Duration::from_secs(10);
 
+/// Number of samples of the cluster health that are kept, one being taken at
+/// each status exchange (one hour of history)
+const HEALTH_HISTORY_LENGTH: usize = 360;
+
+/// Timeout of the pings to other nodes when `rpc_ping_timeout_msec` is not set
+/// (the default of netapp)
+pub const DEFAULT_PING_TIMEOUT_MSEC: u64 = 10_000;
+
 /// Version tag used for version check upon Netapp connection.
 /// Cluster nodes with different version tags are deemed
 /// incompatible and will refuse to connect.
-pub const GARAGE_VERSION_TAG: u64 = 0x6761726167650008; // garage 0x0008
+pub const GARAGE_VERSION_TAG: u64 = 0x6761726167650009; // garage 0x0009
 
 /// RPC endpoint used for calls related to membership
 pub const SYSTEM_RPC_PATH: &str = "garage_rpc/membership.rs/SystemRpc";
@@ -70,12 +86,64 @@ pub enum SystemRpc {
 	GetKnownNodes,
 	/// Return known nodes
 	ReturnKnownNodes(Vec<KnownNodeInfo>),
+	/// Check that a node is responding. Answered with Ok.
+	Ping,
+	/// Remove the storage role of a node in a new version of the cluster layout,
+	/// so that its data is moved to other nodes. Answered with Ok.
+	DrainNode(Uuid),
+	/// Restore the roles of a previous version of the cluster layout from its
+	/// history, in a new version of the layout. Answered with Ok.
+	RollbackLayout(u64),
+	/// Another SystemRpc message, encoded and compressed with zstd.
+	/// Only sent to nodes that advertise support for it in their status.
+	Compressed(#[serde(with = "serde_bytes")] Vec<u8>),
+	/// Compute the changes that applying the staged role changes of a cluster
+	/// layout would make, without applying them. Answered with LayoutApplySimulation.
+	SimulateLayoutApply(ClusterLayout),
+	/// Result of a SimulateLayoutApply
+	LayoutApplySimulation(LayoutApplySimulation),
+	/// Stage a change of the replication factor in the cluster layout, once a quorum
+	/// of the nodes of the layout have agreed to use it. Answered with Ok.
+	ProposeReplicationFactorChange(usize),
+	/// Check that a node can use the given replication factor. Answered with Ok.
+	CheckReplicationFactor(usize),
 }
 
 impl Rpc for SystemRpc {
 	type Response = Result<SystemRpc, Error>;
 }
 
+impl SystemRpc {
+	/// Wrap this message in a `SystemRpc::Compressed` message if its encoding
+	/// is at least `threshold` bytes long and compression makes it smaller
+	fn compress(self, threshold: usize) -> SystemRpc {
+		let bytes = match nonversioned_encode(&self) {
+			Ok(bytes) if bytes.len() >= threshold => bytes,
+			_ => return self,
+		};
+		match zstd::encode_all(&bytes[..], zstd::DEFAULT_COMPRESSION_LEVEL) {
+			Ok(compressed) if compressed.len() < bytes.len() => SystemRpc::Compressed(compressed),
+			_ => self,
+		}
+	}
+
+	/// Unwrap a `SystemRpc::Compressed` message, other messages are returned as is
+	pub fn decompress(self) -> Result<SystemRpc, Error> {
+		match self {
+			SystemRpc::Compressed(compressed) => {
+				let bytes = zstd::decode_all(&compressed[..])?;
+				match nonversioned_decode::<SystemRpc>(&bytes)? {
+					SystemRpc::Compressed(_) => Err(Error::Message(
+						"Invalid nested compressed RPC message".into(),
+					)),
+					msg => Ok(msg),
+				}
+			}
+			msg => Ok(msg),
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PeerList(Vec<(Uuid, SocketAddr)>);
 impl garage_util::migrate::InitialFormat for PeerList {}
@@ -90,9 +158,14 @@ pub struct System {
 
 	local_status: ArcSwap<NodeStatus>,
 	node_status: RwLock<HashMap<Uuid, (u64, NodeStatus)>>,
+	/// Recent samples of the cluster health, with the time (in msec) at
+	/// which they were taken, oldest first
+	health_history: RwLock<VecDeque<(u64, ClusterHealth)>>,
 
 	pub netapp: Arc<NetApp>,
 	fullmesh: Arc<FullMeshPeeringStrategy>,
+	/// Timeout of the pings to other nodes, which can be changed while the node is running
+	ping_timeout_msec: Arc<AtomicU64>,
 	pub rpc: RpcHelper,
 
 	system_endpoint: Arc<Endpoint<SystemRpc, System>>,
@@ -106,11 +179,15 @@ pub struct System {
 	consul_discovery: Option<ConsulDiscovery>,
 	#[cfg(feature = "kubernetes-discovery")]
 	kubernetes_discovery: Option<KubernetesDiscoveryConfig>,
+	#[cfg(feature = "dns-discovery")]
+	dns_discovery: Option<DnsDiscoveryConfig>,
 
 	metrics: SystemMetrics,
 
 	replication_mode: ReplicationMode,
-	replication_factor: usize,
+	zone_redundancy: Option<usize>,
+	rpc_compression_threshold: Option<usize>,
+	rpc_drain_timeout: Duration,
 
 	/// The ring
 	pub ring: watch::Receiver<Arc<Ring>>,
@@ -127,7 +204,7 @@ pub struct NodeStatus {
 	/// Hostname of the node
 	pub hostname: String,
 
-	/// Replication factor configured on the node
+	/// Replication factor of the cluster layout used by the node
 	pub replication_factor: usize,
 	/// Cluster layout version
 	pub cluster_layout_version: u64,
@@ -140,6 +217,12 @@ pub struct NodeStatus {
 	/// Disk usage on partition containing data directory (tuple: `(avail, total)`)
 	#[serde(default)]
 	pub data_disk_avail: Option<(u64, u64)>,
+	/// Number of data blocks stored on the node, if known
+	#[serde(default)]
+	pub stored_blocks: Option<u64>,
+	/// Whether the node can decode `SystemRpc::Compressed` messages
+	#[serde(default)]
+	pub rpc_compression: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -151,7 +234,31 @@ pub struct KnownNodeInfo {
 	pub status: NodeStatus,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// State of the RPC connection from this node to another node of the cluster
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerConnectionInfo {
+	pub id: Uuid,
+	pub addr: SocketAddr,
+	/// Human-readable state of the connection (connected, trying, abandonned...)
+	pub state: String,
+	pub is_up: bool,
+	/// Whether this node is connected to the peer (outbound connection)
+	pub outbound: bool,
+	/// Whether the peer recently sent its status to this node through its own
+	/// connection to this node (inbound connection)
+	pub inbound: bool,
+	pub last_seen_secs_ago: Option<u64>,
+	/// Number of RPCs made by this node to the peer, and number of responses
+	/// received from it (failed or timed out RPCs have no response)
+	pub rpcs_sent: u64,
+	pub responses_received: u64,
+	/// Average, median and maximum round-trip time of recent pings, in milliseconds
+	pub avg_ping_msec: Option<f64>,
+	pub med_ping_msec: Option<f64>,
+	pub max_ping_msec: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClusterHealth {
 	/// The current health status of the cluster (see below)
 	pub status: ClusterHealthStatus,
@@ -169,6 +276,48 @@ pub struct ClusterHealth {
 	pub partitions_quorum: usize,
 	/// Number of partitions for which all storage nodes are connected
 	pub partitions_all_ok: usize,
+	/// Number of partition replicas in the layout (partitions times replication factor)
+	pub partition_replicas: usize,
+	/// Number of partition replicas stored on connected storage nodes.
+	/// As nodes store a number of partitions proportional to their capacity,
+	/// this measures the share of the cluster's capacity that is available.
+	pub partition_replicas_ok: usize,
+	/// For each partition, number of different zones in which
+	/// a connected storage node stores that partition
+	pub zones_represented: Vec<usize>,
+	/// Number of nodes that are no longer storage nodes in the layout,
+	/// but that still store data blocks to be moved to other nodes
+	pub draining_nodes: usize,
+	/// Timeout of the pings sent to other nodes to check that they are up,
+	/// in milliseconds
+	pub ping_timeout_msec: u64,
+}
+
+/// Changes that applying the staged role changes of a cluster layout would make
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayoutApplySimulation {
+	/// Version of the new cluster layout
+	pub version: u64,
+	/// Number of partitions stored by each node before and after the change
+	pub nodes: Vec<NodePartitionChange>,
+	/// Number of partition replicas that would be moved to a different node
+	pub moved_partition_replicas: usize,
+	/// Estimated amount of data in a partition replica, from the current
+	/// disk usage of the storage nodes
+	pub bytes_per_partition_replica: Option<u64>,
+	/// Health of the cluster with the new layout, given the nodes currently connected
+	pub health: ClusterHealth,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodePartitionChange {
+	pub id: Uuid,
+	pub partitions_before: usize,
+	pub partitions_after: usize,
+	/// Data currently stored on the node's data disk
+	pub data_bytes: Option<u64>,
+	/// Data that the node would store once all partitions have been moved
+	pub projected_data_bytes: Option<u64>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -261,14 +410,30 @@ impl System {
 			Persister::new(&config.metadata_dir, "cluster_layout");
 		let persist_peer_list = Persister::new(&config.metadata_dir, "peer_list");
 
-		let cluster_layout = match persist_cluster_layout.load() {
-			Ok(x) => {
+		let mut cluster_layout = match persist_cluster_layout.load() {
+			Ok(mut x) => {
+				// Staged roles saved with tags as a list of strings are now
+				// encoded differently, so their hash has to be computed again
+				x.staging_hash = x.calculate_staging_hash();
 				if x.replication_factor != replication_factor {
-					return Err(Error::Message(format!(
-						"Prevous cluster layout has replication factor {}, which is different than the one specified in the config file ({}). The previous cluster layout can be purged, if you know what you are doing, simply by deleting the `cluster_layout` file in your metadata directory.",
-						x.replication_factor,
-						replication_factor
-					)));
+					if x.ring_assignation_data.is_empty() {
+						// No data has been stored with the previous replication factor yet
+						x.replication_factor = replication_factor;
+					} else if replication_mode
+						.with_replication_factor(x.replication_factor)
+						.is_none()
+					{
+						return Err(Error::Message(format!(
+							"Prevous cluster layout has replication factor {}, which is not supported. The previous cluster layout can be purged, if you know what you are doing, simply by deleting the `cluster_layout` file in your metadata directory.",
+							x.replication_factor
+						)));
+					} else {
+						warn!(
+							"The cluster layout has replication factor {}, which is different than the one specified in the config file ({}). The replication factor of the cluster layout is used, it can be changed with `garage layout replication-factor`.",
+							x.replication_factor,
+							replication_factor
+						);
+					}
 				}
 				x
 			}
@@ -277,16 +442,30 @@ impl System {
 					"No valid previous cluster layout stored ({}), starting fresh.",
 					e
 				);
-				ClusterLayout::new(replication_factor)
+				ClusterLayout::new(replication_factor, config.zone_redundancy)
 			}
 		};
+		if cluster_layout.zone_redundancy != config.zone_redundancy {
+			cluster_layout.zone_redundancy = config.zone_redundancy;
+			if let Err(e) = cluster_layout.check() {
+				warn!(
+					"The zone redundancy specified in the config file ({}) is not satisfied by the current cluster layout: {}. A new layout has to be applied.",
+					cluster_layout.zone_redundancy_string(),
+					e
+				);
+			}
+		}
 
-		let metrics = SystemMetrics::new(replication_factor);
+		let metrics = SystemMetrics::new(cluster_layout.replication_factor);
 
-		let mut local_status = NodeStatus::initial(replication_factor, &cluster_layout);
+		if let Some(hostname) = &config.node_hostname {
+			validate_hostname(hostname)?;
+		}
+		let mut local_status =
+			NodeStatus::initial(&cluster_layout, config.node_hostname.as_deref());
 		local_status.update_disk_usage(&config.metadata_dir, &config.data_dir, &metrics);
 
-		let ring = Ring::new(cluster_layout, replication_factor);
+		let ring = Ring::new(cluster_layout);
 		let (update_ring, ring) = watch::channel(Arc::new(ring));
 
 		let rpc_public_addr = match &config.rpc_public_addr {
@@ -327,16 +506,17 @@ impl System {
 
 		let netapp = NetApp::new(GARAGE_VERSION_TAG, network_key, node_key);
 		let fullmesh = FullMeshPeeringStrategy::new(netapp.clone(), vec![], rpc_public_addr);
-		if let Some(ping_timeout) = config.rpc_ping_timeout_msec {
-			fullmesh.set_ping_timeout_millis(ping_timeout);
-		}
+		let ping_timeout_msec = config
+			.rpc_ping_timeout_msec
+			.unwrap_or(DEFAULT_PING_TIMEOUT_MSEC);
+		fullmesh.set_ping_timeout_millis(ping_timeout_msec);
 
 		let system_endpoint = netapp.endpoint(SYSTEM_RPC_PATH.into());
 
 		#[cfg(feature = "consul-discovery")]
 		let consul_discovery = match &config.consul_discovery {
 			Some(cfg) => Some(
-				ConsulDiscovery::new(cfg.clone())
+				ConsulDiscovery::new(cfg.clone(), config.admin.api_bind_addr.as_ref())
 					.ok_or_message("Invalid Consul discovery configuration")?,
 			),
 			None => None,
@@ -351,23 +531,36 @@ impl System {
 			warn!("Kubernetes discovery is not enabled in this build.");
 		}
 
+		#[cfg(not(feature = "dns-discovery"))]
+		if config.dns_discovery.is_some() {
+			warn!("DNS discovery is not enabled in this build.");
+		}
+
 		let sys = Arc::new(System {
 			id: netapp.id.into(),
 			persist_cluster_layout,
 			persist_peer_list,
 			local_status: ArcSwap::new(Arc::new(local_status)),
 			node_status: RwLock::new(HashMap::new()),
+			health_history: RwLock::new(VecDeque::with_capacity(HEALTH_HISTORY_LENGTH)),
 			netapp: netapp.clone(),
 			fullmesh: fullmesh.clone(),
+			ping_timeout_msec: Arc::new(AtomicU64::new(ping_timeout_msec)),
 			rpc: RpcHelper::new(
 				netapp.id.into(),
 				fullmesh,
 				ring.clone(),
 				config.rpc_timeout_msec.map(Duration::from_millis),
+				config.preferred_tags.clone(),
+				config
+					.preferred_nodes_timeout_msec
+					.map(Duration::from_millis),
 			),
 			system_endpoint,
 			replication_mode,
-			replication_factor,
+			zone_redundancy: config.zone_redundancy,
+			rpc_compression_threshold: config.rpc_compression_threshold,
+			rpc_drain_timeout: Duration::from_secs(config.rpc_drain_timeout_secs),
 			rpc_listen_addr: config.rpc_bind_addr,
 			#[cfg(any(feature = "consul-discovery", feature = "kubernetes-discovery"))]
 			rpc_public_addr,
@@ -376,6 +569,8 @@ impl System {
 			consul_discovery,
 			#[cfg(feature = "kubernetes-discovery")]
 			kubernetes_discovery: config.kubernetes_discovery.clone(),
+			#[cfg(feature = "dns-discovery")]
+			dns_discovery: config.dns_discovery.clone(),
 			metrics,
 
 			ring,
@@ -387,15 +582,39 @@ impl System {
 		Ok(sys)
 	}
 
+	pub fn register_bg_vars(&self, vars: &mut vars::BgVars) {
+		let ping_timeout_msec = self.ping_timeout_msec.clone();
+		let set_ping_timeout_msec = self.ping_timeout_msec.clone();
+		let fullmesh = self.fullmesh.clone();
+		vars.register_rw_fn(
+			"rpc-ping-timeout-msec",
+			move || ping_timeout_msec.load(Ordering::Relaxed),
+			move |timeout: u64| {
+				if timeout == 0 {
+					return Err(Error::Message(
+						"The ping timeout must be greater than zero".into(),
+					));
+				}
+				fullmesh.set_ping_timeout_millis(timeout);
+				set_ping_timeout_msec.store(timeout, Ordering::Relaxed);
+				Ok(())
+			},
+		);
+	}
+
 	/// Perform bootstraping, starting the ping loop
 	pub async fn run(self: Arc<Self>, must_exit: watch::Receiver<bool>) {
+		// Netapp closes all RPC connections as soon as it is told to exit,
+		// so it is only told to once in-flight RPCs have been drained
+		let (netapp_exit_tx, netapp_exit) = watch::channel(false);
 		join!(
 			self.netapp
 				.clone()
-				.listen(self.rpc_listen_addr, None, must_exit.clone()),
-			self.fullmesh.clone().run(must_exit.clone()),
+				.listen(self.rpc_listen_addr, None, netapp_exit.clone()),
+			self.fullmesh.clone().run(netapp_exit),
 			self.discovery_loop(must_exit.clone()),
 			self.status_exchange_loop(must_exit.clone()),
+			self.drain_rpc_on_exit(must_exit.clone(), netapp_exit_tx),
 		);
 	}
 
@@ -425,6 +644,53 @@ impl System {
 		known_nodes
 	}
 
+	pub fn get_peer_connections(&self) -> Vec<PeerConnectionInfo> {
+		let now = Instant::now();
+		let msec = |d: Duration| d.as_secs_f64() * 1000.;
+		// Peers send us their status at each status exchange through their
+		// connection to us: if we received it recently, that connection is up
+		let inbound_since =
+			now_msec().saturating_sub(2 * STATUS_EXCHANGE_INTERVAL.as_millis() as u64);
+		let node_status = self.node_status.read().unwrap();
+		self.fullmesh
+			.get_peer_list()
+			.iter()
+			.map(|n| {
+				let id = n.id.into();
+				let (rpcs_sent, responses_received) = self.rpc.rpc_counts(&id);
+				PeerConnectionInfo {
+					id,
+					addr: n.addr,
+					state: match n.state {
+						PeerConnState::Ourself => "local node".into(),
+						PeerConnState::Connected => "connected".into(),
+						PeerConnState::Waiting(i, t) => format!(
+							"waiting (next attempt #{} in {}s)",
+							i + 1,
+							t.saturating_duration_since(now).as_secs()
+						),
+						PeerConnState::Trying(i) => format!("trying (attempt #{})", i + 1),
+						PeerConnState::Abandonned => "abandonned".into(),
+					},
+					is_up: n.is_up(),
+					outbound: matches!(n.state, PeerConnState::Connected),
+					inbound: node_status
+						.get(&id)
+						.map(|(t, _)| *t >= inbound_since)
+						.unwrap_or(false),
+					last_seen_secs_ago: n
+						.last_seen
+						.map(|t| now.saturating_duration_since(t).as_secs()),
+					rpcs_sent,
+					responses_received,
+					avg_ping_msec: n.avg_ping.map(msec),
+					med_ping_msec: n.med_ping.map(msec),
+					max_ping_msec: n.max_ping.map(msec),
+				}
+			})
+			.collect()
+	}
+
 	pub fn get_cluster_layout(&self) -> ClusterLayout {
 		self.ring.borrow().layout.clone()
 	}
@@ -465,10 +731,84 @@ impl System {
 		}
 	}
 
+	/// Send a ping RPC to a node and return the measured round-trip time.
+	/// The ping fails if the node does not answer within the ping timeout.
+	pub async fn ping_node(&self, node: Uuid) -> Result<Duration, Error> {
+		let timeout = Duration::from_millis(self.ping_timeout_msec.load(Ordering::Relaxed));
+		let start = Instant::now();
+		let res = self
+			.rpc
+			.call(
+				&self.system_endpoint,
+				node,
+				SystemRpc::Ping,
+				RequestStrategy::with_priority(PRIO_HIGH).with_custom_timeout(timeout),
+			)
+			.await;
+		match res {
+			Ok(SystemRpc::Ok) => Ok(start.elapsed()),
+			Ok(m) => Err(Error::unexpected_rpc_message(m)),
+			Err(e) => {
+				// If we know the address of that node, try to connect to it
+				// directly so that we can give a more helpful error message
+				let peer_addr = self
+					.fullmesh
+					.get_peer_list()
+					.iter()
+					.find(|p| p.id == node.into())
+					.map(|p| p.addr);
+				if let Some(addr) = peer_addr {
+					if let Err(ce) = self.netapp.clone().try_connect(addr, node.into()).await {
+						return Err(Error::Message(connect_error_message(addr, node.into(), ce)));
+					}
+				}
+				Err(e)
+			}
+		}
+	}
+
+	/// Replication mode to use with the replication factor of the current cluster
+	/// layout, which has the consistency level of the mode of the configuration
+	pub fn replication_mode(&self) -> ReplicationMode {
+		let replication_factor = self.ring.borrow().replication_factor;
+		self.replication_mode_for(replication_factor)
+	}
+
+	/// The replication mode of the system for a replication factor of the
+	/// cluster layout, which gives the quorums to use with that replication factor
+	pub fn replication_mode_for(&self, replication_factor: usize) -> ReplicationMode {
+		self.replication_mode
+			.with_replication_factor(replication_factor)
+			.unwrap_or(self.replication_mode)
+	}
+
 	pub fn health(&self) -> ClusterHealth {
 		let ring: Arc<_> = self.ring.borrow().clone();
-		let quorum = self.replication_mode.write_quorum();
-		let replication_factor = self.replication_factor;
+		self.ring_health(&ring)
+	}
+
+	/// Return the last `count` samples of the cluster health taken at each
+	/// status exchange, with the time (in msec) at which they were taken,
+	/// oldest first
+	pub fn health_history(&self, count: usize) -> Vec<(u64, ClusterHealth)> {
+		let history = self.health_history.read().unwrap();
+		let skip = history.len().saturating_sub(count);
+		history.iter().skip(skip).cloned().collect()
+	}
+
+	fn record_health(&self) {
+		let health = self.health();
+		let mut history = self.health_history.write().unwrap();
+		if history.len() >= HEALTH_HISTORY_LENGTH {
+			history.pop_front();
+		}
+		history.push_back((now_msec(), health));
+	}
+
+	/// Health of the cluster if the given ring was used, given the nodes currently connected
+	fn ring_health(&self, ring: &Ring) -> ClusterHealth {
+		let replication_factor = ring.replication_factor;
+		let quorum = self.replication_mode_for(replication_factor).write_quorum();
 
 		let nodes = self
 			.get_known_nodes()
@@ -490,20 +830,36 @@ impl System {
 			.count();
 
 		let partitions = ring.partitions();
-		let partitions_n_up = partitions
+		let (partitions_n_up, zones_represented): (Vec<usize>, Vec<usize>) = partitions
 			.iter()
 			.map(|(_, h)| {
 				let pn = ring.get_nodes(h, ring.replication_factor);
-				pn.iter()
+				let up = pn
+					.iter()
 					.filter(|x| nodes.get(x).map(|n| n.is_up).unwrap_or(false))
-					.count()
+					.collect::<Vec<_>>();
+				let zones = up
+					.iter()
+					.filter_map(|x| ring.layout.node_role(x))
+					.map(|role| role.zone.as_str())
+					.collect::<HashSet<_>>();
+				(up.len(), zones.len())
 			})
-			.collect::<Vec<usize>>();
+			.unzip();
 		let partitions_all_ok = partitions_n_up
 			.iter()
 			.filter(|c| **c == replication_factor)
 			.count();
 		let partitions_quorum = partitions_n_up.iter().filter(|c| **c >= quorum).count();
+		let partition_replicas_ok = partitions_n_up.iter().sum::<usize>();
+
+		let draining_nodes = nodes
+			.iter()
+			.filter(|(id, n)| {
+				n.status.stored_blocks.unwrap_or(0) > 0
+					&& !matches!(ring.layout.node_role(id), Some(r) if r.capacity.is_some())
+			})
+			.count();
 
 		let status =
 			if partitions_quorum == partitions.len() && storage_nodes_ok == storage_nodes.len() {
@@ -523,6 +879,11 @@ impl System {
 			partitions: partitions.len(),
 			partitions_quorum,
 			partitions_all_ok,
+			partition_replicas: partitions.len() * replication_factor,
+			partition_replicas_ok,
+			zones_represented,
+			draining_nodes,
+			ping_timeout_msec: self.ping_timeout_msec.load(Ordering::Relaxed),
 		}
 	}
 
@@ -582,6 +943,13 @@ impl System {
 		}
 	}
 
+	/// Persist the current cluster layout and peer list to disk,
+	/// e.g. before the node shuts down
+	pub async fn save_state(&self) -> Result<(), Error> {
+		self.save_cluster_layout().await?;
+		self.save_peer_list().await
+	}
+
 	/// Save network configuration to disc
 	async fn save_cluster_layout(&self) -> Result<(), Error> {
 		let ring: Arc<Ring> = self.ring.borrow().clone();
@@ -592,10 +960,41 @@ impl System {
 		Ok(())
 	}
 
+	/// Change the hostname advertised by this node,
+	/// until it is restarted
+	pub fn set_hostname(&self, hostname: String) -> Result<(), Error> {
+		validate_hostname(&hostname)?;
+		let mut new_si: NodeStatus = self.local_status.load().as_ref().clone();
+		new_si.hostname = hostname;
+		// Other nodes will learn the new hostname at the next status exchange,
+		// but make it visible immediately in the status of this node
+		self.node_status
+			.write()
+			.unwrap()
+			.insert(self.id, (now_msec(), new_si.clone()));
+		self.local_status.swap(Arc::new(new_si));
+		Ok(())
+	}
+
+	/// Update the number of data blocks this node reports storing
+	pub fn set_stored_blocks(&self, stored_blocks: Option<u64>) {
+		let mut new_si: NodeStatus = self.local_status.load().as_ref().clone();
+		if new_si.stored_blocks == stored_blocks {
+			return;
+		}
+		new_si.stored_blocks = stored_blocks;
+		self.node_status
+			.write()
+			.unwrap()
+			.insert(self.id, (now_msec(), new_si.clone()));
+		self.local_status.swap(Arc::new(new_si));
+	}
+
 	fn update_local_status(&self) {
 		let mut new_si: NodeStatus = self.local_status.load().as_ref().clone();
 
 		let ring = self.ring.borrow();
+		new_si.replication_factor = ring.replication_factor;
 		new_si.cluster_layout_version = ring.layout.version;
 		new_si.cluster_layout_staging_hash = ring.layout.staging_hash;
 
@@ -616,6 +1015,246 @@ impl System {
 		SystemRpc::AdvertiseClusterLayout(ring.layout.clone())
 	}
 
+	async fn handle_drain_node(self: &Arc<Self>, node: Uuid) -> Result<SystemRpc, Error> {
+		let layout = self.get_cluster_layout();
+		if layout.has_staged_changes() {
+			return Err(Error::Message(
+				"The cluster layout has staged role changes, apply or revert them before draining a node.".into(),
+			));
+		}
+
+		let role = match layout.node_role(&node) {
+			Some(role) if role.capacity.is_some() => role.clone(),
+			_ => {
+				return Err(Error::Message(format!(
+					"Node {:?} is not a storage node in the current cluster layout",
+					node
+				)))
+			}
+		};
+
+		let mut layout = layout;
+		let drained_role = NodeRoleV(Some(NodeRole {
+			capacity: None,
+			..role
+		}));
+		layout
+			.staging
+			.merge(&layout.roles.update_mutator(node, drained_role));
+		let version = layout.version + 1;
+		let (layout, msg) = layout.apply_staged_changes(Some(version))?;
+
+		info!(
+			"Draining node {:?}: its storage role is removed in cluster layout version {}",
+			node, version
+		);
+		for line in msg.iter().filter(|l| !l.is_empty()) {
+			info!("{}", line);
+		}
+		self.update_cluster_layout(&layout).await?;
+		Ok(SystemRpc::Ok)
+	}
+
+	async fn handle_rollback_layout(self: &Arc<Self>, version: u64) -> Result<SystemRpc, Error> {
+		let layout = self.get_cluster_layout().rollback(version)?;
+		if let Err(e) = layout.check() {
+			return Err(Error::Message(format!(
+				"Cannot roll back to layout version {}, it is not valid anymore: {}",
+				version, e
+			)));
+		}
+
+		info!(
+			"Rolling back to cluster layout version {}, restored as version {}",
+			version, layout.version
+		);
+		self.update_cluster_layout(&layout).await?;
+		Ok(SystemRpc::Ok)
+	}
+
+	async fn handle_propose_replication_factor_change(
+		self: &Arc<Self>,
+		replication_factor: usize,
+	) -> Result<SystemRpc, Error> {
+		let mut layout = self.get_cluster_layout();
+		if layout.has_staged_changes() {
+			return Err(Error::Message(
+				"The cluster layout has staged changes, apply or revert them before changing the replication factor.".into(),
+			));
+		}
+		self.check_replication_factor(&layout, replication_factor)?;
+
+		// All nodes of the cluster will have to use the new replication factor:
+		// check that none of them refuses it, and that a quorum of them accept it
+		let nodes = layout.node_ids().to_vec();
+		let quorum = nodes.len() / 2 + 1;
+		let resps = self
+			.rpc
+			.call_many(
+				&self.system_endpoint,
+				&nodes,
+				SystemRpc::CheckReplicationFactor(replication_factor),
+				RequestStrategy::with_priority(PRIO_HIGH),
+			)
+			.await?;
+		let known_nodes = self.get_known_nodes();
+		let mut accepted = 0;
+		for (node, resp) in resps {
+			match resp {
+				Ok(SystemRpc::Ok) => accepted += 1,
+				Ok(m) => return Err(Error::unexpected_rpc_message(m)),
+				Err(e) => {
+					let is_up = known_nodes.iter().any(|n| n.id == node && n.is_up);
+					if is_up {
+						return Err(Error::Message(format!(
+							"Node {:?} cannot use replication factor {}: {}",
+							node, replication_factor, e
+						)));
+					}
+				}
+			}
+		}
+		if accepted < quorum {
+			return Err(Error::Message(format!(
+				"Only {} nodes out of {} could be checked for replication factor {}, at least {} are required.",
+				accepted,
+				nodes.len(),
+				replication_factor,
+				quorum
+			)));
+		}
+
+		layout.stage_replication_factor(replication_factor);
+
+		info!(
+			"Replication factor {} is staged for cluster layout version {}",
+			replication_factor,
+			layout.version + 1
+		);
+		self.update_cluster_layout(&layout).await?;
+		Ok(SystemRpc::Ok)
+	}
+
+	fn handle_check_replication_factor(
+		&self,
+		replication_factor: usize,
+	) -> Result<SystemRpc, Error> {
+		let layout = self.get_cluster_layout();
+		self.check_replication_factor(&layout, replication_factor)?;
+		Ok(SystemRpc::Ok)
+	}
+
+	/// Check that the replication factor of the cluster layout can be changed
+	/// to the given value, with the replication mode of this node
+	fn check_replication_factor(
+		&self,
+		layout: &ClusterLayout,
+		replication_factor: usize,
+	) -> Result<(), Error> {
+		if self
+			.replication_mode
+			.with_replication_factor(replication_factor)
+			.is_none()
+		{
+			return Err(Error::Message(format!(
+				"Replication factor {} is not supported, it must be 1, 2 or 3",
+				replication_factor
+			)));
+		}
+		if let Some(previous) = layout.previous_replication_factor {
+			return Err(Error::Message(format!(
+				"The replication factor is still being changed from {} to {}",
+				previous, layout.replication_factor
+			)));
+		}
+		if replication_factor == layout.replication_factor {
+			return Err(Error::Message(format!(
+				"The replication factor is already {}",
+				replication_factor
+			)));
+		}
+		Ok(())
+	}
+
+	fn handle_simulate_layout_apply(&self, layout: &ClusterLayout) -> Result<SystemRpc, Error> {
+		let (new_layout, _) = layout
+			.clone()
+			.apply_staged_changes(Some(layout.version + 1))?;
+		if let Err(e) = new_layout.check() {
+			return Err(Error::Message(format!(
+				"The new cluster layout would be invalid: {}",
+				e
+			)));
+		}
+
+		let old_ring = Ring::new(layout.clone());
+		let new_ring = Ring::new(new_layout.clone());
+
+		let moved_partition_replicas = if old_ring.partitions().is_empty() {
+			new_ring.partitions().len() * new_ring.replication_factor
+		} else {
+			new_ring
+				.partitions()
+				.iter()
+				.map(|(_, h)| {
+					let old_nodes = old_ring.get_nodes(h, old_ring.replication_factor);
+					new_ring
+						.get_nodes(h, new_ring.replication_factor)
+						.iter()
+						.filter(|n| !old_nodes.contains(n))
+						.count()
+				})
+				.sum()
+		};
+
+		// Estimate the size of a partition replica from the disk usage
+		// of the nodes that currently store partitions
+		let old_counts = layout.partition_counts();
+		let new_counts = new_layout.partition_counts();
+		let data_bytes = self
+			.get_known_nodes()
+			.into_iter()
+			.filter_map(|n| {
+				let (avail, total) = n.status.data_disk_avail?;
+				Some((n.id, total.saturating_sub(avail)))
+			})
+			.collect::<HashMap<_, _>>();
+		let (used, replicas) = old_counts
+			.iter()
+			.filter_map(|(id, count)| Some((*data_bytes.get(id)?, *count as u64)))
+			.fold((0, 0), |(u, r), (used, count)| (u + used, r + count));
+		let bytes_per_partition_replica = (replicas > 0).then(|| used / replicas);
+
+		let mut node_ids = old_counts
+			.keys()
+			.chain(new_counts.keys())
+			.collect::<Vec<_>>();
+		node_ids.sort();
+		node_ids.dedup();
+		let nodes = node_ids
+			.into_iter()
+			.map(|id| {
+				let partitions_after = new_counts.get(id).copied().unwrap_or(0);
+				NodePartitionChange {
+					id: *id,
+					partitions_before: old_counts.get(id).copied().unwrap_or(0),
+					partitions_after,
+					data_bytes: data_bytes.get(id).copied(),
+					projected_data_bytes: bytes_per_partition_replica
+						.map(|b| b * partitions_after as u64),
+				}
+			})
+			.collect::<Vec<_>>();
+
+		Ok(SystemRpc::LayoutApplySimulation(LayoutApplySimulation {
+			version: new_layout.version,
+			nodes,
+			moved_partition_replicas,
+			bytes_per_partition_replica,
+			health: self.ring_health(&new_ring),
+		}))
+	}
+
 	fn handle_get_known_nodes(&self) -> SystemRpc {
 		let known_nodes = self.get_known_nodes();
 		SystemRpc::ReturnKnownNodes(known_nodes)
@@ -628,7 +1267,12 @@ impl System {
 	) -> Result<SystemRpc, Error> {
 		let local_info = self.local_status.load();
 
-		if local_info.replication_factor < info.replication_factor {
+		// Nodes with different versions of the cluster layout can use different
+		// replication factors while a change of the replication factor is being
+		// propagated, but not nodes that use the same version of the layout
+		if info.cluster_layout_version == local_info.cluster_layout_version
+			&& local_info.replication_factor < info.replication_factor
+		{
 			error!("Some node have a higher replication factor ({}) than this one ({}). This is not supported and will lead to data corruption. Shutting down for safety.",
 				info.replication_factor,
 				local_info.replication_factor);
@@ -653,11 +1297,23 @@ impl System {
 		self: &Arc<Self>,
 		adv: &ClusterLayout,
 	) -> Result<SystemRpc, Error> {
-		if adv.replication_factor != self.replication_factor {
+		if self
+			.replication_mode
+			.with_replication_factor(adv.replication_factor)
+			.is_none()
+		{
 			let msg = format!(
-				"Received a cluster layout from another node with replication factor {}, which is different from what we have in our configuration ({}). Discarding the cluster layout we received.",
-				adv.replication_factor,
-				self.replication_factor
+				"Received a cluster layout from another node with replication factor {}, which is not supported. Discarding the cluster layout we received.",
+				adv.replication_factor
+			);
+			error!("{}", msg);
+			return Err(Error::Message(msg));
+		}
+		if adv.zone_redundancy != self.zone_redundancy {
+			let msg = format!(
+				"Received a cluster layout from another node with zone redundancy {}, which is different from what we have in our configuration ({}). Discarding the cluster layout we received.",
+				adv.zone_redundancy_string(),
+				self.ring.borrow().layout.zone_redundancy_string()
 			);
 			error!("{}", msg);
 			return Err(Error::Message(msg));
@@ -666,30 +1322,29 @@ impl System {
 		let update_ring = self.update_ring.lock().await;
 		let mut layout: ClusterLayout = self.ring.borrow().layout.clone();
 
-		let prev_layout_check = layout.check();
+		let prev_layout_check = layout.check().is_ok();
 		if layout.merge(adv) {
-			if prev_layout_check && !layout.check() {
-				error!("New cluster layout is invalid, discarding.");
-				return Err(Error::Message(
-					"New cluster layout is invalid, discarding.".into(),
-				));
+			if prev_layout_check {
+				if let Err(e) = layout.check() {
+					error!("New cluster layout is invalid, discarding: {}", e);
+					return Err(Error::Message(format!(
+						"New cluster layout is invalid, discarding: {}",
+						e
+					)));
+				}
 			}
 
-			let ring = Ring::new(layout.clone(), self.replication_factor);
+			let ring = Ring::new(layout.clone());
+			self.metrics
+				.values
+				.replication_factor
+				.store(ring.replication_factor as u64, Ordering::Relaxed);
 			update_ring.send(Arc::new(ring))?;
 			drop(update_ring);
 
 			let self2 = self.clone();
 			tokio::spawn(async move {
-				if let Err(e) = self2
-					.rpc
-					.broadcast(
-						&self2.system_endpoint,
-						SystemRpc::AdvertiseClusterLayout(layout),
-						RequestStrategy::with_priority(PRIO_HIGH),
-					)
-					.await
-				{
+				if let Err(e) = self2.broadcast_cluster_layout(layout).await {
 					warn!("Error while broadcasting new cluster layout: {}", e);
 				}
 			});
@@ -700,6 +1355,55 @@ impl System {
 		Ok(SystemRpc::Ok)
 	}
 
+	/// Send a cluster layout to all peers, compressed for those that support it
+	async fn broadcast_cluster_layout(&self, layout: ClusterLayout) -> Result<(), Error> {
+		let msg = SystemRpc::AdvertiseClusterLayout(layout);
+		let strat = RequestStrategy::with_priority(PRIO_HIGH);
+
+		let (to_compressed, to_plain): (Vec<Uuid>, Vec<Uuid>) = self
+			.fullmesh
+			.get_peer_list()
+			.iter()
+			.map(|p| Uuid::from(p.id))
+			.partition(|id| self.rpc_compression_enabled_for(*id));
+
+		let compressed = match self.rpc_compression_threshold {
+			Some(threshold) if !to_compressed.is_empty() => msg.clone().compress(threshold),
+			_ => msg.clone(),
+		};
+
+		let (r_compressed, r_plain) = join!(
+			self.rpc
+				.call_many(&self.system_endpoint, &to_compressed, compressed, strat),
+			self.rpc
+				.call_many(&self.system_endpoint, &to_plain, msg, strat),
+		);
+		r_compressed?;
+		r_plain?;
+		Ok(())
+	}
+
+	/// Whether messages sent to a node may be compressed: compression must be
+	/// enabled in our config, and the node must support it
+	fn rpc_compression_enabled_for(&self, node: Uuid) -> bool {
+		self.rpc_compression_threshold.is_some()
+			&& self
+				.node_status
+				.read()
+				.unwrap()
+				.get(&node)
+				.map(|(_, status)| status.rpc_compression)
+				.unwrap_or(false)
+	}
+
+	/// Compress a message sent to a node, if it is enabled for that node
+	fn compress_for(&self, node: Uuid, msg: SystemRpc) -> SystemRpc {
+		match self.rpc_compression_threshold {
+			Some(threshold) if self.rpc_compression_enabled_for(node) => msg.compress(threshold),
+			_ => msg,
+		}
+	}
+
 	async fn status_exchange_loop(&self, mut stop_signal: watch::Receiver<bool>) {
 		while !*stop_signal.borrow() {
 			let restart_at = Instant::now() + STATUS_EXCHANGE_INTERVAL;
@@ -716,6 +1420,8 @@ impl System {
 				)
 				.await;
 
+			self.record_health();
+
 			select! {
 				_ = tokio::time::sleep_until(restart_at.into()) => {},
 				_ = stop_signal.changed() => {},
@@ -723,10 +1429,35 @@ impl System {
 		}
 	}
 
+	async fn drain_rpc_on_exit(
+		&self,
+		mut must_exit: watch::Receiver<bool>,
+		netapp_exit: watch::Sender<bool>,
+	) {
+		while !*must_exit.borrow_and_update() {
+			if must_exit.changed().await.is_err() {
+				break;
+			}
+		}
+
+		info!("Waiting for in-flight RPCs to complete...");
+		let remaining = drain_rpc_handlers(self.rpc_drain_timeout).await;
+		if remaining > 0 {
+			warn!(
+				"{} RPCs did not complete within {} seconds, closing RPC connections anyway.",
+				remaining,
+				self.rpc_drain_timeout.as_secs()
+			);
+		}
+
+		let _ = netapp_exit.send(true);
+	}
+
 	async fn discovery_loop(self: &Arc<Self>, mut stop_signal: watch::Receiver<bool>) {
 		while !*stop_signal.borrow() {
-			let not_configured = !self.ring.borrow().layout.check();
-			let no_peers = self.fullmesh.get_peer_list().len() < self.replication_factor;
+			let not_configured = self.ring.borrow().layout.check().is_err();
+			let replication_factor = self.ring.borrow().replication_factor;
+			let no_peers = self.fullmesh.get_peer_list().len() < replication_factor;
 			let expected_n_nodes = self.ring.borrow().layout.num_nodes();
 			let bad_peers = self
 				.fullmesh
@@ -780,6 +1511,19 @@ impl System {
 					}
 				}
 
+				// Fetch peer list from DNS SRV records
+				#[cfg(feature = "dns-discovery")]
+				if let Some(d) = &self.dns_discovery {
+					match get_dns_nodes(d).await {
+						Ok(node_list) => {
+							ping_list.extend(node_list);
+						}
+						Err(e) => {
+							warn!("Could not retrieve node list from DNS: {}", e);
+						}
+					}
+				}
+
 				for (node_id, node_addr) in ping_list {
 					let self2 = self.clone();
 					tokio::spawn(async move {
@@ -843,7 +1587,8 @@ impl System {
 				RequestStrategy::with_priority(PRIO_HIGH),
 			)
 			.await;
-		if let Ok(SystemRpc::AdvertiseClusterLayout(layout)) = resp {
+		if let Ok(SystemRpc::AdvertiseClusterLayout(layout)) = resp.and_then(SystemRpc::decompress)
+		{
 			let _: Result<_, _> = self.handle_advertise_cluster_layout(&layout).await;
 		}
 	}
@@ -852,30 +1597,55 @@ impl System {
 #[async_trait]
 impl EndpointHandler<SystemRpc> for System {
 	async fn handle(self: &Arc<Self>, msg: &SystemRpc, from: NodeID) -> Result<SystemRpc, Error> {
+		let _running = RunningRpcHandler::start();
 		match msg {
+			SystemRpc::Compressed(_) => {
+				let msg = msg.clone().decompress()?;
+				EndpointHandler::handle(self, &msg, from).await
+			}
 			SystemRpc::Connect(node) => self.handle_connect(node).await,
-			SystemRpc::PullClusterLayout => Ok(self.handle_pull_cluster_layout()),
+			SystemRpc::PullClusterLayout => {
+				Ok(self.compress_for(from.into(), self.handle_pull_cluster_layout()))
+			}
 			SystemRpc::AdvertiseStatus(adv) => self.handle_advertise_status(from.into(), adv).await,
 			SystemRpc::AdvertiseClusterLayout(adv) => {
 				self.clone().handle_advertise_cluster_layout(adv).await
 			}
-			SystemRpc::GetKnownNodes => Ok(self.handle_get_known_nodes()),
+			SystemRpc::GetKnownNodes => {
+				Ok(self.compress_for(from.into(), self.handle_get_known_nodes()))
+			}
+			SystemRpc::Ping => Ok(SystemRpc::Ok),
+			SystemRpc::DrainNode(node) => self.handle_drain_node(*node).await,
+			SystemRpc::RollbackLayout(version) => self.handle_rollback_layout(*version).await,
+			SystemRpc::SimulateLayoutApply(layout) => self.handle_simulate_layout_apply(layout),
+			SystemRpc::ProposeReplicationFactorChange(replication_factor) => {
+				self.handle_propose_replication_factor_change(*replication_factor)
+					.await
+			}
+			SystemRpc::CheckReplicationFactor(replication_factor) => {
+				self.handle_check_replication_factor(*replication_factor)
+			}
 			m => Err(Error::unexpected_rpc_message(m)),
 		}
 	}
 }
 
 impl NodeStatus {
-	fn initial(replication_factor: usize, layout: &ClusterLayout) -> Self {
+	fn initial(layout: &ClusterLayout, hostname: Option<&str>) -> Self {
 		NodeStatus {
-			hostname: gethostname::gethostname()
-				.into_string()
-				.unwrap_or_else(|_| "<invalid utf-8>".to_string()),
-			replication_factor,
+			hostname: match hostname {
+				Some(h) => h.to_string(),
+				None => gethostname::gethostname()
+					.into_string()
+					.unwrap_or_else(|_| "<invalid utf-8>".to_string()),
+			},
+			replication_factor: layout.replication_factor,
 			cluster_layout_version: layout.version,
 			cluster_layout_staging_hash: layout.staging_hash,
 			meta_disk_avail: None,
 			data_disk_avail: None,
+			stored_blocks: None,
+			rpc_compression: true,
 		}
 	}
 
@@ -887,6 +1657,8 @@ impl NodeStatus {
 			cluster_layout_staging_hash: Hash::from([0u8; 32]),
 			meta_disk_avail: None,
 			data_disk_avail: None,
+			stored_blocks: None,
+			rpc_compression: false,
 		}
 	}
 
@@ -927,6 +1699,21 @@ impl NodeStatus {
 	}
 }
 
+/// Check that a hostname given by the user can be advertised to other nodes
+fn validate_hostname(hostname: &str) -> Result<(), Error> {
+	if hostname.is_empty() || hostname.len() > 255 {
+		return Err(Error::Message(
+			"Hostname must be between 1 and 255 characters long".into(),
+		));
+	}
+	if hostname.contains('\0') {
+		return Err(Error::Message(
+			"Hostname must not contain null bytes".into(),
+		));
+	}
+	Ok(())
+}
+
 fn get_default_ip() -> Option<IpAddr> {
 	pnet_datalink::interfaces()
 		.iter()
@@ -961,3 +1748,45 @@ fn connect_error_message(
 ) -> String {
 	format!("Error establishing RPC connection to remote node: {}@{}.\nThis can happen if the remote node is not reachable on the network, but also if the two nodes are not configured with the same rpc_secret.\n{}", hex::encode(pubkey), addr, e)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_util::crdt::LwwMap;
+
+	#[test]
+	fn test_compress_layout_advertisement() {
+		// Cluster layout advertisement for a 50-node cluster
+		let mut layout = ClusterLayout::new(3, None);
+		for i in 0..50 {
+			layout.staging.merge(&LwwMap::raw_item(
+				gen_uuid(),
+				1,
+				NodeRoleV(Some(NodeRole {
+					zone: format!("dc{}", i % 5),
+					rack: None,
+					capacity: Some(100),
+					tags: [("node".to_string(), i.to_string())].into(),
+				})),
+			));
+		}
+		let layout = layout.apply_staged_changes(Some(1)).unwrap().0;
+		let msg = SystemRpc::AdvertiseClusterLayout(layout);
+		let plain_len = nonversioned_encode(&msg).unwrap().len();
+
+		// Messages under the threshold are left as is
+		assert!(matches!(
+			msg.clone().compress(plain_len + 1),
+			SystemRpc::AdvertiseClusterLayout(_)
+		));
+
+		let compressed = msg.compress(1024);
+		let compressed_len = nonversioned_encode(&compressed).unwrap().len();
+		assert!(compressed_len < plain_len);
+
+		match compressed.decompress().unwrap() {
+			SystemRpc::AdvertiseClusterLayout(layout) => assert_eq!(layout.version, 1),
+			m => panic!("unexpected message: {:?}", m),
+		}
+	}
+}
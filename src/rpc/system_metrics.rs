@@ -14,6 +14,7 @@ pub struct SystemMetrics {
 
 #[derive(Default)]
 pub struct SystemMetricsValues {
+	pub(crate) replication_factor: AtomicU64,
 	pub(crate) data_disk_total: AtomicU64,
 	pub(crate) data_disk_avail: AtomicU64,
 	pub(crate) meta_disk_total: AtomicU64,
@@ -26,6 +27,10 @@ impl SystemMetrics {
 		let values = Arc::new(SystemMetricsValues::default());
 		let values1 = values.clone();
 		let values2 = values.clone();
+		let values3 = values.clone();
+		values
+			.replication_factor
+			.store(replication_factor as u64, Ordering::Relaxed);
 		Self {
 			_garage_build_info: meter
 				.u64_value_observer("garage_build_info", move |observer| {
@@ -41,9 +46,9 @@ impl SystemMetrics {
 				.init(),
 			_replication_factor: meter
 				.u64_value_observer("garage_replication_factor", move |observer| {
-					observer.observe(replication_factor as u64, &[])
+					observer.observe(values3.replication_factor.load(Ordering::Relaxed), &[])
 				})
-				.with_description("Garage replication factor setting")
+				.with_description("Garage replication factor of the cluster layout")
 				.init(),
 			_disk_avail: meter
 				.u64_value_observer("garage_local_disk_avail", move |observer| {
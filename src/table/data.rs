@@ -1,6 +1,7 @@
 use core::borrow::Borrow;
 use std::convert::TryInto;
 use std::sync::Arc;
+use std::time::Duration;
 
 use serde_bytes::ByteBuf;
 use tokio::sync::Notify;
@@ -43,9 +44,7 @@ pub struct TableData<F: TableSchema, R: TableReplication> {
 
 impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 	pub fn new(system: Arc<System>, instance: F, replication: R, db: &db::Db) -> Arc<Self> {
-		let store = db
-			.open_tree(format!("{}:table", F::TABLE_NAME))
-			.expect("Unable to open DB tree");
+		let store = Self::open_store(db);
 
 		let merkle_tree = db
 			.open_tree(format!("{}:merkle_tree", F::TABLE_NAME))
@@ -86,6 +85,13 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		})
 	}
 
+	/// Open the tree in which the entries of the table are stored. This allows
+	/// other tables to read them in their hooks, even if they are created first.
+	pub fn open_store(db: &db::Db) -> db::Tree {
+		db.open_tree(format!("{}:table", F::TABLE_NAME))
+			.expect("Unable to open DB tree")
+	}
+
 	// Read functions
 
 	pub fn read_entry(&self, p: &F::P, s: &F::S) -> Result<Option<ByteBuf>, Error> {
@@ -266,6 +272,33 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		}
 	}
 
+	/// Queue a tombstone stored in the table for deletion by the table GC,
+	/// which will remove it from all nodes once `delay` has elapsed.
+	/// This is used to clean up tombstones that were not added to the GC
+	/// queue when they were written. Returns false if the entry is not a
+	/// tombstone, or if this node is not the one responsible for GC'ing it.
+	pub fn queue_tombstone_gc(
+		&self,
+		tree_key: &[u8],
+		entry_bytes: &[u8],
+		delay: Duration,
+	) -> Result<bool, Error> {
+		if !self.decode_entry(entry_bytes)?.is_tombstone() {
+			return Ok(false);
+		}
+
+		// Same as in update_entry_with: only the leader of the partition GC's the item
+		let pk_hash = Hash::try_from(&tree_key[..32]).unwrap();
+		let nodes = self.replication.write_nodes(&pk_hash);
+		if nodes.first() != Some(&self.system.id) {
+			return Ok(false);
+		}
+
+		GcTodoEntry::new_with_delay(tree_key.to_vec(), blake2sum(entry_bytes), delay)
+			.save(&self.gc_todo)?;
+		Ok(true)
+	}
+
 	pub(crate) fn delete_if_equal(self: &Arc<Self>, k: &[u8], v: &[u8]) -> Result<bool, Error> {
 		let removed = self
 			.store
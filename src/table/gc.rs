@@ -17,6 +17,7 @@ use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::time::*;
 
+use garage_rpc::drain::RunningRpcHandler;
 use garage_rpc::system::System;
 use garage_rpc::*;
 
@@ -275,6 +276,7 @@ impl<F: TableSchema, R: TableReplication> TableGc<F, R> {
 #[async_trait]
 impl<F: TableSchema, R: TableReplication> EndpointHandler<GcRpc> for TableGc<F, R> {
 	async fn handle(self: &Arc<Self>, message: &GcRpc, _from: NodeID) -> Result<GcRpc, Error> {
+		let _running = RunningRpcHandler::start();
 		match message {
 			GcRpc::Update(items) => {
 				self.data.update_many(items)?;
@@ -365,6 +367,19 @@ impl GcTodoEntry {
 		}
 	}
 
+	/// Creates a new GcTodoEntry (not saved in Sled) for an entry that
+	/// must not be deleted before `delay` has elapsed. Delays shorter than
+	/// the default GC delay are not allowed, they are extended to it.
+	pub(crate) fn new_with_delay(key: Vec<u8>, value_hash: Hash, delay: Duration) -> Self {
+		let extra_delay = delay.saturating_sub(TABLE_GC_DELAY);
+		Self {
+			tombstone_timestamp: now_msec() + extra_delay.as_millis() as u64,
+			key,
+			value_hash,
+			value: None,
+		}
+	}
+
 	/// Parses a GcTodoEntry from a (k, v) pair stored in the gc_todo tree
 	pub(crate) fn parse(db_k: &[u8], db_v: &[u8]) -> Self {
 		Self {
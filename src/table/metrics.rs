@@ -1,3 +1,6 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use opentelemetry::{global, metrics::*, KeyValue};
 
 use garage_db as db;
@@ -9,6 +12,7 @@ pub struct TableMetrics {
 	pub(crate) _merkle_tree_size: ValueObserver<u64>,
 	pub(crate) _merkle_todo_len: ValueObserver<u64>,
 	pub(crate) _gc_todo_len: ValueObserver<u64>,
+	pub(crate) _sync_partitions_in_progress: ValueObserver<u64>,
 
 	pub(crate) get_request_counter: BoundCounter<u64>,
 	pub(crate) get_request_duration: BoundValueRecorder<f64>,
@@ -20,6 +24,7 @@ pub struct TableMetrics {
 
 	pub(crate) sync_items_sent: Counter<u64>,
 	pub(crate) sync_items_received: Counter<u64>,
+	pub(crate) sync_partitions_in_progress: Arc<AtomicU64>,
 }
 impl TableMetrics {
 	pub fn new(
@@ -30,6 +35,8 @@ impl TableMetrics {
 		gc_todo: CountedTree,
 	) -> Self {
 		let meter = global::meter(table_name);
+		let sync_partitions_in_progress = Arc::new(AtomicU64::new(0));
+		let sync_in_progress = sync_partitions_in_progress.clone();
 		TableMetrics {
 			_table_size: meter
 				.u64_value_observer(
@@ -85,6 +92,18 @@ impl TableMetrics {
 				)
 				.with_description("Table garbage collector TODO queue length")
 				.init(),
+			_sync_partitions_in_progress: meter
+				.u64_value_observer(
+					"table.sync_partitions_in_progress",
+					move |observer| {
+						observer.observe(
+							sync_in_progress.load(Ordering::Relaxed),
+							&[KeyValue::new("table_name", table_name)],
+						);
+					},
+				)
+				.with_description("Number of partitions being synchronized with other nodes")
+				.init(),
 
 			get_request_counter: meter
 				.u64_counter("table.get_request_counter")
@@ -126,6 +145,7 @@ impl TableMetrics {
 				.u64_counter("table.sync_items_received")
 				.with_description("Number of data items received from other nodes during resync procedures")
 				.init(),
+			sync_partitions_in_progress,
 		}
 	}
 }
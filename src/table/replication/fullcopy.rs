@@ -22,7 +22,7 @@ impl TableReplication for TableFullReplication {
 	fn read_nodes(&self, _hash: &Hash) -> Vec<Uuid> {
 		vec![self.system.id]
 	}
-	fn read_quorum(&self) -> usize {
+	fn read_quorum(&self, _hash: &Hash) -> usize {
 		1
 	}
 
@@ -30,7 +30,7 @@ impl TableReplication for TableFullReplication {
 		let ring = self.system.ring.borrow();
 		ring.layout.node_ids().to_vec()
 	}
-	fn write_quorum(&self) -> usize {
+	fn write_quorum(&self, _hash: &Hash) -> usize {
 		let nmembers = self.system.ring.borrow().layout.node_ids().len();
 		if nmembers > self.max_faults {
 			nmembers - self.max_faults
@@ -38,7 +38,7 @@ impl TableReplication for TableFullReplication {
 			1
 		}
 	}
-	fn max_write_errors(&self) -> usize {
+	fn max_write_errors(&self, _hash: &Hash) -> usize {
 		self.max_faults
 	}
 
@@ -9,13 +9,13 @@ pub trait TableReplication: Send + Sync + 'static {
 	/// Which nodes to send read requests to
 	fn read_nodes(&self, hash: &Hash) -> Vec<Uuid>;
 	/// Responses needed to consider a read succesfull
-	fn read_quorum(&self) -> usize;
+	fn read_quorum(&self, hash: &Hash) -> usize;
 
 	/// Which nodes to send writes to
 	fn write_nodes(&self, hash: &Hash) -> Vec<Uuid>;
 	/// Responses needed to consider a write succesfull
-	fn write_quorum(&self) -> usize;
-	fn max_write_errors(&self) -> usize;
+	fn write_quorum(&self, hash: &Hash) -> usize;
+	fn max_write_errors(&self, hash: &Hash) -> usize;
 
 	// Accessing partitions, for Merkle tree & sync
 	/// Get partition for data with given hash
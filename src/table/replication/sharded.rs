@@ -12,36 +12,64 @@ use crate::replication::*;
 ///   entry's hash in the ring
 /// - reads are done on all of the nodes that replicate the data
 /// - writes as well
+///
+/// The number of nodes is the replication factor of the cluster layout,
+/// which can be changed while the cluster is running, so the quorums are
+/// those of the replication mode of the system for that replication factor.
+/// While it is being changed, the nodes of the assignations before and after
+/// the change are used, and the quorums are reached in both of them.
 #[derive(Clone)]
 pub struct TableShardedReplication {
 	/// The membership manager of this node
 	pub system: Arc<System>,
-	/// How many time each data should be replicated
-	pub replication_factor: usize,
-	/// How many nodes to contact for a read, should be at most `replication_factor`
-	pub read_quorum: usize,
-	/// How many nodes to contact for a write, should be at most `replication_factor`
-	pub write_quorum: usize,
+	/// Read from a single node instead of a read quorum,
+	/// for data that is never modified once written
+	pub read_one: bool,
+}
+
+impl TableShardedReplication {
+	fn nodes_and_quorum(&self, hash: &Hash, quorum: impl Fn(usize) -> usize) -> (Vec<Uuid>, usize) {
+		self.system
+			.ring
+			.borrow()
+			.get_nodes_with_quorum(hash, quorum)
+	}
+
+	fn read_quorum_for(&self, replication_factor: usize) -> usize {
+		if self.read_one {
+			1
+		} else {
+			self.system
+				.replication_mode_for(replication_factor)
+				.read_quorum()
+		}
+	}
+
+	fn write_quorum_for(&self, replication_factor: usize) -> usize {
+		self.system
+			.replication_mode_for(replication_factor)
+			.write_quorum()
+	}
 }
 
 impl TableReplication for TableShardedReplication {
 	fn read_nodes(&self, hash: &Hash) -> Vec<Uuid> {
-		let ring = self.system.ring.borrow();
-		ring.get_nodes(hash, self.replication_factor)
+		self.nodes_and_quorum(hash, |_| 0).0
 	}
-	fn read_quorum(&self) -> usize {
-		self.read_quorum
+	fn read_quorum(&self, hash: &Hash) -> usize {
+		self.nodes_and_quorum(hash, |rf| self.read_quorum_for(rf)).1
 	}
 
 	fn write_nodes(&self, hash: &Hash) -> Vec<Uuid> {
-		let ring = self.system.ring.borrow();
-		ring.get_nodes(hash, self.replication_factor)
+		self.nodes_and_quorum(hash, |_| 0).0
 	}
-	fn write_quorum(&self) -> usize {
-		self.write_quorum
+	fn write_quorum(&self, hash: &Hash) -> usize {
+		self.nodes_and_quorum(hash, |rf| self.write_quorum_for(rf))
+			.1
 	}
-	fn max_write_errors(&self) -> usize {
-		self.replication_factor - self.write_quorum
+	fn max_write_errors(&self, hash: &Hash) -> usize {
+		let (nodes, quorum) = self.nodes_and_quorum(hash, |rf| self.write_quorum_for(rf));
+		nodes.len().saturating_sub(quorum)
 	}
 
 	fn partition_of(&self, hash: &Hash) -> Partition {
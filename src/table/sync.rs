@@ -1,4 +1,5 @@
 use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -13,10 +14,12 @@ use tokio::select;
 use tokio::sync::{mpsc, watch};
 
 use garage_util::background::*;
+use garage_util::config::SyncPriority;
 use garage_util::data::*;
 use garage_util::encode::{debug_serialize, nonversioned_encode};
 use garage_util::error::{Error, OkOrMessage};
 
+use garage_rpc::drain::RunningRpcHandler;
 use garage_rpc::ring::*;
 use garage_rpc::system::System;
 use garage_rpc::*;
@@ -26,15 +29,21 @@ use crate::merkle::*;
 use crate::replication::*;
 use crate::*;
 
-// Do anti-entropy every 10 minutes
+// Do anti-entropy every 10 minutes for tables with normal priority,
+// more or less often for tables with high or low priority
 const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const ANTI_ENTROPY_INTERVAL_HIGH_PRIO: Duration = Duration::from_secs(3 * 60);
+const ANTI_ENTROPY_INTERVAL_LOW_PRIO: Duration = Duration::from_secs(30 * 60);
 
 pub struct TableSyncer<F: TableSchema, R: TableReplication> {
 	system: Arc<System>,
 	data: Arc<TableData<F, R>>,
 	merkle: Arc<MerkleUpdater<F, R>>,
+	priority: SyncPriority,
+	/// Number of workers, each synchronizing a shard of the partitions
+	parallelism: usize,
 
-	add_full_sync_tx: ArcSwapOption<mpsc::UnboundedSender<()>>,
+	add_full_sync_tx: ArcSwapOption<Vec<mpsc::UnboundedSender<()>>>,
 	endpoint: Arc<Endpoint<SyncRpc, Self>>,
 }
 
@@ -67,6 +76,8 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 		system: Arc<System>,
 		data: Arc<TableData<F, R>>,
 		merkle: Arc<MerkleUpdater<F, R>>,
+		priority: SyncPriority,
+		parallelism: usize,
 	) -> Arc<Self> {
 		let endpoint = system
 			.netapp
@@ -76,6 +87,8 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 			system,
 			data,
 			merkle,
+			priority,
+			parallelism: parallelism.max(1),
 			add_full_sync_tx: ArcSwapOption::new(None),
 			endpoint,
 		});
@@ -85,29 +98,54 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 	}
 
 	pub(crate) fn spawn_workers(self: &Arc<Self>, bg: &BackgroundRunner) {
-		let (add_full_sync_tx, add_full_sync_rx) = mpsc::unbounded_channel();
+		let mut add_full_sync_txs = vec![];
+		for shard in 0..self.parallelism {
+			let (add_full_sync_tx, add_full_sync_rx) = mpsc::unbounded_channel();
+			add_full_sync_txs.push(add_full_sync_tx);
+
+			bg.spawn_worker(SyncWorker {
+				syncer: self.clone(),
+				shard,
+				ring_recv: self.system.ring.clone(),
+				ring: self.system.ring.borrow().clone(),
+				add_full_sync_rx,
+				todo: vec![],
+				next_full_sync: Instant::now() + Duration::from_secs(20),
+			});
+		}
 		self.add_full_sync_tx
-			.store(Some(Arc::new(add_full_sync_tx)));
-
-		bg.spawn_worker(SyncWorker {
-			syncer: self.clone(),
-			ring_recv: self.system.ring.clone(),
-			ring: self.system.ring.borrow().clone(),
-			add_full_sync_rx,
-			todo: vec![],
-			next_full_sync: Instant::now() + Duration::from_secs(20),
-		});
+			.store(Some(Arc::new(add_full_sync_txs)));
 	}
 
 	pub fn add_full_sync(&self) -> Result<(), Error> {
-		let tx = self.add_full_sync_tx.load();
-		let tx = tx
+		let txs = self.add_full_sync_tx.load();
+		let txs = txs
 			.as_ref()
 			.ok_or_message("table sync worker is not running")?;
-		tx.send(()).ok_or_message("send error")?;
+		for tx in txs.iter() {
+			tx.send(()).ok_or_message("send error")?;
+		}
 		Ok(())
 	}
 
+	/// Priority of the RPCs sent to synchronize this table
+	fn rpc_priority(&self) -> RequestPriority {
+		match self.priority {
+			SyncPriority::High => PRIO_NORMAL | PRIO_SECONDARY,
+			SyncPriority::Normal => PRIO_BACKGROUND,
+			SyncPriority::Low => PRIO_BACKGROUND | PRIO_SECONDARY,
+		}
+	}
+
+	/// Interval between two full synchronizations of this table
+	fn anti_entropy_interval(&self) -> Duration {
+		match self.priority {
+			SyncPriority::High => ANTI_ENTROPY_INTERVAL_HIGH_PRIO,
+			SyncPriority::Normal => ANTI_ENTROPY_INTERVAL,
+			SyncPriority::Low => ANTI_ENTROPY_INTERVAL_LOW_PRIO,
+		}
+	}
+
 	// ----
 
 	async fn sync_partition(
@@ -147,7 +185,7 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 					warn!("({}) Sync error: {}", F::TABLE_NAME, e);
 				}
 			}
-			if n_errors > self.data.replication.max_write_errors() {
+			if n_errors > self.data.replication.max_write_errors(&partition.begin) {
 				return Err(Error::Message(format!(
 					"Sync failed with too many nodes (should have been: {:?}).",
 					nodes
@@ -201,7 +239,7 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 					);
 					break;
 				}
-				if nodes.len() < self.data.replication.write_quorum() {
+				if nodes.len() < self.data.replication.write_quorum(begin) {
 					return Err(Error::Message(
 						"Not offloading as we don't have a quorum of nodes to write to."
 							.to_string(),
@@ -249,7 +287,7 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 				&self.endpoint,
 				nodes,
 				SyncRpc::Items(values),
-				RequestStrategy::with_priority(PRIO_BACKGROUND).with_quorum(nodes.len()),
+				RequestStrategy::with_priority(self.rpc_priority()).with_quorum(nodes.len()),
 			)
 			.await?;
 
@@ -310,7 +348,7 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 				&self.endpoint,
 				who,
 				SyncRpc::RootCkHash(partition.partition, root_ck_hash),
-				RequestStrategy::with_priority(PRIO_BACKGROUND),
+				RequestStrategy::with_priority(self.rpc_priority()),
 			)
 			.await?;
 
@@ -366,7 +404,7 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 							&self.endpoint,
 							who,
 							SyncRpc::GetNode(key.clone()),
-							RequestStrategy::with_priority(PRIO_BACKGROUND),
+							RequestStrategy::with_priority(self.rpc_priority()),
 						)
 						.await?
 					{
@@ -442,7 +480,7 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 				&self.endpoint,
 				who,
 				SyncRpc::Items(values),
-				RequestStrategy::with_priority(PRIO_BACKGROUND),
+				RequestStrategy::with_priority(self.rpc_priority()),
 			)
 			.await?;
 		if let SyncRpc::Ok = rpc_resp {
@@ -458,6 +496,7 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 #[async_trait]
 impl<F: TableSchema, R: TableReplication> EndpointHandler<SyncRpc> for TableSyncer<F, R> {
 	async fn handle(self: &Arc<Self>, message: &SyncRpc, from: NodeID) -> Result<SyncRpc, Error> {
+		let _running = RunningRpcHandler::start();
 		match message {
 			SyncRpc::RootCkHash(range, h) => {
 				let (_root_ck_key, root_ck) = self.get_root_ck(*range)?;
@@ -492,6 +531,8 @@ impl<F: TableSchema, R: TableReplication> EndpointHandler<SyncRpc> for TableSync
 
 struct SyncWorker<F: TableSchema, R: TableReplication> {
 	syncer: Arc<TableSyncer<F, R>>,
+	/// Index of the shard of the partitions synchronized by this worker
+	shard: usize,
 	ring_recv: watch::Receiver<Arc<Ring>>,
 	ring: Arc<Ring>,
 	add_full_sync_rx: mpsc::UnboundedReceiver<()>,
@@ -506,11 +547,22 @@ impl<F: TableSchema, R: TableReplication> SyncWorker<F, R> {
 
 		let my_id = system.id;
 
+		// While the replication factor of the cluster is being changed, the items
+		// of the partitions we no longer store are kept until the change is
+		// finished, so that data blocks are not deleted before their new
+		// replicas have been made
+		let replication_factor_changing = self.ring.layout.previous_replication_factor.is_some();
+
 		self.todo.clear();
 
 		let partitions = data.replication.partitions();
 
 		for i in 0..partitions.len() {
+			// The partitions are split in contiguous shards, one for each worker
+			if i * self.syncer.parallelism / partitions.len() != self.shard {
+				continue;
+			}
+
 			let begin = partitions[i].1;
 
 			let end = if i + 1 < partitions.len() {
@@ -522,6 +574,9 @@ impl<F: TableSchema, R: TableReplication> SyncWorker<F, R> {
 			let nodes = data.replication.write_nodes(&begin);
 
 			let retain = nodes.contains(&my_id);
+			if !retain && replication_factor_changing {
+				continue;
+			}
 			if !retain {
 				// Check if we have some data to send, otherwise skip
 				match data.store.range(begin..end) {
@@ -545,7 +600,7 @@ impl<F: TableSchema, R: TableReplication> SyncWorker<F, R> {
 			});
 		}
 
-		self.next_full_sync = Instant::now() + ANTI_ENTROPY_INTERVAL;
+		self.next_full_sync = Instant::now() + self.syncer.anti_entropy_interval();
 	}
 
 	fn pop_task(&mut self) -> Option<TodoPartition> {
@@ -567,7 +622,11 @@ impl<F: TableSchema, R: TableReplication> SyncWorker<F, R> {
 #[async_trait]
 impl<F: TableSchema, R: TableReplication> Worker for SyncWorker<F, R> {
 	fn name(&self) -> String {
-		format!("{} sync", F::TABLE_NAME)
+		if self.syncer.parallelism > 1 {
+			format!("{} sync #{}", F::TABLE_NAME, self.shard + 1)
+		} else {
+			format!("{} sync", F::TABLE_NAME)
+		}
 	}
 
 	fn status(&self) -> WorkerStatus {
@@ -579,7 +638,11 @@ impl<F: TableSchema, R: TableReplication> Worker for SyncWorker<F, R> {
 
 	async fn work(&mut self, must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
 		if let Some(partition) = self.pop_task() {
-			self.syncer.sync_partition(&partition, must_exit).await?;
+			let in_progress = &self.syncer.data.metrics.sync_partitions_in_progress;
+			in_progress.fetch_add(1, Ordering::Relaxed);
+			let res = self.syncer.sync_partition(&partition, must_exit).await;
+			in_progress.fetch_sub(1, Ordering::Relaxed);
+			res?;
 			Ok(WorkerState::Busy)
 		} else {
 			Ok(WorkerState::Idle)
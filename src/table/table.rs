@@ -15,11 +15,13 @@ use opentelemetry::{
 use garage_db as db;
 
 use garage_util::background::BackgroundRunner;
+use garage_util::config::SyncPriority;
 use garage_util::data::*;
 use garage_util::error::Error;
 use garage_util::metrics::RecordDuration;
 use garage_util::migrate::Migrate;
 
+use garage_rpc::drain::RunningRpcHandler;
 use garage_rpc::system::System;
 use garage_rpc::*;
 
@@ -68,7 +70,14 @@ impl<F: TableSchema> Rpc for TableRpc<F> {
 impl<F: TableSchema, R: TableReplication> Table<F, R> {
 	// =============== PUBLIC INTERFACE FUNCTIONS (new, insert, get, etc) ===============
 
-	pub fn new(instance: F, replication: R, system: Arc<System>, db: &db::Db) -> Arc<Self> {
+	pub fn new(
+		instance: F,
+		replication: R,
+		system: Arc<System>,
+		db: &db::Db,
+		sync_priority: SyncPriority,
+		sync_parallelism: usize,
+	) -> Arc<Self> {
 		let endpoint = system
 			.netapp
 			.endpoint(format!("garage_table/table.rs/Rpc:{}", F::TABLE_NAME));
@@ -77,7 +86,13 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 
 		let merkle_updater = MerkleUpdater::new(data.clone());
 
-		let syncer = TableSyncer::new(system.clone(), data.clone(), merkle_updater.clone());
+		let syncer = TableSyncer::new(
+			system.clone(),
+			data.clone(),
+			merkle_updater.clone(),
+			sync_priority,
+			sync_parallelism,
+		);
 		let gc = TableGc::new(system.clone(), data.clone());
 
 		let table = Arc::new(Self {
@@ -129,7 +144,7 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				&who[..],
 				rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL)
-					.with_quorum(self.data.replication.write_quorum()),
+					.with_quorum(self.data.replication.write_quorum(&hash)),
 			)
 			.await?;
 
@@ -165,11 +180,17 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		IE: Borrow<F::E> + Send + Sync,
 	{
 		let mut call_list: HashMap<_, Vec<_>> = HashMap::new();
+		// The errors allowed are those allowed for the entry which allows the fewest
+		let mut max_write_errors = usize::MAX;
 
 		for entry in entries.into_iter() {
 			let entry = entry.borrow();
 			let hash = entry.partition_key().hash();
 			let who = self.data.replication.write_nodes(&hash);
+			max_write_errors = std::cmp::min(
+				max_write_errors,
+				self.data.replication.max_write_errors(&hash),
+			);
 			let e_enc = Arc::new(ByteBuf::from(entry.encode()?));
 			for node in who {
 				call_list.entry(node).or_default().push(e_enc.clone());
@@ -199,7 +220,7 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				errors.push(e);
 			}
 		}
-		if errors.len() > self.data.replication.max_write_errors() {
+		if errors.len() > max_write_errors {
 			Err(Error::Message("Too many errors".into()))
 		} else {
 			Ok(())
@@ -242,7 +263,7 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				&who[..],
 				rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL)
-					.with_quorum(self.data.replication.read_quorum())
+					.with_quorum(self.data.replication.read_quorum(&hash))
 					.interrupt_after_quorum(true),
 			)
 			.await?;
@@ -338,7 +359,7 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				&who[..],
 				rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL)
-					.with_quorum(self.data.replication.read_quorum())
+					.with_quorum(self.data.replication.read_quorum(&hash))
 					.interrupt_after_quorum(true),
 			)
 			.await?;
@@ -430,6 +451,7 @@ impl<F: TableSchema, R: TableReplication> EndpointHandler<TableRpc<F>> for Table
 		msg: &TableRpc<F>,
 		_from: NodeID,
 	) -> Result<TableRpc<F>, Error> {
+		let _running = RunningRpcHandler::start();
 		match msg {
 			TableRpc::ReadEntry(key, sort_key) => {
 				let value = self.data.read_entry(key, sort_key)?;
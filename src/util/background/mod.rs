@@ -4,6 +4,7 @@ pub mod vars;
 pub mod worker;
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
@@ -16,6 +17,7 @@ pub use worker::{Worker, WorkerState};
 pub struct BackgroundRunner {
 	send_worker: mpsc::UnboundedSender<Box<dyn Worker>>,
 	worker_info: Arc<std::sync::Mutex<HashMap<usize, WorkerInfo>>>,
+	worker_paused: Arc<std::sync::Mutex<HashMap<usize, Arc<AtomicBool>>>>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -26,6 +28,8 @@ pub struct WorkerInfo {
 	pub errors: usize,
 	pub consecutive_errors: usize,
 	pub last_error: Option<(String, u64)>,
+	#[serde(default)]
+	pub paused: bool,
 }
 
 /// WorkerStatus is a struct returned by the worker with a bunch of canonical
@@ -45,8 +49,13 @@ impl BackgroundRunner {
 		let (send_worker, worker_out) = mpsc::unbounded_channel::<Box<dyn Worker>>();
 
 		let worker_info = Arc::new(std::sync::Mutex::new(HashMap::new()));
-		let mut worker_processor =
-			WorkerProcessor::new(worker_out, stop_signal, worker_info.clone());
+		let worker_paused = Arc::new(std::sync::Mutex::new(HashMap::new()));
+		let mut worker_processor = WorkerProcessor::new(
+			worker_out,
+			stop_signal,
+			worker_info.clone(),
+			worker_paused.clone(),
+		);
 
 		let await_all_done = tokio::spawn(async move {
 			worker_processor.run().await;
@@ -55,6 +64,7 @@ impl BackgroundRunner {
 		let bgrunner = Arc::new(Self {
 			send_worker,
 			worker_info,
+			worker_paused,
 		});
 		(bgrunner, await_all_done)
 	}
@@ -63,6 +73,20 @@ impl BackgroundRunner {
 		self.worker_info.lock().unwrap().clone()
 	}
 
+	/// Pause or resume a worker: a paused worker does no work until it is
+	/// resumed. Returns false if there is no running worker with this task id.
+	pub fn set_worker_paused(&self, tid: usize, paused: bool) -> bool {
+		let flag = match self.worker_paused.lock().unwrap().get(&tid) {
+			Some(flag) => flag.clone(),
+			None => return false,
+		};
+		flag.store(paused, Ordering::Relaxed);
+		if let Some(info) = self.worker_info.lock().unwrap().get_mut(&tid) {
+			info.paused = paused;
+		}
+		true
+	}
+
 	pub fn spawn_worker<W>(&self, worker: W)
 	where
 		W: Worker + 'static,
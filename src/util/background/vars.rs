@@ -51,6 +51,17 @@ impl BgVars {
 		self.vars.insert(name, Box::new(BgVar { get_fn, set_fn }));
 	}
 
+	/// Register a variable that is not persisted, such as a setting of the
+	/// configuration file that can be changed while the node is running
+	pub fn register_rw_fn<T, GF, SF>(&mut self, name: &'static str, get_fn: GF, set_fn: SF)
+	where
+		T: FromStr + ToString + Send + Sync + 'static,
+		GF: Fn() -> T + Send + Sync + 'static,
+		SF: Fn(T) -> Result<(), Error> + Send + Sync + 'static,
+	{
+		self.vars.insert(name, Box::new(BgVar { get_fn, set_fn }));
+	}
+
 	pub fn get(&self, var: &str) -> Result<String, Error> {
 		Ok(self
 			.vars
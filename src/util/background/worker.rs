@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -18,6 +19,9 @@ use crate::time::now_msec;
 // will be interrupted in the middle of whatever they are doing.
 const EXIT_DEADLINE: Duration = Duration::from_secs(8);
 
+// Paused workers check at this interval whether they were resumed
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
 pub enum WorkerState {
 	Busy,
@@ -62,6 +66,7 @@ pub(crate) struct WorkerProcessor {
 	stop_signal: watch::Receiver<bool>,
 	worker_chan: mpsc::UnboundedReceiver<Box<dyn Worker>>,
 	worker_info: Arc<std::sync::Mutex<HashMap<usize, WorkerInfo>>>,
+	worker_paused: Arc<std::sync::Mutex<HashMap<usize, Arc<AtomicBool>>>>,
 }
 
 impl WorkerProcessor {
@@ -69,11 +74,13 @@ impl WorkerProcessor {
 		worker_chan: mpsc::UnboundedReceiver<Box<dyn Worker>>,
 		stop_signal: watch::Receiver<bool>,
 		worker_info: Arc<std::sync::Mutex<HashMap<usize, WorkerInfo>>>,
+		worker_paused: Arc<std::sync::Mutex<HashMap<usize, Arc<AtomicBool>>>>,
 	) -> Self {
 		Self {
 			stop_signal,
 			worker_chan,
 			worker_info,
+			worker_paused,
 		}
 	}
 
@@ -95,11 +102,14 @@ impl WorkerProcessor {
 						let task_id = next_task_id;
 						next_task_id += 1;
 						let stop_signal = self.stop_signal.clone();
+						let paused = Arc::new(AtomicBool::new(false));
+						self.worker_paused.lock().unwrap().insert(task_id, paused.clone());
 						let mut worker = WorkerHandler {
 								task_id,
 								stop_signal,
 								worker: new_worker,
 								state: WorkerState::Busy,
+								paused,
 								errors: 0,
 								consecutive_errors: 0,
 								last_error: None,
@@ -119,6 +129,7 @@ impl WorkerProcessor {
 						match wi.get_mut(&worker.task_id) {
 							Some(i) => {
 								i.state = worker.state;
+								i.paused = worker.paused.load(Ordering::Relaxed);
 								i.status = worker.worker.status();
 								i.errors = worker.errors;
 								i.consecutive_errors = worker.consecutive_errors;
@@ -134,12 +145,14 @@ impl WorkerProcessor {
 									errors: worker.errors,
 									consecutive_errors: worker.consecutive_errors,
 									last_error: worker.last_error.take(),
+									paused: worker.paused.load(Ordering::Relaxed),
 								});
 							}
 						}
 
 						if worker.state == WorkerState::Done {
 							info!("Worker {} (TID {}) exited", worker.worker.name(), worker.task_id);
+							self.worker_paused.lock().unwrap().remove(&worker.task_id);
 						} else {
 							workers.push(async move {
 								worker.step().await;
@@ -180,6 +193,7 @@ struct WorkerHandler {
 	stop_signal: watch::Receiver<bool>,
 	worker: Box<dyn Worker>,
 	state: WorkerState,
+	paused: Arc<AtomicBool>,
 	errors: usize,
 	consecutive_errors: usize,
 	last_error: Option<(String, u64)>,
@@ -187,6 +201,15 @@ struct WorkerHandler {
 
 impl WorkerHandler {
 	async fn step(&mut self) {
+		if self.paused.load(Ordering::Relaxed) {
+			// Don't do any work until the worker is resumed
+			select! {
+				_ = tokio::time::sleep(PAUSE_CHECK_INTERVAL) => (),
+				_ = self.stop_signal.changed() => (),
+			}
+			return;
+		}
+
 		match self.state {
 			WorkerState::Busy => match self.worker.work(&mut self.stop_signal).await {
 				Ok(s) => {
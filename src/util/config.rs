@@ -1,4 +1,5 @@
 //! Contains type and functions related to Garage configuration file
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::Read;
 use std::net::SocketAddr;
@@ -31,12 +32,32 @@ pub struct Config {
 	// (we can add more aliases for this later)
 	pub replication_mode: String,
 
-	/// Zstd compression level used on data blocks
+	/// Minimum number of different zones in which the replicas of each
+	/// partition are stored. Replicas are spread over as many zones as
+	/// possible if not set.
+	#[serde(default)]
+	pub zone_redundancy: Option<usize>,
+
+	/// Compression algorithm used on data blocks, and zstd compression level
 	#[serde(
 		deserialize_with = "deserialize_compression",
 		default = "default_compression"
 	)]
-	pub compression_level: Option<i32>,
+	pub compression_level: CompressionAlgorithm,
+
+	/// Size of the in-memory cache of recently read data blocks, in bytes.
+	/// The cache is disabled when set to 0.
+	#[serde(deserialize_with = "deserialize_capacity", default)]
+	pub block_cache_size: usize,
+
+	/// Key used to encrypt data blocks stored on disk: 32 bytes hex encoded.
+	/// Blocks are stored unencrypted if not set.
+	#[serde(deserialize_with = "deserialize_encryption_key", default)]
+	pub block_encryption_key: Option<[u8; 32]>,
+
+	/// Number of tasks scrubbing the data directory in parallel
+	#[serde(default = "default_scrub_worker_count")]
+	pub scrub_worker_count: usize,
 
 	/// RPC secret key: 32 bytes hex encoded
 	pub rpc_secret: Option<String>,
@@ -47,11 +68,23 @@ pub struct Config {
 	pub rpc_bind_addr: SocketAddr,
 	/// Public IP address of this node
 	pub rpc_public_addr: Option<String>,
+	/// Hostname of this node, overriding the one detected from the system
+	pub node_hostname: Option<String>,
 
 	/// Timeout for Netapp's ping messagess
 	pub rpc_ping_timeout_msec: Option<u64>,
 	/// Timeout for Netapp RPC calls
 	pub rpc_timeout_msec: Option<u64>,
+	/// Minimum size in bytes above which system RPC messages are compressed
+	/// with zstd. Compression is disabled if not set.
+	pub rpc_compression_threshold: Option<usize>,
+	/// Tags of the nodes to which read requests are sent first, e.g.
+	/// `{ dc = "paris" }` to read from the nodes of the local datacenter
+	#[serde(default)]
+	pub preferred_tags: HashMap<String, String>,
+	/// Time to wait for the preferred nodes before also sending read
+	/// requests to the other nodes
+	pub preferred_nodes_timeout_msec: Option<u64>,
 
 	// -- Bootstraping and discovery
 	/// Bootstrap peers RPC address
@@ -64,6 +97,9 @@ pub struct Config {
 	/// Configuration for automatic node discovery through Kubernetes
 	#[serde(default)]
 	pub kubernetes_discovery: Option<KubernetesDiscoveryConfig>,
+	/// Configuration for automatic node discovery through DNS SRV records
+	#[serde(default)]
+	pub dns_discovery: Option<DnsDiscoveryConfig>,
 
 	// -- DB
 	/// Database engine to use for metadata (options: sled, sqlite, lmdb)
@@ -87,6 +123,12 @@ pub struct Config {
 	// -- APIs
 	/// Configuration for S3 api
 	pub s3_api: S3ApiConfig,
+	/// File where to write the access log of the S3 API
+	pub s3_api_access_log: Option<PathBuf>,
+	/// Addresses or networks of the reverse proxies whose X-Forwarded-For header
+	/// is used as the address of the client of API requests
+	#[serde(default)]
+	pub trusted_proxies: Vec<String>,
 
 	/// Configuration for K2V api
 	pub k2v_api: Option<K2VApiConfig>,
@@ -97,6 +139,32 @@ pub struct Config {
 	/// Configuration for the admin API endpoint
 	#[serde(default = "Default::default")]
 	pub admin: AdminConfig,
+
+	/// Priority of the background synchronization of each metadata table
+	#[serde(default = "Default::default")]
+	pub table_sync: TableSyncConfig,
+
+	/// Number of workers synchronizing the partitions of each metadata table in parallel
+	#[serde(default = "default_table_sync_parallelism")]
+	pub table_sync_parallelism: usize,
+
+	/// Minimum number of days to wait before deleting the version tombstones
+	/// found by `garage repair gc-versions`
+	#[serde(default = "default_version_gc_delay_days")]
+	pub version_gc_delay_days: u64,
+
+	/// Maximum time to wait for in-flight API requests to complete
+	/// when the node is shutting down, in seconds
+	#[serde(default = "default_graceful_shutdown_timeout_secs")]
+	pub graceful_shutdown_timeout_secs: u64,
+	/// Maximum time to wait for in-flight RPCs from other nodes to complete
+	/// before closing RPC connections when the node is shutting down, in seconds
+	#[serde(default = "default_rpc_drain_timeout_secs")]
+	pub rpc_drain_timeout_secs: u64,
+
+	/// Format of the log messages written by the server on its standard error
+	#[serde(default)]
+	pub log_format: LogFormat,
 }
 
 /// Configuration for S3 api
@@ -109,6 +177,13 @@ pub struct S3ApiConfig {
 	/// Suffix to remove from domain name to find bucket. If None,
 	/// vhost-style S3 request are disabled
 	pub root_domain: Option<String>,
+	/// Master key from which the keys of objects stored with SSE-S3 are derived:
+	/// 32 bytes hex encoded. SSE-S3 is not available if not set.
+	#[serde(deserialize_with = "deserialize_encryption_key", default)]
+	pub sse_s3_master_key: Option<[u8; 32]>,
+	/// Guess the content type of objects uploaded without one
+	#[serde(default)]
+	pub content_type_autodetect: bool,
 }
 
 /// Configuration for K2V api
@@ -116,6 +191,13 @@ pub struct S3ApiConfig {
 pub struct K2VApiConfig {
 	/// Address and port to bind for api serving
 	pub api_bind_addr: UnixOrTCPSocketAddress,
+	/// Maximum size of a value inserted through the K2V API, in bytes
+	#[serde(default = "default_k2v_max_item_size")]
+	pub max_item_size: usize,
+	/// Maximum number of concurrent values that an item can have
+	/// when a new value is inserted through the K2V API
+	#[serde(default = "default_k2v_max_concurrent_values")]
+	pub max_concurrent_values: usize,
 }
 
 /// Configuration for serving files as normal web server
@@ -127,6 +209,83 @@ pub struct WebConfig {
 	pub root_domain: String,
 }
 
+/// Priority of the background synchronization of metadata tables between nodes
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TableSyncConfig {
+	#[serde(default)]
+	pub bucket_table_priority: SyncPriority,
+	#[serde(default)]
+	pub bucket_alias_table_priority: SyncPriority,
+	#[serde(default)]
+	pub key_table_priority: SyncPriority,
+	#[serde(default)]
+	pub object_table_priority: SyncPriority,
+	#[serde(default)]
+	pub version_table_priority: SyncPriority,
+	#[serde(default)]
+	pub block_ref_table_priority: SyncPriority,
+}
+
+/// Priority of the background synchronization of a table: tables with a higher
+/// priority are synchronized more often and their RPCs are sent first
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncPriority {
+	Low,
+	#[default]
+	Normal,
+	High,
+}
+
+/// Format of the log messages: free-form text, or one JSON object per line
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+	#[default]
+	Text,
+	Json,
+}
+
+/// Algorithm used to compress data blocks before storing them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+	/// Zstd compression, with the given compression level
+	Zstd(i32),
+	/// LZ4 compression: lower ratio than zstd, but much cheaper in CPU
+	Lz4,
+	/// Blocks are stored uncompressed
+	None,
+}
+
+impl std::str::FromStr for CompressionAlgorithm {
+	type Err = String;
+
+	fn from_str(value: &str) -> Result<Self, String> {
+		if value.eq_ignore_ascii_case("none") {
+			Ok(CompressionAlgorithm::None)
+		} else if value.eq_ignore_ascii_case("lz4") {
+			Ok(CompressionAlgorithm::Lz4)
+		} else {
+			value.parse().map(CompressionAlgorithm::Zstd).map_err(|_| {
+				format!(
+					"Invalid compression level: '{}', should be a number, 'lz4' or 'none'",
+					value
+				)
+			})
+		}
+	}
+}
+
+impl std::fmt::Display for CompressionAlgorithm {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			CompressionAlgorithm::Zstd(level) => write!(f, "{}", level),
+			CompressionAlgorithm::Lz4 => write!(f, "lz4"),
+			CompressionAlgorithm::None => write!(f, "none"),
+		}
+	}
+}
+
 /// Configuration for the admin and monitoring HTTP API
 #[derive(Deserialize, Debug, Clone, Default)]
 pub struct AdminConfig {
@@ -142,9 +301,21 @@ pub struct AdminConfig {
 	pub admin_token: Option<String>,
 	/// File to read admin token from
 	pub admin_token_file: Option<String>,
+	/// Secret used to check admin API JWTs signed with HS256
+	pub admin_jwt_secret: Option<String>,
+	/// File to read the admin JWT secret from
+	pub admin_jwt_secret_file: Option<String>,
+	/// PEM file of the RSA public key used to check admin API JWTs signed with RS256
+	pub admin_jwt_public_key_file: Option<PathBuf>,
 
 	/// OTLP server to where to export traces
 	pub trace_sink: Option<String>,
+	/// Fraction of the traces that are exported, between 0 and 1
+	#[serde(default = "default_telemetry_sample_rate")]
+	pub telemetry_sample_rate: f64,
+
+	/// File where a line is appended for each change made through the admin API
+	pub audit_log_file: Option<PathBuf>,
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -181,6 +352,15 @@ pub struct ConsulDiscoveryConfig {
 	/// Additional service metadata to add
 	#[serde(default)]
 	pub meta: Option<std::collections::HashMap<String, String>>,
+	/// URL of the health check to register with the service (`agent` API only).
+	/// Defaults to the `/health` endpoint of the admin API, if it listens on a TCP port
+	pub health_check_url: Option<String>,
+	/// Interval between two runs of the health check, in seconds
+	#[serde(default = "default_consul_health_check_interval_secs")]
+	pub health_check_interval_secs: u64,
+	/// Time after which a node whose health check is failing is deregistered, in seconds
+	#[serde(default = "default_consul_health_check_deregister_after_secs")]
+	pub health_check_deregister_after_secs: u64,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -194,6 +374,29 @@ pub struct KubernetesDiscoveryConfig {
 	pub skip_crd: bool,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct DnsDiscoveryConfig {
+	/// Name of the SRV records listing the nodes, e.g. `_garage-rpc._tcp.example.com`
+	pub service_name: String,
+}
+
+fn default_consul_health_check_interval_secs() -> u64 {
+	10
+}
+fn default_consul_health_check_deregister_after_secs() -> u64 {
+	300
+}
+
+fn default_telemetry_sample_rate() -> f64 {
+	1.0
+}
+
+fn default_k2v_max_item_size() -> usize {
+	64 * 1024
+}
+fn default_k2v_max_concurrent_values() -> usize {
+	64
+}
 fn default_db_engine() -> String {
 	"sled".into()
 }
@@ -207,6 +410,21 @@ fn default_sled_flush_every_ms() -> u64 {
 fn default_block_size() -> usize {
 	1048576
 }
+fn default_scrub_worker_count() -> usize {
+	1
+}
+fn default_table_sync_parallelism() -> usize {
+	4
+}
+fn default_version_gc_delay_days() -> u64 {
+	7
+}
+fn default_graceful_shutdown_timeout_secs() -> u64 {
+	30
+}
+fn default_rpc_drain_timeout_secs() -> u64 {
+	10
+}
 
 /// Read and parse configuration
 pub fn read_config(config_file: PathBuf) -> Result<Config, Error> {
@@ -234,6 +452,11 @@ pub fn read_config(config_file: PathBuf) -> Result<Config, Error> {
 		&parsed_config.admin.admin_token_file,
 		"admin.admin_token",
 	)?;
+	secret_from_file(
+		&mut parsed_config.admin.admin_jwt_secret,
+		&parsed_config.admin.admin_jwt_secret_file,
+		"admin.admin_jwt_secret",
+	)?;
 
 	Ok(parsed_config)
 }
@@ -270,34 +493,27 @@ fn secret_from_file(
 	Ok(())
 }
 
-fn default_compression() -> Option<i32> {
-	Some(1)
+fn default_compression() -> CompressionAlgorithm {
+	CompressionAlgorithm::Zstd(1)
 }
 
-fn deserialize_compression<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
+fn deserialize_compression<'de, D>(deserializer: D) -> Result<CompressionAlgorithm, D::Error>
 where
 	D: de::Deserializer<'de>,
 {
-	struct OptionVisitor;
+	struct CompressionVisitor;
 
-	impl<'de> serde::de::Visitor<'de> for OptionVisitor {
-		type Value = Option<i32>;
+	impl<'de> serde::de::Visitor<'de> for CompressionVisitor {
+		type Value = CompressionAlgorithm;
 		fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-			formatter.write_str("int or 'none'")
+			formatter.write_str("int, 'lz4' or 'none'")
 		}
 
 		fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
 		where
 			E: de::Error,
 		{
-			if value.eq_ignore_ascii_case("none") {
-				Ok(None)
-			} else {
-				Err(E::custom(format!(
-					"Invalid compression level: '{}', should be a number, or 'none'",
-					value
-				)))
-			}
+			value.parse().map_err(E::custom)
 		}
 
 		fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
@@ -305,7 +521,7 @@ where
 			E: de::Error,
 		{
 			i32::try_from(v)
-				.map(Some)
+				.map(CompressionAlgorithm::Zstd)
 				.map_err(|_| E::custom("Compression level out of bound".to_owned()))
 		}
 
@@ -314,12 +530,28 @@ where
 			E: de::Error,
 		{
 			i32::try_from(v)
-				.map(Some)
+				.map(CompressionAlgorithm::Zstd)
 				.map_err(|_| E::custom("Compression level out of bound".to_owned()))
 		}
 	}
 
-	deserializer.deserialize_any(OptionVisitor)
+	deserializer.deserialize_any(CompressionVisitor)
+}
+
+fn deserialize_encryption_key<'de, D>(deserializer: D) -> Result<Option<[u8; 32]>, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	let key = <Option<String>>::deserialize(deserializer)?;
+	key.map(|key| {
+		hex::decode(key.trim())
+			.ok()
+			.and_then(|k| <[u8; 32]>::try_from(k).ok())
+			.ok_or_else(|| {
+				de::Error::custom("Invalid encryption key, should be 32 bytes hex encoded")
+			})
+	})
+	.transpose()
 }
 
 fn deserialize_capacity<'de, D>(deserializer: D) -> Result<usize, D::Error>
@@ -477,4 +709,146 @@ mod tests {
 		drop(file_config);
 		Ok(())
 	}
+
+	#[test]
+	fn test_compression_level() -> Result<(), Error> {
+		use super::CompressionAlgorithm;
+
+		for (value, expected) in [
+			("3", CompressionAlgorithm::Zstd(3)),
+			("'lz4'", CompressionAlgorithm::Lz4),
+			("'none'", CompressionAlgorithm::None),
+		] {
+			let path_config = mktemp::Temp::new_file()?;
+			let mut file_config = File::create(path_config.as_path())?;
+			writeln!(
+				file_config,
+				r#"
+				metadata_dir = "/tmp/garage/meta"
+				data_dir = "/tmp/garage/data"
+				replication_mode = "3"
+				rpc_bind_addr = "[::]:3901"
+				rpc_secret = "foo"
+				compression_level = {}
+
+				[s3_api]
+				s3_region = "garage"
+				api_bind_addr = "[::]:3900"
+				"#,
+				value
+			)?;
+			let config = super::read_config(path_config.to_path_buf())?;
+			assert_eq!(config.compression_level, expected);
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_log_format() -> Result<(), Error> {
+		use super::LogFormat;
+
+		for (value, expected) in [
+			("", LogFormat::Text),
+			("log_format = 'text'", LogFormat::Text),
+			("log_format = 'json'", LogFormat::Json),
+		] {
+			let path_config = mktemp::Temp::new_file()?;
+			let mut file_config = File::create(path_config.as_path())?;
+			writeln!(
+				file_config,
+				r#"
+				metadata_dir = "/tmp/garage/meta"
+				data_dir = "/tmp/garage/data"
+				replication_mode = "3"
+				rpc_bind_addr = "[::]:3901"
+				rpc_secret = "foo"
+				{}
+
+				[s3_api]
+				s3_region = "garage"
+				api_bind_addr = "[::]:3900"
+				"#,
+				value
+			)?;
+			let config = super::read_config(path_config.to_path_buf())?;
+			assert_eq!(config.log_format, expected);
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_preferred_tags() -> Result<(), Error> {
+		let path_config = mktemp::Temp::new_file()?;
+		let mut file_config = File::create(path_config.as_path())?;
+		writeln!(
+			file_config,
+			r#"
+			metadata_dir = "/tmp/garage/meta"
+			data_dir = "/tmp/garage/data"
+			replication_mode = "3"
+			rpc_bind_addr = "[::]:3901"
+			rpc_secret = "foo"
+			preferred_tags = {{ dc = "paris", disk = "ssd" }}
+
+			[s3_api]
+			s3_region = "garage"
+			api_bind_addr = "[::]:3900"
+			"#
+		)?;
+		let config = super::read_config(path_config.to_path_buf())?;
+		assert_eq!(config.preferred_tags.len(), 2);
+		assert_eq!(
+			config.preferred_tags.get("dc").map(String::as_str),
+			Some("paris")
+		);
+		assert_eq!(
+			config.preferred_tags.get("disk").map(String::as_str),
+			Some("ssd")
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_block_encryption_key() -> Result<(), Error> {
+		let key = "5b0f8e3a7d2c41e69a1f6c08b4d3e2a17f9c5e80d1b6a4f23c7e9d0a8b5f1c36";
+		for (value, expected) in [
+			(format!("'{}'", key), Some(hex::decode(key).unwrap())),
+			("'5b0f8e3a'".to_string(), None),
+			("'not hex'".to_string(), None),
+		] {
+			let path_config = mktemp::Temp::new_file()?;
+			let mut file_config = File::create(path_config.as_path())?;
+			writeln!(
+				file_config,
+				r#"
+				metadata_dir = "/tmp/garage/meta"
+				data_dir = "/tmp/garage/data"
+				replication_mode = "3"
+				rpc_bind_addr = "[::]:3901"
+				rpc_secret = "foo"
+				block_encryption_key = {}
+
+				[s3_api]
+				s3_region = "garage"
+				api_bind_addr = "[::]:3900"
+				"#,
+				value
+			)?;
+			match expected {
+				Some(expected) => {
+					let config = super::read_config(path_config.to_path_buf())?;
+					assert_eq!(
+						config.block_encryption_key.map(|k| k.to_vec()),
+						Some(expected)
+					);
+				}
+				None => assert!(super::read_config(path_config.to_path_buf()).is_err()),
+			}
+		}
+
+		Ok(())
+	}
 }
@@ -60,6 +60,15 @@ impl Tranquilizer {
 		}
 	}
 
+	/// Tranquilize one of `n_workers` tasks that do the same background
+	/// operation in parallel, the tranquility being divided equally between them
+	pub async fn tranquilize_shared(&mut self, tranquility: u32, n_workers: u32) {
+		if let Some(delay) = self.tranquilize_internal(tranquility) {
+			sleep(delay / n_workers.max(1)).await;
+			self.reset();
+		}
+	}
+
 	#[must_use]
 	pub fn tranquilize_worker(&mut self, tranquility: u32) -> WorkerState {
 		match self.tranquilize_internal(tranquility) {
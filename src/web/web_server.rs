@@ -13,6 +13,8 @@ use hyper::{
 
 use hyperlocal::UnixServerExt;
 
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
 use tokio::net::UnixStream;
 
 use opentelemetry::{
@@ -31,6 +33,7 @@ use garage_api::s3::error::{
 };
 use garage_api::s3::get::{handle_get, handle_head};
 
+use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
 
 use garage_table::*;
@@ -215,7 +218,7 @@ impl WebServer {
 			.object_table
 			.get(&bucket_id, &key.to_string())
 			.await?
-			.map(|object| object.versions().iter().any(|v| v.is_data()))
+			.map(|object| object.current_data_version().is_some())
 			.unwrap_or(false);
 		Ok(exists)
 	}
@@ -266,10 +269,20 @@ impl WebServer {
 			bucket_name, bucket_id, key, may_redirect
 		);
 
+		// Routing rules without an error code condition apply
+		// before the requested key is even looked up
+		if *req.method() != Method::OPTIONS {
+			if let Some(rule) = find_routing_rule(website_config, &key, None) {
+				return Ok(routing_rule_redirect(req, authority, rule, &key));
+			}
+		}
+
 		let ret_doc = match *req.method() {
 			Method::OPTIONS => handle_options_for_bucket(req, &bucket),
-			Method::HEAD => handle_head(self.garage.clone(), req, bucket_id, &key, None).await,
-			Method::GET => handle_get(self.garage.clone(), req, bucket_id, &key, None).await,
+			Method::HEAD => {
+				handle_head(self.garage.clone(), req, bucket_id, &key, None, None).await
+			}
+			Method::GET => handle_get(self.garage.clone(), req, bucket_id, &key, None, None).await,
 			_ => Err(ApiError::bad_request("HTTP method not supported")),
 		};
 
@@ -289,6 +302,13 @@ impl WebServer {
 
 		match ret_doc_with_redir.map_err(Error::from) {
 			Err(error) => {
+				if *req.method() != Method::OPTIONS {
+					let code = error.http_status_code().as_u16();
+					if let Some(rule) = find_routing_rule(website_config, &key, Some(code)) {
+						return Ok(routing_rule_redirect(req, authority, rule, &key));
+					}
+				}
+
 				// For a HEAD or OPTIONS method, and for non-4xx errors,
 				// we don't return the error document as content,
 				// we return above and just return the error message
@@ -313,7 +333,15 @@ impl WebServer {
 					.body(Body::empty())
 					.unwrap();
 
-				match handle_get(self.garage.clone(), &req2, bucket_id, &error_document, None).await
+				match handle_get(
+					self.garage.clone(),
+					&req2,
+					bucket_id,
+					&error_document,
+					None,
+					None,
+				)
+				.await
 				{
 					Ok(mut error_doc) => {
 						// The error won't be logged back in handle_request,
@@ -349,7 +377,7 @@ impl WebServer {
 			Ok(mut resp) => {
 				// Maybe add CORS headers
 				if let Some(rule) = find_matching_cors_rule(&bucket, req)? {
-					add_cors_headers(&mut resp, rule)
+					add_cors_headers(&mut resp, &rule)
 						.ok_or_internal_error("Invalid bucket CORS configuration")?;
 				}
 				Ok(resp)
@@ -373,6 +401,80 @@ fn error_to_res(e: Error) -> Response<Body> {
 	http_error
 }
 
+/// Characters that are escaped in the key of a routing rule redirection
+const REDIRECT_KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+	.remove(b'/')
+	.remove(b'-')
+	.remove(b'_')
+	.remove(b'.')
+	.remove(b'~');
+
+/// Find the first routing rule of the website configuration that matches
+/// the requested key, and the error code returned for it if any. Rules are
+/// checked in the order in which they are defined, as in AWS S3.
+fn find_routing_rule<'a>(
+	website_config: &'a WebsiteConfig,
+	key: &str,
+	error_code: Option<u16>,
+) -> Option<&'a WebsiteRoutingRule> {
+	website_config.routing_rules.iter().find(|rule| {
+		rule.http_error_code == error_code
+			&& rule
+				.key_prefix
+				.as_ref()
+				.map(|prefix| key.starts_with(prefix.as_str()))
+				.unwrap_or(true)
+	})
+}
+
+/// Build the redirection response of a routing rule that matched `key`
+fn routing_rule_redirect(
+	req: &Request<Body>,
+	authority: &str,
+	rule: &WebsiteRoutingRule,
+	key: &str,
+) -> Response<Body> {
+	let redirect = &rule.redirect;
+	let new_key = match (
+		&redirect.replace_key_with,
+		&redirect.replace_key_prefix_with,
+	) {
+		(Some(full), _) => full.clone(),
+		(None, Some(prefix)) => {
+			let matched = rule.key_prefix.as_deref().unwrap_or_default();
+			format!("{}{}", prefix, &key[matched.len()..])
+		}
+		(None, None) => key.to_string(),
+	};
+	let path = format!(
+		"/{}",
+		utf8_percent_encode(new_key.trim_start_matches('/'), REDIRECT_KEY_ENCODE_SET)
+	);
+
+	// Without a host name nor a protocol, redirect on the same site
+	let location = if redirect.hostname.is_none() && redirect.protocol.is_none() {
+		path
+	} else {
+		let protocol = redirect
+			.protocol
+			.as_deref()
+			.or_else(|| {
+				req.headers()
+					.get("x-forwarded-proto")
+					.and_then(|x| x.to_str().ok())
+			})
+			.unwrap_or("http");
+		let host = redirect.hostname.as_deref().unwrap_or(authority);
+		format!("{}://{}{}", protocol, host, path)
+	};
+
+	Response::builder()
+		.status(redirect.http_redirect_code)
+		.header("Location", location)
+		.body(Body::empty())
+		.unwrap()
+}
+
 #[derive(Debug, PartialEq)]
 enum ImplicitRedirect {
 	No,
@@ -453,4 +555,58 @@ mod tests {
 		assert!(path_to_keys("i/am/relative", "index.html").is_err());
 		Ok(())
 	}
+
+	#[test]
+	fn routing_rules_test() {
+		let rule = |key_prefix: Option<&str>, http_error_code, redirect| WebsiteRoutingRule {
+			key_prefix: key_prefix.map(String::from),
+			http_error_code,
+			redirect,
+		};
+		let website_config = WebsiteConfig {
+			index_document: "index.html".into(),
+			error_document: None,
+			routing_rules: vec![
+				rule(
+					Some("docs/"),
+					None,
+					WebsiteRedirect {
+						hostname: None,
+						protocol: None,
+						http_redirect_code: 301,
+						replace_key_prefix_with: Some("documents/".into()),
+						replace_key_with: None,
+					},
+				),
+				rule(
+					None,
+					Some(404),
+					WebsiteRedirect {
+						hostname: Some("example.com".into()),
+						protocol: Some("https".into()),
+						http_redirect_code: 302,
+						replace_key_prefix_with: None,
+						replace_key_with: Some("not found.html".into()),
+					},
+				),
+			],
+		};
+		let req = Request::builder().body(Body::empty()).unwrap();
+
+		assert!(find_routing_rule(&website_config, "img/a.png", None).is_none());
+		assert!(find_routing_rule(&website_config, "docs/a.html", Some(403)).is_none());
+
+		let r = find_routing_rule(&website_config, "docs/a.html", None).unwrap();
+		let resp = routing_rule_redirect(&req, "site.tld", r, "docs/a.html");
+		assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+		assert_eq!(resp.headers()["location"], "/documents/a.html");
+
+		let r = find_routing_rule(&website_config, "img/a.png", Some(404)).unwrap();
+		let resp = routing_rule_redirect(&req, "site.tld", r, "img/a.png");
+		assert_eq!(resp.status(), StatusCode::FOUND);
+		assert_eq!(
+			resp.headers()["location"],
+			"https://example.com/not%20found.html"
+		);
+	}
 }